@@ -0,0 +1,166 @@
+use crate::file::{DirEntry, Metadata, OpenOptions};
+use crate::util::{invalid_input, make_relative, normalize_path, not_supported, parent_iter};
+use async_trait::async_trait;
+use duplicate::duplicate_item;
+use futures_util::Stream;
+use std::io::ErrorKind;
+use std::iter::once;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncWrite, AsyncWriteExt};
+
+/// The entries of a directory, listed as they're read from the filesystem.
+pub type ReadDirStream = Pin<Box<dyn Stream<Item = crate::Result<DirEntry>> + Send>>;
+
+/// A file that can be read and written without blocking.
+#[async_trait]
+pub trait AsyncFile: AsyncRead + AsyncWrite + AsyncSeek + Send + Unpin {
+    /// Returns the directory entry for the file.
+    async fn metadata(&self) -> crate::Result<Metadata>;
+
+    /// Reads a file into a vector.
+    async fn read_into_vec(&mut self) -> crate::Result<Vec<u8>> {
+        let mut vec = Vec::with_capacity(self.metadata().await?.len() as usize);
+        self.read_to_end(&mut vec).await?;
+        Ok(vec)
+    }
+
+    /// Reads a file into a string.
+    async fn read_into_string(&mut self) -> crate::Result<String> {
+        let mut str = String::with_capacity(self.metadata().await?.len() as usize);
+        self.read_to_string(&mut str).await?;
+        Ok(str)
+    }
+}
+
+/// A file system with a directory tree whose operations don't block, such as one backed by `tokio::fs` or by a
+/// network service.
+#[async_trait]
+pub trait AsyncFileSystem: Send + Sync {
+    /// Creates a directory at `path`.
+    async fn create_dir(&self, path: &str) -> crate::Result<()>;
+    /// Returns the metadata for the file/folder at `path`.
+    async fn metadata(&self, path: &str) -> crate::Result<Metadata>;
+    /// Opens a file at `path` with options `options`.
+    async fn open_file_options(
+        &self,
+        path: &str,
+        options: &OpenOptions,
+    ) -> crate::Result<Box<dyn AsyncFile>>;
+    /// Lists the files and folders contained in the directory denoted by `path`.
+    async fn read_dir(&self, path: &str) -> crate::Result<ReadDirStream>;
+    /// Removes the directory at `path`.
+    async fn remove_dir(&self, path: &str) -> crate::Result<()>;
+    /// Removes a file at `path`.
+    async fn remove_file(&self, path: &str) -> crate::Result<()>;
+
+    /// Creates a directory `path` and all of its parents.
+    async fn create_dir_all(&self, path: &str) -> crate::Result<()> {
+        let normalized = normalize_path(make_relative(path));
+        for path in parent_iter(&normalized).chain(once(normalized.as_ref())) {
+            // unwrap: `path` should already be a valid UTF-8 string
+            if let Err(err) = self.create_dir(path.to_str().unwrap()).await {
+                if err.kind() != ErrorKind::AlreadyExists {
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Creates a file at `path` in write mode. The file will be opened in truncate mode, so all contents will be
+    /// overwritten. If this is not desirable, use `open_file` directly.
+    async fn create_file(&self, path: &str) -> crate::Result<Box<dyn AsyncFile>> {
+        self.open_file_options(
+            path,
+            &OpenOptions::default()
+                .write(true)
+                .create(true)
+                .truncate(true),
+        )
+        .await
+    }
+    /// Returns `Ok(true)` or `Ok(false)` if a file or folder at `path` does or does not exist, and `Err(_)` if the
+    /// presence cannot be verified.
+    async fn exists(&self, path: &str) -> crate::Result<bool> {
+        match self.metadata(path).await {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+    /// Opens a file at `path` for reading.
+    async fn open_file(&self, path: &str) -> crate::Result<Box<dyn AsyncFile>> {
+        self.open_file_options(path, &OpenOptions::default()).await
+    }
+    /// Copies the contents of the file at `from` to the file at `to`, which is created or truncated. Returns the
+    /// number of bytes copied.
+    async fn copy_file(&self, from: &str, to: &str) -> crate::Result<u64> {
+        let mut source = self.open_file(from).await?;
+        if !source.metadata().await?.is_file() {
+            return Err(invalid_input("Source is not a file"));
+        }
+
+        let mut destination = self.create_file(to).await?;
+        let len = tokio::io::copy(&mut source, &mut destination).await?;
+        destination.flush().await?;
+        Ok(len)
+    }
+    /// Renames the file or directory at `from` to `to`, replacing the file at `to` if there is one. By default, only
+    /// files can be renamed, by copying them and removing the original, so the rename isn't atomic.
+    async fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        if !self.metadata(from).await?.is_file() {
+            return Err(not_supported());
+        }
+        if normalize_path(make_relative(from)) == normalize_path(make_relative(to)) {
+            return Ok(());
+        }
+
+        self.copy_file(from, to).await?;
+        self.remove_file(from).await
+    }
+}
+
+#[duplicate_item(pointer; [Box]; [Arc])]
+#[async_trait]
+impl<T: AsyncFileSystem + ?Sized> AsyncFileSystem for pointer<T> {
+    async fn create_dir(&self, path: &str) -> crate::Result<()> {
+        (**self).create_dir(path).await
+    }
+    async fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        (**self).metadata(path).await
+    }
+    async fn open_file_options(
+        &self,
+        path: &str,
+        options: &OpenOptions,
+    ) -> crate::Result<Box<dyn AsyncFile>> {
+        (**self).open_file_options(path, options).await
+    }
+    async fn read_dir(&self, path: &str) -> crate::Result<ReadDirStream> {
+        (**self).read_dir(path).await
+    }
+    async fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        (**self).remove_dir(path).await
+    }
+    async fn remove_file(&self, path: &str) -> crate::Result<()> {
+        (**self).remove_file(path).await
+    }
+    async fn create_dir_all(&self, path: &str) -> crate::Result<()> {
+        (**self).create_dir_all(path).await
+    }
+    async fn create_file(&self, path: &str) -> crate::Result<Box<dyn AsyncFile>> {
+        (**self).create_file(path).await
+    }
+    async fn exists(&self, path: &str) -> crate::Result<bool> {
+        (**self).exists(path).await
+    }
+    async fn open_file(&self, path: &str) -> crate::Result<Box<dyn AsyncFile>> {
+        (**self).open_file(path).await
+    }
+    async fn copy_file(&self, from: &str, to: &str) -> crate::Result<u64> {
+        (**self).copy_file(from, to).await
+    }
+    async fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        (**self).rename(from, to).await
+    }
+}
@@ -0,0 +1,329 @@
+use crate::file::{DirEntry, File, Metadata, OpenOptions};
+use crate::tar_fs::TarFS;
+use crate::tree::normalize_and_relativize;
+use crate::util::{invalid_path, not_found, read_only};
+use crate::zip_fs::ZipFS;
+use crate::FileSystem;
+use itertools::Itertools;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{Cursor, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The filesystem an archive is mounted as. Archives can contain archives themselves.
+type ArchiveFS = AutoMountFS<Box<dyn FileSystem + Send + Sync>>;
+
+/// A filesystem that transparently mounts archives as they're traversed, so that files named
+/// `*.zip` are directories backed by `ZipFS`, and files named `*.tar` or `*.tar.*` are directories
+/// backed by `TarFS`. Compressed tarballs require their respective feature to be enabled.
+///
+/// Archives are read into memory and mounted once, on first access. They're read-only, and can't be
+/// opened as files through this filesystem; use `inner` to access the archive files themselves.
+pub struct AutoMountFS<FS> {
+    inner: FS,
+    archives: Mutex<HashMap<PathBuf, Arc<ArchiveFS>>>,
+}
+
+/// The kind of archive a file is, based on its name.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+}
+
+impl ArchiveKind {
+    /// Returns the kind of archive a file is, or `None` if it isn't an archive.
+    ///
+    /// # Arguments
+    /// `name`: The name of the file.  
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar")
+            || name.contains(".tar.")
+            || [".tgz", ".txz", ".tbz2", ".tzst"]
+                .iter()
+                .any(|extension| name.ends_with(extension))
+        {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the kind of archive the entry at `path` is, or `None` if it isn't an archive.
+    ///
+    /// # Arguments
+    /// `path`: The path of the entry.  
+    fn from_path(path: &Path) -> Option<Self> {
+        path.file_name()
+            .and_then(OsStr::to_str)
+            .and_then(Self::from_name)
+    }
+}
+
+/// Where a path resolves to.
+enum Resolved {
+    /// A path within the inner filesystem.
+    Inner(PathBuf),
+    /// A path within an archive.
+    Archive(Arc<ArchiveFS>, PathBuf),
+}
+
+impl<FS: FileSystem> AutoMountFS<FS> {
+    /// Creates a new filesystem that mounts the archives within `inner`.
+    ///
+    /// # Arguments
+    /// `inner`: The filesystem containing the archives.  
+    pub fn new(inner: FS) -> Self {
+        Self {
+            inner,
+            archives: Mutex::default(),
+        }
+    }
+
+    /// Returns the filesystem containing the archives.
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Resolves `path` to either the inner filesystem or the outermost archive containing it.
+    /// Archives resolve the rest of the path themselves, mounting any archives they contain.
+    ///
+    /// # Arguments
+    /// `path`: The path to resolve.  
+    fn resolve(&self, path: &str) -> crate::Result<Resolved> {
+        let normalized_path = normalize_and_relativize(path);
+
+        for archive_path in normalized_path.ancestors().collect_vec().into_iter().rev() {
+            let Some(kind) = ArchiveKind::from_path(archive_path) else {
+                continue;
+            };
+
+            if let Some(archive) = self.archive(archive_path, kind)? {
+                // `archive_path` is an ancestor of `normalized_path`, so this is safe
+                let remaining_path = normalized_path.strip_prefix(archive_path).unwrap();
+                return Ok(Resolved::Archive(archive, remaining_path.to_owned()));
+            }
+        }
+
+        Ok(Resolved::Inner(normalized_path))
+    }
+
+    /// Returns the archive at `path`, mounting it if it hasn't been already. Returns `None` if
+    /// there is no file at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path of the archive.  
+    /// `kind`: The kind of archive.  
+    fn archive(&self, path: &Path, kind: ArchiveKind) -> crate::Result<Option<Arc<ArchiveFS>>> {
+        let mut archives = self.archives.lock();
+        if let Some(archive) = archives.get(path) {
+            return Ok(Some(archive.clone()));
+        }
+
+        // directories can have archive names too
+        let path_str = path.to_str().ok_or_else(invalid_path)?;
+        match self.inner.metadata(path_str) {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => return Ok(None),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        }
+
+        let contents = Cursor::new(self.inner.open_file(path_str)?.read_into_vec()?);
+        let fs: Box<dyn FileSystem + Send + Sync> = match kind {
+            ArchiveKind::Zip => Box::new(ZipFS::new(contents)?),
+            ArchiveKind::Tar => Box::new(TarFS::open(contents)?),
+        };
+
+        let archive = Arc::new(AutoMountFS::new(fs));
+        archives.insert(path.to_owned(), archive.clone());
+        Ok(Some(archive))
+    }
+}
+
+impl<FS: FileSystem> FileSystem for AutoMountFS<FS> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        match self.resolve(path)? {
+            Resolved::Inner(path) => self
+                .inner
+                .create_dir(path.to_str().ok_or_else(invalid_path)?),
+            Resolved::Archive(..) => Err(read_only()),
+        }
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        match self.resolve(path)? {
+            Resolved::Inner(path) => self.inner.metadata(path.to_str().ok_or_else(invalid_path)?),
+            // the archive itself is a directory
+            Resolved::Archive(_, path) if path.as_os_str().is_empty() => Ok(Metadata::directory()),
+            Resolved::Archive(archive, path) => {
+                archive.metadata(path.to_str().ok_or_else(invalid_path)?)
+            }
+        }
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        match self.resolve(path)? {
+            Resolved::Inner(path) => self
+                .inner
+                .open_file_options(path.to_str().ok_or_else(invalid_path)?, options),
+            Resolved::Archive(_, path) if path.as_os_str().is_empty() => Err(not_found()),
+            Resolved::Archive(archive, path) => {
+                archive.open_file_options(path.to_str().ok_or_else(invalid_path)?, options)
+            }
+        }
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        match self.resolve(path)? {
+            Resolved::Inner(path) => {
+                let entries = self
+                    .inner
+                    .read_dir(path.to_str().ok_or_else(invalid_path)?)?;
+
+                // archives are listed as directories without mounting them
+                Ok(Box::new(entries.map_ok(|entry| {
+                    if entry.metadata.is_file() && ArchiveKind::from_path(&entry.path).is_some() {
+                        DirEntry {
+                            metadata: Metadata::directory(),
+                            ..entry
+                        }
+                    } else {
+                        entry
+                    }
+                })))
+            }
+            Resolved::Archive(archive, archive_path) => {
+                // entries are rooted at the archive, so rebase them onto the requested directory
+                let directory = normalize_and_relativize(path);
+                let entries = archive.read_dir(archive_path.to_str().ok_or_else(invalid_path)?)?;
+                Ok(Box::new(entries.map_ok(move |entry| DirEntry {
+                    path: directory.join(entry.file_name()),
+                    ..entry
+                })))
+            }
+        }
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        match self.resolve(path)? {
+            Resolved::Inner(path) => self
+                .inner
+                .remove_dir(path.to_str().ok_or_else(invalid_path)?),
+            Resolved::Archive(..) => Err(read_only()),
+        }
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        match self.resolve(path)? {
+            Resolved::Inner(path) => self
+                .inner
+                .remove_file(path.to_str().ok_or_else(invalid_path)?),
+            Resolved::Archive(..) => Err(read_only()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::auto_mount_fs::AutoMountFS;
+    use crate::file::Metadata;
+    use crate::memory_fs::MemoryFS;
+    use crate::tar_fs::write_tar;
+    use crate::util::test::read_directory;
+    use crate::FileSystem;
+    use std::fs;
+    use std::io::Write;
+
+    fn archives_fs() -> AutoMountFS<MemoryFS> {
+        let fs = MemoryFS::default();
+        fs.create_dir_all("assets/not_an_archive.zip").unwrap();
+        write!(fs.create_file("assets/readme").unwrap(), "readme").unwrap();
+        fs.create_file("assets/deep.ZIP")
+            .unwrap()
+            .write_all(&fs::read("test/deep_fs.zip").unwrap())
+            .unwrap();
+
+        // a tarball containing another archive
+        let packed = MemoryFS::default();
+        packed
+            .create_file("inner.zip")
+            .unwrap()
+            .write_all(&fs::read("test/deep_fs.zip").unwrap())
+            .unwrap();
+        let tarball = write_tar(&packed, "", Vec::new()).unwrap();
+        fs.create_file("assets/packed.tar")
+            .unwrap()
+            .write_all(&tarball)
+            .unwrap();
+
+        AutoMountFS::new(fs)
+    }
+
+    #[test]
+    fn read_dir() {
+        let fs = archives_fs();
+
+        let assets = read_directory(&fs, "assets");
+        itertools::assert_equal(
+            assets.keys(),
+            vec![
+                "assets/deep.ZIP",
+                "assets/not_an_archive.zip",
+                "assets/packed.tar",
+                "assets/readme",
+            ],
+        );
+        itertools::assert_equal(
+            assets.values(),
+            vec![
+                &Metadata::directory(),
+                &Metadata::directory(),
+                &Metadata::directory(),
+                &Metadata::file(6),
+            ],
+        );
+
+        itertools::assert_equal(
+            read_directory(&fs, "assets/deep.ZIP/folder").keys(),
+            vec!["assets/deep.ZIP/folder/and", "assets/deep.ZIP/folder/desc"],
+        );
+        itertools::assert_equal(
+            read_directory(&fs, "assets/packed.tar/inner.zip").keys(),
+            vec![
+                "assets/packed.tar/inner.zip/file",
+                "assets/packed.tar/inner.zip/folder",
+            ],
+        );
+    }
+
+    #[test]
+    fn open_file() {
+        let fs = archives_fs();
+
+        for path in [
+            "assets/deep.ZIP/folder/and/it/desc",
+            "/assets/packed.tar/inner.zip/folder/and/it/desc",
+        ] {
+            let contents = fs.open_file(path).unwrap().read_into_string().unwrap();
+            assert_eq!(contents, "it\n");
+        }
+
+        assert_eq!(
+            fs.metadata("assets/packed.tar/inner.zip").unwrap(),
+            Metadata::directory()
+        );
+        assert!(fs.open_file("assets/deep.ZIP").is_err());
+        assert!(fs.create_file("assets/deep.ZIP/new").is_err());
+        assert!(!fs.exists("assets/deep.ZIP/nonsense").unwrap());
+        assert!(fs.exists("assets/not_an_archive.zip").unwrap());
+    }
+}
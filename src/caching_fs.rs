@@ -0,0 +1,452 @@
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::not_writable;
+use crate::FileSystem;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A cached entry.
+struct CacheEntry {
+    metadata: Option<Metadata>,
+    contents: Option<Arc<[u8]>>,
+    /// The tick the entry was last used at, which orders it for eviction.
+    last_used: u64,
+}
+
+impl CacheEntry {
+    /// Returns the number of bytes the entry counts against the budget.
+    ///
+    /// # Arguments
+    /// `path`: The path of the entry.  
+    fn cost(&self, path: &Path) -> u64 {
+        (path.as_os_str().len()
+            + size_of::<Self>()
+            + self.contents.as_ref().map_or(0, |contents| contents.len())) as u64
+    }
+}
+
+/// The cached entries, in least-recently-used order.
+#[derive(Default)]
+struct Cache {
+    budget: u64,
+    used: u64,
+    tick: u64,
+    entries: HashMap<PathBuf, CacheEntry>,
+    lru: BTreeMap<u64, PathBuf>,
+}
+
+impl Cache {
+    /// Returns the cached entry at `path`, marking it as used.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path of the entry.  
+    fn get(&mut self, path: &Path) -> Option<&CacheEntry> {
+        let entry = self.entries.get_mut(path)?;
+        self.tick += 1;
+        self.lru.remove(&entry.last_used);
+        self.lru.insert(self.tick, path.to_owned());
+        entry.last_used = self.tick;
+        Some(entry)
+    }
+
+    /// Updates the entry at `path`, evicting the least recently used entries until it fits within the budget. Entries
+    /// larger than the budget aren't cached.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path of the entry.  
+    /// `update`: Updates the entry, which is empty if it isn't cached.  
+    fn update<F: FnOnce(&mut CacheEntry)>(&mut self, path: &Path, update: F) {
+        let mut entry = self.remove(path).unwrap_or(CacheEntry {
+            metadata: None,
+            contents: None,
+            last_used: 0,
+        });
+        update(&mut entry);
+
+        let cost = entry.cost(path);
+        if cost > self.budget {
+            return;
+        }
+        while self.used + cost > self.budget {
+            let Some((_, evicted)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some(evicted_entry) = self.entries.remove(&evicted) {
+                self.used -= evicted_entry.cost(&evicted);
+            }
+        }
+
+        self.tick += 1;
+        entry.last_used = self.tick;
+        self.lru.insert(self.tick, path.to_owned());
+        self.entries.insert(path.to_owned(), entry);
+        self.used += cost;
+    }
+
+    /// Removes the entry at `path`, returning it if it was cached.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path of the entry.  
+    fn remove(&mut self, path: &Path) -> Option<CacheEntry> {
+        let entry = self.entries.remove(path)?;
+        self.lru.remove(&entry.last_used);
+        self.used -= entry.cost(path);
+        Some(entry)
+    }
+
+    /// Removes the entry at `path` and every entry beneath it.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn invalidate(&mut self, path: &Path) {
+        let invalidated: Vec<_> = self
+            .entries
+            .keys()
+            .filter(|cached| cached.starts_with(path))
+            .cloned()
+            .collect();
+        for cached in invalidated {
+            self.remove(&cached);
+        }
+    }
+}
+
+/// A filesystem that caches the metadata and contents of the files of a slow filesystem, such as an archive or a
+/// network filesystem, in memory.
+///
+/// Cached entries count their contents, along with a small fixed overhead, against a budget in bytes, and the least
+/// recently used entries are evicted to stay within it. Files are read in their entirety when they're opened for
+/// reading, and files larger than the budget aren't cached.
+///
+/// Changes made through this filesystem invalidate the entries they affect. Changes made to the inner filesystem
+/// directly aren't noticed, so they must be followed by a call to `invalidate` or `invalidate_all`.
+pub struct CachingFS<FS> {
+    inner: FS,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl<FS: FileSystem> CachingFS<FS> {
+    /// Creates a new caching filesystem.
+    ///
+    /// # Arguments
+    /// `inner`: The filesystem to cache.  
+    /// `budget`: The maximum number of bytes to cache.  
+    pub fn new(inner: FS, budget: u64) -> Self {
+        Self {
+            inner,
+            cache: Arc::new(Mutex::new(Cache {
+                budget,
+                ..Cache::default()
+            })),
+        }
+    }
+
+    /// Returns the cached filesystem.
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Returns the number of bytes currently counted against the budget.
+    pub fn cached_bytes(&self) -> u64 {
+        self.cache.lock().used
+    }
+
+    /// Evicts the cached entry at `path`, and every entry beneath it.
+    ///
+    /// # Arguments
+    /// `path`: The path to invalidate.  
+    pub fn invalidate(&self, path: &str) {
+        self.cache
+            .lock()
+            .invalidate(&normalize_and_relativize(path))
+    }
+
+    /// Evicts every cached entry.
+    pub fn invalidate_all(&self) {
+        let mut cache = self.cache.lock();
+        cache.entries.clear();
+        cache.lru.clear();
+        cache.used = 0;
+    }
+}
+
+impl<FS: FileSystem> FileSystem for CachingFS<FS> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.inner.create_dir(path)?;
+        self.invalidate(path);
+        Ok(())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        let normalized_path = normalize_and_relativize(path);
+        if let Some(metadata) = self
+            .cache
+            .lock()
+            .get(&normalized_path)
+            .and_then(|entry| entry.metadata.clone())
+        {
+            return Ok(metadata);
+        }
+
+        let metadata = self.inner.metadata(path)?;
+        self.cache.lock().update(&normalized_path, |entry| {
+            entry.metadata = Some(metadata.clone())
+        });
+        Ok(metadata)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        let normalized_path = normalize_and_relativize(path);
+
+        if options.writable() {
+            // the file is invalidated again when it's closed, in case it was read in the meantime
+            self.cache.lock().invalidate(&normalized_path);
+            return Ok(Box::new(InvalidatingFile {
+                inner: self.inner.open_file_options(path, options)?,
+                cache: self.cache.clone(),
+                path: normalized_path,
+            }));
+        }
+
+        let cached = self
+            .cache
+            .lock()
+            .get(&normalized_path)
+            .and_then(|entry| Some((entry.contents.clone()?, entry.metadata.clone()?)));
+        let (contents, metadata) = match cached {
+            Some(cached) => cached,
+            None => {
+                let mut file = self.inner.open_file_options(path, options)?;
+                let metadata = file.metadata()?;
+                let contents: Arc<[u8]> = file.read_into_vec()?.into();
+                self.cache.lock().update(&normalized_path, |entry| {
+                    entry.metadata = Some(metadata.clone());
+                    entry.contents = Some(contents.clone());
+                });
+                (contents, metadata)
+            }
+        };
+
+        Ok(Box::new(CachedFile {
+            contents: Cursor::new(contents),
+            metadata,
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        self.inner.read_dir(path)
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        let result = self.inner.remove_dir(path);
+        self.invalidate(path);
+        result
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        let result = self.inner.remove_file(path);
+        self.invalidate(path);
+        result
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        self.inner.create_dir_with(path, mode)?;
+        self.invalidate(path);
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        let result = self.inner.rename(from, to);
+        self.invalidate(from);
+        self.invalidate(to);
+        result
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        self.inner.stats()
+    }
+}
+
+/// A file served from the cache.
+struct CachedFile {
+    contents: Cursor<Arc<[u8]>>,
+    metadata: Metadata,
+}
+
+impl Read for CachedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.contents.read(buf)
+    }
+}
+
+impl Seek for CachedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.contents.seek(pos)
+    }
+}
+
+impl Write for CachedFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(not_writable())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl File for CachedFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(self.metadata.clone())
+    }
+}
+
+/// A file opened for writing, which invalidates its cached entry when it's closed.
+struct InvalidatingFile {
+    inner: Box<dyn File>,
+    cache: Arc<Mutex<Cache>>,
+    path: PathBuf,
+}
+
+impl Read for InvalidatingFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for InvalidatingFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl Write for InvalidatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl File for InvalidatingFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        self.inner.metadata()
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        self.inner.set_len(len)
+    }
+}
+
+impl Drop for InvalidatingFile {
+    fn drop(&mut self) {
+        self.cache.lock().invalidate(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::caching_fs::CachingFS;
+    use crate::memory_fs::MemoryFS;
+    use crate::util::test::check_open_options;
+    use crate::FileSystem;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    fn cached_memory_fs(budget: u64) -> (Arc<MemoryFS>, CachingFS<Arc<MemoryFS>>) {
+        let inner = Arc::new(MemoryFS::default());
+        inner.create_dir("dir").unwrap();
+        for (name, len) in [("a", 1000), ("b", 1000), ("c", 1000)] {
+            inner
+                .create_file(&format!("dir/{name}"))
+                .unwrap()
+                .write_all(&vec![name.as_bytes()[0]; len])
+                .unwrap();
+        }
+
+        (inner.clone(), CachingFS::new(inner, budget))
+    }
+
+    fn read(fs: &impl FileSystem, path: &str) -> Vec<u8> {
+        fs.open_file(path).unwrap().read_into_vec().unwrap()
+    }
+
+    #[test]
+    fn caches_until_invalidated() {
+        let (inner, caching_fs) = cached_memory_fs(1 << 20);
+
+        assert_eq!(read(&caching_fs, "dir/a"), vec![b'a'; 1000]);
+        assert_eq!(caching_fs.metadata("/dir/./a").unwrap().len(), 1000);
+        assert!(caching_fs.cached_bytes() > 1000);
+
+        // changes to the inner filesystem aren't seen until they're invalidated
+        write!(inner.create_file("dir/a").unwrap(), "changed").unwrap();
+        assert_eq!(read(&caching_fs, "dir/a"), vec![b'a'; 1000]);
+        assert_eq!(caching_fs.metadata("dir/a").unwrap().len(), 1000);
+
+        caching_fs.invalidate("dir");
+        assert_eq!(read(&caching_fs, "dir/a"), b"changed");
+        assert_eq!(caching_fs.metadata("dir/a").unwrap().len(), 7);
+
+        caching_fs.invalidate_all();
+        assert_eq!(caching_fs.cached_bytes(), 0);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        // room for two files, but not three
+        let (inner, caching_fs) = cached_memory_fs(2500);
+
+        read(&caching_fs, "dir/a");
+        read(&caching_fs, "dir/b");
+        read(&caching_fs, "dir/a");
+        read(&caching_fs, "dir/c");
+        assert!(caching_fs.cached_bytes() <= 2500);
+
+        for name in ["a", "b", "c"] {
+            inner.remove_file(&format!("dir/{name}")).unwrap();
+        }
+        assert!(caching_fs.open_file("dir/a").is_ok());
+        assert!(caching_fs.open_file("dir/b").is_err());
+        assert!(caching_fs.open_file("dir/c").is_ok());
+
+        // files larger than the budget aren't cached at all
+        let (_, caching_fs) = cached_memory_fs(500);
+        read(&caching_fs, "dir/a");
+        assert_eq!(caching_fs.cached_bytes(), 0);
+    }
+
+    #[test]
+    fn changes_invalidate() {
+        let (_, caching_fs) = cached_memory_fs(1 << 20);
+
+        read(&caching_fs, "dir/a");
+        write!(caching_fs.create_file("dir/a").unwrap(), "rewritten").unwrap();
+        assert_eq!(read(&caching_fs, "dir/a"), b"rewritten");
+
+        read(&caching_fs, "dir/b");
+        caching_fs.rename("dir/b", "dir/renamed").unwrap();
+        assert!(caching_fs.open_file("dir/b").is_err());
+        assert_eq!(read(&caching_fs, "dir/renamed"), vec![b'b'; 1000]);
+
+        read(&caching_fs, "dir/c");
+        caching_fs.remove_file("dir/c").unwrap();
+        assert!(!caching_fs.exists("dir/c").unwrap());
+    }
+
+    #[test]
+    fn open_options() {
+        let (_, caching_fs) = cached_memory_fs(1 << 20);
+        check_open_options(&caching_fs, "dir/options");
+    }
+}
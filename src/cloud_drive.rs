@@ -0,0 +1,600 @@
+//! OAuth token providers and other pieces shared by the cloud drive backends.
+
+use crate::file::{File, Metadata};
+use crate::range_reader::convert_error_with;
+use crate::time::DateTime;
+use crate::util::{invalid_input, not_supported, now, Instant};
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::io;
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The token endpoint of Google's OAuth server.
+pub const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+/// The token endpoint of the Microsoft identity platform, for personal and work accounts.
+pub const MICROSOFT_TOKEN_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/token";
+/// How long before it expires an access token is refreshed, so that it doesn't expire in flight.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// A source of the OAuth access tokens requests are authorized with.
+pub trait TokenProvider: Send + Sync {
+    /// Returns a valid access token, refreshing it if it expired.
+    fn access_token(&self) -> crate::Result<String>;
+
+    /// Discards the current access token after the service rejected it, so that the next one is fresh.
+    fn invalidate(&self) {}
+}
+
+/// A fixed access token, which is never refreshed.
+impl TokenProvider for String {
+    fn access_token(&self) -> crate::Result<String> {
+        Ok(self.clone())
+    }
+}
+
+/// Access tokens obtained from an OAuth server with a refresh token, such as one an app was granted when the user
+/// signed in. Tokens are cached until shortly before they expire, and refresh tokens the server rotates are kept.
+pub struct RefreshToken {
+    agent: ureq::Agent,
+    token_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    scope: Option<String>,
+    state: Mutex<TokenState>,
+}
+
+/// The tokens of a `RefreshToken`.
+struct TokenState {
+    refresh_token: String,
+    /// The access token and when it's due to be refreshed.
+    access_token: Option<(String, Instant)>,
+}
+
+impl RefreshToken {
+    /// Creates a token provider for an OAuth server.
+    ///
+    /// # Arguments
+    /// `token_url`: The URL of the server's token endpoint.  
+    /// `client_id`: The identifier of the app.  
+    /// `refresh_token`: The refresh token.  
+    pub fn new<S: Into<String>, T: Into<String>, U: Into<String>>(
+        token_url: S,
+        client_id: T,
+        refresh_token: U,
+    ) -> Self {
+        Self {
+            agent: ureq::Agent::new(),
+            token_url: token_url.into(),
+            client_id: client_id.into(),
+            client_secret: None,
+            scope: None,
+            state: Mutex::new(TokenState {
+                refresh_token: refresh_token.into(),
+                access_token: None,
+            }),
+        }
+    }
+
+    /// Creates a token provider for a Google account.
+    ///
+    /// # Arguments
+    /// `client_id`: The identifier of the app.  
+    /// `client_secret`: The secret of the app.  
+    /// `refresh_token`: The refresh token.  
+    pub fn google<S: Into<String>, T: Into<String>, U: Into<String>>(
+        client_id: S,
+        client_secret: T,
+        refresh_token: U,
+    ) -> Self {
+        Self::new(GOOGLE_TOKEN_URL, client_id, refresh_token).client_secret(client_secret)
+    }
+
+    /// Creates a token provider for a Microsoft account, scoped to the user's files.
+    ///
+    /// # Arguments
+    /// `client_id`: The identifier of the app.  
+    /// `refresh_token`: The refresh token.  
+    pub fn microsoft<S: Into<String>, T: Into<String>>(client_id: S, refresh_token: T) -> Self {
+        Self::new(MICROSOFT_TOKEN_URL, client_id, refresh_token)
+            .scope("Files.ReadWrite.All offline_access")
+    }
+
+    /// # Arguments
+    /// `client_secret`: The secret of a confidential app.  
+    pub fn client_secret<S: Into<String>>(mut self, client_secret: S) -> Self {
+        self.client_secret = Some(client_secret.into());
+        self
+    }
+
+    /// # Arguments
+    /// `scope`: The space-separated scopes access tokens are requested for.  
+    pub fn scope<S: Into<String>>(mut self, scope: S) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    /// # Arguments
+    /// `agent`: The agent used to issue requests.  
+    pub fn agent(mut self, agent: ureq::Agent) -> Self {
+        self.agent = agent;
+        self
+    }
+}
+
+impl TokenProvider for RefreshToken {
+    fn access_token(&self) -> crate::Result<String> {
+        let mut state = self.state.lock();
+        if let Some((access_token, refresh_at)) = &state.access_token {
+            if Instant::now() < *refresh_at {
+                return Ok(access_token.clone());
+            }
+        }
+
+        let mut form = vec![
+            ("grant_type", "refresh_token"),
+            ("client_id", &self.client_id),
+            ("refresh_token", &state.refresh_token),
+        ];
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope));
+        }
+        let response = match self.agent.post(&self.token_url).send_form(&form) {
+            Ok(response) => response,
+            // the server describes why the grant was refused
+            Err(ureq::Error::Status(400 | 401, response)) => {
+                let body = parse_json(response).unwrap_or_default();
+                let error = body["error"].as_str().unwrap_or("invalid_grant");
+                return Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!("OAuth token refresh failed: {error}"),
+                ));
+            }
+            Err(err) => return Err(io::Error::other(err)),
+        };
+
+        let body = parse_json(response)?;
+        let access_token = body["access_token"]
+            .as_str()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Missing access token"))?
+            .to_owned();
+        let expires_in = Duration::from_secs(body["expires_in"].as_u64().unwrap_or(3600));
+        if let Some(refresh_token) = body["refresh_token"].as_str() {
+            state.refresh_token = refresh_token.to_owned();
+        }
+        state.access_token = Some((
+            access_token.clone(),
+            Instant::now() + expires_in.saturating_sub(EXPIRY_MARGIN),
+        ));
+        Ok(access_token)
+    }
+
+    fn invalidate(&self) {
+        self.state.lock().access_token = None;
+    }
+}
+
+/// The body of a request to a cloud drive.
+#[derive(Clone, Copy)]
+pub(crate) enum Body<'a> {
+    Empty,
+    Json(&'a Value),
+    Bytes(&'a [u8]),
+}
+
+/// A client of a cloud drive's REST API, which authorizes requests with OAuth access tokens.
+#[derive(Clone)]
+pub(crate) struct Client {
+    pub agent: ureq::Agent,
+    pub tokens: Arc<dyn TokenProvider>,
+}
+
+impl Client {
+    /// Issues an authorized request. A request that's rejected as unauthorized is retried once with a fresh token.
+    ///
+    /// # Arguments
+    /// `method`: The HTTP method.  
+    /// `url`: The URL of the request.  
+    /// `query`: The query parameters.  
+    /// `body`: The body of the request.  
+    pub fn call(
+        &self,
+        method: &str,
+        url: &str,
+        query: &[(&str, &str)],
+        body: Body,
+    ) -> crate::Result<ureq::Response> {
+        let mut retried = false;
+        loop {
+            let token = self.tokens.access_token()?;
+            let mut request = self
+                .agent
+                .request(method, url)
+                .set("Authorization", &format!("Bearer {token}"));
+            for (name, value) in query {
+                request = request.query(name, value);
+            }
+
+            let result = match body {
+                Body::Empty => request.call(),
+                Body::Json(value) => request
+                    .set("Content-Type", "application/json")
+                    .send_string(&value.to_string()),
+                Body::Bytes(bytes) => request
+                    .set("Content-Type", "application/octet-stream")
+                    .send_bytes(bytes),
+            };
+            match result {
+                Err(ureq::Error::Status(401, _)) if !retried => {
+                    self.tokens.invalidate();
+                    retried = true;
+                }
+                result => return result.map_err(convert_error),
+            }
+        }
+    }
+
+    /// Issues an authorized request and parses its JSON response.
+    ///
+    /// # Arguments
+    /// `method`: The HTTP method.  
+    /// `url`: The URL of the request.  
+    /// `query`: The query parameters.  
+    /// `body`: The body of the request.  
+    pub fn call_json(
+        &self,
+        method: &str,
+        url: &str,
+        query: &[(&str, &str)],
+        body: Body,
+    ) -> crate::Result<Value> {
+        parse_json(self.call(method, url, query, body)?)
+    }
+
+    /// Downloads the contents of a file.
+    ///
+    /// # Arguments
+    /// `url`: The URL of the contents.  
+    /// `query`: The query parameters.  
+    pub fn download(&self, url: &str, query: &[(&str, &str)]) -> crate::Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        self.call("GET", url, query, Body::Empty)?
+            .into_reader()
+            .read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+}
+
+/// Parses the JSON body of a response.
+///
+/// # Arguments
+/// `response`: The response.  
+pub(crate) fn parse_json(response: ureq::Response) -> crate::Result<Value> {
+    serde_json::from_reader(response.into_reader())
+        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+}
+
+/// Converts a request error to an IO error.
+pub(crate) fn convert_error(err: ureq::Error) -> io::Error {
+    convert_error_with(err, |code, response| match code {
+        401 => access_denied(),
+        409 => io::Error::new(ErrorKind::AlreadyExists, "Conflict"),
+        code => {
+            // both services describe the error in the body
+            let body = parse_json(response).unwrap_or_default();
+            let message = body["error"]["message"].as_str().unwrap_or("Unknown");
+            let kind = match code {
+                // Google Drive forbids requests once the storage quota is used up
+                403 if body["error"]["errors"][0]["reason"] == "storageQuotaExceeded" => {
+                    ErrorKind::QuotaExceeded
+                }
+                403 => return access_denied(),
+                507 => ErrorKind::QuotaExceeded,
+                _ => ErrorKind::Other,
+            };
+            io::Error::new(kind, format!("HTTP error {code}: {message}"))
+        }
+    })
+}
+
+/// Returns an error indicating that the request wasn't authorized.
+fn access_denied() -> io::Error {
+    io::Error::new(ErrorKind::PermissionDenied, "Access denied")
+}
+
+/// Parses a UTC timestamp of the form `YYYY-MM-DDTHH:MM:SSZ`, optionally with fractional seconds.
+///
+/// # Arguments
+/// `value`: The timestamp.  
+pub(crate) fn parse_time(value: &str) -> Option<SystemTime> {
+    let value = value.strip_suffix('Z')?;
+    let (date_time, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let field = |range: std::ops::Range<usize>| date_time.get(range)?.parse::<u32>().ok();
+    if date_time.len() != 19 {
+        return None;
+    }
+
+    let time = DateTime {
+        year: i64::from(field(0..4)?),
+        month: field(5..7)?,
+        day: field(8..10)?,
+        hour: field(11..13)?,
+        minute: field(14..16)?,
+        second: field(17..19)?,
+    }
+    .to_system_time()?;
+    let nanos = match fraction.get(..fraction.len().min(9)) {
+        Some("") | None => 0,
+        Some(digits) => digits.parse::<u32>().ok()? * 10u32.pow(9 - digits.len() as u32),
+    };
+    Some(time + Duration::from_nanos(nanos.into()))
+}
+
+/// Uploads the contents of a file.
+pub(crate) type Upload = Box<dyn FnMut(&[u8]) -> crate::Result<()>>;
+
+/// A remote file that's downloaded when it's opened and uploaded when it's flushed after being written.
+pub(crate) struct DriveFile {
+    pub contents: Cursor<Vec<u8>>,
+    pub modified: Option<SystemTime>,
+    /// Uploads the file, or is `None` if it's read-only.
+    pub upload: Option<Upload>,
+    pub append: bool,
+    /// True if the file was written since it was last uploaded.
+    pub dirty: bool,
+}
+
+impl File for DriveFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(Metadata {
+            modified: self.modified,
+            ..Metadata::file(self.contents.get_ref().len() as u64)
+        })
+    }
+}
+
+impl Read for DriveFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.contents.read(buf)
+    }
+}
+
+impl Seek for DriveFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        if let SeekFrom::Current(offset) = pos {
+            if self
+                .contents
+                .position()
+                .checked_add_signed(offset)
+                .is_none()
+            {
+                return Err(invalid_input(
+                    "Invalid seek to a negative or overflowing position",
+                ));
+            }
+        }
+        self.contents.seek(pos)
+    }
+}
+
+impl Write for DriveFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.upload.is_none() {
+            return Err(not_supported());
+        }
+        if self.append {
+            self.contents.seek(SeekFrom::End(0))?;
+        }
+
+        self.dirty = true;
+        self.contents.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let Some(upload) = &mut self.upload else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+
+        upload(self.contents.get_ref())?;
+        self.modified = Some(now());
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Drop for DriveFile {
+    fn drop(&mut self) {
+        // errors can't be returned from here, so callers that need to see them must flush first
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::cloud_drive::{parse_time, RefreshToken, TokenProvider};
+    use std::collections::BTreeMap;
+    use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    /// A request received by a test server.
+    pub(crate) struct Request {
+        pub method: String,
+        /// The decoded path.
+        pub path: String,
+        /// The decoded query parameters.
+        pub query: BTreeMap<String, String>,
+        /// The headers, with lowercase names.
+        pub headers: BTreeMap<String, String>,
+        pub body: Vec<u8>,
+    }
+
+    impl Request {
+        /// Returns the body parsed as JSON.
+        pub fn json(&self) -> serde_json::Value {
+            serde_json::from_slice(&self.body).unwrap()
+        }
+    }
+
+    /// Decodes a percent-encoded URL component, in which `+` is a space in the query.
+    ///
+    /// # Arguments
+    /// `value`: The encoded component.  
+    /// `query`: True if the component is part of the query.  
+    pub(crate) fn decode(value: &str, query: bool) -> String {
+        let mut bytes = Vec::new();
+        let mut chars = value.bytes();
+        while let Some(byte) = chars.next() {
+            match byte {
+                b'%' => {
+                    let hex = [chars.next().unwrap(), chars.next().unwrap()];
+                    bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).unwrap(), 16).unwrap());
+                }
+                b'+' if query => bytes.push(b' '),
+                byte => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).unwrap()
+    }
+
+    /// Serves HTTP requests with `handler`, which returns the status, additional headers and body of the response.
+    /// Returns the URL of the server.
+    ///
+    /// # Arguments
+    /// `handler`: Handles a request, given the URL of the server.  
+    pub(crate) fn serve<
+        H: FnMut(&str, Request) -> (&'static str, String, Vec<u8>) + Send + 'static,
+    >(
+        mut handler: H,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server_url = url.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut parts = request_line.split_whitespace();
+                let (method, target) = (parts.next().unwrap(), parts.next().unwrap());
+
+                let mut headers = BTreeMap::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                        break;
+                    }
+                    let (name, value) = line.split_once(':').unwrap();
+                    headers.insert(name.to_lowercase(), value.trim().to_owned());
+                }
+                let mut body = vec![
+                    0;
+                    headers
+                        .get("content-length")
+                        .map_or(0, |len| len.parse().unwrap())
+                ];
+                reader.read_exact(&mut body).unwrap();
+
+                let (path, query) = target.split_once('?').unwrap_or((target, ""));
+                let query = query
+                    .split('&')
+                    .filter_map(|param| param.split_once('='))
+                    .map(|(name, value)| (decode(name, true), decode(value, true)))
+                    .collect();
+                let request = Request {
+                    method: method.to_owned(),
+                    path: decode(path, false),
+                    query,
+                    headers,
+                    body,
+                };
+
+                let (status, headers, body) = handler(&server_url, request);
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn refresh_token() {
+        // the server rotates the refresh token each time, and tokens expire within the margin after the first
+        let refreshes = Arc::new(Mutex::new(Vec::new()));
+        let served = refreshes.clone();
+        let url = serve(move |_, request| {
+            let form: BTreeMap<_, _> = std::str::from_utf8(&request.body)
+                .unwrap()
+                .split('&')
+                .filter_map(|param| param.split_once('='))
+                .map(|(name, value)| (decode(name, true), decode(value, true)))
+                .collect();
+            if form["client_id"] != "client" || form["grant_type"] != "refresh_token" {
+                return ("400 Bad Request", String::new(), Vec::new());
+            }
+            if form["refresh_token"] == "revoked" {
+                let body = br#"{"error":"invalid_grant"}"#;
+                return ("400 Bad Request", String::new(), body.to_vec());
+            }
+
+            let mut refreshes = served.lock().unwrap();
+            refreshes.push(form["refresh_token"].clone());
+            let n = refreshes.len();
+            let expires_in = if n == 1 { 3600 } else { 30 };
+            let body = format!(
+                r#"{{"access_token":"access{n}","expires_in":{expires_in},"refresh_token":"refresh{n}"}}"#
+            );
+            ("200 OK", String::new(), body.into_bytes())
+        });
+
+        let tokens = RefreshToken::new(format!("{url}/token"), "client", "refresh0");
+        assert_eq!(tokens.access_token().unwrap(), "access1");
+        assert_eq!(tokens.access_token().unwrap(), "access1");
+        tokens.invalidate();
+        assert_eq!(tokens.access_token().unwrap(), "access2");
+        // the second token is already due to be refreshed
+        assert_eq!(tokens.access_token().unwrap(), "access3");
+        itertools::assert_equal(
+            refreshes.lock().unwrap().iter(),
+            &["refresh0", "refresh1", "refresh2"],
+        );
+
+        let revoked = RefreshToken::new(format!("{url}/token"), "client", "revoked");
+        let err = revoked.access_token().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("invalid_grant"));
+    }
+
+    #[test]
+    fn time() {
+        let time = |seconds, nanos| SystemTime::UNIX_EPOCH + Duration::new(seconds, nanos);
+        assert_eq!(
+            parse_time("2024-01-02T03:04:05Z"),
+            Some(time(1704164645, 0))
+        );
+        assert_eq!(
+            parse_time("2024-01-02T03:04:05.25Z"),
+            Some(time(1704164645, 250_000_000))
+        );
+        assert_eq!(parse_time("2024-01-02T03:04:05"), None);
+        assert_eq!(parse_time("2024-01-02"), None);
+    }
+}
@@ -0,0 +1,602 @@
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::{already_exists, invalid_path, not_found, not_writable};
+use crate::FileSystem;
+use itertools::Itertools;
+use std::collections::HashSet;
+use std::io;
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The magic that starts every compressed file.
+const MAGIC: [u8; 4] = *b"VFZ\x01";
+/// The length of the header of a compressed file: the magic and the length of the decompressed contents.
+const HEADER_LEN: usize = MAGIC.len() + 8;
+
+/// Decides which files are stored compressed.
+#[derive(Debug, Clone)]
+pub struct CompressionPolicy {
+    level: i32,
+    min_size: u64,
+    extensions: Option<HashSet<String>>,
+    excluded_extensions: HashSet<String>,
+}
+
+impl CompressionPolicy {
+    /// # Arguments
+    /// `level`: The zstd compression level, from 1 to 22. The default is 3.  
+    pub fn level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// # Arguments
+    /// `min_size`: The size below which files aren't worth compressing. The default is 128 bytes.  
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// # Arguments
+    /// `extensions`: The extensions of the files to compress, without the leading dot. Files with any other extension,
+    /// or without one, aren't compressed. By default, files with any extension are compressed.  
+    pub fn extensions<I: IntoIterator<Item = S>, S: AsRef<str>>(mut self, extensions: I) -> Self {
+        self.extensions = Some(
+            extensions
+                .into_iter()
+                .map(|extension| extension.as_ref().to_ascii_lowercase())
+                .collect(),
+        );
+        self
+    }
+
+    /// # Arguments
+    /// `extensions`: Further extensions of files never to compress, without the leading dot. By default, common
+    /// formats that are compressed already, such as archives, images and media, are excluded.  
+    pub fn exclude_extensions<I: IntoIterator<Item = S>, S: AsRef<str>>(
+        mut self,
+        extensions: I,
+    ) -> Self {
+        self.excluded_extensions.extend(
+            extensions
+                .into_iter()
+                .map(|extension| extension.as_ref().to_ascii_lowercase()),
+        );
+        self
+    }
+
+    /// Returns true if a file should be stored compressed. Files are stored uncompressed regardless if compression
+    /// doesn't make them smaller.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file.  
+    /// `len`: The length of the file.  
+    pub fn should_compress(&self, path: &str, len: u64) -> bool {
+        if len < self.min_size {
+            return false;
+        }
+
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match (&self.extensions, extension) {
+            (_, Some(extension)) if self.excluded_extensions.contains(&extension) => false,
+            (Some(extensions), Some(extension)) => extensions.contains(&extension),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        Self {
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            min_size: 128,
+            extensions: None,
+            excluded_extensions: [
+                "7z", "avif", "br", "bz2", "flac", "gif", "gz", "heic", "jar", "jpeg", "jpg",
+                "lz4", "m4a", "mkv", "mov", "mp3", "mp4", "ogg", "opus", "png", "rar", "tgz",
+                "webm", "webp", "woff2", "xz", "zip", "zst",
+            ]
+            .into_iter()
+            .map(str::to_owned)
+            .collect(),
+        }
+    }
+}
+
+/// A filesystem that transparently stores the files of another filesystem zstd-compressed, according to a
+/// `CompressionPolicy`. Files are decompressed as they're read, and report the length of their decompressed contents
+/// in their metadata.
+///
+/// Compressed files are prefixed with a header holding their decompressed length, so files written outside of this
+/// filesystem are read as they are, and files keep their encoding when they're renamed. Finding the length of a file
+/// requires reading its header, so listing directories opens every file within them.
+///
+/// Opening a compressed file, or opening any file for writing, reads its contents into memory. Modified files are
+/// encoded and written back when they're flushed or dropped.
+pub struct CompressedFS<FS> {
+    inner: Arc<FS>,
+    policy: Arc<CompressionPolicy>,
+}
+
+impl<FS: FileSystem> CompressedFS<FS> {
+    /// Creates a new compressed filesystem.
+    ///
+    /// # Arguments
+    /// `inner`: The filesystem the files are stored in.  
+    /// `policy`: The policy deciding which files are compressed.  
+    pub fn new(inner: FS, policy: CompressionPolicy) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            policy: Arc::new(policy),
+        }
+    }
+
+    /// Returns the filesystem the files are stored in.
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Returns the metadata of an entry, with the length of compressed files converted to their decompressed length.
+    ///
+    /// # Arguments
+    /// `path`: The path of the entry in the inner filesystem.  
+    /// `metadata`: The metadata of the entry in the inner filesystem.  
+    fn outer_metadata(&self, path: &str, metadata: Metadata) -> crate::Result<Metadata> {
+        if !metadata.is_file() || metadata.len < HEADER_LEN as u64 {
+            return Ok(metadata);
+        }
+
+        let mut header = [0; HEADER_LEN];
+        self.inner.open_file(path)?.read_exact(&mut header)?;
+        Ok(Metadata {
+            len: decompressed_len(&header).unwrap_or(metadata.len),
+            ..metadata
+        })
+    }
+}
+
+impl<FS: FileSystem + 'static> FileSystem for CompressedFS<FS> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.inner.create_dir(path)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.outer_metadata(path, self.inner.metadata(path)?)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        let path = normalize_and_relativize(path)
+            .to_str()
+            .ok_or_else(invalid_path)?
+            .to_owned();
+
+        options.validate()?;
+        if !options.writable() {
+            let mut file = self.inner.open_file_options(&path, options)?;
+            let mut header = [0; HEADER_LEN];
+            let header_len = read_up_to(&mut file, &mut header)?;
+            if decompressed_len(&header[..header_len]).is_none() {
+                // uncompressed files are read directly
+                file.seek(SeekFrom::Start(0))?;
+                return Ok(file);
+            }
+
+            let metadata = file.metadata()?;
+            let contents = zstd::stream::decode_all(file)?;
+            return Ok(Box::new(CompressedFile {
+                fs: self.inner.clone(),
+                policy: self.policy.clone(),
+                path,
+                contents: Cursor::new(contents),
+                metadata,
+                append: false,
+                write: false,
+                dirty: false,
+            }));
+        }
+
+        let exists = match self.inner.metadata(&path) {
+            Ok(_) if options.create_new => return Err(already_exists()),
+            Ok(metadata) if metadata.is_file() => true,
+            Ok(_) => return Err(not_found()),
+            Err(err)
+                if err.kind() == ErrorKind::NotFound && (options.create || options.create_new) =>
+            {
+                false
+            }
+            Err(err) => return Err(err),
+        };
+
+        let contents = if exists && !options.truncate {
+            decode(self.inner.open_file(&path)?.read_into_vec()?)?
+        } else {
+            Vec::new()
+        };
+
+        let mut file = CompressedFile {
+            fs: self.inner.clone(),
+            policy: self.policy.clone(),
+            path,
+            contents: Cursor::new(contents),
+            metadata: Metadata::file(0),
+            append: options.append,
+            write: true,
+            dirty: !exists || options.truncate,
+        };
+        // new and truncated files are visible as soon as they're opened
+        file.flush()?;
+        file.metadata = self.inner.metadata(&file.path)?;
+
+        Ok(Box::new(file))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let entries = self
+            .inner
+            .read_dir(path)?
+            .map(|entry| {
+                let entry = entry?;
+                let inner_path = entry.path.to_str().ok_or_else(invalid_path)?;
+
+                Ok(DirEntry {
+                    metadata: self.outer_metadata(inner_path, entry.metadata)?,
+                    path: entry.path,
+                })
+            })
+            .collect_vec();
+
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.inner.remove_dir(path)
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.inner.remove_file(path)
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        self.inner.create_dir_with(path, mode)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        self.inner.rename(from, to)
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        self.inner.stats()
+    }
+}
+
+/// A handle to the decompressed contents of a file. The contents are encoded and written back to the inner
+/// filesystem when they're flushed after being modified.
+struct CompressedFile<FS: FileSystem> {
+    fs: Arc<FS>,
+    policy: Arc<CompressionPolicy>,
+    path: String,
+    contents: Cursor<Vec<u8>>,
+    metadata: Metadata,
+    append: bool,
+    write: bool,
+    dirty: bool,
+}
+
+impl<FS: FileSystem> Read for CompressedFile<FS> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.contents.read(buf)
+    }
+}
+
+impl<FS: FileSystem> Seek for CompressedFile<FS> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.contents.seek(pos)
+    }
+}
+
+impl<FS: FileSystem> Write for CompressedFile<FS> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.write {
+            return Err(not_writable());
+        }
+        if self.append {
+            self.contents.seek(SeekFrom::End(0))?;
+        }
+
+        self.dirty = true;
+        self.contents.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let encoded = encode(&self.policy, &self.path, self.contents.get_ref())?;
+        let mut file = self.fs.create_file(&self.path)?;
+        file.write_all(&encoded)?;
+        file.flush()?;
+        self.dirty = false;
+
+        Ok(())
+    }
+}
+
+impl<FS: FileSystem> File for CompressedFile<FS> {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(Metadata {
+            len: self.contents.get_ref().len() as u64,
+            ..self.metadata.clone()
+        })
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        if !self.write {
+            return Err(not_writable());
+        }
+
+        self.contents.get_mut().resize(len as usize, 0);
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+impl<FS: FileSystem> Drop for CompressedFile<FS> {
+    fn drop(&mut self) {
+        // errors can't be reported here, so callers who need to know should flush first
+        let _ = self.flush();
+    }
+}
+
+/// Returns the decompressed length of a file from its header, or `None` if it isn't compressed.
+///
+/// # Arguments
+/// `header`: The start of the file.  
+fn decompressed_len(header: &[u8]) -> Option<u64> {
+    let len = header.strip_prefix(&MAGIC)?.get(..8)?;
+    // unwrap: the slice is 8 bytes long
+    Some(u64::from_le_bytes(len.try_into().unwrap()))
+}
+
+/// Encodes the contents of a file as they're stored in the inner filesystem.
+///
+/// # Arguments
+/// `policy`: The compression policy.  
+/// `path`: The path of the file.  
+/// `contents`: The contents of the file.  
+fn encode(policy: &CompressionPolicy, path: &str, contents: &[u8]) -> io::Result<Vec<u8>> {
+    // uncompressed files that look compressed are compressed regardless, so that they're read back correctly
+    let ambiguous = contents.starts_with(&MAGIC);
+    if !ambiguous && !policy.should_compress(path, contents.len() as u64) {
+        return Ok(contents.to_vec());
+    }
+
+    let mut encoded = MAGIC.to_vec();
+    encoded.extend((contents.len() as u64).to_le_bytes());
+    encoded.extend(zstd::bulk::compress(contents, policy.level)?);
+    if !ambiguous && encoded.len() >= contents.len() {
+        return Ok(contents.to_vec());
+    }
+
+    Ok(encoded)
+}
+
+/// Decodes the contents of a file as they're stored in the inner filesystem.
+///
+/// # Arguments
+/// `stored`: The stored contents of the file.  
+fn decode(stored: Vec<u8>) -> io::Result<Vec<u8>> {
+    if decompressed_len(&stored).is_none() {
+        return Ok(stored);
+    }
+
+    zstd::stream::decode_all(&stored[HEADER_LEN..])
+}
+
+/// Reads until the buffer is full or the end of the file is reached, returning the number of bytes read.
+///
+/// # Arguments
+/// `file`: The file.  
+/// `buf`: The buffer.  
+fn read_up_to(file: &mut Box<dyn File>, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read_len = 0;
+    while read_len < buf.len() {
+        match file.read(&mut buf[read_len..]) {
+            Ok(0) => break,
+            Ok(len) => read_len += len,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(read_len)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compressed_fs::{CompressedFS, CompressionPolicy, MAGIC};
+    use crate::file::OpenOptions;
+    use crate::memory_fs::MemoryFS;
+    use crate::util::test::{check_open_options, check_set_len, read_directory};
+    use crate::FileSystem;
+    use std::io::{Seek, SeekFrom, Write};
+    use std::sync::Arc;
+
+    fn text(len: usize) -> String {
+        "all work and no play makes jack a dull boy\n"
+            .chars()
+            .cycle()
+            .take(len)
+            .collect()
+    }
+
+    fn stored_len(fs: &MemoryFS, path: &str) -> u64 {
+        fs.metadata(path).unwrap().len()
+    }
+
+    #[test]
+    fn round_trip() {
+        let inner = Arc::new(MemoryFS::default());
+        let compressed_fs = CompressedFS::new(inner.clone(), CompressionPolicy::default());
+        let contents = text(10000);
+
+        compressed_fs.create_dir("dir").unwrap();
+        write!(
+            compressed_fs.create_file("dir/log.txt").unwrap(),
+            "{contents}"
+        )
+        .unwrap();
+
+        assert!(stored_len(&inner, "dir/log.txt") < 1000);
+        assert_eq!(compressed_fs.metadata("dir/log.txt").unwrap().len(), 10000);
+        assert_eq!(
+            read_directory(&compressed_fs, "dir")["dir/log.txt"].len(),
+            10000
+        );
+        assert_eq!(
+            compressed_fs
+                .open_file("dir/log.txt")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            contents
+        );
+
+        // modifying a compressed file
+        let mut file = compressed_fs
+            .open_file_options("dir/log.txt", &OpenOptions::default().write(true))
+            .unwrap();
+        file.seek(SeekFrom::Start(4)).unwrap();
+        file.write_all(b"WORK").unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 10000);
+        drop(file);
+        assert!(compressed_fs
+            .open_file("dir/log.txt")
+            .unwrap()
+            .read_into_string()
+            .unwrap()
+            .starts_with("all WORK and"));
+
+        // appending
+        write!(
+            compressed_fs
+                .open_file_options("dir/log.txt", &OpenOptions::default().append(true))
+                .unwrap(),
+            "the end"
+        )
+        .unwrap();
+        assert_eq!(compressed_fs.metadata("dir/log.txt").unwrap().len(), 10007);
+    }
+
+    #[test]
+    fn policy() {
+        let inner = Arc::new(MemoryFS::default());
+        let policy = CompressionPolicy::default()
+            .min_size(16)
+            .exclude_extensions(["bin"]);
+        let compressed_fs = CompressedFS::new(inner.clone(), policy);
+
+        for (path, compressed) in [
+            ("small.txt", false),
+            ("image.PNG", false),
+            ("data.bin", false),
+            ("large.txt", true),
+            ("no_extension", true),
+        ] {
+            let contents = if path == "small.txt" {
+                text(10)
+            } else {
+                text(1000)
+            };
+            write!(compressed_fs.create_file(path).unwrap(), "{contents}").unwrap();
+
+            assert_eq!(
+                stored_len(&inner, path) < contents.len() as u64,
+                compressed,
+                "{path}"
+            );
+            assert_eq!(
+                compressed_fs
+                    .open_file(path)
+                    .unwrap()
+                    .read_into_string()
+                    .unwrap(),
+                contents
+            );
+        }
+
+        let only_text = CompressionPolicy::default().extensions(["txt"]);
+        assert!(only_text.should_compress("notes.TXT", 1000));
+        assert!(!only_text.should_compress("notes.md", 1000));
+        assert!(!only_text.should_compress("notes", 1000));
+    }
+
+    #[test]
+    fn incompressible_and_ambiguous() {
+        let inner = Arc::new(MemoryFS::default());
+        let compressed_fs = CompressedFS::new(inner.clone(), CompressionPolicy::default());
+
+        // files that don't get smaller are stored as they are
+        let mut state = 0x2545_f491_u32;
+        let noise = (0..1000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        compressed_fs
+            .create_file("noise")
+            .unwrap()
+            .write_all(&noise)
+            .unwrap();
+        assert_eq!(stored_len(&inner, "noise"), 1000);
+
+        // files that look compressed are compressed anyway
+        let mut lookalike = MAGIC.to_vec();
+        lookalike.extend(b"\xff\xff\xff\xff\xff\xff\xff\xff not really");
+        compressed_fs
+            .create_file("lookalike")
+            .unwrap()
+            .write_all(&lookalike)
+            .unwrap();
+        assert_eq!(
+            compressed_fs
+                .open_file("lookalike")
+                .unwrap()
+                .read_into_vec()
+                .unwrap(),
+            lookalike
+        );
+        assert_eq!(
+            compressed_fs.metadata("lookalike").unwrap().len(),
+            lookalike.len() as u64
+        );
+
+        // files created and truncated exist straight away
+        let _file = compressed_fs.create_file("new").unwrap();
+        assert!(inner.exists("new").unwrap());
+        assert!(compressed_fs.open_file("missing").is_err());
+    }
+
+    #[test]
+    fn open_options() {
+        let compressed_fs = CompressedFS::new(MemoryFS::default(), CompressionPolicy::default());
+        check_open_options(&compressed_fs, "options");
+    }
+
+    #[test]
+    fn set_len() {
+        let compressed_fs = CompressedFS::new(MemoryFS::default(), CompressionPolicy::default());
+        check_set_len(&compressed_fs, "set_len");
+    }
+}
@@ -0,0 +1,432 @@
+use crate::file::{DirEntry, File, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::{already_exists, create_dir_all, invalid_input, invalid_path, not_found};
+use crate::FileSystem;
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// The prefix of the names of the markers that hide entries of the base.
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// A copy-on-write filesystem over a read-only base, such as a `ZipFS` or `TarFS` of shipped game data, so that it can
+/// be patched with user modifications without being modified. Reads fall through to the base unless the upper layer
+/// contains the entry, and every write and removal lands in the upper layer. Writing to a file from the base copies it
+/// up to the upper layer first.
+///
+/// Removing an entry from the base hides it, and everything within it, with a whiteout marker in the upper layer: an
+/// empty file named after the entry with a `.wh.` prefix. Whiteouts are persisted along with the rest of the upper
+/// layer, so the modifications survive the filesystem being recreated over the same layers. Entries created in place
+/// of a removed entry only contain what is written to the upper layer. Names beginning with `.wh.` are reserved.
+pub struct CowFS<B, U> {
+    base: B,
+    upper: U,
+}
+
+impl<B: FileSystem, U: FileSystem> CowFS<B, U> {
+    /// Creates a new copy-on-write filesystem.
+    ///
+    /// # Arguments
+    /// `base`: The read-only base, which is never modified.  
+    /// `upper`: The writable layer, which receives all writes and whiteouts.  
+    pub fn new(base: B, upper: U) -> Self {
+        Self { base, upper }
+    }
+
+    /// Returns the read-only base.
+    pub fn base(&self) -> &B {
+        &self.base
+    }
+
+    /// Returns the writable layer.
+    pub fn upper(&self) -> &U {
+        &self.upper
+    }
+
+    /// Returns true if the entry at `path` in the base is hidden by a whiteout of it or any of its ancestors.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn is_whited_out(&self, path: &Path) -> crate::Result<bool> {
+        for ancestor in path.ancestors() {
+            if ancestor.as_os_str().is_empty() {
+                break;
+            }
+            if self.upper_metadata(&whiteout_path(ancestor)?)?.is_some() {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Returns the metadata of the entry at `path` in the upper layer, if present.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn upper_metadata(&self, path: &str) -> crate::Result<Option<Metadata>> {
+        // the root is a directory in every layer
+        if path.is_empty() {
+            return Ok(Some(Metadata::directory()));
+        }
+
+        match self.upper.metadata(path) {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns the metadata of the entry at `path` in the base, if present and not hidden by a whiteout.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn base_metadata(&self, path: &Path) -> crate::Result<Option<Metadata>> {
+        if path.as_os_str().is_empty() {
+            return Ok(Some(Metadata::directory()));
+        }
+        if self.is_whited_out(path)? {
+            return Ok(None);
+        }
+
+        match self.base.metadata(path.to_str().ok_or_else(invalid_path)?) {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Creates the parent directories of `path` in the upper layer, if they aren't already there. The parent must be a
+    /// directory in the copy-on-write filesystem.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn copy_up_parent(&self, path: &Path) -> crate::Result<()> {
+        let parent = path.parent().ok_or_else(invalid_path)?;
+        let parent = parent.to_str().ok_or_else(invalid_path)?;
+        if parent.is_empty() {
+            return Ok(());
+        }
+        if !self.metadata(parent)?.is_directory() {
+            return Err(not_found());
+        }
+
+        create_dir_all(&self.upper, parent)
+    }
+
+    /// Hides the entry at `path` in the base, if there is one.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn white_out(&self, path: &Path) -> crate::Result<()> {
+        if self.base_metadata(path)?.is_some() {
+            self.copy_up_parent(path)?;
+            self.upper.create_file(&whiteout_path(path)?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the entries of the directory at `path` in the upper layer, without its whiteouts, and the names hidden
+    /// by the whiteouts.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn upper_entries(&self, path: &str) -> crate::Result<(Vec<DirEntry>, HashSet<OsString>)> {
+        let mut entries = Vec::new();
+        let mut whiteouts = HashSet::new();
+        if self
+            .upper_metadata(path)?
+            .is_some_and(|metadata| metadata.is_directory())
+        {
+            for entry in self.upper.read_dir(path)? {
+                let entry = entry?;
+                let whited_out = entry
+                    .path
+                    .file_name()
+                    .and_then(OsStr::to_str)
+                    .and_then(|name| name.strip_prefix(WHITEOUT_PREFIX));
+                match whited_out {
+                    Some(name) => {
+                        whiteouts.insert(OsString::from(name));
+                    }
+                    None => entries.push(entry),
+                }
+            }
+        }
+
+        Ok((entries, whiteouts))
+    }
+}
+
+impl<B: FileSystem, U: FileSystem> FileSystem for CowFS<B, U> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        let normalized_path = normalize_and_relativize(path);
+        check_reserved(&normalized_path)?;
+        if self.exists(path)? {
+            return Err(already_exists());
+        }
+
+        self.copy_up_parent(&normalized_path)?;
+        self.upper
+            .create_dir(normalized_path.to_str().ok_or_else(invalid_path)?)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        let normalized_path = normalize_and_relativize(path);
+        check_reserved(&normalized_path)?;
+        if let Some(metadata) =
+            self.upper_metadata(normalized_path.to_str().ok_or_else(invalid_path)?)?
+        {
+            return Ok(metadata);
+        }
+        self.base_metadata(&normalized_path)?.ok_or_else(not_found)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        let normalized_path = normalize_and_relativize(path);
+        check_reserved(&normalized_path)?;
+        let path = normalized_path.to_str().ok_or_else(invalid_path)?;
+        options.validate()?;
+
+        if self.upper_metadata(path)?.is_none() {
+            match self.base_metadata(&normalized_path)? {
+                Some(metadata) if !metadata.is_file() => return Err(not_found()),
+                Some(_) if options.create_new => return Err(already_exists()),
+                Some(_) if !options.writable() => {
+                    return self.base.open_file_options(path, options)
+                }
+                Some(_) => {
+                    self.copy_up_parent(&normalized_path)?;
+                    let mut upper_file = self.upper.create_file(path)?;
+                    if !options.truncate {
+                        io::copy(&mut self.base.open_file(path)?, &mut upper_file)?;
+                    }
+                }
+                None if options.create || options.create_new => {
+                    self.copy_up_parent(&normalized_path)?
+                }
+                None => return Err(not_found()),
+            }
+        }
+
+        self.upper.open_file_options(path, options)
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let normalized_path = normalize_and_relativize(path);
+        let path = normalized_path.to_str().ok_or_else(invalid_path)?;
+        if !self.metadata(path)?.is_directory() {
+            return Err(not_found());
+        }
+
+        // entries in the upper layer shadow those with the same name in the base
+        let (mut entries, whiteouts) = self.upper_entries(path)?;
+        let mut names = entries
+            .iter()
+            .map(|entry| entry.path.file_name().map(OsStr::to_owned))
+            .collect::<HashSet<_>>();
+        if self
+            .base_metadata(&normalized_path)?
+            .is_some_and(|metadata| metadata.is_directory())
+        {
+            for entry in self.base.read_dir(path)? {
+                let entry = entry?;
+                let name = entry.path.file_name().map(OsStr::to_owned);
+                let hidden = name.as_ref().is_some_and(|name| whiteouts.contains(name));
+                if !hidden && names.insert(name) {
+                    entries.push(entry);
+                }
+            }
+        }
+
+        Ok(Box::new(entries.into_iter().map(Ok)))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        let normalized_path = normalize_and_relativize(path);
+        let path = normalized_path.to_str().ok_or_else(invalid_path)?;
+        if !self.metadata(path)?.is_directory() {
+            return Err(not_found());
+        }
+        if self.read_dir(path)?.next().is_some() {
+            return Err(io::Error::new(
+                ErrorKind::DirectoryNotEmpty,
+                "Directory not empty",
+            ));
+        }
+
+        if self.upper_metadata(path)?.is_some() {
+            // the directory's own whiteouts are superseded by the whiteout of the directory
+            let (_, whiteouts) = self.upper_entries(path)?;
+            for name in whiteouts {
+                let name = name.to_str().ok_or_else(invalid_path)?;
+                self.upper
+                    .remove_file(&whiteout_path(&normalized_path.join(name))?)?;
+            }
+            self.upper.remove_dir(path)?;
+        }
+        self.white_out(&normalized_path)
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        let normalized_path = normalize_and_relativize(path);
+        let path = normalized_path.to_str().ok_or_else(invalid_path)?;
+        if !self.metadata(path)?.is_file() {
+            return Err(not_found());
+        }
+
+        if self.upper_metadata(path)?.is_some() {
+            self.upper.remove_file(path)?;
+        }
+        self.white_out(&normalized_path)
+    }
+}
+
+/// Returns the path of the whiteout that hides the entry at `path` in the base.
+///
+/// # Arguments
+/// `path`: The normalized path of the entry.  
+fn whiteout_path(path: &Path) -> crate::Result<String> {
+    let name = path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .ok_or_else(invalid_path)?;
+    let whiteout: PathBuf = path.with_file_name(format!("{WHITEOUT_PREFIX}{name}"));
+    Ok(whiteout.to_str().ok_or_else(invalid_path)?.to_owned())
+}
+
+/// Fails if any component of `path` is a reserved name.
+///
+/// # Arguments
+/// `path`: The normalized path.  
+fn check_reserved(path: &Path) -> crate::Result<()> {
+    let reserved = path.iter().any(|component| {
+        component
+            .to_str()
+            .is_some_and(|component| component.starts_with(WHITEOUT_PREFIX))
+    });
+    match reserved {
+        true => Err(invalid_input("Names beginning with .wh. are reserved")),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cow_fs::CowFS;
+    use crate::file::{Metadata, OpenOptions};
+    use crate::memory_fs::MemoryFS;
+    use crate::util::test::{check_open_options, read_directory};
+    use crate::FileSystem;
+    use std::io::{ErrorKind, Write};
+    use std::sync::Arc;
+
+    fn base() -> MemoryFS {
+        let base = MemoryFS::default();
+        base.create_dir_all("textures/hd").unwrap();
+        write!(base.create_file("textures/hd/stone").unwrap(), "stone").unwrap();
+        write!(base.create_file("config").unwrap(), "base config").unwrap();
+        base
+    }
+
+    #[test]
+    fn copy_on_write() {
+        let fs = CowFS::new(base(), MemoryFS::default());
+        assert_eq!(fs.metadata("textures/hd").unwrap(), Metadata::directory());
+
+        fs.open_file_options("textures/hd/stone", &OpenOptions::default().write(true))
+            .unwrap()
+            .write_all(b"S")
+            .unwrap();
+        write!(fs.create_file("textures/hd/brick").unwrap(), "brick").unwrap();
+        let read = |path| fs.open_file(path).unwrap().read_into_string().unwrap();
+        assert_eq!(read("textures/hd/stone"), "Stone");
+        assert_eq!(
+            fs.base()
+                .open_file("textures/hd/stone")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "stone"
+        );
+        itertools::assert_equal(
+            read_directory(&fs, "textures/hd").keys(),
+            vec!["textures/hd/brick", "textures/hd/stone"],
+        );
+        assert!(!fs.base().exists("textures/hd/brick").unwrap());
+
+        assert!(fs.create_file("missing/file").is_err());
+        assert!(fs.create_dir("textures").is_err());
+        assert_eq!(
+            fs.create_file("textures/.wh.hd").err().unwrap().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn open_options() {
+        let fs = CowFS::new(base(), MemoryFS::default());
+        check_open_options(&fs, "textures/options");
+
+        // files of the base already exist, and are copied up to be appended to
+        let create_new = OpenOptions::new().write(true).create_new(true);
+        assert_eq!(
+            fs.open_file_options("config", &create_new)
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::AlreadyExists
+        );
+        fs.open_file_options("config", &OpenOptions::new().append(true))
+            .unwrap()
+            .write_all(b"!")
+            .unwrap();
+        assert_eq!(
+            fs.open_file("config").unwrap().read_into_string().unwrap(),
+            "base config!"
+        );
+        assert!(fs.upper().exists("config").unwrap());
+    }
+
+    #[test]
+    fn whiteouts() {
+        let upper = Arc::new(MemoryFS::default());
+        let fs = CowFS::new(base(), upper.clone());
+
+        fs.remove_file("config").unwrap();
+        assert!(!fs.exists("config").unwrap());
+        assert!(fs.base().exists("config").unwrap());
+        assert_eq!(
+            fs.remove_dir("textures").unwrap_err().kind(),
+            ErrorKind::DirectoryNotEmpty
+        );
+        fs.remove_file("textures/hd/stone").unwrap();
+        fs.remove_dir("textures/hd").unwrap();
+        assert!(read_directory(&fs, "textures").is_empty());
+
+        // whiteouts are kept in the upper layer, so they outlive the filesystem
+        let fs = CowFS::new(base(), upper);
+        itertools::assert_equal(read_directory(&fs, "").keys(), vec!["textures"]);
+        fs.remove_dir("textures").unwrap();
+        assert!(read_directory(&fs, "").is_empty());
+        itertools::assert_equal(
+            read_directory(fs.upper(), "").keys(),
+            vec![".wh.config", ".wh.textures"],
+        );
+
+        // recreated directories don't reveal the base's contents
+        fs.create_dir_all("textures/hd").unwrap();
+        assert!(read_directory(&fs, "textures/hd").is_empty());
+        write!(fs.create_file("config").unwrap(), "new").unwrap();
+        assert_eq!(
+            fs.open_file("config").unwrap().read_into_string().unwrap(),
+            "new"
+        );
+    }
+}
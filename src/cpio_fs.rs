@@ -0,0 +1,883 @@
+use crate::file::{DirEntry, File, FileType, Metadata, OpenOptions};
+use crate::tar_fs::{Compression, FileSystemFilter};
+use crate::tree::{normalize_and_relativize, Directory, Entry, FilesystemTree};
+use crate::util::{
+    invalid_input, invalid_path, is_a_directory, not_a_directory, not_supported, read_only,
+};
+use crate::FileSystem;
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io;
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The magic number of a newc header.
+const NEWC_MAGIC: &[u8; 6] = b"070701";
+/// The magic number of a newc header with a checksum of the file's contents.
+const CRC_MAGIC: &[u8; 6] = b"070702";
+/// The length of a newc header, excluding the name.
+const HEADER_LEN: usize = 110;
+/// The name of the entry that ends an archive.
+const TRAILER: &str = "TRAILER!!!";
+/// The maximum length of a name, including its terminator.
+const MAX_NAME_LEN: u32 = 64 * 1024;
+/// The maximum number of symbolic links followed while resolving a path.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// The bits of a mode that hold the type of the entry.
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// A read-only filesystem mounted on a cpio archive in the portable "newc" format, such as a Linux
+/// initramfs image. All files are loaded into memory when the archive is mounted.
+///
+/// Archives that are concatenated, as initramfs images often are, are read as a single archive
+/// where later entries take precedence. Hard links share the contents of the last entry of their
+/// group, symbolic links are followed when they are accessed, and device nodes, FIFOs and sockets
+/// are ignored.
+pub struct CpioFS {
+    tree: FilesystemTree<CpioEntry>,
+}
+
+/// A non-directory entry within a cpio archive.
+#[derive(Clone)]
+enum CpioEntry {
+    /// A regular file's contents and metadata.
+    File(Arc<[u8]>, Metadata),
+    /// A symbolic link to the normalized path of its target.
+    Symlink(PathBuf),
+}
+
+/// The fields of a newc header that are used. Device numbers of device nodes are ignored.
+#[derive(Debug, Default, Copy, Clone)]
+struct Header {
+    ino: u32,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u32,
+    mtime: u32,
+    file_size: u32,
+    dev_major: u32,
+    dev_minor: u32,
+}
+
+/// The paths of the hard links to an inode, and the contents stored with the last one.
+type HardLinks = (Vec<PathBuf>, Option<Arc<[u8]>>);
+
+/// The result of looking up a path that may pass through symbolic links.
+enum Lookup<RV> {
+    /// The path was resolved to an entry.
+    Resolved(RV),
+    /// The path passes through a symbolic link, and resolves to the contained path instead.
+    Follow(PathBuf),
+}
+
+impl CpioFS {
+    /// Creates a new cpio-backed filesystem.
+    ///
+    /// # Arguments
+    /// `archive`: The cpio archive itself.  
+    pub fn new<R: Read>(archive: R) -> crate::Result<Self> {
+        Self::new_filtered(archive, |_: &_| true)
+    }
+
+    /// Creates a new cpio-backed filesystem, transparently decompressing the archive like
+    /// `TarFS::open`.
+    ///
+    /// # Arguments
+    /// `archive`: The possibly compressed cpio archive.  
+    pub fn open<R: Read>(archive: R) -> crate::Result<Self> {
+        Self::new(Compression::decompress(archive)?)
+    }
+
+    /// Creates a new cpio-backed filesystem with filtered contents.
+    ///
+    /// # Arguments
+    /// `archive`: The cpio archive itself.  
+    /// `filter`: A filter that determines which entries are included in the filesystem.  
+    pub fn new_filtered<R: Read, F: FileSystemFilter>(
+        mut archive: R,
+        filter: F,
+    ) -> crate::Result<Self> {
+        let tree = FilesystemTree::default();
+        let mut hard_links: HashMap<(u32, u32, u32), HardLinks> = HashMap::new();
+
+        while let Some((header, name)) = read_header(&mut archive)? {
+            // the contents are padded to a multiple of 4 bytes
+            let mut contents = vec![0; header.file_size as usize];
+            archive.read_exact(&mut contents)?;
+            skip(&mut archive, padding(header.file_size as usize))?;
+
+            let path = normalize_and_relativize(&name);
+            if path.as_os_str().is_empty() || !filter.should_include(&path) {
+                continue;
+            }
+
+            match header.mode & S_IFMT {
+                S_IFDIR => insert_directory(&tree, &path)?,
+                S_IFREG => {
+                    let metadata = Metadata {
+                        mode: Some(header.mode & 0o7777),
+                        modified: Some(UNIX_EPOCH + Duration::from_secs(header.mtime.into())),
+                        uid: Some(header.uid),
+                        gid: Some(header.gid),
+                        ..Metadata::file(contents.len() as u64)
+                    };
+                    let contents: Arc<[u8]> = contents.into();
+
+                    if header.nlink > 1 {
+                        let key = (header.dev_major, header.dev_minor, header.ino);
+                        let (paths, data) = hard_links.entry(key).or_default();
+                        paths.push(path.clone());
+                        if !contents.is_empty() {
+                            *data = Some(contents.clone());
+                        }
+                    }
+                    insert_entry(&tree, &path, CpioEntry::File(contents, metadata))?;
+                }
+                S_IFLNK => {
+                    // relative targets are relative to the link's directory
+                    let target = String::from_utf8(contents).map_err(|_| invalid_path())?;
+                    let link_directory = path.parent().unwrap_or(Path::new(""));
+                    let target = normalize_and_relativize(link_directory.join(target));
+                    insert_entry(&tree, &path, CpioEntry::Symlink(target))?;
+                }
+                // ignore anything else
+                _ => {}
+            }
+        }
+
+        // every link but the last of a group is stored without contents
+        for (paths, contents) in hard_links.into_values() {
+            let Some(contents) = contents else {
+                continue;
+            };
+            for path in paths {
+                tree.with_entry(&path, |entry| {
+                    if let Err((CpioEntry::File(file, metadata), _)) = entry {
+                        *file = contents.clone();
+                        metadata.len = contents.len() as u64;
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+
+        Ok(Self { tree })
+    }
+
+    /// Calls `f` with the directory or file at `path`, following any symbolic links along the way.
+    ///
+    /// # Arguments
+    /// `path`: The path to resolve.  
+    /// `f`: The function.  
+    fn with_resolved_entry<
+        RV,
+        F: FnOnce(Result<&mut Directory<CpioEntry>, (&Arc<[u8]>, &Metadata)>) -> crate::Result<RV>,
+    >(
+        &self,
+        path: &Path,
+        f: F,
+    ) -> crate::Result<RV> {
+        let mut path = path.to_owned();
+        let mut f = Some(f);
+
+        for _ in 0..=MAX_SYMLINK_HOPS {
+            let lookup = self.tree.with_entry(&path, |entry| {
+                // `f` is only taken when the lookup resolves, which ends the loop
+                let mut resolve = |entry| f.take().unwrap()(entry).map(Lookup::Resolved);
+                match entry {
+                    Ok(dir) => resolve(Ok(dir)),
+                    Err((CpioEntry::Symlink(target), remaining_path)) => {
+                        Ok(Lookup::Follow(target.join(remaining_path)))
+                    }
+                    Err((CpioEntry::File(contents, metadata), remaining_path))
+                        if remaining_path.as_os_str().is_empty() =>
+                    {
+                        resolve(Err((contents, metadata)))
+                    }
+                    Err(_) => Err(not_a_directory()),
+                }
+            })?;
+
+            match lookup {
+                Lookup::Resolved(rv) => return Ok(rv),
+                Lookup::Follow(target) => path = target,
+            }
+        }
+
+        Err(invalid_input("Too many levels of symbolic links"))
+    }
+
+    /// Returns the metadata of the entry at `path`, following symbolic links.
+    ///
+    /// # Arguments
+    /// `path`: The path of the entry.  
+    fn resolved_metadata(&self, path: &Path) -> crate::Result<Metadata> {
+        self.with_resolved_entry(path, |entry| {
+            Ok(match entry {
+                Ok(_) => Metadata::directory(),
+                Err((_, metadata)) => metadata.clone(),
+            })
+        })
+    }
+}
+
+impl FileSystem for CpioFS {
+    fn create_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.resolved_metadata(Path::new(path))
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        if options.writable() {
+            return Err(read_only());
+        }
+
+        let (contents, metadata) = self.with_resolved_entry(Path::new(path), |entry| {
+            entry
+                .err()
+                .map(|(contents, metadata)| (contents.clone(), metadata.clone()))
+                .ok_or_else(is_a_directory)
+        })?;
+
+        Ok(Box::new(CpioFileHandle {
+            contents: Cursor::new(contents),
+            metadata,
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let directory = normalize_and_relativize(path);
+        let entries = self.with_resolved_entry(Path::new(path), |entry| {
+            let dir = entry.map_err(|_| not_a_directory())?;
+            Ok(dir
+                .iter()
+                .map(|(name, entry)| {
+                    let metadata = match entry {
+                        Entry::Directory(_) => Ok(Metadata::directory()),
+                        Entry::UserData(CpioEntry::File(_, metadata)) => Ok(metadata.clone()),
+                        Entry::UserData(CpioEntry::Symlink(target)) => Err(target.clone()),
+                    };
+                    (name.clone(), metadata)
+                })
+                .collect_vec())
+        })?;
+
+        // resolve symbolic links once the tree is no longer borrowed. links that can't be resolved
+        // are reported with an unknown type, and either way with their targets
+        Ok(Box::new(
+            entries
+                .into_iter()
+                .map(|(name, metadata)| {
+                    let metadata = metadata.unwrap_or_else(|target| Metadata {
+                        link_target: Some(target.clone()),
+                        ..self
+                            .resolved_metadata(&target)
+                            .unwrap_or(Metadata::new(FileType::Unknown, 0))
+                    });
+
+                    Ok(DirEntry {
+                        path: directory.join(name),
+                        metadata,
+                    })
+                })
+                .collect_vec()
+                .into_iter(),
+        ))
+    }
+
+    fn remove_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn remove_file(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+}
+
+/// Inserts an entry into the tree, creating its parent directories. Entries replace any entry
+/// already at the same path, so later entries in the archive take precedence.
+///
+/// # Arguments
+/// `tree`: The file tree.  
+/// `path`: The normalized path of the entry.  
+/// `cpio_entry`: The entry.  
+fn insert_entry(
+    tree: &FilesystemTree<CpioEntry>,
+    path: &Path,
+    cpio_entry: CpioEntry,
+) -> crate::Result<()> {
+    let parent_path = path.parent().ok_or_else(invalid_path)?;
+    let file_name = path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .ok_or_else(invalid_path)?;
+
+    tree.create_dir_all(parent_path, |dir| {
+        dir.insert(file_name.to_owned(), Entry::UserData(cpio_entry));
+    })
+}
+
+/// Inserts a directory into the tree, creating its parent directories. Directories replace any
+/// file already at the same path, but keep the contents of an existing directory.
+///
+/// # Arguments
+/// `tree`: The file tree.  
+/// `path`: The normalized path of the directory.  
+fn insert_directory(tree: &FilesystemTree<CpioEntry>, path: &Path) -> crate::Result<()> {
+    let parent_path = path.parent().ok_or_else(invalid_path)?;
+    let file_name = path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .ok_or_else(invalid_path)?;
+
+    tree.create_dir_all(parent_path, |dir| {
+        let entry = dir
+            .entry(file_name.to_owned())
+            .or_insert_with(|| Entry::Directory(Default::default()));
+        if let Entry::UserData(_) = entry {
+            *entry = Entry::Directory(Default::default());
+        }
+    })
+}
+
+/// Reads the next header and name from the archive, skipping the trailers and padding between
+/// concatenated archives. Returns `None` at the end of the archive.
+///
+/// # Arguments
+/// `archive`: The cpio archive, positioned at a 4 byte boundary.  
+fn read_header<R: Read>(archive: &mut R) -> crate::Result<Option<(Header, String)>> {
+    loop {
+        // archives are padded with zeros, and may end without a trailer
+        let mut header = [0; HEADER_LEN];
+        let mut read = 0;
+        while read < 4 {
+            match archive.read(&mut header[read..4])? {
+                0 if read == 0 => return Ok(None),
+                0 => return Err(ErrorKind::UnexpectedEof.into()),
+                n => read += n,
+            }
+            if read == 4 && header[..4] == [0; 4] {
+                read = 0;
+            }
+        }
+        archive.read_exact(&mut header[4..])?;
+
+        if &header[..6] != NEWC_MAGIC && &header[..6] != CRC_MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Unsupported cpio format; only newc archives are supported",
+            ));
+        }
+
+        // the fields are 8 digit hexadecimal numbers
+        let mut fields = [0; 13];
+        for (i, field) in fields.iter_mut().enumerate() {
+            let digits = &header[6 + i * 8..6 + (i + 1) * 8];
+            *field = std::str::from_utf8(digits)
+                .ok()
+                .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Invalid cpio header"))?;
+        }
+        let [ino, mode, uid, gid, nlink, mtime, file_size, dev_major, dev_minor, _rdev_major, _rdev_minor, name_size, _check] =
+            fields;
+
+        if name_size == 0 || name_size > MAX_NAME_LEN {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Invalid cpio name"));
+        }
+
+        // the name is terminated and padded so that the contents start at a 4 byte boundary
+        let mut name = vec![0; name_size as usize];
+        archive.read_exact(&mut name)?;
+        skip(archive, padding(HEADER_LEN + name.len()))?;
+        if name.pop() != Some(0) {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Invalid cpio name"));
+        }
+        let name = String::from_utf8(name).map_err(|_| invalid_path())?;
+
+        if name == TRAILER {
+            continue;
+        }
+
+        return Ok(Some((
+            Header {
+                ino,
+                mode,
+                uid,
+                gid,
+                nlink,
+                mtime,
+                file_size,
+                dev_major,
+                dev_minor,
+            },
+            name,
+        )));
+    }
+}
+
+/// Returns the number of bytes needed to pad `len` bytes to a 4 byte boundary.
+fn padding(len: usize) -> usize {
+    len.next_multiple_of(4) - len
+}
+
+/// Skips `len` bytes of the archive.
+fn skip<R: Read>(archive: &mut R, len: usize) -> io::Result<()> {
+    io::copy(&mut archive.take(len as u64), &mut io::sink())?;
+    Ok(())
+}
+
+/// A read-only handle to a file within a cpio archive.
+struct CpioFileHandle {
+    contents: Cursor<Arc<[u8]>>,
+    metadata: Metadata,
+}
+
+impl Read for CpioFileHandle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.contents.read(buf)
+    }
+}
+
+impl Seek for CpioFileHandle {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.contents.seek(pos)
+    }
+}
+
+impl Write for CpioFileHandle {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(not_supported())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(not_supported())
+    }
+}
+
+impl File for CpioFileHandle {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(self.metadata.clone())
+    }
+}
+
+/// Writes a cpio archive in the portable "newc" format, as used for Linux initramfs images.
+/// Entries are written with the owner `0:0`, and with the Unix epoch as their modification time
+/// unless it's known.
+pub struct CpioBuilder<W: Write> {
+    writer: W,
+    /// The number of bytes written so far, used to pad entries.
+    written: u64,
+    /// The inode number of the next entry.
+    next_ino: u32,
+}
+
+impl<W: Write> CpioBuilder<W> {
+    /// Creates a new builder that writes an archive to `writer`.
+    ///
+    /// # Arguments
+    /// `writer`: The writer that receives the archive.  
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            written: 0,
+            next_ino: 1,
+        }
+    }
+
+    /// Appends a directory. Its parent directories aren't created, so they should be appended
+    /// first.
+    ///
+    /// # Arguments
+    /// `path`: The path of the directory within the archive.  
+    /// `mode`: The permission bits of the directory.  
+    pub fn append_directory(&mut self, path: &str, mode: u32) -> crate::Result<()> {
+        self.append_entry(path, S_IFDIR | (mode & 0o7777), 0, 0, io::empty())
+    }
+
+    /// Appends a regular file.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file within the archive.  
+    /// `mode`: The permission bits of the file.  
+    /// `len`: The length of the file, which must fit in 32 bits.  
+    /// `contents`: The contents of the file, of which `len` bytes are read.  
+    pub fn append_file<R: Read>(
+        &mut self,
+        path: &str,
+        mode: u32,
+        len: u64,
+        contents: R,
+    ) -> crate::Result<()> {
+        self.append_entry(path, S_IFREG | (mode & 0o7777), 0, len, contents)
+    }
+
+    /// Appends a symbolic link.
+    ///
+    /// # Arguments
+    /// `path`: The path of the link within the archive.  
+    /// `target`: The target of the link, which is relative to the link's directory unless it's
+    /// absolute.  
+    pub fn append_symlink(&mut self, path: &str, target: &str) -> crate::Result<()> {
+        self.append_entry(
+            path,
+            S_IFLNK | 0o777,
+            0,
+            target.len() as u64,
+            target.as_bytes(),
+        )
+    }
+
+    /// Writes the trailer and pads the archive to a multiple of 512 bytes, returning the writer.
+    pub fn finish(mut self) -> crate::Result<W> {
+        self.write_header(TRAILER, Header::default())?;
+
+        let len = self.written.next_multiple_of(512) - self.written;
+        io::copy(&mut io::repeat(0).take(len), &mut self.writer)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
+    /// Appends an entry.
+    ///
+    /// # Arguments
+    /// `path`: The path of the entry within the archive.  
+    /// `mode`: The type and permission bits of the entry.  
+    /// `mtime`: The modification time of the entry, in seconds since the Unix epoch.  
+    /// `len`: The length of the entry's contents.  
+    /// `contents`: The contents of the entry, of which `len` bytes are read.  
+    fn append_entry<R: Read>(
+        &mut self,
+        path: &str,
+        mode: u32,
+        mtime: u64,
+        len: u64,
+        contents: R,
+    ) -> crate::Result<()> {
+        let path = normalize_and_relativize(path);
+        let path = path.to_str().ok_or_else(invalid_path)?.replace('\\', "/");
+        if path.is_empty() || path == TRAILER {
+            return Err(invalid_path());
+        }
+        let file_size =
+            u32::try_from(len).map_err(|_| invalid_input("File too large for a cpio archive"))?;
+
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        self.write_header(
+            &path,
+            Header {
+                ino,
+                mode,
+                uid: 0,
+                gid: 0,
+                nlink: if mode & S_IFMT == S_IFDIR { 2 } else { 1 },
+                // times past 2106 can't be represented
+                mtime: mtime.min(u32::MAX.into()) as u32,
+                file_size,
+                dev_major: 0,
+                dev_minor: 0,
+            },
+        )?;
+
+        let copied = io::copy(&mut contents.take(len), &mut self.writer)?;
+        if copied != len {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        self.written += len;
+        self.pad()
+    }
+
+    /// Writes a header and name, padded so that the contents start at a 4 byte boundary.
+    ///
+    /// # Arguments
+    /// `name`: The name of the entry.  
+    /// `header`: The header of the entry.  
+    fn write_header(&mut self, name: &str, header: Header) -> crate::Result<()> {
+        if name.contains('\0') {
+            return Err(invalid_path());
+        }
+
+        let fields = [
+            header.ino,
+            header.mode,
+            header.uid,
+            header.gid,
+            header.nlink,
+            header.mtime,
+            header.file_size,
+            header.dev_major,
+            header.dev_minor,
+            0,
+            0,
+            name.len() as u32 + 1,
+            0,
+        ];
+        let mut bytes = NEWC_MAGIC.to_vec();
+        for field in fields {
+            bytes.extend(format!("{field:08X}").bytes());
+        }
+        bytes.extend(name.bytes());
+        bytes.push(0);
+
+        self.writer.write_all(&bytes)?;
+        self.written += bytes.len() as u64;
+        self.pad()
+    }
+
+    /// Pads the archive to a 4 byte boundary.
+    fn pad(&mut self) -> crate::Result<()> {
+        let len = padding((self.written % 4) as usize);
+        self.writer.write_all(&[0; 3][..len])?;
+        self.written += len as u64;
+        Ok(())
+    }
+}
+
+/// Writes the contents of the directory `root` within `fs` to `writer` as a newc cpio archive,
+/// recursively. Paths within the archive are relative to `root`. Unless it is known, files are
+/// archived with mode `0o644`, directories with mode `0o755`, and modification times with the Unix
+/// epoch.
+///
+/// To compress the archive, wrap `writer` in an encoder. The writer is returned once the archive
+/// is complete, so that the encoder can be finished.
+///
+/// # Arguments
+/// `fs`: The filesystem to archive.  
+/// `root`: The directory within `fs` to archive.  
+/// `writer`: The writer that receives the archive.  
+pub fn write_cpio<FS: FileSystem + ?Sized, W: Write>(
+    fs: &FS,
+    root: &str,
+    writer: W,
+) -> crate::Result<W> {
+    let mut builder = CpioBuilder::new(writer);
+    append_contents(fs, &mut builder, Path::new(root), Path::new(""))?;
+    builder.finish()
+}
+
+/// Appends the contents of the directory `path` within `fs` to the archive, recursively.
+///
+/// # Arguments
+/// `fs`: The filesystem to archive.  
+/// `builder`: The archive builder.  
+/// `path`: The path to the directory within `fs`.  
+/// `archive_path`: The path to the directory within the archive.  
+fn append_contents<FS: FileSystem + ?Sized, W: Write>(
+    fs: &FS,
+    builder: &mut CpioBuilder<W>,
+    path: &Path,
+    archive_path: &Path,
+) -> crate::Result<()> {
+    // archive entries in a stable order
+    let entries = fs
+        .read_dir(path.to_str().ok_or_else(invalid_path)?)?
+        .try_collect::<_, Vec<_>, _>()?
+        .into_iter()
+        .sorted_by(|a, b| a.path.cmp(&b.path));
+
+    for entry in entries {
+        let name = entry.path.file_name().ok_or_else(invalid_path)?;
+        let path = path.join(name);
+        let archive_path = archive_path.join(name);
+        let archive_path_str = archive_path.to_str().ok_or_else(invalid_path)?;
+
+        let is_directory = entry.metadata.is_directory();
+        let mode = entry
+            .metadata
+            .mode
+            .unwrap_or(if is_directory { 0o755 } else { 0o644 })
+            & 0o7777;
+        let mtime = entry
+            .metadata
+            .modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |modified| modified.as_secs());
+
+        match entry.metadata.file_type {
+            FileType::Directory => {
+                builder.append_entry(archive_path_str, S_IFDIR | mode, mtime, 0, io::empty())?;
+                append_contents(fs, builder, &path, &archive_path)?;
+            }
+            FileType::File => {
+                let file = fs.open_file(path.to_str().ok_or_else(invalid_path)?)?;
+
+                // the file may have changed since the directory was read
+                let len = file.metadata()?.len();
+                builder.append_entry(archive_path_str, S_IFREG | mode, mtime, len, file)?;
+            }
+            // there's nothing to archive
+            FileType::Unknown => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpio_fs::{write_cpio, CpioBuilder, CpioFS, Header, S_IFREG};
+    use crate::file::{FileType, Metadata};
+    use crate::memory_fs::MemoryFS;
+    use crate::util::test::read_directory;
+    use crate::FileSystem;
+    use std::io::{ErrorKind, Read, Write};
+    use std::time::UNIX_EPOCH;
+
+    fn initramfs() -> Vec<u8> {
+        let mut builder = CpioBuilder::new(Vec::new());
+        builder.append_directory("bin", 0o755).unwrap();
+        builder
+            .append_file("bin/busybox", 0o755, 7, &b"busybox"[..])
+            .unwrap();
+        builder.append_symlink("bin/sh", "busybox").unwrap();
+        builder.append_directory("sbin", 0o755).unwrap();
+        builder.append_symlink("sbin/init", "/bin/sh").unwrap();
+        builder.append_symlink("lib", "missing").unwrap();
+        builder
+            .append_file("/etc/../init", 0o700, 4, &b"init"[..])
+            .unwrap();
+        builder.finish().unwrap()
+    }
+
+    #[test]
+    fn read() {
+        let archive = initramfs();
+        assert_eq!(archive.len() % 512, 0);
+        let cpio_fs = CpioFS::new(archive.as_slice()).unwrap();
+
+        assert_eq!(
+            cpio_fs
+                .open_file("sbin/init")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "busybox"
+        );
+        let metadata = cpio_fs.metadata("/init").unwrap();
+        assert_eq!(metadata.len(), 4);
+        assert_eq!(metadata.mode, Some(0o700));
+        assert_eq!(metadata.modified, Some(UNIX_EPOCH));
+
+        let root = read_directory(&cpio_fs, "");
+        itertools::assert_equal(root.keys(), vec!["bin", "init", "lib", "sbin"]);
+        assert_eq!(root["bin"], Metadata::directory());
+        assert_eq!(root["lib"].file_type, FileType::Unknown);
+        itertools::assert_equal(
+            read_directory(&cpio_fs, "bin").keys(),
+            vec!["bin/busybox", "bin/sh"],
+        );
+
+        assert!(cpio_fs.create_file("new").is_err());
+        assert_eq!(
+            cpio_fs.open_file("lib").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert!(CpioFS::new(&b"0707070000"[..]).is_err());
+    }
+
+    #[test]
+    fn concatenated() {
+        // a second archive replaces a file, after the padding of the first
+        let mut archive = initramfs();
+        let mut builder = CpioBuilder::new(Vec::new());
+        builder.append_file("init", 0o755, 3, &b"new"[..]).unwrap();
+        archive.extend(builder.finish().unwrap());
+
+        let cpio_fs = CpioFS::new(archive.as_slice()).unwrap();
+        assert_eq!(
+            cpio_fs
+                .open_file("init")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "new"
+        );
+        assert!(cpio_fs.exists("bin/busybox").unwrap());
+    }
+
+    #[test]
+    fn hard_links() {
+        // only the last link of a group carries the contents
+        let mut builder = CpioBuilder::new(Vec::new());
+        for (name, contents) in [("a", &b""[..]), ("b", &b"linked"[..])] {
+            let header = Header {
+                ino: 7,
+                mode: S_IFREG | 0o644,
+                nlink: 2,
+                file_size: contents.len() as u32,
+                ..Header::default()
+            };
+            builder.write_header(name, header).unwrap();
+            builder.writer.write_all(contents).unwrap();
+            builder.written += contents.len() as u64;
+            builder.pad().unwrap();
+        }
+        let archive = builder.finish().unwrap();
+
+        let cpio_fs = CpioFS::new(archive.as_slice()).unwrap();
+        for name in ["a", "b"] {
+            let mut contents = String::new();
+            cpio_fs
+                .open_file(name)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            assert_eq!(contents, "linked");
+            assert_eq!(cpio_fs.metadata(name).unwrap().len(), 6);
+        }
+    }
+
+    #[test]
+    fn write() {
+        let memory_fs = MemoryFS::default();
+        memory_fs.create_dir("dir").unwrap();
+        memory_fs.create_dir("dir/empty").unwrap();
+        write!(memory_fs.create_file("dir/file").unwrap(), "file").unwrap();
+        write!(memory_fs.create_file("root").unwrap(), "root").unwrap();
+
+        let archive = write_cpio(&memory_fs, "", Vec::new()).unwrap();
+        let cpio_fs = CpioFS::new(archive.as_slice()).unwrap();
+        itertools::assert_equal(read_directory(&cpio_fs, "").keys(), vec!["dir", "root"]);
+        itertools::assert_equal(
+            read_directory(&cpio_fs, "dir").keys(),
+            vec!["dir/empty", "dir/file"],
+        );
+        let metadata = cpio_fs.metadata("dir/file").unwrap();
+        assert_eq!(metadata.mode, Some(0o644));
+        assert_eq!(
+            cpio_fs
+                .open_file("dir/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "file"
+        );
+
+        // a subdirectory becomes the root of the archive
+        let archive = write_cpio(&memory_fs, "dir", Vec::new()).unwrap();
+        let cpio_fs = CpioFS::new(archive.as_slice()).unwrap();
+        itertools::assert_equal(read_directory(&cpio_fs, "").keys(), vec!["empty", "file"]);
+
+        let mut builder = CpioBuilder::new(Vec::new());
+        assert!(builder.append_file("", 0o644, 0, &b""[..]).is_err());
+        assert!(builder
+            .append_file("short", 0o644, 10, &b"abc"[..])
+            .is_err());
+    }
+}
@@ -0,0 +1,431 @@
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::{already_exists, encode_hex, invalid_input, invalid_path, not_found};
+use crate::FileSystem;
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::io;
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+/// The directory of the inner filesystem that holds the file bodies, named by their hashes.
+const OBJECTS: &str = "objects";
+/// The directory of the inner filesystem that mirrors the tree of the deduplicated filesystem, holding the hash of
+/// each file's body in its place.
+const FILES: &str = "files";
+
+/// The inner filesystem, shared with the files opened for writing.
+struct Store<FS> {
+    fs: FS,
+    /// Held while bodies are stored and referenced, so that they can't be collected in between.
+    lock: Mutex<()>,
+}
+
+impl<FS: FileSystem> Store<FS> {
+    /// Returns the hash stored in the index for the file at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file in the index.  
+    fn hash_of(&self, path: &str) -> crate::Result<String> {
+        let hash = self.fs.open_file(path)?.read_into_string()?;
+        if hash.len() != 64 || !hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(invalid_input("Invalid hash in the index"));
+        }
+
+        Ok(hash)
+    }
+
+    /// Returns the metadata of the file at `path`, which is the metadata of its index entry with the length of its body.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file in the index.  
+    /// `metadata`: The metadata of the index entry.  
+    fn file_metadata(&self, path: &str, metadata: Metadata) -> crate::Result<Metadata> {
+        if !metadata.is_file() {
+            return Ok(metadata);
+        }
+
+        let body = self.fs.metadata(&object_path(&self.hash_of(path)?))?;
+        Ok(Metadata {
+            len: body.len,
+            ..metadata
+        })
+    }
+
+    /// Stores `contents` as the body of the file at `path`, storing the body itself only if it isn't already stored.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file in the index.  
+    /// `contents`: The contents of the file.  
+    fn store(&self, path: &str, contents: &[u8]) -> crate::Result<()> {
+        let hash = encode_hex(&hmac_sha256::Hash::hash(contents));
+        let object_path = object_path(&hash);
+
+        let _lock = self.lock.lock();
+        if !self.fs.exists(&object_path)? {
+            self.fs.create_file(&object_path)?.write_all(contents)?;
+        }
+        self.fs.create_file(path)?.write_all(hash.as_bytes())
+    }
+}
+
+/// A filesystem that deduplicates the files it stores in another filesystem by their contents. File bodies are stored
+/// once in an object area of the inner filesystem, named by their SHA-256 hash, and an index that mirrors the
+/// directory tree maps each path to the hash of its body. Identical files share a body, and copies only copy the
+/// index entry.
+///
+/// Bodies aren't removed as soon as nothing refers to them, so that removing and rewriting files is cheap; `gc`
+/// removes the bodies that are no longer referenced. Files opened for writing are held in memory and stored when
+/// they're flushed or dropped. Errors storing a file that's dropped are lost, so files should be flushed before
+/// they're dropped to see them.
+pub struct DedupFS<FS> {
+    store: Arc<Store<FS>>,
+}
+
+impl<FS: FileSystem> DedupFS<FS> {
+    /// Creates a new deduplicating filesystem, creating the object area and the index in `inner` if they aren't
+    /// already there.
+    ///
+    /// # Arguments
+    /// `inner`: The filesystem to store files in.  
+    pub fn new(inner: FS) -> crate::Result<Self> {
+        for dir in [OBJECTS, FILES] {
+            if !inner.exists(dir)? {
+                inner.create_dir(dir)?;
+            }
+        }
+
+        Ok(Self {
+            store: Arc::new(Store {
+                fs: inner,
+                lock: Mutex::default(),
+            }),
+        })
+    }
+
+    /// Returns the filesystem the files are stored in.
+    pub fn inner(&self) -> &FS {
+        &self.store.fs
+    }
+
+    /// Removes the bodies that no file refers to. Returns the number of bytes freed.
+    pub fn gc(&self) -> crate::Result<u64> {
+        let _lock = self.store.lock.lock();
+
+        let mut referenced = HashSet::new();
+        for entry in self.store.fs.walk_dir(FILES)? {
+            let entry = entry?;
+            if entry.metadata.is_file() {
+                let path = entry.path.to_str().ok_or_else(invalid_path)?;
+                referenced.insert(self.store.hash_of(path)?);
+            }
+        }
+
+        let mut freed = 0;
+        for entry in self.store.fs.read_dir(OBJECTS)? {
+            let entry = entry?;
+            let hash = entry
+                .path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(invalid_path)?;
+            if !referenced.contains(hash) {
+                self.store.fs.remove_file(&object_path(hash))?;
+                freed += entry.metadata.len;
+            }
+        }
+
+        Ok(freed)
+    }
+
+    /// Returns the metadata of the file at `path`, failing if it isn't a file.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file in the index.  
+    fn metadata_of_file(&self, path: &str) -> crate::Result<Metadata> {
+        let metadata = self.store.fs.metadata(path)?;
+        if !metadata.is_file() {
+            return Err(invalid_input("Source is not a file"));
+        }
+
+        self.store.file_metadata(path, metadata)
+    }
+}
+
+impl<FS: FileSystem + 'static> FileSystem for DedupFS<FS> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        let index_path = index_path(path)?;
+        if index_path == FILES {
+            return Err(already_exists());
+        }
+
+        self.store.fs.create_dir(&index_path)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        let index_path = index_path(path)?;
+        let metadata = self.store.fs.metadata(&index_path)?;
+        self.store.file_metadata(&index_path, metadata)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        let index_path = index_path(path)?;
+        let existing = match self.store.fs.metadata(&index_path) {
+            Ok(metadata) if metadata.is_file() => Some(self.store.hash_of(&index_path)?),
+            Ok(_) => return Err(not_found()),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+
+        if !options.writable() {
+            let hash = existing.ok_or_else(not_found)?;
+            return self.store.fs.open_file(&object_path(&hash));
+        }
+
+        let contents = match existing {
+            Some(_) if options.create_new => return Err(already_exists()),
+            Some(hash) if !options.truncate => self
+                .store
+                .fs
+                .open_file(&object_path(&hash))?
+                .read_into_vec()?,
+            Some(_) => {
+                self.store.store(&index_path, &[])?;
+                Vec::new()
+            }
+            None if options.create || options.create_new => {
+                // the file exists from the moment it's created, even if it's never written
+                self.store.store(&index_path, &[])?;
+                Vec::new()
+            }
+            None => return Err(not_found()),
+        };
+
+        let mut contents = Cursor::new(contents);
+        if options.append {
+            contents.seek(SeekFrom::End(0))?;
+        }
+
+        Ok(Box::new(DedupFile {
+            store: self.store.clone(),
+            index_path,
+            contents,
+            append: options.append,
+            dirty: false,
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let index_path = index_path(path)?;
+        let dir_path = normalize_and_relativize(path);
+
+        let mut entries = Vec::new();
+        for entry in self.store.fs.read_dir(&index_path)? {
+            let entry = entry?;
+            entries.push(Ok(DirEntry {
+                path: dir_path.join(entry.file_name()),
+                metadata: self.store.file_metadata(
+                    entry.path.to_str().ok_or_else(invalid_path)?,
+                    entry.metadata,
+                )?,
+            }));
+        }
+
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        let index_path = index_path(path)?;
+        if index_path == FILES {
+            return Err(invalid_input("The root can't be removed"));
+        }
+
+        self.store.fs.remove_dir(&index_path)
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.store.fs.remove_file(&index_path(path)?)
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> crate::Result<u64> {
+        let from = index_path(from)?;
+        let len = self.metadata_of_file(&from)?.len;
+        self.store.fs.copy_file(&from, &index_path(to)?)?;
+        Ok(len)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        self.store.fs.rename(&index_path(from)?, &index_path(to)?)
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        self.store.fs.stats()
+    }
+}
+
+/// A file held in memory, which is stored when it's flushed after being written.
+struct DedupFile<FS: FileSystem> {
+    store: Arc<Store<FS>>,
+    index_path: String,
+    contents: Cursor<Vec<u8>>,
+    append: bool,
+    /// True if the file was written since it was last stored.
+    dirty: bool,
+}
+
+impl<FS: FileSystem> File for DedupFile<FS> {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(Metadata::file(self.contents.get_ref().len() as u64))
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        self.contents.get_mut().resize(len as usize, 0);
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+impl<FS: FileSystem> Read for DedupFile<FS> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.contents.read(buf)
+    }
+}
+
+impl<FS: FileSystem> Seek for DedupFile<FS> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.contents.seek(pos)
+    }
+}
+
+impl<FS: FileSystem> Write for DedupFile<FS> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.append {
+            self.contents.seek(SeekFrom::End(0))?;
+        }
+
+        self.dirty = true;
+        self.contents.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.dirty {
+            self.store
+                .store(&self.index_path, self.contents.get_ref())?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+impl<FS: FileSystem> Drop for DedupFile<FS> {
+    fn drop(&mut self) {
+        // errors can't be returned from here, so callers that need to see them must flush first
+        let _ = self.flush();
+    }
+}
+
+/// Returns the path of the index entry of `path`.
+///
+/// # Arguments
+/// `path`: The virtual path.  
+fn index_path(path: &str) -> crate::Result<String> {
+    let path = normalize_and_relativize(path);
+    let path = path.to_str().ok_or_else(invalid_path)?.replace('\\', "/");
+    Ok(match path.is_empty() {
+        true => FILES.to_owned(),
+        false => format!("{FILES}/{path}"),
+    })
+}
+
+/// Returns the path of the body with `hash`.
+///
+/// # Arguments
+/// `hash`: The hex-encoded hash of the body.  
+fn object_path(hash: &str) -> String {
+    format!("{OBJECTS}/{hash}")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dedup_fs::{DedupFS, OBJECTS};
+    use crate::file::{Metadata, OpenOptions};
+    use crate::memory_fs::MemoryFS;
+    use crate::util::test::{check_open_options, check_set_len, read_directory};
+    use crate::FileSystem;
+    use std::io::{ErrorKind, Write};
+    use std::sync::Arc;
+
+    #[test]
+    fn dedup() {
+        let inner = Arc::new(MemoryFS::default());
+        let fs = DedupFS::new(inner.clone()).unwrap();
+        let objects = || read_directory(&inner, OBJECTS).len();
+
+        fs.create_dir("dir").unwrap();
+        write!(fs.create_file("dir/a").unwrap(), "same").unwrap();
+        write!(fs.create_file("b").unwrap(), "same").unwrap();
+        assert_eq!(fs.copy_file("b", "dir/c").unwrap(), 4);
+        // the empty body of new files, and the shared body
+        assert_eq!(objects(), 2);
+
+        assert_eq!(fs.metadata("dir/a").unwrap().len, 4);
+        itertools::assert_equal(read_directory(&fs, "").keys(), vec!["b", "dir"]);
+        itertools::assert_equal(
+            read_directory(&fs, "dir")
+                .values()
+                .map(|metadata| metadata.len),
+            vec![4, 4],
+        );
+        assert_eq!(
+            fs.open_file("dir/c").unwrap().read_into_string().unwrap(),
+            "same"
+        );
+
+        let mut file = fs
+            .open_file_options("b", &OpenOptions::default().write(true).append(true))
+            .unwrap();
+        write!(file, "!").unwrap();
+        file.flush().unwrap();
+        assert_eq!(fs.metadata("b").unwrap(), Metadata::file(5));
+        drop(file);
+        assert_eq!(
+            fs.open_file("b").unwrap().read_into_string().unwrap(),
+            "same!"
+        );
+        assert_eq!(
+            fs.open_file("missing").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert!(fs.create_file("missing/file").is_err());
+
+        // unreferenced bodies are only removed by collecting them
+        fs.remove_file("dir/a").unwrap();
+        fs.remove_file("dir/c").unwrap();
+        assert_eq!(objects(), 3);
+        assert_eq!(fs.gc().unwrap(), 4);
+        assert_eq!(objects(), 1);
+        assert_eq!(
+            fs.open_file("b").unwrap().read_into_string().unwrap(),
+            "same!"
+        );
+
+        // the store outlives the filesystem
+        drop(fs);
+        let fs = DedupFS::new(inner.clone()).unwrap();
+        assert_eq!(fs.metadata("b").unwrap().len, 5);
+    }
+
+    #[test]
+    fn open_options() {
+        check_open_options(&DedupFS::new(MemoryFS::default()).unwrap(), "options");
+    }
+
+    #[test]
+    fn set_len() {
+        check_set_len(&DedupFS::new(MemoryFS::default()).unwrap(), "set_len");
+    }
+}
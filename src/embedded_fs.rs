@@ -0,0 +1,316 @@
+use crate::file::{DirEntry, File, Metadata, OpenOptions};
+use crate::tree::{normalize_and_relativize, Entry, FilesystemTree};
+use crate::util::{
+    invalid_path, is_a_directory, not_a_directory, not_found, not_supported, read_only,
+};
+use crate::FileSystem;
+use itertools::Itertools;
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// A read-only filesystem over assets compiled into the binary, with either `rust-embed` or `include_dir`. It can be
+/// mounted in a `RocFS` beneath or above physical directories, so that runtime files override the embedded ones or
+/// the other way around.
+///
+/// The directory structure is indexed when the filesystem is created.
+pub struct EmbeddedFS {
+    tree: FilesystemTree<EmbeddedFile>,
+}
+
+/// The contents and metadata of an embedded file.
+struct EmbeddedFile {
+    contents: Cow<'static, [u8]>,
+    metadata: Metadata,
+}
+
+impl EmbeddedFS {
+    /// Creates a filesystem of the files embedded by a `rust_embed::RustEmbed` type. Directories are inferred from the
+    /// paths of the files, so empty directories aren't present.
+    ///
+    /// When `rust-embed` reads the files from disk, as it does in debug builds by default, they're read once here.
+    #[cfg(feature = "rust-embed")]
+    pub fn from_rust_embed<E: rust_embed::RustEmbed>() -> crate::Result<Self> {
+        let tree = FilesystemTree::default();
+        for name in E::iter() {
+            // files may disappear between listing and reading them when they're read from disk
+            let Some(file) = E::get(&name) else {
+                continue;
+            };
+
+            let modified = file
+                .metadata
+                .last_modified()
+                .map(|seconds| std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds));
+            let metadata = Metadata {
+                modified,
+                ..Metadata::file(file.data.len() as u64)
+            };
+            insert_file(
+                &tree,
+                &normalize_and_relativize(name.as_ref()),
+                EmbeddedFile {
+                    contents: file.data,
+                    metadata,
+                },
+            )?;
+        }
+
+        Ok(Self { tree })
+    }
+
+    /// Creates a filesystem of the contents of an `include_dir::Dir`, which becomes the root.
+    ///
+    /// # Arguments
+    /// `dir`: The embedded directory.  
+    #[cfg(feature = "include_dir")]
+    pub fn from_include_dir(dir: &'static include_dir::Dir<'static>) -> crate::Result<Self> {
+        let tree = FilesystemTree::default();
+        let mut stack = vec![dir];
+        while let Some(current) = stack.pop() {
+            for entry in current.entries() {
+                // entries are named relative to the root of the embedding, which may be above `dir`
+                let path = entry
+                    .path()
+                    .strip_prefix(dir.path())
+                    .map_err(|_| invalid_path())?;
+                let path = normalize_and_relativize(path);
+
+                match entry {
+                    include_dir::DirEntry::Dir(child) => {
+                        tree.create_dir_all(&path, |_| ())?;
+                        stack.push(child);
+                    }
+                    include_dir::DirEntry::File(file) => {
+                        let metadata = Metadata {
+                            modified: file.metadata().map(include_dir::Metadata::modified),
+                            accessed: file.metadata().map(include_dir::Metadata::accessed),
+                            created: file.metadata().map(include_dir::Metadata::created),
+                            ..Metadata::file(file.contents().len() as u64)
+                        };
+                        insert_file(
+                            &tree,
+                            &path,
+                            EmbeddedFile {
+                                contents: Cow::Borrowed(file.contents()),
+                                metadata,
+                            },
+                        )?;
+                    }
+                }
+            }
+        }
+
+        Ok(Self { tree })
+    }
+}
+
+impl FileSystem for EmbeddedFS {
+    fn create_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.tree.with_entry(path, |entry| match entry {
+            Ok(_) => Ok(Metadata::directory()),
+            Err((file, remaining)) if remaining.as_os_str().is_empty() => Ok(file.metadata.clone()),
+            Err(_) => Err(not_found()),
+        })
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        if options.writable() {
+            return Err(read_only());
+        }
+
+        let (contents, metadata) = self.tree.with_entry(path, |entry| match entry {
+            Err((file, remaining)) if remaining.as_os_str().is_empty() => {
+                Ok((file.contents.clone(), file.metadata.clone()))
+            }
+            Ok(_) => Err(is_a_directory()),
+            Err(_) => Err(not_a_directory()),
+        })?;
+
+        Ok(Box::new(EmbeddedFileHandle {
+            contents: Cursor::new(contents),
+            metadata,
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let directory = normalize_and_relativize(path);
+        let entries = self.tree.with_directory(&directory, |dir| {
+            dir.iter()
+                .map(|(name, entry)| {
+                    let metadata = match entry {
+                        Entry::Directory(_) => Metadata::directory(),
+                        Entry::UserData(file) => file.metadata.clone(),
+                    };
+                    Ok(DirEntry {
+                        path: directory.join(name),
+                        metadata,
+                    })
+                })
+                .collect_vec()
+        })?;
+
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn remove_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn remove_file(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+}
+
+/// Inserts a file into the tree, creating its parent directories.
+///
+/// # Arguments
+/// `tree`: The file tree.  
+/// `path`: The normalized path of the file.  
+/// `file`: The file.  
+fn insert_file(
+    tree: &FilesystemTree<EmbeddedFile>,
+    path: &Path,
+    file: EmbeddedFile,
+) -> crate::Result<()> {
+    let parent_path = path.parent().ok_or_else(invalid_path)?;
+    let file_name = path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .ok_or_else(invalid_path)?;
+
+    tree.create_dir_all(parent_path, |dir| {
+        dir.insert(file_name.to_owned(), Entry::UserData(file));
+    })
+}
+
+struct EmbeddedFileHandle {
+    contents: Cursor<Cow<'static, [u8]>>,
+    metadata: Metadata,
+}
+
+impl Read for EmbeddedFileHandle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.contents.read(buf)
+    }
+}
+
+impl Seek for EmbeddedFileHandle {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.contents.seek(pos)
+    }
+}
+
+impl Write for EmbeddedFileHandle {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(not_supported())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(not_supported())
+    }
+}
+
+impl File for EmbeddedFileHandle {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(self.metadata.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::embedded_fs::EmbeddedFS;
+    use crate::util::test::read_directory;
+    use crate::FileSystem;
+    #[cfg(feature = "include_dir")]
+    use crate::{file::Metadata, physical_fs::PhysicalFS, roc_fs::RocFS};
+    #[cfg(feature = "include_dir")]
+    use std::io::ErrorKind;
+
+    #[cfg(feature = "rust-embed")]
+    #[derive(rust_embed::Embed)]
+    #[folder = "test/folder_a"]
+    struct Assets;
+
+    #[cfg(feature = "rust-embed")]
+    #[test]
+    fn rust_embed() {
+        let embedded_fs = EmbeddedFS::from_rust_embed::<Assets>().unwrap();
+
+        let root = read_directory(&embedded_fs, "");
+        itertools::assert_equal(root.keys(), vec!["file_a"]);
+        assert_eq!(root["file_a"].len(), 6);
+        assert_eq!(
+            embedded_fs
+                .open_file("dir/../file_a")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "file a"
+        );
+        assert!(embedded_fs.metadata("file_a").unwrap().modified.is_some());
+        assert!(embedded_fs.open_file("file_b").is_err());
+    }
+
+    #[cfg(feature = "include_dir")]
+    #[test]
+    fn include_dir() {
+        static TEST: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/test/a");
+        let embedded_fs = EmbeddedFS::from_include_dir(TEST.get_dir("b/c").unwrap()).unwrap();
+
+        itertools::assert_equal(read_directory(&embedded_fs, "").keys(), vec!["d"]);
+        assert_eq!(
+            embedded_fs.metadata("d/e/f").unwrap(),
+            Metadata::directory()
+        );
+        let metadata = embedded_fs.metadata("/d/e/f/.gitkeep").unwrap();
+        assert_eq!(metadata.len(), 0);
+        assert!(metadata.modified.is_some());
+
+        assert_eq!(
+            embedded_fs.open_file("d").err().unwrap().kind(),
+            ErrorKind::IsADirectory
+        );
+        assert!(embedded_fs.create_file("d/new").is_err());
+        assert_eq!(
+            embedded_fs
+                .open_file("d/e/f/.gitkeep/more")
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::NotADirectory
+        );
+    }
+
+    #[cfg(feature = "include_dir")]
+    #[test]
+    fn layered() {
+        // compiled-in assets and runtime files are composed in one stack
+        static ASSETS: include_dir::Dir =
+            include_dir::include_dir!("$CARGO_MANIFEST_DIR/test/folder_b");
+        let roc_fs = RocFS::new(vec![
+            Box::new(PhysicalFS::new("test/folder_a")),
+            Box::new(EmbeddedFS::from_include_dir(&ASSETS).unwrap()),
+        ]);
+
+        assert!(roc_fs.exists("file_a").unwrap());
+        assert_eq!(
+            roc_fs
+                .open_file("file_b")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "file b"
+        );
+    }
+}
@@ -0,0 +1,1006 @@
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::{component_iter, invalid_input, invalid_path, not_writable};
+use crate::FileSystem;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::XChaCha20Poly1305;
+use itertools::Itertools;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+/// The magic that starts every encrypted file.
+const MAGIC: [u8; 4] = *b"VFE\x01";
+/// The length of the random identifier of an encrypted file, which binds its chunks to it.
+const FILE_ID_LEN: usize = 16;
+/// The length of the header of an encrypted file: the magic, the cipher, and the file identifier.
+const HEADER_LEN: u64 = (MAGIC.len() + 1 + FILE_ID_LEN) as u64;
+/// The number of plaintext bytes in each chunk. Every chunk but the last is full.
+const CHUNK_SIZE: u64 = 4096;
+/// The length of the authentication tag of both ciphers.
+const TAG_LEN: u64 = 16;
+
+/// The authenticated cipher that file contents and names are encrypted with.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Cipher {
+    /// AES-256 in Galois/Counter Mode. It's fastest on hardware with AES instructions, but because its nonces are
+    /// random and only 96 bits long, a key shouldn't be used for more than around 2^32 chunk writes.
+    Aes256Gcm,
+    /// XChaCha20-Poly1305. It's fast in software, and its 192-bit nonces can be chosen at random without limit.
+    XChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// Returns the identifier of the cipher in the header of an encrypted file.
+    fn id(self) -> u8 {
+        match self {
+            Self::Aes256Gcm => 1,
+            Self::XChaCha20Poly1305 => 2,
+        }
+    }
+
+    /// Returns the length of the nonces of the cipher, in bytes.
+    fn nonce_len(self) -> usize {
+        match self {
+            Self::Aes256Gcm => 12,
+            Self::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    /// Returns the number of bytes that encryption adds to each chunk: its nonce and its tag.
+    fn chunk_overhead(self) -> u64 {
+        self.nonce_len() as u64 + TAG_LEN
+    }
+
+    /// Returns the offset of a chunk within an encrypted file.
+    ///
+    /// # Arguments
+    /// `index`: The index of the chunk.  
+    fn chunk_offset(self, index: u64) -> u64 {
+        HEADER_LEN + index * (CHUNK_SIZE + self.chunk_overhead())
+    }
+
+    /// Returns the length of the plaintext of an encrypted file, or `None` if the file is malformed.
+    ///
+    /// # Arguments
+    /// `len`: The length of the encrypted file.  
+    fn plaintext_len(self, len: u64) -> Option<u64> {
+        // files that were never written to don't have a header yet
+        if len == 0 {
+            return Some(0);
+        }
+
+        let body = len.checked_sub(HEADER_LEN)?;
+        let stored_chunk_len = CHUNK_SIZE + self.chunk_overhead();
+        let full_chunks = body / stored_chunk_len;
+        match body % stored_chunk_len {
+            0 => Some(full_chunks * CHUNK_SIZE),
+            // chunks are never empty
+            partial if partial <= self.chunk_overhead() => None,
+            partial => Some(full_chunks * CHUNK_SIZE + partial - self.chunk_overhead()),
+        }
+    }
+}
+
+/// A cipher initialized with a key.
+enum KeyedCipher {
+    Aes256Gcm(Box<Aes256Gcm>),
+    XChaCha20Poly1305(XChaCha20Poly1305),
+}
+
+impl KeyedCipher {
+    /// Initializes a cipher with a key.
+    ///
+    /// # Arguments
+    /// `cipher`: The cipher.  
+    /// `key`: The key.  
+    fn new(cipher: Cipher, key: &[u8; 32]) -> Self {
+        match cipher {
+            Cipher::Aes256Gcm => Self::Aes256Gcm(Box::new(Aes256Gcm::new(key.into()))),
+            Cipher::XChaCha20Poly1305 => {
+                Self::XChaCha20Poly1305(XChaCha20Poly1305::new(key.into()))
+            }
+        }
+    }
+
+    /// Encrypts a message, returning the ciphertext followed by the tag.
+    ///
+    /// # Arguments
+    /// `nonce`: The nonce, which is the cipher's nonce length.  
+    /// `msg`: The plaintext.  
+    /// `aad`: The associated data.  
+    fn encrypt(&self, nonce: &[u8], msg: &[u8], aad: &[u8]) -> Vec<u8> {
+        let payload = Payload { msg, aad };
+        match self {
+            Self::Aes256Gcm(cipher) => cipher.encrypt(nonce.into(), payload),
+            Self::XChaCha20Poly1305(cipher) => cipher.encrypt(nonce.into(), payload),
+        }
+        // encryption only fails for messages far longer than chunks and names
+        .expect("message is short enough to encrypt")
+    }
+
+    /// Decrypts and authenticates a message.
+    ///
+    /// # Arguments
+    /// `nonce`: The nonce, which is the cipher's nonce length.  
+    /// `msg`: The ciphertext followed by the tag.  
+    /// `aad`: The associated data.  
+    fn decrypt(&self, nonce: &[u8], msg: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+        let payload = Payload { msg, aad };
+        match self {
+            Self::Aes256Gcm(cipher) => cipher.decrypt(nonce.into(), payload),
+            Self::XChaCha20Poly1305(cipher) => cipher.decrypt(nonce.into(), payload),
+        }
+        .map_err(|_| malformed("Failed to authenticate encrypted data"))
+    }
+}
+
+/// The keys of an encrypted filesystem, each derived from the master key.
+struct Keys {
+    cipher: Cipher,
+    contents: KeyedCipher,
+    names: KeyedCipher,
+    name_nonces: [u8; 32],
+}
+
+impl Keys {
+    /// Returns a random nonce for the cipher.
+    fn random_nonce(&self) -> Vec<u8> {
+        let mut nonce = vec![0; self.cipher.nonce_len()];
+        OsRng.fill_bytes(&mut nonce);
+        nonce
+    }
+
+    /// Encrypts a file name deterministically, so that it can be looked up again. The nonce is derived from the name
+    /// itself, so distinct names never share a nonce.
+    ///
+    /// # Arguments
+    /// `name`: The file name.  
+    fn encrypt_name(&self, name: &str) -> String {
+        let nonce = hmac_sha256::HMAC::mac(name, self.name_nonces);
+        let nonce = &nonce[..self.cipher.nonce_len()];
+
+        let mut encrypted = nonce.to_vec();
+        encrypted.extend(self.names.encrypt(nonce, name.as_bytes(), &[]));
+        base32_encode(&encrypted)
+    }
+
+    /// Decrypts a file name, returning `None` if it isn't a name encrypted with these keys.
+    ///
+    /// # Arguments
+    /// `name`: The encrypted file name.  
+    fn decrypt_name(&self, name: &str) -> Option<String> {
+        let encrypted = base32_decode(name)?;
+        if encrypted.len() < self.cipher.chunk_overhead() as usize {
+            return None;
+        }
+
+        let (nonce, ciphertext) = encrypted.split_at(self.cipher.nonce_len());
+        let name = self.names.decrypt(nonce, ciphertext, &[]).ok()?;
+        String::from_utf8(name).ok()
+    }
+}
+
+/// A filesystem that transparently encrypts the contents of the files in another filesystem, and optionally their
+/// names, so that it can be stored on an untrusted disk.
+///
+/// Files are encrypted in chunks of 4 KiB, each with a fresh random nonce, so that they can be read and written at
+/// arbitrary offsets without re-encrypting the whole file. Chunks are authenticated together with the file they
+/// belong to, their index, and whether they're the last chunk, so that chunks can't be modified, reordered, swapped
+/// between files or truncated away without reads failing with `InvalidData`. Directory structure, approximate file
+/// sizes and modification times are not hidden.
+///
+/// With name obfuscation, every path component is encrypted deterministically and encoded in lowercase base32, which
+/// is safe on case-insensitive filesystems. Names grow by around 60% plus 45 to 65 characters, depending on the
+/// cipher, so long names may exceed the limits of the inner filesystem. Entries in the inner filesystem that weren't
+/// created through this filesystem are skipped when listing directories.
+pub struct EncryptedFS<FS> {
+    inner: FS,
+    keys: Arc<Keys>,
+    obfuscate_names: bool,
+}
+
+impl<FS: FileSystem> EncryptedFS<FS> {
+    /// Creates a new encrypted filesystem. Files written by this filesystem can only be read back with the same key
+    /// and cipher.
+    ///
+    /// # Arguments
+    /// `inner`: The filesystem the encrypted files are stored in.  
+    /// `key`: The secret key. It should be random, or derived from a password with a key derivation function.  
+    /// `cipher`: The cipher to encrypt files with.  
+    pub fn new(inner: FS, key: [u8; 32], cipher: Cipher) -> Self {
+        let derive = |purpose: &str| hmac_sha256::HMAC::mac(purpose, key);
+        Self {
+            inner,
+            keys: Arc::new(Keys {
+                cipher,
+                contents: KeyedCipher::new(cipher, &derive("virtual-fs contents")),
+                names: KeyedCipher::new(cipher, &derive("virtual-fs names")),
+                name_nonces: derive("virtual-fs name nonces"),
+            }),
+            obfuscate_names: false,
+        }
+    }
+
+    /// # Arguments
+    /// `obfuscate_names`: If true, the names of files and directories are encrypted too. A filesystem must always be
+    /// opened with the same setting.  
+    pub fn obfuscate_names(mut self, obfuscate_names: bool) -> Self {
+        self.obfuscate_names = obfuscate_names;
+        self
+    }
+
+    /// Returns the filesystem the encrypted files are stored in.
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Returns the path of an entry within the inner filesystem.
+    ///
+    /// # Arguments
+    /// `path`: The path of the entry.  
+    fn inner_path(&self, path: &str) -> crate::Result<String> {
+        let path = normalize_and_relativize(path);
+        if self.obfuscate_names {
+            Ok(component_iter(&path)
+                .map(|name| self.keys.encrypt_name(name))
+                .join("/"))
+        } else {
+            path.to_str().map(str::to_owned).ok_or_else(invalid_path)
+        }
+    }
+
+    /// Returns the metadata of an entry, with the length of files converted to the length of their plaintext.
+    ///
+    /// # Arguments
+    /// `metadata`: The metadata of the entry in the inner filesystem.  
+    fn outer_metadata(&self, metadata: Metadata) -> crate::Result<Metadata> {
+        if !metadata.is_file() {
+            return Ok(metadata);
+        }
+
+        let len = self
+            .keys
+            .cipher
+            .plaintext_len(metadata.len)
+            .ok_or_else(|| malformed("Malformed encrypted file"))?;
+        Ok(Metadata { len, ..metadata })
+    }
+}
+
+impl<FS: FileSystem> FileSystem for EncryptedFS<FS> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.inner.create_dir(&self.inner_path(path)?)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.outer_metadata(self.inner.metadata(&self.inner_path(path)?)?)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        // chunks are read before they're modified, and appends are positioned by the handle
+        options.validate()?;
+        let inner_options = OpenOptions {
+            append: false,
+            create: options.create,
+            create_new: options.create_new,
+            read: true,
+            truncate: options.truncate,
+            write: options.writable(),
+            mode: options.mode,
+            direct: false,
+            access: options.access,
+        };
+        let inner = self
+            .inner
+            .open_file_options(&self.inner_path(path)?, &inner_options)?;
+
+        Ok(Box::new(EncryptedFile::new(
+            inner,
+            self.keys.clone(),
+            options,
+        )?))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let dir = normalize_and_relativize(path);
+        let entries = self
+            .inner
+            .read_dir(&self.inner_path(path)?)?
+            .filter_map(|entry| {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => return Some(Err(err)),
+                };
+
+                let name = entry.path.file_name()?.to_str()?;
+                let name = if self.obfuscate_names {
+                    self.keys.decrypt_name(name)?
+                } else {
+                    name.to_owned()
+                };
+
+                let path = dir.join(name);
+                Some(
+                    self.outer_metadata(entry.metadata)
+                        .map(|metadata| DirEntry { path, metadata }),
+                )
+            })
+            .collect_vec();
+
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.inner.remove_dir(&self.inner_path(path)?)
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.inner.remove_file(&self.inner_path(path)?)
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        self.inner.create_dir_with(&self.inner_path(path)?, mode)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        // chunks aren't bound to the path of their file, so they can be moved as they are
+        self.inner
+            .rename(&self.inner_path(from)?, &self.inner_path(to)?)
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        self.inner.stats()
+    }
+}
+
+/// A decrypted chunk of a file.
+struct Chunk {
+    index: u64,
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+/// A handle to an encrypted file. The chunk at the cursor is kept decrypted, and is encrypted and written back when
+/// the cursor leaves it, the file is flushed, or the handle is dropped.
+struct EncryptedFile {
+    inner: Box<dyn File>,
+    keys: Arc<Keys>,
+    /// The identifier of the file, or `None` if the header hasn't been written yet.
+    file_id: Option<[u8; FILE_ID_LEN]>,
+    len: u64,
+    position: u64,
+    append: bool,
+    write: bool,
+    chunk: Option<Chunk>,
+}
+
+impl EncryptedFile {
+    /// Creates a handle to an encrypted file, reading its header.
+    ///
+    /// # Arguments
+    /// `inner`: The encrypted file, opened for reading.  
+    /// `keys`: The keys of the filesystem.  
+    /// `options`: The options the file was opened with.  
+    fn new(
+        mut inner: Box<dyn File>,
+        keys: Arc<Keys>,
+        options: &OpenOptions,
+    ) -> crate::Result<Self> {
+        let inner_len = inner.metadata()?.len;
+        let len = keys
+            .cipher
+            .plaintext_len(inner_len)
+            .ok_or_else(|| malformed("Malformed encrypted file"))?;
+
+        let file_id = if inner_len == 0 {
+            None
+        } else {
+            let mut header = [0; HEADER_LEN as usize];
+            inner.seek(SeekFrom::Start(0))?;
+            inner.read_exact(&mut header)?;
+
+            let (magic, rest) = header.split_at(MAGIC.len());
+            let (cipher, file_id) = rest.split_at(1);
+            if magic != MAGIC {
+                return Err(malformed("Not an encrypted file"));
+            }
+            if cipher[0] != keys.cipher.id() {
+                return Err(malformed("Encrypted with a different cipher"));
+            }
+
+            // unwrap: the header is sized to fit the identifier
+            Some(file_id.try_into().unwrap())
+        };
+
+        Ok(Self {
+            inner,
+            keys,
+            file_id,
+            len,
+            position: 0,
+            append: options.append,
+            write: options.writable(),
+            chunk: None,
+        })
+    }
+
+    /// Returns the number of chunks in the file.
+    fn chunk_count(&self) -> u64 {
+        self.len.div_ceil(CHUNK_SIZE)
+    }
+
+    /// Returns the number of plaintext bytes in a chunk within the file.
+    ///
+    /// # Arguments
+    /// `index`: The index of the chunk.  
+    fn chunk_len(&self, index: u64) -> usize {
+        (self.len - index * CHUNK_SIZE).min(CHUNK_SIZE) as usize
+    }
+
+    /// Returns the data a chunk is authenticated with.
+    ///
+    /// # Arguments
+    /// `file_id`: The identifier of the file.  
+    /// `index`: The index of the chunk.  
+    /// `last`: True if the chunk is the last in the file.  
+    fn associated_data(file_id: &[u8; FILE_ID_LEN], index: u64, last: bool) -> Vec<u8> {
+        let mut aad = file_id.to_vec();
+        aad.extend(index.to_le_bytes());
+        aad.push(last as u8);
+        aad
+    }
+
+    /// Decrypts a chunk and makes it the current chunk, writing back the previous one. Chunks past the end of the file
+    /// are empty.
+    ///
+    /// # Arguments
+    /// `index`: The index of the chunk.  
+    fn load_chunk(&mut self, index: u64) -> io::Result<&mut Chunk> {
+        if self.chunk.as_ref().map(|chunk| chunk.index) != Some(index) {
+            self.flush_chunk()?;
+
+            let data = match self.file_id {
+                Some(file_id) if index < self.chunk_count() => {
+                    let mut stored =
+                        vec![0; self.chunk_len(index) + self.keys.cipher.chunk_overhead() as usize];
+                    self.inner
+                        .seek(SeekFrom::Start(self.keys.cipher.chunk_offset(index)))?;
+                    self.inner.read_exact(&mut stored)?;
+
+                    let (nonce, ciphertext) = stored.split_at(self.keys.cipher.nonce_len());
+                    let aad =
+                        Self::associated_data(&file_id, index, index + 1 == self.chunk_count());
+                    self.keys.contents.decrypt(nonce, ciphertext, &aad)?
+                }
+                _ => Vec::new(),
+            };
+            self.chunk = Some(Chunk {
+                index,
+                data,
+                dirty: false,
+            });
+        }
+
+        // unwrap: the chunk was just loaded
+        Ok(self.chunk.as_mut().unwrap())
+    }
+
+    /// Encrypts the current chunk and writes it to the inner file, if it was modified.
+    fn flush_chunk(&mut self) -> io::Result<()> {
+        if !self.chunk.as_ref().is_some_and(|chunk| chunk.dirty) {
+            return Ok(());
+        }
+
+        let file_id = match self.file_id {
+            Some(file_id) => file_id,
+            None => self.write_header()?,
+        };
+        let chunk_count = self.chunk_count();
+        // unwrap: the chunk is dirty, so it's present
+        let chunk = self.chunk.as_mut().unwrap();
+
+        let nonce = self.keys.random_nonce();
+        let aad = Self::associated_data(&file_id, chunk.index, chunk.index + 1 == chunk_count);
+        let ciphertext = self.keys.contents.encrypt(&nonce, &chunk.data, &aad);
+
+        self.inner
+            .seek(SeekFrom::Start(self.keys.cipher.chunk_offset(chunk.index)))?;
+        self.inner.write_all(&nonce)?;
+        self.inner.write_all(&ciphertext)?;
+        chunk.dirty = false;
+
+        Ok(())
+    }
+
+    /// Writes the header of a new file with a random identifier, returning the identifier.
+    fn write_header(&mut self) -> io::Result<[u8; FILE_ID_LEN]> {
+        let mut file_id = [0; FILE_ID_LEN];
+        OsRng.fill_bytes(&mut file_id);
+
+        self.inner.seek(SeekFrom::Start(0))?;
+        self.inner.write_all(&MAGIC)?;
+        self.inner.write_all(&[self.keys.cipher.id()])?;
+        self.inner.write_all(&file_id)?;
+        self.file_id = Some(file_id);
+
+        Ok(file_id)
+    }
+
+    /// Shrinks the file, re-encrypting the chunk that becomes the last one and cutting off the chunks after it.
+    ///
+    /// # Arguments
+    /// `len`: The new length of the file, which is shorter than the current length.  
+    fn shrink(&mut self, len: u64) -> io::Result<()> {
+        let chunk_count = len.div_ceil(CHUNK_SIZE);
+        // the chunk is loaded before the length changes, since its stored length depends on it
+        if let Some(last) = chunk_count.checked_sub(1) {
+            let chunk = self.load_chunk(last)?;
+            chunk.data.truncate((len - last * CHUNK_SIZE) as usize);
+            chunk.dirty = true;
+        } else {
+            self.chunk = None;
+        }
+        self.len = len;
+        self.flush_chunk()?;
+
+        if self.file_id.is_some() {
+            let inner_len = HEADER_LEN + len + chunk_count * self.keys.cipher.chunk_overhead();
+            self.inner.set_len(inner_len)?;
+        }
+        Ok(())
+    }
+
+    /// Extends the file with zeros.
+    ///
+    /// # Arguments
+    /// `len`: The new length of the file, which is longer than the current length.  
+    fn extend(&mut self, len: u64) -> io::Result<()> {
+        let old_chunk_count = self.chunk_count();
+        let new_chunk_count = len.div_ceil(CHUNK_SIZE);
+
+        // the last chunk fills up, and stops being the last chunk if chunks follow it
+        if let Some(last) = old_chunk_count.checked_sub(1) {
+            let chunk_len = (len - last * CHUNK_SIZE).min(CHUNK_SIZE) as usize;
+            let chunk = self.load_chunk(last)?;
+            chunk.data.resize(chunk_len, 0);
+            chunk.dirty = true;
+        }
+        self.len = len;
+
+        // chunks are written in order, so the inner file never has holes
+        for index in old_chunk_count..new_chunk_count {
+            self.flush_chunk()?;
+            self.chunk = Some(Chunk {
+                index,
+                data: vec![0; self.chunk_len(index)],
+                dirty: true,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for EncryptedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.len {
+            return Ok(0);
+        }
+
+        let offset = (self.position % CHUNK_SIZE) as usize;
+        let chunk = self.load_chunk(self.position / CHUNK_SIZE)?;
+        let read_len = buf.len().min(chunk.data.len() - offset);
+        buf[..read_len].copy_from_slice(&chunk.data[offset..offset + read_len]);
+        self.position += read_len as u64;
+
+        Ok(read_len)
+    }
+}
+
+impl Seek for EncryptedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+        }
+        .ok_or_else(|| invalid_input("Invalid seek to a negative or overflowing position"))?;
+
+        self.position = position;
+        Ok(position)
+    }
+}
+
+impl Write for EncryptedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.write {
+            return Err(not_writable());
+        }
+        if self.append {
+            self.position = self.len;
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // writes are split at chunk boundaries
+        let offset = (self.position % CHUNK_SIZE) as usize;
+        let write_len = buf.len().min(CHUNK_SIZE as usize - offset);
+        let end = self.position + write_len as u64;
+        if end > self.len {
+            self.extend(end)?;
+        }
+
+        let chunk = self.load_chunk(self.position / CHUNK_SIZE)?;
+        chunk.data[offset..offset + write_len].copy_from_slice(&buf[..write_len]);
+        chunk.dirty = true;
+        self.position = end;
+
+        Ok(write_len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.write {
+            return Ok(());
+        }
+
+        self.flush_chunk()?;
+        self.inner.flush()
+    }
+}
+
+impl File for EncryptedFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(Metadata {
+            len: self.len,
+            ..self.inner.metadata()?
+        })
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        if !self.write {
+            return Err(not_writable());
+        }
+        if len >= self.len {
+            return self.extend(len);
+        }
+        self.shrink(len)
+    }
+}
+
+impl Drop for EncryptedFile {
+    fn drop(&mut self) {
+        // errors can't be reported here, so callers who need to know should flush first
+        let _ = self.flush_chunk();
+    }
+}
+
+/// The alphabet of base32-encoded names, which is RFC 4648's in lowercase.
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Encodes bytes in unpadded, lowercase base32.
+///
+/// # Arguments
+/// `bytes`: The bytes to encode.  
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+
+    encoded
+}
+
+/// Decodes unpadded base32, in either case. Returns `None` if the string isn't base32.
+///
+/// # Arguments
+/// `encoded`: The encoded string.  
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for character in encoded.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&c| c == character.to_ascii_lowercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(decoded)
+}
+
+/// Returns an error indicating that encrypted data is malformed or has been tampered with.
+///
+/// # Arguments
+/// `error`: The description of the error.  
+fn malformed(error: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::encrypted_fs::{
+        base32_decode, base32_encode, Cipher, EncryptedFS, CHUNK_SIZE, HEADER_LEN,
+    };
+    use crate::memory_fs::MemoryFS;
+    use crate::util::test::{check_open_options, check_set_len, read_directory};
+    use crate::FileSystem;
+    use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+    use std::sync::Arc;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn contents(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for cipher in [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305] {
+            let inner = Arc::new(MemoryFS::default());
+            let encrypted_fs = EncryptedFS::new(inner.clone(), KEY, cipher);
+            let data = contents(10000);
+
+            encrypted_fs.create_dir("dir").unwrap();
+            encrypted_fs
+                .create_file("dir/file")
+                .unwrap()
+                .write_all(&data)
+                .unwrap();
+            encrypted_fs.create_file("empty").unwrap();
+
+            assert_eq!(
+                encrypted_fs
+                    .open_file("dir/file")
+                    .unwrap()
+                    .read_into_vec()
+                    .unwrap(),
+                data
+            );
+            assert_eq!(encrypted_fs.metadata("dir/file").unwrap().len(), 10000);
+            assert_eq!(
+                read_directory(&encrypted_fs, "dir")["dir/file"].len(),
+                10000
+            );
+            assert_eq!(encrypted_fs.metadata("empty").unwrap().len(), 0);
+
+            // three chunks, each with a nonce and a tag
+            let stored = inner
+                .open_file("dir/file")
+                .unwrap()
+                .read_into_vec()
+                .unwrap();
+            assert_eq!(
+                stored.len() as u64,
+                HEADER_LEN + 10000 + 3 * (cipher.nonce_len() as u64 + 16)
+            );
+            assert!(!stored.windows(64).any(|window| window == &data[..64]));
+        }
+    }
+
+    #[test]
+    fn seek_and_overwrite() {
+        let encrypted_fs = EncryptedFS::new(MemoryFS::default(), KEY, Cipher::XChaCha20Poly1305);
+        let mut expected = contents(5000);
+        encrypted_fs
+            .create_file("file")
+            .unwrap()
+            .write_all(&expected)
+            .unwrap();
+
+        // overwrite across a chunk boundary, then extend past the end with a gap
+        let mut file = encrypted_fs
+            .open_file_options("file", &crate::file::OpenOptions::default().write(true))
+            .unwrap();
+        file.seek(SeekFrom::Start(4000)).unwrap();
+        file.write_all(&[1; 200]).unwrap();
+        file.seek(SeekFrom::Start(9000)).unwrap();
+        file.write_all(b"end").unwrap();
+        file.seek(SeekFrom::Start(4090)).unwrap();
+        let mut buf = [0; 10];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1; 10]);
+        drop(file);
+
+        expected[4000..4200].fill(1);
+        expected.resize(9000, 0);
+        expected.extend(b"end");
+        assert_eq!(
+            encrypted_fs
+                .open_file("file")
+                .unwrap()
+                .read_into_vec()
+                .unwrap(),
+            expected
+        );
+
+        // appends go to the end regardless of the cursor
+        let mut file = encrypted_fs
+            .open_file_options("file", &crate::file::OpenOptions::default().append(true))
+            .unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(b"!").unwrap();
+        drop(file);
+        expected.push(b'!');
+        assert_eq!(
+            encrypted_fs
+                .open_file("file")
+                .unwrap()
+                .read_into_vec()
+                .unwrap(),
+            expected
+        );
+
+        assert!(encrypted_fs
+            .open_file("file")
+            .unwrap()
+            .write(b"no")
+            .is_err());
+    }
+
+    #[test]
+    fn obfuscated_names() {
+        let inner = Arc::new(MemoryFS::default());
+        let encrypted_fs =
+            EncryptedFS::new(inner.clone(), KEY, Cipher::Aes256Gcm).obfuscate_names(true);
+
+        encrypted_fs.create_dir_all("secret/plans").unwrap();
+        write!(
+            encrypted_fs.create_file("secret/plans/world").unwrap(),
+            "domination"
+        )
+        .unwrap();
+        encrypted_fs
+            .rename("secret/plans/world", "secret/world")
+            .unwrap();
+
+        itertools::assert_equal(
+            read_directory(&encrypted_fs, "secret").keys(),
+            vec!["secret/plans", "secret/world"],
+        );
+        assert_eq!(
+            encrypted_fs
+                .open_file("/secret/./world")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "domination"
+        );
+
+        // the inner filesystem only sees encrypted names, and foreign entries are hidden
+        inner.create_file("plain").unwrap();
+        let inner_root = read_directory(&inner, "");
+        assert_eq!(inner_root.len(), 2);
+        assert!(inner_root.keys().all(|name| !name.contains("secret")));
+        itertools::assert_equal(read_directory(&encrypted_fs, "").keys(), vec!["secret"]);
+    }
+
+    #[test]
+    fn tampering() {
+        let inner = Arc::new(MemoryFS::default());
+        let encrypted_fs = EncryptedFS::new(inner.clone(), KEY, Cipher::Aes256Gcm);
+        encrypted_fs
+            .create_file("file")
+            .unwrap()
+            .write_all(&contents(8192))
+            .unwrap();
+        let stored = inner.open_file("file").unwrap().read_into_vec().unwrap();
+
+        // a flipped bit
+        let mut modified = stored.clone();
+        modified[HEADER_LEN as usize + 100] ^= 1;
+        inner
+            .create_file("file")
+            .unwrap()
+            .write_all(&modified)
+            .unwrap();
+        let err = encrypted_fs
+            .open_file("file")
+            .unwrap()
+            .read_into_vec()
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // the last chunk truncated away
+        inner
+            .create_file("file")
+            .unwrap()
+            .write_all(&stored[..HEADER_LEN as usize + 4096 + 28])
+            .unwrap();
+        assert_eq!(encrypted_fs.metadata("file").unwrap().len(), 4096);
+        let err = encrypted_fs
+            .open_file("file")
+            .unwrap()
+            .read_into_vec()
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // a different key or cipher
+        inner
+            .create_file("file")
+            .unwrap()
+            .write_all(&stored)
+            .unwrap();
+        let other_key = EncryptedFS::new(inner.clone(), [8; 32], Cipher::Aes256Gcm);
+        assert!(other_key
+            .open_file("file")
+            .unwrap()
+            .read_into_vec()
+            .is_err());
+        let other_cipher = EncryptedFS::new(inner.clone(), KEY, Cipher::XChaCha20Poly1305);
+        assert!(other_cipher.open_file("file").is_err());
+    }
+
+    #[test]
+    fn base32() {
+        for len in 0..12 {
+            let bytes = contents(len);
+            let encoded = base32_encode(&bytes);
+            assert!(encoded.bytes().all(|c| !c.is_ascii_uppercase()));
+            assert_eq!(base32_decode(&encoded).unwrap(), bytes);
+            assert_eq!(base32_decode(&encoded.to_uppercase()).unwrap(), bytes);
+        }
+        assert!(base32_decode("not base 32!").is_none());
+    }
+
+    #[test]
+    fn open_options() {
+        for cipher in [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305] {
+            check_open_options(
+                &EncryptedFS::new(MemoryFS::default(), KEY, cipher),
+                "options",
+            );
+        }
+    }
+
+    #[test]
+    fn set_len() {
+        for cipher in [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305] {
+            let inner = Arc::new(MemoryFS::default());
+            let fs = EncryptedFS::new(inner.clone(), KEY, cipher);
+            check_set_len(&fs, "set_len");
+
+            // shrinking across chunks re-encrypts the new last chunk and cuts the stored file
+            let contents = contents(CHUNK_SIZE as usize * 2 + 10);
+            fs.create_file("chunks")
+                .unwrap()
+                .write_all(&contents)
+                .unwrap();
+            let mut file = fs
+                .open_file_options("chunks", &crate::file::OpenOptions::default().write(true))
+                .unwrap();
+            file.set_len(CHUNK_SIZE + 3).unwrap();
+            drop(file);
+            assert_eq!(
+                fs.open_file("chunks").unwrap().read_into_vec().unwrap(),
+                &contents[..CHUNK_SIZE as usize + 3]
+            );
+            let stored = read_directory(&*inner, "");
+            assert!(stored.values().any(|metadata| metadata.len
+                == HEADER_LEN + CHUNK_SIZE + 3 + 2 * cipher.chunk_overhead()));
+        }
+    }
+}
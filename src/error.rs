@@ -1,4 +1,168 @@
-use std::io;
+use std::fmt::{Display, Formatter};
+use std::{fmt, io};
 
 /// The result of a virtual filesystem operation.
 pub type Result<T> = io::Result<T>;
+
+/// A precise cause of failure that filesystems report, which callers can branch on with [`ErrorExt::vfs_kind`]
+/// rather than matching messages.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum VfsErrorKind {
+    /// A component of the path that was expected to be a directory isn't one.
+    NotADirectory,
+    /// The path refers to a directory where a file was expected.
+    IsADirectory,
+    /// The filesystem can't be modified.
+    ReadOnlyFilesystem,
+    /// The operation spans two filesystems, such as renaming from one mount to another.
+    CrossesMountPoint,
+    /// A limit on the bytes or files stored was reached.
+    QuotaExceeded,
+}
+
+impl VfsErrorKind {
+    /// Returns the `io::ErrorKind` that errors of this kind are reported with, so that hosts and protocols that only
+    /// know the standard kinds still see the closest match.
+    pub fn io_kind(self) -> io::ErrorKind {
+        match self {
+            Self::NotADirectory => io::ErrorKind::NotADirectory,
+            Self::IsADirectory => io::ErrorKind::IsADirectory,
+            Self::ReadOnlyFilesystem => io::ErrorKind::ReadOnlyFilesystem,
+            Self::CrossesMountPoint => io::ErrorKind::CrossesDevices,
+            Self::QuotaExceeded => io::ErrorKind::QuotaExceeded,
+        }
+    }
+
+    /// Returns the kind an `io::ErrorKind` from a host or a remote peer corresponds to, if any.
+    ///
+    /// # Arguments
+    /// `kind`: The standard kind.  
+    pub fn from_io_kind(kind: io::ErrorKind) -> Option<Self> {
+        match kind {
+            io::ErrorKind::NotADirectory => Some(Self::NotADirectory),
+            io::ErrorKind::IsADirectory => Some(Self::IsADirectory),
+            io::ErrorKind::ReadOnlyFilesystem => Some(Self::ReadOnlyFilesystem),
+            io::ErrorKind::CrossesDevices => Some(Self::CrossesMountPoint),
+            io::ErrorKind::QuotaExceeded => Some(Self::QuotaExceeded),
+            _ => None,
+        }
+    }
+
+    /// Returns a description of the kind.
+    fn description(self) -> &'static str {
+        match self {
+            Self::NotADirectory => "Not a directory",
+            Self::IsADirectory => "Is a directory",
+            Self::ReadOnlyFilesystem => "Read-only filesystem",
+            Self::CrossesMountPoint => "Crosses a mount point",
+            Self::QuotaExceeded => "Quota exceeded",
+        }
+    }
+}
+
+impl Display for VfsErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+/// An error of a [`VfsErrorKind`], carried within an `io::Error` of the corresponding standard kind.
+#[derive(Debug)]
+pub struct VfsError {
+    kind: VfsErrorKind,
+    message: Option<String>,
+}
+
+impl VfsError {
+    /// Creates an error with a message describing it beyond its kind.
+    ///
+    /// # Arguments
+    /// `kind`: The kind of the error.  
+    /// `message`: The message.  
+    pub fn new<S: Into<String>>(kind: VfsErrorKind, message: S) -> Self {
+        Self {
+            kind,
+            message: Some(message.into()),
+        }
+    }
+
+    /// Returns the kind of the error.
+    pub fn kind(&self) -> VfsErrorKind {
+        self.kind
+    }
+}
+
+impl Display for VfsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.message {
+            Some(message) => f.write_str(message),
+            None => Display::fmt(&self.kind, f),
+        }
+    }
+}
+
+impl std::error::Error for VfsError {}
+
+impl From<VfsErrorKind> for VfsError {
+    fn from(kind: VfsErrorKind) -> Self {
+        Self {
+            kind,
+            message: None,
+        }
+    }
+}
+
+impl From<VfsError> for io::Error {
+    fn from(error: VfsError) -> Self {
+        io::Error::new(error.kind.io_kind(), error)
+    }
+}
+
+impl From<VfsErrorKind> for io::Error {
+    fn from(kind: VfsErrorKind) -> Self {
+        VfsError::from(kind).into()
+    }
+}
+
+/// Extends `io::Error` with the precise causes of failure of virtual filesystems.
+pub trait ErrorExt {
+    /// Returns the precise cause of the error, if it has one. Errors raised by this crate carry their kind, and
+    /// errors of hosts and remote peers are classified by their standard kind.
+    fn vfs_kind(&self) -> Option<VfsErrorKind>;
+}
+
+impl ErrorExt for io::Error {
+    fn vfs_kind(&self) -> Option<VfsErrorKind> {
+        self.get_ref()
+            .and_then(|error| error.downcast_ref::<VfsError>())
+            .map(VfsError::kind)
+            .or_else(|| VfsErrorKind::from_io_kind(self.kind()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::{ErrorExt, VfsError, VfsErrorKind};
+    use std::io;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn vfs_kind() {
+        let err = io::Error::from(VfsErrorKind::CrossesMountPoint);
+        assert_eq!(err.kind(), ErrorKind::CrossesDevices);
+        assert_eq!(err.vfs_kind(), Some(VfsErrorKind::CrossesMountPoint));
+        assert_eq!(err.to_string(), "Crosses a mount point");
+
+        let err = io::Error::from(VfsError::new(VfsErrorKind::QuotaExceeded, "Too many files"));
+        assert_eq!(err.kind(), ErrorKind::QuotaExceeded);
+        assert_eq!(err.vfs_kind(), Some(VfsErrorKind::QuotaExceeded));
+        assert_eq!(err.to_string(), "Too many files");
+
+        // errors of hosts are classified by their standard kind
+        let err = io::Error::from(ErrorKind::NotADirectory);
+        assert_eq!(err.vfs_kind(), Some(VfsErrorKind::NotADirectory));
+        assert_eq!(io::Error::from(ErrorKind::NotFound).vfs_kind(), None);
+        assert_eq!(io::Error::other("message").vfs_kind(), None);
+    }
+}
@@ -0,0 +1,1759 @@
+use crate::file::{FileSystemStats, Metadata};
+use crate::time::DateTime;
+use crate::util::{
+    already_exists, invalid_input, invalid_path, is_a_directory, not_a_directory, not_found, now,
+};
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The name of the filesystem in an exFAT boot sector, where a FAT boot sector names the OEM.
+pub(crate) const SIGNATURE: &[u8; 8] = b"EXFAT   ";
+
+/// The size of a directory entry.
+const ENTRY_SIZE: usize = 32;
+/// The number of the first cluster of the cluster heap.
+const FIRST_CLUSTER: u32 = 2;
+/// The FAT entry of the last cluster of a chain.
+const END_OF_CHAIN: u32 = 0xffff_ffff;
+/// The size of the sectors of new volumes.
+const SECTOR_SHIFT: u8 = 9;
+/// The number of UTF-16 code units of a name held by each file name entry.
+const NAME_ENTRY_CHARS: usize = 15;
+/// The number of UTF-16 code units of the longest name.
+const MAX_NAME_LEN: usize = 255;
+/// The number of UTF-16 code units of the longest volume label.
+const MAX_LABEL_LEN: usize = 11;
+
+/// The types of directory entries.
+mod entry_type {
+    pub(crate) const END_OF_DIRECTORY: u8 = 0x00;
+    /// Set on entries that are in use. Deleted entries keep their type without this bit.
+    pub(crate) const IN_USE: u8 = 0x80;
+    pub(crate) const ALLOCATION_BITMAP: u8 = 0x81;
+    pub(crate) const UP_CASE_TABLE: u8 = 0x82;
+    pub(crate) const VOLUME_LABEL: u8 = 0x83;
+    pub(crate) const FILE: u8 = 0x85;
+    pub(crate) const STREAM_EXTENSION: u8 = 0xc0;
+    pub(crate) const FILE_NAME: u8 = 0xc1;
+}
+
+/// The attributes of files and directories.
+mod attributes {
+    pub(crate) const DIRECTORY: u16 = 0x10;
+    pub(crate) const ARCHIVE: u16 = 0x20;
+}
+
+/// The flags of stream extension entries.
+mod stream_flags {
+    pub(crate) const ALLOCATION_POSSIBLE: u8 = 0x1;
+    /// The clusters are contiguous, and aren't described by the FAT.
+    pub(crate) const NO_FAT_CHAIN: u8 = 0x2;
+}
+
+/// The flags of the volume, in the boot sector.
+mod volume_flags {
+    /// Selects the second FAT and allocation bitmap of TexFAT volumes.
+    pub(crate) const ACTIVE_FAT: u16 = 0x1;
+    pub(crate) const VOLUME_DIRTY: u16 = 0x2;
+}
+
+/// The offset of the volume flags within the boot sector.
+const VOLUME_FLAGS_OFFSET: u64 = 106;
+/// The offset of the percentage of the cluster heap in use within the boot sector.
+const PERCENT_IN_USE_OFFSET: u64 = 112;
+/// The timestamp offset of times in UTC.
+const UTC: u8 = 0x80;
+
+/// An exFAT volume. Directory entries and file contents are read from the disk as they're needed, while the
+/// allocation bitmap and the up-case table are kept in memory.
+pub(crate) struct ExFat<T: Read + Write + Seek> {
+    disk: T,
+    cluster_shift: u8,
+    /// The offset of the active FAT, in bytes.
+    fat_offset: u64,
+    /// The offset of the cluster heap, in bytes.
+    heap_offset: u64,
+    cluster_count: u32,
+    root: Chain,
+    bitmap: Vec<u8>,
+    bitmap_clusters: Vec<u32>,
+    /// Maps UTF-16 code units to their upper case. Code units past the end of the table are their own upper case.
+    up_case: Vec<u16>,
+    label: String,
+    volume_flags: u16,
+}
+
+/// The clusters holding the contents of a file or directory.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Chain {
+    /// The first cluster, or zero if there are no clusters.
+    first_cluster: u32,
+    /// True if the clusters are contiguous, and aren't described by the FAT.
+    contiguous: bool,
+    /// The length of the contents, in bytes, which determines the number of contiguous clusters.
+    len: u64,
+}
+
+impl Chain {
+    /// A chain without clusters.
+    const EMPTY: Self = Self {
+        first_cluster: 0,
+        contiguous: false,
+        len: 0,
+    };
+}
+
+/// The entry set of a file or directory, along with where it's stored.
+#[derive(Clone, Debug)]
+struct EntrySet {
+    /// The directory holding the set.
+    parent: Chain,
+    /// The index of the set's first entry within the directory.
+    index: usize,
+    /// The file entry, the stream extension entry and the file name entries.
+    entries: Vec<[u8; ENTRY_SIZE]>,
+}
+
+impl EntrySet {
+    /// Returns the attributes of the file or directory.
+    fn attributes(&self) -> u16 {
+        le16(&self.entries[0], 4)
+    }
+
+    /// Returns true if the set describes a directory.
+    fn is_directory(&self) -> bool {
+        self.attributes() & attributes::DIRECTORY != 0
+    }
+
+    /// Returns the name of the file or directory, in UTF-16.
+    fn name(&self) -> Vec<u16> {
+        let name_len = usize::from(self.entries[1][3]);
+        self.entries[2..]
+            .iter()
+            .flat_map(|entry| entry[2..].chunks_exact(2).map(|char| le16(char, 0)))
+            .take(name_len)
+            .collect()
+    }
+
+    /// Returns the clusters of the contents.
+    fn chain(&self) -> Chain {
+        let stream = &self.entries[1];
+        Chain {
+            first_cluster: le32(stream, 20),
+            contiguous: stream[1] & stream_flags::NO_FAT_CHAIN != 0,
+            len: le64(stream, 24),
+        }
+    }
+
+    /// Sets the clusters and the length of the contents.
+    ///
+    /// # Arguments
+    /// `chain`: The clusters of the contents.  
+    fn set_chain(&mut self, chain: Chain) {
+        let stream = &mut self.entries[1];
+        stream[1] = stream_flags::ALLOCATION_POSSIBLE;
+        if chain.contiguous {
+            stream[1] |= stream_flags::NO_FAT_CHAIN;
+        }
+        stream[20..24].copy_from_slice(&chain.first_cluster.to_le_bytes());
+        stream[24..32].copy_from_slice(&chain.len.to_le_bytes());
+    }
+
+    /// Returns the length of the contents that has been written. The rest of the contents reads as zeros.
+    fn valid_len(&self) -> u64 {
+        le64(&self.entries[1], 8).min(self.chain().len)
+    }
+
+    /// Sets the length of the contents that has been written.
+    ///
+    /// # Arguments
+    /// `valid_len`: The length that has been written.  
+    fn set_valid_len(&mut self, valid_len: u64) {
+        self.entries[1][8..16].copy_from_slice(&valid_len.to_le_bytes());
+    }
+
+    /// Records that the contents were modified now.
+    fn touch(&mut self) {
+        let (timestamp, increment, utc_offset) = encode_timestamp(now());
+        let file = &mut self.entries[0];
+        let attributes = le16(file, 4) | attributes::ARCHIVE;
+        file[4..6].copy_from_slice(&attributes.to_le_bytes());
+        file[12..16].copy_from_slice(&timestamp.to_le_bytes());
+        file[16..20].copy_from_slice(&timestamp.to_le_bytes());
+        file[21] = increment;
+        file[23] = utc_offset;
+        file[24] = utc_offset;
+    }
+
+    /// Returns the metadata of the file or directory.
+    fn metadata(&self) -> Metadata {
+        let metadata = if self.is_directory() {
+            Metadata::directory()
+        } else {
+            Metadata::file(self.chain().len)
+        };
+
+        let file = &self.entries[0];
+        Metadata {
+            created: decode_timestamp(le32(file, 8), file[20], file[22]),
+            modified: decode_timestamp(le32(file, 12), file[21], file[23]),
+            accessed: decode_timestamp(le32(file, 16), 0, file[24]),
+            ..metadata
+        }
+    }
+
+    /// Returns true if the entries form a valid set.
+    fn is_valid(&self) -> bool {
+        let Some(stream) = self.entries.get(1) else {
+            return false;
+        };
+        let name_len = usize::from(stream[3]);
+        let name_entries = name_len.div_ceil(NAME_ENTRY_CHARS);
+
+        self.entries.len() >= 2 + name_entries
+            && stream[0] == entry_type::STREAM_EXTENSION
+            && name_len > 0
+            && self.entries[2..2 + name_entries]
+                .iter()
+                .all(|entry| entry[0] == entry_type::FILE_NAME)
+            && le16(&self.entries[0], 2) == set_checksum(&self.entries)
+    }
+}
+
+/// A directory, along with its entry set, which the root directory doesn't have.
+struct Directory {
+    chain: Chain,
+    set: Option<EntrySet>,
+}
+
+impl<T: Read + Write + Seek> ExFat<T> {
+    /// Mounts the exFAT volume in `disk`.
+    ///
+    /// # Arguments
+    /// `disk`: The disk image.  
+    pub(crate) fn new(mut disk: T) -> crate::Result<Self> {
+        let mut boot_sector = [0; 512];
+        disk.seek(SeekFrom::Start(0))?;
+        disk.read_exact(&mut boot_sector)?;
+        let sector_shift = boot_sector[108];
+        let cluster_shift = sector_shift.wrapping_add(boot_sector[109]);
+        if &boot_sector[3..11] != SIGNATURE
+            || boot_sector[510..] != [0x55, 0xaa]
+            || !(9..=12).contains(&sector_shift)
+            || cluster_shift > 25
+        {
+            return Err(corrupt());
+        }
+
+        // the last sector of the boot region holds the checksum of the others
+        let sector_size = 1 << sector_shift;
+        let mut boot_region = vec![0; 12 * sector_size];
+        disk.seek(SeekFrom::Start(0))?;
+        disk.read_exact(&mut boot_region)?;
+        let (boot_sectors, checksum_sector) = boot_region.split_at(11 * sector_size);
+        let checksum = boot_checksum(boot_sectors);
+        if checksum_sector
+            .chunks_exact(4)
+            .any(|bytes| le32(bytes, 0) != checksum)
+        {
+            return Err(corrupt());
+        }
+
+        let volume_flags = le16(&boot_sector, 106);
+        let active_fat =
+            u8::from(boot_sector[110] == 2 && volume_flags & volume_flags::ACTIVE_FAT != 0);
+        let fat_len = u64::from(le32(&boot_sector, 84)) << sector_shift;
+        let cluster_count = le32(&boot_sector, 92);
+        if (u64::from(cluster_count) + 2) * 4 > fat_len {
+            return Err(corrupt());
+        }
+
+        let mut volume = Self {
+            disk,
+            cluster_shift,
+            fat_offset: (u64::from(le32(&boot_sector, 80)) << sector_shift)
+                + u64::from(active_fat) * fat_len,
+            heap_offset: u64::from(le32(&boot_sector, 88)) << sector_shift,
+            cluster_count,
+            root: Chain {
+                first_cluster: le32(&boot_sector, 96),
+                ..Chain::EMPTY
+            },
+            bitmap: Vec::new(),
+            bitmap_clusters: Vec::new(),
+            up_case: Vec::new(),
+            label: String::new(),
+            volume_flags,
+        };
+
+        // the root directory describes the allocation bitmap and the up-case table
+        let mut bitmap = None;
+        let mut up_case = None;
+        for entry in volume.read_directory(volume.root)? {
+            match entry[0] {
+                entry_type::END_OF_DIRECTORY => break,
+                entry_type::ALLOCATION_BITMAP if entry[1] & 1 == active_fat => {
+                    bitmap = Some((
+                        Chain {
+                            first_cluster: le32(&entry, 20),
+                            ..Chain::EMPTY
+                        },
+                        le64(&entry, 24),
+                    ));
+                }
+                entry_type::UP_CASE_TABLE => {
+                    up_case = Some((
+                        Chain {
+                            first_cluster: le32(&entry, 20),
+                            ..Chain::EMPTY
+                        },
+                        le64(&entry, 24),
+                        le32(&entry, 4),
+                    ));
+                }
+                entry_type::VOLUME_LABEL => {
+                    let label_len = usize::from(entry[1]).min(MAX_LABEL_LEN);
+                    let label = entry[2..2 + 2 * label_len]
+                        .chunks_exact(2)
+                        .map(|char| le16(char, 0))
+                        .collect::<Vec<_>>();
+                    volume.label = String::from_utf16_lossy(&label);
+                }
+                _ => {}
+            }
+        }
+
+        let (bitmap_chain, bitmap_len) = bitmap.ok_or_else(corrupt)?;
+        if bitmap_len < u64::from(cluster_count).div_ceil(8) {
+            return Err(corrupt());
+        }
+        let bitmap_clusters = volume.clusters(bitmap_chain)?;
+        volume.bitmap = volume.read_chain(&bitmap_clusters, bitmap_len)?;
+        volume.bitmap_clusters = bitmap_clusters;
+
+        let (up_case_chain, up_case_len, up_case_checksum) = up_case.ok_or_else(corrupt)?;
+        let up_case_clusters = volume.clusters(up_case_chain)?;
+        let up_case = volume.read_chain(&up_case_clusters, up_case_len)?;
+        if table_checksum(&up_case) != up_case_checksum {
+            return Err(corrupt());
+        }
+        volume.up_case = decompress_up_case(&up_case);
+
+        Ok(volume)
+    }
+
+    /// Formats `disk` as an empty exFAT volume spanning the whole image. The cluster size is chosen from the size
+    /// of the image.
+    ///
+    /// # Arguments
+    /// `disk`: The disk image.  
+    /// `label`: The volume label, of up to 11 characters.  
+    pub(crate) fn format(disk: &mut T, label: &str) -> crate::Result<()> {
+        let label = label.encode_utf16().collect::<Vec<_>>();
+        if label.len() > MAX_LABEL_LEN {
+            return Err(invalid_input("Volume labels are at most 11 characters"));
+        }
+
+        // larger volumes have larger clusters, as recommended by the specification
+        let disk_len = disk.seek(SeekFrom::End(0))?;
+        let cluster_shift: u8 = if disk_len <= 0x1000_0000 {
+            12
+        } else if disk_len <= 0x8_0000_0000 {
+            15
+        } else {
+            17
+        };
+        let sectors_per_cluster = 1u64 << (cluster_shift - SECTOR_SHIFT);
+        let cluster_size = 1u64 << cluster_shift;
+        let volume_len = disk_len >> SECTOR_SHIFT;
+
+        // the FAT follows the main and backup boot regions, and everything is aligned to clusters
+        let fat_offset = 24u64.next_multiple_of(sectors_per_cluster);
+        let max_clusters = volume_len.saturating_sub(fat_offset) / sectors_per_cluster;
+        let fat_len = ((max_clusters + 2) * 4).div_ceil(1 << SECTOR_SHIFT);
+        let heap_offset = (fat_offset + fat_len).next_multiple_of(sectors_per_cluster);
+        let cluster_count =
+            (volume_len.saturating_sub(heap_offset) / sectors_per_cluster).min(0xffff_fff5);
+
+        // the allocation bitmap, the up-case table and the root directory are the first clusters of the heap
+        let up_case = compressed_up_case()
+            .into_iter()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        let bitmap_len = cluster_count.div_ceil(8);
+        let system_chains = [
+            bitmap_len.div_ceil(cluster_size),
+            (up_case.len() as u64).div_ceil(cluster_size),
+            1,
+        ];
+        let used_clusters = system_chains.iter().sum::<u64>();
+        if cluster_count <= used_clusters {
+            return Err(invalid_input(
+                "The disk image is too small for an exFAT volume",
+            ));
+        }
+        let cluster_offset =
+            |cluster: u64| (heap_offset << SECTOR_SHIFT) + (cluster - 2) * cluster_size;
+
+        let mut fat = vec![0; (fat_len << SECTOR_SHIFT) as usize];
+        fat[..8].copy_from_slice(&[0xf8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        let mut cluster = u64::from(FIRST_CLUSTER);
+        let mut first_clusters = [0; 3];
+        for (first_cluster, clusters) in first_clusters.iter_mut().zip(system_chains) {
+            *first_cluster = cluster as u32;
+            for _ in 1..clusters {
+                fat[cluster as usize * 4..][..4]
+                    .copy_from_slice(&(cluster as u32 + 1).to_le_bytes());
+                cluster += 1;
+            }
+            fat[cluster as usize * 4..][..4].copy_from_slice(&END_OF_CHAIN.to_le_bytes());
+            cluster += 1;
+        }
+        write_at(disk, fat_offset << SECTOR_SHIFT, &fat)?;
+
+        let mut bitmap = vec![0; (system_chains[0] * cluster_size) as usize];
+        for cluster in 0..used_clusters as usize {
+            bitmap[cluster / 8] |= 1 << (cluster % 8);
+        }
+        write_at(disk, cluster_offset(first_clusters[0].into()), &bitmap)?;
+
+        let mut up_case_clusters = up_case.clone();
+        up_case_clusters.resize((system_chains[1] * cluster_size) as usize, 0);
+        write_at(
+            disk,
+            cluster_offset(first_clusters[1].into()),
+            &up_case_clusters,
+        )?;
+
+        let mut root = vec![0; cluster_size as usize];
+        root[0] = entry_type::VOLUME_LABEL;
+        root[1] = label.len() as u8;
+        for (char, bytes) in label.iter().zip(root[2..24].chunks_exact_mut(2)) {
+            bytes.copy_from_slice(&char.to_le_bytes());
+        }
+        let bitmap_entry = &mut root[ENTRY_SIZE..2 * ENTRY_SIZE];
+        bitmap_entry[0] = entry_type::ALLOCATION_BITMAP;
+        bitmap_entry[20..24].copy_from_slice(&first_clusters[0].to_le_bytes());
+        bitmap_entry[24..32].copy_from_slice(&bitmap_len.to_le_bytes());
+        let up_case_entry = &mut root[2 * ENTRY_SIZE..3 * ENTRY_SIZE];
+        up_case_entry[0] = entry_type::UP_CASE_TABLE;
+        up_case_entry[4..8].copy_from_slice(&table_checksum(&up_case).to_le_bytes());
+        up_case_entry[20..24].copy_from_slice(&first_clusters[1].to_le_bytes());
+        up_case_entry[24..32].copy_from_slice(&(up_case.len() as u64).to_le_bytes());
+        write_at(disk, cluster_offset(first_clusters[2].into()), &root)?;
+
+        let mut boot_region = vec![0; 12 << SECTOR_SHIFT];
+        let boot_sector = &mut boot_region[..1 << SECTOR_SHIFT];
+        boot_sector[..3].copy_from_slice(&[0xeb, 0x76, 0x90]);
+        boot_sector[3..11].copy_from_slice(SIGNATURE);
+        boot_sector[72..80].copy_from_slice(&volume_len.to_le_bytes());
+        boot_sector[80..84].copy_from_slice(&(fat_offset as u32).to_le_bytes());
+        boot_sector[84..88].copy_from_slice(&(fat_len as u32).to_le_bytes());
+        boot_sector[88..92].copy_from_slice(&(heap_offset as u32).to_le_bytes());
+        boot_sector[92..96].copy_from_slice(&(cluster_count as u32).to_le_bytes());
+        boot_sector[96..100].copy_from_slice(&first_clusters[2].to_le_bytes());
+        let serial_number = now().duration_since(UNIX_EPOCH).map_or(0, |duration| {
+            duration.as_secs() as u32 ^ duration.subsec_nanos()
+        });
+        boot_sector[100..104].copy_from_slice(&serial_number.to_le_bytes());
+        boot_sector[104..106].copy_from_slice(&0x0100u16.to_le_bytes());
+        boot_sector[108] = SECTOR_SHIFT;
+        boot_sector[109] = cluster_shift - SECTOR_SHIFT;
+        boot_sector[110] = 1;
+        boot_sector[111] = 0x80;
+        boot_sector[112] = (used_clusters * 100 / cluster_count) as u8;
+        // there's no boot code, so booting from the volume halts
+        boot_sector[120..510].fill(0xf4);
+        boot_sector[510..].copy_from_slice(&[0x55, 0xaa]);
+        for sector in
+            boot_region[1 << SECTOR_SHIFT..9 << SECTOR_SHIFT].chunks_exact_mut(1 << SECTOR_SHIFT)
+        {
+            sector[(1 << SECTOR_SHIFT) - 4..].copy_from_slice(&[0, 0, 0x55, 0xaa]);
+        }
+        let checksum = boot_checksum(&boot_region[..11 << SECTOR_SHIFT]);
+        for bytes in boot_region[11 << SECTOR_SHIFT..].chunks_exact_mut(4) {
+            bytes.copy_from_slice(&checksum.to_le_bytes());
+        }
+        write_at(disk, 0, &boot_region)?;
+        write_at(disk, 12 << SECTOR_SHIFT, &boot_region)?;
+
+        disk.flush()
+    }
+
+    /// Returns the volume label.
+    pub(crate) fn label(&self) -> String {
+        self.label.clone()
+    }
+
+    /// Returns the metadata of the file or directory at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The path within the volume.  
+    pub(crate) fn metadata(&mut self, path: &str) -> crate::Result<Metadata> {
+        Ok(self
+            .find(path)?
+            .map_or_else(Metadata::directory, |set| set.metadata()))
+    }
+
+    /// Returns the names and metadata of the entries of the directory at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The path within the volume.  
+    pub(crate) fn read_dir(&mut self, path: &str) -> crate::Result<Vec<(String, Metadata)>> {
+        let directory = self.directory(path)?;
+        Ok(self
+            .sets(directory.chain)?
+            .into_iter()
+            .map(|set| (String::from_utf16_lossy(&set.name()), set.metadata()))
+            .collect())
+    }
+
+    /// Creates a directory at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The path within the volume.  
+    pub(crate) fn create_dir(&mut self, path: &str) -> crate::Result<()> {
+        let (mut parent, name) = self.new_entry(path)?;
+
+        // directories always have a cluster, filled with unused entries
+        let mut chain = Chain::EMPTY;
+        self.resize_chain(&mut chain, 1 << self.cluster_shift)?;
+        let zeros = vec![0; 1 << self.cluster_shift];
+        self.write(self.cluster_offset(chain.first_cluster), &zeros)?;
+
+        let entries = self.new_set(&name, attributes::DIRECTORY, chain);
+        self.insert_set(&mut parent, entries)?;
+        self.disk.flush()
+    }
+
+    /// Creates an empty file at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The path within the volume.  
+    pub(crate) fn create_file(&mut self, path: &str) -> crate::Result<()> {
+        let (mut parent, name) = self.new_entry(path)?;
+        let entries = self.new_set(&name, attributes::ARCHIVE, Chain::EMPTY);
+        self.insert_set(&mut parent, entries)?;
+        self.disk.flush()
+    }
+
+    /// Returns the length of the file at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The path within the volume.  
+    pub(crate) fn file_len(&mut self, path: &str) -> crate::Result<u64> {
+        Ok(self.file(path)?.chain().len)
+    }
+
+    /// Reads from the file at `path`, returning the number of bytes read.
+    ///
+    /// # Arguments
+    /// `path`: The path within the volume.  
+    /// `offset`: The offset within the file to read from.  
+    /// `buf`: The buffer to read into.  
+    pub(crate) fn read_file(
+        &mut self,
+        path: &str,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> crate::Result<usize> {
+        let set = self.file(path)?;
+        let len = set.chain().len;
+        if offset >= len {
+            return Ok(0);
+        }
+        let read = (len - offset).min(buf.len() as u64) as usize;
+        let buf = &mut buf[..read];
+
+        // contents past the valid length read as zeros
+        let valid = set.valid_len().saturating_sub(offset).min(buf.len() as u64) as usize;
+        buf[valid..].fill(0);
+        let clusters = self.clusters(set.chain())?;
+        for (disk_offset, range) in self.spans(&clusters, offset, valid)? {
+            self.disk.seek(SeekFrom::Start(disk_offset))?;
+            self.disk.read_exact(&mut buf[range])?;
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Writes to the file at `path`, extending it if the write ends past its end.
+    ///
+    /// # Arguments
+    /// `path`: The path within the volume.  
+    /// `offset`: The offset within the file to write to.  
+    /// `buf`: The bytes to write.  
+    pub(crate) fn write_file(&mut self, path: &str, offset: u64, buf: &[u8]) -> crate::Result<()> {
+        let mut set = self.file(path)?;
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .ok_or_else(|| invalid_input("Write past the maximum file length"))?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let mut chain = set.chain();
+        if end > chain.len {
+            self.resize_chain(&mut chain, end)?;
+            set.set_chain(chain);
+        }
+        let clusters = self.clusters(chain)?;
+
+        // the contents between the valid length and the write would otherwise read as whatever is on the disk
+        let valid_len = set.valid_len();
+        if offset > valid_len {
+            let zeros = vec![0; (offset - valid_len).min(1 << 16) as usize];
+            let mut position = valid_len;
+            while position < offset {
+                let len = (offset - position).min(zeros.len() as u64) as usize;
+                for (disk_offset, range) in self.spans(&clusters, position, len)? {
+                    self.write(disk_offset, &zeros[range])?;
+                }
+                position += len as u64;
+            }
+        }
+        for (disk_offset, range) in self.spans(&clusters, offset, buf.len())? {
+            self.write(disk_offset, &buf[range])?;
+        }
+
+        set.set_valid_len(valid_len.max(end));
+        set.touch();
+        self.write_set(&mut set)?;
+        self.disk.flush()
+    }
+
+    /// Truncates or extends the file at `path`. Extended contents read as zeros.
+    ///
+    /// # Arguments
+    /// `path`: The path within the volume.  
+    /// `len`: The new length of the file.  
+    pub(crate) fn set_file_len(&mut self, path: &str, len: u64) -> crate::Result<()> {
+        let mut set = self.file(path)?;
+        let mut chain = set.chain();
+        self.resize_chain(&mut chain, len)?;
+        set.set_chain(chain);
+        set.set_valid_len(set.valid_len().min(len));
+        set.touch();
+        self.write_set(&mut set)?;
+        self.disk.flush()
+    }
+
+    /// Removes the file or empty directory at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The path within the volume.  
+    /// `directory`: True if the entry must be a directory, or false if it must be a file.  
+    pub(crate) fn remove(&mut self, path: &str, directory: bool) -> crate::Result<()> {
+        let set = self.find(path)?.ok_or_else(not_found)?;
+        if set.is_directory() != directory {
+            return Err(not_found());
+        }
+        if directory && !self.sets(set.chain())?.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::DirectoryNotEmpty,
+                "Directory not empty",
+            ));
+        }
+
+        self.delete_set(&set)?;
+        self.resize_chain(&mut set.chain(), 0)?;
+        self.disk.flush()
+    }
+
+    /// Moves the file or directory at `from` to `to`, replacing the file at `to`.
+    ///
+    /// # Arguments
+    /// `from`: The path within the volume of the entry.  
+    /// `to`: The new path within the volume of the entry.  
+    pub(crate) fn rename(&mut self, from: &str, to: &str) -> crate::Result<()> {
+        let set = self.find(from)?.ok_or_else(not_found)?;
+
+        // directories can't be moved into themselves
+        let (from_key, to_key) = (self.up_case_str(from), self.up_case_str(to));
+        if set.is_directory()
+            && to_key.starts_with(&from_key)
+            && to_key
+                .get(from_key.len())
+                .is_some_and(|&char| char == u16::from(b'/'))
+        {
+            return Err(invalid_input("A directory can't be moved into itself"));
+        }
+
+        let (parent_path, name) = split_path(to);
+        let name = encode_name(name)?;
+        let mut parent = self.directory(parent_path)?;
+        if let Some(existing) = self.find_in(parent.chain, &name)? {
+            let renamed_in_place = existing.parent == set.parent && existing.index == set.index;
+            if !renamed_in_place {
+                // only files can be replaced
+                if set.is_directory() || existing.is_directory() {
+                    return Err(already_exists());
+                }
+                self.delete_set(&existing)?;
+                self.resize_chain(&mut existing.chain(), 0)?;
+            }
+        }
+
+        // the new entries are written before the old ones are removed, so that the entry isn't lost if the volume
+        // is full
+        let entries = self.named_set(set.entries[0], set.entries[1], &name);
+        self.insert_set(&mut parent, entries)?;
+        self.delete_set(&set)?;
+        self.disk.flush()
+    }
+
+    /// Returns the space statistics of the volume.
+    pub(crate) fn stats(&self) -> FileSystemStats {
+        let cluster_size = 1u64 << self.cluster_shift;
+        let used_clusters = (0..self.cluster_count as usize)
+            .filter(|&index| self.bitmap[index / 8] & (1 << (index % 8)) != 0)
+            .count() as u64;
+        let free_space = (u64::from(self.cluster_count) - used_clusters) * cluster_size;
+
+        FileSystemStats {
+            total_space: u64::from(self.cluster_count) * cluster_size,
+            free_space,
+            available_space: free_space,
+            block_size: cluster_size,
+        }
+    }
+
+    /// Finds the entry set at `path`, or `None` for the root directory.
+    ///
+    /// # Arguments
+    /// `path`: The path within the volume.  
+    fn find(&mut self, path: &str) -> crate::Result<Option<EntrySet>> {
+        let mut set: Option<EntrySet> = None;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let directory = match &set {
+                None => self.root,
+                Some(set) if set.is_directory() => set.chain(),
+                Some(_) => return Err(not_a_directory()),
+            };
+            let name = name.encode_utf16().collect::<Vec<_>>();
+            set = Some(self.find_in(directory, &name)?.ok_or_else(not_found)?);
+        }
+
+        Ok(set)
+    }
+
+    /// Finds the entry set of the entry named `name` in a directory. Names are compared without regard to case.
+    ///
+    /// # Arguments
+    /// `directory`: The clusters of the directory.  
+    /// `name`: The name of the entry, in UTF-16.  
+    fn find_in(&mut self, directory: Chain, name: &[u16]) -> crate::Result<Option<EntrySet>> {
+        let name = self.up_case_name(name);
+        let hash = name_hash(&name);
+        Ok(self
+            .sets(directory)?
+            .into_iter()
+            .find(|set| le16(&set.entries[1], 4) == hash && self.up_case_name(&set.name()) == name))
+    }
+
+    /// Returns the directory at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The path within the volume.  
+    fn directory(&mut self, path: &str) -> crate::Result<Directory> {
+        match self.find(path)? {
+            None => Ok(Directory {
+                chain: self.root,
+                set: None,
+            }),
+            Some(set) if set.is_directory() => Ok(Directory {
+                chain: set.chain(),
+                set: Some(set),
+            }),
+            Some(_) => Err(not_a_directory()),
+        }
+    }
+
+    /// Returns the entry set of the file at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The path within the volume.  
+    fn file(&mut self, path: &str) -> crate::Result<EntrySet> {
+        match self.find(path)? {
+            Some(set) if !set.is_directory() => Ok(set),
+            Some(_) => Err(is_a_directory()),
+            None => Err(not_found()),
+        }
+    }
+
+    /// Returns the parent directory of a new entry at `path`, along with the entry's name in UTF-16.
+    ///
+    /// # Arguments
+    /// `path`: The path within the volume.  
+    fn new_entry(&mut self, path: &str) -> crate::Result<(Directory, Vec<u16>)> {
+        if path.is_empty() {
+            return Err(already_exists());
+        }
+
+        let (parent_path, name) = split_path(path);
+        let name = encode_name(name)?;
+        let parent = self.directory(parent_path)?;
+        if self.find_in(parent.chain, &name)?.is_some() {
+            return Err(already_exists());
+        }
+
+        Ok((parent, name))
+    }
+
+    /// Returns the entry sets of the files and directories in a directory.
+    ///
+    /// # Arguments
+    /// `directory`: The clusters of the directory.  
+    fn sets(&mut self, directory: Chain) -> crate::Result<Vec<EntrySet>> {
+        let entries = self.read_directory(directory)?;
+        let mut sets = Vec::new();
+        let mut index = 0;
+
+        while let Some(entry) = entries.get(index) {
+            match entry[0] {
+                entry_type::END_OF_DIRECTORY => break,
+                entry_type::FILE => {
+                    let set =
+                        entries
+                            .get(index..index + 1 + usize::from(entry[1]))
+                            .map(|entries| EntrySet {
+                                parent: directory,
+                                index,
+                                entries: entries.to_vec(),
+                            });
+                    // invalid sets are skipped along with any of their entries
+                    match set {
+                        Some(set) if set.is_valid() => {
+                            index += set.entries.len();
+                            sets.push(set);
+                        }
+                        _ => index += 1,
+                    }
+                }
+                _ => index += 1,
+            }
+        }
+
+        Ok(sets)
+    }
+
+    /// Returns the entries of a directory.
+    ///
+    /// # Arguments
+    /// `directory`: The clusters of the directory.  
+    fn read_directory(&mut self, directory: Chain) -> crate::Result<Vec<[u8; ENTRY_SIZE]>> {
+        let clusters = self.clusters(directory)?;
+        let len = (clusters.len() as u64) << self.cluster_shift;
+        let contents = self.read_chain(&clusters, len)?;
+
+        Ok(contents
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| entry.try_into().unwrap())
+            .collect())
+    }
+
+    /// Builds the entry set of a new file or directory.
+    ///
+    /// # Arguments
+    /// `name`: The name of the entry, in UTF-16.  
+    /// `attributes`: The attributes of the entry.  
+    /// `chain`: The clusters of the contents, which are all valid.  
+    fn new_set(&self, name: &[u16], attributes: u16, chain: Chain) -> Vec<[u8; ENTRY_SIZE]> {
+        let (timestamp, increment, utc_offset) = encode_timestamp(now());
+        let mut file = [0; ENTRY_SIZE];
+        file[0] = entry_type::FILE;
+        file[4..6].copy_from_slice(&attributes.to_le_bytes());
+        for offset in [8, 12, 16] {
+            file[offset..offset + 4].copy_from_slice(&timestamp.to_le_bytes());
+        }
+        file[20] = increment;
+        file[21] = increment;
+        file[22..25].fill(utc_offset);
+
+        let mut set = EntrySet {
+            parent: Chain::EMPTY,
+            index: 0,
+            entries: vec![file, [0; ENTRY_SIZE]],
+        };
+        set.entries[1][0] = entry_type::STREAM_EXTENSION;
+        set.set_chain(chain);
+        set.set_valid_len(chain.len);
+        self.named_set(set.entries[0], set.entries[1], name)
+    }
+
+    /// Builds an entry set from a file entry and a stream extension entry, giving it a name.
+    ///
+    /// # Arguments
+    /// `file`: The file entry.  
+    /// `stream`: The stream extension entry.  
+    /// `name`: The name, in UTF-16.  
+    fn named_set(
+        &self,
+        mut file: [u8; ENTRY_SIZE],
+        mut stream: [u8; ENTRY_SIZE],
+        name: &[u16],
+    ) -> Vec<[u8; ENTRY_SIZE]> {
+        let name_entries = name.chunks(NAME_ENTRY_CHARS).map(|chars| {
+            let mut entry = [0; ENTRY_SIZE];
+            entry[0] = entry_type::FILE_NAME;
+            for (char, bytes) in chars.iter().zip(entry[2..].chunks_exact_mut(2)) {
+                bytes.copy_from_slice(&char.to_le_bytes());
+            }
+            entry
+        });
+
+        file[1] = (1 + name.len().div_ceil(NAME_ENTRY_CHARS)) as u8;
+        stream[3] = name.len() as u8;
+        stream[4..6].copy_from_slice(&name_hash(&self.up_case_name(name)).to_le_bytes());
+        [file, stream].into_iter().chain(name_entries).collect()
+    }
+
+    /// Writes a new entry set into a directory, growing the directory if there's no room for it.
+    ///
+    /// # Arguments
+    /// `directory`: The directory.  
+    /// `entries`: The entries of the set.  
+    fn insert_set(
+        &mut self,
+        directory: &mut Directory,
+        entries: Vec<[u8; ENTRY_SIZE]>,
+    ) -> crate::Result<()> {
+        // the set takes the first run of unused entries that's long enough for it. unused entries at the end of the
+        // directory are followed only by more unused entries, so they're always part of the last run
+        let existing = self.read_directory(directory.chain)?;
+        let mut run = 0;
+        let mut index = None;
+        for (entry_index, entry) in existing.iter().enumerate() {
+            if entry[0] & entry_type::IN_USE != 0 {
+                run = 0;
+                continue;
+            }
+
+            run += 1;
+            if run == entries.len() {
+                index = Some(entry_index + 1 - run);
+                break;
+            }
+        }
+
+        let index = match index {
+            Some(index) => index,
+            None => {
+                let index = existing.len() - run;
+                let len = ((index + entries.len()) * ENTRY_SIZE) as u64;
+                self.grow_directory(directory, len.next_multiple_of(1 << self.cluster_shift))?;
+                index
+            }
+        };
+
+        self.write_set(&mut EntrySet {
+            parent: directory.chain,
+            index,
+            entries,
+        })
+    }
+
+    /// Grows a directory with clusters of unused entries.
+    ///
+    /// # Arguments
+    /// `directory`: The directory.  
+    /// `len`: The new length of the directory, which is a multiple of the cluster size.  
+    fn grow_directory(&mut self, directory: &mut Directory, len: u64) -> crate::Result<()> {
+        let mut chain = directory.chain;
+        let old_clusters = self.clusters(chain)?.len();
+        chain.len = (old_clusters as u64) << self.cluster_shift;
+        self.resize_chain(&mut chain, len)?;
+
+        let zeros = vec![0; 1 << self.cluster_shift];
+        for cluster in &self.clusters(chain)?[old_clusters..] {
+            self.write(self.cluster_offset(*cluster), &zeros)?;
+        }
+
+        // the length of the root directory is only recorded by the FAT
+        directory.chain = chain;
+        if let Some(set) = &mut directory.set {
+            set.set_chain(chain);
+            set.set_valid_len(len);
+            self.write_set(set)?;
+        }
+        Ok(())
+    }
+
+    /// Writes an entry set to its directory, updating its checksum.
+    ///
+    /// # Arguments
+    /// `set`: The entry set.  
+    fn write_set(&mut self, set: &mut EntrySet) -> crate::Result<()> {
+        let checksum = set_checksum(&set.entries);
+        set.entries[0][2..4].copy_from_slice(&checksum.to_le_bytes());
+        self.write_entries(set)
+    }
+
+    /// Marks the entries of an entry set as unused.
+    ///
+    /// # Arguments
+    /// `set`: The entry set.  
+    fn delete_set(&mut self, set: &EntrySet) -> crate::Result<()> {
+        let mut set = set.clone();
+        for entry in &mut set.entries {
+            entry[0] &= !entry_type::IN_USE;
+        }
+        self.write_entries(&set)
+    }
+
+    /// Writes the entries of an entry set to its directory as they are.
+    ///
+    /// # Arguments
+    /// `set`: The entry set.  
+    fn write_entries(&mut self, set: &EntrySet) -> crate::Result<()> {
+        let clusters = self.clusters(set.parent)?;
+        let entries = set.entries.concat();
+        for (disk_offset, range) in
+            self.spans(&clusters, (set.index * ENTRY_SIZE) as u64, entries.len())?
+        {
+            self.write(disk_offset, &entries[range])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the clusters of a chain.
+    ///
+    /// # Arguments
+    /// `chain`: The chain.  
+    fn clusters(&mut self, chain: Chain) -> crate::Result<Vec<u32>> {
+        if chain.first_cluster == 0 {
+            return Ok(Vec::new());
+        }
+
+        let end = u64::from(self.cluster_count) + u64::from(FIRST_CLUSTER);
+        if chain.contiguous {
+            let count = chain.len.div_ceil(1 << self.cluster_shift);
+            if u64::from(chain.first_cluster) + count > end {
+                return Err(corrupt());
+            }
+            return Ok((chain.first_cluster..chain.first_cluster + count as u32).collect());
+        }
+
+        let mut clusters = Vec::new();
+        let mut cluster = chain.first_cluster;
+        loop {
+            // chains can't be longer than the heap, unless they loop
+            if !(u64::from(FIRST_CLUSTER)..end).contains(&cluster.into())
+                || clusters.len() >= self.cluster_count as usize
+            {
+                return Err(corrupt());
+            }
+
+            clusters.push(cluster);
+            match self.fat_entry(cluster)? {
+                END_OF_CHAIN => return Ok(clusters),
+                next => cluster = next,
+            }
+        }
+    }
+
+    /// Allocates or frees clusters so that a chain holds `len` bytes.
+    ///
+    /// # Arguments
+    /// `chain`: The chain, which is updated.  
+    /// `len`: The new length of the contents.  
+    fn resize_chain(&mut self, chain: &mut Chain, len: u64) -> crate::Result<()> {
+        let mut clusters = self.clusters(*chain)?;
+        let count = len.div_ceil(1 << self.cluster_shift);
+
+        if count > clusters.len() as u64 {
+            // clusters are allocated before any are linked, so that nothing leaks if the volume is full
+            let mut new_clusters = Vec::new();
+            let mut last = clusters.last().copied();
+            for _ in clusters.len() as u64..count {
+                match self.allocate(last) {
+                    Ok(cluster) => {
+                        new_clusters.push(cluster);
+                        last = Some(cluster);
+                    }
+                    Err(err) => {
+                        for cluster in new_clusters {
+                            self.set_allocated(cluster, false)?;
+                        }
+                        return Err(err);
+                    }
+                }
+            }
+
+            // contiguous chains that can't stay contiguous are described by the FAT from now on
+            let still_contiguous = clusters
+                .iter()
+                .chain(&new_clusters)
+                .zip(clusters.iter().chain(&new_clusters).skip(1))
+                .all(|(cluster, next)| cluster + 1 == *next);
+            if chain.contiguous && !still_contiguous {
+                chain.contiguous = false;
+                for pair in clusters.windows(2) {
+                    self.set_fat_entry(pair[0], pair[1])?;
+                }
+            }
+            if !chain.contiguous {
+                for (index, &cluster) in new_clusters.iter().enumerate() {
+                    let next = new_clusters.get(index + 1).copied().unwrap_or(END_OF_CHAIN);
+                    self.set_fat_entry(cluster, next)?;
+                }
+                if let Some(&last) = clusters.last() {
+                    self.set_fat_entry(last, new_clusters[0])?;
+                }
+            }
+
+            if chain.first_cluster == 0 {
+                chain.first_cluster = new_clusters[0];
+            }
+            clusters.extend(new_clusters);
+        } else {
+            for &cluster in &clusters[count as usize..] {
+                self.set_allocated(cluster, false)?;
+                if !chain.contiguous {
+                    self.set_fat_entry(cluster, 0)?;
+                }
+            }
+            clusters.truncate(count as usize);
+
+            match clusters.last() {
+                None => *chain = Chain::EMPTY,
+                Some(&last) if !chain.contiguous => self.set_fat_entry(last, END_OF_CHAIN)?,
+                Some(_) => {}
+            }
+        }
+
+        chain.len = len;
+        Ok(())
+    }
+
+    /// Allocates a free cluster, preferring the one after `after` so that chains stay contiguous.
+    ///
+    /// # Arguments
+    /// `after`: The cluster the allocated cluster follows.  
+    fn allocate(&mut self, after: Option<u32>) -> crate::Result<u32> {
+        let start = after.map_or(0, |cluster| (cluster + 1 - FIRST_CLUSTER) as usize);
+        let cluster_count = self.cluster_count as usize;
+        let index = (start..cluster_count)
+            .chain(0..start.min(cluster_count))
+            .find(|&index| self.bitmap[index / 8] & (1 << (index % 8)) == 0)
+            .ok_or_else(|| io::Error::new(ErrorKind::StorageFull, "The volume is full"))?;
+
+        let cluster = index as u32 + FIRST_CLUSTER;
+        self.set_allocated(cluster, true)?;
+        Ok(cluster)
+    }
+
+    /// Marks a cluster as allocated or free in the allocation bitmap.
+    ///
+    /// # Arguments
+    /// `cluster`: The cluster.  
+    /// `allocated`: True if the cluster is allocated.  
+    fn set_allocated(&mut self, cluster: u32, allocated: bool) -> crate::Result<()> {
+        let index = (cluster - FIRST_CLUSTER) as usize;
+        let byte = &mut self.bitmap[index / 8];
+        if allocated {
+            *byte |= 1 << (index % 8);
+        } else {
+            *byte &= !(1 << (index % 8));
+        }
+
+        let byte = *byte;
+        for (disk_offset, _) in self.spans(&self.bitmap_clusters, (index / 8) as u64, 1)? {
+            self.write(disk_offset, &[byte])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the FAT entry of a cluster, which is the next cluster of its chain.
+    ///
+    /// # Arguments
+    /// `cluster`: The cluster.  
+    fn fat_entry(&mut self, cluster: u32) -> crate::Result<u32> {
+        let mut entry = [0; 4];
+        self.disk
+            .seek(SeekFrom::Start(self.fat_offset + u64::from(cluster) * 4))?;
+        self.disk.read_exact(&mut entry)?;
+        Ok(u32::from_le_bytes(entry))
+    }
+
+    /// Sets the FAT entry of a cluster.
+    ///
+    /// # Arguments
+    /// `cluster`: The cluster.  
+    /// `next`: The next cluster of its chain.  
+    fn set_fat_entry(&mut self, cluster: u32, next: u32) -> crate::Result<()> {
+        self.write(
+            self.fat_offset + u64::from(cluster) * 4,
+            &next.to_le_bytes(),
+        )
+    }
+
+    /// Returns the offset of a cluster on the disk.
+    ///
+    /// # Arguments
+    /// `cluster`: The cluster.  
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.heap_offset + (u64::from(cluster - FIRST_CLUSTER) << self.cluster_shift)
+    }
+
+    /// Maps `len` bytes at `offset` within the contents of clusters to where they're stored on the disk. Returns the
+    /// offset on the disk of each part, along with where it is within the bytes.
+    ///
+    /// # Arguments
+    /// `clusters`: The clusters of the contents.  
+    /// `offset`: The offset within the contents.  
+    /// `len`: The number of bytes.  
+    fn spans(
+        &self,
+        clusters: &[u32],
+        offset: u64,
+        len: usize,
+    ) -> crate::Result<Vec<(u64, Range<usize>)>> {
+        let cluster_size = 1u64 << self.cluster_shift;
+        let mut spans = Vec::new();
+        let mut done = 0;
+
+        while done < len {
+            let position = offset + done as u64;
+            let cluster = *usize::try_from(position >> self.cluster_shift)
+                .ok()
+                .and_then(|index| clusters.get(index))
+                .ok_or_else(corrupt)?;
+            let within = position & (cluster_size - 1);
+            let part_len = ((cluster_size - within) as usize).min(len - done);
+            spans.push((self.cluster_offset(cluster) + within, done..done + part_len));
+            done += part_len;
+        }
+
+        Ok(spans)
+    }
+
+    /// Reads the first `len` bytes held by clusters.
+    ///
+    /// # Arguments
+    /// `clusters`: The clusters.  
+    /// `len`: The number of bytes.  
+    fn read_chain(&mut self, clusters: &[u32], len: u64) -> crate::Result<Vec<u8>> {
+        let mut contents = vec![0; usize::try_from(len).map_err(|_| corrupt())?];
+        for (disk_offset, range) in self.spans(clusters, 0, contents.len())? {
+            self.disk.seek(SeekFrom::Start(disk_offset))?;
+            self.disk.read_exact(&mut contents[range])?;
+        }
+        Ok(contents)
+    }
+
+    /// Writes to the disk, marking the volume as dirty until it's unmounted.
+    ///
+    /// # Arguments
+    /// `offset`: The offset on the disk.  
+    /// `buf`: The bytes to write.  
+    fn write(&mut self, offset: u64, buf: &[u8]) -> crate::Result<()> {
+        if self.volume_flags & volume_flags::VOLUME_DIRTY == 0 {
+            self.volume_flags |= volume_flags::VOLUME_DIRTY;
+            write_at(
+                &mut self.disk,
+                VOLUME_FLAGS_OFFSET,
+                &self.volume_flags.to_le_bytes(),
+            )?;
+        }
+        write_at(&mut self.disk, offset, buf)
+    }
+
+    /// Returns the upper case of a name, in UTF-16.
+    ///
+    /// # Arguments
+    /// `name`: The name, in UTF-16.  
+    fn up_case_name(&self, name: &[u16]) -> Vec<u16> {
+        name.iter()
+            .map(|&char| self.up_case.get(usize::from(char)).copied().unwrap_or(char))
+            .collect()
+    }
+
+    /// Returns the upper case of a path, in UTF-16.
+    ///
+    /// # Arguments
+    /// `path`: The path.  
+    fn up_case_str(&self, path: &str) -> Vec<u16> {
+        self.up_case_name(&path.encode_utf16().collect::<Vec<_>>())
+    }
+}
+
+impl<T: Read + Write + Seek> Drop for ExFat<T> {
+    fn drop(&mut self) {
+        if self.volume_flags & volume_flags::VOLUME_DIRTY == 0 {
+            return;
+        }
+
+        // the volume is clean once it's unmounted, and neither field is covered by the boot checksum
+        let stats = self.stats();
+        let percent_in_use = (stats.free_space * 100)
+            .checked_div(stats.total_space)
+            .map_or(0, |percent_free| (100 - percent_free) as u8);
+        self.volume_flags &= !volume_flags::VOLUME_DIRTY;
+        let _ = write_at(&mut self.disk, PERCENT_IN_USE_OFFSET, &[percent_in_use])
+            .and_then(|_| {
+                write_at(
+                    &mut self.disk,
+                    VOLUME_FLAGS_OFFSET,
+                    &self.volume_flags.to_le_bytes(),
+                )
+            })
+            .and_then(|_| self.disk.flush());
+    }
+}
+
+/// Returns the parent path and the name of the entry at `path`.
+///
+/// # Arguments
+/// `path`: The path within the volume.  
+fn split_path(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+/// Encodes a name in UTF-16, checking that exFAT can store it.
+///
+/// # Arguments
+/// `name`: The name.  
+fn encode_name(name: &str) -> crate::Result<Vec<u16>> {
+    let encoded = name.encode_utf16().collect::<Vec<_>>();
+    if encoded.is_empty()
+        || encoded.len() > MAX_NAME_LEN
+        || name
+            .chars()
+            .any(|char| char < ' ' || "\"*/:<>?\\|".contains(char))
+    {
+        return Err(invalid_path());
+    }
+
+    Ok(encoded)
+}
+
+/// Returns the checksum of the boot sectors of a boot region, which skips the fields that change while the volume
+/// is mounted.
+///
+/// # Arguments
+/// `sectors`: The boot sectors, without the checksum sector.  
+fn boot_checksum(sectors: &[u8]) -> u32 {
+    sectors
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !matches!(index, 106 | 107 | 112))
+        .fold(0u32, |checksum, (_, &byte)| {
+            checksum.rotate_right(1).wrapping_add(byte.into())
+        })
+}
+
+/// Returns the checksum of the up-case table.
+///
+/// # Arguments
+/// `table`: The compressed table.  
+fn table_checksum(table: &[u8]) -> u32 {
+    table.iter().fold(0u32, |checksum, &byte| {
+        checksum.rotate_right(1).wrapping_add(byte.into())
+    })
+}
+
+/// Returns the checksum of an entry set, which skips the checksum field.
+///
+/// # Arguments
+/// `entries`: The entries of the set.  
+fn set_checksum(entries: &[[u8; ENTRY_SIZE]]) -> u16 {
+    entries
+        .iter()
+        .flatten()
+        .enumerate()
+        .filter(|(index, _)| !matches!(index, 2 | 3))
+        .fold(0u16, |checksum, (_, &byte)| {
+            checksum.rotate_right(1).wrapping_add(byte.into())
+        })
+}
+
+/// Returns the hash of a name, which lets lookups skip most entries without comparing names.
+///
+/// # Arguments
+/// `name`: The upper case of the name, in UTF-16.  
+fn name_hash(name: &[u16]) -> u16 {
+    name.iter()
+        .flat_map(|char| char.to_le_bytes())
+        .fold(0u16, |hash, byte| {
+            hash.rotate_right(1).wrapping_add(byte.into())
+        })
+}
+
+/// Returns the up-case table of new volumes, which maps each character of the Basic Multilingual Plane to its
+/// single-character upper case. Runs of characters that are their own upper case are compressed.
+fn compressed_up_case() -> Vec<u16> {
+    let mut table = Vec::new();
+    let mut identity_run = 0u16;
+
+    for char in 0..=u16::MAX {
+        let upper = char::from_u32(char.into())
+            .and_then(|char| {
+                let mut upper = char.to_uppercase();
+                match (upper.next(), upper.next()) {
+                    (Some(upper), None) => u16::try_from(u32::from(upper)).ok(),
+                    _ => None,
+                }
+            })
+            .unwrap_or(char);
+        if upper == char {
+            identity_run += 1;
+            continue;
+        }
+
+        if identity_run > 0 {
+            table.extend([0xffff, identity_run]);
+            identity_run = 0;
+        }
+        table.push(upper);
+    }
+
+    if identity_run > 0 {
+        table.extend([0xffff, identity_run]);
+    }
+    table
+}
+
+/// Expands a compressed up-case table.
+///
+/// # Arguments
+/// `table`: The compressed table.  
+fn decompress_up_case(table: &[u8]) -> Vec<u16> {
+    let mut up_case = Vec::new();
+    let mut words = table.chunks_exact(2).map(|bytes| le16(bytes, 0));
+
+    while let Some(word) = words.next() {
+        // 0xffff is followed by the length of a run of characters that are their own upper case
+        match (word, words.clone().next()) {
+            (0xffff, Some(run)) => {
+                words.next();
+                let start = up_case.len();
+                up_case.extend((start..start + usize::from(run)).map(|char| char as u16));
+            }
+            _ => up_case.push(word),
+        }
+        if up_case.len() > usize::from(u16::MAX) {
+            up_case.truncate(usize::from(u16::MAX) + 1);
+            break;
+        }
+    }
+
+    up_case
+}
+
+/// Encodes a time as an exFAT timestamp in UTC, returning the timestamp, its increment of 10 milliseconds and its
+/// UTC offset.
+///
+/// # Arguments
+/// `time`: The time.  
+fn encode_timestamp(time: SystemTime) -> (u32, u8, u8) {
+    let date_time = DateTime::from_system_time(time);
+    let centiseconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.subsec_millis() / 10)
+        + date_time.second % 2 * 100;
+
+    // timestamps can't be represented outside of these years
+    let year = date_time.year.clamp(1980, 2107) as u32 - 1980;
+    let timestamp = (year << 25)
+        | (date_time.month << 21)
+        | (date_time.day << 16)
+        | (date_time.hour << 11)
+        | (date_time.minute << 5)
+        | (date_time.second / 2);
+    (timestamp, centiseconds as u8, UTC)
+}
+
+/// Decodes an exFAT timestamp, returning `None` if it's invalid.
+///
+/// # Arguments
+/// `timestamp`: The timestamp, with a resolution of 2 seconds.  
+/// `increment`: The number of 10 milliseconds past the timestamp.  
+/// `utc_offset`: The offset of the timestamp from UTC, in 15 minute intervals, if its high bit is set.  
+fn decode_timestamp(timestamp: u32, increment: u8, utc_offset: u8) -> Option<SystemTime> {
+    let time = DateTime {
+        year: 1980 + i64::from(timestamp >> 25),
+        month: (timestamp >> 21) & 0xf,
+        day: (timestamp >> 16) & 0x1f,
+        hour: (timestamp >> 11) & 0x1f,
+        minute: (timestamp >> 5) & 0x3f,
+        second: (timestamp & 0x1f) * 2,
+    }
+    .to_system_time()?
+        + Duration::from_millis(u64::from(increment.min(199)) * 10);
+
+    // timestamps without an offset are in an unknown time zone, and are treated as UTC
+    if utc_offset & UTC == 0 {
+        return Some(time);
+    }
+    let offset = i64::from((utc_offset << 1) as i8 >> 1) * 15 * 60;
+    if offset >= 0 {
+        time.checked_sub(Duration::from_secs(offset as u64))
+    } else {
+        time.checked_add(Duration::from_secs(offset.unsigned_abs()))
+    }
+}
+
+/// Writes bytes to the disk.
+///
+/// # Arguments
+/// `disk`: The disk.  
+/// `offset`: The offset on the disk.  
+/// `buf`: The bytes.  
+fn write_at<T: Write + Seek>(disk: &mut T, offset: u64, buf: &[u8]) -> crate::Result<()> {
+    disk.seek(SeekFrom::Start(offset))?;
+    disk.write_all(buf)
+}
+
+/// Returns an error indicating that the volume is corrupt.
+fn corrupt() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "Corrupt exFAT volume")
+}
+
+/// Reads a little-endian `u16` at `offset`.
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+/// Reads a little-endian `u32` at `offset`.
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reads a little-endian `u64` at `offset`.
+fn le64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::fat_fs::exfat::{
+        compressed_up_case, decode_timestamp, decompress_up_case, encode_timestamp, le32,
+        stream_flags, volume_flags, Chain, ExFat, END_OF_CHAIN, PERCENT_IN_USE_OFFSET, UTC,
+        VOLUME_FLAGS_OFFSET,
+    };
+    use std::io::{Cursor, ErrorKind};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn image() -> Cursor<Vec<u8>> {
+        let mut image = Cursor::new(vec![0; 1024 * 1024]);
+        ExFat::format(&mut image, "").unwrap();
+        image
+    }
+
+    #[test]
+    fn up_case() {
+        let up_case = decompress_up_case(
+            &compressed_up_case()
+                .into_iter()
+                .flat_map(u16::to_le_bytes)
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(up_case.len(), 0x10000);
+        for (char, upper) in [('a', 'A'), ('A', 'A'), ('é', 'É'), ('ω', 'Ω'), ('1', '1')] {
+            assert_eq!(up_case[char as usize], upper as u16);
+        }
+        // characters whose upper case is more than one character are their own upper case
+        assert_eq!(up_case['ß' as usize], 'ß' as u16);
+
+        // runs of identity-mapped characters are expanded
+        assert_eq!(
+            decompress_up_case(&[0xff, 0xff, 0x61, 0x00, 0x41, 0x00, 0x42, 0x00]),
+            (0..0x61).chain([0x41, 0x42]).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn timestamps() {
+        let time = UNIX_EPOCH + Duration::from_millis(1709208001230);
+        let (timestamp, increment, utc_offset) = encode_timestamp(time);
+        // the seconds are stored in units of two, with the odd second in the increment
+        assert_eq!(timestamp & 0x1f, 0);
+        assert_eq!(increment, 123);
+        assert_eq!(
+            decode_timestamp(timestamp, increment, utc_offset),
+            Some(time)
+        );
+
+        // local times are converted to UTC with their offset, in 15 minute intervals
+        let utc = decode_timestamp(timestamp, 0, 0).unwrap();
+        assert_eq!(
+            decode_timestamp(timestamp, 0, UTC | 4),
+            Some(utc - Duration::from_secs(3600))
+        );
+        assert_eq!(
+            decode_timestamp(timestamp, 0, UTC | 0x7c),
+            Some(utc + Duration::from_secs(3600))
+        );
+        assert_eq!(decode_timestamp(0, 0, UTC), None);
+
+        // times outside of the range of timestamps are clamped
+        let (timestamp, ..) = encode_timestamp(SystemTime::UNIX_EPOCH);
+        assert_eq!(timestamp >> 25, 0);
+    }
+
+    #[test]
+    fn boot_region() {
+        let mut image = image();
+        // the backup boot region matches the main one
+        assert_eq!(
+            image.get_ref()[12 * 512..24 * 512],
+            image.get_ref()[..12 * 512]
+        );
+
+        {
+            let mut volume = ExFat::new(&mut image).unwrap();
+            volume.create_file("file").unwrap();
+            assert_ne!(volume.volume_flags & volume_flags::VOLUME_DIRTY, 0);
+        }
+        // the volume is clean after it's unmounted
+        assert_eq!(
+            u16::from(image.get_ref()[VOLUME_FLAGS_OFFSET as usize]) & volume_flags::VOLUME_DIRTY,
+            0
+        );
+        assert_ne!(image.get_ref()[PERCENT_IN_USE_OFFSET as usize], 0);
+
+        // the flags and the percentage in use aren't part of the checksum, unlike the rest of the boot sector
+        image.get_mut()[VOLUME_FLAGS_OFFSET as usize] = volume_flags::VOLUME_DIRTY as u8;
+        assert!(ExFat::new(&mut image).is_ok());
+        image.get_mut()[100] ^= 1;
+        assert_eq!(
+            ExFat::new(&mut image).err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn corruption() {
+        let mut image = image();
+        let mut volume = ExFat::new(&mut image).unwrap();
+        volume.create_dir("dir").unwrap();
+        let dir = volume.find("dir").unwrap().unwrap().chain().first_cluster;
+
+        // a chain that loops is detected, rather than followed forever
+        volume.set_fat_entry(dir, dir).unwrap();
+        assert_eq!(
+            volume.read_dir("dir").err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+        volume.set_fat_entry(dir, END_OF_CHAIN).unwrap();
+        assert!(volume.read_dir("dir").unwrap().is_empty());
+
+        // entry sets whose checksum doesn't match are skipped
+        let mut set = volume.find("dir").unwrap().unwrap();
+        set.entries[1][3] += 1;
+        volume.write_entries(&set).unwrap();
+        assert!(volume.read_dir("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn full() {
+        let mut image = image();
+        let mut volume = ExFat::new(&mut image).unwrap();
+        let stats = volume.stats();
+        volume.create_file("file").unwrap();
+
+        // clusters aren't leaked by a write that doesn't fit
+        assert_eq!(
+            volume
+                .set_file_len("file", stats.total_space)
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::StorageFull
+        );
+        assert_eq!(volume.stats(), stats);
+        volume.set_file_len("file", stats.free_space).unwrap();
+        assert_eq!(volume.stats().free_space, 0);
+        assert_eq!(
+            volume.create_dir("dir").err().unwrap().kind(),
+            ErrorKind::StorageFull
+        );
+
+        // extended contents read as zeros
+        let mut buf = [1; 4];
+        assert_eq!(volume.read_file("file", 4096, &mut buf).unwrap(), 4);
+        assert_eq!(buf, [0; 4]);
+        volume.set_file_len("file", 0).unwrap();
+        assert_eq!(volume.stats(), stats);
+    }
+
+    #[test]
+    fn directory_growth() {
+        let mut image = image();
+        let mut volume = ExFat::new(&mut image).unwrap();
+        volume.create_dir("dir").unwrap();
+
+        // each entry set takes three entries, so a cluster holds 42 of them
+        for index in 0..100 {
+            volume.create_file(&format!("dir/{index}")).unwrap();
+            volume.create_file(&format!("{index}")).unwrap();
+        }
+        let dir = volume.find("dir").unwrap().unwrap();
+        assert_eq!(dir.chain().len, 3 * 4096);
+        assert_eq!(dir.valid_len(), 3 * 4096);
+        assert_eq!(volume.read_dir("dir").unwrap().len(), 100);
+        assert_eq!(volume.read_dir("").unwrap().len(), 101);
+
+        // deleted entries are reused
+        let stats = volume.stats();
+        volume.remove("dir/0", false).unwrap();
+        volume.create_file("dir/new").unwrap();
+        assert_eq!(volume.find("dir/new").unwrap().unwrap().index, 0);
+        assert_eq!(volume.stats(), stats);
+    }
+
+    #[test]
+    fn contiguous() {
+        let mut image = image();
+        let mut volume = ExFat::new(&mut image).unwrap();
+        volume.create_file("contiguous").unwrap();
+        volume
+            .write_file("contiguous", 0, &vec![1; 2 * 4096])
+            .unwrap();
+        volume.create_file("after").unwrap();
+        volume.write_file("after", 0, &[2]).unwrap();
+
+        // other implementations leave contiguous files out of the FAT
+        let mut set = volume.find("contiguous").unwrap().unwrap();
+        let first_cluster = set.chain().first_cluster;
+        set.set_chain(Chain {
+            contiguous: true,
+            ..set.chain()
+        });
+        volume.write_set(&mut set).unwrap();
+        for cluster in first_cluster..first_cluster + 2 {
+            volume.set_fat_entry(cluster, 0).unwrap();
+        }
+        assert_eq!(
+            set.entries[1][1] & stream_flags::NO_FAT_CHAIN,
+            stream_flags::NO_FAT_CHAIN
+        );
+
+        // growing past the next file's cluster moves the chain into the FAT
+        volume.write_file("contiguous", 2 * 4096, &[3]).unwrap();
+        let chain = volume.find("contiguous").unwrap().unwrap().chain();
+        assert!(!chain.contiguous);
+        let clusters = volume.clusters(chain).unwrap();
+        assert_eq!(clusters.len(), 3);
+        assert_eq!(clusters[..2], [first_cluster, first_cluster + 1]);
+        assert_ne!(clusters[2], first_cluster + 2);
+
+        let mut contents = vec![0; 3 * 4096];
+        assert_eq!(
+            volume.read_file("contiguous", 0, &mut contents).unwrap(),
+            2 * 4096 + 1
+        );
+        assert!(contents[..2 * 4096].iter().all(|&byte| byte == 1));
+        assert_eq!(contents[2 * 4096], 3);
+        let mut after = [0];
+        volume.read_file("after", 0, &mut after).unwrap();
+        assert_eq!(after, [2]);
+        assert_eq!(
+            le32(&volume.find("contiguous").unwrap().unwrap().entries[1], 20),
+            first_cluster
+        );
+    }
+}
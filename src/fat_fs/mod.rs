@@ -0,0 +1,959 @@
+mod exfat;
+
+use crate::fat_fs::exfat::ExFat;
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::time::DateTime;
+use crate::tree::normalize_and_relativize;
+use crate::util::{
+    already_exists, invalid_input, invalid_path, is_a_directory, not_found, not_writable, now,
+};
+use crate::FileSystem;
+use parking_lot::Mutex;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+/// A read-write filesystem on a FAT12, FAT16, FAT32 or exFAT disk image. The image is a bare volume, without a
+/// partition table.
+///
+/// FAT12, FAT16 and FAT32 volumes are backed by the `fatfs` crate. exFAT volumes are read and written directly,
+/// through their allocation bitmap, up-case table and directory entry sets, and are unmounted with their volume dirty
+/// flag cleared. TexFAT volumes are used through their active FAT.
+///
+/// Open files refer to their entries by path, so a file that's renamed or removed while it's open can no longer be
+/// read or written through its handle. Changes reach the image as they're made, and the volume is unmounted cleanly
+/// once the filesystem and all of its files are dropped.
+pub struct FatFS<T: Read + Write + Seek> {
+    volume: Arc<Mutex<Volume<T>>>,
+}
+
+/// The type of a FAT volume.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+    ExFat,
+}
+
+/// A mounted volume.
+enum Volume<T: Read + Write + Seek> {
+    Fat(fatfs::FileSystem<T>),
+    ExFat(ExFat<T>),
+}
+
+impl<T: Read + Write + Seek> FatFS<T> {
+    /// Mounts the FAT or exFAT volume in `disk`.
+    ///
+    /// # Arguments
+    /// `disk`: The disk image.  
+    pub fn new(mut disk: T) -> crate::Result<Self> {
+        let mut boot_jump_and_name = [0; 11];
+        let is_exfat = disk.read_exact(&mut boot_jump_and_name).is_ok()
+            && &boot_jump_and_name[3..] == exfat::SIGNATURE;
+        disk.seek(SeekFrom::Start(0))?;
+
+        let volume = if is_exfat {
+            Volume::ExFat(ExFat::new(disk)?)
+        } else {
+            let options = fatfs::FsOptions::new().time_provider(&TIME_PROVIDER);
+            Volume::Fat(fatfs::FileSystem::new(disk, options)?)
+        };
+        Ok(Self {
+            volume: Arc::new(Mutex::new(volume)),
+        })
+    }
+
+    /// Formats `disk` as an empty FAT volume spanning the whole image and mounts it. The FAT type and cluster size are
+    /// chosen from the size of the image.
+    ///
+    /// # Arguments
+    /// `disk`: The disk image.  
+    pub fn format(disk: T) -> crate::Result<Self> {
+        Self::format_with_options(disk, fatfs::FormatVolumeOptions::new())
+    }
+
+    /// Formats `disk` as an empty FAT volume and mounts it.
+    ///
+    /// # Arguments
+    /// `disk`: The disk image.  
+    /// `options`: The layout of the volume, such as its FAT type and label.  
+    pub fn format_with_options(
+        mut disk: T,
+        options: fatfs::FormatVolumeOptions,
+    ) -> crate::Result<Self> {
+        fatfs::format_volume(&mut disk, options)?;
+        disk.seek(SeekFrom::Start(0))?;
+        Self::new(disk)
+    }
+
+    /// Formats `disk` as an empty exFAT volume spanning the whole image and mounts it. The cluster size is chosen from
+    /// the size of the image.
+    ///
+    /// # Arguments
+    /// `disk`: The disk image.  
+    pub fn format_exfat(disk: T) -> crate::Result<Self> {
+        Self::format_exfat_with_label(disk, "")
+    }
+
+    /// Formats `disk` as an empty exFAT volume with a label and mounts it.
+    ///
+    /// # Arguments
+    /// `disk`: The disk image.  
+    /// `label`: The volume label, of up to 11 characters.  
+    pub fn format_exfat_with_label(mut disk: T, label: &str) -> crate::Result<Self> {
+        ExFat::format(&mut disk, label)?;
+        disk.seek(SeekFrom::Start(0))?;
+        Self::new(disk)
+    }
+
+    /// Returns the FAT type of the volume.
+    pub fn fat_type(&self) -> FatType {
+        match &*self.volume.lock() {
+            Volume::Fat(volume) => match volume.fat_type() {
+                fatfs::FatType::Fat12 => FatType::Fat12,
+                fatfs::FatType::Fat16 => FatType::Fat16,
+                fatfs::FatType::Fat32 => FatType::Fat32,
+            },
+            Volume::ExFat(_) => FatType::ExFat,
+        }
+    }
+
+    /// Returns the volume label, without its padding.
+    pub fn volume_label(&self) -> String {
+        match &*self.volume.lock() {
+            Volume::Fat(volume) => volume.volume_label(),
+            Volume::ExFat(volume) => volume.label(),
+        }
+    }
+}
+
+impl<T: Read + Write + Seek + 'static> FileSystem for FatFS<T> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        let path = volume_path(path)?;
+        match &mut *self.volume.lock() {
+            Volume::Fat(volume) => {
+                if path.is_empty() || find_entry(volume, &path).is_ok() {
+                    return Err(already_exists());
+                }
+
+                volume.root_dir().create_dir(&path)?;
+                Ok(())
+            }
+            Volume::ExFat(volume) => volume.create_dir(&path),
+        }
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        let path = volume_path(path)?;
+        match &mut *self.volume.lock() {
+            Volume::Fat(_) if path.is_empty() => Ok(Metadata::directory()),
+            Volume::Fat(volume) => Ok(entry_metadata(&find_entry(volume, &path)?)),
+            Volume::ExFat(volume) => volume.metadata(&path),
+        }
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        let path = volume_path(path)?;
+        if path.is_empty() {
+            return Err(not_found());
+        }
+
+        options.validate()?;
+        let position = match &mut *self.volume.lock() {
+            Volume::Fat(volume) => {
+                let root = volume.root_dir();
+                let mut file = match root.open_file(&path) {
+                    Ok(_) if options.create_new => return Err(already_exists()),
+                    Ok(file) => file,
+                    Err(err)
+                        if err.kind() == ErrorKind::NotFound
+                            && (options.create || options.create_new) =>
+                    {
+                        root.create_file(&path)?
+                    }
+                    Err(err) => return Err(err),
+                };
+                if options.truncate {
+                    file.truncate()?;
+                }
+                let position = if options.append {
+                    file.seek(SeekFrom::End(0))?
+                } else {
+                    0
+                };
+                file.flush()?;
+                position
+            }
+            Volume::ExFat(volume) => {
+                match volume.metadata(&path) {
+                    Ok(_) if options.create_new => return Err(already_exists()),
+                    Ok(metadata) if metadata.is_directory() => return Err(is_a_directory()),
+                    Ok(_) => {}
+                    Err(err)
+                        if err.kind() == ErrorKind::NotFound
+                            && (options.create || options.create_new) =>
+                    {
+                        volume.create_file(&path)?
+                    }
+                    Err(err) => return Err(err),
+                }
+                if options.truncate {
+                    volume.set_file_len(&path, 0)?;
+                }
+                if options.append {
+                    volume.file_len(&path)?
+                } else {
+                    0
+                }
+            }
+        };
+
+        Ok(Box::new(FatFile {
+            volume: self.volume.clone(),
+            path,
+            position,
+            append: options.append,
+            write: options.writable(),
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let directory = normalize_and_relativize(path);
+        let path = volume_path(path)?;
+
+        let entries = match &mut *self.volume.lock() {
+            Volume::Fat(volume) => {
+                let dir = if path.is_empty() {
+                    volume.root_dir()
+                } else {
+                    volume.root_dir().open_dir(&path)?
+                };
+                // the iterator borrows the volume, so the entries are collected while it's locked
+                dir.iter()
+                    .filter(|entry| {
+                        entry.as_ref().map_or(true, |entry| {
+                            !matches!(entry.file_name().as_str(), "." | "..")
+                        })
+                    })
+                    .map(|entry| {
+                        entry.map(|entry| DirEntry {
+                            path: directory.join(entry.file_name()),
+                            metadata: entry_metadata(&entry),
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            }
+            Volume::ExFat(volume) => volume
+                .read_dir(&path)?
+                .into_iter()
+                .map(|(name, metadata)| {
+                    Ok(DirEntry {
+                        path: directory.join(name),
+                        metadata,
+                    })
+                })
+                .collect(),
+        };
+
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        let path = volume_path(path)?;
+        if path.is_empty() {
+            return Err(not_found());
+        }
+
+        match &mut *self.volume.lock() {
+            Volume::Fat(volume) => {
+                if !find_entry(volume, &path)?.is_dir() {
+                    return Err(not_found());
+                }
+
+                volume.root_dir().remove(&path)
+            }
+            Volume::ExFat(volume) => volume.remove(&path, true),
+        }
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        let path = volume_path(path)?;
+        if path.is_empty() {
+            return Err(not_found());
+        }
+
+        match &mut *self.volume.lock() {
+            Volume::Fat(volume) => {
+                if !find_entry(volume, &path)?.is_file() {
+                    return Err(not_found());
+                }
+
+                volume.root_dir().remove(&path)
+            }
+            Volume::ExFat(volume) => volume.remove(&path, false),
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        let (from, to) = (volume_path(from)?, volume_path(to)?);
+        if from.is_empty() || to.is_empty() {
+            return Err(invalid_path());
+        }
+
+        match &mut *self.volume.lock() {
+            Volume::Fat(volume) => {
+                let root = volume.root_dir();
+                // FAT refuses to rename over an entry, so a replaced file is removed first
+                if find_entry(volume, &from)?.is_file()
+                    && find_entry(volume, &to).is_ok_and(|entry| entry.is_file())
+                {
+                    root.remove(&to)?;
+                }
+
+                root.rename(&from, &root, &to)
+            }
+            Volume::ExFat(volume) => volume.rename(&from, &to),
+        }
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        match &*self.volume.lock() {
+            Volume::Fat(volume) => {
+                let stats = volume.stats()?;
+                let cluster_size = u64::from(stats.cluster_size());
+                let free_space = u64::from(stats.free_clusters()) * cluster_size;
+
+                Ok(FileSystemStats {
+                    total_space: u64::from(stats.total_clusters()) * cluster_size,
+                    free_space,
+                    available_space: free_space,
+                    block_size: cluster_size,
+                })
+            }
+            Volume::ExFat(volume) => Ok(volume.stats()),
+        }
+    }
+}
+
+/// Returns the path within the volume of `path`, separated by forward slashes. The root is empty.
+///
+/// # Arguments
+/// `path`: The virtual path.  
+fn volume_path(path: &str) -> crate::Result<String> {
+    let path = normalize_and_relativize(path);
+    Ok(path.to_str().ok_or_else(invalid_path)?.replace('\\', "/"))
+}
+
+/// Finds the entry at `path`, which must not be the root.
+///
+/// # Arguments
+/// `volume`: The volume.  
+/// `path`: The path within the volume.  
+fn find_entry<'a, T: Read + Write + Seek>(
+    volume: &'a fatfs::FileSystem<T>,
+    path: &str,
+) -> crate::Result<fatfs::DirEntry<'a, T>> {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((parent, name)) => (volume.root_dir().open_dir(parent)?, name),
+        None => (volume.root_dir(), path),
+    };
+
+    // names are compared the way FAT does, ignoring case and matching either the long or short name
+    for entry in dir.iter() {
+        let entry = entry?;
+        if entry.file_name().to_uppercase() == name.to_uppercase()
+            || entry.short_file_name().eq_ignore_ascii_case(name)
+        {
+            return Ok(entry);
+        }
+    }
+
+    Err(not_found())
+}
+
+/// Returns the metadata of a directory entry.
+///
+/// # Arguments
+/// `entry`: The directory entry.  
+fn entry_metadata<T: Read + Write + Seek>(entry: &fatfs::DirEntry<'_, T>) -> Metadata {
+    let metadata = if entry.is_dir() {
+        Metadata::directory()
+    } else {
+        Metadata::file(entry.len())
+    };
+
+    let system_time = |fatfs::DateTime { date, time }| {
+        DateTime {
+            year: i64::from(date.year),
+            month: u32::from(date.month),
+            day: u32::from(date.day),
+            hour: u32::from(time.hour),
+            minute: u32::from(time.min),
+            second: u32::from(time.sec),
+        }
+        .to_system_time()
+    };
+    Metadata {
+        modified: system_time(entry.modified()),
+        created: system_time(entry.created()),
+        // only the date of the last access is recorded
+        accessed: system_time(fatfs::DateTime {
+            date: entry.accessed(),
+            time: fatfs::Time {
+                hour: 0,
+                min: 0,
+                sec: 0,
+                millis: 0,
+            },
+        }),
+        ..metadata
+    }
+}
+
+/// Stamps entries with the current time in UTC, rather than the DOS epoch.
+#[derive(Debug)]
+struct SystemTimeProvider;
+
+static TIME_PROVIDER: SystemTimeProvider = SystemTimeProvider;
+
+impl fatfs::TimeProvider for SystemTimeProvider {
+    fn get_current_date(&self) -> fatfs::Date {
+        self.get_current_date_time().date
+    }
+
+    fn get_current_date_time(&self) -> fatfs::DateTime {
+        let now = DateTime::from_system_time(now());
+        fatfs::DateTime {
+            // FAT dates can't be represented outside of these years
+            date: fatfs::Date {
+                year: now.year.clamp(1980, 2107) as u16,
+                month: now.month as u16,
+                day: now.day as u16,
+            },
+            time: fatfs::Time {
+                hour: now.hour as u16,
+                min: now.minute as u16,
+                sec: now.second.min(59) as u16,
+                millis: 0,
+            },
+        }
+    }
+}
+
+/// A file on a FAT volume. The file is reopened by its path for each operation, because the volume can't be borrowed
+/// past the lock.
+struct FatFile<T: Read + Write + Seek> {
+    volume: Arc<Mutex<Volume<T>>>,
+    path: String,
+    position: u64,
+    append: bool,
+    write: bool,
+}
+
+/// Reopens a file on a FAT12, FAT16 or FAT32 volume at `position` and runs `f` on it, remembering where it leaves the
+/// cursor.
+///
+/// # Arguments
+/// `volume`: The volume.  
+/// `path`: The path within the volume of the file.  
+/// `position`: The cursor.  
+/// `f`: The operation on the file.  
+fn with_fat_file<T: Read + Write + Seek, R, F: FnOnce(&mut fatfs::File<'_, T>) -> io::Result<R>>(
+    volume: &fatfs::FileSystem<T>,
+    path: &str,
+    position: &mut u64,
+    f: F,
+) -> io::Result<R> {
+    let mut file = volume.root_dir().open_file(path)?;
+    file.seek(SeekFrom::Start(*position))?;
+
+    let result = f(&mut file)?;
+    *position = file.stream_position()?;
+    file.flush()?;
+    Ok(result)
+}
+
+impl<T: Read + Write + Seek> File for FatFile<T> {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        match &mut *self.volume.lock() {
+            Volume::Fat(volume) => Ok(entry_metadata(&find_entry(volume, &self.path)?)),
+            Volume::ExFat(volume) => volume.metadata(&self.path),
+        }
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        if !self.write {
+            return Err(not_writable());
+        }
+
+        match &mut *self.volume.lock() {
+            // FAT files are truncated at their cursor, and extended by writing zeros at their end
+            Volume::Fat(volume) => with_fat_file(volume, &self.path, &mut 0, |file| {
+                let current = file.seek(SeekFrom::End(0))?;
+                if len < current {
+                    file.seek(SeekFrom::Start(len))?;
+                    file.truncate()
+                } else {
+                    io::copy(&mut io::repeat(0).take(len - current), file).map(|_| ())
+                }
+            }),
+            Volume::ExFat(volume) => volume.set_file_len(&self.path, len),
+        }
+    }
+}
+
+impl<T: Read + Write + Seek> Read for FatFile<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut *self.volume.lock() {
+            Volume::Fat(volume) => with_fat_file(volume, &self.path, &mut self.position, |file| {
+                file.read(buf)
+            }),
+            Volume::ExFat(volume) => {
+                let read = volume.read_file(&self.path, self.position, buf)?;
+                self.position += read as u64;
+                Ok(read)
+            }
+        }
+    }
+}
+
+impl<T: Read + Write + Seek> Seek for FatFile<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match &mut *self.volume.lock() {
+            Volume::Fat(volume) => with_fat_file(volume, &self.path, &mut self.position, |file| {
+                file.seek(pos)
+            }),
+            Volume::ExFat(volume) => {
+                let position = match pos {
+                    SeekFrom::Start(offset) => Some(offset),
+                    SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+                    SeekFrom::End(offset) => {
+                        volume.file_len(&self.path)?.checked_add_signed(offset)
+                    }
+                };
+                self.position = position.ok_or_else(|| {
+                    invalid_input("Invalid seek to a negative or overflowing position")
+                })?;
+                Ok(self.position)
+            }
+        }
+    }
+}
+
+impl<T: Read + Write + Seek> Write for FatFile<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.write {
+            return Err(not_writable());
+        }
+
+        let append = self.append;
+        match &mut *self.volume.lock() {
+            Volume::Fat(volume) => with_fat_file(volume, &self.path, &mut self.position, |file| {
+                if append {
+                    file.seek(SeekFrom::End(0))?;
+                }
+                file.write(buf)
+            }),
+            Volume::ExFat(volume) => {
+                if append {
+                    self.position = volume.file_len(&self.path)?;
+                }
+                volume.write_file(&self.path, self.position, buf)?;
+                self.position += buf.len() as u64;
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // every write is flushed as it's made
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::fat_fs::{FatFS, FatType};
+    use crate::file::{Metadata, OpenOptions};
+    use crate::util::test::{check_open_options, check_set_len, read_directory};
+    use crate::FileSystem;
+    use std::fs;
+    use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+
+    fn fat_fs() -> FatFS<Cursor<Vec<u8>>> {
+        let fat_fs = FatFS::format(Cursor::new(vec![0; 4 * 1024 * 1024])).unwrap();
+        fat_fs.create_dir("boot").unwrap();
+        write!(fat_fs.create_file("boot/config.txt").unwrap(), "config").unwrap();
+        write!(fat_fs.create_file("Long File Name.bin").unwrap(), "binary").unwrap();
+        fat_fs
+    }
+
+    #[test]
+    fn read() {
+        let fat_fs = fat_fs();
+        assert_eq!(fat_fs.fat_type(), FatType::Fat12);
+
+        assert_eq!(
+            fat_fs
+                .open_file("/boot/../boot/config.txt")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "config"
+        );
+        // names are case-insensitive, and short names are aliases
+        assert!(fat_fs.exists("BOOT/CONFIG.TXT").unwrap());
+        assert!(fat_fs.exists("LONGFI~1.BIN").unwrap());
+
+        let root = read_directory(&fat_fs, "");
+        itertools::assert_equal(root.keys(), vec!["Long File Name.bin", "boot"]);
+        assert!(root["boot"].is_directory());
+        assert_eq!(root["Long File Name.bin"].len(), 6);
+        assert!(root["Long File Name.bin"].modified.is_some());
+        assert_eq!(fat_fs.metadata("").unwrap(), Metadata::directory());
+
+        assert_eq!(
+            fat_fs.open_file("missing").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert!(fat_fs.open_file("boot").is_err());
+    }
+
+    #[test]
+    fn write() {
+        let fat_fs = fat_fs();
+
+        let mut file = fat_fs
+            .open_file_options("boot/config.txt", &OpenOptions::default().append(true))
+            .unwrap();
+        write!(file, "uration").unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "configuration");
+        assert_eq!(file.metadata().unwrap().len(), 13);
+        drop(file);
+
+        let mut file = fat_fs.create_file("boot/config.txt").unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 0);
+        write!(file, "new").unwrap();
+        assert_eq!(fat_fs.metadata("boot/config.txt").unwrap().len(), 3);
+        assert!(fat_fs
+            .open_file("boot/config.txt")
+            .unwrap()
+            .write(b"read-only")
+            .is_err());
+
+        assert_eq!(
+            fat_fs.create_dir("boot").err().unwrap().kind(),
+            ErrorKind::AlreadyExists
+        );
+        assert!(fat_fs.remove_dir("boot").is_err());
+        assert!(fat_fs.remove_file("boot").is_err());
+        fat_fs.remove_file("boot/config.txt").unwrap();
+        fat_fs.remove_dir("boot").unwrap();
+        assert!(!fat_fs.exists("boot").unwrap());
+    }
+
+    #[test]
+    fn rename() {
+        let fat_fs = fat_fs();
+
+        fat_fs
+            .rename("Long File Name.bin", "boot/config.txt")
+            .unwrap();
+        assert_eq!(
+            fat_fs
+                .open_file("boot/config.txt")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "binary"
+        );
+        fat_fs.rename("boot", "firmware").unwrap();
+        itertools::assert_equal(read_directory(&fat_fs, "").keys(), vec!["firmware"]);
+        assert!(fat_fs.exists("firmware/config.txt").unwrap());
+    }
+
+    #[test]
+    fn stats() {
+        let fat_fs = fat_fs();
+        let stats = fat_fs.stats().unwrap();
+        assert!(stats.total_space > 3 * 1024 * 1024 && stats.total_space <= 4 * 1024 * 1024);
+        assert!(stats.free_space < stats.total_space);
+        assert_eq!(stats.block_size, 1024);
+    }
+
+    fn exfat_fs() -> FatFS<Cursor<Vec<u8>>> {
+        let exfat_fs =
+            FatFS::format_exfat_with_label(Cursor::new(vec![0; 4 * 1024 * 1024]), "SD CARD")
+                .unwrap();
+        exfat_fs.create_dir("DCIM").unwrap();
+        write!(exfat_fs.create_file("DCIM/photo.jpg").unwrap(), "photo").unwrap();
+        write!(
+            exfat_fs
+                .create_file("A Long File Name Spanning Entries.bin")
+                .unwrap(),
+            "binary"
+        )
+        .unwrap();
+        exfat_fs
+    }
+
+    #[test]
+    fn exfat_read() {
+        let exfat_fs = exfat_fs();
+        assert_eq!(exfat_fs.fat_type(), FatType::ExFat);
+        assert_eq!(exfat_fs.volume_label(), "SD CARD");
+
+        assert_eq!(
+            exfat_fs
+                .open_file("/DCIM/../DCIM/photo.jpg")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "photo"
+        );
+        // names are compared through the up-case table
+        assert!(exfat_fs.exists("dcim/PHOTO.JPG").unwrap());
+
+        let root = read_directory(&exfat_fs, "");
+        itertools::assert_equal(
+            root.keys(),
+            vec!["A Long File Name Spanning Entries.bin", "DCIM"],
+        );
+        assert!(root["DCIM"].is_directory());
+        assert_eq!(root["A Long File Name Spanning Entries.bin"].len(), 6);
+        assert!(root["A Long File Name Spanning Entries.bin"]
+            .modified
+            .is_some());
+        assert_eq!(exfat_fs.metadata("").unwrap(), Metadata::directory());
+
+        assert_eq!(
+            exfat_fs.open_file("missing").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            exfat_fs.open_file("DCIM").err().unwrap().kind(),
+            ErrorKind::IsADirectory
+        );
+        assert_eq!(
+            exfat_fs
+                .metadata("DCIM/photo.jpg/child")
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::NotADirectory
+        );
+    }
+
+    #[test]
+    fn exfat_write() {
+        let exfat_fs = exfat_fs();
+
+        let mut file = exfat_fs
+            .open_file_options("DCIM/photo.jpg", &OpenOptions::default().append(true))
+            .unwrap();
+        write!(file, "graph").unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "photograph");
+        assert_eq!(file.metadata().unwrap().len(), 10);
+        assert!(file.seek(SeekFrom::Current(-11)).is_err());
+        drop(file);
+
+        // writes past the end leave zeros behind them, across clusters
+        let mut file = exfat_fs.create_file("DCIM/sparse").unwrap();
+        file.seek(SeekFrom::Start(10000)).unwrap();
+        write!(file, "end").unwrap();
+        drop(file);
+        let mut contents = Vec::new();
+        exfat_fs
+            .open_file("DCIM/sparse")
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents.len(), 10003);
+        assert!(contents[..10000].iter().all(|&byte| byte == 0));
+        assert_eq!(&contents[10000..], b"end");
+
+        let mut file = exfat_fs.create_file("DCIM/photo.jpg").unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 0);
+        write!(file, "new").unwrap();
+        assert_eq!(exfat_fs.metadata("DCIM/photo.jpg").unwrap().len(), 3);
+        assert!(exfat_fs
+            .open_file("DCIM/photo.jpg")
+            .unwrap()
+            .write(b"read-only")
+            .is_err());
+
+        assert_eq!(
+            exfat_fs.create_dir("dcim").err().unwrap().kind(),
+            ErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            exfat_fs.create_file("DCIM/bad:name").err().unwrap().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            exfat_fs.remove_dir("DCIM").err().unwrap().kind(),
+            ErrorKind::DirectoryNotEmpty
+        );
+        assert!(exfat_fs.remove_file("DCIM").is_err());
+        exfat_fs.remove_file("DCIM/photo.jpg").unwrap();
+        exfat_fs.remove_file("DCIM/sparse").unwrap();
+        exfat_fs.remove_dir("DCIM").unwrap();
+        assert!(!exfat_fs.exists("DCIM").unwrap());
+    }
+
+    #[test]
+    fn exfat_rename() {
+        let exfat_fs = exfat_fs();
+
+        exfat_fs
+            .rename("A Long File Name Spanning Entries.bin", "DCIM/photo.jpg")
+            .unwrap();
+        assert_eq!(
+            exfat_fs
+                .open_file("DCIM/photo.jpg")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "binary"
+        );
+        // the case of a name can be changed in place
+        exfat_fs.rename("DCIM/photo.jpg", "DCIM/PHOTO.JPG").unwrap();
+        itertools::assert_equal(
+            read_directory(&exfat_fs, "DCIM").keys(),
+            vec!["DCIM/PHOTO.JPG"],
+        );
+
+        exfat_fs.create_dir("DCIM/100MEDIA").unwrap();
+        assert_eq!(
+            exfat_fs
+                .rename("DCIM", "DCIM/100MEDIA/DCIM")
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            exfat_fs
+                .rename("DCIM/PHOTO.JPG", "DCIM/100MEDIA")
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::AlreadyExists
+        );
+        exfat_fs.rename("DCIM", "Pictures").unwrap();
+        itertools::assert_equal(read_directory(&exfat_fs, "").keys(), vec!["Pictures"]);
+        assert!(exfat_fs.exists("Pictures/PHOTO.JPG").unwrap());
+        assert!(exfat_fs.exists("Pictures/100MEDIA").unwrap());
+    }
+
+    #[test]
+    fn exfat_stats() {
+        let exfat_fs = exfat_fs();
+        let stats = exfat_fs.stats().unwrap();
+        assert!(stats.total_space > 3 * 1024 * 1024 && stats.total_space <= 4 * 1024 * 1024);
+        assert_eq!(stats.block_size, 4096);
+
+        // removed entries give their clusters back
+        let mut file = exfat_fs.create_file("large").unwrap();
+        file.write_all(&vec![1; 1024 * 1024]).unwrap();
+        drop(file);
+        assert_eq!(
+            exfat_fs.stats().unwrap().free_space,
+            stats.free_space - 1024 * 1024
+        );
+        exfat_fs.remove_file("large").unwrap();
+        assert_eq!(exfat_fs.stats().unwrap(), stats);
+
+        assert_eq!(
+            FatFS::format_exfat(Cursor::new(vec![0; 16 * 1024]))
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn image() {
+        // changes persist in the image after it's unmounted
+        let path = std::env::temp_dir().join(format!("fat-{}.img", std::process::id()));
+        let image = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        image.set_len(1024 * 1024).unwrap();
+        let fat_fs = FatFS::format(image).unwrap();
+        write!(fat_fs.create_file("file").unwrap(), "file").unwrap();
+        drop(fat_fs);
+
+        let image = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let fat_fs = FatFS::new(image).unwrap();
+        assert_eq!(
+            fat_fs
+                .open_file("file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "file"
+        );
+        drop(fat_fs);
+
+        let image = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let exfat_fs = FatFS::format_exfat(image).unwrap();
+        exfat_fs.create_dir("dir").unwrap();
+        write!(exfat_fs.create_file("dir/file").unwrap(), "file").unwrap();
+        drop(exfat_fs);
+
+        let image = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let exfat_fs = FatFS::new(image).unwrap();
+        assert_eq!(exfat_fs.fat_type(), FatType::ExFat);
+        assert_eq!(
+            exfat_fs
+                .open_file("dir/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "file"
+        );
+        drop(exfat_fs);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn open_options() {
+        check_open_options(&fat_fs(), "boot/options");
+        check_open_options(&exfat_fs(), "DCIM/options");
+    }
+
+    #[test]
+    fn set_len() {
+        check_set_len(&fat_fs(), "boot/set_len");
+        check_set_len(&exfat_fs(), "DCIM/set_len");
+    }
+}
@@ -1,6 +1,13 @@
-use std::fs;
-use std::io::{Read, Seek, Write};
+use crate::util::{invalid_input, invalid_path, not_supported};
+use crate::FileSystem;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
+use std::time::SystemTime;
+use std::{fmt, fs};
 
 /// The type of a file.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -25,16 +32,43 @@ impl From<fs::FileType> for FileType {
     }
 }
 
+/// The capacity of the volume backing a filesystem.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FileSystemStats {
+    /// The total size of the volume, in bytes.
+    pub total_space: u64,
+    /// The free space on the volume, in bytes.
+    pub free_space: u64,
+    /// The free space available to the current user, in bytes. This may be less than the free space if some of it
+    /// is reserved.
+    pub available_space: u64,
+    /// The optimal block size for I/O, in bytes.
+    pub block_size: u64,
+}
+
 /// A directory entry.
 #[derive(Debug, Clone)]
 pub struct DirEntry {
-    /// The path to the file.
+    /// The path to the file, relative to the root of the filesystem that produced the entry.
     pub path: PathBuf,
     /// Metadata about the file.
     pub metadata: Metadata,
 }
 
 impl DirEntry {
+    /// Returns the final component of the entry's path, or an empty string if there is none.
+    pub fn file_name(&self) -> &str {
+        self.path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("")
+    }
+
+    /// Returns the type of the entry.
+    pub fn file_type(&self) -> FileType {
+        self.metadata.file_type
+    }
+
     /// Returns true if the entry is a directory.
     pub fn is_directory(&self) -> bool {
         self.metadata.is_directory()
@@ -42,7 +76,15 @@ impl DirEntry {
 
     /// Returns true if the entry is a file.
     pub fn is_file(&self) -> bool {
-        self.metadata.is_directory()
+        self.metadata.is_file()
+    }
+
+    /// Opens the entry for reading on the filesystem that produced it.
+    ///
+    /// # Arguments
+    /// `fs`: The filesystem the entry was read from.  
+    pub fn open<FS: FileSystem + ?Sized>(&self, fs: &FS) -> crate::Result<Box<dyn File>> {
+        fs.open_file(self.path.to_str().ok_or_else(invalid_path)?)
     }
 
     /// Returns the length of the file, in bytes.
@@ -52,30 +94,63 @@ impl DirEntry {
     }
 }
 
-/// Metadata about a file.
+/// Metadata about a file. Backends fill in the attributes they know about and leave the rest unset, so new
+/// attributes can be added without breaking existing filesystems.
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
 pub struct Metadata {
     /// True if the entry is a directory.
     pub file_type: FileType,
-    /// The length of the file.
+    /// The length of the file. Directories have a length of 0 on every filesystem, including the host, where the
+    /// size reported for a directory varies between platforms.
     pub len: u64,
+    /// The Unix mode bits of the entry, if known.
+    pub mode: Option<u32>,
+    /// The last modification time of the entry, if known.
+    pub modified: Option<SystemTime>,
+    /// The last access time of the entry, if known.
+    pub accessed: Option<SystemTime>,
+    /// The creation time of the entry, if known.
+    pub created: Option<SystemTime>,
+    /// The ID of the user owning the entry, if known.
+    pub uid: Option<u32>,
+    /// The ID of the group owning the entry, if known.
+    pub gid: Option<u32>,
+    /// The target of the entry if it's a symbolic link, if known.
+    pub link_target: Option<PathBuf>,
+    /// Attributes specific to the backend.
+    pub extensions: Extensions,
 }
 
 impl Metadata {
-    /// Returns metadata for a directory
-    pub fn directory() -> Self {
+    /// Returns metadata of `file_type` where only the length is known.
+    ///
+    /// # Arguments
+    /// `file_type`: The type of the entry.  
+    /// `len`: The length of the entry.  
+    pub fn new(file_type: FileType, len: u64) -> Self {
         Self {
-            file_type: FileType::Directory,
-            len: 0,
+            file_type,
+            len,
+            mode: None,
+            modified: None,
+            accessed: None,
+            created: None,
+            uid: None,
+            gid: None,
+            link_target: None,
+            extensions: Extensions::default(),
         }
     }
 
+    /// Returns metadata for a directory
+    pub fn directory() -> Self {
+        Self::new(FileType::Directory, 0)
+    }
+
     /// Returns metadata for a file.
     pub fn file(len: u64) -> Self {
-        Self {
-            file_type: FileType::File,
-            len,
-        }
+        Self::new(FileType::File, len)
     }
 
     /// Returns true if the entry is a directory.
@@ -97,101 +172,331 @@ impl Metadata {
 
 impl From<fs::Metadata> for Metadata {
     fn from(value: fs::Metadata) -> Self {
+        let file_type = value.file_type().into();
+        // directory sizes are host-specific, so report them as empty like every other filesystem
+        let len = if file_type == FileType::Directory {
+            0
+        } else {
+            value.len()
+        };
+        let mut metadata = Self::new(file_type, len);
+        metadata.modified = value.modified().ok();
+        metadata.accessed = value.accessed().ok();
+        metadata.created = value.created().ok();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            metadata.mode = Some(value.mode() & 0o7777);
+            metadata.uid = Some(value.uid());
+            metadata.gid = Some(value.gid());
+        }
+
+        metadata
+    }
+}
+
+/// A map of attributes specific to a backend, with at most one value of each type.
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Extension>>,
+}
+
+impl Extensions {
+    /// Returns the attribute of type `T`, if there is one.
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.as_any().downcast_ref())
+    }
+
+    /// Inserts an attribute, returning the previous attribute of the same type.
+    ///
+    /// # Arguments
+    /// `value`: The attribute.  
+    pub fn insert<T: Any + Clone + Debug + Eq + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|value| value.into_any().downcast().ok())
+            .map(|value| *value)
+    }
+
+    /// Returns true if there are no attributes.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Removes the attribute of type `T`, returning it if there was one.
+    pub fn remove<T: Any>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.into_any().downcast().ok())
+            .map(|value| *value)
+    }
+}
+
+impl Clone for Extensions {
+    fn clone(&self) -> Self {
         Self {
-            file_type: value.file_type().into(),
-            len: value.len(),
+            map: self
+                .map
+                .iter()
+                .map(|(&id, value)| (id, value.clone_box()))
+                .collect(),
         }
     }
 }
 
-/// Options for opening a file. The default mode is read-only.
-#[derive(Debug)]
+impl Debug for Extensions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.map.values()).finish()
+    }
+}
+
+impl PartialEq for Extensions {
+    fn eq(&self, other: &Self) -> bool {
+        self.map.len() == other.map.len()
+            && self.map.iter().all(|(id, value)| {
+                other
+                    .map
+                    .get(id)
+                    .is_some_and(|other| value.eq_any(other.as_any()))
+            })
+    }
+}
+
+impl Eq for Extensions {}
+
+/// An attribute within [`Extensions`].
+trait Extension: Any + Debug + Send + Sync {
+    /// Returns the attribute as `Any`, to downcast it.
+    fn as_any(&self) -> &dyn Any;
+
+    /// Converts the boxed attribute to `Any`, to downcast it.
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+
+    /// Clones the attribute into a new box.
+    fn clone_box(&self) -> Box<dyn Extension>;
+
+    /// Returns true if `other` is an equal attribute of the same type.
+    ///
+    /// # Arguments
+    /// `other`: The other attribute.  
+    fn eq_any(&self, other: &dyn Any) -> bool;
+}
+
+impl<T: Any + Clone + Debug + Eq + Send + Sync> Extension for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+
+    fn clone_box(&self) -> Box<dyn Extension> {
+        Box::new(self.clone())
+    }
+
+    fn eq_any(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<T>() == Some(self)
+    }
+}
+
+/// How a file is expected to be accessed, which hosts may use to tune read-ahead and caching.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum AccessPattern {
+    /// No particular pattern.
+    #[default]
+    Normal,
+    /// The file is read from beginning to end, so reading ahead pays off.
+    Sequential,
+    /// The file is read at scattered offsets, so reading ahead is wasted.
+    Random,
+}
+
+/// Options for opening a file, which behave exactly like `std::fs::OpenOptions`. The default mode is read-only.
+#[derive(Debug, Clone)]
 pub struct OpenOptions {
-    /// True if the file should be able to be appended to.
+    /// True if the file should be opened for appending. This grants write access by itself.
     pub append: bool,
     /// True if the file should be created if not present.
     pub create: bool,
+    /// True if a new file must be created, failing if it's already present. This overrides `create` and `truncate`.
+    pub create_new: bool,
     /// True if the file should be able to be read.
     pub read: bool,
     /// True if the file should be truncated.
     pub truncate: bool,
     /// True if the file should be written to.
     pub write: bool,
+    /// The Unix mode bits the file is created with, if it's created. Filesystems without permissions ignore it.
+    pub mode: Option<u32>,
+    /// True if reads and writes should bypass the host's page cache, where supported.
+    pub direct: bool,
+    /// How the file is expected to be accessed.
+    pub access: AccessPattern,
 }
 
 impl From<&OpenOptions> for fs::OpenOptions {
     fn from(value: &OpenOptions) -> Self {
-        Self::new()
+        let mut options = Self::new();
+        options
             .create(value.create)
+            .create_new(value.create_new)
             .append(value.append)
             .truncate(value.truncate)
             .read(value.read)
-            .clone()
+            .write(value.write);
+
+        #[cfg(unix)]
+        if let Some(mode) = value.mode {
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+        }
+
+        // hints that aren't flags are applied once the file is open
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+        if value.direct {
+            std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_DIRECT);
+        }
+        #[cfg(windows)]
+        {
+            use windows_sys::Win32::Storage::FileSystem::{
+                FILE_FLAG_NO_BUFFERING, FILE_FLAG_RANDOM_ACCESS, FILE_FLAG_SEQUENTIAL_SCAN,
+            };
+
+            let mut flags = match value.access {
+                AccessPattern::Normal => 0,
+                AccessPattern::Sequential => FILE_FLAG_SEQUENTIAL_SCAN,
+                AccessPattern::Random => FILE_FLAG_RANDOM_ACCESS,
+            };
+            if value.direct {
+                flags |= FILE_FLAG_NO_BUFFERING;
+            }
+            std::os::windows::fs::OpenOptionsExt::custom_flags(&mut options, flags);
+        }
+
+        options
     }
 }
 
 impl OpenOptions {
+    /// Returns options with every flag cleared, like `std::fs::OpenOptions::new`. At least one of `read`, `write`
+    /// and `append` must be set before opening a file.
+    pub fn new() -> Self {
+        Self {
+            append: false,
+            create: false,
+            create_new: false,
+            read: false,
+            truncate: false,
+            write: false,
+            mode: None,
+            direct: false,
+            access: AccessPattern::Normal,
+        }
+    }
+
     /// # Arguments
-    /// `append`: If true, the file should be opened with the cursor set to the end of the file,
-    /// rather than overwriting the file contents. Note that setting this to true will implicitly
-    /// enable writing.  
+    /// `append`: If true, writes always go to the end of the file. This grants write access, even if `write` is
+    /// not set.  
     pub fn append(mut self, append: bool) -> Self {
-        if append {
-            self.write = true;
-        }
         self.append = append;
-        self.truncate = !append;
         self
     }
 
     /// # Arguments
-    /// `append`: If true, the file should be created if it does not exist. Note that setting this
-    /// to true will implicitly enable writing.  
+    /// `create`: If true, the file should be created if it does not exist. Requires write access.  
     pub fn create(mut self, create: bool) -> Self {
-        if create {
-            self.write = true;
-        }
-        self.create = true;
+        self.create = create;
         self
     }
 
     /// # Arguments
-    /// `read`: If true, the file should be able to be read in entirety.  
+    /// `create_new`: If true, a new file is created, and opening fails if the file already exists. Requires write
+    /// access.  
+    pub fn create_new(mut self, create_new: bool) -> Self {
+        self.create_new = create_new;
+        self
+    }
+
+    /// # Arguments
+    /// `read`: If true, the file should be able to be read.  
     pub fn read(mut self, read: bool) -> Self {
         self.read = read;
         self
     }
 
     /// # Arguments
-    /// `truncate`: If true, the file should be opened with the cursor set to the beginning of the
-    /// file, overwriting all contents. Note that setting this to true will implicitly enable
-    /// writing.  
+    /// `truncate`: If true, an existing file is truncated to zero length. Requires `write`, and can't be combined
+    /// with `append`.  
     pub fn truncate(mut self, truncate: bool) -> Self {
-        if truncate {
-            self.write = true;
-        }
-        self.append = !truncate;
         self.truncate = truncate;
         self
     }
 
     /// # Arguments
-    /// `write`: If true, the file should be able to be written. By default, this will truncate
-    /// the contents of the file, unless `append` is set.
+    /// `write`: If true, the file should be able to be written. Existing contents are overwritten in place, and
+    /// kept unless `truncate` is set.  
     pub fn write(mut self, write: bool) -> Self {
         self.write = write;
         self
     }
+
+    /// # Arguments
+    /// `mode`: The Unix mode bits the file is created with, before the process's umask is applied. Filesystems
+    /// without permissions ignore it.  
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// # Arguments
+    /// `direct`: If true, reads and writes bypass the host's page cache where supported. Hosts may then require
+    /// buffers, offsets and lengths to be aligned to the volume's block size. Filesystems without a page cache
+    /// ignore it.  
+    pub fn direct(mut self, direct: bool) -> Self {
+        self.direct = direct;
+        self
+    }
+
+    /// # Arguments
+    /// `access`: How the file is expected to be accessed. Filesystems that can't make use of it ignore it.  
+    pub fn access(mut self, access: AccessPattern) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Returns true if the file is opened for writing, by either `write` or `append`.
+    pub fn writable(&self) -> bool {
+        self.write || self.append
+    }
+
+    /// Validates the combination of flags, rejecting the same combinations as `std::fs::OpenOptions` does.
+    pub fn validate(&self) -> crate::Result<()> {
+        if !self.read && !self.writable() {
+            return Err(invalid_input("no access mode was requested"));
+        }
+        if !self.writable() && (self.create || self.create_new || self.truncate) {
+            return Err(invalid_input(
+                "creating or truncating a file requires write access",
+            ));
+        }
+        if self.append && self.truncate && !self.create_new {
+            return Err(invalid_input(
+                "a file can't be both appended to and truncated",
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl Default for OpenOptions {
     fn default() -> Self {
         Self {
-            append: false,
-            create: false,
             read: true,
-            truncate: false,
-            write: false,
+            ..Self::new()
         }
     }
 }
@@ -214,4 +519,181 @@ pub trait File: Read + Write + Seek {
         self.read_to_string(&mut str)?;
         Ok(str)
     }
+
+    /// Changes the length of the file to `len`, extending it with zeros. The cursor isn't moved. By default, files
+    /// can only be extended, by writing zeros at their end, and shrinking them returns `Unsupported`.
+    ///
+    /// # Arguments
+    /// `len`: The new length of the file.  
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        let pos = self.stream_position()?;
+        let current = self.seek(SeekFrom::End(0))?;
+        if len < current {
+            self.seek(SeekFrom::Start(pos))?;
+            return Err(not_supported());
+        }
+
+        io::copy(&mut io::repeat(0).take(len - current), self)?;
+        self.seek(SeekFrom::Start(pos))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::{Extensions, File, FileType, Metadata, OpenOptions};
+    use crate::memory_fs::MemoryFS;
+    use crate::util::test::DefaultLenFile;
+    use crate::FileSystem;
+    use std::fs;
+    use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+
+    #[test]
+    fn validate() {
+        for options in [
+            OpenOptions::default(),
+            OpenOptions::new().write(true),
+            OpenOptions::new().append(true),
+            OpenOptions::new().append(true).create(true),
+            OpenOptions::new().write(true).create(true).truncate(true),
+            // new files are empty anyway, so truncating them is allowed even when appending
+            OpenOptions::new()
+                .append(true)
+                .truncate(true)
+                .create_new(true),
+        ] {
+            assert!(options.validate().is_ok(), "{options:?}");
+        }
+
+        for options in [
+            OpenOptions::new(),
+            OpenOptions::default().create(true),
+            OpenOptions::default().create_new(true),
+            OpenOptions::default().truncate(true),
+            OpenOptions::new().append(true).truncate(true),
+            OpenOptions::new().write(true).append(true).truncate(true),
+        ] {
+            assert_eq!(
+                options.validate().unwrap_err().kind(),
+                ErrorKind::InvalidInput,
+                "{options:?}"
+            );
+        }
+
+        // setting an option doesn't change any other option
+        let options = OpenOptions::new().append(true).truncate(false);
+        assert!(options.append && !options.write && options.writable());
+        let options = OpenOptions::default().create(false);
+        assert!(options.read && !options.create && !options.writable());
+    }
+
+    #[test]
+    fn host_open_options() {
+        let root = std::env::temp_dir().join(format!("open-options-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let path = root.join("file");
+        let open = |options: OpenOptions| fs::OpenOptions::from(&options).open(&path);
+
+        let mut file = open(OpenOptions::new().write(true).create_new(true)).unwrap();
+        file.write_all(b"written").unwrap();
+        drop(file);
+        assert_eq!(
+            open(OpenOptions::new().write(true).create_new(true))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::AlreadyExists
+        );
+
+        // files opened read-only can't be written
+        let mut file = open(OpenOptions::default()).unwrap();
+        assert!(file.write_all(b"more").is_err());
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "written");
+
+        // appending doesn't require writing to be requested explicitly
+        open(OpenOptions::new().append(true))
+            .unwrap()
+            .write_all(b" more")
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "written more");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn dir_entry() {
+        let fs = MemoryFS::default();
+        fs.create_dir_all("a/b").unwrap();
+        write!(fs.create_file("a/b/file").unwrap(), "contents").unwrap();
+
+        let entry = fs.read_dir("/a/./b/").unwrap().next().unwrap().unwrap();
+        assert_eq!(entry.path.to_str(), Some("a/b/file"));
+        assert_eq!(entry.file_name(), "file");
+        assert_eq!(entry.file_type(), FileType::File);
+        assert!(entry.is_file());
+        assert!(!entry.is_directory());
+
+        let mut contents = String::new();
+        entry
+            .open(&fs)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "contents");
+
+        let entry = fs.read_dir("a").unwrap().next().unwrap().unwrap();
+        assert_eq!(entry.path.to_str(), Some("a/b"));
+        assert_eq!(entry.file_type(), FileType::Directory);
+        assert!(!entry.is_file());
+        assert!(entry.open(&fs).is_err());
+    }
+
+    #[test]
+    fn set_len() {
+        // by default, files are extended with zeros at their end and can't be shrunk, and the cursor stays put
+        let fs = MemoryFS::default();
+        let mut file = DefaultLenFile(fs.create_file("file").unwrap());
+        file.write_all(b"hello").unwrap();
+        file.seek(SeekFrom::Start(1)).unwrap();
+        file.set_len(8).unwrap();
+        assert_eq!(file.stream_position().unwrap(), 1);
+        file.set_len(8).unwrap();
+        assert_eq!(file.set_len(2).unwrap_err().kind(), ErrorKind::Unsupported);
+        assert_eq!(file.stream_position().unwrap(), 1);
+        drop(file);
+        assert_eq!(
+            fs.open_file("file").unwrap().read_into_vec().unwrap(),
+            b"hello\0\0\0"
+        );
+    }
+
+    #[test]
+    fn extensions() {
+        #[derive(Debug, Clone, Eq, PartialEq)]
+        struct Etag(String);
+
+        let mut extensions = Extensions::default();
+        assert!(extensions.is_empty());
+        assert_eq!(extensions.insert(Etag("a".to_owned())), None);
+        assert_eq!(extensions.insert(7u32), None);
+        assert_eq!(
+            extensions.insert(Etag("b".to_owned())),
+            Some(Etag("a".to_owned()))
+        );
+        assert_eq!(extensions.get::<Etag>(), Some(&Etag("b".to_owned())));
+        assert_eq!(extensions.get::<u64>(), None);
+
+        // metadata is compared and cloned along with its extensions
+        let mut metadata = Metadata::file(1);
+        metadata.extensions = extensions.clone();
+        assert_eq!(metadata.clone(), metadata);
+        assert_ne!(metadata, Metadata::file(1));
+        metadata.extensions.insert(8u32);
+        assert_ne!(metadata.extensions, extensions);
+
+        assert_eq!(extensions.remove::<u32>(), Some(7));
+        assert_eq!(extensions.remove::<u32>(), None);
+        assert_eq!(format!("{extensions:?}"), r#"{Etag("b")}"#);
+    }
 }
@@ -0,0 +1,221 @@
+use crate::file::File;
+use bytes::{Buf, Bytes};
+use http_body::{Body, Frame, SizeHint};
+use std::error::Error;
+use std::future::poll_fn;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, Write};
+use std::pin::{pin, Pin};
+use std::task::{Context, Poll};
+
+/// The size of the chunks a file is streamed in by default.
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// An `http_body::Body` that streams a file from its position to its end in chunks, so that large files, such as the
+/// entries of an archive, can be sent by `hyper` or `axum` without reading them into memory.
+///
+/// Each chunk is read when the body is polled for it, blocking the task that polls it. The length of the body is the
+/// length of the file past its position when the body was created, and the body fails with `UnexpectedEof` if the file
+/// ends before it. The body is `Send` if the file is, such as a `Box<dyn File + Send>`.
+pub struct FileBody<F: ?Sized = dyn File> {
+    file: Box<F>,
+    chunk_size: usize,
+    /// The number of bytes left to stream.
+    remaining: u64,
+}
+
+impl<F: File + ?Sized> FileBody<F> {
+    /// Creates a body that streams `file` from its position to its end.
+    ///
+    /// # Arguments
+    /// `file`: The file to stream.  
+    pub fn new(mut file: Box<F>) -> crate::Result<Self> {
+        let remaining = file
+            .metadata()?
+            .len()
+            .saturating_sub(file.stream_position()?);
+        Ok(Self {
+            file,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            remaining,
+        })
+    }
+
+    /// # Arguments
+    /// `chunk_size`: The most bytes that are read into a single frame. Defaults to 64 KiB.  
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// # Arguments
+    /// `limit`: The most bytes that are streamed, such as the length of the range of a range request. Defaults to the
+    /// rest of the file.  
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.remaining = self.remaining.min(limit);
+        self
+    }
+
+    /// Returns the file being streamed.
+    pub fn into_inner(self) -> Box<F> {
+        self.file
+    }
+}
+
+impl<F: File + ?Sized> Body for FileBody<F> {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        if self.remaining == 0 {
+            return Poll::Ready(None);
+        }
+
+        let len = self.remaining.min(self.chunk_size as u64) as usize;
+        let mut chunk = vec![0; len];
+        let read_len = loop {
+            match self.file.read(&mut chunk) {
+                Ok(0) => {
+                    self.remaining = 0;
+                    return Poll::Ready(Some(Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "The file ended before its body",
+                    ))));
+                }
+                Ok(read_len) => break read_len,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Poll::Ready(Some(Err(err))),
+            }
+        };
+        chunk.truncate(read_len);
+        self.remaining -= read_len as u64;
+        Poll::Ready(Some(Ok(Frame::data(Bytes::from(chunk)))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining)
+    }
+}
+
+/// Writes the data of `body` into `file` as it arrives, such as the body of an upload, without buffering it. Trailers
+/// are ignored. Returns the number of bytes written.
+///
+/// # Arguments
+/// `body`: The body to write.  
+/// `file`: The file to write the body into.  
+pub async fn write_body<B, W>(body: B, file: &mut W) -> crate::Result<u64>
+where
+    B: Body,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+    W: Write + ?Sized,
+{
+    let mut body = pin!(body);
+    let mut written = 0;
+    while let Some(frame) = poll_fn(|cx| body.as_mut().poll_frame(cx)).await {
+        let frame = frame.map_err(io::Error::other)?;
+        if let Ok(mut data) = frame.into_data() {
+            while data.has_remaining() {
+                let chunk_len = data.chunk().len();
+                file.write_all(data.chunk())?;
+                data.advance(chunk_len);
+                written += chunk_len as u64;
+            }
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file_body::{write_body, FileBody};
+    use crate::memory_fs::MemoryFS;
+    use crate::FileSystem;
+    use http_body::Body;
+    use std::future::Future;
+    use std::io::{ErrorKind, Seek, SeekFrom, Write};
+    use std::pin::{pin, Pin};
+    use std::task::{Context, Poll, Waker};
+
+    /// Collects the frames of a body, which never has to wait for them.
+    fn collect<B: Body + Unpin>(mut body: B) -> Vec<Result<B::Data, B::Error>> {
+        let mut frames = Vec::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        while let Poll::Ready(Some(frame)) = Pin::new(&mut body).poll_frame(&mut cx) {
+            frames.push(frame.map(|frame| frame.into_data().ok().unwrap()));
+        }
+        frames
+    }
+
+    /// Polls a future that never has to wait to completion.
+    fn ready<T>(future: impl Future<Output = T>) -> T {
+        match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("the future should be ready"),
+        }
+    }
+
+    #[test]
+    fn stream() {
+        let fs = MemoryFS::default();
+        write!(fs.create_file("file").unwrap(), "hello world").unwrap();
+        let mut file = fs.open_file("file").unwrap();
+        file.seek(SeekFrom::Start(2)).unwrap();
+
+        let body = FileBody::new(file).unwrap().chunk_size(4);
+        assert_eq!(body.size_hint().exact(), Some(9));
+        let chunks = collect(body)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(chunks, vec!["llo ", "worl", "d"]);
+
+        let body = FileBody::new(fs.open_file("file").unwrap())
+            .unwrap()
+            .limit(5);
+        assert_eq!(body.size_hint().exact(), Some(5));
+        assert_eq!(collect(body)[0].as_ref().unwrap(), "hello");
+    }
+
+    #[test]
+    fn truncated() {
+        let fs = MemoryFS::default();
+        write!(fs.create_file("file").unwrap(), "hello").unwrap();
+        // the file is shorter than the body claims it is
+        let body = FileBody {
+            remaining: 10,
+            ..FileBody::new(fs.open_file("file").unwrap()).unwrap()
+        };
+
+        let frames = collect(body);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].as_ref().unwrap(), "hello");
+        assert_eq!(
+            frames[1].as_ref().err().unwrap().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn write() {
+        let fs = MemoryFS::default();
+        write!(fs.create_file("from").unwrap(), "hello world").unwrap();
+        let body = FileBody::new(fs.open_file("from").unwrap())
+            .unwrap()
+            .chunk_size(3);
+
+        let mut to = fs.create_file("to").unwrap();
+        assert_eq!(ready(write_body(body, &mut to)).unwrap(), 11);
+        drop(to);
+        assert_eq!(
+            fs.open_file("to").unwrap().read_into_string().unwrap(),
+            "hello world"
+        );
+    }
+}
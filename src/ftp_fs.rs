@@ -0,0 +1,1141 @@
+use crate::file::{DirEntry, File, FileType, Metadata, OpenOptions};
+use crate::time::DateTime;
+use crate::tree::normalize_and_relativize;
+use crate::util::{already_exists, invalid_input, invalid_path, not_found, not_supported};
+use crate::FileSystem;
+use parking_lot::Mutex;
+use std::io;
+use std::io::{BufRead, BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// How data connections for listings and transfers are established.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum DataMode {
+    /// The client connects to a port opened by the server. This works behind client-side NAT and firewalls.
+    #[default]
+    Passive,
+    /// The server connects to a port opened by the client.
+    Active,
+}
+
+/// A read-write filesystem on a remote FTP or FTPS server. Paths are relative to the working directory the server
+/// logs in to.
+///
+/// Files are transferred whole: opening a file downloads it into memory, and the contents of a file opened for
+/// writing are uploaded when it's flushed or dropped.
+pub struct FtpFS {
+    control: Arc<Mutex<Control>>,
+    root: String,
+}
+
+impl FtpFS {
+    /// Connects and logs in to an FTP server.
+    ///
+    /// # Arguments
+    /// `addr`: The address of the server.  
+    /// `user`: The user to log in as, such as `anonymous`.  
+    /// `password`: The password of the user.  
+    pub fn connect<A: ToSocketAddrs>(addr: A, user: &str, password: &str) -> crate::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        let mut control = Control::new(Stream::Plain(stream))?;
+        control.login(user, password)?;
+
+        Self::with_control(control)
+    }
+
+    /// Connects to an FTP server and upgrades the connection to TLS with `AUTH TLS` before logging in. The server's
+    /// certificate is verified against the Mozilla root certificates.
+    ///
+    /// # Arguments
+    /// `addr`: The address of the server.  
+    /// `domain`: The domain name the server's certificate is verified against.  
+    /// `user`: The user to log in as.  
+    /// `password`: The password of the user.  
+    #[cfg(feature = "ftps")]
+    pub fn connect_tls<A: ToSocketAddrs>(
+        addr: A,
+        domain: &str,
+        user: &str,
+        password: &str,
+    ) -> crate::Result<Self> {
+        let roots =
+            rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+        Self::connect_tls_with_config(addr, domain, Arc::new(config), user, password)
+    }
+
+    /// Connects to an FTP server and upgrades the connection to TLS with `AUTH TLS` before logging in.
+    ///
+    /// # Arguments
+    /// `addr`: The address of the server.  
+    /// `domain`: The domain name the server's certificate is verified against.  
+    /// `config`: The TLS configuration, used for the control connection and every data connection.  
+    /// `user`: The user to log in as.  
+    /// `password`: The password of the user.  
+    #[cfg(feature = "ftps")]
+    pub fn connect_tls_with_config<A: ToSocketAddrs>(
+        addr: A,
+        domain: &str,
+        config: Arc<rustls::ClientConfig>,
+        user: &str,
+        password: &str,
+    ) -> crate::Result<Self> {
+        let server_name = rustls::pki_types::ServerName::try_from(domain.to_owned())
+            .map_err(|_| invalid_input("Invalid domain name"))?;
+
+        let stream = TcpStream::connect(addr)?;
+        let mut control = Control::new(Stream::Plain(stream))?;
+        control.expect("AUTH TLS", 2)?;
+        control.tls = Some(Tls {
+            config,
+            server_name,
+        });
+        control.stream =
+            BufReader::new(control.secure(control.stream.get_ref().tcp().try_clone()?)?);
+
+        control.login(user, password)?;
+        // protect data connections too
+        control.expect("PBSZ 0", 2)?;
+        control.expect("PROT P", 2)?;
+
+        Self::with_control(control)
+    }
+
+    /// Finishes setting up a logged in control connection.
+    ///
+    /// # Arguments
+    /// `control`: The control connection.  
+    fn with_control(mut control: Control) -> crate::Result<Self> {
+        control.expect("TYPE I", 2)?;
+        let root = parse_pwd(&control.expect("PWD", 2)?.text)?;
+
+        Ok(Self {
+            control: Arc::new(Mutex::new(control)),
+            root,
+        })
+    }
+
+    /// # Arguments
+    /// `mode`: How data connections are established.  
+    pub fn data_mode(self, mode: DataMode) -> Self {
+        self.control.lock().mode = mode;
+        self
+    }
+
+    /// Returns the path on the server of `path`.
+    ///
+    /// # Arguments
+    /// `path`: The virtual path.  
+    fn remote_path(&self, path: &str) -> crate::Result<String> {
+        let path = normalize_and_relativize(path);
+        let path = path.to_str().ok_or_else(invalid_path)?.replace('\\', "/");
+        // line breaks would smuggle in extra commands
+        if path.contains(['\r', '\n']) {
+            return Err(invalid_path());
+        }
+
+        Ok(match (self.root.trim_end_matches('/'), path.is_empty()) {
+            ("", true) => "/".to_owned(),
+            (_, true) => self.root.clone(),
+            (root, false) => format!("{root}/{path}"),
+        })
+    }
+}
+
+impl FileSystem for FtpFS {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        let path = self.remote_path(path)?;
+        self.control.lock().expect(&format!("MKD {path}"), 2)?;
+        Ok(())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        let path = self.remote_path(path)?;
+        self.control.lock().metadata(&path)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        let remote_path = self.remote_path(path)?;
+        if normalize_and_relativize(path).as_os_str().is_empty() {
+            return Err(not_found());
+        }
+
+        options.validate()?;
+        let mut control = self.control.lock();
+        if !options.writable() {
+            let contents = control.retrieve(&remote_path)?;
+            return Ok(Box::new(FtpFile {
+                contents: Cursor::new(contents),
+                upload: None,
+                append: false,
+                dirty: false,
+            }));
+        }
+
+        let existing = match control.retrieve(&remote_path) {
+            Ok(contents) => Some(contents),
+            Err(err)
+                if err.kind() == ErrorKind::NotFound && (options.create || options.create_new) =>
+            {
+                None
+            }
+            Err(err) => return Err(err),
+        };
+        let contents = match existing {
+            Some(_) if options.create_new => return Err(already_exists()),
+            Some(contents) if !options.truncate => contents,
+            existing => {
+                // the file exists from the moment it's created or truncated, even if it's never written
+                if existing.is_none_or(|contents| !contents.is_empty()) {
+                    control.store(&remote_path, &[])?;
+                }
+                Vec::new()
+            }
+        };
+
+        let mut contents = Cursor::new(contents);
+        if options.append {
+            contents.seek(SeekFrom::End(0))?;
+        }
+
+        Ok(Box::new(FtpFile {
+            contents,
+            upload: Some((self.control.clone(), remote_path)),
+            append: options.append,
+            dirty: false,
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let remote_path = self.remote_path(path)?;
+        let entries = self.control.lock().list(&remote_path)?;
+
+        let directory = normalize_and_relativize(path);
+        Ok(Box::new(entries.into_iter().map(
+            move |(name, metadata)| {
+                Ok(DirEntry {
+                    path: directory.join(name),
+                    metadata,
+                })
+            },
+        )))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        let path = self.remote_path(path)?;
+        self.control.lock().expect(&format!("RMD {path}"), 2)?;
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        let path = self.remote_path(path)?;
+        self.control.lock().expect(&format!("DELE {path}"), 2)?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        let (from, to) = (self.remote_path(from)?, self.remote_path(to)?);
+
+        let mut control = self.control.lock();
+        control.expect(&format!("RNFR {from}"), 3)?;
+        control.expect(&format!("RNTO {to}"), 2)?;
+        Ok(())
+    }
+}
+
+/// A reply from the server.
+struct Reply {
+    code: u16,
+    /// The text of every line of the reply, without the codes.
+    text: String,
+}
+
+/// The TLS configuration of a secured connection.
+#[cfg(feature = "ftps")]
+struct Tls {
+    config: Arc<rustls::ClientConfig>,
+    server_name: rustls::pki_types::ServerName<'static>,
+}
+
+/// The control connection, which commands are issued through.
+struct Control {
+    stream: BufReader<Stream>,
+    mode: DataMode,
+    #[cfg(feature = "ftps")]
+    tls: Option<Tls>,
+    /// False if the server doesn't support `MLST` and `MLSD`.
+    machine_listings: bool,
+}
+
+impl Control {
+    /// Wraps a fresh control connection, waiting for the server's greeting.
+    ///
+    /// # Arguments
+    /// `stream`: The control connection.  
+    fn new(stream: Stream) -> crate::Result<Self> {
+        let mut control = Self {
+            stream: BufReader::new(stream),
+            mode: DataMode::default(),
+            #[cfg(feature = "ftps")]
+            tls: None,
+            machine_listings: true,
+        };
+
+        // the server may ask us to wait before it's ready
+        loop {
+            let reply = control.read_reply()?;
+            match reply.code / 100 {
+                1 => continue,
+                2 => return Ok(control),
+                _ => return Err(reply_error(&reply)),
+            }
+        }
+    }
+
+    /// Logs in as `user`.
+    ///
+    /// # Arguments
+    /// `user`: The user to log in as.  
+    /// `password`: The password of the user.  
+    fn login(&mut self, user: &str, password: &str) -> crate::Result<()> {
+        if [user, password].iter().any(|s| s.contains(['\r', '\n'])) {
+            return Err(invalid_input("Invalid credentials"));
+        }
+
+        let reply = self.command(&format!("USER {user}"))?;
+        match reply.code {
+            230 => Ok(()),
+            331 | 332 => {
+                self.expect(&format!("PASS {password}"), 2)?;
+                Ok(())
+            }
+            _ => Err(reply_error(&reply)),
+        }
+    }
+
+    /// Issues a command and reads the reply.
+    ///
+    /// # Arguments
+    /// `command`: The command, without the line ending.  
+    fn command(&mut self, command: &str) -> crate::Result<Reply> {
+        let stream = self.stream.get_mut();
+        stream.write_all(format!("{command}\r\n").as_bytes())?;
+        stream.flush()?;
+
+        self.read_reply()
+    }
+
+    /// Issues a command, failing unless the reply has the expected class.
+    ///
+    /// # Arguments
+    /// `command`: The command, without the line ending.  
+    /// `class`: The expected first digit of the reply code.  
+    fn expect(&mut self, command: &str, class: u16) -> crate::Result<Reply> {
+        let reply = self.command(command)?;
+        if reply.code / 100 == class {
+            Ok(reply)
+        } else {
+            Err(reply_error(&reply))
+        }
+    }
+
+    /// Reads a reply, which may span multiple lines.
+    fn read_reply(&mut self) -> crate::Result<Reply> {
+        let first = self.read_line()?;
+        let code = first
+            .get(..3)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Malformed FTP reply"))?;
+        let mut text = first.get(4..).unwrap_or("").to_owned();
+
+        // multi-line replies continue until a line starting with the code and a space
+        if first.as_bytes().get(3) == Some(&b'-') {
+            let end = format!("{code} ");
+            loop {
+                let line = self.read_line()?;
+                text.push('\n');
+                match line.strip_prefix(&end) {
+                    Some(rest) => {
+                        text.push_str(rest);
+                        break;
+                    }
+                    None => text.push_str(&line),
+                }
+            }
+        }
+
+        Ok(Reply { code, text })
+    }
+
+    /// Reads a line without its line ending.
+    fn read_line(&mut self) -> crate::Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+    }
+
+    /// Issues a command that transfers data, returning the data connection.
+    ///
+    /// # Arguments
+    /// `command`: The command, without the line ending.  
+    fn open_data(&mut self, command: &str) -> crate::Result<Stream> {
+        let stream = match self.mode {
+            DataMode::Passive => {
+                let addr = self.passive_addr()?;
+                let stream = TcpStream::connect(addr)?;
+                self.expect(command, 1)?;
+                stream
+            }
+            DataMode::Active => {
+                let local = self.stream.get_ref().tcp().local_addr()?;
+                let listener = TcpListener::bind((local.ip(), 0))?;
+                let port = listener.local_addr()?.port();
+                match local.ip() {
+                    IpAddr::V4(ip) => {
+                        let [a, b, c, d] = ip.octets();
+                        let [p1, p2] = port.to_be_bytes();
+                        self.expect(&format!("PORT {a},{b},{c},{d},{p1},{p2}"), 2)?
+                    }
+                    IpAddr::V6(ip) => self.expect(&format!("EPRT |2|{ip}|{port}|"), 2)?,
+                };
+                self.expect(command, 1)?;
+                listener.accept()?.0
+            }
+        };
+
+        self.secure(stream)
+    }
+
+    /// Asks the server to open a port for a passive data connection, returning its address.
+    fn passive_addr(&mut self) -> crate::Result<SocketAddr> {
+        // data connections go to the same host as the control connection, whatever the server claims
+        let peer = self.stream.get_ref().tcp().peer_addr()?;
+
+        let reply = self.command("EPSV")?;
+        if reply.code == 229 {
+            let port = reply
+                .text
+                .split('|')
+                .nth(3)
+                .and_then(|port| port.parse().ok())
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Malformed EPSV reply"))?;
+            return Ok(SocketAddr::new(peer.ip(), port));
+        }
+        if !peer.is_ipv4() {
+            return Err(reply_error(&reply));
+        }
+
+        let reply = self.expect("PASV", 2)?;
+        let numbers: Vec<u16> = reply
+            .text
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|number| !number.is_empty())
+            .filter_map(|number| number.parse().ok())
+            .collect();
+        match numbers.as_slice() {
+            [.., p1, p2] if numbers.len() >= 6 => Ok(SocketAddr::new(peer.ip(), p1 * 256 + p2)),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Malformed PASV reply",
+            )),
+        }
+    }
+
+    /// Secures a connection if the control connection is secured.
+    ///
+    /// # Arguments
+    /// `stream`: The connection to secure.  
+    fn secure(&self, stream: TcpStream) -> crate::Result<Stream> {
+        #[cfg(feature = "ftps")]
+        if let Some(tls) = &self.tls {
+            let connection =
+                rustls::ClientConnection::new(tls.config.clone(), tls.server_name.clone())
+                    .map_err(io::Error::other)?;
+            return Ok(Stream::Tls(Box::new(rustls::StreamOwned::new(
+                connection, stream,
+            ))));
+        }
+
+        Ok(Stream::Plain(stream))
+    }
+
+    /// Closes a data connection and waits for the server to confirm the transfer.
+    ///
+    /// # Arguments
+    /// `data`: The data connection.  
+    fn close_data(&mut self, data: Stream) -> crate::Result<()> {
+        data.shutdown()?;
+
+        let reply = self.read_reply()?;
+        if reply.code / 100 == 2 {
+            Ok(())
+        } else {
+            Err(reply_error(&reply))
+        }
+    }
+
+    /// Downloads the file at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The path on the server.  
+    fn retrieve(&mut self, path: &str) -> crate::Result<Vec<u8>> {
+        let mut data = self.open_data(&format!("RETR {path}"))?;
+        let mut contents = Vec::new();
+        data.read_to_end(&mut contents)?;
+        self.close_data(data)?;
+
+        Ok(contents)
+    }
+
+    /// Uploads `contents` to the file at `path`, replacing it.
+    ///
+    /// # Arguments
+    /// `path`: The path on the server.  
+    /// `contents`: The new contents of the file.  
+    fn store(&mut self, path: &str, contents: &[u8]) -> crate::Result<()> {
+        let mut data = self.open_data(&format!("STOR {path}"))?;
+        data.write_all(contents)?;
+        data.flush()?;
+        self.close_data(data)
+    }
+
+    /// Fetches the metadata of the entry at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The path on the server.  
+    fn metadata(&mut self, path: &str) -> crate::Result<Metadata> {
+        if self.machine_listings {
+            let reply = self.command(&format!("MLST {path}"))?;
+            match reply.code {
+                // the facts are on the line indented by a space
+                250 => {
+                    return reply
+                        .text
+                        .lines()
+                        .find_map(|line| line.strip_prefix(' '))
+                        .and_then(parse_facts)
+                        .map(|(_, metadata)| metadata)
+                        .ok_or_else(|| {
+                            io::Error::new(ErrorKind::InvalidData, "Malformed MLST reply")
+                        })
+                }
+                500 | 502 | 504 => self.machine_listings = false,
+                _ => return Err(reply_error(&reply)),
+            }
+        }
+
+        // without machine listings, files have sizes and directories can be entered
+        let reply = self.command(&format!("SIZE {path}"))?;
+        if reply.code == 213 {
+            return Ok(Metadata::file(reply.text.trim().parse().unwrap_or(0)));
+        }
+
+        let pwd = parse_pwd(&self.expect("PWD", 2)?.text)?;
+        self.expect(&format!("CWD {path}"), 2)?;
+        self.expect(&format!("CWD {pwd}"), 2)?;
+        Ok(Metadata::directory())
+    }
+
+    /// Lists the entries of the directory at `path`, returning the name and metadata of each.
+    ///
+    /// # Arguments
+    /// `path`: The path on the server.  
+    fn list(&mut self, path: &str) -> crate::Result<Vec<(String, Metadata)>> {
+        if self.machine_listings {
+            match self.read_listing(&format!("MLSD {path}")) {
+                Ok(lines) => {
+                    return Ok(lines
+                        .iter()
+                        .filter_map(|line| parse_facts(line))
+                        .filter(|(name, _)| !matches!(name.as_str(), "." | ".."))
+                        .collect())
+                }
+                Err(err) if err.kind() == ErrorKind::Unsupported => self.machine_listings = false,
+                Err(err) => return Err(err),
+            }
+        }
+
+        // plain listings are just names, so each entry is queried separately
+        let names = self.read_listing(&format!("NLST {path}"))?;
+        names
+            .into_iter()
+            // some servers list full paths
+            .map(|name| name.rsplit('/').next().unwrap_or(&name).to_owned())
+            .filter(|name| !matches!(name.as_str(), "" | "." | ".."))
+            .map(|name| {
+                let metadata = self.metadata(&format!("{}/{name}", path.trim_end_matches('/')))?;
+                Ok((name, metadata))
+            })
+            .collect()
+    }
+
+    /// Issues a command that transfers a listing, returning its lines.
+    ///
+    /// # Arguments
+    /// `command`: The command, without the line ending.  
+    fn read_listing(&mut self, command: &str) -> crate::Result<Vec<String>> {
+        let mut data = self.open_data(command)?;
+        let mut listing = String::new();
+        data.read_to_string(&mut listing)?;
+        self.close_data(data)?;
+
+        Ok(listing
+            .lines()
+            .map(|line| line.trim_end_matches('\r').to_owned())
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        let _ = self.command("QUIT");
+    }
+}
+
+/// A control or data connection, which may be secured with TLS.
+enum Stream {
+    Plain(TcpStream),
+    #[cfg(feature = "ftps")]
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+}
+
+impl Stream {
+    /// Returns the underlying TCP connection.
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(stream) => stream,
+            #[cfg(feature = "ftps")]
+            Stream::Tls(stream) => stream.get_ref(),
+        }
+    }
+
+    /// Closes the connection, notifying the peer of the end of a secured session first.
+    fn shutdown(self) -> io::Result<()> {
+        #[cfg(feature = "ftps")]
+        if let Stream::Tls(mut stream) = self {
+            stream.conn.send_close_notify();
+            stream.flush()?;
+            return Ok(());
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buf),
+            #[cfg(feature = "ftps")]
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "ftps")]
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            #[cfg(feature = "ftps")]
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A remote file held in memory. Files opened for writing are uploaded when they're flushed after being written.
+struct FtpFile {
+    contents: Cursor<Vec<u8>>,
+    /// The control connection and the path on the server the file is uploaded to, if it's writable.
+    upload: Option<(Arc<Mutex<Control>>, String)>,
+    append: bool,
+    /// True if the file was written since it was last uploaded.
+    dirty: bool,
+}
+
+impl File for FtpFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(Metadata::file(self.contents.get_ref().len() as u64))
+    }
+}
+
+impl Read for FtpFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.contents.read(buf)
+    }
+}
+
+impl Seek for FtpFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.contents.seek(pos)
+    }
+}
+
+impl Write for FtpFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.upload.is_none() {
+            return Err(not_supported());
+        }
+        if self.append {
+            self.contents.seek(SeekFrom::End(0))?;
+        }
+
+        self.dirty = true;
+        self.contents.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &self.upload {
+            Some((control, path)) if self.dirty => {
+                control.lock().store(path, self.contents.get_ref())?;
+                self.dirty = false;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for FtpFile {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Converts an error reply to an IO error.
+///
+/// # Arguments
+/// `reply`: The error reply.  
+fn reply_error(reply: &Reply) -> io::Error {
+    let kind = match reply.code {
+        450 | 550 => ErrorKind::NotFound,
+        530 | 532 | 553 => ErrorKind::PermissionDenied,
+        500 | 502 | 504 => ErrorKind::Unsupported,
+        452 => ErrorKind::StorageFull,
+        552 => ErrorKind::QuotaExceeded,
+        501 => ErrorKind::InvalidInput,
+        421 => ErrorKind::ConnectionAborted,
+        _ => ErrorKind::Other,
+    };
+
+    io::Error::new(kind, format!("FTP error {}: {}", reply.code, reply.text))
+}
+
+/// Parses the directory from a reply to `PWD`, which is quoted with embedded quotes doubled.
+///
+/// # Arguments
+/// `text`: The text of the reply.  
+fn parse_pwd(text: &str) -> crate::Result<String> {
+    let malformed = || io::Error::new(ErrorKind::InvalidData, "Malformed PWD reply");
+
+    let mut chars = text.chars().skip_while(|c| *c != '"').skip(1).peekable();
+    let mut directory = String::new();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            if chars.peek() != Some(&'"') {
+                return Ok(directory);
+            }
+            chars.next();
+        }
+        directory.push(c);
+    }
+
+    Err(malformed())
+}
+
+/// Parses an entry of a machine listing, such as `type=file;size=6;modify=20240101000000; name`. Returns the name
+/// and metadata of the entry.
+///
+/// # Arguments
+/// `line`: The entry.  
+fn parse_facts(line: &str) -> Option<(String, Metadata)> {
+    let (facts, name) = line.split_once(' ')?;
+
+    let mut metadata = Metadata::file(0);
+    for (fact, value) in facts.split(';').filter_map(|fact| fact.split_once('=')) {
+        match fact.to_ascii_lowercase().as_str() {
+            "type" => {
+                metadata.file_type = match value.to_ascii_lowercase().as_str() {
+                    "file" => FileType::File,
+                    "dir" | "cdir" | "pdir" => FileType::Directory,
+                    _ => FileType::Unknown,
+                }
+            }
+            "size" => metadata.len = value.parse().unwrap_or(0),
+            "modify" => metadata.modified = parse_time(value),
+            "unix.mode" => metadata.mode = u32::from_str_radix(value, 8).ok(),
+            _ => {}
+        }
+    }
+    if metadata.is_directory() {
+        metadata.len = 0;
+    }
+
+    // the name is the last component of the path for `MLST`
+    let name = name.trim_end_matches('/');
+    Some((name.rsplit('/').next().unwrap_or(name).to_owned(), metadata))
+}
+
+/// Parses a UTC timestamp of the form `YYYYMMDDHHMMSS`, optionally followed by fractional seconds.
+///
+/// # Arguments
+/// `value`: The timestamp.  
+fn parse_time(value: &str) -> Option<SystemTime> {
+    let field = |range: std::ops::Range<usize>| value.get(range)?.parse::<u32>().ok();
+    DateTime {
+        year: i64::from(field(0..4)?),
+        month: field(4..6)?,
+        day: field(6..8)?,
+        hour: field(8..10)?,
+        minute: field(10..12)?,
+        second: field(12..14)?,
+    }
+    .to_system_time()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::Metadata;
+    use crate::ftp_fs::{parse_facts, parse_pwd, parse_time, DataMode, FtpFS};
+    use crate::memory_fs::MemoryFS;
+    use crate::util::test::{check_open_options, read_directory};
+    use crate::FileSystem;
+    use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    /// Serves `fs` over FTP from the working directory `/home`. Returns the address of the server.
+    ///
+    /// # Arguments
+    /// `machine_listings`: True if `MLST` and `MLSD` are supported.  
+    fn serve(fs: MemoryFS, machine_listings: bool) -> SocketAddr {
+        let fs = Arc::new(fs);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let fs = fs.clone();
+                thread::spawn(move || session(&fs, stream.unwrap(), machine_listings));
+            }
+        });
+
+        addr
+    }
+
+    /// Serves a single control connection.
+    fn session(fs: &MemoryFS, mut stream: TcpStream, machine_listings: bool) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let reply = |stream: &mut TcpStream, reply: &str| {
+            stream.write_all(format!("{reply}\r\n").as_bytes()).unwrap()
+        };
+        let facts = |metadata: &Metadata| match metadata.is_directory() {
+            true => "type=dir;".to_owned(),
+            false => format!("type=file;size={};", metadata.len()),
+        };
+
+        // the memory filesystem has no metadata for its root
+        let metadata = |path: &str| match path {
+            "/" => Ok(Metadata::directory()),
+            path => fs.metadata(path),
+        };
+
+        reply(&mut stream, "220 Ready");
+        let mut passive: Option<TcpListener> = None;
+        let mut active: Option<SocketAddr> = None;
+        let mut rename_from = None;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                return;
+            }
+            let line = line.trim_end();
+            let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+            let path = match argument.strip_prefix("/home").unwrap_or(argument) {
+                "" => "/",
+                path => path,
+            };
+
+            let mut data = || match (passive.take(), active.take()) {
+                (Some(listener), _) => listener.accept().unwrap().0,
+                (_, Some(addr)) => TcpStream::connect(addr).unwrap(),
+                _ => panic!("no data connection"),
+            };
+
+            match command {
+                "USER" => reply(&mut stream, "331 Password required"),
+                "PASS" if argument == "secret" => reply(&mut stream, "230 Logged in"),
+                "PASS" => reply(&mut stream, "530 Login incorrect"),
+                "TYPE" => reply(&mut stream, "200 Type set"),
+                "PWD" => reply(&mut stream, "257 \"/home\" is the current directory"),
+                "EPSV" => {
+                    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                    let port = listener.local_addr().unwrap().port();
+                    passive = Some(listener);
+                    reply(
+                        &mut stream,
+                        &format!("229 Entering Extended Passive Mode (|||{port}|)"),
+                    );
+                }
+                "PORT" => {
+                    let numbers: Vec<u16> =
+                        argument.split(',').map(|n| n.parse().unwrap()).collect();
+                    active = Some(SocketAddr::from((
+                        [127, 0, 0, 1],
+                        numbers[4] * 256 + numbers[5],
+                    )));
+                    reply(&mut stream, "200 PORT command successful");
+                }
+                "MLST" if machine_listings => match metadata(path) {
+                    Ok(metadata) => reply(
+                        &mut stream,
+                        &format!(
+                            "250-Listing {argument}\r\n {} {argument}\r\n250 End",
+                            facts(&metadata)
+                        ),
+                    ),
+                    Err(_) => reply(&mut stream, "550 No such file"),
+                },
+                "MLSD" | "NLST" if command == "NLST" || machine_listings => {
+                    match fs.read_dir(path) {
+                        Ok(entries) => {
+                            reply(&mut stream, "150 Here comes the listing");
+                            let mut data = data();
+                            for entry in entries {
+                                let entry = entry.unwrap();
+                                let name = entry.file_name();
+                                match command {
+                                    "MLSD" => write!(data, "{} {name}\r\n", facts(&entry.metadata)),
+                                    _ => write!(data, "{name}\r\n"),
+                                }
+                                .unwrap();
+                            }
+                            drop(data);
+                            reply(&mut stream, "226 Transfer complete");
+                        }
+                        Err(_) => reply(&mut stream, "550 No such directory"),
+                    }
+                }
+                "SIZE" => match metadata(path) {
+                    Ok(metadata) if metadata.is_file() => {
+                        reply(&mut stream, &format!("213 {}", metadata.len()))
+                    }
+                    _ => reply(&mut stream, "550 Not a file"),
+                },
+                "CWD" => match metadata(path) {
+                    Ok(metadata) if metadata.is_directory() => {
+                        reply(&mut stream, "250 Directory changed")
+                    }
+                    _ => reply(&mut stream, "550 Not a directory"),
+                },
+                "RETR" => match fs.open_file(path) {
+                    Ok(mut file) => {
+                        reply(&mut stream, "150 Opening data connection");
+                        let mut contents = Vec::new();
+                        file.read_to_end(&mut contents).unwrap();
+                        data().write_all(&contents).unwrap();
+                        reply(&mut stream, "226 Transfer complete");
+                    }
+                    Err(_) => reply(&mut stream, "550 No such file"),
+                },
+                "STOR" => match fs.create_file(path) {
+                    Ok(mut file) => {
+                        reply(&mut stream, "150 Ok to send data");
+                        let mut contents = Vec::new();
+                        data().read_to_end(&mut contents).unwrap();
+                        file.write_all(&contents).unwrap();
+                        reply(&mut stream, "226 Transfer complete");
+                    }
+                    Err(_) => reply(&mut stream, "553 Could not create file"),
+                },
+                "MKD" => match fs.create_dir(path) {
+                    Ok(()) => reply(&mut stream, &format!("257 \"{argument}\" created")),
+                    Err(_) => reply(&mut stream, "550 Create directory operation failed"),
+                },
+                "RMD" | "DELE" => {
+                    let result = match command {
+                        "RMD" => fs.remove_dir(path),
+                        _ => fs.remove_file(path),
+                    };
+                    match result {
+                        Ok(()) => reply(&mut stream, "250 Removed"),
+                        Err(_) => reply(&mut stream, "550 Remove operation failed"),
+                    }
+                }
+                "RNFR" => {
+                    rename_from = Some(path.to_owned());
+                    reply(&mut stream, "350 Ready for RNTO");
+                }
+                "RNTO" => match fs.rename(&rename_from.take().unwrap(), path) {
+                    Ok(()) => reply(&mut stream, "250 Rename successful"),
+                    Err(_) => reply(&mut stream, "550 Rename failed"),
+                },
+                "QUIT" => {
+                    reply(&mut stream, "221 Goodbye");
+                    return;
+                }
+                _ => reply(&mut stream, "502 Command not implemented"),
+            }
+        }
+    }
+
+    fn memory_fs() -> MemoryFS {
+        let fs = MemoryFS::default();
+        fs.create_dir("dir").unwrap();
+        write!(fs.create_file("dir/file").unwrap(), "file").unwrap();
+        write!(fs.create_file("readme").unwrap(), "readme").unwrap();
+        fs
+    }
+
+    fn check(ftp_fs: &FtpFS) {
+        assert_eq!(
+            ftp_fs
+                .open_file("/dir/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "file"
+        );
+        assert_eq!(ftp_fs.metadata("readme").unwrap(), Metadata::file(6));
+        assert_eq!(ftp_fs.metadata("dir").unwrap(), Metadata::directory());
+        assert_eq!(ftp_fs.metadata("").unwrap(), Metadata::directory());
+        assert_eq!(
+            ftp_fs.metadata("missing").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        let root = read_directory(ftp_fs, "");
+        itertools::assert_equal(root.keys(), vec!["dir", "readme"]);
+        assert_eq!(root["readme"], Metadata::file(6));
+        assert_eq!(root["dir"], Metadata::directory());
+        itertools::assert_equal(read_directory(ftp_fs, "dir").keys(), vec!["dir/file"]);
+    }
+
+    #[test]
+    fn read() {
+        let ftp_fs = FtpFS::connect(serve(memory_fs(), true), "user", "secret").unwrap();
+        check(&ftp_fs);
+
+        // the active mode
+        check(&ftp_fs.data_mode(DataMode::Active));
+
+        // without machine listings
+        check(&FtpFS::connect(serve(memory_fs(), false), "user", "secret").unwrap());
+
+        assert_eq!(
+            FtpFS::connect(serve(memory_fs(), true), "user", "wrong")
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn write() {
+        let ftp_fs = FtpFS::connect(serve(memory_fs(), true), "user", "secret").unwrap();
+
+        // uploads happen on flush and drop
+        let mut file = ftp_fs.create_file("new").unwrap();
+        assert_eq!(ftp_fs.metadata("new").unwrap(), Metadata::file(0));
+        write!(file, "new").unwrap();
+        file.flush().unwrap();
+        assert_eq!(ftp_fs.metadata("new").unwrap(), Metadata::file(3));
+        write!(file, " file").unwrap();
+        drop(file);
+        assert_eq!(
+            ftp_fs.open_file("new").unwrap().read_into_string().unwrap(),
+            "new file"
+        );
+
+        // appends keep the existing contents
+        let mut file = ftp_fs
+            .open_file_options(
+                "readme",
+                &crate::file::OpenOptions::default().append(true).write(true),
+            )
+            .unwrap();
+        write!(file, "!").unwrap();
+        drop(file);
+        assert_eq!(
+            ftp_fs
+                .open_file("readme")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "readme!"
+        );
+
+        ftp_fs.create_dir("dir/nested").unwrap();
+        ftp_fs.rename("new", "dir/nested/renamed").unwrap();
+        itertools::assert_equal(
+            read_directory(&ftp_fs, "dir/nested").keys(),
+            vec!["dir/nested/renamed"],
+        );
+        ftp_fs.remove_file("dir/nested/renamed").unwrap();
+        ftp_fs.remove_dir("dir/nested").unwrap();
+        assert!(!ftp_fs.exists("dir/nested").unwrap());
+
+        assert!(ftp_fs.open_file("readme\r\nDELE readme").is_err());
+        assert!(ftp_fs.exists("readme").unwrap());
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(
+            parse_pwd("\"/a \"\"b\"\"\" is current").unwrap(),
+            "/a \"b\""
+        );
+        assert!(parse_pwd("no quotes").is_err());
+
+        let (name, metadata) =
+            parse_facts("Type=file;Size=10;Modify=19700102000001.5;UNIX.mode=0644; a file")
+                .unwrap();
+        assert_eq!(name, "a file");
+        assert_eq!(metadata.len(), 10);
+        assert_eq!(metadata.mode, Some(0o644));
+        assert_eq!(
+            metadata.modified,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(86401))
+        );
+        assert_eq!(
+            parse_facts("type=cdir;size=4096; /home/dir").unwrap(),
+            ("dir".to_owned(), Metadata::directory())
+        );
+        assert_eq!(
+            parse_time("20240229120000"),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1709208000))
+        );
+    }
+
+    #[test]
+    fn open_options() {
+        let ftp_fs = FtpFS::connect(serve(memory_fs(), true), "user", "secret").unwrap();
+        check_open_options(&ftp_fs, "options");
+    }
+}
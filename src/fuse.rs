@@ -0,0 +1,1601 @@
+use crate::file::{File, FileType, Metadata, OpenOptions};
+use crate::{util, FileSystem};
+use fuser::{
+    BackgroundSession, BsdFileFlags, Config, Errno, FileAttr, FileHandle, FopenFlags, Generation,
+    INodeNo, InitFlags, KernelConfig, LockOwner, MountOption, OpenFlags, RenameFlags, ReplyAttr,
+    ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyStatfs,
+    ReplyWrite, Request, TimeOrNow, WriteFlags,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long the kernel may cache entries and attributes for.
+const TTL: Duration = Duration::from_secs(1);
+/// The block size reported for files, and for volumes without stats.
+const BLOCK_SIZE: u32 = 4096;
+
+/// Mounts `fs` at `mountpoint` and serves it until it's unmounted, such as with `fusermount -u`. The mount is made
+/// directly if the process may mount filesystems, or through `fusermount3` or `fusermount` otherwise.
+///
+/// # Arguments
+/// `fs`: The filesystem to serve.  
+/// `mountpoint`: The directory to mount the filesystem at.  
+pub fn mount<FS: FileSystem + Send + 'static, P: AsRef<Path>>(
+    fs: FS,
+    mountpoint: P,
+) -> io::Result<()> {
+    fuser::mount(Fuse::new(fs)?, mountpoint, &config())
+}
+
+/// Mounts `fs` at `mountpoint` and serves it on a background thread until the returned `Mount` is unmounted or
+/// dropped.
+///
+/// # Arguments
+/// `fs`: The filesystem to serve.  
+/// `mountpoint`: The directory to mount the filesystem at.  
+pub fn spawn_mount<FS: FileSystem + Send + 'static, P: AsRef<Path>>(
+    fs: FS,
+    mountpoint: P,
+) -> io::Result<Mount> {
+    let mountpoint = mountpoint.as_ref().to_owned();
+    let session = fuser::spawn_mount(Fuse::new(fs)?, &mountpoint, &config())?;
+    Ok(Mount {
+        mountpoint,
+        session,
+    })
+}
+
+/// A filesystem mounted by `spawn_mount`, which is unmounted when dropped.
+pub struct Mount {
+    mountpoint: PathBuf,
+    session: BackgroundSession,
+}
+
+impl Mount {
+    /// Returns the directory the filesystem is mounted at.
+    pub fn mountpoint(&self) -> &Path {
+        &self.mountpoint
+    }
+
+    /// Unmounts the filesystem, returning the error that stopped it being served, if any.
+    pub fn unmount(self) -> io::Result<()> {
+        self.session.umount_and_join()
+    }
+}
+
+/// Returns the options filesystems are mounted with.
+fn config() -> Config {
+    let mut config = Config::default();
+    config.mount_options = vec![
+        MountOption::FSName("virtual-fs".to_owned()),
+        MountOption::Subtype("virtual-fs".to_owned()),
+        MountOption::NoSuid,
+        MountOption::NoDev,
+    ];
+    config
+}
+
+/// A node the kernel has looked up.
+struct Node {
+    path: String,
+    /// The number of lookups the kernel hasn't forgotten yet.
+    lookups: u64,
+}
+
+/// A file opened by one or more handles. Handles of the same path share it, since some filesystems, such as
+/// `MemoryFS`, lock their files while they're open.
+struct OpenFile {
+    /// The path of the file, or `None` once it's been removed or replaced.
+    path: Option<String>,
+    /// The file, or `None` if it couldn't be reopened after it was closed.
+    file: Option<Box<dyn File>>,
+    read: bool,
+    write: bool,
+    handles: usize,
+}
+
+/// An open file or directory.
+enum Handle {
+    /// The number of the open file.
+    File(u64),
+    /// The entries of a directory as of when it was opened, including `.` and `..`.
+    Directory(Vec<(String, fuser::FileType)>),
+}
+
+/// The statistics of a volume, in blocks of its block size.
+#[derive(Debug, PartialEq)]
+struct Stats {
+    blocks: u64,
+    free: u64,
+    available: u64,
+    block_size: u32,
+}
+
+/// A request, which is handled with the session of the mount.
+type Call<FS> = Box<dyn FnOnce(&mut Session<FS>) + Send>;
+
+/// Serves a filesystem to `fuser`. Requests are handled one at a time, in order, by a worker that owns the session,
+/// since the files it holds open needn't be `Send`.
+struct Fuse<FS> {
+    calls: Option<Sender<Call<FS>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl<FS: FileSystem + Send + 'static> Fuse<FS> {
+    fn new(fs: FS) -> io::Result<Self> {
+        let (calls, requests) = mpsc::channel::<Call<FS>>();
+        let worker = thread::Builder::new()
+            .name("virtual-fs-fuse".to_owned())
+            .spawn(move || {
+                let mut session = Session::new(fs);
+                for call in requests {
+                    call(&mut session);
+                }
+            })?;
+        Ok(Self {
+            calls: Some(calls),
+            worker: Some(worker),
+        })
+    }
+
+    /// Handles a request with the session. If the worker is gone, the reply captured by the request is dropped,
+    /// which fails it with `EIO`.
+    ///
+    /// # Arguments
+    /// `call`: The request.  
+    fn call(&self, call: impl FnOnce(&mut Session<FS>) + Send + 'static) {
+        if let Some(calls) = &self.calls {
+            let _ = calls.send(Box::new(call));
+        }
+    }
+}
+
+impl<FS> Fuse<FS> {
+    /// Stops the worker once it has handled every request, which closes the files that are still open.
+    fn stop(&mut self) {
+        self.calls = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<FS> Drop for Fuse<FS> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The state of a mount: the nodes the kernel knows of, and the files and directories it has open.
+struct Session<FS> {
+    fs: FS,
+    nodes: HashMap<u64, Node>,
+    ids: HashMap<String, u64>,
+    next_id: u64,
+    handles: HashMap<u64, Handle>,
+    next_handle: u64,
+    files: HashMap<u64, OpenFile>,
+    open_paths: HashMap<String, u64>,
+    uid: u32,
+    gid: u32,
+}
+
+impl<FS: FileSystem> Session<FS> {
+    fn new(fs: FS) -> Self {
+        // SAFETY: `getuid` and `getgid` can't fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let root = Node {
+            path: String::new(),
+            lookups: 1,
+        };
+        Self {
+            fs,
+            nodes: HashMap::from([(INodeNo::ROOT.0, root)]),
+            ids: HashMap::from([(String::new(), INodeNo::ROOT.0)]),
+            next_id: INodeNo::ROOT.0 + 1,
+            handles: HashMap::new(),
+            next_handle: 1,
+            files: HashMap::new(),
+            open_paths: HashMap::new(),
+            uid,
+            gid,
+        }
+    }
+
+    /// Looks up the entry `name` in the directory `parent`, counting a lookup of its node.
+    fn lookup(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr, Errno> {
+        let path = self.child(parent, name)?;
+        let metadata = self.metadata(&path)?;
+        let id = self.remember(path);
+        Ok(self.attr(id, &metadata))
+    }
+
+    /// Returns the attributes of `node`, from the open file `handle` if it's given.
+    fn getattr(&mut self, node: u64, handle: Option<u64>) -> Result<FileAttr, Errno> {
+        let metadata = match handle.map(|handle| self.file(handle)) {
+            Some(Ok(file)) => file.metadata().map_err(errno)?,
+            _ => self.metadata(self.path(node)?)?,
+        };
+        Ok(self.attr(node, &metadata))
+    }
+
+    /// Changes the attributes of `node`. Only the size can be changed, since modes, owners and times are ignored by
+    /// filesystems.
+    fn setattr(&mut self, node: u64, size: Option<u64>) -> Result<FileAttr, Errno> {
+        let path = self.path(node)?.to_owned();
+        if let Some(size) = size {
+            self.truncate(&path, size).map_err(errno)?;
+        }
+        let metadata = self.metadata(&path)?;
+        Ok(self.attr(node, &metadata))
+    }
+
+    /// Creates the directory `name` in the directory `parent` with the mode `mode`.
+    fn mkdir(&mut self, parent: u64, name: &OsStr, mode: u32) -> Result<FileAttr, Errno> {
+        let path = self.child(parent, name)?;
+        self.fs
+            .create_dir_with(&path, mode & 0o7777)
+            .map_err(errno)?;
+        let metadata = self.metadata(&path)?;
+        let id = self.remember(path);
+        Ok(self.attr(id, &metadata))
+    }
+
+    /// Removes the file `name` from the directory `parent`.
+    fn unlink(&mut self, parent: u64, name: &OsStr) -> Result<(), Errno> {
+        let path = self.child(parent, name)?;
+        self.fs.remove_file(&path).map_err(errno)?;
+        self.detach(&path);
+        Ok(())
+    }
+
+    /// Removes the empty directory `name` from the directory `parent`.
+    fn rmdir(&mut self, parent: u64, name: &OsStr) -> Result<(), Errno> {
+        let path = self.child(parent, name)?;
+        // some filesystems remove directories with their contents, which `rmdir` never does
+        let mut entries = self.fs.read_dir(&path).map_err(errno)?;
+        if entries.next().is_some() {
+            return Err(Errno::ENOTEMPTY);
+        }
+        self.fs.remove_dir(&path).map_err(errno)
+    }
+
+    /// Renames the entry `name` in the directory `parent` to `new_name` in the directory `new_parent`.
+    fn rename(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        new_parent: u64,
+        new_name: &OsStr,
+        flags: RenameFlags,
+    ) -> Result<(), Errno> {
+        // exchanging and refusing to replace aren't supported
+        if !flags.is_empty() {
+            return Err(Errno::EINVAL);
+        }
+        let from = self.child(parent, name)?;
+        let to = self.child(new_parent, new_name)?;
+        self.rename_open(&from, &to).map_err(errno)?;
+        self.moved(&from, &to);
+        Ok(())
+    }
+
+    /// Opens the file `node` with the Linux open flags `flags`. Returns its handle.
+    fn open(&mut self, node: u64, flags: i32) -> Result<u64, Errno> {
+        let path = self.path(node)?.to_owned();
+        let number = self.open_file(&path, &open_options(flags)).map_err(errno)?;
+        Ok(self.open_handle(Handle::File(number)))
+    }
+
+    /// Creates and opens the file `name` in the directory `parent` with the mode `mode` and the Linux open flags
+    /// `flags`. Returns its attributes and handle.
+    fn create(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: i32,
+    ) -> Result<(FileAttr, u64), Errno> {
+        let path = self.child(parent, name)?;
+        if flags & libc::O_EXCL != 0 {
+            match self.metadata(&path) {
+                Err(errno) if errno == Errno::ENOENT => {}
+                Err(errno) => return Err(errno),
+                Ok(_) => return Err(Errno::EEXIST),
+            }
+        }
+        // the kernel creates files even when they're opened read-only, which requires write access here
+        let options = open_options(flags)
+            .write(true)
+            .create(true)
+            .mode(mode & 0o7777);
+        let number = self.open_file(&path, &options).map_err(errno)?;
+        let handle = self.open_handle(Handle::File(number));
+        let metadata = self.metadata(&path)?;
+        let id = self.remember(path);
+        Ok((self.attr(id, &metadata), handle))
+    }
+
+    /// Reads up to `size` bytes at `offset` of the open file `handle`.
+    fn read(&mut self, handle: u64, offset: u64, size: u32) -> Result<Vec<u8>, Errno> {
+        let file = self.file(handle)?;
+        file.seek(SeekFrom::Start(offset)).map_err(errno)?;
+        let mut data = Vec::new();
+        file.take(size as u64)
+            .read_to_end(&mut data)
+            .map_err(errno)?;
+        Ok(data)
+    }
+
+    /// Writes `data` at `offset` of the open file `handle`. Returns the number of bytes written.
+    fn write(&mut self, handle: u64, offset: u64, data: &[u8]) -> Result<u32, Errno> {
+        let file = self.file(handle)?;
+        file.seek(SeekFrom::Start(offset)).map_err(errno)?;
+        file.write_all(data).map_err(errno)?;
+        Ok(data.len() as u32)
+    }
+
+    /// Flushes the open file `handle`.
+    fn flush(&mut self, handle: u64) -> Result<(), Errno> {
+        self.file(handle)?.flush().map_err(errno)
+    }
+
+    /// Releases the open file or directory `handle`.
+    fn release(&mut self, handle: u64) -> Result<(), Errno> {
+        match self.handles.remove(&handle) {
+            Some(Handle::File(number)) => self.release_file(number),
+            Some(Handle::Directory(_)) => {}
+            None => return Err(Errno::EBADF),
+        }
+        Ok(())
+    }
+
+    /// Opens the directory `node`, listing its entries. Returns its handle.
+    fn opendir(&mut self, node: u64) -> Result<u64, Errno> {
+        let entries = self.list(node)?;
+        Ok(self.open_handle(Handle::Directory(entries)))
+    }
+
+    /// Returns the entries after the first `offset` ones of the open directory `handle` of `node`, with their inode
+    /// numbers and the offsets that follow them.
+    fn readdir(
+        &self,
+        node: u64,
+        handle: u64,
+        offset: u64,
+    ) -> Result<Vec<(u64, u64, fuser::FileType, String)>, Errno> {
+        let Some(Handle::Directory(entries)) = self.handles.get(&handle) else {
+            return Err(Errno::EBADF);
+        };
+        let parent = self.path(node)?;
+        Ok(entries
+            .iter()
+            .enumerate()
+            .skip(offset as usize)
+            .map(|(index, (name, kind))| {
+                (
+                    inode(&join(parent, name)),
+                    index as u64 + 1,
+                    *kind,
+                    name.clone(),
+                )
+            })
+            .collect())
+    }
+
+    /// Returns the statistics of the volume, which are zero if the filesystem has none.
+    fn statfs(&self) -> Stats {
+        let Ok(stats) = self.fs.stats() else {
+            return Stats {
+                blocks: 0,
+                free: 0,
+                available: 0,
+                block_size: BLOCK_SIZE,
+            };
+        };
+        let block_size = stats.block_size.clamp(1, u32::MAX as u64);
+        Stats {
+            blocks: stats.total_space / block_size,
+            free: stats.free_space / block_size,
+            available: stats.available_space / block_size,
+            block_size: block_size as u32,
+        }
+    }
+
+    /// Returns the path of `node`.
+    fn path(&self, node: u64) -> Result<&str, Errno> {
+        self.nodes
+            .get(&node)
+            .map(|node| node.path.as_str())
+            .ok_or(Errno::ENOENT)
+    }
+
+    /// Returns the path of the entry `name` in the directory `parent`.
+    fn child(&self, parent: u64, name: &OsStr) -> Result<String, Errno> {
+        let name = name.to_str().ok_or(Errno::EINVAL)?;
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(Errno::EINVAL);
+        }
+        Ok(join(self.path(parent)?, name))
+    }
+
+    /// Returns the metadata of the entry at `path`.
+    fn metadata(&self, path: &str) -> Result<Metadata, Errno> {
+        // open files are asked, since they may be locked or hold writes the filesystem hasn't seen yet
+        let open = self
+            .open_paths
+            .get(path)
+            .and_then(|number| self.files.get(number));
+        if let Some(file) = open.and_then(|open| open.file.as_ref()) {
+            return file.metadata().map_err(errno);
+        }
+        util::metadata_or_root(&self.fs, path).map_err(errno)
+    }
+
+    /// Returns the ID of the node at `path`, counting a lookup of it.
+    fn remember(&mut self, path: String) -> u64 {
+        if let Some(&id) = self.ids.get(&path) {
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.lookups += 1;
+                return id;
+            }
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.ids.insert(path.clone(), id);
+        self.nodes.insert(id, Node { path, lookups: 1 });
+        id
+    }
+
+    /// Forgets `lookups` lookups of `node`, dropping it once the kernel has forgotten every one.
+    fn forget(&mut self, node: u64, lookups: u64) {
+        if node == INodeNo::ROOT.0 {
+            return;
+        }
+        let Some(entry) = self.nodes.get_mut(&node) else {
+            return;
+        };
+        entry.lookups = entry.lookups.saturating_sub(lookups);
+        if entry.lookups > 0 {
+            return;
+        }
+        if let Some(entry) = self.nodes.remove(&node) {
+            if self.ids.get(&entry.path) == Some(&node) {
+                self.ids.remove(&entry.path);
+            }
+        }
+    }
+
+    /// Moves the nodes at and under `from` to `to`.
+    fn moved(&mut self, from: &str, to: &str) {
+        // a replaced entry's node stays until the kernel forgets it, but is no longer found by its path
+        self.ids.remove(to);
+        for (&id, node) in &mut self.nodes {
+            if let Some(path) = moved_path(&node.path, from, to) {
+                self.ids.remove(&node.path);
+                self.ids.insert(path.clone(), id);
+                node.path = path;
+            }
+        }
+    }
+
+    /// Stores an open handle, returning its number.
+    fn open_handle(&mut self, handle: Handle) -> u64 {
+        let number = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(number, handle);
+        number
+    }
+
+    /// Returns the open file `handle`.
+    fn file(&mut self, handle: u64) -> Result<&mut Box<dyn File>, Errno> {
+        match self.handles.get(&handle) {
+            Some(Handle::File(number)) => self
+                .files
+                .get_mut(number)
+                .and_then(|open| open.file.as_mut())
+                .ok_or(Errno::EBADF),
+            _ => Err(Errno::EBADF),
+        }
+    }
+
+    /// Opens the file at `path` with `options`, sharing the file that's already open at the path, if any. Returns
+    /// the number of the open file, which must be released.
+    fn open_file(&mut self, path: &str, options: &OpenOptions) -> crate::Result<u64> {
+        let shared = self.open_paths.get(path).copied();
+        let Some((number, open)) =
+            shared.and_then(|number| Some((number, self.files.get_mut(&number)?)))
+        else {
+            let file = self.fs.open_file_options(path, options)?;
+            let number = self.next_handle;
+            self.next_handle += 1;
+            self.open_paths.insert(path.to_owned(), number);
+            let open = OpenFile {
+                path: Some(path.to_owned()),
+                file: Some(file),
+                read: options.read,
+                write: options.write,
+                handles: 1,
+            };
+            self.files.insert(number, open);
+            return Ok(number);
+        };
+
+        // the shared file is reopened if it lacks access or must be truncated, once it's closed to unlock it
+        if (options.read && !open.read) || (options.write && !open.write) || options.truncate {
+            let (read, write) = (open.read || options.read, open.write || options.write);
+            open.file = None;
+            let reopened = OpenOptions {
+                read,
+                write,
+                truncate: options.truncate,
+                ..OpenOptions::default()
+            };
+            match self.fs.open_file_options(path, &reopened) {
+                Ok(file) => {
+                    open.file = Some(file);
+                    (open.read, open.write) = (read, write);
+                }
+                Err(err) => {
+                    open.file = self.fs.open_file_options(path, &reopen_options(open)).ok();
+                    return Err(err);
+                }
+            }
+        }
+        open.handles += 1;
+        Ok(number)
+    }
+
+    /// Releases a handle of the open file `number`, closing it once every handle is released.
+    fn release_file(&mut self, number: u64) {
+        let Some(open) = self.files.get_mut(&number) else {
+            return;
+        };
+        open.handles -= 1;
+        if open.handles == 0 {
+            if let Some(path) = self.files.remove(&number).and_then(|open| open.path) {
+                self.open_paths.remove(&path);
+            }
+        }
+    }
+
+    /// Forgets the path of the file open at `path`, which has been removed.
+    fn detach(&mut self, path: &str) {
+        let open = self
+            .open_paths
+            .remove(path)
+            .and_then(|number| self.files.get_mut(&number));
+        if let Some(open) = open {
+            open.path = None;
+        }
+    }
+
+    /// Renames the entry at `from` to `to`. The files open at or under `from` are closed while it's renamed, since
+    /// renaming may open them, and are reopened at their new paths. A file open at `to` is closed for good, since
+    /// it's replaced.
+    fn rename_open(&mut self, from: &str, to: &str) -> crate::Result<()> {
+        let closed = self
+            .open_paths
+            .iter()
+            .filter(|(path, _)| *path == to || moved_path(path, from, to).is_some())
+            .map(|(_, &number)| number)
+            .collect::<Vec<_>>();
+        for number in &closed {
+            if let Some(open) = self.files.get_mut(number) {
+                open.file = None;
+            }
+        }
+
+        let result = self.fs.rename(from, to);
+        if result.is_ok() {
+            self.detach(to);
+            for number in &closed {
+                let Some(open) = self.files.get_mut(number) else {
+                    continue;
+                };
+                let Some(moved) = open
+                    .path
+                    .as_ref()
+                    .and_then(|path| moved_path(path, from, to))
+                else {
+                    continue;
+                };
+                if let Some(path) = open.path.replace(moved) {
+                    self.open_paths.remove(&path);
+                }
+            }
+        }
+
+        for number in closed {
+            let Some(open) = self.files.get_mut(&number) else {
+                continue;
+            };
+            if let Some(path) = &open.path {
+                self.open_paths.insert(path.clone(), number);
+                open.file = self.fs.open_file_options(path, &reopen_options(open)).ok();
+            }
+        }
+        result
+    }
+
+    /// Lists the entries of the directory `node`.
+    fn list(&self, node: u64) -> Result<Vec<(String, fuser::FileType)>, Errno> {
+        let mut entries = vec![
+            (".".to_owned(), fuser::FileType::Directory),
+            ("..".to_owned(), fuser::FileType::Directory),
+        ];
+        for entry in self.fs.read_dir(self.path(node)?).map_err(errno)? {
+            let entry = entry.map_err(errno)?;
+            let Some(name) = entry.path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            entries.push((name.to_owned(), kind(&entry.metadata)));
+        }
+        Ok(entries)
+    }
+
+    /// Changes the length of the file at `path` to `len`. The file open at `path` is closed while its length is
+    /// changed, since changing it may open the file, and is reopened after.
+    fn truncate(&mut self, path: &str, len: u64) -> crate::Result<()> {
+        let open = self
+            .open_paths
+            .get(path)
+            .and_then(|number| self.files.get_mut(number));
+        let Some(open) = open else {
+            return util::set_len(&self.fs, path, len);
+        };
+
+        open.file = None;
+        let result = util::set_len(&self.fs, path, len);
+        open.file = self.fs.open_file_options(path, &reopen_options(open)).ok();
+        result
+    }
+
+    /// Returns the attributes of `node`, whose metadata is `metadata`.
+    fn attr(&self, node: u64, metadata: &Metadata) -> FileAttr {
+        let (default_mode, links) = match metadata.file_type {
+            FileType::Directory => (0o755, 2),
+            _ => (0o644, 1),
+        };
+        let modified = metadata.modified.unwrap_or(UNIX_EPOCH);
+        FileAttr {
+            ino: INodeNo(node),
+            size: metadata.len,
+            blocks: metadata.len.div_ceil(512),
+            atime: modified,
+            mtime: modified,
+            ctime: modified,
+            crtime: modified,
+            kind: kind(metadata),
+            perm: (metadata.mode.unwrap_or(default_mode) & 0o7777) as u16,
+            nlink: links,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: BLOCK_SIZE,
+            flags: 0,
+        }
+    }
+}
+
+impl<FS: FileSystem + Send + 'static> fuser::Filesystem for Fuse<FS> {
+    fn init(&mut self, _req: &Request, config: &mut KernelConfig) -> io::Result<()> {
+        // truncation is passed to `open`, which saves rewriting files that are about to be overwritten
+        let _ = config.add_capabilities(InitFlags::FUSE_ATOMIC_O_TRUNC);
+        Ok(())
+    }
+
+    fn destroy(&mut self) {
+        self.stop();
+    }
+
+    fn lookup(&self, _req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEntry) {
+        let name = name.to_owned();
+        self.call(move |session| match session.lookup(parent.0, &name) {
+            Ok(attr) => reply.entry(&TTL, &attr, Generation(0)),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn forget(&self, _req: &Request, ino: INodeNo, nlookup: u64) {
+        self.call(move |session| session.forget(ino.0, nlookup));
+    }
+
+    fn getattr(&self, _req: &Request, ino: INodeNo, fh: Option<FileHandle>, reply: ReplyAttr) {
+        self.call(
+            move |session| match session.getattr(ino.0, fh.map(|fh| fh.0)) {
+                Ok(attr) => reply.attr(&TTL, &attr),
+                Err(errno) => reply.error(errno),
+            },
+        );
+    }
+
+    fn setattr(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<FileHandle>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<BsdFileFlags>,
+        reply: ReplyAttr,
+    ) {
+        self.call(move |session| match session.setattr(ino.0, size) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn mkdir(
+        &self,
+        _req: &Request,
+        parent: INodeNo,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let name = name.to_owned();
+        self.call(move |session| match session.mkdir(parent.0, &name, mode) {
+            Ok(attr) => reply.entry(&TTL, &attr, Generation(0)),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn unlink(&self, _req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEmpty) {
+        let name = name.to_owned();
+        self.call(move |session| empty(reply, session.unlink(parent.0, &name)));
+    }
+
+    fn rmdir(&self, _req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEmpty) {
+        let name = name.to_owned();
+        self.call(move |session| empty(reply, session.rmdir(parent.0, &name)));
+    }
+
+    fn rename(
+        &self,
+        _req: &Request,
+        parent: INodeNo,
+        name: &OsStr,
+        newparent: INodeNo,
+        newname: &OsStr,
+        flags: RenameFlags,
+        reply: ReplyEmpty,
+    ) {
+        let (name, newname) = (name.to_owned(), newname.to_owned());
+        self.call(move |session| {
+            let result = session.rename(parent.0, &name, newparent.0, &newname, flags);
+            empty(reply, result);
+        });
+    }
+
+    fn open(&self, _req: &Request, ino: INodeNo, flags: OpenFlags, reply: ReplyOpen) {
+        self.call(move |session| match session.open(ino.0, flags.0) {
+            Ok(handle) => reply.opened(FileHandle(handle), FopenFlags::empty()),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn read(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        fh: FileHandle,
+        offset: u64,
+        size: u32,
+        _flags: OpenFlags,
+        _lock_owner: Option<LockOwner>,
+        reply: ReplyData,
+    ) {
+        self.call(move |session| match session.read(fh.0, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn write(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        fh: FileHandle,
+        offset: u64,
+        data: &[u8],
+        _write_flags: WriteFlags,
+        _flags: OpenFlags,
+        _lock_owner: Option<LockOwner>,
+        reply: ReplyWrite,
+    ) {
+        let data = data.to_vec();
+        self.call(move |session| match session.write(fh.0, offset, &data) {
+            Ok(written) => reply.written(written),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn flush(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        fh: FileHandle,
+        _lock_owner: LockOwner,
+        reply: ReplyEmpty,
+    ) {
+        self.call(move |session| empty(reply, session.flush(fh.0)));
+    }
+
+    fn release(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        fh: FileHandle,
+        _flags: OpenFlags,
+        _lock_owner: Option<LockOwner>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.call(move |session| empty(reply, session.release(fh.0)));
+    }
+
+    fn fsync(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        fh: FileHandle,
+        _datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.call(move |session| empty(reply, session.flush(fh.0)));
+    }
+
+    fn opendir(&self, _req: &Request, ino: INodeNo, _flags: OpenFlags, reply: ReplyOpen) {
+        self.call(move |session| match session.opendir(ino.0) {
+            Ok(handle) => reply.opened(FileHandle(handle), FopenFlags::empty()),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn readdir(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        fh: FileHandle,
+        offset: u64,
+        mut reply: ReplyDirectory,
+    ) {
+        self.call(move |session| match session.readdir(ino.0, fh.0, offset) {
+            Ok(entries) => {
+                for (inode, offset, kind, name) in entries {
+                    if reply.add(INodeNo(inode), offset, kind, name) {
+                        break;
+                    }
+                }
+                reply.ok()
+            }
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn releasedir(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        fh: FileHandle,
+        _flags: OpenFlags,
+        reply: ReplyEmpty,
+    ) {
+        self.call(move |session| empty(reply, session.release(fh.0)));
+    }
+
+    fn fsyncdir(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        _fh: FileHandle,
+        _datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        reply.ok();
+    }
+
+    fn statfs(&self, _req: &Request, _ino: INodeNo, reply: ReplyStatfs) {
+        self.call(move |session| {
+            let stats = session.statfs();
+            // the numbers of inodes and free inodes are unknown
+            reply.statfs(
+                stats.blocks,
+                stats.free,
+                stats.available,
+                0,
+                0,
+                stats.block_size,
+                255,
+                stats.block_size,
+            );
+        });
+    }
+
+    fn create(
+        &self,
+        _req: &Request,
+        parent: INodeNo,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let name = name.to_owned();
+        self.call(
+            move |session| match session.create(parent.0, &name, mode, flags) {
+                Ok((attr, handle)) => reply.created(
+                    &TTL,
+                    &attr,
+                    Generation(0),
+                    FileHandle(handle),
+                    FopenFlags::empty(),
+                ),
+                Err(errno) => reply.error(errno),
+            },
+        );
+    }
+}
+
+/// Replies to a request without a body.
+///
+/// # Arguments
+/// `reply`: The reply.  
+/// `result`: The result of the request.  
+fn empty(reply: ReplyEmpty, result: Result<(), Errno>) {
+    match result {
+        Ok(()) => reply.ok(),
+        Err(errno) => reply.error(errno),
+    }
+}
+
+/// Returns the options of opening a file with the Linux open flags `flags`.
+fn open_options(flags: i32) -> OpenOptions {
+    let (read, write) = match flags & libc::O_ACCMODE {
+        libc::O_WRONLY => (false, true),
+        libc::O_RDWR => (true, true),
+        _ => (true, false),
+    };
+    // appends are positioned by the kernel, so the file is only opened for writing
+    OpenOptions {
+        read,
+        write,
+        // the kernel leaves truncating read-only opens unspecified, and they're invalid here
+        truncate: write && flags & libc::O_TRUNC != 0,
+        ..OpenOptions::default()
+    }
+}
+
+/// Returns the options of reopening `open` without truncating it.
+fn reopen_options(open: &OpenFile) -> OpenOptions {
+    OpenOptions {
+        read: open.read,
+        write: open.write,
+        ..OpenOptions::default()
+    }
+}
+
+/// Returns the path that `path` is moved to by renaming `from` to `to`, or `None` if it isn't moved.
+fn moved_path(path: &str, from: &str, to: &str) -> Option<String> {
+    match path.strip_prefix(from)? {
+        "" => Some(to.to_owned()),
+        rest if rest.starts_with('/') => Some(format!("{to}{rest}")),
+        _ => None,
+    }
+}
+
+/// Returns the kind of the entry whose metadata is `metadata`.
+fn kind(metadata: &Metadata) -> fuser::FileType {
+    match metadata.file_type {
+        FileType::Directory => fuser::FileType::Directory,
+        _ => fuser::FileType::RegularFile,
+    }
+}
+
+/// Returns the path of `name` in the directory at `parent`.
+fn join(parent: &str, name: &str) -> String {
+    match parent {
+        "" => name.to_owned(),
+        parent => format!("{parent}/{name}"),
+    }
+}
+
+/// Returns the inode number listed for the entry at `path`, which is stable for the path.
+fn inode(path: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Returns the error number of `err`.
+fn errno(err: io::Error) -> Errno {
+    if let Some(errno) = err.raw_os_error() {
+        return Errno::from_i32(errno);
+    }
+    match err.kind() {
+        ErrorKind::NotFound => Errno::ENOENT,
+        ErrorKind::PermissionDenied => Errno::EACCES,
+        ErrorKind::AlreadyExists => Errno::EEXIST,
+        ErrorKind::InvalidInput => Errno::EINVAL,
+        ErrorKind::Unsupported => Errno::ENOTSUP,
+        ErrorKind::DirectoryNotEmpty => Errno::ENOTEMPTY,
+        ErrorKind::IsADirectory => Errno::EISDIR,
+        ErrorKind::NotADirectory => Errno::ENOTDIR,
+        ErrorKind::ReadOnlyFilesystem => Errno::EROFS,
+        ErrorKind::StorageFull => Errno::ENOSPC,
+        ErrorKind::QuotaExceeded => Errno::EDQUOT,
+        ErrorKind::CrossesDevices => Errno::EXDEV,
+        _ => Errno::EIO,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::FileSystemStats;
+    use crate::fuse::{spawn_mount, Fuse, Session, Stats, BLOCK_SIZE};
+    use crate::memory_fs::MemoryFS;
+    use crate::{FileSystem, MockFileSystem};
+    use fuser::{Errno, FileAttr, FileType, INodeNo, RenameFlags};
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::io::Write;
+    use std::sync::mpsc;
+
+    const ROOT: u64 = INodeNo::ROOT.0;
+    /// A node and a handle that were never handed out.
+    const STALE: u64 = 9999;
+
+    fn memory_fs() -> MemoryFS {
+        let fs = MemoryFS::default();
+        fs.create_dir("dir").unwrap();
+        write!(fs.create_file("dir/file").unwrap(), "hello world").unwrap();
+        fs
+    }
+
+    fn name(name: &str) -> &OsStr {
+        OsStr::new(name)
+    }
+
+    /// Looks up `dir` and `dir/file`, returning their nodes.
+    fn lookup_file(session: &mut Session<MemoryFS>) -> (u64, u64) {
+        let dir = session.lookup(ROOT, name("dir")).unwrap().ino.0;
+        let file = session.lookup(dir, name("file")).unwrap().ino.0;
+        (dir, file)
+    }
+
+    fn read_all(session: &mut Session<MemoryFS>, handle: u64) -> Result<Vec<u8>, Errno> {
+        session.read(handle, 0, 4096)
+    }
+
+    #[test]
+    fn lookup() {
+        let mut session = Session::new(memory_fs());
+        let dir: FileAttr = session.lookup(ROOT, name("dir")).unwrap();
+        assert_eq!(
+            (dir.kind, dir.perm, dir.nlink),
+            (FileType::Directory, 0o755, 2)
+        );
+        let file = session.lookup(dir.ino.0, name("file")).unwrap();
+        assert_eq!(
+            (file.kind, file.perm, file.size, file.blocks),
+            (FileType::RegularFile, 0o644, 11, 1)
+        );
+        assert_ne!(file.ino, dir.ino);
+
+        // the same path is the same node
+        assert_eq!(
+            session.lookup(dir.ino.0, name("file")).unwrap().ino,
+            file.ino
+        );
+        assert_eq!(session.nodes[&file.ino.0].lookups, 2);
+
+        assert_eq!(
+            session.lookup(dir.ino.0, name("missing")).unwrap_err(),
+            Errno::ENOENT
+        );
+        for invalid in ["", ".", "..", "a/b"] {
+            assert_eq!(
+                session.lookup(ROOT, name(invalid)).unwrap_err(),
+                Errno::EINVAL
+            );
+        }
+        // a parent the kernel never looked up doesn't exist
+        assert_eq!(
+            session.lookup(STALE, name("file")).unwrap_err(),
+            Errno::ENOENT
+        );
+    }
+
+    #[test]
+    fn forget() {
+        let mut session = Session::new(memory_fs());
+        let (_, file) = lookup_file(&mut session);
+        session.lookup(ROOT, name("dir")).unwrap();
+
+        // nodes are dropped once every lookup is forgotten
+        let dir = session.lookup(ROOT, name("dir")).unwrap().ino.0;
+        session.forget(dir, 1);
+        assert!(session.getattr(dir, None).is_ok());
+        session.forget(dir, 2);
+        assert_eq!(session.getattr(dir, None).unwrap_err(), Errno::ENOENT);
+        assert!(session.getattr(file, None).is_ok());
+        session.forget(file, 5);
+        assert_eq!(session.getattr(file, None).unwrap_err(), Errno::ENOENT);
+
+        // the root and unknown nodes are never forgotten
+        session.forget(ROOT, 1);
+        session.forget(STALE, 1);
+        assert_eq!(
+            session.getattr(ROOT, None).unwrap().kind,
+            FileType::Directory
+        );
+
+        // a forgotten path gets a new node
+        let dir = session.lookup(ROOT, name("dir")).unwrap().ino.0;
+        assert_eq!(session.nodes[&dir].lookups, 1);
+    }
+
+    #[test]
+    fn getattr() {
+        let mut session = Session::new(memory_fs());
+        let (_, file) = lookup_file(&mut session);
+        assert_eq!(session.getattr(file, None).unwrap().size, 11);
+        assert_eq!(session.getattr(file, None).unwrap().ino.0, file);
+
+        // an open file is asked for its attributes
+        let handle = session.open(file, libc::O_RDWR).unwrap();
+        session.write(handle, 11, b"!").unwrap();
+        assert_eq!(session.getattr(file, Some(handle)).unwrap().size, 12);
+        // a stale handle falls back to the path
+        assert_eq!(session.getattr(file, Some(STALE)).unwrap().size, 12);
+
+        assert_eq!(session.getattr(STALE, None).unwrap_err(), Errno::ENOENT);
+    }
+
+    #[test]
+    fn setattr() {
+        let mut session = Session::new(memory_fs());
+        let (_, file) = lookup_file(&mut session);
+        assert_eq!(session.setattr(file, None).unwrap().size, 11);
+
+        // truncate the file while it's open
+        let handle = session.open(file, libc::O_RDONLY).unwrap();
+        assert_eq!(session.setattr(file, Some(5)).unwrap().size, 5);
+        assert_eq!(read_all(&mut session, handle).unwrap(), b"hello");
+        assert_eq!(session.setattr(file, Some(7)).unwrap().size, 7);
+        assert_eq!(read_all(&mut session, handle).unwrap(), b"hello\0\0");
+        session.release(handle).unwrap();
+        assert!(session.files.is_empty() && session.open_paths.is_empty());
+
+        assert_eq!(session.setattr(STALE, Some(0)).unwrap_err(), Errno::ENOENT);
+    }
+
+    #[test]
+    fn mkdir() {
+        let mut session = Session::new(memory_fs());
+        let dir = session.lookup(ROOT, name("dir")).unwrap().ino.0;
+        let sub = session.mkdir(dir, name("sub"), 0o40700).unwrap();
+        assert_eq!(sub.kind, FileType::Directory);
+        assert!(session.fs.metadata("dir/sub").unwrap().is_directory());
+        assert_eq!(session.path(sub.ino.0), Ok("dir/sub"));
+
+        assert_eq!(
+            session.mkdir(dir, name("sub"), 0o755).unwrap_err(),
+            Errno::EEXIST
+        );
+        assert_eq!(
+            session.mkdir(STALE, name("sub"), 0o755).unwrap_err(),
+            Errno::ENOENT
+        );
+    }
+
+    #[test]
+    fn unlink() {
+        let mut session = Session::new(memory_fs());
+        let (dir, file) = lookup_file(&mut session);
+        let handle = session.open(file, libc::O_RDONLY).unwrap();
+        session.unlink(dir, name("file")).unwrap();
+        assert!(!session.fs.exists("dir/file").unwrap());
+
+        // the open file is still readable, but no longer found by its path
+        assert_eq!(read_all(&mut session, handle).unwrap(), b"hello world");
+        assert!(session.open_paths.is_empty());
+        session.release(handle).unwrap();
+        assert!(session.files.is_empty());
+
+        assert_eq!(
+            session.unlink(dir, name("file")).unwrap_err(),
+            Errno::ENOENT
+        );
+        assert_eq!(
+            session.unlink(STALE, name("file")).unwrap_err(),
+            Errno::ENOENT
+        );
+    }
+
+    #[test]
+    fn rmdir() {
+        let mut session = Session::new(memory_fs());
+        let dir = session.lookup(ROOT, name("dir")).unwrap().ino.0;
+        assert_eq!(
+            session.rmdir(ROOT, name("dir")).unwrap_err(),
+            Errno::ENOTEMPTY
+        );
+        session.mkdir(dir, name("sub"), 0o755).unwrap();
+        session.rmdir(dir, name("sub")).unwrap();
+        assert!(!session.fs.exists("dir/sub").unwrap());
+        assert_eq!(session.rmdir(dir, name("sub")).unwrap_err(), Errno::ENOENT);
+        assert_eq!(
+            session.rmdir(STALE, name("sub")).unwrap_err(),
+            Errno::ENOENT
+        );
+    }
+
+    #[test]
+    fn rename() {
+        let mut session = Session::new(memory_fs());
+        let (dir, file) = lookup_file(&mut session);
+        let handle = session.open(file, libc::O_WRONLY).unwrap();
+
+        // the node and the open file move with the entry
+        session
+            .rename(dir, name("file"), ROOT, name("moved"), RenameFlags::empty())
+            .unwrap();
+        assert_eq!(session.path(file), Ok("moved"));
+        assert_eq!(session.lookup(ROOT, name("moved")).unwrap().ino.0, file);
+        session.write(handle, 0, b"HELLO").unwrap();
+        session.release(handle).unwrap();
+        assert_eq!(
+            session
+                .fs
+                .open_file("moved")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "HELLO world"
+        );
+
+        // a failed rename moves nothing
+        session
+            .rename(ROOT, name("moved"), dir, name("file"), RenameFlags::empty())
+            .unwrap();
+        assert_eq!(
+            session
+                .rename(
+                    ROOT,
+                    name("dir"),
+                    ROOT,
+                    name("renamed"),
+                    RenameFlags::empty(),
+                )
+                .unwrap_err(),
+            Errno::EOPNOTSUPP
+        );
+        assert_eq!(session.path(dir), Ok("dir"));
+        assert_eq!(session.path(file), Ok("dir/file"));
+
+        assert_eq!(
+            session
+                .rename(
+                    dir,
+                    name("file"),
+                    ROOT,
+                    name("new"),
+                    RenameFlags::RENAME_NOREPLACE
+                )
+                .unwrap_err(),
+            Errno::EINVAL
+        );
+        assert_eq!(
+            session
+                .rename(
+                    dir,
+                    name("missing"),
+                    ROOT,
+                    name("new"),
+                    RenameFlags::empty()
+                )
+                .unwrap_err(),
+            Errno::ENOENT
+        );
+        assert_eq!(
+            session
+                .rename(dir, name("file"), STALE, name("new"), RenameFlags::empty())
+                .unwrap_err(),
+            Errno::ENOENT
+        );
+    }
+
+    #[test]
+    fn moved_path() {
+        // entries under a renamed directory move with it, but its siblings don't
+        assert_eq!(
+            super::moved_path("dir", "dir", "new"),
+            Some("new".to_owned())
+        );
+        assert_eq!(
+            super::moved_path("dir/sub/file", "dir", "new"),
+            Some("new/sub/file".to_owned())
+        );
+        assert_eq!(super::moved_path("directory", "dir", "new"), None);
+        assert_eq!(super::moved_path("other/dir", "dir", "new"), None);
+    }
+
+    #[test]
+    fn open() {
+        let mut session = Session::new(memory_fs());
+        let (dir, file) = lookup_file(&mut session);
+
+        // handles of the same path share the open file
+        let reader = session.open(file, libc::O_RDONLY).unwrap();
+        let writer = session.open(file, libc::O_WRONLY).unwrap();
+        assert_eq!(session.files.len(), 1);
+        session.write(writer, 0, b"J").unwrap();
+        assert_eq!(read_all(&mut session, reader).unwrap(), b"Jello world");
+
+        // truncating reopens the shared file
+        let truncated = session.open(file, libc::O_WRONLY | libc::O_TRUNC).unwrap();
+        assert_eq!(read_all(&mut session, reader).unwrap(), b"");
+        for handle in [reader, writer, truncated] {
+            session.release(handle).unwrap();
+        }
+
+        assert_eq!(
+            session.open(dir, libc::O_RDONLY).unwrap_err(),
+            Errno::EISDIR
+        );
+        assert_eq!(
+            session.open(STALE, libc::O_RDONLY).unwrap_err(),
+            Errno::ENOENT
+        );
+    }
+
+    #[test]
+    fn create() {
+        let mut session = Session::new(memory_fs());
+        let dir = session.lookup(ROOT, name("dir")).unwrap().ino.0;
+        let (attr, handle) = session
+            .create(dir, name("new"), 0o100600, libc::O_RDONLY | libc::O_CREAT)
+            .unwrap();
+        assert_eq!((attr.kind, attr.size), (FileType::RegularFile, 0));
+        assert_eq!(session.path(attr.ino.0), Ok("dir/new"));
+        session.write(handle, 0, b"new").unwrap();
+
+        // creating it again opens it, unless it's exclusive
+        let (_, second) = session
+            .create(dir, name("new"), 0o600, libc::O_RDWR | libc::O_CREAT)
+            .unwrap();
+        assert_eq!(read_all(&mut session, second).unwrap(), b"new");
+        assert_eq!(
+            session
+                .create(
+                    dir,
+                    name("new"),
+                    0o600,
+                    libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL
+                )
+                .unwrap_err(),
+            Errno::EEXIST
+        );
+        assert_eq!(
+            session
+                .create(STALE, name("new"), 0o600, libc::O_WRONLY | libc::O_CREAT)
+                .unwrap_err(),
+            Errno::ENOENT
+        );
+    }
+
+    #[test]
+    fn read() {
+        let mut session = Session::new(memory_fs());
+        let (dir, file) = lookup_file(&mut session);
+        let handle = session.open(file, libc::O_RDONLY).unwrap();
+        assert_eq!(session.read(handle, 6, 100).unwrap(), b"world");
+        assert_eq!(session.read(handle, 0, 5).unwrap(), b"hello");
+        assert_eq!(session.read(handle, 100, 5).unwrap(), b"");
+
+        // handles that were released, never handed out or are of directories aren't files
+        session.release(handle).unwrap();
+        let listing = session.opendir(dir).unwrap();
+        for handle in [handle, STALE, listing] {
+            assert_eq!(session.read(handle, 0, 5).unwrap_err(), Errno::EBADF);
+        }
+    }
+
+    #[test]
+    fn write() {
+        let mut session = Session::new(memory_fs());
+        let (_, file) = lookup_file(&mut session);
+        let handle = session.open(file, libc::O_RDWR).unwrap();
+        assert_eq!(session.write(handle, 6, b"there").unwrap(), 5);
+        assert_eq!(session.write(handle, 11, b"!").unwrap(), 1);
+        assert_eq!(read_all(&mut session, handle).unwrap(), b"hello there!");
+        session.release(handle).unwrap();
+
+        // writing through a read-only handle fails, and a stale handle isn't a file
+        let handle = session.open(file, libc::O_RDONLY).unwrap();
+        assert!(session.write(handle, 0, b"x").is_err());
+        assert_eq!(session.write(STALE, 0, b"x").unwrap_err(), Errno::EBADF);
+    }
+
+    #[test]
+    fn flush() {
+        let mut session = Session::new(memory_fs());
+        let (_, file) = lookup_file(&mut session);
+        let handle = session.open(file, libc::O_WRONLY).unwrap();
+        session.flush(handle).unwrap();
+        session.release(handle).unwrap();
+        assert_eq!(session.flush(handle).unwrap_err(), Errno::EBADF);
+    }
+
+    #[test]
+    fn release() {
+        let mut session = Session::new(memory_fs());
+        let (dir, file) = lookup_file(&mut session);
+        let listing = session.opendir(dir).unwrap();
+        let first = session.open(file, libc::O_RDONLY).unwrap();
+        let second = session.open(file, libc::O_RDONLY).unwrap();
+
+        // the shared file is closed once every handle is released
+        session.release(first).unwrap();
+        assert_eq!(session.files.len(), 1);
+        session.release(second).unwrap();
+        assert!(session.files.is_empty() && session.open_paths.is_empty());
+        session.release(listing).unwrap();
+        assert!(session.handles.is_empty());
+
+        for handle in [first, STALE] {
+            assert_eq!(session.release(handle).unwrap_err(), Errno::EBADF);
+        }
+    }
+
+    #[test]
+    fn readdir() {
+        let mut session = Session::new(memory_fs());
+        let (dir, file) = lookup_file(&mut session);
+        session.mkdir(dir, name("sub"), 0o755).unwrap();
+        let handle = session.opendir(dir).unwrap();
+
+        let mut entries = session.readdir(dir, handle, 0).unwrap();
+        entries.sort_by_key(|(_, offset, _, _)| *offset);
+        let listed = entries
+            .iter()
+            .map(|(_, offset, kind, name)| (*offset, *kind, name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            &listed[..2],
+            [
+                (1, FileType::Directory, "."),
+                (2, FileType::Directory, "..")
+            ]
+        );
+        let mut children = listed[2..]
+            .iter()
+            .map(|(_, kind, name)| (*kind, *name))
+            .collect::<Vec<_>>();
+        children.sort_by_key(|(_, name)| *name);
+        assert_eq!(
+            children,
+            [
+                (FileType::RegularFile, "file"),
+                (FileType::Directory, "sub")
+            ]
+        );
+
+        // the listing resumes after the offset, and isn't changed by later changes
+        assert_eq!(session.readdir(dir, handle, 2).unwrap()[..], entries[2..]);
+        assert!(session.readdir(dir, handle, 4).unwrap().is_empty());
+        session.rmdir(dir, name("sub")).unwrap();
+        assert_eq!(session.readdir(dir, handle, 0).unwrap().len(), 4);
+
+        let reader = session.open(file, libc::O_RDONLY).unwrap();
+        for stale in [reader, STALE] {
+            assert_eq!(session.readdir(dir, stale, 0).unwrap_err(), Errno::EBADF);
+        }
+        assert_eq!(session.opendir(file).unwrap_err(), Errno::ENOTDIR);
+        assert_eq!(session.opendir(STALE).unwrap_err(), Errno::ENOENT);
+    }
+
+    #[test]
+    fn statfs() {
+        // filesystems without stats report none
+        let session = Session::new(memory_fs());
+        assert_eq!(
+            session.statfs(),
+            Stats {
+                blocks: 0,
+                free: 0,
+                available: 0,
+                block_size: BLOCK_SIZE
+            }
+        );
+
+        let mut fs = MockFileSystem::new();
+        fs.expect_stats().returning(|| {
+            Ok(FileSystemStats {
+                total_space: 1 << 30,
+                free_space: 1 << 29,
+                available_space: 1 << 28,
+                block_size: 1 << 16,
+            })
+        });
+        assert_eq!(
+            Session::new(fs).statfs(),
+            Stats {
+                blocks: 1 << 14,
+                free: 1 << 13,
+                available: 1 << 12,
+                block_size: 1 << 16
+            }
+        );
+    }
+
+    #[test]
+    fn worker() {
+        // requests are handled in order by the worker, which closes the open files when it stops
+        let mut fuse = Fuse::new(memory_fs()).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        fuse.call(|session| {
+            let (_, file) = lookup_file(session);
+            let handle = session.open(file, libc::O_WRONLY | libc::O_TRUNC).unwrap();
+            session.write(handle, 0, b"written").unwrap();
+        });
+        fuse.call(move |session| {
+            sender.send(session.files.len()).unwrap();
+        });
+        assert_eq!(receiver.recv().unwrap(), 1);
+        fuse.stop();
+        assert!(fuse.worker.is_none());
+
+        // requests made once it has stopped are dropped
+        fuse.call(|_| panic!("the worker is stopped"));
+    }
+
+    #[test]
+    #[ignore = "mounting requires /dev/fuse and permission to mount"]
+    fn mount() {
+        let root = std::env::temp_dir().join(format!("fuse-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mount = spawn_mount(memory_fs(), &root).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(root.join("dir/file")).unwrap(),
+            "hello world"
+        );
+        fs::write(root.join("dir/new"), "written").unwrap();
+        assert_eq!(fs::read_to_string(root.join("dir/new")).unwrap(), "written");
+        fs::rename(root.join("dir/new"), root.join("renamed")).unwrap();
+        let mut names = fs::read_dir(root.join("dir"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["file"]);
+
+        mount.unmount().unwrap();
+        assert!(!root.join("dir").exists());
+        fs::remove_dir(root).unwrap();
+    }
+}
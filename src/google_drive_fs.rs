@@ -0,0 +1,760 @@
+use crate::cloud_drive::{parse_time, Body, Client, DriveFile, TokenProvider, Upload};
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::{already_exists, component_iter, invalid_input, invalid_path, not_found};
+use crate::FileSystem;
+use serde_json::{json, Value};
+use std::io;
+use std::io::{Cursor, ErrorKind, Seek, SeekFrom};
+use std::sync::Arc;
+
+/// The URL of Google's APIs.
+const DEFAULT_ENDPOINT: &str = "https://www.googleapis.com";
+/// The MIME type of folders.
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+/// The prefix of the MIME types of Google's own documents, which have no contents and can only be exported.
+const GOOGLE_APPS_MIME_TYPE: &str = "application/vnd.google-apps.";
+/// The fields of a file that are requested.
+const FILE_FIELDS: &str = "id,name,mimeType,size,modifiedTime,createdTime";
+
+/// A read-write filesystem on a Google Drive, through the Drive v3 API. Folders are directories, and paths are
+/// resolved by looking up each name in turn. Drive allows several files with the same name in a folder, in which case
+/// only one of them is visible at that path.
+///
+/// Files are downloaded when they're opened, and uploaded when they're flushed or dropped. Google's own documents,
+/// such as Docs and Sheets, appear as empty files that can't be opened. Deleted files are removed permanently
+/// rather than moved to the trash.
+///
+/// Errors uploading a file that's dropped are lost, so files should be flushed before they're dropped to see them.
+pub struct GoogleDriveFS {
+    client: Client,
+    endpoint: String,
+    root: String,
+}
+
+/// A file or folder on the drive.
+struct Item {
+    id: String,
+    name: String,
+    metadata: Metadata,
+    /// True if the item is one of Google's own documents.
+    native: bool,
+}
+
+impl GoogleDriveFS {
+    /// Creates a filesystem on the drive of the user the tokens are issued to.
+    ///
+    /// # Arguments
+    /// `tokens`: The provider of access tokens with a Drive scope.  
+    pub fn new<T: TokenProvider + 'static>(tokens: T) -> Self {
+        Self::with_endpoint(DEFAULT_ENDPOINT, tokens)
+    }
+
+    /// Creates a filesystem on a drive served at `endpoint` instead of by Google.
+    ///
+    /// # Arguments
+    /// `endpoint`: The URL the Drive API is served at, without the `/drive/v3` path.  
+    /// `tokens`: The provider of access tokens.  
+    pub fn with_endpoint<T: TokenProvider + 'static>(endpoint: &str, tokens: T) -> Self {
+        Self {
+            client: Client {
+                agent: ureq::Agent::new(),
+                tokens: Arc::new(tokens),
+            },
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            root: "root".to_owned(),
+        }
+    }
+
+    /// # Arguments
+    /// `agent`: The agent used to issue requests.  
+    pub fn agent(mut self, agent: ureq::Agent) -> Self {
+        self.client.agent = agent;
+        self
+    }
+
+    /// # Arguments
+    /// `folder_id`: The identifier of the folder the root of the filesystem maps to, instead of the root of the
+    /// drive.  
+    pub fn root_folder(mut self, folder_id: &str) -> Self {
+        self.root = folder_id.to_owned();
+        self
+    }
+
+    /// Returns the URL of the metadata of a file, or of the collection of files if `id` is empty.
+    ///
+    /// # Arguments
+    /// `id`: The identifier of the file.  
+    fn files_url(&self, id: &str) -> String {
+        match id {
+            "" => format!("{}/drive/v3/files", self.endpoint),
+            id => format!("{}/drive/v3/files/{id}", self.endpoint),
+        }
+    }
+
+    /// Returns the root folder.
+    fn root_item(&self) -> Item {
+        Item {
+            id: self.root.clone(),
+            name: String::new(),
+            metadata: Metadata::directory(),
+            native: false,
+        }
+    }
+
+    /// Lists the files in a folder that match a query.
+    ///
+    /// # Arguments
+    /// `parent`: The identifier of the folder.  
+    /// `name`: The name of the only file to list, if any.  
+    fn list(&self, parent: &str, name: Option<&str>) -> crate::Result<Vec<Item>> {
+        let mut query = format!("'{}' in parents and trashed = false", escape(parent));
+        if let Some(name) = name {
+            query += &format!(" and name = '{}'", escape(name));
+        }
+        let fields = format!("nextPageToken,files({FILE_FIELDS})");
+
+        let mut items = Vec::new();
+        let mut page_token = String::new();
+        loop {
+            let mut params = vec![("q", query.as_str()), ("fields", &fields)];
+            if !page_token.is_empty() {
+                params.push(("pageToken", &page_token));
+            }
+            let page = self
+                .client
+                .call_json("GET", &self.files_url(""), &params, Body::Empty)?;
+
+            items.extend(
+                page["files"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(item),
+            );
+            match page["nextPageToken"].as_str() {
+                Some(next) if name.is_none() => page_token = next.to_owned(),
+                _ => return Ok(items),
+            }
+        }
+    }
+
+    /// Looks up the file at a path.
+    ///
+    /// # Arguments
+    /// `names`: The names of the components of the path.  
+    fn lookup(&self, names: &[&str]) -> crate::Result<Item> {
+        let mut current = self.root_item();
+        for name in names {
+            if !current.metadata.is_directory() {
+                return Err(not_found());
+            }
+            current = self
+                .list(&current.id, Some(name))?
+                .into_iter()
+                .next()
+                .ok_or_else(not_found)?;
+        }
+        Ok(current)
+    }
+
+    /// Looks up the folder a path is in, and returns it with the name of the entry at the path.
+    ///
+    /// # Arguments
+    /// `path`: The virtual path.  
+    fn lookup_parent(&self, path: &str) -> crate::Result<(Item, String)> {
+        let path = normalize_and_relativize(path);
+        let names: Vec<_> = component_iter(&path).collect();
+        let Some((name, parent)) = names.split_last() else {
+            return Err(invalid_path());
+        };
+
+        let parent = self.lookup(parent)?;
+        if !parent.metadata.is_directory() {
+            return Err(not_found());
+        }
+        Ok((parent, name.to_string()))
+    }
+
+    /// Creates a file or folder.
+    ///
+    /// # Arguments
+    /// `parent`: The identifier of the folder to create it in.  
+    /// `name`: The name of the file.  
+    /// `mime_type`: The MIME type of the file, if it's known.  
+    fn create(&self, parent: &str, name: &str, mime_type: Option<&str>) -> crate::Result<String> {
+        let mut metadata = json!({ "name": name, "parents": [parent] });
+        if let Some(mime_type) = mime_type {
+            metadata["mimeType"] = mime_type.into();
+        }
+        let created = self.client.call_json(
+            "POST",
+            &self.files_url(""),
+            &[("fields", "id")],
+            Body::Json(&metadata),
+        )?;
+        created["id"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Missing file ID"))
+    }
+
+    /// Deletes a file or folder permanently.
+    ///
+    /// # Arguments
+    /// `id`: The identifier of the file.  
+    fn delete(&self, id: &str) -> crate::Result<()> {
+        self.client
+            .call("DELETE", &self.files_url(id), &[], Body::Empty)?;
+        Ok(())
+    }
+}
+
+impl FileSystem for GoogleDriveFS {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        // the root always exists
+        if component_iter(&normalize_and_relativize(path))
+            .next()
+            .is_none()
+        {
+            return Err(already_exists());
+        }
+        let (parent, name) = self.lookup_parent(path)?;
+        // the drive would happily create a second folder with the same name
+        if !self.list(&parent.id, Some(&name))?.is_empty() {
+            return Err(already_exists());
+        }
+
+        self.create(&parent.id, &name, Some(FOLDER_MIME_TYPE))?;
+        Ok(())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        let path = normalize_and_relativize(path);
+        let names: Vec<_> = component_iter(&path).collect();
+        Ok(self.lookup(&names)?.metadata)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        let (parent, name) = self.lookup_parent(path)?;
+        let writable = options.writable();
+
+        let (id, contents, modified) = match self.list(&parent.id, Some(&name))?.pop() {
+            Some(_) if options.create_new => return Err(already_exists()),
+            Some(item) => {
+                if item.metadata.is_directory() {
+                    return Err(invalid_input("path is a directory"));
+                }
+                if item.native {
+                    return Err(io::Error::new(
+                        ErrorKind::Unsupported,
+                        "Google documents can only be exported",
+                    ));
+                }
+
+                let contents = if writable && options.truncate {
+                    // the file is truncated from the moment it's opened, even if it's never written
+                    if item.metadata.len() > 0 {
+                        upload_contents(&self.client, &self.endpoint, &item.id, &[])?;
+                    }
+                    Vec::new()
+                } else {
+                    self.client
+                        .download(&self.files_url(&item.id), &[("alt", "media")])?
+                };
+                (item.id, contents, item.metadata.modified)
+            }
+            None if options.create || options.create_new => {
+                (self.create(&parent.id, &name, None)?, Vec::new(), None)
+            }
+            None => return Err(not_found()),
+        };
+
+        let mut contents = Cursor::new(contents);
+        if options.append {
+            contents.seek(SeekFrom::End(0))?;
+        }
+        let upload = writable.then(|| {
+            let (client, endpoint) = (self.client.clone(), self.endpoint.clone());
+            Box::new(move |contents: &[u8]| upload_contents(&client, &endpoint, &id, contents))
+                as Upload
+        });
+
+        Ok(Box::new(DriveFile {
+            contents,
+            modified,
+            upload,
+            append: options.append,
+            dirty: false,
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let directory = normalize_and_relativize(path);
+        let names: Vec<_> = component_iter(&directory).collect();
+        let folder = self.lookup(&names)?;
+        if !folder.metadata.is_directory() {
+            return Err(invalid_input("path is not a directory"));
+        }
+
+        let items = self.list(&folder.id, None)?;
+        Ok(Box::new(items.into_iter().map(move |item| {
+            Ok(DirEntry {
+                path: directory.join(item.name),
+                metadata: item.metadata,
+            })
+        })))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        let (parent, name) = self.lookup_parent(path)?;
+        let folder = self
+            .list(&parent.id, Some(&name))?
+            .pop()
+            .ok_or_else(not_found)?;
+        if !folder.metadata.is_directory() {
+            return Err(invalid_input("path is not a directory"));
+        }
+        // deleting a folder would delete everything in it
+        if !self.list(&folder.id, None)?.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::DirectoryNotEmpty,
+                "Directory not empty",
+            ));
+        }
+
+        self.delete(&folder.id)
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        let (parent, name) = self.lookup_parent(path)?;
+        let file = self
+            .list(&parent.id, Some(&name))?
+            .pop()
+            .ok_or_else(not_found)?;
+        if file.metadata.is_directory() {
+            return Err(invalid_input("path is a directory"));
+        }
+
+        self.delete(&file.id)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        let (from_parent, from_name) = self.lookup_parent(from)?;
+        let item = self
+            .list(&from_parent.id, Some(&from_name))?
+            .pop()
+            .ok_or_else(not_found)?;
+        let (to_parent, to_name) = self.lookup_parent(to)?;
+
+        // an existing file is replaced, as it would be by a local rename
+        if let Some(existing) = self.list(&to_parent.id, Some(&to_name))?.pop() {
+            if existing.id == item.id {
+                return Ok(());
+            }
+            if existing.metadata.is_directory() {
+                return Err(already_exists());
+            }
+            self.delete(&existing.id)?;
+        }
+
+        let mut params = vec![];
+        if to_parent.id != from_parent.id {
+            params.push(("addParents", to_parent.id.as_str()));
+            params.push(("removeParents", from_parent.id.as_str()));
+        }
+        self.client.call(
+            "PATCH",
+            &self.files_url(&item.id),
+            &params,
+            Body::Json(&json!({ "name": to_name })),
+        )?;
+        Ok(())
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        let about = self.client.call_json(
+            "GET",
+            &format!("{}/drive/v3/about", self.endpoint),
+            &[("fields", "storageQuota")],
+            Body::Empty,
+        )?;
+        // quantities are strings, and drives without a limit have none
+        let quantity = |name| about["storageQuota"][name].as_str()?.parse::<u64>().ok();
+        let total_space = quantity("limit").unwrap_or(u64::MAX);
+        let free_space = total_space.saturating_sub(quantity("usage").unwrap_or(0));
+        Ok(FileSystemStats {
+            total_space,
+            free_space,
+            available_space: free_space,
+            block_size: 1,
+        })
+    }
+}
+
+/// Parses a file resource.
+///
+/// # Arguments
+/// `file`: The file resource.  
+fn item(file: &Value) -> Option<Item> {
+    let mime_type = file["mimeType"].as_str().unwrap_or_default();
+    let metadata = if mime_type == FOLDER_MIME_TYPE {
+        Metadata::directory()
+    } else {
+        // sizes are strings, and Google's own documents have none
+        Metadata::file(
+            file["size"]
+                .as_str()
+                .and_then(|size| size.parse().ok())
+                .unwrap_or(0),
+        )
+    };
+
+    Some(Item {
+        id: file["id"].as_str()?.to_owned(),
+        name: file["name"].as_str()?.to_owned(),
+        metadata: Metadata {
+            modified: file["modifiedTime"].as_str().and_then(parse_time),
+            created: file["createdTime"].as_str().and_then(parse_time),
+            ..metadata
+        },
+        native: mime_type.starts_with(GOOGLE_APPS_MIME_TYPE) && mime_type != FOLDER_MIME_TYPE,
+    })
+}
+
+/// Escapes a string in a query.
+///
+/// # Arguments
+/// `value`: The string.  
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+/// Replaces the contents of a file.
+///
+/// # Arguments
+/// `client`: The client.  
+/// `endpoint`: The URL of Google's APIs.  
+/// `id`: The identifier of the file.  
+/// `contents`: The new contents of the file.  
+fn upload_contents(
+    client: &Client,
+    endpoint: &str,
+    id: &str,
+    contents: &[u8],
+) -> crate::Result<()> {
+    client.call(
+        "PATCH",
+        &format!("{endpoint}/upload/drive/v3/files/{id}"),
+        &[("uploadType", "media")],
+        Body::Bytes(contents),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cloud_drive::test::serve;
+    use crate::file::{FileSystemStats, OpenOptions};
+    use crate::google_drive_fs::{escape, GoogleDriveFS, FOLDER_MIME_TYPE};
+    use crate::util::test::{check_open_options, read_directory};
+    use crate::FileSystem;
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+    use std::io::{ErrorKind, Write};
+
+    /// A file on the test server.
+    struct ServedFile {
+        name: String,
+        parent: String,
+        mime_type: String,
+        contents: Vec<u8>,
+    }
+
+    /// Serves a drive holding `files`, which are identifiers, parents, names, and contents or `None` for folders.
+    /// Listings return at most two files per page, and every request must carry the access token `token`.
+    fn google_drive_fs(files: &[(&str, &str, &str, Option<&str>)]) -> GoogleDriveFS {
+        let mut files: BTreeMap<String, ServedFile> = files
+            .iter()
+            .map(|(id, parent, name, contents)| {
+                let file = ServedFile {
+                    name: name.to_string(),
+                    parent: parent.to_string(),
+                    mime_type: match contents {
+                        Some(_) => "text/plain".to_owned(),
+                        None => FOLDER_MIME_TYPE.to_owned(),
+                    },
+                    contents: contents.unwrap_or_default().as_bytes().to_vec(),
+                };
+                (id.to_string(), file)
+            })
+            .collect();
+        let resource = |id: &str, file: &ServedFile| {
+            json!({
+                "id": id,
+                "name": file.name,
+                "mimeType": file.mime_type,
+                "size": file.contents.len().to_string(),
+                "modifiedTime": "2024-01-02T03:04:05.000Z",
+            })
+        };
+
+        let endpoint = serve(move |_, request| {
+            if request.headers.get("authorization").map(String::as_str) != Some("Bearer token") {
+                return ("401 Unauthorized", String::new(), Vec::new());
+            }
+            let ok = |value: Value| ("200 OK", String::new(), value.to_string().into_bytes());
+            let not_found = ("404 Not Found", String::new(), Vec::new());
+
+            let path = request.path.as_str();
+            let method = request.method.as_str();
+            if let Some(id) = path.strip_prefix("/upload/drive/v3/files/") {
+                return match (method, files.get_mut(id)) {
+                    ("PATCH", Some(file)) if request.query["uploadType"] == "media" => {
+                        file.contents = request.body;
+                        ok(resource(id, file))
+                    }
+                    _ => not_found,
+                };
+            }
+            if path == "/drive/v3/about" {
+                let usage: usize = files.values().map(|file| file.contents.len()).sum();
+                return ok(json!({
+                    "storageQuota": { "limit": "1000", "usage": usage.to_string() }
+                }));
+            }
+
+            match (method, path.strip_prefix("/drive/v3/files")) {
+                ("GET", Some("")) => {
+                    // queries look like `'parent' in parents and trashed = false and name = 'name'`
+                    let query = &request.query["q"];
+                    let (parent, rest) = query[1..].split_once("' in parents").unwrap();
+                    let name = rest
+                        .split_once(" and name = '")
+                        .map(|(_, name)| name.strip_suffix('\'').unwrap());
+                    let start = request
+                        .query
+                        .get("pageToken")
+                        .map_or(0, |token| token.parse().unwrap());
+                    let matches: Vec<_> = files
+                        .iter()
+                        .filter(|(_, file)| {
+                            escape(&file.parent) == parent
+                                && name.is_none_or(|name| escape(&file.name) == name)
+                        })
+                        .collect();
+
+                    let mut page = json!({
+                        "files": matches
+                            .iter()
+                            .skip(start)
+                            .take(2)
+                            .map(|(id, file)| resource(id, file))
+                            .collect::<Vec<_>>(),
+                    });
+                    if matches.len() > start + 2 {
+                        page["nextPageToken"] = (start + 2).to_string().into();
+                    }
+                    ok(page)
+                }
+                ("POST", Some("")) => {
+                    let metadata = request.json();
+                    let parent = metadata["parents"][0].as_str().unwrap().to_owned();
+                    if parent != "root" && !files.contains_key(&parent) {
+                        return not_found;
+                    }
+                    let id = format!("id{}", files.len());
+                    let file = ServedFile {
+                        name: metadata["name"].as_str().unwrap().to_owned(),
+                        parent,
+                        mime_type: metadata["mimeType"]
+                            .as_str()
+                            .unwrap_or("application/octet-stream")
+                            .to_owned(),
+                        contents: Vec::new(),
+                    };
+                    files.insert(id.clone(), file);
+                    ok(json!({ "id": id }))
+                }
+                (method, Some(id)) => {
+                    let id = id.trim_start_matches('/');
+                    let Some(file) = files.get_mut(id) else {
+                        return not_found;
+                    };
+                    match method {
+                        "GET" if request.query.get("alt").map(String::as_str) == Some("media") => {
+                            ("200 OK", String::new(), file.contents.clone())
+                        }
+                        "PATCH" => {
+                            if let Some(name) = request.json()["name"].as_str() {
+                                file.name = name.to_owned();
+                            }
+                            if let Some(add_parents) = request.query.get("addParents") {
+                                assert_eq!(file.parent, request.query["removeParents"]);
+                                file.parent = add_parents.clone();
+                            }
+                            ok(resource(id, file))
+                        }
+                        "DELETE" => {
+                            files.remove(id);
+                            ("204 No Content", String::new(), Vec::new())
+                        }
+                        _ => not_found,
+                    }
+                }
+                _ => not_found,
+            }
+        });
+
+        GoogleDriveFS::with_endpoint(&endpoint, "token".to_owned())
+    }
+
+    #[test]
+    fn read() {
+        let fs = google_drive_fs(&[
+            ("readme", "root", "readme", Some("readme")),
+            ("docs", "root", "docs", None),
+            ("a", "docs", "it's", Some("a")),
+            ("b", "docs", "b", Some("bb")),
+            ("c", "docs", "c", Some("ccc")),
+            ("nested", "docs", "nested", None),
+        ]);
+
+        assert_eq!(
+            fs.open_file("docs/it's")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "a"
+        );
+        let metadata = fs.metadata("docs/c").unwrap();
+        assert_eq!(metadata.len(), 3);
+        assert!(metadata.modified.is_some());
+        assert!(fs.metadata("").unwrap().is_directory());
+        assert!(fs.metadata("docs/nested").unwrap().is_directory());
+        assert_eq!(
+            fs.metadata("readme/file").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            fs.open_file("missing").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        // the listing spans several pages
+        let docs = read_directory(&fs, "docs");
+        itertools::assert_equal(
+            docs.keys(),
+            vec!["docs/b", "docs/c", "docs/it's", "docs/nested"],
+        );
+        assert!(docs["docs/nested"].is_directory());
+        itertools::assert_equal(read_directory(&fs, "").keys(), vec!["docs", "readme"]);
+
+        // only the files in the root folder are visible
+        let docs = google_drive_fs(&[("docs", "root", "docs", None), ("b", "docs", "b", Some(""))])
+            .root_folder("docs");
+        itertools::assert_equal(read_directory(&docs, "/").keys(), vec!["b"]);
+
+        // requests without the token are rejected
+        let unauthorized = GoogleDriveFS::with_endpoint(
+            &serve(|_, _| ("401 Unauthorized", String::new(), Vec::new())),
+            "token".to_owned(),
+        );
+        assert_eq!(
+            unauthorized.metadata("readme").err().unwrap().kind(),
+            ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn write() {
+        let fs = google_drive_fs(&[
+            ("readme", "root", "readme", Some("readme")),
+            ("docs", "root", "docs", None),
+            ("file", "docs", "file", Some("file")),
+        ]);
+
+        let mut file = fs.create_file("docs/new").unwrap();
+        assert_eq!(fs.metadata("docs/new").unwrap().len(), 0);
+        write!(file, "new").unwrap();
+        file.flush().unwrap();
+        assert_eq!(fs.metadata("docs/new").unwrap().len(), 3);
+        write!(file, " file").unwrap();
+        drop(file);
+        assert_eq!(
+            fs.open_file("docs/new")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "new file"
+        );
+        assert!(fs.create_file("missing/new").is_err());
+
+        let mut file = fs
+            .open_file_options("readme", &OpenOptions::default().append(true))
+            .unwrap();
+        write!(file, "!").unwrap();
+        drop(file);
+        assert_eq!(
+            fs.open_file("readme").unwrap().read_into_string().unwrap(),
+            "readme!"
+        );
+        drop(fs.create_file("readme").unwrap());
+        assert_eq!(fs.metadata("readme").unwrap().len(), 0);
+
+        // read-only files can't be written
+        assert!(write!(fs.open_file("docs/file").unwrap(), "file").is_err());
+
+        fs.create_dir("docs/dir").unwrap();
+        assert_eq!(
+            fs.create_dir("docs/dir").err().unwrap().kind(),
+            ErrorKind::AlreadyExists
+        );
+        assert!(fs.metadata("docs/dir").unwrap().is_directory());
+
+        // files replace the files they're renamed to
+        fs.rename("docs/new", "docs/dir/renamed").unwrap();
+        fs.rename("docs/file", "readme").unwrap();
+        assert_eq!(
+            fs.open_file("readme").unwrap().read_into_string().unwrap(),
+            "file"
+        );
+        itertools::assert_equal(read_directory(&fs, "docs").keys(), vec!["docs/dir"]);
+        itertools::assert_equal(
+            read_directory(&fs, "docs/dir").keys(),
+            vec!["docs/dir/renamed"],
+        );
+
+        assert_eq!(
+            fs.remove_dir("docs/dir").err().unwrap().kind(),
+            ErrorKind::DirectoryNotEmpty
+        );
+        assert!(fs.remove_file("docs/dir").is_err());
+        fs.remove_file("docs/dir/renamed").unwrap();
+        fs.remove_dir("docs/dir").unwrap();
+        assert!(read_directory(&fs, "docs").is_empty());
+        assert_eq!(
+            fs.remove_file("docs/dir").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        assert_eq!(
+            fs.stats().unwrap(),
+            FileSystemStats {
+                total_space: 1000,
+                free_space: 996,
+                available_space: 996,
+                block_size: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn open_options() {
+        check_open_options(&google_drive_fs(&[]), "options");
+    }
+}
@@ -0,0 +1,446 @@
+use crate::file::{DirEntry, File, Metadata, OpenOptions};
+use crate::range_reader::{convert_error, RangeReader};
+use crate::tree::normalize_and_relativize;
+use crate::util::{invalid_path, not_found, not_supported, path, read_only};
+use crate::FileSystem;
+use std::collections::HashSet;
+use std::io;
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+
+/// A read-only filesystem that maps paths to URLs under a base URL. Metadata is fetched with `HEAD` requests, and
+/// files are read lazily through range requests, or downloaded entirely if the server doesn't support them.
+///
+/// HTTP has no notion of directories, so by default only the root is a directory and nothing can be listed. With
+/// `directory_index` enabled, directories are URLs ending in `/`, and they're listed by parsing the links in the
+/// index page the server generates for them.
+pub struct HttpFS {
+    agent: ureq::Agent,
+    base: String,
+    directory_index: bool,
+}
+
+impl HttpFS {
+    /// Creates a new filesystem mapping paths to URLs under `base`.
+    ///
+    /// # Arguments
+    /// `base`: The base URL.  
+    pub fn new<S: Into<String>>(base: S) -> Self {
+        Self::with_agent(ureq::Agent::new(), base)
+    }
+
+    /// Creates a new filesystem mapping paths to URLs under `base`, issuing requests through `agent`.
+    ///
+    /// # Arguments
+    /// `agent`: The agent used to issue requests.  
+    /// `base`: The base URL.  
+    pub fn with_agent<S: Into<String>>(agent: ureq::Agent, base: S) -> Self {
+        Self {
+            agent,
+            base: base.into().trim_end_matches('/').to_owned(),
+            directory_index: false,
+        }
+    }
+
+    /// # Arguments
+    /// `directory_index`: True if directories should be detected and listed through the index pages the server
+    /// generates for URLs ending in `/`.  
+    pub fn directory_index(mut self, directory_index: bool) -> Self {
+        self.directory_index = directory_index;
+        self
+    }
+
+    /// Returns the URL of the file at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file.  
+    fn file_url(&self, path: &str) -> crate::Result<String> {
+        let path = normalize_and_relativize(path);
+        Ok(format!(
+            "{}/{}",
+            self.base,
+            path::encode(path.to_str().ok_or_else(invalid_path)?)
+        ))
+    }
+
+    /// Returns the URL of the index of the directory at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The path of the directory.  
+    fn directory_url(&self, path: &str) -> crate::Result<String> {
+        let url = self.file_url(path)?;
+        Ok(if url.ends_with('/') { url } else { url + "/" })
+    }
+}
+
+impl FileSystem for HttpFS {
+    fn create_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        if normalize_and_relativize(path).as_os_str().is_empty() {
+            return Ok(Metadata::directory());
+        }
+
+        match head(&self.agent, &self.file_url(path)?, self.directory_index) {
+            // the directory may only be served with a trailing slash
+            Err(err) if err.kind() == ErrorKind::NotFound && self.directory_index => {
+                head(&self.agent, &self.directory_url(path)?, true).map(|_| Metadata::directory())
+            }
+            result => result,
+        }
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        // ensure we only want to read
+        options.validate()?;
+        if options.writable() {
+            return Err(read_only());
+        }
+        if normalize_and_relativize(path).as_os_str().is_empty() {
+            return Err(not_found());
+        }
+
+        let url = self.file_url(path)?;
+        match RangeReader::with_agent(self.agent.clone(), &url) {
+            Ok(reader) => Ok(Box::new(HttpFile {
+                len: reader.len(),
+                inner: reader,
+            })),
+            // the server doesn't support ranges, or the file is empty, so download it entirely
+            Err(err) if matches!(err.kind(), ErrorKind::Unsupported | ErrorKind::InvalidInput) => {
+                let mut contents = Vec::new();
+                self.agent
+                    .get(&url)
+                    .call()
+                    .map_err(convert_error)?
+                    .into_reader()
+                    .read_to_end(&mut contents)?;
+
+                Ok(Box::new(HttpFile {
+                    len: contents.len() as u64,
+                    inner: Cursor::new(contents),
+                }))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        if !self.directory_index {
+            return Err(not_supported());
+        }
+
+        let url = self.directory_url(path)?;
+        let index = self
+            .agent
+            .get(&url)
+            .call()
+            .map_err(convert_error)?
+            .into_string()?;
+
+        let directory = normalize_and_relativize(path);
+        let agent = self.agent.clone();
+        Ok(Box::new(parse_index(&index).into_iter().map(
+            move |(name, is_directory)| {
+                let metadata = if is_directory {
+                    Metadata::directory()
+                } else {
+                    head(&agent, &format!("{url}{}", path::encode(&name)), false)?
+                };
+
+                Ok(DirEntry {
+                    path: directory.join(name),
+                    metadata,
+                })
+            },
+        )))
+    }
+
+    fn remove_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn remove_file(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+}
+
+/// Fetches the metadata of the resource at `url` with a `HEAD` request.
+///
+/// # Arguments
+/// `agent`: The agent used to issue the request.  
+/// `url`: The URL of the resource.  
+/// `directory_index`: True if resources redirected to a URL ending in `/` are directories.  
+fn head(agent: &ureq::Agent, url: &str, directory_index: bool) -> crate::Result<Metadata> {
+    let response = agent.head(url).call().map_err(convert_error)?;
+    if directory_index && response.get_url().ends_with('/') {
+        return Ok(Metadata::directory());
+    }
+
+    Ok(Metadata::file(
+        response
+            .header("Content-Length")
+            .and_then(|len| len.parse().ok())
+            .unwrap_or(0),
+    ))
+}
+
+/// Parses the entries directly contained in a directory from the links in its index page. Returns the name of each
+/// entry and whether it's a directory.
+///
+/// # Arguments
+/// `index`: The HTML of the index page.  
+fn parse_index(index: &str) -> Vec<(String, bool)> {
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+
+    let lowercase_index = index.to_ascii_lowercase();
+    for (start, _) in lowercase_index.match_indices("href=") {
+        let rest = &index[start + "href=".len()..];
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        let Some(link) = rest[1..].split(quote).next() else {
+            continue;
+        };
+
+        // only relative links to direct children are entries, not sorting links, parents or other sites
+        let link = link.replace("&amp;", "&");
+        let link = link.trim_start_matches("./");
+        if link.is_empty() || link.starts_with(['/', '?', '#', '.']) || link.contains(':') {
+            continue;
+        }
+        let link = link.split(['?', '#']).next().unwrap_or(link);
+        let (name, is_directory) = match link.strip_suffix('/') {
+            Some(name) => (name, true),
+            None => (link, false),
+        };
+        if name.is_empty() || name.contains('/') {
+            continue;
+        }
+
+        let name = path::decode(name);
+        if seen.insert(name.clone()) {
+            entries.push((name, is_directory));
+        }
+    }
+
+    entries
+}
+
+/// A remote file, read either lazily or from memory.
+struct HttpFile<R> {
+    inner: R,
+    len: u64,
+}
+
+impl<R: Read> Read for HttpFile<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for HttpFile<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<R> Write for HttpFile<R> {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(not_supported())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(not_supported())
+    }
+}
+
+impl<R: Read + Seek> File for HttpFile<R> {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(Metadata::file(self.len))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::Metadata;
+    use crate::http_fs::{parse_index, HttpFS};
+    use crate::util::test::read_directory;
+    use crate::FileSystem;
+    use std::io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serves a small tree over HTTP, with directory indices and range support on files other than `no-ranges`.
+    /// Returns the base URL.
+    fn serve() -> String {
+        let files = [
+            ("/readme.txt", "readme"),
+            ("/no-ranges", "no ranges"),
+            ("/assets/big%20file", "0123456789"),
+            ("/assets/nested/file", "nested"),
+        ];
+        let indices = [
+            (
+                "/",
+                r#"<a href="?C=N;O=D">Name</a><a href="readme.txt">readme.txt</a><a href="assets/">assets/</a><a href="no-ranges">no-ranges</a>"#,
+            ),
+            (
+                "/assets/",
+                r#"<a href="../">Parent</a><a href='big%20file'>big file</a><a href="nested/">nested/</a><a href="https://example.com">Elsewhere</a>"#,
+            ),
+            ("/assets/nested/", r#"<a href="./file">file</a>"#),
+        ];
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut parts = request_line.split_whitespace();
+                let (method, path) = (parts.next().unwrap(), parts.next().unwrap().to_owned());
+
+                // find the range header
+                let mut range = None;
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = line.to_lowercase().strip_prefix("range: bytes=") {
+                        let (start, end) = value.trim().split_once('-').unwrap();
+                        range = Some((
+                            start.parse::<usize>().unwrap(),
+                            end.parse::<usize>().unwrap(),
+                        ));
+                    }
+                }
+
+                let file = files.iter().find(|(file_path, _)| *file_path == path);
+                let index = indices.iter().find(|(index_path, _)| *index_path == path);
+                let (status, headers, body) = if let Some((file_path, contents)) = file {
+                    match range.filter(|_| *file_path != "/no-ranges") {
+                        Some((start, end)) => {
+                            let end = end.min(contents.len() - 1);
+                            (
+                                "206 Partial Content",
+                                format!(
+                                    "Content-Range: bytes {start}-{end}/{}\r\n",
+                                    contents.len()
+                                ),
+                                contents[start..=end].to_owned(),
+                            )
+                        }
+                        None => ("200 OK", String::new(), contents.to_string()),
+                    }
+                } else if let Some((_, index)) = index {
+                    ("200 OK", String::new(), index.to_string())
+                } else if indices
+                    .iter()
+                    .any(|(index_path, _)| *index_path == path.clone() + "/")
+                {
+                    (
+                        "301 Moved Permanently",
+                        format!("Location: {path}/\r\n"),
+                        String::new(),
+                    )
+                } else {
+                    ("404 Not Found", String::new(), String::new())
+                };
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                if method != "HEAD" {
+                    stream.write_all(body.as_bytes()).unwrap();
+                }
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn open_file() {
+        let http_fs = HttpFS::new(serve());
+
+        let mut file = http_fs.open_file("assets/big file").unwrap();
+        file.seek(SeekFrom::Start(4)).unwrap();
+        let mut buf = [0; 3];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"456");
+        assert_eq!(file.metadata().unwrap(), Metadata::file(10));
+
+        assert_eq!(
+            http_fs
+                .open_file("/no-ranges")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "no ranges"
+        );
+        assert_eq!(
+            http_fs.open_file("missing").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert!(http_fs.create_file("readme.txt").is_err());
+    }
+
+    #[test]
+    fn metadata() {
+        let http_fs = HttpFS::new(serve());
+        assert_eq!(http_fs.metadata("readme.txt").unwrap(), Metadata::file(6));
+        assert_eq!(http_fs.metadata("").unwrap(), Metadata::directory());
+        // without directory indices, the index page is just another resource
+        assert!(http_fs.metadata("assets").unwrap().is_file());
+        assert!(http_fs.read_dir("").is_err());
+
+        let http_fs = http_fs.directory_index(true);
+        assert_eq!(http_fs.metadata("assets").unwrap(), Metadata::directory());
+        assert_eq!(
+            http_fs.metadata("assets/nested/").unwrap(),
+            Metadata::directory()
+        );
+        assert!(!http_fs.exists("assets/missing").unwrap());
+    }
+
+    #[test]
+    fn read_dir() {
+        let http_fs = HttpFS::new(serve()).directory_index(true);
+
+        let root = read_directory(&http_fs, "");
+        itertools::assert_equal(root.keys(), vec!["assets", "no-ranges", "readme.txt"]);
+        assert_eq!(root["readme.txt"], Metadata::file(6));
+        assert_eq!(root["assets"], Metadata::directory());
+
+        let assets = read_directory(&http_fs, "assets");
+        itertools::assert_equal(assets.keys(), vec!["assets/big file", "assets/nested"]);
+        assert_eq!(assets["assets/big file"], Metadata::file(10));
+
+        itertools::assert_equal(
+            read_directory(&http_fs, "assets/nested").keys(),
+            vec!["assets/nested/file"],
+        );
+    }
+
+    #[test]
+    fn index() {
+        assert_eq!(
+            parse_index(r#"<A HREF="dir/">dir</A> <a href="file?x=1">file</a>"#),
+            vec![("dir".to_owned(), true), ("file".to_owned(), false)]
+        );
+    }
+}
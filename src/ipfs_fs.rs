@@ -0,0 +1,1001 @@
+use crate::file::{DirEntry, File, FileType, Metadata, OpenOptions};
+use crate::range_reader::convert_error_with;
+use crate::tree::normalize_and_relativize;
+use crate::util::{component_iter, invalid_input, not_found, not_supported, read_only};
+use crate::FileSystem;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The multicodec of UnixFS nodes.
+const DAG_PB: u64 = 0x70;
+/// The multicodec of raw blocks, which are the leaves of files.
+const RAW: u64 = 0x55;
+/// The multihash code of the identity hash, whose digest is the block itself.
+const IDENTITY: u64 = 0x00;
+/// The multihash code of SHA-256.
+const SHA2_256: u64 = 0x12;
+/// The number of bytes of blocks cached by default.
+const DEFAULT_CACHE_SIZE: usize = 64 * 1024 * 1024;
+/// The alphabet of lowercase base32, the multibase CIDv1s are written in.
+const BASE32: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+/// The alphabet of base58btc, the multibase CIDv0s are written in.
+const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// The types of UnixFS nodes.
+mod node_type {
+    pub const RAW: u64 = 0;
+    pub const DIRECTORY: u64 = 1;
+    pub const FILE: u64 = 2;
+    pub const SYMLINK: u64 = 4;
+    pub const HAMT_SHARD: u64 = 5;
+}
+
+/// A read-only filesystem on a UnixFS directory published to IPFS, fetched block by block from an HTTP gateway. The
+/// gateway can be a local node's or a public one, and it isn't trusted: every block is verified against its CID, so
+/// only the content the CID names is ever read.
+///
+/// Blocks are immutable, so they're cached in memory up to a size. Sharded directories are supported, and symbolic
+/// links are listed with an unknown file type. Listing a directory fetches the root block of each of its entries.
+pub struct IpfsFS {
+    blocks: Arc<BlockStore>,
+    root: Cid,
+}
+
+impl IpfsFS {
+    /// Creates a filesystem on a directory.
+    ///
+    /// # Arguments
+    /// `gateway`: The URL of the gateway, such as `http://127.0.0.1:8080` for a local node.  
+    /// `cid`: The CID of the directory.  
+    pub fn new(gateway: &str, cid: &str) -> crate::Result<Self> {
+        Ok(Self {
+            blocks: Arc::new(BlockStore {
+                agent: ureq::Agent::new(),
+                gateway: gateway.trim_end_matches('/').to_owned(),
+                cache: Mutex::new(BlockCache {
+                    capacity: DEFAULT_CACHE_SIZE,
+                    ..BlockCache::default()
+                }),
+            }),
+            root: Cid::parse(cid).ok_or_else(|| invalid_input("Invalid CID"))?,
+        })
+    }
+
+    /// # Arguments
+    /// `agent`: The agent used to issue requests.  
+    pub fn agent(mut self, agent: ureq::Agent) -> Self {
+        Arc::get_mut(&mut self.blocks)
+            .expect("the filesystem isn't shared before it's built")
+            .agent = agent;
+        self
+    }
+
+    /// # Arguments
+    /// `cache_size`: The number of bytes of blocks cached in memory.  
+    pub fn cache_size(self, cache_size: usize) -> Self {
+        self.blocks.cache.lock().capacity = cache_size;
+        self
+    }
+
+    /// Returns the CID of the entry at `path`, and its node.
+    ///
+    /// # Arguments
+    /// `path`: The virtual path.  
+    fn lookup(&self, path: &str) -> crate::Result<(Cid, Node)> {
+        let path = normalize_and_relativize(path);
+        let mut cid = self.root.clone();
+        let mut node = self.blocks.node(&cid)?;
+        for name in component_iter(&path) {
+            cid = self
+                .blocks
+                .entries(&node)?
+                .into_iter()
+                .find(|(entry, _)| entry == name)
+                .map(|(_, cid)| cid)
+                .ok_or_else(not_found)?;
+            node = self.blocks.node(&cid)?;
+        }
+        Ok((cid, node))
+    }
+}
+
+impl FileSystem for IpfsFS {
+    fn create_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        Ok(self.lookup(path)?.1.metadata())
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        if options.writable() {
+            return Err(read_only());
+        }
+
+        let (cid, node) = self.lookup(path)?;
+        let metadata = node.metadata();
+        if !metadata.is_file() {
+            return Err(invalid_input("path is not a file"));
+        }
+        Ok(Box::new(IpfsFile {
+            blocks: self.blocks.clone(),
+            root: cid,
+            metadata,
+            pos: 0,
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let (_, node) = self.lookup(path)?;
+        if !node.is_directory() {
+            return Err(invalid_input("path is not a directory"));
+        }
+
+        let directory = normalize_and_relativize(path);
+        let blocks = self.blocks.clone();
+        Ok(Box::new(blocks.entries(&node)?.into_iter().map(
+            move |(name, cid)| {
+                Ok(DirEntry {
+                    path: directory.join(name),
+                    metadata: blocks.node(&cid)?.metadata(),
+                })
+            },
+        )))
+    }
+
+    fn remove_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn remove_file(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+}
+
+/// A content identifier.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct Cid {
+    codec: u64,
+    /// The multihash of the block, with its code and length.
+    multihash: Vec<u8>,
+}
+
+impl Cid {
+    /// Parses a CIDv0, or a CIDv1 in base32 or base58btc.
+    ///
+    /// # Arguments
+    /// `text`: The CID.  
+    fn parse(text: &str) -> Option<Self> {
+        if text.len() == 46 && text.starts_with("Qm") {
+            return Self::from_bytes(&base58_decode(text)?);
+        }
+        let bytes = match text.split_at_checked(1)? {
+            ("b", rest) => base32_decode(rest)?,
+            ("z", rest) => base58_decode(rest)?,
+            _ => return None,
+        };
+        Self::from_bytes(&bytes)
+    }
+
+    /// Parses a binary CID, as found in the links of a node.
+    ///
+    /// # Arguments
+    /// `bytes`: The CID.  
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        // a CIDv0 is a bare SHA-256 multihash of a UnixFS node
+        if bytes.len() == 34 && bytes[..2] == [SHA2_256 as u8, 32] {
+            return Some(Self {
+                codec: DAG_PB,
+                multihash: bytes.to_vec(),
+            });
+        }
+
+        let mut reader = ProtoReader(bytes);
+        if reader.varint()? != 1 {
+            return None;
+        }
+        let codec = reader.varint()?;
+        let multihash = reader.0;
+        // the multihash must be exactly its code, length and digest
+        let mut digest = ProtoReader(multihash);
+        digest.varint()?;
+        let len = digest.varint()?;
+        (digest.0.len() as u64 == len).then(|| Self {
+            codec,
+            multihash: multihash.to_vec(),
+        })
+    }
+
+    /// Returns the CIDv1 in base32, which every gateway understands.
+    fn to_v1_string(&self) -> String {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, 1);
+        write_varint(&mut bytes, self.codec);
+        bytes.extend_from_slice(&self.multihash);
+        format!("b{}", base32_encode(&bytes))
+    }
+
+    /// Returns the code of the hash function and the digest of the multihash.
+    fn digest(&self) -> (u64, &[u8]) {
+        let mut reader = ProtoReader(&self.multihash);
+        let code = reader.varint().unwrap_or(u64::MAX);
+        reader.varint();
+        (code, reader.0)
+    }
+}
+
+/// A link from a UnixFS node to another.
+struct Link {
+    cid: Cid,
+    name: String,
+}
+
+/// A decoded UnixFS node, or a raw block.
+#[derive(Default)]
+struct Node {
+    node_type: u64,
+    data: Vec<u8>,
+    links: Vec<Link>,
+    /// The sizes of the data of the children of a file.
+    block_sizes: Vec<u64>,
+    file_size: Option<u64>,
+    fanout: u64,
+    mode: Option<u32>,
+    modified: Option<SystemTime>,
+}
+
+impl Node {
+    /// Decodes a block.
+    ///
+    /// # Arguments
+    /// `cid`: The CID of the block.  
+    /// `block`: The block.  
+    fn decode(cid: &Cid, block: &[u8]) -> crate::Result<Self> {
+        let malformed = || io::Error::new(ErrorKind::InvalidData, "Malformed UnixFS node");
+        match cid.codec {
+            RAW => {
+                return Ok(Self {
+                    node_type: node_type::RAW,
+                    data: block.to_vec(),
+                    ..Self::default()
+                })
+            }
+            DAG_PB => {}
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    "Unsupported IPLD codec",
+                ))
+            }
+        }
+
+        // a PBNode of links and a UnixFS data message
+        let mut node = Self::default();
+        let mut unixfs = None;
+        let mut reader = ProtoReader(block);
+        while !reader.0.is_empty() {
+            match reader.field().ok_or_else(malformed)? {
+                (1, value) => unixfs = Some(value.bytes().ok_or_else(malformed)?),
+                (2, value) => {
+                    let mut link = ProtoReader(value.bytes().ok_or_else(malformed)?);
+                    let (mut cid, mut name) = (None, String::new());
+                    while !link.0.is_empty() {
+                        match link.field().ok_or_else(malformed)? {
+                            (1, value) => {
+                                cid = Cid::from_bytes(value.bytes().ok_or_else(malformed)?)
+                            }
+                            (2, value) => {
+                                name =
+                                    String::from_utf8(value.bytes().ok_or_else(malformed)?.to_vec())
+                                        .map_err(|_| malformed())?
+                            }
+                            _ => {}
+                        }
+                    }
+                    node.links.push(Link {
+                        cid: cid.ok_or_else(malformed)?,
+                        name,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let mut reader = ProtoReader(unixfs.ok_or_else(malformed)?);
+        while !reader.0.is_empty() {
+            match reader.field().ok_or_else(malformed)? {
+                (1, value) => node.node_type = value.varint().ok_or_else(malformed)?,
+                (2, value) => node.data = value.bytes().ok_or_else(malformed)?.to_vec(),
+                (3, value) => node.file_size = Some(value.varint().ok_or_else(malformed)?),
+                // sizes are packed by some encoders and repeated by others
+                (4, FieldValue::Bytes(mut packed)) => {
+                    while !packed.is_empty() {
+                        let mut reader = ProtoReader(packed);
+                        node.block_sizes
+                            .push(reader.varint().ok_or_else(malformed)?);
+                        packed = reader.0;
+                    }
+                }
+                (4, value) => node.block_sizes.push(value.varint().ok_or_else(malformed)?),
+                (6, value) => node.fanout = value.varint().ok_or_else(malformed)?,
+                (7, value) => node.mode = Some(value.varint().ok_or_else(malformed)? as u32),
+                (8, value) => {
+                    let mut time = ProtoReader(value.bytes().ok_or_else(malformed)?);
+                    let (mut seconds, mut nanos) = (0, 0);
+                    while !time.0.is_empty() {
+                        match time.field().ok_or_else(malformed)? {
+                            (1, value) => seconds = value.varint().ok_or_else(malformed)?,
+                            (2, FieldValue::Fixed32(value)) => nanos = value,
+                            _ => {}
+                        }
+                    }
+                    node.modified = SystemTime::UNIX_EPOCH
+                        .checked_add(Duration::new(seconds, nanos.min(999_999_999)));
+                }
+                _ => {}
+            }
+        }
+
+        if node.node_type == node_type::FILE && node.block_sizes.len() != node.links.len() {
+            return Err(malformed());
+        }
+        Ok(node)
+    }
+
+    /// Returns true if the node is a directory or a shard of one.
+    fn is_directory(&self) -> bool {
+        matches!(self.node_type, node_type::DIRECTORY | node_type::HAMT_SHARD)
+    }
+
+    /// Returns the metadata of the entry the node is the root of.
+    fn metadata(&self) -> Metadata {
+        let metadata = match self.node_type {
+            _ if self.is_directory() => Metadata::directory(),
+            node_type::RAW | node_type::FILE => Metadata::file(
+                self.file_size
+                    .unwrap_or(self.data.len() as u64 + self.block_sizes.iter().sum::<u64>()),
+            ),
+            node_type::SYMLINK => Metadata {
+                file_type: FileType::Unknown,
+                link_target: std::str::from_utf8(&self.data).ok().map(PathBuf::from),
+                ..Metadata::file(self.data.len() as u64)
+            },
+            _ => Metadata {
+                file_type: FileType::Unknown,
+                ..Metadata::file(0)
+            },
+        };
+        Metadata {
+            mode: self.mode,
+            modified: self.modified,
+            ..metadata
+        }
+    }
+}
+
+/// Fetches and caches the blocks of a gateway.
+struct BlockStore {
+    agent: ureq::Agent,
+    gateway: String,
+    cache: Mutex<BlockCache>,
+}
+
+/// A cache of blocks, bounded by their total size. The oldest blocks are evicted first.
+#[derive(Default)]
+struct BlockCache {
+    capacity: usize,
+    size: usize,
+    blocks: HashMap<Cid, Arc<[u8]>>,
+    order: VecDeque<Cid>,
+}
+
+impl BlockStore {
+    /// Fetches a block and verifies it against its CID.
+    ///
+    /// # Arguments
+    /// `cid`: The CID of the block.  
+    fn block(&self, cid: &Cid) -> crate::Result<Arc<[u8]>> {
+        let (code, digest) = cid.digest();
+        // small blocks may be inlined in their CIDs
+        if code == IDENTITY {
+            return Ok(digest.into());
+        }
+        if code != SHA2_256 {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "Unsupported hash function",
+            ));
+        }
+        if let Some(block) = self.cache.lock().blocks.get(cid) {
+            return Ok(block.clone());
+        }
+
+        let mut block = Vec::new();
+        self.agent
+            .get(&format!("{}/ipfs/{}", self.gateway, cid.to_v1_string()))
+            .query("format", "raw")
+            .set("Accept", "application/vnd.ipld.raw")
+            .call()
+            .map_err(convert_error)?
+            .into_reader()
+            .read_to_end(&mut block)?;
+        if hmac_sha256::Hash::hash(&block)[..] != *digest {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Block doesn't match its CID",
+            ));
+        }
+
+        let block: Arc<[u8]> = block.into();
+        let mut cache = self.cache.lock();
+        if block.len() <= cache.capacity && !cache.blocks.contains_key(cid) {
+            while cache.size + block.len() > cache.capacity {
+                let Some(oldest) = cache.order.pop_front() else {
+                    break;
+                };
+                if let Some(evicted) = cache.blocks.remove(&oldest) {
+                    cache.size -= evicted.len();
+                }
+            }
+            cache.size += block.len();
+            cache.order.push_back(cid.clone());
+            cache.blocks.insert(cid.clone(), block.clone());
+        }
+        Ok(block)
+    }
+
+    /// Fetches and decodes a node.
+    ///
+    /// # Arguments
+    /// `cid`: The CID of the node.  
+    fn node(&self, cid: &Cid) -> crate::Result<Node> {
+        Node::decode(cid, &self.block(cid)?)
+    }
+
+    /// Returns the names and CIDs of the entries of a directory, fetching the shards of a sharded directory.
+    ///
+    /// # Arguments
+    /// `node`: The directory.  
+    fn entries(&self, node: &Node) -> crate::Result<Vec<(String, Cid)>> {
+        if node.node_type != node_type::HAMT_SHARD {
+            return Ok(node
+                .links
+                .iter()
+                .map(|link| (link.name.clone(), link.cid.clone()))
+                .collect());
+        }
+
+        // names are prefixed by their index in the shard in hex, and bare indices are nested shards
+        let prefix_len = format!("{:X}", node.fanout.max(2) - 1).len();
+        let mut entries = Vec::new();
+        for link in &node.links {
+            match link.name.get(prefix_len..) {
+                Some("") => entries.extend(self.entries(&self.node(&link.cid)?)?),
+                Some(name) => entries.push((name.to_owned(), link.cid.clone())),
+                None => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "Malformed directory shard",
+                    ))
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Reads the part of a file at `offset` that's in a single block, and returns the number of bytes read.
+    ///
+    /// # Arguments
+    /// `cid`: The CID of the root of the file.  
+    /// `offset`: The offset to read at.  
+    /// `buf`: The buffer to read into.  
+    fn read_at(&self, cid: &Cid, mut offset: u64, buf: &mut [u8]) -> crate::Result<usize> {
+        let node = self.node(cid)?;
+
+        // the data of a node precedes the data of its children
+        if let Some(data) = node
+            .data
+            .get(offset as usize..)
+            .filter(|data| !data.is_empty())
+        {
+            let n = data.len().min(buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            return Ok(n);
+        }
+        offset -= (node.data.len() as u64).min(offset);
+        for (link, size) in node.links.iter().zip(&node.block_sizes) {
+            if offset < *size {
+                return self.read_at(&link.cid, offset, buf);
+            }
+            offset -= size;
+        }
+        Ok(0)
+    }
+}
+
+/// A file read from the blocks of its DAG.
+struct IpfsFile {
+    blocks: Arc<BlockStore>,
+    root: Cid,
+    metadata: Metadata,
+    pos: u64,
+}
+
+impl File for IpfsFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(self.metadata.clone())
+    }
+}
+
+impl Read for IpfsFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.metadata.len() {
+            return Ok(0);
+        }
+
+        let n = self.blocks.read_at(&self.root, self.pos, buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for IpfsFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base_pos, offset) = match pos {
+            SeekFrom::Start(n) => (n, 0),
+            SeekFrom::Current(n) => (self.pos, n),
+            SeekFrom::End(n) => (self.metadata.len(), n),
+        };
+
+        let Some(n) = base_pos.checked_add_signed(offset) else {
+            return Err(invalid_input(
+                "Invalid seek to a negative or overflowing position",
+            ));
+        };
+        self.pos = n;
+        Ok(n)
+    }
+}
+
+impl Write for IpfsFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(not_supported())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The value of a protobuf field.
+enum FieldValue<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> FieldValue<'a> {
+    fn varint(self) -> Option<u64> {
+        match self {
+            Self::Varint(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn bytes(self) -> Option<&'a [u8]> {
+        match self {
+            Self::Bytes(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Reads protobuf fields and unsigned varints from the front of a buffer.
+struct ProtoReader<'a>(&'a [u8]);
+
+impl<'a> ProtoReader<'a> {
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for (n, byte) in self.0.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * n);
+            if byte & 0x80 == 0 {
+                self.0 = &self.0[n + 1..];
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Reads the number and value of a field.
+    fn field(&mut self) -> Option<(u64, FieldValue<'a>)> {
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => FieldValue::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                FieldValue::Fixed64
+            }
+            2 => {
+                let len = self.varint()?;
+                FieldValue::Bytes(self.take(usize::try_from(len).ok()?)?)
+            }
+            5 => FieldValue::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().ok()?)),
+            _ => return None,
+        };
+        Some((key >> 3, value))
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (value, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(value)
+    }
+}
+
+/// Appends an unsigned varint.
+///
+/// # Arguments
+/// `bytes`: The buffer.  
+/// `value`: The value.  
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Encodes bytes as unpadded lowercase base32.
+///
+/// # Arguments
+/// `bytes`: The bytes.  
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            text.push(char::from(BASE32[(buffer >> bits) as usize & 31]));
+        }
+    }
+    if bits > 0 {
+        text.push(char::from(BASE32[(buffer << (5 - bits)) as usize & 31]));
+    }
+    text
+}
+
+/// Decodes unpadded base32, in either case.
+///
+/// # Arguments
+/// `text`: The encoded bytes.  
+fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for char in text.bytes() {
+        let value = BASE32
+            .iter()
+            .position(|digit| *digit == char.to_ascii_lowercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// Decodes base58btc.
+///
+/// # Arguments
+/// `text`: The encoded bytes.  
+fn base58_decode(text: &str) -> Option<Vec<u8>> {
+    // little-endian digits of the value in base 256
+    let mut bytes: Vec<u8> = Vec::new();
+    for char in text.bytes() {
+        let mut carry = BASE58.iter().position(|digit| *digit == char)? as u32;
+        for byte in &mut bytes {
+            carry += u32::from(*byte) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    // leading ones are leading zeros
+    let zeros = text.bytes().take_while(|char| *char == b'1').count();
+    bytes.extend(std::iter::repeat_n(0, zeros));
+    bytes.reverse();
+    Some(bytes)
+}
+
+/// Converts a request error to an IO error.
+fn convert_error(err: ureq::Error) -> io::Error {
+    convert_error_with(err, |code, _| {
+        io::Error::other(format!("Gateway error {code}"))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::{FileType, Metadata};
+    use crate::ipfs_fs::{write_varint, Cid, IpfsFS, DAG_PB, RAW, SHA2_256};
+    use crate::util::test::read_directory;
+    use crate::FileSystem;
+    use std::collections::BTreeMap;
+    use std::io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    /// Encodes a varint field.
+    fn varint_field(field: u64, value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, field << 3);
+        write_varint(&mut bytes, value);
+        bytes
+    }
+
+    /// Encodes a length-delimited field.
+    fn bytes_field(field: u64, value: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, (field << 3) | 2);
+        write_varint(&mut bytes, value.len() as u64);
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    /// Builds the blocks of a DAG, keyed by their CIDs.
+    #[derive(Default)]
+    struct Dag {
+        blocks: BTreeMap<String, Vec<u8>>,
+    }
+
+    impl Dag {
+        /// Adds a block, and returns its CID.
+        fn add(&mut self, codec: u64, block: Vec<u8>) -> Cid {
+            let mut multihash = vec![SHA2_256 as u8, 32];
+            multihash.extend_from_slice(&hmac_sha256::Hash::hash(&block));
+            let cid = Cid { codec, multihash };
+            self.blocks.insert(cid.to_v1_string(), block);
+            cid
+        }
+
+        /// Adds a UnixFS node with links, and returns its CID.
+        fn node(&mut self, unixfs: Vec<u8>, links: &[(&str, &Cid)]) -> Cid {
+            let mut block = Vec::new();
+            for (name, cid) in links {
+                let mut hash = Vec::new();
+                write_varint(&mut hash, 1);
+                write_varint(&mut hash, cid.codec);
+                hash.extend_from_slice(&cid.multihash);
+                let link = [bytes_field(1, &hash), bytes_field(2, name.as_bytes())].concat();
+                block.extend(bytes_field(2, &link));
+            }
+            block.extend(bytes_field(1, &unixfs));
+            self.add(DAG_PB, block)
+        }
+
+        /// Adds a directory, and returns its CID.
+        fn directory(&mut self, entries: &[(&str, &Cid)]) -> Cid {
+            self.node(varint_field(1, 1), entries)
+        }
+    }
+
+    /// Serves the blocks of a trustless gateway. Returns the URL of the gateway and the CIDs requested.
+    fn serve(blocks: BTreeMap<String, Vec<u8>>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let served = requests.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let gateway = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                        break;
+                    }
+                }
+
+                let target = request_line.split_whitespace().nth(1).unwrap();
+                let (cid, query) = target
+                    .strip_prefix("/ipfs/")
+                    .unwrap()
+                    .split_once('?')
+                    .unwrap();
+                assert_eq!(query, "format=raw");
+                served.lock().unwrap().push(cid.to_owned());
+
+                let (status, body) = match blocks.get(cid) {
+                    Some(block) => ("200 OK", block.clone()),
+                    None => ("404 Not Found", Vec::new()),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+
+        (gateway, requests)
+    }
+
+    #[test]
+    fn cid() {
+        // the CIDv0 and CIDv1 of the empty directory
+        let v0 = Cid::parse("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn").unwrap();
+        let v1 = Cid::parse("bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354").unwrap();
+        assert_eq!(v0, v1);
+        assert_eq!(
+            v0.to_v1_string(),
+            "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354"
+        );
+        assert_eq!(Cid::parse("bafkqaaa").unwrap().codec, RAW);
+        assert!(Cid::parse("QmInvalid").is_none());
+        assert!(Cid::parse("xyz").is_none());
+    }
+
+    #[test]
+    fn read() {
+        let mut dag = Dag::default();
+
+        // a file of two raw leaves under a root with data of its own, with a mode and modification time
+        let leaves = [
+            dag.add(RAW, b"hello ".to_vec()),
+            dag.add(RAW, b"world".to_vec()),
+        ];
+        let time = [
+            varint_field(1, 1709208000),
+            vec![0x15],
+            500u32.to_le_bytes().to_vec(),
+        ]
+        .concat();
+        let file = dag.node(
+            [
+                varint_field(1, 2),
+                bytes_field(2, b">> "),
+                varint_field(3, 14),
+                bytes_field(4, &[6, 5]),
+                varint_field(7, 0o644),
+                bytes_field(8, &time),
+            ]
+            .concat(),
+            &[("", &leaves[0]), ("", &leaves[1])],
+        );
+        let small = dag.node([varint_field(1, 2), bytes_field(2, b"small")].concat(), &[]);
+        let link = dag.node([varint_field(1, 4), bytes_field(2, b"small")].concat(), &[]);
+        // an identity CID of an empty raw block, which is never fetched
+        let empty = Cid::parse("bafkqaaa").unwrap();
+
+        // a sharded directory with a nested shard
+        let fanout = varint_field(6, 256);
+        let nested = dag.node(
+            [varint_field(1, 5), fanout.clone()].concat(),
+            &[("0Bbee", &small), ("1Fcat", &small)],
+        );
+        let sharded = dag.node(
+            [varint_field(1, 5), fanout].concat(),
+            &[("00a", &small), ("0A", &nested)],
+        );
+
+        let nested_dir = dag.directory(&[("small", &small)]);
+        let root = dag.directory(&[
+            ("file", &file),
+            ("small", &small),
+            ("link", &link),
+            ("empty", &empty),
+            ("dir", &nested_dir),
+            ("sharded", &sharded),
+        ]);
+        let (gateway, requests) = serve(dag.blocks);
+        let fs = IpfsFS::new(&gateway, &root.to_v1_string()).unwrap();
+
+        let mut file = fs.open_file("file").unwrap();
+        assert_eq!(file.read_into_string().unwrap(), ">> hello world");
+        file.seek(SeekFrom::Start(5)).unwrap();
+        let mut buf = [0; 20];
+        assert_eq!(file.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"llo ");
+        file.seek(SeekFrom::End(-3)).unwrap();
+        assert_eq!(file.read_into_string().unwrap(), "rld");
+        assert!(file.write(b"x").is_err());
+
+        let metadata = fs.metadata("file").unwrap();
+        assert_eq!(metadata.len(), 14);
+        assert_eq!(metadata.mode, Some(0o644));
+        assert_eq!(
+            metadata.modified,
+            Some(SystemTime::UNIX_EPOCH + Duration::new(1709208000, 500))
+        );
+        assert_eq!(
+            fs.open_file("dir/small")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "small"
+        );
+        assert_eq!(fs.metadata("empty").unwrap(), Metadata::file(0));
+        assert_eq!(fs.metadata("link").unwrap().file_type, FileType::Unknown);
+        assert!(fs.metadata("").unwrap().is_directory());
+        assert_eq!(
+            fs.metadata("dir/missing").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert!(fs.open_file("dir").is_err());
+
+        let entries = read_directory(&fs, "");
+        itertools::assert_equal(
+            entries.keys(),
+            vec!["dir", "empty", "file", "link", "sharded", "small"],
+        );
+        assert!(entries["sharded"].is_directory());
+        itertools::assert_equal(
+            read_directory(&fs, "sharded").keys(),
+            vec!["sharded/a", "sharded/bee", "sharded/cat"],
+        );
+        assert_eq!(fs.metadata("sharded/cat").unwrap(), Metadata::file(5));
+
+        // blocks are cached, and the identity block is never requested
+        let requested = requests.lock().unwrap().clone();
+        let mut unique = requested.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(requested.len(), unique.len());
+        assert!(!requested.contains(&empty.to_v1_string()));
+
+        assert_eq!(
+            fs.create_dir("new").err().unwrap().kind(),
+            ErrorKind::ReadOnlyFilesystem
+        );
+        assert!(fs.create_file("new").is_err());
+        assert!(fs.remove_file("file").is_err());
+    }
+
+    #[test]
+    fn untrusted() {
+        // a gateway serving a block that doesn't match its CID is caught
+        let mut dag = Dag::default();
+        let root = dag.directory(&[]);
+        let (cid, _) = dag.blocks.pop_first().unwrap();
+        let tampered = [(cid, b"tampered".to_vec())].into_iter().collect();
+        let (gateway, _) = serve(tampered);
+
+        let fs = IpfsFS::new(&gateway, &root.to_v1_string()).unwrap();
+        assert_eq!(
+            fs.read_dir("").err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+
+        // missing blocks aren't found
+        let missing = IpfsFS::new(&serve(BTreeMap::new()).0, &root.to_v1_string()).unwrap();
+        assert_eq!(
+            missing.metadata("").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert!(IpfsFS::new(&gateway, "invalid").is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use crate::kv_fs::KvStore;
+use crate::util::journal::Journal;
+use crate::util::web::{global, js_error};
+use js_sys::{Array, Function, Promise, Uint8Array};
+use parking_lot::RwLock;
+use std::collections::BTreeMap;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{IdbDatabase, IdbFactory, IdbRequest, IdbTransaction, IdbTransactionMode};
+
+/// The object store that keeps the keys and values in the database.
+const OBJECT_STORE: &str = "kv";
+
+/// A change to the database.
+enum Change {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+/// A `KvStore` in an IndexedDB database of the browser, so that a `KvFS` persists across page loads.
+///
+/// IndexedDB is asynchronous and stores aren't, so the whole database is read into memory when it's opened. Reads are
+/// served from memory, and changes are made to memory at once and to the database in the background, in order and in
+/// as few transactions as possible. `flush` waits until every change has reached the database.
+///
+/// # Example
+/// ```no_run
+/// use virtual_filesystem::kv_fs::{IndexedDbStore, KvFS};
+/// use virtual_filesystem::FileSystem;
+/// use std::io::Write;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let fs = KvFS::new(IndexedDbStore::open("files").await?);
+/// fs.create_file("notes.txt")?.write_all(b"hello")?;
+/// fs.store().flush().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct IndexedDbStore {
+    name: String,
+    entries: RwLock<BTreeMap<Vec<u8>, Vec<u8>>>,
+    journal: Journal<Change>,
+}
+
+impl IndexedDbStore {
+    /// Opens the database `name`, creating it if it doesn't exist, and reads it into memory.
+    ///
+    /// # Arguments
+    /// `name`: The name of the database.  
+    pub async fn open(name: &str) -> crate::Result<Self> {
+        let database = open_database(name).await?;
+        let transaction = database
+            .transaction_with_str_and_mode(OBJECT_STORE, IdbTransactionMode::Readonly)
+            .map_err(js_error)?;
+        let store = transaction.object_store(OBJECT_STORE).map_err(js_error)?;
+        // both are in the order of the keys
+        let keys = store.get_all_keys().map_err(js_error)?;
+        let values = store.get_all().map_err(js_error)?;
+        let keys: Array = request(&keys).await?.unchecked_into();
+        let values: Array = request(&values).await?.unchecked_into();
+        database.close();
+
+        let entries = keys
+            .iter()
+            .zip(values.iter())
+            .map(|(key, value)| (bytes(&key), bytes(&value)))
+            .collect();
+        Ok(Self {
+            name: name.to_owned(),
+            entries: RwLock::new(entries),
+            journal: Journal::default(),
+        })
+    }
+
+    /// Waits until every change has reached the database. Returns the first error that writing a change failed with
+    /// since the last flush, after which changes may have been lost.
+    pub async fn flush(&self) -> crate::Result<()> {
+        self.journal.flush().await
+    }
+
+    /// Queues a change to the database, starting a writer if there isn't one.
+    ///
+    /// # Arguments
+    /// `change`: The change.  
+    fn persist(&self, change: Change) {
+        if self.journal.push(change) {
+            spawn_local(write_changes(self.name.clone(), self.journal.clone()));
+        }
+    }
+}
+
+impl KvStore for IndexedDbStore {
+    fn get(&self, key: &[u8]) -> crate::Result<Option<Vec<u8>>> {
+        self.entries.get(key)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> crate::Result<()> {
+        self.entries.put(key, value)?;
+        self.persist(Change::Put(key.to_vec(), value.to_vec()));
+        Ok(())
+    }
+
+    fn delete(&self, key: &[u8]) -> crate::Result<()> {
+        self.entries.delete(key)?;
+        self.persist(Change::Delete(key.to_vec()));
+        Ok(())
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> crate::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.entries.scan_prefix(prefix)
+    }
+}
+
+/// Writes the changes in a journal to a database until there are none left.
+///
+/// # Arguments
+/// `name`: The name of the database.  
+/// `journal`: The journal.  
+async fn write_changes(name: String, journal: Journal<Change>) {
+    loop {
+        let changes = journal.take();
+        if changes.is_empty() {
+            return;
+        }
+        if let Err(err) = write(&name, changes).await {
+            journal.fail(err);
+        }
+    }
+}
+
+/// Writes changes to a database in a single transaction.
+///
+/// # Arguments
+/// `name`: The name of the database.  
+/// `changes`: The changes.  
+async fn write(name: &str, changes: Vec<Change>) -> crate::Result<()> {
+    let database = open_database(name).await?;
+    let transaction = database
+        .transaction_with_str_and_mode(OBJECT_STORE, IdbTransactionMode::Readwrite)
+        .map_err(js_error)?;
+    let store = transaction.object_store(OBJECT_STORE).map_err(js_error)?;
+    for change in changes {
+        match change {
+            Change::Put(key, value) => {
+                let value = Uint8Array::from(&value[..]);
+                store.put_with_key(&value, &Uint8Array::from(&key[..]))
+            }
+            Change::Delete(key) => store.delete(&Uint8Array::from(&key[..])),
+        }
+        .map_err(js_error)?;
+    }
+
+    let committed = complete(&transaction).await;
+    database.close();
+    committed
+}
+
+/// Opens a database, creating its object store if it's new.
+///
+/// # Arguments
+/// `name`: The name of the database.  
+async fn open_database(name: &str) -> crate::Result<IdbDatabase> {
+    let factory: IdbFactory = global("indexedDB")?;
+    let open = factory.open_with_u32(name, 1).map_err(js_error)?;
+    // the upgrade runs before the request succeeds, so the closure outlives it
+    let upgrade = Closure::<dyn FnMut(JsValue)>::new({
+        let open = open.clone();
+        move |_| {
+            if let Ok(database) = open.result() {
+                let _ = database
+                    .unchecked_into::<IdbDatabase>()
+                    .create_object_store(OBJECT_STORE);
+            }
+        }
+    });
+    open.set_onupgradeneeded(Some(upgrade.as_ref().unchecked_ref()));
+    let database = request(&open).await;
+    drop(upgrade);
+    Ok(database?.unchecked_into())
+}
+
+/// Waits for a request to succeed. Returns its result.
+///
+/// # Arguments
+/// `request`: The request.  
+async fn request(request: &IdbRequest) -> crate::Result<JsValue> {
+    let settled = JsFuture::from(Promise::new(&mut |resolve: Function, reject: Function| {
+        request.set_onsuccess(Some(&resolve));
+        request.set_onerror(Some(&reject));
+    }))
+    .await;
+    request.set_onsuccess(None);
+    request.set_onerror(None);
+
+    match settled {
+        Ok(_) => request.result().map_err(js_error),
+        Err(event) => Err(match request.error() {
+            Ok(Some(exception)) => js_error(exception.into()),
+            _ => js_error(event),
+        }),
+    }
+}
+
+/// Waits for a transaction to commit.
+///
+/// # Arguments
+/// `transaction`: The transaction.  
+async fn complete(transaction: &IdbTransaction) -> crate::Result<()> {
+    let settled = JsFuture::from(Promise::new(&mut |resolve: Function, reject: Function| {
+        transaction.set_oncomplete(Some(&resolve));
+        transaction.set_onerror(Some(&reject));
+        transaction.set_onabort(Some(&reject));
+    }))
+    .await;
+
+    settled
+        .map(|_| ())
+        .map_err(|event| match transaction.error() {
+            Some(exception) => js_error(exception.into()),
+            None => js_error(event),
+        })
+}
+
+/// Copies the bytes of a key or value, which is an `ArrayBuffer` or a `Uint8Array`.
+///
+/// # Arguments
+/// `value`: The key or value.  
+fn bytes(value: &JsValue) -> Vec<u8> {
+    Uint8Array::new(value).to_vec()
+}
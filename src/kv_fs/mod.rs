@@ -0,0 +1,746 @@
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+mod indexed_db;
+
+use crate::file::{DirEntry, File, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::{already_exists, component_iter, invalid_input, not_found, not_supported, now};
+use crate::FileSystem;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub use indexed_db::IndexedDbStore;
+use parking_lot::RwLock;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The size of the chunks files are stored in by default.
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+/// The prefix of the keys of entries.
+const ENTRY_PREFIX: u8 = b'e';
+/// The prefix of the keys of the chunks of files.
+const CHUNK_PREFIX: u8 = b'c';
+/// The kind of an entry that's a file.
+const FILE: u8 = 0;
+/// The kind of an entry that's a directory.
+const DIRECTORY: u8 = 1;
+
+/// An ordered key-value store that a `KvFS` keeps its tree in. Implementing these four methods over a database such
+/// as sled, RocksDB or DynamoDB turns it into a filesystem.
+pub trait KvStore: Send + Sync {
+    /// Returns the value of a key, or `None` if there isn't one.
+    ///
+    /// # Arguments
+    /// `key`: The key.  
+    fn get(&self, key: &[u8]) -> crate::Result<Option<Vec<u8>>>;
+
+    /// Sets the value of a key, replacing any value it had.
+    ///
+    /// # Arguments
+    /// `key`: The key.  
+    /// `value`: The value.  
+    fn put(&self, key: &[u8], value: &[u8]) -> crate::Result<()>;
+
+    /// Deletes a key. Deleting a key that doesn't exist succeeds.
+    ///
+    /// # Arguments
+    /// `key`: The key.  
+    fn delete(&self, key: &[u8]) -> crate::Result<()>;
+
+    /// Returns every key that starts with `prefix` and its value, in any order.
+    ///
+    /// # Arguments
+    /// `prefix`: The prefix.  
+    fn scan_prefix(&self, prefix: &[u8]) -> crate::Result<Vec<(Vec<u8>, Vec<u8>)>>;
+}
+
+/// An in-memory store, for tests and for trees that are later persisted elsewhere.
+impl KvStore for RwLock<BTreeMap<Vec<u8>, Vec<u8>>> {
+    fn get(&self, key: &[u8]) -> crate::Result<Option<Vec<u8>>> {
+        Ok(self.read().get(key).cloned())
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> crate::Result<()> {
+        self.write().insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, key: &[u8]) -> crate::Result<()> {
+        self.write().remove(key);
+        Ok(())
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> crate::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self
+            .read()
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+}
+
+/// A read-write filesystem kept in a key-value store, which emulates the tree on top of its keys.
+///
+/// Each file and directory is an entry keyed by the path of its parent directory and its name, so a directory is
+/// listed by a single prefix scan. The contents of files are stored in fixed-size chunks keyed by their index, and
+/// writes go straight to the store, a chunk at a time. The root directory always exists.
+pub struct KvFS<S: KvStore> {
+    store: Arc<S>,
+    chunk_size: usize,
+}
+
+/// The record of an entry.
+#[derive(Clone, Copy)]
+struct Entry {
+    kind: u8,
+    len: u64,
+    modified: SystemTime,
+}
+
+impl Entry {
+    fn decode(value: &[u8]) -> crate::Result<Self> {
+        let malformed = || io::Error::new(ErrorKind::InvalidData, "Malformed entry");
+        let (&kind, rest) = value.split_first().ok_or_else(malformed)?;
+        if rest.len() != 16 || kind > DIRECTORY {
+            return Err(malformed());
+        }
+        let len = u64::from_le_bytes(rest[..8].try_into().unwrap());
+        let nanos = u64::from_le_bytes(rest[8..].try_into().unwrap());
+        Ok(Self {
+            kind,
+            len,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos),
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let nanos = self
+            .modified
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64);
+        let mut value = vec![self.kind];
+        value.extend_from_slice(&self.len.to_le_bytes());
+        value.extend_from_slice(&nanos.to_le_bytes());
+        value
+    }
+
+    fn metadata(&self) -> Metadata {
+        let metadata = match self.kind {
+            DIRECTORY => Metadata::directory(),
+            _ => Metadata::file(self.len),
+        };
+        Metadata {
+            modified: Some(self.modified),
+            ..metadata
+        }
+    }
+}
+
+impl<S: KvStore> KvFS<S> {
+    /// Creates a filesystem in a store, which may already hold one.
+    ///
+    /// # Arguments
+    /// `store`: The store.  
+    pub fn new(store: S) -> Self {
+        Self {
+            store: Arc::new(store),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// # Arguments
+    /// `chunk_size`: The size of the chunks files are stored in, which must not change once files are written.  
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Returns the store.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the entry at a path, or `None` if there isn't one. The root is always a directory.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn entry(&self, path: &Path) -> crate::Result<Option<Entry>> {
+        if path.as_os_str().is_empty() {
+            return Ok(Some(Entry {
+                kind: DIRECTORY,
+                len: 0,
+                modified: SystemTime::UNIX_EPOCH,
+            }));
+        }
+        self.store
+            .get(&entry_key(path))?
+            .map(|value| Entry::decode(&value))
+            .transpose()
+    }
+
+    /// Fails unless the parent of a path is a directory.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn check_parent(&self, path: &Path) -> crate::Result<()> {
+        match self.entry(path.parent().unwrap_or(Path::new("")))? {
+            Some(entry) if entry.kind == DIRECTORY => Ok(()),
+            _ => Err(not_found()),
+        }
+    }
+
+    /// Returns the names and entries of the children of a directory.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path of the directory.  
+    fn children(&self, path: &Path) -> crate::Result<Vec<(String, Entry)>> {
+        let prefix = children_prefix(path);
+        self.store
+            .scan_prefix(&prefix)?
+            .into_iter()
+            .map(|(key, value)| {
+                let name = String::from_utf8(key[prefix.len()..].to_vec())
+                    .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Malformed entry name"))?;
+                Ok((name, Entry::decode(&value)?))
+            })
+            .collect()
+    }
+
+    /// Deletes the chunks of a file.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path of the file.  
+    fn delete_chunks(&self, path: &Path) -> crate::Result<()> {
+        for (key, _) in self.store.scan_prefix(&chunk_prefix(path))? {
+            self.store.delete(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Moves an entry and everything under it.
+    ///
+    /// # Arguments
+    /// `from`: The normalized path of the entry.  
+    /// `to`: The normalized path to move it to.  
+    /// `entry`: The entry.  
+    fn move_entry(&self, from: &Path, to: &Path, entry: Entry) -> crate::Result<()> {
+        self.store.put(&entry_key(to), &entry.encode())?;
+        if entry.kind == DIRECTORY {
+            for (name, child) in self.children(from)? {
+                self.move_entry(&from.join(&name), &to.join(&name), child)?;
+            }
+        } else {
+            let from_prefix = chunk_prefix(from);
+            let to_prefix = chunk_prefix(to);
+            for (key, chunk) in self.store.scan_prefix(&from_prefix)? {
+                let to_key = [&to_prefix, &key[from_prefix.len()..]].concat();
+                self.store.put(&to_key, &chunk)?;
+                self.store.delete(&key)?;
+            }
+        }
+        self.store.delete(&entry_key(from))
+    }
+}
+
+impl<S: KvStore + 'static> FileSystem for KvFS<S> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        let path = normalize_and_relativize(path);
+        if self.entry(&path)?.is_some() {
+            return Err(already_exists());
+        }
+        self.check_parent(&path)?;
+
+        let entry = Entry {
+            kind: DIRECTORY,
+            len: 0,
+            modified: now(),
+        };
+        self.store.put(&entry_key(&path), &entry.encode())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        let path = normalize_and_relativize(path);
+        // the root has no entry of its own
+        if path.as_os_str().is_empty() {
+            return Ok(Metadata::directory());
+        }
+        self.entry(&path)?
+            .map(|entry| entry.metadata())
+            .ok_or_else(not_found)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        let path = normalize_and_relativize(path);
+        let writable = options.writable();
+
+        let entry = match self.entry(&path)? {
+            Some(_) if options.create_new => return Err(already_exists()),
+            Some(entry) if entry.kind == DIRECTORY => {
+                return Err(invalid_input("path is a directory"))
+            }
+            Some(entry) if writable && options.truncate => {
+                self.delete_chunks(&path)?;
+                Some(entry)
+            }
+            Some(entry) => return Ok(self.file(path, entry, options, writable)),
+            None if options.create || options.create_new => {
+                self.check_parent(&path)?;
+                None
+            }
+            None => return Err(not_found()),
+        };
+
+        // the file is created or truncated from the moment it's opened, even if it's never written
+        let entry = Entry {
+            kind: FILE,
+            len: 0,
+            modified: match entry {
+                Some(entry) if entry.len == 0 => entry.modified,
+                _ => now(),
+            },
+        };
+        self.store.put(&entry_key(&path), &entry.encode())?;
+        Ok(self.file(path, entry, options, writable))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let directory = normalize_and_relativize(path);
+        match self.entry(&directory)? {
+            Some(entry) if entry.kind == DIRECTORY => {}
+            Some(_) => return Err(invalid_input("path is not a directory")),
+            None => return Err(not_found()),
+        }
+
+        let children = self.children(&directory)?;
+        Ok(Box::new(children.into_iter().map(move |(name, entry)| {
+            Ok(DirEntry {
+                path: directory.join(name),
+                metadata: entry.metadata(),
+            })
+        })))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        let path = normalize_and_relativize(path);
+        if path.as_os_str().is_empty() {
+            return Err(not_supported());
+        }
+        match self.entry(&path)? {
+            Some(entry) if entry.kind == DIRECTORY => {}
+            Some(_) => return Err(invalid_input("path is not a directory")),
+            None => return Err(not_found()),
+        }
+        if !self.store.scan_prefix(&children_prefix(&path))?.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::DirectoryNotEmpty,
+                "Directory not empty",
+            ));
+        }
+
+        self.store.delete(&entry_key(&path))
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        let path = normalize_and_relativize(path);
+        match self.entry(&path)? {
+            Some(entry) if entry.kind == DIRECTORY => {
+                return Err(invalid_input("path is a directory"))
+            }
+            Some(_) => {}
+            None => return Err(not_found()),
+        }
+
+        self.delete_chunks(&path)?;
+        self.store.delete(&entry_key(&path))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        let (from, to) = (normalize_and_relativize(from), normalize_and_relativize(to));
+        if from.as_os_str().is_empty() {
+            return Err(not_supported());
+        }
+        let entry = self.entry(&from)?.ok_or_else(not_found)?;
+        if from == to {
+            return Ok(());
+        }
+        if to.starts_with(&from) {
+            return Err(invalid_input("Cannot move a directory into itself"));
+        }
+        self.check_parent(&to)?;
+
+        // an existing file is replaced, as it would be by a local rename
+        match self.entry(&to)? {
+            Some(existing) if existing.kind == DIRECTORY || entry.kind == DIRECTORY => {
+                return Err(already_exists())
+            }
+            Some(_) => self.delete_chunks(&to)?,
+            None => {}
+        }
+        self.move_entry(&from, &to, entry)
+    }
+}
+
+impl<S: KvStore + 'static> KvFS<S> {
+    /// Returns a handle to an open file.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path of the file.  
+    /// `entry`: The entry of the file.  
+    /// `options`: The options the file was opened with.  
+    /// `writable`: True if the file can be written.  
+    fn file(
+        &self,
+        path: PathBuf,
+        entry: Entry,
+        options: &OpenOptions,
+        writable: bool,
+    ) -> Box<dyn File> {
+        Box::new(KvFile {
+            store: self.store.clone(),
+            chunk_size: self.chunk_size,
+            pos: if options.append { entry.len } else { 0 },
+            path,
+            entry,
+            append: options.append,
+            writable,
+        })
+    }
+}
+
+/// Returns the key of the entry at a path, which is its parent's path and its name separated by a null byte.
+///
+/// # Arguments
+/// `path`: The normalized path, which isn't the root.  
+fn entry_key(path: &Path) -> Vec<u8> {
+    let mut key = children_prefix(path.parent().unwrap_or(Path::new("")));
+    key.extend_from_slice(path.file_name().unwrap_or_default().as_encoded_bytes());
+    key
+}
+
+/// Returns the prefix of the keys of the children of a directory.
+///
+/// # Arguments
+/// `path`: The normalized path of the directory.  
+fn children_prefix(path: &Path) -> Vec<u8> {
+    let mut key = vec![ENTRY_PREFIX];
+    key.extend(component_iter(path).collect::<Vec<_>>().join("/").bytes());
+    key.push(0);
+    key
+}
+
+/// Returns the prefix of the keys of the chunks of a file, which are followed by their big-endian indices.
+///
+/// # Arguments
+/// `path`: The normalized path of the file.  
+fn chunk_prefix(path: &Path) -> Vec<u8> {
+    let mut key = vec![CHUNK_PREFIX];
+    key.extend(component_iter(path).collect::<Vec<_>>().join("/").bytes());
+    key.push(0);
+    key
+}
+
+/// A file whose chunks are read and written in the store as it's used.
+struct KvFile<S: KvStore> {
+    store: Arc<S>,
+    chunk_size: usize,
+    path: PathBuf,
+    entry: Entry,
+    pos: u64,
+    append: bool,
+    writable: bool,
+}
+
+impl<S: KvStore> KvFile<S> {
+    /// Returns the key of a chunk.
+    ///
+    /// # Arguments
+    /// `index`: The index of the chunk.  
+    fn chunk_key(&self, index: u64) -> Vec<u8> {
+        let mut key = chunk_prefix(&self.path);
+        key.extend_from_slice(&index.to_be_bytes());
+        key
+    }
+}
+
+impl<S: KvStore> File for KvFile<S> {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(self.entry.metadata())
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        if !self.writable {
+            return Err(not_supported());
+        }
+
+        // chunks past the end are deleted and the last one is cut, so that extending the file reads zeros
+        if len < self.entry.len {
+            let chunk_size = self.chunk_size as u64;
+            let kept = len.div_ceil(chunk_size);
+            for (key, _) in self.store.scan_prefix(&chunk_prefix(&self.path))? {
+                // unwrap: chunk keys end with their 8-byte index
+                let index = u64::from_be_bytes(key[key.len() - 8..].try_into().unwrap());
+                if index >= kept {
+                    self.store.delete(&key)?;
+                }
+            }
+
+            let offset = (len % chunk_size) as usize;
+            let key = self.chunk_key(len / chunk_size);
+            if let Some(mut chunk) = self.store.get(&key)?.filter(|chunk| chunk.len() > offset) {
+                chunk.truncate(offset);
+                self.store.put(&key, &chunk)?;
+            }
+        }
+
+        self.entry.len = len;
+        self.entry.modified = now();
+        self.store.put(&entry_key(&self.path), &self.entry.encode())
+    }
+}
+
+impl<S: KvStore> Read for KvFile<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.entry.len {
+            return Ok(0);
+        }
+
+        let chunk_size = self.chunk_size as u64;
+        let (index, offset) = (self.pos / chunk_size, (self.pos % chunk_size) as usize);
+        let n = buf
+            .len()
+            .min(self.chunk_size - offset)
+            .min((self.entry.len - self.pos) as usize);
+        // chunks that were never written are zero-filled
+        let chunk = self.store.get(&self.chunk_key(index))?.unwrap_or_default();
+        buf[..n].fill(0);
+        if let Some(data) = chunk.get(offset..) {
+            let len = data.len().min(n);
+            buf[..len].copy_from_slice(&data[..len]);
+        }
+
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<S: KvStore> Seek for KvFile<S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base_pos, offset) = match pos {
+            SeekFrom::Start(n) => (n, 0),
+            SeekFrom::Current(n) => (self.pos, n),
+            SeekFrom::End(n) => (self.entry.len, n),
+        };
+
+        let Some(n) = base_pos.checked_add_signed(offset) else {
+            return Err(invalid_input(
+                "Invalid seek to a negative or overflowing position",
+            ));
+        };
+        self.pos = n;
+        Ok(n)
+    }
+}
+
+impl<S: KvStore> Write for KvFile<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.writable {
+            return Err(not_supported());
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.append {
+            self.pos = self.entry.len;
+        }
+
+        let chunk_size = self.chunk_size as u64;
+        let (index, offset) = (self.pos / chunk_size, (self.pos % chunk_size) as usize);
+        let n = buf.len().min(self.chunk_size - offset);
+        let key = self.chunk_key(index);
+        let mut chunk = self.store.get(&key)?.unwrap_or_default();
+        if chunk.len() < offset + n {
+            chunk.resize(offset + n, 0);
+        }
+        chunk[offset..offset + n].copy_from_slice(&buf[..n]);
+        self.store.put(&key, &chunk)?;
+
+        self.pos += n as u64;
+        self.entry.len = self.entry.len.max(self.pos);
+        self.entry.modified = now();
+        self.store
+            .put(&entry_key(&self.path), &self.entry.encode())?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::{Metadata, OpenOptions};
+    use crate::kv_fs::KvFS;
+    use crate::util::test::{check_open_options, check_set_len, read_directory};
+    use crate::FileSystem;
+    use parking_lot::RwLock;
+    use std::collections::BTreeMap;
+    use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+
+    fn kv_fs() -> KvFS<RwLock<BTreeMap<Vec<u8>, Vec<u8>>>> {
+        KvFS::new(RwLock::default()).chunk_size(4)
+    }
+
+    #[test]
+    fn read_write() {
+        let fs = kv_fs();
+        fs.create_dir("dir").unwrap();
+        fs.create_dir("dir/nested").unwrap();
+        assert_eq!(
+            fs.create_dir("dir").err().unwrap().kind(),
+            ErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            fs.create_dir("missing/dir").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        // the contents span several chunks
+        let mut file = fs.create_file("dir/file").unwrap();
+        write!(file, "hello world").unwrap();
+        file.seek(SeekFrom::Start(3)).unwrap();
+        write!(file, "LO").unwrap();
+        drop(file);
+        assert_eq!(
+            fs.open_file("dir/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "helLO world"
+        );
+        assert_eq!(fs.metadata("dir/file").unwrap().len(), 11);
+        assert!(fs.metadata("dir/file").unwrap().modified.is_some());
+        assert!(fs.metadata("").unwrap().is_directory());
+
+        // gaps are zero-filled
+        let mut file = fs.create_file("sparse").unwrap();
+        file.seek(SeekFrom::Start(9)).unwrap();
+        write!(file, "x").unwrap();
+        drop(file);
+        let mut contents = Vec::new();
+        fs.open_file("sparse")
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"\0\0\0\0\0\0\0\0\0x");
+
+        let mut file = fs
+            .open_file_options("dir/file", &OpenOptions::default().append(true))
+            .unwrap();
+        write!(file, "!").unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 12);
+        drop(fs.create_file("sparse").unwrap());
+        assert_eq!(fs.metadata("sparse").unwrap().len(), 0);
+        assert!(write!(fs.open_file("dir/file").unwrap(), "x").is_err());
+        assert!(fs.open_file("dir").is_err());
+        assert!(fs.create_file("missing/file").is_err());
+
+        itertools::assert_equal(read_directory(&fs, "").keys(), vec!["dir", "sparse"]);
+        let dir = read_directory(&fs, "dir");
+        itertools::assert_equal(dir.keys(), vec!["dir/file", "dir/nested"]);
+        assert!(dir["dir/nested"].is_directory());
+        assert_eq!(dir["dir/file"].len(), 12);
+        assert!(fs.read_dir("sparse").is_err());
+
+        // a store holding a tree opens as the same filesystem
+        let reopened = KvFS::new(RwLock::new(fs.store().read().clone())).chunk_size(4);
+        assert_eq!(
+            reopened
+                .open_file("dir/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "helLO world!"
+        );
+    }
+
+    #[test]
+    fn remove_and_rename() {
+        let fs = kv_fs();
+        fs.create_dir("dir").unwrap();
+        fs.create_dir("dir/nested").unwrap();
+        write!(fs.create_file("dir/nested/file").unwrap(), "nested file").unwrap();
+        write!(fs.create_file("file").unwrap(), "file").unwrap();
+        write!(fs.create_file("other").unwrap(), "other file").unwrap();
+
+        // directories move with everything in them
+        fs.rename("dir", "moved").unwrap();
+        assert!(!fs.exists("dir").unwrap());
+        assert_eq!(
+            fs.open_file("moved/nested/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "nested file"
+        );
+        assert!(fs.rename("moved", "moved/nested/inner").is_err());
+        assert!(fs.rename("file", "moved").is_err());
+
+        // files replace the files they're renamed to
+        fs.rename("other", "file").unwrap();
+        assert_eq!(
+            fs.open_file("file").unwrap().read_into_string().unwrap(),
+            "other file"
+        );
+        itertools::assert_equal(read_directory(&fs, "").keys(), vec!["file", "moved"]);
+
+        assert_eq!(
+            fs.remove_dir("moved").err().unwrap().kind(),
+            ErrorKind::DirectoryNotEmpty
+        );
+        assert!(fs.remove_file("moved").is_err());
+        assert!(fs.remove_dir("file").is_err());
+        fs.remove_file("moved/nested/file").unwrap();
+        fs.remove_dir("moved/nested").unwrap();
+        fs.remove_dir("moved").unwrap();
+        fs.remove_file("file").unwrap();
+        assert_eq!(
+            fs.remove_file("file").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        // nothing is left behind in the store
+        assert!(fs.store().read().is_empty());
+        assert_eq!(fs.metadata("").unwrap(), Metadata::directory());
+    }
+
+    #[test]
+    fn open_options() {
+        check_open_options(&kv_fs(), "options");
+    }
+
+    #[test]
+    fn set_len() {
+        let fs = kv_fs();
+        check_set_len(&fs, "set_len");
+
+        // chunks past the new end are removed, so growing the file again reads zeros
+        write!(fs.create_file("chunks").unwrap(), "hello world").unwrap();
+        let mut file = fs
+            .open_file_options("chunks", &OpenOptions::new().write(true))
+            .unwrap();
+        file.set_len(6).unwrap();
+        file.set_len(11).unwrap();
+        drop(file);
+        assert_eq!(
+            fs.open_file("chunks").unwrap().read_into_vec().unwrap(),
+            b"hello \0\0\0\0\0"
+        );
+    }
+}
@@ -5,17 +5,140 @@
 //! `virtual-fs` has the following FileSystems implemented out of the box:
 //! - `PhysicalFS`: A read-write physical filesystem mounted at a directory. Path traversal outside the root is permitted.
 //! - `SandboxedPhysicalFS`: A read-write physical filesystem that guards against traversal through backtracking and symbolic link
-//! traversal.
+//!   traversal.
 //! - `MemoryFS`: A read-write in-memory filesystem.
+//! - `NullFS`: A filesystem that discards writes and reads from a configurable synthetic tree of zero-filled files,
+//!   to benchmark an application apart from its backend.
 //! - `RocFS`: A "read-only collection" filesystem. This filesystem is similar to `OverlayFS`, but is read-only. This
-//! filesystem searches filesystems in mount-order for files, allowing multiple filesystems to be mounted at once.
+//!   filesystem searches filesystems in mount-order for files, allowing multiple filesystems to be mounted at once.
+//! - `OverlayFS`: A read-write filesystem layered over a `RocFS`. Files from the lower layers are copied up to the upper
+//!   layer when they're written, and removals are recorded as in-memory whiteouts.
+//! - `CowFS`: A copy-on-write filesystem over a read-only base, such as a `ZipFS` or `TarFS`, whose writes and removals
+//!   land in a writable upper layer along with persistent whiteouts.
 //! - `MountableFS`: A read-write filesystem that supports mounting other filesystems at given paths.
 //! - `ZipFS`: A read-only filesystem that mounts a ZIP archive, backed by the `zip` crate.
 //! - `TarFS` A read-only filesystem that mounts a Tarball, backed by the `tar` crate.
+//! - `CpioFS`: A read-only filesystem that mounts a "newc" cpio archive, such as an initramfs image. Archives can be
+//!   assembled with `CpioBuilder`, or written from another filesystem with `write_cpio`.
+//! - `AutoMountFS`: A filesystem that transparently mounts the ZIP archives and Tarballs within another filesystem as
+//!   directories as they are traversed.
+//! - `CachingFS`: A filesystem that caches the metadata and contents of the files of a slow filesystem in memory,
+//!   evicting the least recently used entries to stay within a budget in bytes.
+//! - `QuotaFS`: A filesystem that limits the number of bytes and files stored in another filesystem, and reports their
+//!   usage.
+//! - `ReadOnlyFS`: A filesystem that exposes another filesystem read-only, rejecting every modification with
+//!   `ReadOnlyFilesystem`.
+//! - `SubdirFS`: A filesystem that exposes a directory of another filesystem as its root, confining every path to it.
+//! - `ThrottleFS`: A filesystem that slows down another filesystem with a latency, jitter and bandwidth limits, to test
+//!   against slow disks and network filesystems.
+//! - `TtlFS`: A filesystem whose files expire after a time-to-live since they were last written or an idle period
+//!   since they were last opened, for caches of generated thumbnails and artifacts.
+//! - `VersionedFS`: A filesystem that keeps the prior versions of the files of another filesystem as they're changed,
+//!   which can be listed, opened and restored as of any earlier snapshot.
+//! - `KvFS`: A read-write filesystem kept in any ordered key-value store that implements the four methods of
+//!   `KvStore`, with the tree emulated over its keys and the contents of files stored in chunks.
+//!
+//! The following optional features are available:
+//! - `cap-std`: Enables `CapPhysicalFS`, a sandboxed physical filesystem that resolves paths relative to an open
+//!   handle to its root, which is immune to symbolic links being swapped in while a path is opened, and implements
+//!   `FileSystem` for `cap_std::fs::Dir` so that directory handles can be mounted or layered directly.
+//! - `checksum`: Enables `util::hash_tree`, which computes the SHA-1 or SHA-256 hashes of the files within a directory
+//!   and a Merkle digest of it, such as to verify an extracted install against the archive it was shipped in.
+//! - `compression`: Enables `CompressedFS`, which transparently stores the files of another filesystem
+//!   zstd-compressed, according to a policy on their extensions and sizes.
+//! - `config`: Enables `MountableFS::from_config`, which builds a mount table from a TOML or JSON configuration.
+//! - `dedup`: Enables `DedupFS`, which deduplicates the files stored in another filesystem by storing their bodies
+//!   once by their SHA-256 hash, and collects the bodies that are no longer referenced with `gc`.
+//! - `embed`: Enables `embed_dir!`, which embeds a directory into the binary at build time and constructs a `MemoryFS`
+//!   preloaded with its contents, optionally storing them gzip-compressed.
+//! - `encryption`: Enables `EncryptedFS`, which transparently encrypts the contents and optionally the names of the
+//!   files in another filesystem with AES-256-GCM or XChaCha20-Poly1305.
+//! - `fat`: Enables `FatFS`, a read-write filesystem on a FAT12, FAT16, FAT32 or exFAT disk image, such as an SD card
+//!   image, which can also be formatted from scratch.
+//! - `ftp`: Enables `FtpFS`, a read-write filesystem on a remote FTP server, with passive or active data
+//!   connections.
+//! - `ftps`: Enables `FtpFS::connect_tls`, which secures the control and data connections with TLS through
+//!   `rustls`.
+//! - `fuse`: Enables `fuse::mount` and `fuse::spawn_mount`, which mount any filesystem on Linux through FUSE with `fuser`, so that
+//!   a `ZipFS`, `MemoryFS` or `RocFS` can be browsed by every program.
+//! - `google-drive`: Enables `GoogleDriveFS`, a read-write filesystem on a Google Drive through the Drive API,
+//!   authorized by OAuth token providers that refresh their access tokens.
+//! - `gzip`, `xz`, `zstd`, `bzip2`: Enable transparent decompression of the respective formats in `TarFS::open`.
+//! - `http`: Enables `HttpFS`, a read-only filesystem of the resources under a base URL, and `RangeReader`, which
+//!   reads remote resources through HTTP range requests so that a remote ZIP archive can be mounted without downloading
+//!   it entirely.
+//! - `http-body`: Enables `FileBody`, an `http_body::Body` that streams a file in chunks of a configurable size so
+//!   that `hyper` and `axum` services can send large archive entries without buffering them, and `write_body`, which
+//!   streams a body into a file.
+//! - `include_dir`, `rust-embed`: Enable `EmbeddedFS`, a read-only filesystem over the assets compiled into the
+//!   binary by an `include_dir::Dir` or a `rust_embed::RustEmbed` type respectively.
+//! - `ipfs`: Enables `IpfsFS`, a read-only filesystem on a UnixFS directory published to IPFS, fetched from a local
+//!   or public HTTP gateway and verified block by block against its CID, so it can back a `RocFS` layer.
+//! - `metrics`: Enables `MetricsFS`, which records counters and latency histograms of every operation on another
+//!   filesystem, and the bytes read and written through it, through the `metrics` facade.
+//! - `mmap`: Enables `ZipFS::new_mmap`, which mounts a memory-mapped ZIP archive. It's `unsafe`, since the
+//!   archive must not be modified while it's mapped.
+//! - `nfs`: Enables `NfsFS`, a read-write filesystem on an export of an NFSv3 server, spoken to over TCP entirely in
+//!   userspace so that nothing has to be mounted by the kernel, and `nfs::serve`, which exports any filesystem to
+//!   other machines as an NFSv3 server. NFSv4 isn't supported by either.
+//! - `ninep`: Enables `NinePFS`, a read-write filesystem on a 9P2000.L server over TCP or a Unix socket, such as a
+//!   directory shared with a virtual machine, and `ninep::serve`, which exports any filesystem over 9P2000.L so that
+//!   virtual machines can mount it.
+//! - `ntfs`: Enables `NtfsFS`, a read-only filesystem on an NTFS partition image, with access to the alternate data
+//!   streams of its files.
+//! - `onedrive`: Enables `OneDriveFS`, a read-write filesystem on a OneDrive or SharePoint document library through
+//!   the Microsoft Graph API, authorized by OAuth token providers that refresh their access tokens.
+//! - `projfs`: Enables `projfs::project`, which projects any filesystem into a directory on Windows through the
+//!   Projected File System, hydrating files on demand so that tools that need real paths can read a `ZipFS` or
+//!   `TarFS` without it being extracted.
+//! - `remote`: Enables `RemoteFS`, a read-write filesystem served by another process over TCP or any other stream,
+//!   and `remote::serve_remote`, which shares any filesystem, such as a composed `MountableFS`, with `RemoteFS`
+//!   clients over a compact binary protocol with streamed reads and writes and pluggable token authentication.
+//! - `s3`: Enables `S3FS`, a read-write filesystem on an S3 bucket or S3-compatible object store, with directories
+//!   emulated over key prefixes. Large files are streamed as multipart uploads, and files must be flushed to see
+//!   upload errors.
+//! - `shell`: Enables `shell::run`, a small interactive shell with `cd`, `ls`, `cat`, `put`, `get` and `stat` over any
+//!   filesystem, for inspecting layered `RocFS` or `MountableFS` configurations live from a debug console.
+//! - `smb`: Enables `SmbFS`, a read-write filesystem on a Windows or Samba share over SMB 2 and 3, with NTLMv2
+//!   authentication, message signing, and DFS referrals followed to the shares they point to.
+//! - `ssh-server`: Enables `sftp::Server`, an SSH server that serves any filesystem with the SFTP subsystem to
+//!   clients such as `sftp` and `sshfs`, with password and Ed25519 key authentication and an optional read-only mode.
+//! - `tokio`: Enables `AsyncFileSystem` and `AsyncFile`, whose operations don't block, and `TokioPhysicalFS`, which
+//!   implements them on `tokio::fs` with directory listings streamed as they're read.
+//! - `tower`: Enables `ServeVfs`, a `tower` service that serves the files of any filesystem over HTTP with range
+//!   requests, ETags and directory listings, which `axum` routes to like `tower_http`'s `ServeDir`.
+//! - `tracing`: Enables `TracingFS`, which emits `tracing` spans and events with the path, duration, byte counts and
+//!   result of every operation on another filesystem.
+//! - `vfs-compat`: Enables `FromVfs` and `IntoVfs`, which wrap a `vfs::FileSystem` as a filesystem of this crate and
+//!   vice versa, for projects migrating from existing `vfs`-based backends.
+//! - `wasi-host`: Enables `wasi_host`, which implements the WASI preview 2 `wasi:filesystem` interface on any
+//!   filesystem for `wasmtime` guests, so a `MemoryFS`, `ZipFS` or `OverlayFS` can be their world, sandboxed by
+//!   construction.
+//! - `wasm`: Enables `OpfsFS`, a read-write filesystem in the origin private file system of the browser, and
+//!   `kv_fs::IndexedDbStore`, a `KvStore` in an IndexedDB database, on `wasm32-unknown-unknown`. Both are read into
+//!   memory when they're opened and persist changes in the background, which `flush` waits for.
+//! - `watch`: Enables `PhysicalFS::watch`, which reports changes within the filesystem through the `notify` crate.
+//! - `webdav`: Enables `webdav::Server`, which serves any filesystem over WebDAV so that file managers such as Finder
+//!   and Explorer can browse a `MemoryFS` or `ZipFS` directly.
+//! - `websocket`: Enables `RemoteFS::connect_websocket` and `remote::websocket::serve_websocket`, which carry the
+//!   remote filesystem protocol over WebSocket so that clients can reach a filesystem through HTTP proxies, and
+//!   browser frontends can speak it with their own WebSocket.
+//!
+//! The crate builds for `wasm32-unknown-unknown`, so the same `FileSystem`-based code can run in the browser on a
+//! `MemoryFS`, a `ZipFS` over the bytes of a fetched archive, or, with the `wasm` feature, an `OpfsFS` or a `KvFS` over
+//! an `IndexedDbStore`. There, `ZipFS` reads stored and deflated entries only, and `ThrottleFS` adds no latency since
+//! the thread can't block. Times are read from the clock of the browser with the `wasm` feature, and are the Unix
+//! epoch without it.
+//!
+//! The `vfs-cli` binary in the workspace, built with its `cli` feature, lists, prints, copies and extracts the
+//! contents of `dir://`, `zip://` and `tar://` URIs and overlays of them, such as
+//! `vfs-cli tree dir://patches+zip://base.zip`.
 
-use crate::file::{DirEntry, File, Metadata, OpenOptions};
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use duplicate::duplicate_item;
 use mockall::automock;
 use std::io::ErrorKind;
+use std::sync::Arc;
 
 pub use error::*;
 
@@ -26,7 +149,8 @@ pub trait FileSystem {
     fn create_dir(&self, path: &str) -> Result<()>;
     /// Returns the metadata for the file/folder at `path.
     fn metadata(&self, path: &str) -> Result<Metadata>;
-    /// Opens a file at `path` with options `options`.
+    /// Opens a file at `path` with options `options`, which follow the semantics of `std::fs::OpenOptions`.
+    /// Combinations of options that `OpenOptions::validate` rejects fail with `ErrorKind::InvalidInput`.
     fn open_file_options(&self, path: &str, options: &OpenOptions) -> Result<Box<dyn File>>;
     /// Lists the files and folders contained in the directory denoted by `path`.
     fn read_dir(&self, path: &str) -> Result<Box<dyn Iterator<Item = Result<DirEntry>>>>;
@@ -35,6 +159,12 @@ pub trait FileSystem {
     /// Removes a file at `path`.
     fn remove_file(&self, path: &str) -> Result<()>;
 
+    /// Creates a directory at `path` with the Unix mode bits `mode`, before the process's umask is applied. Filesystems
+    /// without permissions ignore the mode.
+    fn create_dir_with(&self, path: &str, mode: u32) -> Result<()> {
+        let _ = mode;
+        self.create_dir(path)
+    }
     /// Creates a directory `path` and all of its parents.
     fn create_dir_all(&self, path: &str) -> Result<()> {
         util::create_dir_all(self, path)
@@ -42,7 +172,13 @@ pub trait FileSystem {
     /// Creates a file at `path` in write mode. The file will be opened in truncate mode, so all contents will be
     /// overwritten. If this is not desirable, use `open_file` directly.
     fn create_file(&self, path: &str) -> Result<Box<dyn File>> {
-        self.open_file_options(path, &OpenOptions::default().create(true).truncate(true))
+        self.open_file_options(
+            path,
+            &OpenOptions::default()
+                .write(true)
+                .create(true)
+                .truncate(true),
+        )
     }
     /// Returns `Ok(true)` or `Ok(false)` if a file or folder at `path` does or does not exist, and `Err(_)` if the
     /// presence cannot be verified.  
@@ -57,15 +193,194 @@ pub trait FileSystem {
     fn open_file(&self, path: &str) -> Result<Box<dyn File>> {
         self.open_file_options(path, &OpenOptions::default())
     }
+    /// Copies the contents of the file at `from` to the file at `to`, which is created or truncated. Returns the
+    /// number of bytes copied.
+    fn copy_file(&self, from: &str, to: &str) -> Result<u64> {
+        util::copy_file(self, from, to)
+    }
+    /// Renames the file or directory at `from` to `to`, replacing the file at `to` if there is one. By default, only
+    /// files can be renamed, by copying them and removing the original, so the rename isn't atomic.
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        util::rename(self, from, to)
+    }
+    /// Recursively walks the directory at `path`. Entries are returned depth-first, with each directory preceding its
+    /// contents, and their paths are relative to the root of the filesystem.
+    fn walk_dir<'a>(
+        &'a self,
+        path: &str,
+    ) -> Result<Box<dyn Iterator<Item = Result<DirEntry>> + 'a>> {
+        util::walk_dir(self, path)
+    }
+    /// Returns the capacity of the volume backing the filesystem. Filesystems that aren't backed by a volume return
+    /// `Unsupported`.
+    fn stats(&self) -> Result<FileSystemStats> {
+        Err(util::not_supported())
+    }
 }
 
+#[duplicate_item(pointer; [Box]; [Arc])]
+impl<T: FileSystem + ?Sized> FileSystem for pointer<T> {
+    fn create_dir(&self, path: &str) -> Result<()> {
+        (**self).create_dir(path)
+    }
+    fn metadata(&self, path: &str) -> Result<Metadata> {
+        (**self).metadata(path)
+    }
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> Result<Box<dyn File>> {
+        (**self).open_file_options(path, options)
+    }
+    fn read_dir(&self, path: &str) -> Result<Box<dyn Iterator<Item = Result<DirEntry>>>> {
+        (**self).read_dir(path)
+    }
+    fn remove_dir(&self, path: &str) -> Result<()> {
+        (**self).remove_dir(path)
+    }
+    fn remove_file(&self, path: &str) -> Result<()> {
+        (**self).remove_file(path)
+    }
+    fn create_dir_with(&self, path: &str, mode: u32) -> Result<()> {
+        (**self).create_dir_with(path, mode)
+    }
+    fn create_dir_all(&self, path: &str) -> Result<()> {
+        (**self).create_dir_all(path)
+    }
+    fn create_file(&self, path: &str) -> Result<Box<dyn File>> {
+        (**self).create_file(path)
+    }
+    fn exists(&self, path: &str) -> Result<bool> {
+        (**self).exists(path)
+    }
+    fn open_file(&self, path: &str) -> Result<Box<dyn File>> {
+        (**self).open_file(path)
+    }
+    fn copy_file(&self, from: &str, to: &str) -> Result<u64> {
+        (**self).copy_file(from, to)
+    }
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        (**self).rename(from, to)
+    }
+    fn walk_dir<'a>(
+        &'a self,
+        path: &str,
+    ) -> Result<Box<dyn Iterator<Item = Result<DirEntry>> + 'a>> {
+        (**self).walk_dir(path)
+    }
+    fn stats(&self) -> Result<FileSystemStats> {
+        (**self).stats()
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub mod async_fs;
+pub mod auto_mount_fs;
+pub mod caching_fs;
+#[cfg(any(feature = "google-drive", feature = "onedrive"))]
+pub mod cloud_drive;
+#[cfg(feature = "compression")]
+pub mod compressed_fs;
+pub mod cow_fs;
+pub mod cpio_fs;
+#[cfg(feature = "dedup")]
+pub mod dedup_fs;
+#[cfg(any(feature = "include_dir", feature = "rust-embed"))]
+pub mod embedded_fs;
+#[cfg(feature = "encryption")]
+pub mod encrypted_fs;
 pub mod error;
+#[cfg(feature = "fat")]
+pub mod fat_fs;
 pub mod file;
+#[cfg(feature = "http-body")]
+pub mod file_body;
+#[cfg(feature = "ftp")]
+pub mod ftp_fs;
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+pub mod fuse;
+#[cfg(feature = "google-drive")]
+pub mod google_drive_fs;
+#[cfg(feature = "http")]
+pub mod http_fs;
+#[cfg(feature = "ipfs")]
+pub mod ipfs_fs;
+pub mod kv_fs;
 pub mod memory_fs;
+#[cfg(feature = "metrics")]
+pub mod metrics_fs;
 pub mod mountable_fs;
+#[cfg(feature = "nfs")]
+pub mod nfs;
+#[cfg(feature = "nfs")]
+pub mod nfs_fs;
+#[cfg(feature = "ninep")]
+pub mod ninep;
+#[cfg(feature = "ninep")]
+pub mod ninep_fs;
+#[cfg(feature = "ntfs")]
+pub mod ntfs_fs;
+pub mod null_fs;
+#[cfg(feature = "onedrive")]
+pub mod onedrive_fs;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod opfs_fs;
+pub mod overlay_fs;
 pub mod physical_fs;
+#[cfg(all(feature = "projfs", windows))]
+pub mod projfs;
+pub mod quota_fs;
+#[cfg(any(
+    feature = "google-drive",
+    feature = "http",
+    feature = "ipfs",
+    feature = "onedrive",
+    feature = "s3"
+))]
+pub mod range_reader;
+pub mod read_only_fs;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(feature = "remote")]
+pub mod remote_fs;
 pub mod roc_fs;
+#[cfg(feature = "s3")]
+pub mod s3_fs;
+#[cfg(feature = "tower")]
+pub mod serve_vfs;
+#[cfg(feature = "ssh-server")]
+pub mod sftp;
+#[cfg(feature = "shell")]
+pub mod shell;
+#[cfg(feature = "smb")]
+pub mod smb_fs;
+pub mod subdir_fs;
 pub mod tar_fs;
+pub mod throttle_fs;
+#[cfg(any(
+    feature = "fat",
+    feature = "ftp",
+    feature = "google-drive",
+    feature = "onedrive",
+    feature = "s3",
+    feature = "ssh-server",
+    feature = "webdav"
+))]
+mod time;
+#[cfg(feature = "tracing")]
+pub mod tracing_fs;
 mod tree;
+pub mod ttl_fs;
 pub mod util;
+pub mod versioned_fs;
+#[cfg(feature = "vfs-compat")]
+pub mod vfs_compat;
+#[cfg(feature = "wasi-host")]
+pub mod wasi_host;
+#[cfg(feature = "webdav")]
+pub mod webdav;
 pub mod zip_fs;
+
+#[cfg(feature = "embed")]
+pub use virtual_filesystem_macros::embed_dir;
+
+// lets `embed_dir!` refer to this crate by name from within it
+#[cfg(all(test, feature = "embed"))]
+extern crate self as virtual_filesystem;
@@ -12,6 +12,7 @@ use std::{io, mem};
 pub enum FileMode {
     Read,
     Write,
+    Append,
 }
 
 impl FileMode {
@@ -24,9 +25,12 @@ impl FileMode {
         if open_options.read {
             mode.insert(FileMode::Read);
         }
-        if open_options.write {
+        if open_options.writable() {
             mode.insert(FileMode::Write);
         }
+        if open_options.append {
+            mode.insert(FileMode::Append);
+        }
 
         mode
     }
@@ -51,7 +55,9 @@ impl FileHandle {
         let contents = contents_mutex.lock();
 
         Self {
-            contents: unsafe { mem::transmute(contents) },
+            contents: unsafe {
+                mem::transmute::<MutexGuard<'_, Vec<u8>>, MutexGuard<'static, Vec<u8>>>(contents)
+            },
             _mutex: contents_mutex,
             pos: 0,
             mode,
@@ -117,6 +123,11 @@ impl Write for FileHandle {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         Self::check_mode(self.mode.contains(FileMode::Write))?;
 
+        // appended writes always go to the end, wherever the cursor was
+        if self.mode.contains(FileMode::Append) {
+            self.pos = self.contents.len();
+        }
+
         let pos = self.pos.min(self.contents.len());
         let needed_len = pos.saturating_add(buf.len());
 
@@ -126,6 +137,7 @@ impl Write for FileHandle {
         }
 
         self.contents[pos..needed_len].copy_from_slice(buf);
+        self.pos = needed_len;
 
         Ok(needed_len - pos)
     }
@@ -142,4 +154,11 @@ impl File for FileHandle {
     fn metadata(&self) -> crate::Result<Metadata> {
         Ok(Metadata::file(self.contents.len() as u64))
     }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        Self::check_mode(self.mode.contains(FileMode::Write))?;
+
+        self.contents.resize(len as usize, 0);
+        Ok(())
+    }
 }
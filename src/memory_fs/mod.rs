@@ -3,8 +3,8 @@ mod file;
 
 use crate::file::{DirEntry, Metadata, OpenOptions};
 use crate::memory_fs::file::{FileHandle, FileMode};
-use crate::tree::{Directory, Entry, FilesystemTree};
-use crate::util::{already_exists, invalid_path, not_found};
+use crate::tree::{normalize_and_relativize, Directory, Entry, FilesystemTree};
+use crate::util::{already_exists, invalid_path, is_a_directory, not_a_directory, not_found};
 use crate::FileSystem;
 use itertools::Itertools;
 use parking_lot::Mutex;
@@ -23,14 +23,69 @@ pub struct MemoryFS {
 }
 
 impl MemoryFS {
+    /// Creates a filesystem preloaded with embedded entries. Used by `embed_dir!`.
+    ///
+    /// # Arguments
+    /// `entries`: The paths of the entries, with the contents of files. Parents precede their children.  
+    #[cfg(feature = "embed")]
+    #[doc(hidden)]
+    pub fn from_embedded(entries: &[(&str, Option<&[u8]>)]) -> Self {
+        let fs = Self::default();
+        for (path, contents) in entries {
+            fs.insert_embedded(path, contents.map(<[u8]>::to_vec));
+        }
+        fs
+    }
+
+    /// Creates a filesystem preloaded with embedded entries, the contents of which are gzip-compressed. Used by
+    /// `embed_dir!`.
+    ///
+    /// # Arguments
+    /// `entries`: The paths of the entries, with the compressed contents of files. Parents precede their children.  
+    #[cfg(feature = "embed")]
+    #[doc(hidden)]
+    pub fn from_embedded_gzip(entries: &[(&str, Option<&[u8]>)]) -> Self {
+        use std::io::Read;
+
+        let fs = Self::default();
+        for (path, compressed) in entries {
+            let contents = compressed.map(|compressed| {
+                let mut contents = Vec::new();
+                flate2::read::GzDecoder::new(compressed)
+                    .read_to_end(&mut contents)
+                    .expect("embedded contents are valid gzip streams");
+                contents
+            });
+            fs.insert_embedded(path, contents);
+        }
+        fs
+    }
+
+    /// Inserts an embedded directory, or file if `contents` is present.
+    #[cfg(feature = "embed")]
+    fn insert_embedded(&self, path: &str, contents: Option<Vec<u8>>) {
+        let path = normalize_and_relativize(path);
+        match contents {
+            Some(contents) => self.with_parent_and_child_name(&path, |dir, file_name| {
+                dir.insert(
+                    file_name.to_owned(),
+                    Entry::UserData(File::new(Mutex::new(contents))),
+                );
+            }),
+            None => self.inner.create_dir_all(&path, |_| ()),
+        }
+        .expect("embedded entries are preceded by their parents");
+    }
+
     fn with_parent_and_child_name<R, P: AsRef<Path>, F: FnOnce(&mut Directory<File>, &str) -> R>(
         &self,
         path: P,
         f: F,
     ) -> crate::Result<R> {
-        let parent_directory = path.as_ref().parent().ok_or_else(invalid_path)?;
+        // virtual paths are split the same way on every host
+        let path = normalize_and_relativize(path);
+        let parent_directory = path.parent().ok_or_else(invalid_path)?;
         let child_name = path
-            .as_ref()
             .file_name()
             .and_then(OsStr::to_str)
             .ok_or_else(invalid_path)?;
@@ -69,19 +124,24 @@ impl FileSystem for MemoryFS {
         path: &str,
         options: &OpenOptions,
     ) -> crate::Result<Box<dyn crate::File>> {
+        options.validate()?;
+
         // grab the file
         let mut file = self.with_parent_and_child_name(path, |dir, file_name| {
             let file = match dir.entry(file_name.to_owned()) {
+                hash_map::Entry::Occupied(_) if options.create_new => {
+                    return Err(already_exists());
+                }
                 hash_map::Entry::Occupied(entry) => {
                     // of course we can only grab the file if it's a file
                     if let Entry::UserData(file) = entry.get() {
                         file.clone()
                     } else {
-                        return Err(not_found());
+                        return Err(is_a_directory());
                     }
                 }
                 hash_map::Entry::Vacant(vacant) => {
-                    if options.create {
+                    if options.create || options.create_new {
                         // create a new empty file and return it
                         let file = File::new(Mutex::default());
                         vacant.insert(Entry::UserData(file.clone()));
@@ -108,12 +168,13 @@ impl FileSystem for MemoryFS {
         &self,
         path: &str,
     ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
-        self.inner.with_directory(path, |dir| {
+        let directory = normalize_and_relativize(path);
+        self.inner.with_directory(&directory, |dir| {
             let iter: Box<dyn Iterator<Item = crate::Result<DirEntry>>> = Box::new(
                 dir.iter()
                     .map(|(name, entry)| {
                         Ok(DirEntry {
-                            path: name.into(),
+                            path: directory.join(name),
                             metadata: entry.into(),
                         })
                     })
@@ -130,7 +191,8 @@ impl FileSystem for MemoryFS {
                 occ.remove();
                 Ok(())
             }
-            _ => Err(not_found()),
+            hash_map::Entry::Occupied(_) => Err(not_a_directory()),
+            hash_map::Entry::Vacant(_) => Err(not_found()),
         })?
     }
 
@@ -140,7 +202,8 @@ impl FileSystem for MemoryFS {
                 occ.remove();
                 Ok(())
             }
-            _ => Err(not_found()),
+            hash_map::Entry::Occupied(_) => Err(is_a_directory()),
+            hash_map::Entry::Vacant(_) => Err(not_found()),
         })?
     }
 
@@ -153,9 +216,10 @@ impl FileSystem for MemoryFS {
 mod test {
     use crate::file::{FileType, Metadata};
     use crate::memory_fs::MemoryFS;
+    use crate::util::test::check_open_options;
     use crate::FileSystem;
     use std::collections::BTreeMap;
-    use std::io::Write;
+    use std::io::{ErrorKind, Write};
 
     fn memory_fs() -> MemoryFS {
         let fs = MemoryFS::default();
@@ -182,6 +246,11 @@ mod test {
         memory_fs();
     }
 
+    #[test]
+    fn open_options() {
+        check_open_options(&memory_fs(), "options");
+    }
+
     #[test]
     fn metadata() {
         let fs = memory_fs();
@@ -236,7 +305,10 @@ mod test {
             "\\folder\\and\\it\\goes\\",
         ] {
             let files = read_directory(&fs, name);
-            itertools::assert_equal(files.keys(), vec!["deeper", "desc"]);
+            itertools::assert_equal(
+                files.keys(),
+                vec!["folder/and/it/goes/deeper", "folder/and/it/goes/desc"],
+            );
             itertools::assert_equal(
                 files.values(),
                 vec![&Metadata::directory(), &Metadata::file(4)],
@@ -268,6 +340,12 @@ mod test {
         assert!(!fs.exists("folder/and/it/goes").unwrap());
         assert!(!fs.exists("/folder/and/it").unwrap());
         assert!(!fs.exists("/folder/and/it/goes/desc").unwrap());
+
+        assert_eq!(
+            fs.remove_dir("file").unwrap_err().kind(),
+            ErrorKind::NotADirectory
+        );
+        assert!(fs.exists("file").unwrap());
     }
 
     #[test]
@@ -278,5 +356,53 @@ mod test {
         fs.remove_file("folder/and/it/goes/desc").unwrap();
         assert!(fs.exists("folder/and/it/goes/deeper").unwrap());
         assert!(!fs.exists("folder/and/it/goes/desc").unwrap());
+
+        assert_eq!(
+            fs.remove_file("folder").unwrap_err().kind(),
+            ErrorKind::IsADirectory
+        );
+        assert_eq!(
+            fs.open_file("folder").err().unwrap().kind(),
+            ErrorKind::IsADirectory
+        );
+        assert_eq!(
+            fs.read_dir("file/nested").err().unwrap().kind(),
+            ErrorKind::NotADirectory
+        );
+    }
+
+    #[cfg(feature = "embed")]
+    #[test]
+    fn embed_dir() {
+        let fs = crate::embed_dir!("test/a");
+
+        itertools::assert_equal(
+            read_directory(&fs, "b/c/d/e/f").keys(),
+            vec!["b/c/d/e/f/.gitkeep"],
+        );
+        assert_eq!(fs.metadata("b/c/d").unwrap(), Metadata::directory());
+        assert_eq!(
+            fs.metadata("b/c/d/e/f/.gitkeep").unwrap(),
+            Metadata::file(0)
+        );
+
+        // the embedded filesystem is writable like any other
+        write!(fs.create_file("b/new").unwrap(), "new").unwrap();
+        assert_eq!(
+            fs.open_file("b/new").unwrap().read_into_string().unwrap(),
+            "new"
+        );
+    }
+
+    #[cfg(feature = "embed")]
+    #[test]
+    fn embed_dir_compressed() {
+        let fs = crate::embed_dir!("test/folder_b", compress);
+
+        itertools::assert_equal(read_directory(&fs, "").keys(), vec!["file_b"]);
+        assert_eq!(
+            fs.open_file("file_b").unwrap().read_into_string().unwrap(),
+            "file b"
+        );
     }
 }
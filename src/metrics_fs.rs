@@ -0,0 +1,340 @@
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::util::Instant;
+use crate::FileSystem;
+use metrics::Label;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// The counter of completed operations.
+pub const OPERATIONS: &str = "vfs_operations_total";
+/// The histogram of the duration of operations, in seconds.
+pub const OPERATION_DURATION: &str = "vfs_operation_duration_seconds";
+/// The counter of bytes read from files.
+pub const READ_BYTES: &str = "vfs_read_bytes_total";
+/// The counter of bytes written to files.
+pub const WRITTEN_BYTES: &str = "vfs_written_bytes_total";
+
+/// A filesystem that records metrics of every operation on another filesystem through the `metrics` facade, so that
+/// the throughput of each mounted backend can be put on a dashboard.
+///
+/// Every operation increments [`OPERATIONS`] and records its duration in [`OPERATION_DURATION`], labeled with the
+/// name of the filesystem as `fs` and the operation as `operation`. [`OPERATIONS`] is also labeled with `result`,
+/// which is `ok` or `error`. Reads and writes of open files are the `read` and `write` operations, and add their byte
+/// counts to [`READ_BYTES`] and [`WRITTEN_BYTES`].
+pub struct MetricsFS<FS> {
+    inner: FS,
+    name: String,
+}
+
+impl<FS: FileSystem> MetricsFS<FS> {
+    /// Creates a new measured filesystem.
+    ///
+    /// # Arguments
+    /// `inner`: The filesystem to measure.  
+    pub fn new(inner: FS) -> Self {
+        Self {
+            inner,
+            name: String::new(),
+        }
+    }
+
+    /// Sets the name the metrics of the filesystem are labeled with, which tells apart the mounted backends.
+    ///
+    /// # Arguments
+    /// `name`: The name of the filesystem.  
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_owned();
+        self
+    }
+
+    /// Returns the measured filesystem.
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Consumes the measured filesystem, returning the inner filesystem.
+    pub fn into_inner(self) -> FS {
+        self.inner
+    }
+
+    /// Runs an operation, recording its metrics once it completes.
+    ///
+    /// # Arguments
+    /// `operation`: The name of the operation.  
+    /// `f`: The operation.  
+    fn measure<T>(
+        &self,
+        operation: &'static str,
+        f: impl FnOnce() -> crate::Result<T>,
+    ) -> crate::Result<T> {
+        measure(&self.name, operation, f)
+    }
+}
+
+/// Runs an operation, recording its metrics once it completes.
+///
+/// # Arguments
+/// `name`: The name of the filesystem.  
+/// `operation`: The name of the operation.  
+/// `f`: The operation.  
+fn measure<T>(
+    name: &str,
+    operation: &'static str,
+    f: impl FnOnce() -> crate::Result<T>,
+) -> crate::Result<T> {
+    let start = Instant::now();
+    let result = f();
+    let mut labels = vec![
+        Label::new("fs", name.to_owned()),
+        Label::new("operation", operation),
+    ];
+
+    metrics::histogram!(OPERATION_DURATION, labels.clone()).record(start.elapsed());
+    labels.push(Label::new(
+        "result",
+        match result {
+            Ok(_) => "ok",
+            Err(_) => "error",
+        },
+    ));
+    metrics::counter!(OPERATIONS, labels).increment(1);
+    result
+}
+
+impl<FS: FileSystem> FileSystem for MetricsFS<FS> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.measure("create_dir", || self.inner.create_dir(path))
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.measure("metadata", || self.inner.metadata(path))
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        let inner = self.measure("open_file", || self.inner.open_file_options(path, options))?;
+        Ok(Box::new(MeasuredFile {
+            inner,
+            name: self.name.clone(),
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        self.measure("read_dir", || self.inner.read_dir(path))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.measure("remove_dir", || self.inner.remove_dir(path))
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.measure("remove_file", || self.inner.remove_file(path))
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        self.measure("create_dir", || self.inner.create_dir_with(path, mode))
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> crate::Result<u64> {
+        self.measure("copy_file", || self.inner.copy_file(from, to))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        self.measure("rename", || self.inner.rename(from, to))
+    }
+
+    fn walk_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>> + '_>> {
+        self.measure("walk_dir", || self.inner.walk_dir(path))
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        self.measure("stats", || self.inner.stats())
+    }
+}
+
+/// A file whose reads and writes are measured.
+struct MeasuredFile {
+    inner: Box<dyn File>,
+    name: String,
+}
+
+impl Read for MeasuredFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_len = measure(&self.name, "read", || self.inner.read(buf))?;
+        metrics::counter!(READ_BYTES, "fs" => self.name.clone()).increment(read_len as u64);
+        Ok(read_len)
+    }
+}
+
+impl Seek for MeasuredFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl Write for MeasuredFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = measure(&self.name, "write", || self.inner.write(buf))?;
+        metrics::counter!(WRITTEN_BYTES, "fs" => self.name.clone()).increment(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        measure(&self.name, "flush", || self.inner.flush())
+    }
+}
+
+impl File for MeasuredFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        self.inner.metadata()
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        self.inner.set_len(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    use crate::metrics_fs::{MetricsFS, OPERATIONS, OPERATION_DURATION, READ_BYTES, WRITTEN_BYTES};
+    use crate::FileSystem;
+    use metrics::{
+        Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString,
+        Unit,
+    };
+    use parking_lot::Mutex;
+    use std::collections::BTreeMap;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    /// A histogram that counts the values recorded into it.
+    #[derive(Default)]
+    struct CountingHistogram(AtomicU64);
+
+    impl HistogramFn for CountingHistogram {
+        fn record(&self, _value: f64) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// A recorder that keeps the value of every counter and the number of values of every histogram, by key.
+    #[derive(Default)]
+    struct TestRecorder {
+        counters: Mutex<BTreeMap<String, Arc<AtomicU64>>>,
+        histograms: Mutex<BTreeMap<String, Arc<CountingHistogram>>>,
+    }
+
+    impl TestRecorder {
+        /// Returns the value of the counter with `key`, formatted as `name{label=value,...}`.
+        fn counter(&self, key: &str) -> u64 {
+            self.counters
+                .lock()
+                .get(key)
+                .map_or(0, |counter| counter.load(Ordering::Relaxed))
+        }
+
+        /// Returns the number of values recorded into the histogram with `key`.
+        fn histogram(&self, key: &str) -> u64 {
+            self.histograms
+                .lock()
+                .get(key)
+                .map_or(0, |histogram| histogram.0.load(Ordering::Relaxed))
+        }
+    }
+
+    /// Formats a key as `name{label=value,...}`.
+    fn format_key(key: &Key) -> String {
+        let labels: Vec<_> = key
+            .labels()
+            .map(|label| format!("{}={}", label.key(), label.value()))
+            .collect();
+        format!("{}{{{}}}", key.name(), labels.join(","))
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {
+        }
+
+        fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+        fn describe_histogram(
+            &self,
+            _key: KeyName,
+            _unit: Option<Unit>,
+            _description: SharedString,
+        ) {
+        }
+
+        fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+            Counter::from_arc(
+                self.counters
+                    .lock()
+                    .entry(format_key(key))
+                    .or_default()
+                    .clone(),
+            )
+        }
+
+        fn register_gauge(&self, _key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+            Histogram::from_arc(
+                self.histograms
+                    .lock()
+                    .entry(format_key(key))
+                    .or_default()
+                    .clone(),
+            )
+        }
+    }
+
+    #[test]
+    fn measure() {
+        let recorder = TestRecorder::default();
+        let fs = MetricsFS::new(MemoryFS::default()).name("memory");
+
+        metrics::with_local_recorder(&recorder, || {
+            fs.create_dir("dir").unwrap();
+            let mut file = fs.create_file("dir/file").unwrap();
+            file.write_all(b"hello").unwrap();
+            file.write_all(b" world").unwrap();
+            drop(file);
+            assert_eq!(
+                fs.open_file("dir/file").unwrap().read_into_vec().unwrap(),
+                b"hello world"
+            );
+            assert!(fs.remove_dir("missing").is_err());
+        });
+
+        let operations = |operation: &str, result: &str| {
+            recorder.counter(&format!(
+                "{OPERATIONS}{{fs=memory,operation={operation},result={result}}}"
+            ))
+        };
+        assert_eq!(operations("create_dir", "ok"), 1);
+        assert_eq!(operations("open_file", "ok"), 2);
+        assert_eq!(operations("write", "ok"), 2);
+        assert_eq!(operations("remove_dir", "error"), 1);
+        assert_eq!(operations("remove_dir", "ok"), 0);
+        assert_eq!(
+            recorder.histogram(&format!(
+                "{OPERATION_DURATION}{{fs=memory,operation=write}}"
+            )),
+            2
+        );
+        assert_eq!(recorder.counter(&format!("{READ_BYTES}{{fs=memory}}")), 11);
+        assert_eq!(
+            recorder.counter(&format!("{WRITTEN_BYTES}{{fs=memory}}")),
+            11
+        );
+    }
+}
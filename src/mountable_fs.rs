@@ -1,18 +1,136 @@
 use crate::file::{DirEntry, File, Metadata, OpenOptions};
-use crate::tree::{normalize_and_relativize, Entry, FilesystemTree};
-use crate::util::{already_exists, invalid_path, not_found, not_supported};
+use crate::memory_fs::MemoryFS;
+use crate::physical_fs::{PhysicalFS, SandboxedPhysicalFS};
+use crate::tar_fs::TarFS;
+use crate::tree::normalize_and_relativize;
+use crate::util::{
+    already_exists, crosses_mount_point, invalid_input, invalid_path, not_found, not_supported,
+    read_only,
+};
+use crate::zip_fs::ZipFS;
 use crate::FileSystem;
 use itertools::Itertools;
-use std::collections::hash_map;
-use std::ffi::OsStr;
-use std::path::Path;
+use parking_lot::RwLock;
+#[cfg(feature = "config")]
+use serde::Deserialize;
+use std::any::type_name;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{fs, io};
 
 type FS = Box<dyn FileSystem + Send + Sync>;
 
-/// A filesystem that supports the mounting of other filesystems at designated paths (excluding the root).
+/// A filesystem that supports the mounting of other filesystems at designated paths. Filesystems can
+/// be mounted within one another, including within a filesystem mounted at the root. Paths are
+/// served by the filesystem mounted closest to them, so inner mounts shadow the contents of outer
+/// ones.
 #[derive(Default)]
 pub struct MountableFS {
-    inner: FilesystemTree<FS>,
+    mounts: RwLock<BTreeMap<PathBuf, Arc<Mount>>>,
+    fallback: RwLock<Option<Arc<Mount>>>,
+}
+
+/// Information about a mounted filesystem.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MountInfo {
+    /// True if the filesystem is mounted read-only.
+    pub read_only: bool,
+    /// The type name of the filesystem.
+    pub type_name: &'static str,
+    /// The directory within the filesystem that is mounted. This is empty unless the filesystem is
+    /// bind mounted.
+    pub source_path: PathBuf,
+}
+
+/// Options for mounting a filesystem. By default, filesystems are mounted as they are.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "config", derive(Deserialize), serde(default))]
+pub struct MountOptions {
+    /// True if the filesystem can't be written to through the mount. The write permissions are
+    /// also removed from the mode of its files.
+    pub read_only: bool,
+    /// True if the mount point is left out of the listing of its parent directory.
+    pub hide_from_read_dir: bool,
+    /// The maximum size of files written through the mount, in bytes.
+    pub size_limit: Option<u64>,
+}
+
+impl MountOptions {
+    /// # Arguments
+    /// `read_only`: If true, the filesystem can't be written to through the mount.  
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// # Arguments
+    /// `hide_from_read_dir`: If true, the mount point is left out of the listing of its parent
+    /// directory. It can still be accessed directly.  
+    pub fn hide_from_read_dir(mut self, hide_from_read_dir: bool) -> Self {
+        self.hide_from_read_dir = hide_from_read_dir;
+        self
+    }
+
+    /// # Arguments
+    /// `size_limit`: The maximum size of files written through the mount, in bytes. Writes past the
+    /// limit fail.  
+    pub fn size_limit(mut self, size_limit: Option<u64>) -> Self {
+        self.size_limit = size_limit;
+        self
+    }
+}
+
+/// A mount table entry, as declared in a configuration.
+#[cfg(feature = "config")]
+#[derive(Deserialize)]
+struct MountConfig {
+    /// The path to mount the filesystem at.
+    path: String,
+    /// The URI of the filesystem.
+    uri: String,
+    /// The options to mount the filesystem with.
+    #[serde(flatten)]
+    options: MountOptions,
+}
+
+/// A mount table, as declared in a configuration.
+#[cfg(feature = "config")]
+#[derive(Deserialize)]
+struct MountTableConfig {
+    #[serde(default)]
+    mounts: Vec<MountConfig>,
+}
+
+/// A mounted filesystem.
+struct Mount {
+    fs: FS,
+    info: MountInfo,
+    options: MountOptions,
+}
+
+impl Mount {
+    /// Returns an error if the filesystem can't be written to through the mount.
+    fn check_writable(&self) -> crate::Result<()> {
+        if self.options.read_only {
+            Err(read_only())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Applies the mount's options to the metadata of an entry within the filesystem.
+    ///
+    /// # Arguments
+    /// `metadata`: The metadata of the entry.  
+    fn mask_metadata(&self, mut metadata: Metadata) -> Metadata {
+        if self.options.read_only {
+            metadata.mode = metadata.mode.map(|mode| mode & !0o222);
+        }
+        metadata
+    }
 }
 
 impl MountableFS {
@@ -21,31 +139,353 @@ impl MountableFS {
     /// # Arguments
     /// `path`: The path to mount the filesystem at.  
     /// `fs`: The filesystem to mount.  
-    pub fn mount<P: AsRef<Path>>(
+    pub fn mount<P: AsRef<Path>, F: FileSystem + Send + Sync + 'static>(
         &self,
         path: P,
-        fs: Box<dyn FileSystem + Send + Sync>,
+        fs: F,
     ) -> crate::Result<()> {
-        // find the parent path
-        let normalized_path = normalize_and_relativize(path);
-        let parent_path = normalized_path.parent().ok_or_else(invalid_path)?;
-        let child_path = normalized_path
-            .file_name()
-            .and_then(OsStr::to_str)
-            .ok_or_else(invalid_path)?;
-
-        // create the parent path
-        self.inner.create_dir_all(parent_path, |dir| {
-            if let hash_map::Entry::Vacant(vac) = dir.entry(child_path.to_owned()) {
-                vac.insert(Entry::UserData(fs));
-                Ok(())
-            } else {
-                Err(already_exists())
+        self.mount_with_options(path, fs, MountOptions::default())
+    }
+
+    /// Mounts a filesystem at the given path with options.
+    ///
+    /// # Arguments
+    /// `path`: The path to mount the filesystem at.  
+    /// `fs`: The filesystem to mount.  
+    /// `options`: The options to mount the filesystem with.  
+    pub fn mount_with_options<P: AsRef<Path>, F: FileSystem + Send + Sync + 'static>(
+        &self,
+        path: P,
+        fs: F,
+        options: MountOptions,
+    ) -> crate::Result<()> {
+        self.insert_mount(path, fs, PathBuf::new(), options)
+            .map(|_| ())
+    }
+
+    /// Mounts the filesystem described by a URI at the given path. The URI's scheme selects the
+    /// backend, and the rest of it is the path to the backing directory or archive on the host:
+    /// - `physical://<directory>`: A `PhysicalFS` rooted at the directory.
+    /// - `sandboxed://<directory>`: A `SandboxedPhysicalFS` rooted at the directory.
+    /// - `memory://`: An empty `MemoryFS`.
+    /// - `zip://<archive>`: A `ZipFS` over the ZIP archive.
+    /// - `tar://<archive>`: A `TarFS` over the possibly compressed tarball.
+    ///
+    /// # Arguments
+    /// `uri`: The URI of the filesystem, such as `zip:///path/to/a.zip`.  
+    /// `path`: The path to mount the filesystem at.  
+    pub fn mount_uri<P: AsRef<Path>>(&self, uri: &str, path: P) -> crate::Result<()> {
+        self.mount_uri_with_options(uri, path, MountOptions::default())
+    }
+
+    /// Mounts the filesystem described by a URI at the given path with options. See `mount_uri`
+    /// for the supported URIs.
+    ///
+    /// # Arguments
+    /// `uri`: The URI of the filesystem.  
+    /// `path`: The path to mount the filesystem at.  
+    /// `options`: The options to mount the filesystem with.  
+    pub fn mount_uri_with_options<P: AsRef<Path>>(
+        &self,
+        uri: &str,
+        path: P,
+        options: MountOptions,
+    ) -> crate::Result<()> {
+        let (scheme, source) = uri
+            .split_once("://")
+            .ok_or_else(|| invalid_input("Mount URI has no scheme"))?;
+
+        match scheme {
+            "physical" => self.mount_with_options(path, PhysicalFS::new(source), options),
+            "sandboxed" => self.mount_with_options(path, SandboxedPhysicalFS::new(source), options),
+            "memory" if source.is_empty() => {
+                self.mount_with_options(path, MemoryFS::default(), options)
             }
-        })??;
+            "memory" => Err(invalid_input("Memory mount URIs don't take a path")),
+            "zip" => self.mount_with_options(path, ZipFS::new(fs::File::open(source)?)?, options),
+            "tar" => self.mount_with_options(path, TarFS::open(fs::File::open(source)?)?, options),
+            _ => Err(invalid_input("Unsupported mount URI scheme")),
+        }
+    }
+
+    /// Builds a mount table from a TOML or JSON configuration. Configurations starting with `{`
+    /// are parsed as JSON, and anything else as TOML. Each entry of `mounts` declares the `path`
+    /// and `uri` of a filesystem as accepted by `mount_uri`, along with any of the fields of
+    /// `MountOptions`. Filesystems are mounted in order.
+    ///
+    /// ```toml
+    /// [[mounts]]
+    /// path = "/assets"
+    /// uri = "zip:///path/to/a.zip"
+    /// read_only = true
+    /// ```
+    ///
+    /// # Arguments
+    /// `config`: The configuration.  
+    #[cfg(feature = "config")]
+    pub fn from_config(config: &str) -> crate::Result<Self> {
+        let config: MountTableConfig = if config.trim_start().starts_with('{') {
+            serde_json::from_str(config)
+                .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?
+        } else {
+            toml::from_str(config).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?
+        };
+
+        let mountable_fs = Self::default();
+        for mount in config.mounts {
+            mountable_fs.mount_uri_with_options(&mount.uri, mount.path, mount.options)?;
+        }
+        Ok(mountable_fs)
+    }
+
+    /// Mounts the directory `source_path` within a filesystem at the given path, so that only its
+    /// contents are visible. To bind a filesystem that is mounted elsewhere, share it through an
+    /// `Arc`.
+    ///
+    /// # Arguments
+    /// `path`: The path to mount the directory at.  
+    /// `fs`: The filesystem containing the directory.  
+    /// `source_path`: The path to the directory within `fs`.  
+    pub fn bind<P: AsRef<Path>, F: FileSystem + Send + Sync + 'static, S: AsRef<Path>>(
+        &self,
+        path: P,
+        fs: F,
+        source_path: S,
+    ) -> crate::Result<()> {
+        let source_path = normalize_and_relativize(source_path);
+        if !fs
+            .metadata(source_path.to_str().ok_or_else(invalid_path)?)?
+            .is_directory()
+        {
+            return Err(invalid_input("Bind source is not a directory"));
+        }
+
+        self.insert_mount(path, fs, source_path, MountOptions::default())
+            .map(|_| ())
+    }
+
+    /// Inserts a filesystem into the mount table, returning the mount.
+    ///
+    /// # Arguments
+    /// `path`: The path to mount the filesystem at.  
+    /// `fs`: The filesystem to mount.  
+    /// `source_path`: The normalized path to the directory within `fs` that is mounted.  
+    /// `options`: The options to mount the filesystem with.  
+    fn insert_mount<P: AsRef<Path>, F: FileSystem + Send + Sync + 'static>(
+        &self,
+        path: P,
+        fs: F,
+        source_path: PathBuf,
+        options: MountOptions,
+    ) -> crate::Result<Arc<Mount>> {
+        let info = MountInfo {
+            read_only: options.read_only,
+            type_name: type_name::<F>(),
+            source_path,
+        };
+
+        let normalized_path = normalize_and_relativize(path);
+        let mut mounts = self.mounts.write();
+        if mounts.contains_key(&normalized_path) {
+            return Err(already_exists());
+        }
+
+        let mount = Arc::new(Mount {
+            fs: Box::new(fs),
+            info,
+            options,
+        });
+        mounts.insert(normalized_path, mount.clone());
+
+        Ok(mount)
+    }
+
+    /// Mounts a filesystem at the given path until the returned guard is dropped.
+    ///
+    /// # Arguments
+    /// `path`: The path to mount the filesystem at.  
+    /// `fs`: The filesystem to mount.  
+    pub fn mount_scoped<P: AsRef<Path>, F: FileSystem + Send + Sync + 'static>(
+        &self,
+        path: P,
+        fs: F,
+    ) -> crate::Result<MountGuard<'_>> {
+        let normalized_path = normalize_and_relativize(path);
+        let mount = self.insert_mount(&normalized_path, fs, PathBuf::new(), Default::default())?;
+
+        Ok(MountGuard {
+            fs: self,
+            path: normalized_path,
+            mount,
+        })
+    }
+
+    /// Unmounts the filesystem mounted at the given path. Files that were already opened remain
+    /// open.
+    ///
+    /// # Arguments
+    /// `path`: The path the filesystem is mounted at.  
+    pub fn unmount<P: AsRef<Path>>(&self, path: P) -> crate::Result<()> {
+        self.mounts
+            .write()
+            .remove(&normalize_and_relativize(path))
+            .map(|_| ())
+            .ok_or_else(not_found)
+    }
+
+    /// Atomically replaces the filesystem mounted at the given path, keeping its mount options.
+    /// Lookups see either the old or the new filesystem, and files that were already opened remain
+    /// open on the old filesystem.
+    ///
+    /// # Arguments
+    /// `path`: The path the filesystem is mounted at.  
+    /// `fs`: The filesystem to mount in its place.  
+    pub fn remount<P: AsRef<Path>, F: FileSystem + Send + Sync + 'static>(
+        &self,
+        path: P,
+        fs: F,
+    ) -> crate::Result<()> {
+        let normalized_path = normalize_and_relativize(path);
+        let mut mounts = self.mounts.write();
+        let mount = mounts.get_mut(&normalized_path).ok_or_else(not_found)?;
+
+        *mount = Arc::new(Mount {
+            fs: Box::new(fs),
+            info: MountInfo {
+                type_name: type_name::<F>(),
+                ..mount.info.clone()
+            },
+            options: mount.options.clone(),
+        });
 
         Ok(())
     }
+
+    /// Sets the filesystem that serves paths outside of every mount, replacing any previous one.
+    /// Without a fallback, such paths are not found. A filesystem mounted at the root takes
+    /// precedence over the fallback.
+    ///
+    /// # Arguments
+    /// `fs`: The fallback filesystem.  
+    pub fn set_fallback<F: FileSystem + Send + Sync + 'static>(&self, fs: F) {
+        *self.fallback.write() = Some(Arc::new(Mount {
+            fs: Box::new(fs),
+            info: MountInfo {
+                read_only: false,
+                type_name: type_name::<F>(),
+                source_path: PathBuf::new(),
+            },
+            options: MountOptions::default(),
+        }));
+    }
+
+    /// Returns the path of each mounted filesystem along with information about it, ordered by
+    /// path.
+    pub fn mounts(&self) -> Vec<(PathBuf, MountInfo)> {
+        self.mounts
+            .read()
+            .iter()
+            .map(|(path, mount)| (path.clone(), mount.info.clone()))
+            .collect()
+    }
+
+    /// Resolves `path` to the filesystem mounted closest to it, or the fallback filesystem if
+    /// there is none, returning the filesystem along with the path within it.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn find_mount(&self, path: &Path) -> Option<(Arc<Mount>, PathBuf)> {
+        let mounts = self.mounts.read();
+        path.ancestors()
+            .find_map(|ancestor| {
+                mounts.get(ancestor).map(|mount| {
+                    // `ancestor` is an ancestor of `path`, so this is safe
+                    let remaining_path = path.strip_prefix(ancestor).unwrap();
+                    (mount.clone(), mount.info.source_path.join(remaining_path))
+                })
+            })
+            .or_else(|| {
+                let fallback = self.fallback.read().clone()?;
+                Some((fallback, path.to_owned()))
+            })
+    }
+
+    /// Returns the names of the directories in `path` that lead to mount points.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    /// `include_hidden`: True if mounts hidden from `read_dir` should be included.  
+    fn mount_directories(&self, path: &Path, include_hidden: bool) -> HashSet<String> {
+        self.mounts
+            .read()
+            .range::<Path, _>((Bound::Excluded(path), Bound::Unbounded))
+            .take_while(|(mount_path, _)| mount_path.starts_with(path))
+            .filter(|(_, mount)| include_hidden || !mount.options.hide_from_read_dir)
+            .filter_map(|(mount_path, _)| {
+                // `mount_path` is within `path`, so this is safe
+                let relative_path = mount_path.strip_prefix(path).unwrap();
+                relative_path
+                    .iter()
+                    .next()
+                    .and_then(|name| name.to_str())
+                    .map(str::to_owned)
+            })
+            .collect()
+    }
+
+    /// Returns true if `path` is a directory provided by the mount table itself, rather than a
+    /// mounted filesystem. This is the root, each mount point, and each directory leading to one.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn is_mount_directory(&self, path: &Path) -> bool {
+        path.as_os_str().is_empty()
+            || self.mounts.read().contains_key(path)
+            || !self.mount_directories(path, true).is_empty()
+    }
+
+    /// Calls `f` with the mount that owns `path` and the path within it.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    /// `f`: The function.  
+    fn with_mount<R, F: FnOnce(&Mount, &str) -> crate::Result<R>>(
+        &self,
+        path: &Path,
+        f: F,
+    ) -> crate::Result<R> {
+        let (mount, remaining_path) = self.find_mount(path).ok_or_else(not_found)?;
+
+        // `remaining_path` is derived from `path`, so this is safe
+        f(&mount, remaining_path.to_str().unwrap())
+    }
+}
+
+/// A guard that unmounts a filesystem when it is dropped. If the filesystem has already been
+/// unmounted or replaced, the guard does nothing.
+pub struct MountGuard<'a> {
+    fs: &'a MountableFS,
+    path: PathBuf,
+    mount: Arc<Mount>,
+}
+
+impl MountGuard<'_> {
+    /// Returns the path the filesystem is mounted at.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for MountGuard<'_> {
+    fn drop(&mut self) {
+        let mut mounts = self.fs.mounts.write();
+        if mounts
+            .get(&self.path)
+            .is_some_and(|mount| Arc::ptr_eq(mount, &self.mount))
+        {
+            mounts.remove(&self.path);
+        }
+    }
 }
 
 impl<'a> FromIterator<(&'a str, Box<dyn FileSystem + Send + Sync>)> for MountableFS {
@@ -61,86 +501,249 @@ impl<'a> FromIterator<(&'a str, Box<dyn FileSystem + Send + Sync>)> for Mountabl
 }
 
 impl FileSystem for MountableFS {
-    fn create_dir(&self, _path: &str) -> crate::Result<()> {
-        Err(not_supported())
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        let normalized_path = normalize_and_relativize(path);
+        if self.is_mount_directory(&normalized_path) {
+            return Err(already_exists());
+        }
+
+        self.with_mount(&normalized_path, |mount, path| {
+            mount.check_writable()?;
+            mount.fs.create_dir(path)
+        })
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        let normalized_path = normalize_and_relativize(path);
+        if self.is_mount_directory(&normalized_path) {
+            return Err(already_exists());
+        }
+
+        self.with_mount(&normalized_path, |mount, path| {
+            mount.check_writable()?;
+            mount.fs.create_dir_with(path, mode)
+        })
     }
 
     fn metadata(&self, path: &str) -> crate::Result<Metadata> {
-        self.inner.with_entry(path, |maybe_directory| {
-            match maybe_directory {
-                Ok(_dir) => Ok(Metadata::directory()),
-                Err((fs, remaining_path)) => {
-                    if remaining_path.as_os_str().is_empty() {
-                        // the root directory of a filesystem is a directory
-                        Ok(Metadata::directory())
-                    } else {
-                        // `remaining_path` is derived from `path`, so this is safe
-                        fs.metadata(remaining_path.to_str().unwrap())
-                    }
-                }
-            }
+        let normalized_path = normalize_and_relativize(path);
+
+        // the root and directories leading to mounts shadow the contents of the root mount
+        if normalized_path.as_os_str().is_empty()
+            || !self.mount_directories(&normalized_path, true).is_empty()
+        {
+            return Ok(Metadata::directory());
+        }
+
+        // the root directory of a mounted filesystem is a directory
+        if self.mounts.read().contains_key(&normalized_path) {
+            return Ok(Metadata::directory());
+        }
+
+        self.with_mount(&normalized_path, |mount, path| {
+            mount.fs.metadata(path).map(|md| mount.mask_metadata(md))
         })
     }
 
     fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
-        self.inner.with_entry(path, |maybe_directory| {
-            maybe_directory
-                .err()
-                .map(|(fs, remaining_path)| {
-                    // `remaining_path` is derived from `path`, so this is safe
-                    fs.open_file_options(remaining_path.to_str().unwrap(), options)
-                })
-                .ok_or_else(not_found)
-        })?
+        let normalized_path = normalize_and_relativize(path);
+        if !self.mount_directories(&normalized_path, true).is_empty() {
+            return Err(not_found());
+        }
+
+        self.with_mount(&normalized_path, |mount, path| {
+            if options.writable() {
+                mount.check_writable()?;
+            }
+
+            let file = mount.fs.open_file_options(path, options)?;
+            Ok(match mount.options.size_limit {
+                Some(size_limit) if options.writable() => Box::new(LimitedFile {
+                    file,
+                    size_limit,
+                    append: options.append,
+                }),
+                _ => file,
+            })
+        })
     }
 
     fn read_dir(
         &self,
         path: &str,
     ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
-        self.inner
-            .with_entry(path, |maybe_entry| match maybe_entry {
-                Ok(dir) => {
-                    // we should have a directory
-                    let entries = dir
-                        .iter()
-                        .map(|(path, _)| {
-                            // filesystems and directories are both functionally directories
-                            Ok(DirEntry {
-                                path: path.into(),
-                                metadata: Metadata::directory(),
-                            })
-                        })
-                        .collect_vec();
-
-                    Ok::<Box<dyn Iterator<Item = crate::Result<DirEntry>>>, _>(Box::new(
-                        entries.into_iter(),
-                    ))
-                }
-                Err((fs, remaining_path)) => {
-                    // `remaining_path` is derived from `path`, so this is safe
-                    fs.read_dir(remaining_path.to_str().unwrap())
-                }
+        let normalized_path = normalize_and_relativize(path);
+        let shadowing_directories = self.mount_directories(&normalized_path, true);
+        let mount_directories = self.mount_directories(&normalized_path, false);
+
+        let entries = self
+            .find_mount(&normalized_path)
+            .map(|(mount, remaining_path)| {
+                // `remaining_path` is derived from `path`, so this is safe
+                let entries = mount.fs.read_dir(remaining_path.to_str().unwrap())?;
+                // entries are rooted at the mount point, so rebase them onto the requested directory
+                let directory = normalized_path.clone();
+                Ok::<_, io::Error>(entries.map(move |entry| {
+                    entry.map(|entry| DirEntry {
+                        path: directory.join(entry.file_name()),
+                        metadata: mount.mask_metadata(entry.metadata),
+                    })
+                }))
+            });
+        let entries = match entries {
+            // nothing is shadowed, so the entries can be returned as-is
+            Some(entries) if shadowing_directories.is_empty() => {
+                return Ok(Box::new(entries?));
+            }
+            Some(Ok(entries)) => Some(entries),
+            Some(Err(err)) if err.kind() != ErrorKind::NotFound => return Err(err),
+            _ if shadowing_directories.is_empty() && !normalized_path.as_os_str().is_empty() => {
+                return Err(not_found())
+            }
+            _ => None,
+        };
+
+        // directories leading to mounts shadow entries with the same name
+        let mut entries = entries
+            .into_iter()
+            .flatten()
+            .filter_ok(|entry| {
+                entry
+                    .path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_none_or(|name| !shadowing_directories.contains(name))
             })
+            .try_collect::<_, Vec<_>, _>()?;
+
+        // filesystems and directories are both functionally directories
+        entries.extend(mount_directories.into_iter().map(|name| DirEntry {
+            path: normalized_path.join(name),
+            metadata: Metadata::directory(),
+        }));
+
+        Ok(Box::new(entries.into_iter().map(Ok)))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        // directories provided by the mount table only go away when filesystems are unmounted
+        let normalized_path = normalize_and_relativize(path);
+        if self.is_mount_directory(&normalized_path) {
+            return Err(not_supported());
+        }
+
+        self.with_mount(&normalized_path, |mount, path| {
+            mount.check_writable()?;
+            mount.fs.remove_dir(path)
+        })
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        let normalized_path = normalize_and_relativize(path);
+        if self.is_mount_directory(&normalized_path) {
+            return Err(not_found());
+        }
+
+        self.with_mount(&normalized_path, |mount, path| {
+            mount.check_writable()?;
+            mount.fs.remove_file(path)
+        })
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        // directories provided by the mount table can't be moved or replaced
+        let (from, to) = (normalize_and_relativize(from), normalize_and_relativize(to));
+        if self.is_mount_directory(&from) || self.is_mount_directory(&to) {
+            return Err(not_supported());
+        }
+
+        // entries can only be renamed within the filesystem that owns them
+        let (from_mount, from_path) = self.find_mount(&from).ok_or_else(not_found)?;
+        let (to_mount, to_path) = self.find_mount(&to).ok_or_else(not_found)?;
+        if !Arc::ptr_eq(&from_mount, &to_mount) {
+            return Err(crosses_mount_point());
+        }
+
+        // both paths are derived from normalized paths, so this is safe
+        from_mount.check_writable()?;
+        from_mount
+            .fs
+            .rename(from_path.to_str().unwrap(), to_path.to_str().unwrap())
+    }
+}
+
+/// A file that can't be written past a size limit.
+struct LimitedFile {
+    file: Box<dyn File>,
+    size_limit: u64,
+    /// Whether writes land at the end of the file rather than at the current position.
+    append: bool,
+}
+
+impl Read for LimitedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Seek for LimitedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl Write for LimitedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // write as much as fits, so that the caller sees the error on its next write
+        let offset = if self.append {
+            self.file.metadata()?.len
+        } else {
+            self.file.stream_position()?
+        };
+        let available = self.size_limit.saturating_sub(offset);
+        if available == 0 && !buf.is_empty() {
+            return Err(file_too_large());
+        }
+
+        let len = buf.len().min(available.try_into().unwrap_or(usize::MAX));
+        self.file.write(&buf[..len])
     }
 
-    fn remove_dir(&self, _path: &str) -> crate::Result<()> {
-        Err(not_supported())
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
     }
+}
 
-    fn remove_file(&self, _path: &str) -> crate::Result<()> {
-        Err(not_supported())
+impl File for LimitedFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        self.file.metadata()
     }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        if len > self.size_limit {
+            return Err(file_too_large());
+        }
+        self.file.set_len(len)
+    }
+}
+
+/// Returns an error indicating that a file would exceed the size limit of its mount.
+fn file_too_large() -> io::Error {
+    io::Error::new(ErrorKind::FileTooLarge, "File size limit exceeded")
 }
 
 #[cfg(test)]
 mod test {
-    use crate::file::Metadata;
+    use crate::error::{ErrorExt, VfsErrorKind};
+    use crate::file::{Metadata, OpenOptions};
     use crate::memory_fs::MemoryFS;
-    use crate::mountable_fs::MountableFS;
-    use crate::util::test::read_directory;
+    use crate::mountable_fs::{MountInfo, MountOptions, MountableFS};
+    use crate::util::test::{check_open_options, check_set_len, read_directory};
     use crate::{FileSystem, MockFileSystem};
-    use std::io::Write;
+    use std::io::{ErrorKind, Seek, Write};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::thread;
 
     const TEST_PATHS: [&str; 4] = [
         "test/abc",
@@ -155,8 +758,7 @@ mod test {
             let fs = MountableFS::default();
             assert!(!fs.exists("test/abc").unwrap());
 
-            fs.mount(mount_point, Box::new(MockFileSystem::new()))
-                .unwrap();
+            fs.mount(mount_point, MockFileSystem::new()).unwrap();
             assert!(fs.exists("test/abc").unwrap());
         }
     }
@@ -165,11 +767,8 @@ mod test {
     fn double_mount() {
         for mount_point in TEST_PATHS {
             let fs = MountableFS::default();
-            fs.mount(mount_point, Box::new(MockFileSystem::new()))
-                .unwrap();
-            assert!(fs
-                .mount(mount_point, Box::new(MockFileSystem::new()))
-                .is_err())
+            fs.mount(mount_point, MockFileSystem::new()).unwrap();
+            assert!(fs.mount(mount_point, MockFileSystem::new()).is_err())
         }
     }
 
@@ -179,7 +778,7 @@ mod test {
         let memory_fs = MemoryFS::default();
         write!(memory_fs.create_file("abc").unwrap(), "file").unwrap();
         memory_fs.create_dir_all("folder/and/it").unwrap();
-        fs.mount("test", Box::new(memory_fs)).unwrap();
+        fs.mount("test", memory_fs).unwrap();
 
         fs
     }
@@ -209,6 +808,16 @@ mod test {
         assert!(fs.open_file("folder").is_err());
     }
 
+    #[test]
+    fn open_options() {
+        check_open_options(&mounted_fs(), "test/options");
+    }
+
+    #[test]
+    fn set_len() {
+        check_set_len(&mounted_fs(), "test/set_len");
+    }
+
     #[test]
     fn read_dir() {
         let fs = mounted_fs();
@@ -221,7 +830,7 @@ mod test {
 
         for path in ["/test", "./test/", "\\test/\\", "test/../test//"] {
             let dir = read_directory(&fs, path);
-            itertools::assert_equal(dir.keys(), vec!["abc", "folder"]);
+            itertools::assert_equal(dir.keys(), vec!["test/abc", "test/folder"]);
             itertools::assert_equal(
                 dir.values(),
                 vec![&Metadata::file(4), &Metadata::directory()],
@@ -229,6 +838,352 @@ mod test {
         }
     }
 
+    #[test]
+    fn root_mount() {
+        let fs = mounted_fs();
+
+        let base = MemoryFS::default();
+        write!(base.create_file("base").unwrap(), "base").unwrap();
+        write!(base.create_file("test").unwrap(), "shadowed").unwrap();
+        fs.mount("/", base).unwrap();
+        assert!(fs.mount("", MemoryFS::default()).is_err());
+
+        // the root mount serves everything outside of other mounts
+        assert_eq!(
+            fs.open_file("base").unwrap().read_into_string().unwrap(),
+            "base"
+        );
+        assert_eq!(
+            fs.open_file("test/abc")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "file"
+        );
+        assert_eq!(fs.metadata("test").unwrap(), Metadata::directory());
+
+        let root = read_directory(&fs, "/");
+        itertools::assert_equal(root.keys(), vec!["base", "test"]);
+        itertools::assert_equal(
+            root.values(),
+            vec![&Metadata::file(4), &Metadata::directory()],
+        );
+
+        // mounts can be nested within the root mount in any order
+        let fs = MountableFS::default();
+        fs.mount("", MemoryFS::default()).unwrap();
+        fs.mount("deep/mount", MemoryFS::default()).unwrap();
+        assert!(fs.exists("deep").unwrap());
+        itertools::assert_equal(read_directory(&fs, "").keys(), vec!["deep"]);
+    }
+
+    #[test]
+    fn nested_mounts() {
+        let data = MemoryFS::default();
+        write!(data.create_file("base").unwrap(), "base").unwrap();
+        write!(data.create_file("patch").unwrap(), "shadowed").unwrap();
+        data.create_dir_all("deep/folder").unwrap();
+
+        let patch = MemoryFS::default();
+        write!(patch.create_file("fix").unwrap(), "fix").unwrap();
+
+        // the order of mounting doesn't matter
+        let (data, patch) = (Arc::new(data), Arc::new(patch));
+        let forward = MountableFS::default();
+        forward.mount("data", data.clone()).unwrap();
+        forward.mount("data/patch", patch.clone()).unwrap();
+        let reverse = MountableFS::default();
+        reverse.mount("data/patch", patch).unwrap();
+        reverse.mount("data", data).unwrap();
+
+        for fs in [forward, reverse] {
+            fs.mount("data/deep/folder/inner", MemoryFS::default())
+                .unwrap();
+            itertools::assert_equal(read_directory(&fs, "").keys(), vec!["data"]);
+
+            let dir = read_directory(&fs, "data");
+            itertools::assert_equal(dir.keys(), vec!["data/base", "data/deep", "data/patch"]);
+            assert_eq!(dir["data/patch"], Metadata::directory());
+            assert_eq!(
+                fs.open_file("data/patch/fix")
+                    .unwrap()
+                    .read_into_string()
+                    .unwrap(),
+                "fix"
+            );
+            assert_eq!(
+                fs.open_file("data/base")
+                    .unwrap()
+                    .read_into_string()
+                    .unwrap(),
+                "base"
+            );
+            itertools::assert_equal(
+                read_directory(&fs, "data/deep/folder").keys(),
+                vec!["data/deep/folder/inner"],
+            );
+        }
+    }
+
+    #[test]
+    fn write_through() {
+        let fs = mounted_fs();
+
+        fs.create_dir("test/folder/new").unwrap();
+        fs.create_dir_all("test/new/nested").unwrap();
+        write!(fs.create_file("test/new/nested/file").unwrap(), "new").unwrap();
+        assert_eq!(
+            fs.open_file("test/new/nested/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "new"
+        );
+
+        fs.remove_file("test/new/nested/file").unwrap();
+        fs.remove_dir("test/new/nested").unwrap();
+        fs.remove_dir("test/folder/new").unwrap();
+        assert!(!fs.exists("test/new/nested").unwrap());
+        assert!(!fs.exists("test/folder/new").unwrap());
+
+        // the mount table itself can't be changed through the filesystem
+        assert!(fs.create_dir("test").is_err());
+        assert!(fs.create_dir("nonsense").is_err());
+        assert!(fs.remove_dir("test").is_err());
+        assert!(fs.remove_dir("").is_err());
+        assert!(fs.remove_file("test").is_err());
+    }
+
+    #[test]
+    fn rename() {
+        let fs = mounted_fs();
+        fs.mount("other", MemoryFS::default()).unwrap();
+
+        fs.rename("test/abc", "test/folder/renamed").unwrap();
+        assert!(!fs.exists("test/abc").unwrap());
+        assert_eq!(
+            fs.open_file("test/folder/renamed")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "file"
+        );
+
+        // entries can't leave the filesystem that owns them
+        let err = fs.rename("test/folder/renamed", "other/file").unwrap_err();
+        assert_eq!(err.vfs_kind(), Some(VfsErrorKind::CrossesMountPoint));
+        assert!(fs.exists("test/folder/renamed").unwrap());
+        assert!(!fs.exists("other/file").unwrap());
+
+        // nor can mount points be moved
+        assert!(fs.rename("test", "moved").is_err());
+        assert!(fs.rename("test/folder/renamed", "other").is_err());
+    }
+
+    #[test]
+    fn mount_options() {
+        let fs = MountableFS::default();
+
+        let mut read_only = MockFileSystem::new();
+        read_only.expect_metadata().returning(|_| {
+            Ok(Metadata {
+                mode: Some(0o755),
+                ..Metadata::file(4)
+            })
+        });
+        fs.mount_with_options(
+            "read_only",
+            read_only,
+            MountOptions::default().read_only(true),
+        )
+        .unwrap();
+        fs.mount_with_options(
+            "hidden",
+            MemoryFS::default(),
+            MountOptions::default().hide_from_read_dir(true),
+        )
+        .unwrap();
+        fs.mount_with_options(
+            "limited",
+            MemoryFS::default(),
+            MountOptions::default().size_limit(Some(4)),
+        )
+        .unwrap();
+
+        // read-only mounts reject writes before they reach the filesystem
+        assert_eq!(fs.metadata("read_only/file").unwrap().mode, Some(0o555));
+        for err in [
+            fs.create_file("read_only/file").err().unwrap(),
+            fs.open_file_options("read_only/file", &OpenOptions::new().append(true))
+                .err()
+                .unwrap(),
+            fs.create_dir("read_only/dir").unwrap_err(),
+            fs.remove_file("read_only/file").unwrap_err(),
+        ] {
+            assert_eq!(err.kind(), ErrorKind::ReadOnlyFilesystem);
+        }
+        assert!(fs.mounts()[2].1.read_only);
+
+        // hidden mounts can still be accessed
+        itertools::assert_equal(read_directory(&fs, "").keys(), vec!["limited", "read_only"]);
+        write!(fs.create_file("hidden/file").unwrap(), "file").unwrap();
+        assert!(fs.exists("hidden/file").unwrap());
+
+        let mut file = fs.create_file("limited/file").unwrap();
+        assert_eq!(
+            file.write_all(b"too long").unwrap_err().kind(),
+            ErrorKind::FileTooLarge
+        );
+        drop(file);
+        assert_eq!(
+            fs.open_file("limited/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "too "
+        );
+
+        // appended writes land at the end of the file, wherever the handle is positioned
+        let mut file = fs
+            .open_file_options("limited/file", &OpenOptions::new().append(true))
+            .unwrap();
+        file.rewind().unwrap();
+        assert_eq!(
+            file.write(b"more").unwrap_err().kind(),
+            ErrorKind::FileTooLarge
+        );
+        drop(file);
+        assert_eq!(fs.metadata("limited/file").unwrap().len, 4);
+
+        // files can't be resized past the limit either
+        let mut file = fs
+            .open_file_options("limited/file", &OpenOptions::new().write(true))
+            .unwrap();
+        assert_eq!(file.set_len(5).unwrap_err().kind(), ErrorKind::FileTooLarge);
+        file.set_len(2).unwrap();
+        drop(file);
+        assert_eq!(fs.metadata("limited/file").unwrap().len, 2);
+    }
+
+    #[test]
+    fn bind() {
+        let source = Arc::new(MemoryFS::default());
+        source.create_dir_all("textures/hd").unwrap();
+        write!(source.create_file("textures/hd/stone").unwrap(), "stone").unwrap();
+        write!(source.create_file("outside").unwrap(), "outside").unwrap();
+
+        let fs = MountableFS::default();
+        fs.mount("source", source.clone()).unwrap();
+        fs.bind("/hd", source.clone(), "/textures/hd").unwrap();
+        assert!(fs.bind("file", source.clone(), "outside").is_err());
+        assert!(fs.bind("missing", source, "missing").is_err());
+
+        assert_eq!(fs.metadata("hd").unwrap(), Metadata::directory());
+        itertools::assert_equal(read_directory(&fs, "hd").keys(), vec!["hd/stone"]);
+        assert_eq!(
+            fs.open_file("hd/stone")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "stone"
+        );
+
+        // the directory is shared with the original mount
+        write!(fs.create_file("hd/brick").unwrap(), "brick").unwrap();
+        assert!(fs.exists("source/textures/hd/brick").unwrap());
+        assert_eq!(fs.mounts()[0].1.source_path, Path::new("textures/hd"));
+    }
+
+    #[test]
+    fn remount() {
+        let fs = mounted_fs();
+        let mut old_file = fs.open_file("test/abc").unwrap();
+        assert!(fs.remount("nonsense", MemoryFS::default()).is_err());
+
+        let new_fs = MemoryFS::default();
+        write!(new_fs.create_file("abc").unwrap(), "new file").unwrap();
+        fs.remount("/test/", new_fs).unwrap();
+        assert_eq!(
+            fs.open_file("test/abc")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "new file"
+        );
+        assert!(!fs.exists("test/folder").unwrap());
+        assert_eq!(old_file.read_into_string().unwrap(), "file");
+
+        // lookups racing with remounts never miss the mount point
+        let fs = Arc::new(fs);
+        let reader = thread::spawn({
+            let fs = fs.clone();
+            move || {
+                for _ in 0..1000 {
+                    assert!(fs.exists("test/abc").unwrap());
+                }
+            }
+        });
+        for i in 0..100 {
+            let new_fs = MemoryFS::default();
+            write!(new_fs.create_file("abc").unwrap(), "{i}").unwrap();
+            fs.remount("test", new_fs).unwrap();
+        }
+        reader.join().unwrap();
+    }
+
+    #[test]
+    fn unmount() {
+        let fs = mounted_fs();
+        assert!(fs.unmount("nonsense").is_err());
+        fs.unmount("/test").unwrap();
+        assert!(!fs.exists("test").unwrap());
+        assert!(fs.unmount("test").is_err());
+    }
+
+    #[test]
+    fn mount_scoped() {
+        let fs = MountableFS::default();
+        {
+            let guard = fs.mount_scoped("/scoped/", MemoryFS::default()).unwrap();
+            assert_eq!(guard.path(), Path::new("scoped"));
+            assert!(fs.exists("scoped").unwrap());
+            assert!(fs.mount_scoped("scoped", MemoryFS::default()).is_err());
+        }
+        assert!(!fs.exists("scoped").unwrap());
+
+        // guards leave other filesystems mounted at the same path alone
+        let guard = fs.mount_scoped("scoped", MemoryFS::default()).unwrap();
+        fs.unmount("scoped").unwrap();
+        fs.mount("scoped", MemoryFS::default()).unwrap();
+        drop(guard);
+        assert!(fs.exists("scoped").unwrap());
+    }
+
+    #[test]
+    fn mounts() {
+        let fs = mounted_fs();
+        fs.mount("other/nested", MockFileSystem::new()).unwrap();
+        fs.mount("a", MemoryFS::default()).unwrap();
+
+        let mounts = fs.mounts();
+        itertools::assert_equal(
+            mounts.iter().map(|(path, _)| path.to_str().unwrap()),
+            vec!["a", "other/nested", "test"],
+        );
+        assert_eq!(
+            mounts[1].1,
+            MountInfo {
+                read_only: false,
+                type_name: "virtual_filesystem::MockFileSystem",
+                source_path: PathBuf::new(),
+            }
+        );
+        assert_eq!(
+            mounts[2].1.type_name,
+            "virtual_filesystem::memory_fs::MemoryFS"
+        );
+    }
+
     #[test]
     fn exists() {
         let fs = mounted_fs();
@@ -246,4 +1201,108 @@ mod test {
         assert!(fs.exists("test/folder").unwrap());
         assert!(fs.exists("test/folder/and/").unwrap());
     }
+
+    #[test]
+    fn mount_uri() {
+        let fs = MountableFS::default();
+        fs.mount_uri("zip://test/deep_fs.zip", "/zip").unwrap();
+        #[cfg(feature = "xz")]
+        fs.mount_uri("tar://test/deep_fs.tar.xz", "tar").unwrap();
+        fs.mount_uri("physical://test", "physical").unwrap();
+        fs.mount_uri("memory://", "scratch").unwrap();
+
+        assert_eq!(
+            fs.open_file("zip/folder/and/it/desc")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "it\n"
+        );
+        assert!(fs.exists("physical/deep_fs.zip").unwrap());
+        write!(fs.create_file("scratch/file").unwrap(), "file").unwrap();
+        assert_eq!(fs.metadata("scratch/file").unwrap(), Metadata::file(4));
+        #[cfg(feature = "xz")]
+        assert!(fs.exists("tar/folder/and/it/desc").unwrap());
+        assert_eq!(
+            fs.mounts().last().unwrap().1.type_name,
+            "virtual_filesystem::zip_fs::ZipFS<std::fs::File>"
+        );
+
+        for uri in [
+            "test/deep_fs.zip",
+            "nonsense://test",
+            "memory://test",
+            "zip://test/nonsense.zip",
+        ] {
+            assert!(fs.mount_uri(uri, "error").is_err());
+        }
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn from_config() {
+        let toml = r#"
+            [[mounts]]
+            path = "/assets"
+            uri = "zip://test/deep_fs.zip"
+            read_only = true
+
+            [[mounts]]
+            path = "scratch"
+            uri = "memory://"
+            size_limit = 4
+        "#;
+        let json = r#"{
+            "mounts": [
+                { "path": "/assets", "uri": "zip://test/deep_fs.zip", "read_only": true },
+                { "path": "scratch", "uri": "memory://", "size_limit": 4 }
+            ]
+        }"#;
+
+        for config in [toml, json] {
+            let fs = MountableFS::from_config(config).unwrap();
+            itertools::assert_equal(read_directory(&fs, "").keys(), vec!["assets", "scratch"]);
+            assert!(fs.mounts()[0].1.read_only);
+            assert!(fs.exists("assets/folder/and/it/desc").unwrap());
+
+            let mut file = fs.create_file("scratch/file").unwrap();
+            assert_eq!(
+                file.write_all(b"too long").unwrap_err().kind(),
+                ErrorKind::FileTooLarge
+            );
+        }
+
+        assert_eq!(
+            MountableFS::from_config("[[mounts]]\npath = 1")
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::InvalidData
+        );
+        assert!(MountableFS::from_config("").unwrap().mounts().is_empty());
+    }
+
+    #[test]
+    fn fallback() {
+        let fs = mounted_fs();
+        assert!(!fs.exists("scratch").unwrap());
+
+        let scratch = MemoryFS::default();
+        write!(scratch.create_file("test").unwrap(), "shadowed").unwrap();
+        fs.set_fallback(scratch);
+
+        // unmatched paths are delegated to the fallback, including writes
+        fs.create_dir("scratch").unwrap();
+        write!(fs.create_file("scratch/file").unwrap(), "file").unwrap();
+        assert_eq!(fs.metadata("scratch/file").unwrap(), Metadata::file(4));
+        itertools::assert_equal(read_directory(&fs, "").keys(), vec!["scratch", "test"]);
+        assert_eq!(fs.metadata("test").unwrap(), Metadata::directory());
+        assert_eq!(fs.mounts().len(), 1);
+
+        // a root mount takes precedence
+        fs.mount("/", MemoryFS::default()).unwrap();
+        assert!(!fs.exists("scratch").unwrap());
+        fs.unmount("/").unwrap();
+        assert!(fs.exists("scratch/file").unwrap());
+    }
 }
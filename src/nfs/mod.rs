@@ -0,0 +1,404 @@
+mod server;
+
+use crate::file::{FileType, Metadata};
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, SystemTime};
+
+pub use server::{serve, serve_listener};
+
+/// The programs and versions spoken.
+pub(crate) const PORTMAP_PROGRAM: u32 = 100000;
+pub(crate) const PORTMAP_VERSION: u32 = 2;
+pub(crate) const NFS_PROGRAM: u32 = 100003;
+pub(crate) const NFS_VERSION: u32 = 3;
+pub(crate) const MOUNT_PROGRAM: u32 = 100005;
+pub(crate) const MOUNT_VERSION: u32 = 3;
+/// The protocol number of TCP, which the ports of programs are looked up for.
+pub(crate) const IPPROTO_TCP: u32 = 6;
+/// The largest record that may be received, which bounds the payload of reads and writes.
+pub(crate) const MAX_RECORD_SIZE: usize = 4 * 1024 * 1024;
+/// The flavor of `AUTH_SYS` credentials, which carry the numeric user and groups of the caller.
+pub(crate) const AUTH_SYS: u32 = 1;
+
+/// The procedures of the portmapper, the mount protocol and NFS.
+pub(crate) mod procedure {
+    /// The procedure of every program that does nothing, to check that the program is served.
+    pub(crate) const NULL: u32 = 0;
+    pub(crate) const PMAP_GETPORT: u32 = 3;
+    pub(crate) const MOUNT_MNT: u32 = 1;
+    pub(crate) const MOUNT_DUMP: u32 = 2;
+    pub(crate) const MOUNT_UMNT: u32 = 3;
+    pub(crate) const MOUNT_UMNTALL: u32 = 4;
+    pub(crate) const MOUNT_EXPORT: u32 = 5;
+    pub(crate) const GETATTR: u32 = 1;
+    pub(crate) const SETATTR: u32 = 2;
+    pub(crate) const LOOKUP: u32 = 3;
+    pub(crate) const ACCESS: u32 = 4;
+    pub(crate) const READLINK: u32 = 5;
+    pub(crate) const READ: u32 = 6;
+    pub(crate) const WRITE: u32 = 7;
+    pub(crate) const CREATE: u32 = 8;
+    pub(crate) const MKDIR: u32 = 9;
+    pub(crate) const SYMLINK: u32 = 10;
+    pub(crate) const MKNOD: u32 = 11;
+    pub(crate) const REMOVE: u32 = 12;
+    pub(crate) const RMDIR: u32 = 13;
+    pub(crate) const RENAME: u32 = 14;
+    pub(crate) const LINK: u32 = 15;
+    pub(crate) const READDIR: u32 = 16;
+    pub(crate) const READDIRPLUS: u32 = 17;
+    pub(crate) const FSSTAT: u32 = 18;
+    pub(crate) const FSINFO: u32 = 19;
+    pub(crate) const PATHCONF: u32 = 20;
+    pub(crate) const COMMIT: u32 = 21;
+}
+
+/// The status codes of NFS results.
+pub(crate) mod status {
+    pub(crate) const OK: u32 = 0;
+    pub(crate) const PERM: u32 = 1;
+    pub(crate) const NOENT: u32 = 2;
+    pub(crate) const IO: u32 = 5;
+    pub(crate) const ACCES: u32 = 13;
+    pub(crate) const EXIST: u32 = 17;
+    pub(crate) const XDEV: u32 = 18;
+    pub(crate) const NOTDIR: u32 = 20;
+    pub(crate) const ISDIR: u32 = 21;
+    pub(crate) const INVAL: u32 = 22;
+    pub(crate) const NOSPC: u32 = 28;
+    pub(crate) const ROFS: u32 = 30;
+    pub(crate) const NAMETOOLONG: u32 = 63;
+    pub(crate) const NOTEMPTY: u32 = 66;
+    pub(crate) const DQUOT: u32 = 69;
+    pub(crate) const STALE: u32 = 70;
+    pub(crate) const BADHANDLE: u32 = 10001;
+    pub(crate) const NOTSUPP: u32 = 10004;
+    pub(crate) const TOOSMALL: u32 = 10005;
+}
+
+/// The types of files.
+pub(crate) const NF3REG: u32 = 1;
+pub(crate) const NF3DIR: u32 = 2;
+/// The mode of `CREATE` that creates the file or opens it if it exists.
+pub(crate) const UNCHECKED: u32 = 0;
+/// The modes of `CREATE` that fail if the file exists, the latter of which has a verifier instead of attributes.
+pub(crate) const GUARDED: u32 = 1;
+pub(crate) const EXCLUSIVE: u32 = 2;
+/// The stability of writes that are committed to storage before they're acknowledged.
+pub(crate) const FILE_SYNC: u32 = 2;
+
+/// Encodes XDR data, which is big-endian and padded to four bytes.
+#[derive(Default)]
+pub(crate) struct Encoder(Vec<u8>);
+
+impl Encoder {
+    pub(crate) fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) -> &mut Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) -> &mut Self {
+        self.u32(u32::from(value))
+    }
+
+    /// Appends variable-length opaque data, prefixed with its length.
+    ///
+    /// # Arguments
+    /// `value`: The data.  
+    pub(crate) fn opaque(&mut self, value: &[u8]) -> &mut Self {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value);
+        self.0.resize(self.0.len().next_multiple_of(4), 0);
+        self
+    }
+
+    /// Appends a string, prefixed with its length.
+    ///
+    /// # Arguments
+    /// `value`: The string.  
+    pub(crate) fn str(&mut self, value: &str) -> &mut Self {
+        self.opaque(value.as_bytes())
+    }
+
+    /// Returns the encoded bytes.
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Decodes XDR data.
+pub(crate) struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    /// Creates a decoder of `data`.
+    ///
+    /// # Arguments
+    /// `data`: The data.  
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+
+    /// Takes `len` bytes, and the padding after them.
+    ///
+    /// # Arguments
+    /// `len`: The number of bytes.  
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let padded_len = len.next_multiple_of(4);
+        if self.0.len() < padded_len {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Truncated XDR data"));
+        }
+
+        let (taken, rest) = self.0.split_at(padded_len);
+        self.0 = rest;
+        Ok(&taken[..len])
+    }
+
+    pub(crate) fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u32()? != 0)
+    }
+
+    /// Takes variable-length opaque data prefixed with its length.
+    pub(crate) fn opaque(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Takes a string prefixed with its length.
+    pub(crate) fn str(&mut self) -> io::Result<&'a str> {
+        std::str::from_utf8(self.opaque()?)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "XDR string isn't UTF-8"))
+    }
+
+    /// Returns the data that wasn't decoded yet.
+    pub(crate) fn rest(&self) -> &'a [u8] {
+        self.0
+    }
+
+    /// Skips `len` bytes.
+    ///
+    /// # Arguments
+    /// `len`: The number of bytes, which is a multiple of four.  
+    pub(crate) fn skip(&mut self, len: usize) -> io::Result<()> {
+        self.take(len).map(|_| ())
+    }
+
+    /// Takes the attributes of a file, returning its metadata.
+    pub(crate) fn fattr(&mut self) -> io::Result<Metadata> {
+        let (ty, mode) = (self.u32()?, self.u32()?);
+        // the link count
+        self.skip(4)?;
+        let (uid, gid, size) = (self.u32()?, self.u32()?, self.u64()?);
+        // the used space, device, filesystem and file ID
+        self.skip(8 + 8 + 8 + 8)?;
+        let accessed = self.time()?;
+        let modified = self.time()?;
+        // the change time
+        self.skip(8)?;
+
+        let file_type = match ty {
+            NF3DIR => FileType::Directory,
+            NF3REG => FileType::File,
+            _ => FileType::Unknown,
+        };
+        let mut metadata = Metadata::new(
+            file_type,
+            if file_type == FileType::Directory {
+                0
+            } else {
+                size
+            },
+        );
+        metadata.mode = Some(mode & 0o7777);
+        metadata.modified = modified;
+        metadata.accessed = accessed;
+        metadata.uid = Some(uid);
+        metadata.gid = Some(gid);
+        Ok(metadata)
+    }
+
+    /// Takes a time, which may not be representable on this host.
+    fn time(&mut self) -> io::Result<Option<SystemTime>> {
+        let (secs, nsecs) = (self.u32()?, self.u32()?);
+        Ok(SystemTime::UNIX_EPOCH.checked_add(Duration::new(secs as u64, nsecs.min(999_999_999))))
+    }
+
+    /// Takes attributes that may be missing.
+    pub(crate) fn post_op_attr(&mut self) -> io::Result<Option<Metadata>> {
+        self.bool()?.then(|| self.fattr()).transpose()
+    }
+
+    /// Takes a file handle that may be missing.
+    pub(crate) fn post_op_fh(&mut self) -> io::Result<Option<&'a [u8]>> {
+        self.bool()?.then(|| self.opaque()).transpose()
+    }
+
+    /// Skips the attributes of a directory before and after it was changed.
+    pub(crate) fn wcc_data(&mut self) -> io::Result<()> {
+        // the size, modification time and change time before
+        if self.bool()? {
+            self.skip(8 + 8 + 8)?;
+        }
+        self.post_op_attr().map(|_| ())
+    }
+}
+
+/// Encodes the header of a call, which its arguments follow.
+///
+/// # Arguments
+/// `xid`: The ID the call is matched with its reply by.  
+/// `program`: The program.  
+/// `version`: The version of the program.  
+/// `procedure`: The procedure.  
+/// `credentials`: The body of the `AUTH_SYS` credentials.  
+pub(crate) fn call_header(
+    xid: u32,
+    program: u32,
+    version: u32,
+    procedure: u32,
+    credentials: &[u8],
+) -> Encoder {
+    let mut call = Encoder::default();
+    // a call of RPC version 2, and no verifier
+    call.u32(xid)
+        .u32(0)
+        .u32(2)
+        .u32(program)
+        .u32(version)
+        .u32(procedure);
+    call.u32(AUTH_SYS).opaque(credentials).u32(0).u32(0);
+    call
+}
+
+/// Decodes the header of the reply to the call `xid`. Returns the decoder of its results.
+///
+/// # Arguments
+/// `reply`: The reply.  
+/// `xid`: The ID of the call.  
+pub(crate) fn reply_results(reply: &[u8], xid: u32) -> io::Result<Decoder<'_>> {
+    let mut reply = Decoder::new(reply);
+    if reply.u32()? != xid || reply.u32()? != 1 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Unexpected RPC reply",
+        ));
+    }
+    if reply.u32()? != 0 {
+        return Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            "RPC call was denied",
+        ));
+    }
+
+    let _verifier_flavor = reply.u32()?;
+    reply.opaque()?;
+    match reply.u32()? {
+        0 => Ok(reply),
+        1..=3 => Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "RPC program or procedure is unavailable",
+        )),
+        _ => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "RPC call was rejected",
+        )),
+    }
+}
+
+/// Reads a record, joining its fragments.
+///
+/// # Arguments
+/// `reader`: The connection.  
+pub(crate) fn read_record<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut record = Vec::new();
+    loop {
+        let mut header = [0; 4];
+        reader.read_exact(&mut header)?;
+        let header = u32::from_be_bytes(header);
+        let len = (header & 0x7fff_ffff) as usize;
+        if record.len() + len > MAX_RECORD_SIZE {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "RPC record is too large",
+            ));
+        }
+
+        let start = record.len();
+        record.resize(start + len, 0);
+        reader.read_exact(&mut record[start..])?;
+        // the high bit marks the last fragment
+        if header & 0x8000_0000 != 0 {
+            return Ok(record);
+        }
+    }
+}
+
+/// Writes a record as a single fragment.
+///
+/// # Arguments
+/// `writer`: The connection.  
+/// `record`: The record.  
+pub(crate) fn write_record<W: Write>(writer: &mut W, record: &[u8]) -> io::Result<()> {
+    let header = 0x8000_0000 | record.len() as u32;
+    writer.write_all(&[&header.to_be_bytes()[..], record].concat())?;
+    writer.flush()
+}
+
+/// Converts an NFS status to an IO error.
+///
+/// # Arguments
+/// `status`: The status.  
+pub(crate) fn status_error(status: u32) -> io::Error {
+    let kind = match status {
+        status::NOENT | status::STALE => ErrorKind::NotFound,
+        status::PERM | status::ACCES => ErrorKind::PermissionDenied,
+        status::EXIST => ErrorKind::AlreadyExists,
+        status::XDEV => ErrorKind::CrossesDevices,
+        status::NOTDIR => ErrorKind::NotADirectory,
+        status::ISDIR => ErrorKind::IsADirectory,
+        status::INVAL | status::NAMETOOLONG => ErrorKind::InvalidInput,
+        status::NOSPC => ErrorKind::StorageFull,
+        status::ROFS => ErrorKind::ReadOnlyFilesystem,
+        status::NOTEMPTY => ErrorKind::DirectoryNotEmpty,
+        status::DQUOT => ErrorKind::QuotaExceeded,
+        status::NOTSUPP => ErrorKind::Unsupported,
+        _ => ErrorKind::Other,
+    };
+
+    io::Error::new(kind, format!("NFS error {status}"))
+}
+
+/// Converts an IO error to an NFS status.
+///
+/// # Arguments
+/// `err`: The error.  
+pub(crate) fn error_status(err: &io::Error) -> u32 {
+    match err.kind() {
+        ErrorKind::NotFound => status::NOENT,
+        ErrorKind::PermissionDenied => status::ACCES,
+        ErrorKind::AlreadyExists => status::EXIST,
+        ErrorKind::CrossesDevices => status::XDEV,
+        ErrorKind::NotADirectory => status::NOTDIR,
+        ErrorKind::IsADirectory => status::ISDIR,
+        ErrorKind::InvalidInput => status::INVAL,
+        ErrorKind::StorageFull => status::NOSPC,
+        ErrorKind::QuotaExceeded => status::DQUOT,
+        ErrorKind::ReadOnlyFilesystem => status::ROFS,
+        ErrorKind::DirectoryNotEmpty => status::NOTEMPTY,
+        ErrorKind::Unsupported => status::NOTSUPP,
+        _ => status::IO,
+    }
+}
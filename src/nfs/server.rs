@@ -0,0 +1,990 @@
+use crate::file::{FileType, Metadata, OpenOptions};
+use crate::nfs::{
+    error_status, procedure, read_record, status, write_record, Decoder, Encoder, AUTH_SYS,
+    EXCLUSIVE, FILE_SYNC, GUARDED, IPPROTO_TCP, MAX_RECORD_SIZE, MOUNT_PROGRAM, MOUNT_VERSION,
+    NF3DIR, NF3REG, NFS_PROGRAM, NFS_VERSION, PORTMAP_PROGRAM, PORTMAP_VERSION,
+};
+use crate::util::{component_iter, now};
+use crate::{util, FileSystem};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+/// The version of RPC spoken.
+const RPC_VERSION: u32 = 2;
+/// The flavor of calls without credentials, and of the verifiers of replies.
+const AUTH_NONE: u32 = 0;
+/// The reason a call is denied when its credentials are malformed.
+const AUTH_BADCRED: u32 = 1;
+/// The user and group that files are owned by for callers without credentials.
+const NOBODY: u32 = 65534;
+/// The most bytes a single read or write transfers, which leaves room in a record for the rest of the reply.
+const MAX_IO_SIZE: u32 = (MAX_RECORD_SIZE / 4) as u32;
+/// The file system ID of every file.
+const FSID: u64 = 0x7666_7300;
+/// The size of encoded attributes, and of attributes that may be missing.
+const FATTR_SIZE: usize = 84;
+const POST_OP_ATTR_SIZE: usize = 4 + FATTR_SIZE;
+/// The size of a file handle, and of a file handle that may be missing.
+const HANDLE_SIZE: usize = 16;
+const POST_OP_FH_SIZE: usize = 4 + 4 + HANDLE_SIZE;
+
+/// The reasons an accepted call has no results.
+mod accept {
+    pub(super) const SUCCESS: u32 = 0;
+    pub(super) const PROG_UNAVAIL: u32 = 1;
+    pub(super) const PROG_MISMATCH: u32 = 2;
+    pub(super) const PROC_UNAVAIL: u32 = 3;
+    pub(super) const GARBAGE_ARGS: u32 = 4;
+}
+
+/// Serves `fs` over NFSv3 on TCP at `addr` until accepting a connection fails. The root of the filesystem is
+/// exported as `/`, and any of its directories may be mounted by their paths.
+///
+/// The portmapper, the mount protocol and NFS are all served on the same port, so a client that doesn't ask the
+/// portmapper on port 111 must be told the port of each, such as with
+/// `mount -t nfs -o vers=3,proto=tcp,port=2049,mountport=2049,nolock server:/ /mnt`. Callers aren't authenticated,
+/// and files are reported as owned by the caller.
+///
+/// # Arguments
+/// `fs`: The filesystem to serve.  
+/// `addr`: The address to listen on.  
+pub fn serve<FS: FileSystem + Sync, A: ToSocketAddrs>(fs: FS, addr: A) -> io::Result<()> {
+    serve_listener(fs, TcpListener::bind(addr)?)
+}
+
+/// Serves `fs` over NFSv3 on the connections accepted by `listener` until accepting one fails. This allows serving
+/// on a port that was picked by the system.
+///
+/// # Arguments
+/// `fs`: The filesystem to serve.  
+/// `listener`: The listener to accept connections from.  
+pub fn serve_listener<FS: FileSystem + Sync>(fs: FS, listener: TcpListener) -> io::Result<()> {
+    let server = Server::new(fs, listener.local_addr()?.port());
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let server = &server;
+            scope.spawn(move || server.session(stream));
+        }
+        Ok(())
+    })
+}
+
+/// The paths of the file handles that were handed out. Handles hold a number rather than a path, since they're
+/// limited to 64 bytes.
+#[derive(Default)]
+struct Handles {
+    paths: HashMap<u64, String>,
+    ids: HashMap<String, u64>,
+    next_id: u64,
+}
+
+impl Handles {
+    /// Returns the number of the handle of `path`.
+    fn id(&mut self, path: &str) -> u64 {
+        if let Some(id) = self.ids.get(path) {
+            return *id;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.paths.insert(id, path.to_owned());
+        self.ids.insert(path.to_owned(), id);
+        id
+    }
+
+    /// Forgets the handle of `path`, which was removed.
+    fn remove(&mut self, path: &str) {
+        if let Some(id) = self.ids.remove(path) {
+            self.paths.remove(&id);
+        }
+    }
+
+    /// Moves the handles of `from` and the entries under it to `to`, so that they follow the renamed entries.
+    fn rename(&mut self, from: &str, to: &str) {
+        self.remove(to);
+        let prefix = format!("{from}/");
+        let moved = self
+            .ids
+            .iter()
+            .filter(|(path, _)| *path == from || path.starts_with(&prefix))
+            .map(|(path, id)| (path.clone(), *id))
+            .collect::<Vec<_>>();
+        for (path, id) in moved {
+            let renamed = format!("{to}{}", &path[from.len()..]);
+            self.ids.remove(&path);
+            self.ids.insert(renamed.clone(), id);
+            self.paths.insert(id, renamed);
+        }
+    }
+}
+
+/// The caller of a procedure.
+#[derive(Copy, Clone)]
+struct Caller {
+    uid: u32,
+    gid: u32,
+}
+
+/// Serves a filesystem to every connection.
+struct Server<FS> {
+    fs: FS,
+    handles: Mutex<Handles>,
+    /// The port served on, which the portmapper reports for the mount protocol and NFS.
+    port: u16,
+    /// Identifies this instance of the server, so that its handles and writes aren't mistaken for those of another.
+    verifier: u64,
+}
+
+impl<FS: FileSystem> Server<FS> {
+    fn new(fs: FS, port: u16) -> Self {
+        let verifier = now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_nanos() as u64);
+        Self {
+            fs,
+            handles: Mutex::default(),
+            port,
+            verifier,
+        }
+    }
+
+    /// Serves a connection until it's closed.
+    fn session(&self, mut stream: TcpStream) {
+        while let Ok(call) = read_record(&mut stream) {
+            let Some(reply) = self.reply(&call) else {
+                continue;
+            };
+            if write_record(&mut stream, reply.bytes()).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Handles a call, returning its reply, or `None` if it isn't a call.
+    fn reply(&self, call: &[u8]) -> Option<Encoder> {
+        let mut call = Decoder::new(call);
+        let xid = call.u32().ok()?;
+        if call.u32().ok()? != 0 {
+            return None;
+        }
+
+        let mut reply = Encoder::default();
+        reply.u32(xid).u32(1);
+        if call.u32().ok()? != RPC_VERSION {
+            // denied, since the RPC version isn't supported
+            reply.u32(1).u32(0).u32(RPC_VERSION).u32(RPC_VERSION);
+            return Some(reply);
+        }
+
+        let (program, version, procedure) = (call.u32().ok()?, call.u32().ok()?, call.u32().ok()?);
+        let (flavor, credentials) = (call.u32().ok()?, call.opaque().ok()?);
+        let (_verifier_flavor, _verifier) = (call.u32().ok()?, call.opaque().ok()?);
+        let caller = match flavor {
+            AUTH_SYS => {
+                let mut credentials = Decoder::new(credentials);
+                let caller = credentials.u32().and_then(|_stamp| {
+                    let _machine_name = credentials.str()?;
+                    Ok(Caller {
+                        uid: credentials.u32()?,
+                        gid: credentials.u32()?,
+                    })
+                });
+                match caller {
+                    Ok(caller) => caller,
+                    // denied, since the credentials are malformed
+                    Err(_) => {
+                        reply.u32(1).u32(1).u32(AUTH_BADCRED);
+                        return Some(reply);
+                    }
+                }
+            }
+            _ => Caller {
+                uid: NOBODY,
+                gid: NOBODY,
+            },
+        };
+
+        // accepted, with no verifier
+        reply.u32(0).u32(AUTH_NONE).opaque(&[]);
+        let header_len = reply.0.len();
+        reply.u32(accept::SUCCESS);
+        let result = match (program, version) {
+            (PORTMAP_PROGRAM, PORTMAP_VERSION) => self.portmap(procedure, &mut call, &mut reply),
+            (MOUNT_PROGRAM, MOUNT_VERSION) => self.mount(procedure, &mut call, &mut reply),
+            (NFS_PROGRAM, NFS_VERSION) => self.nfs(procedure, caller, &mut call, &mut reply),
+            (PORTMAP_PROGRAM | MOUNT_PROGRAM | NFS_PROGRAM, _) => {
+                let version = match program {
+                    PORTMAP_PROGRAM => PORTMAP_VERSION,
+                    MOUNT_PROGRAM => MOUNT_VERSION,
+                    _ => NFS_VERSION,
+                };
+                reply.0.truncate(header_len);
+                reply.u32(accept::PROG_MISMATCH).u32(version).u32(version);
+                return Some(reply);
+            }
+            _ => Ok(false),
+        };
+
+        let accept = match result {
+            Ok(true) => return Some(reply),
+            Ok(false) if ![PORTMAP_PROGRAM, MOUNT_PROGRAM, NFS_PROGRAM].contains(&program) => {
+                accept::PROG_UNAVAIL
+            }
+            Ok(false) => accept::PROC_UNAVAIL,
+            Err(_) => accept::GARBAGE_ARGS,
+        };
+        reply.0.truncate(header_len);
+        reply.u32(accept);
+        Some(reply)
+    }
+
+    /// Handles a call to the portmapper. Returns false if the procedure isn't served.
+    fn portmap(&self, procedure: u32, args: &mut Decoder, reply: &mut Encoder) -> io::Result<bool> {
+        match procedure {
+            procedure::NULL => {}
+            procedure::PMAP_GETPORT => {
+                let (program, version, protocol) = (args.u32()?, args.u32()?, args.u32()?);
+                let served = matches!(
+                    (program, version),
+                    (MOUNT_PROGRAM, MOUNT_VERSION) | (NFS_PROGRAM, NFS_VERSION)
+                );
+                reply.u32(match served && protocol == IPPROTO_TCP {
+                    true => self.port as u32,
+                    false => 0,
+                });
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Handles a call to the mount protocol. Returns false if the procedure isn't served.
+    fn mount(&self, procedure: u32, args: &mut Decoder, reply: &mut Encoder) -> io::Result<bool> {
+        match procedure {
+            procedure::NULL | procedure::MOUNT_UMNT | procedure::MOUNT_UMNTALL => {}
+            procedure::MOUNT_MNT => {
+                let path = component_iter(Path::new(args.str()?))
+                    .collect::<Vec<_>>()
+                    .join("/");
+                match self.metadata(&path) {
+                    Ok(metadata) if metadata.is_directory() => {
+                        reply.u32(status::OK).opaque(&self.handle(&path));
+                        reply.u32(2).u32(AUTH_NONE).u32(AUTH_SYS);
+                    }
+                    Ok(_) => {
+                        reply.u32(status::NOTDIR);
+                    }
+                    Err(status) => {
+                        reply.u32(status);
+                    }
+                }
+            }
+            // nothing is mounted, since mounts aren't recorded
+            procedure::MOUNT_DUMP => {
+                reply.bool(false);
+            }
+            // the root is exported to everyone
+            procedure::MOUNT_EXPORT => {
+                reply.bool(true).str("/").bool(false).bool(false);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Handles a call to NFS. Returns false if the procedure isn't served.
+    fn nfs(
+        &self,
+        procedure: u32,
+        caller: Caller,
+        args: &mut Decoder,
+        reply: &mut Encoder,
+    ) -> io::Result<bool> {
+        match procedure {
+            procedure::NULL => {}
+            procedure::GETATTR => {
+                let path = self.path(args.opaque()?);
+                match path.and_then(|path| Ok((self.metadata(&path)?, path))) {
+                    Ok((metadata, path)) => {
+                        reply.u32(status::OK);
+                        self.fattr(reply, caller, &path, &metadata);
+                    }
+                    Err(status) => {
+                        reply.u32(status);
+                    }
+                }
+            }
+            procedure::SETATTR => {
+                let path = self.path(args.opaque()?);
+                let attributes = Attributes::decode(args)?;
+                // the guard on the change time, which is never checked
+                if args.bool()? {
+                    args.skip(8)?;
+                }
+                // modes, owners and times are ignored, since filesystems can't change them
+                let result = path.and_then(|path| match attributes.size {
+                    Some(size) => util::set_len(&self.fs, &path, size)
+                        .map(|_| path)
+                        .map_err(|err| error_status(&err)),
+                    None => self.metadata(&path).map(|_| path),
+                });
+                self.status(reply, &result);
+                self.wcc_data(reply, caller, result.ok().as_deref());
+            }
+            procedure::LOOKUP => {
+                let (dir, name) = (self.path(args.opaque()?), args.str()?);
+                let result = dir.clone().and_then(|dir| {
+                    let path = self.lookup(&dir, name)?;
+                    Ok((self.metadata(&path)?, path))
+                });
+                match result {
+                    Ok((metadata, path)) => {
+                        reply.u32(status::OK).opaque(&self.handle(&path)).bool(true);
+                        self.fattr(reply, caller, &path, &metadata);
+                    }
+                    Err(status) => {
+                        reply.u32(status);
+                    }
+                }
+                self.post_op_attr(reply, caller, dir.ok().as_deref());
+            }
+            procedure::ACCESS => {
+                let (path, access) = (self.path(args.opaque()?), args.u32()?);
+                let result = path.and_then(|path| self.metadata(&path).map(|_| path));
+                self.status(reply, &result);
+                self.post_op_attr(reply, caller, result.as_deref().ok());
+                // everything that's asked for is allowed, and refused by the filesystem if it isn't
+                if result.is_ok() {
+                    reply.u32(access & 0x3f);
+                }
+            }
+            procedure::READ => {
+                let path = self.path(args.opaque()?);
+                let (offset, count) = (args.u64()?, args.u32()?.min(MAX_IO_SIZE));
+                let result = path.clone().and_then(|path| {
+                    let mut file = self.fs.open_file(&path).map_err(|err| error_status(&err))?;
+                    let len = file.metadata().map_err(|err| error_status(&err))?.len();
+                    let mut data = Vec::new();
+                    file.seek(SeekFrom::Start(offset))
+                        .and_then(|_| file.take(count as u64).read_to_end(&mut data))
+                        .map_err(|err| error_status(&err))?;
+                    Ok((offset + data.len() as u64 >= len, data))
+                });
+                self.status(reply, &result);
+                self.post_op_attr(reply, caller, path.ok().as_deref());
+                if let Ok((eof, data)) = result {
+                    reply.u32(data.len() as u32).bool(eof).opaque(&data);
+                }
+            }
+            procedure::WRITE => {
+                let path = self.path(args.opaque()?);
+                let (offset, _count, _stable) = (args.u64()?, args.u32()?, args.u32()?);
+                let data = args.opaque()?;
+                let result = path.and_then(|path| {
+                    let options = OpenOptions {
+                        write: true,
+                        ..OpenOptions::default()
+                    };
+                    let mut file = self
+                        .fs
+                        .open_file_options(&path, &options)
+                        .map_err(|err| error_status(&err))?;
+                    file.seek(SeekFrom::Start(offset))
+                        .and_then(|_| file.write_all(data))
+                        .and_then(|_| file.flush())
+                        .map_err(|err| error_status(&err))?;
+                    Ok(path)
+                });
+                self.status(reply, &result);
+                self.wcc_data(reply, caller, result.as_deref().ok());
+                // every write is committed before it's acknowledged
+                if result.is_ok() {
+                    reply
+                        .u32(data.len() as u32)
+                        .u32(FILE_SYNC)
+                        .u64(self.verifier);
+                }
+            }
+            procedure::CREATE | procedure::MKDIR => {
+                let (dir, name) = (self.path(args.opaque()?), args.str()?);
+                let (mode, attributes) = match procedure {
+                    procedure::CREATE => match args.u32()? {
+                        EXCLUSIVE => {
+                            args.skip(8)?;
+                            (EXCLUSIVE, Attributes::default())
+                        }
+                        mode => (mode, Attributes::decode(args)?),
+                    },
+                    _ => (GUARDED, Attributes::decode(args)?),
+                };
+                let result = dir.clone().and_then(|dir| {
+                    let path = self.child(&dir, name)?;
+                    let exclusive = mode == GUARDED || mode == EXCLUSIVE;
+                    if exclusive && self.fs.exists(&path).map_err(|err| error_status(&err))? {
+                        return Err(status::EXIST);
+                    }
+                    let created = match procedure {
+                        procedure::CREATE => {
+                            let options = OpenOptions {
+                                create: true,
+                                write: true,
+                                truncate: attributes.size == Some(0),
+                                mode: attributes.mode,
+                                ..OpenOptions::default()
+                            };
+                            self.fs.open_file_options(&path, &options).map(drop)
+                        }
+                        _ => self
+                            .fs
+                            .create_dir_with(&path, attributes.mode.unwrap_or(0o755)),
+                    };
+                    created.map_err(|err| error_status(&err))?;
+                    Ok(path)
+                });
+                self.status(reply, &result);
+                if let Ok(path) = &result {
+                    reply.bool(true).opaque(&self.handle(path));
+                    self.post_op_attr(reply, caller, Some(path));
+                }
+                self.wcc_data(reply, caller, dir.ok().as_deref());
+            }
+            procedure::REMOVE | procedure::RMDIR => {
+                let (dir, name) = (self.path(args.opaque()?), args.str()?);
+                let result = dir.clone().and_then(|dir| {
+                    let path = self.child(&dir, name)?;
+                    let removed = match procedure {
+                        procedure::REMOVE => self.fs.remove_file(&path),
+                        // some filesystems remove directories with their contents, which `RMDIR` never does
+                        _ => match self.fs.read_dir(&path).map(|mut entries| entries.next()) {
+                            Ok(Some(_)) => return Err(status::NOTEMPTY),
+                            Ok(None) => self.fs.remove_dir(&path),
+                            Err(err) => Err(err),
+                        },
+                    };
+                    removed.map_err(|err| error_status(&err))?;
+                    self.handles.lock().remove(&path);
+                    Ok(())
+                });
+                self.status(reply, &result);
+                self.wcc_data(reply, caller, dir.ok().as_deref());
+            }
+            procedure::RENAME => {
+                let (from_dir, from_name) = (self.path(args.opaque()?), args.str()?);
+                let (to_dir, to_name) = (self.path(args.opaque()?), args.str()?);
+                let result = from_dir.clone().and_then(|from_dir| {
+                    let to_dir = to_dir.clone()?;
+                    let (from, to) = (
+                        self.child(&from_dir, from_name)?,
+                        self.child(&to_dir, to_name)?,
+                    );
+                    self.fs
+                        .rename(&from, &to)
+                        .map_err(|err| error_status(&err))?;
+                    self.handles.lock().rename(&from, &to);
+                    Ok(())
+                });
+                self.status(reply, &result);
+                self.wcc_data(reply, caller, from_dir.ok().as_deref());
+                self.wcc_data(reply, caller, to_dir.ok().as_deref());
+            }
+            procedure::READDIR | procedure::READDIRPLUS => {
+                let path = self.path(args.opaque()?);
+                let (cookie, _verifier) = (args.u64()?, args.u64()?);
+                let (dir_count, max_count) = match procedure {
+                    procedure::READDIR => (u32::MAX, args.u32()?),
+                    _ => (args.u32()?, args.u32()?),
+                };
+                let plus = procedure == procedure::READDIRPLUS;
+                let result = path
+                    .clone()
+                    .and_then(|path| self.list(&path).map(|entries| (path, entries)));
+                let (path, entries) = match result {
+                    Ok(result) => result,
+                    Err(status) => {
+                        reply.u32(status);
+                        self.post_op_attr(reply, caller, path.ok().as_deref());
+                        return Ok(true);
+                    }
+                };
+
+                reply.u32(status::OK);
+                self.post_op_attr(reply, caller, Some(&path));
+                reply.u64(0);
+                // the entries that fit, leaving room for the end of the list
+                let (start, mut dir_len) = (reply.0.len(), 0);
+                let mut eof = true;
+                for (index, (name, entry)) in entries.iter().enumerate().skip(cookie as usize) {
+                    let name_len = name.len().next_multiple_of(4);
+                    let entry_len = 4 + 8 + 4 + name_len + 8;
+                    let plus_len = if plus {
+                        POST_OP_ATTR_SIZE + POST_OP_FH_SIZE
+                    } else {
+                        0
+                    };
+                    let len =
+                        reply.0.len() + entry_len + plus_len + 8 - start + POST_OP_ATTR_SIZE + 16;
+                    if len > max_count as usize || dir_len + entry_len > dir_count as usize {
+                        if reply.0.len() == start {
+                            reply.0.truncate(start - 8 - POST_OP_ATTR_SIZE - 4);
+                            reply.u32(status::TOOSMALL);
+                            self.post_op_attr(reply, caller, Some(&path));
+                            return Ok(true);
+                        }
+                        eof = false;
+                        break;
+                    }
+                    dir_len += entry_len;
+
+                    let id = self.handles.lock().id(entry);
+                    reply.bool(true).u64(id).str(name).u64(index as u64 + 1);
+                    if plus {
+                        self.post_op_attr(reply, caller, Some(entry));
+                        reply.bool(true).opaque(&self.handle(entry));
+                    }
+                }
+                reply.bool(false).bool(eof);
+            }
+            procedure::FSSTAT => {
+                let path = self.path(args.opaque()?);
+                let result = path.and_then(|path| self.metadata(&path).map(|_| path));
+                self.status(reply, &result);
+                self.post_op_attr(reply, caller, result.as_deref().ok());
+                if result.is_ok() {
+                    let stats = util::stats_or_empty(&self.fs);
+                    reply
+                        .u64(stats.total_space)
+                        .u64(stats.free_space)
+                        .u64(stats.available_space);
+                    // the numbers of files are unknown, and the statistics may change at any time
+                    reply.u64(0).u64(0).u64(0).u32(0);
+                }
+            }
+            procedure::FSINFO => {
+                let path = self.path(args.opaque()?);
+                let result = path.and_then(|path| self.metadata(&path).map(|_| path));
+                self.status(reply, &result);
+                self.post_op_attr(reply, caller, result.as_deref().ok());
+                if result.is_ok() {
+                    // the largest, preferred and multiple sizes of reads and writes, and the preferred listing size
+                    reply.u32(MAX_IO_SIZE).u32(MAX_IO_SIZE).u32(4096);
+                    reply.u32(MAX_IO_SIZE).u32(MAX_IO_SIZE).u32(4096);
+                    reply.u32(64 * 1024).u64(u64::MAX);
+                    // times are precise to the nanosecond, and every file has the same properties
+                    reply.u32(0).u32(1).u32(0x8);
+                }
+            }
+            procedure::PATHCONF => {
+                let path = self.path(args.opaque()?);
+                let result = path.and_then(|path| self.metadata(&path).map(|_| path));
+                self.status(reply, &result);
+                self.post_op_attr(reply, caller, result.as_deref().ok());
+                if result.is_ok() {
+                    // one link per file, names up to 255 bytes that aren't truncated, and owners that can't change
+                    reply.u32(1).u32(255).bool(true).bool(true);
+                    // names are case-sensitive and preserved
+                    reply.bool(false).bool(true);
+                }
+            }
+            procedure::COMMIT => {
+                let path = self.path(args.opaque()?);
+                let result = path.and_then(|path| self.metadata(&path).map(|_| path));
+                self.status(reply, &result);
+                self.wcc_data(reply, caller, result.as_deref().ok());
+                if result.is_ok() {
+                    reply.u64(self.verifier);
+                }
+            }
+            // links and special files aren't supported, which is reported along with missing attributes
+            procedure::READLINK => {
+                reply.u32(status::NOTSUPP).bool(false);
+            }
+            procedure::SYMLINK | procedure::MKNOD => {
+                reply.u32(status::NOTSUPP).bool(false).bool(false);
+            }
+            procedure::LINK => {
+                reply
+                    .u32(status::NOTSUPP)
+                    .bool(false)
+                    .bool(false)
+                    .bool(false);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Returns the handle of the entry at `path`.
+    fn handle(&self, path: &str) -> Vec<u8> {
+        let id = self.handles.lock().id(path);
+        [id.to_be_bytes(), self.verifier.to_be_bytes()].concat()
+    }
+
+    /// Returns the path of the entry with the file handle `handle`.
+    fn path(&self, handle: &[u8]) -> Result<String, u32> {
+        if handle.len() != HANDLE_SIZE {
+            return Err(status::BADHANDLE);
+        }
+        let (id, verifier) = handle.split_at(8);
+        // handles of other instances of the server are stale, since their numbers may be of other paths
+        if verifier != self.verifier.to_be_bytes() {
+            return Err(status::STALE);
+        }
+        let id = u64::from_be_bytes(id.try_into().unwrap());
+        let handles = self.handles.lock();
+        handles.paths.get(&id).cloned().ok_or(status::STALE)
+    }
+
+    /// Returns the path of the entry `name` in the directory at `dir`, which may be `.` or `..`.
+    fn lookup(&self, dir: &str, name: &str) -> Result<String, u32> {
+        if !self.metadata(dir)?.is_directory() {
+            return Err(status::NOTDIR);
+        }
+        match name {
+            "." => Ok(dir.to_owned()),
+            ".." => Ok(dir
+                .rsplit_once('/')
+                .map_or("", |(parent, _)| parent)
+                .to_owned()),
+            name => self.child(dir, name),
+        }
+    }
+
+    /// Returns the path of the entry `name` in the directory at `dir`.
+    fn child(&self, dir: &str, name: &str) -> Result<String, u32> {
+        match name {
+            "" | "." | ".." => Err(status::INVAL),
+            name if name.contains('/') => Err(status::INVAL),
+            name if name.len() > 255 => Err(status::NAMETOOLONG),
+            name if dir.is_empty() => Ok(name.to_owned()),
+            name => Ok(format!("{dir}/{name}")),
+        }
+    }
+
+    /// Returns the metadata of the entry at `path`, or the status of the error.
+    fn metadata(&self, path: &str) -> Result<Metadata, u32> {
+        util::metadata_or_root(&self.fs, path).map_err(|err| error_status(&err))
+    }
+
+    /// Returns the entries of the directory at `path` by name, including `.` and `..`, with their paths.
+    fn list(&self, path: &str) -> Result<Vec<(String, String)>, u32> {
+        if !self.metadata(path)?.is_directory() {
+            return Err(status::NOTDIR);
+        }
+        let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        let mut entries = vec![
+            (".".to_owned(), path.to_owned()),
+            ("..".to_owned(), parent.to_owned()),
+        ];
+        let mut names = Vec::new();
+        for entry in self.fs.read_dir(path).map_err(|err| error_status(&err))? {
+            let entry = entry.map_err(|err| error_status(&err))?;
+            if let Some(name) = entry.path.file_name().and_then(|name| name.to_str()) {
+                names.push(name.to_owned());
+            }
+        }
+        // the entries are sorted, so that cookies are positions in a stable order
+        names.sort();
+        for name in names {
+            let entry = self.child(path, &name)?;
+            entries.push((name, entry));
+        }
+        Ok(entries)
+    }
+
+    /// Encodes the status of `result`.
+    fn status<T>(&self, reply: &mut Encoder, result: &Result<T, u32>) {
+        reply.u32(match result {
+            Ok(_) => status::OK,
+            Err(status) => *status,
+        });
+    }
+
+    /// Encodes the attributes of the entry at `path`, whose metadata is `metadata`.
+    fn fattr(&self, reply: &mut Encoder, caller: Caller, path: &str, metadata: &Metadata) {
+        let (ty, default_mode, links) = match metadata.file_type {
+            FileType::Directory => (NF3DIR, 0o755, 2),
+            _ => (NF3REG, 0o644, 1),
+        };
+        let modified = metadata
+            .modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+
+        reply
+            .u32(ty)
+            .u32(metadata.mode.unwrap_or(default_mode) & 0o7777)
+            .u32(links)
+            .u32(caller.uid)
+            .u32(caller.gid);
+        // the size, the used space, and the device
+        reply
+            .u64(metadata.len)
+            .u64(metadata.len.next_multiple_of(4096))
+            .u32(0)
+            .u32(0);
+        reply.u64(FSID).u64(self.handles.lock().id(path));
+        // the access, modification and change times
+        for _ in 0..3 {
+            reply
+                .u32(modified.as_secs() as u32)
+                .u32(modified.subsec_nanos());
+        }
+    }
+
+    /// Encodes the attributes of the entry at `path`, if there is one and they're known.
+    fn post_op_attr(&self, reply: &mut Encoder, caller: Caller, path: Option<&str>) {
+        match path.map(|path| (path, self.metadata(path))) {
+            Some((path, Ok(metadata))) => {
+                reply.bool(true);
+                self.fattr(reply, caller, path, &metadata);
+            }
+            _ => {
+                reply.bool(false);
+            }
+        }
+    }
+
+    /// Encodes the attributes of the entry at `path` after it was changed, without those from before.
+    fn wcc_data(&self, reply: &mut Encoder, caller: Caller, path: Option<&str>) {
+        reply.bool(false);
+        self.post_op_attr(reply, caller, path);
+    }
+}
+
+/// The attributes set by `SETATTR`, `CREATE` and `MKDIR` that are honored.
+#[derive(Default)]
+struct Attributes {
+    mode: Option<u32>,
+    size: Option<u64>,
+}
+
+impl Attributes {
+    /// Decodes the attributes to set, skipping those that can't be.
+    fn decode(args: &mut Decoder) -> io::Result<Self> {
+        let mode = args.bool()?.then(|| args.u32()).transpose()?;
+        // the owner and group
+        for _ in 0..2 {
+            if args.bool()? {
+                args.u32()?;
+            }
+        }
+        let size = args.bool()?.then(|| args.u64()).transpose()?;
+        // the access and modification times, which are only sent if they're set to a time from the client
+        for _ in 0..2 {
+            if args.u32()? == 2 {
+                args.skip(8)?;
+            }
+        }
+        Ok(Self { mode, size })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::OpenOptions;
+    use crate::memory_fs::MemoryFS;
+    use crate::nfs::{
+        call_header, procedure, read_record, reply_results, serve_listener, status, write_record,
+        Decoder, Encoder, MOUNT_PROGRAM, MOUNT_VERSION, NFS_PROGRAM, NFS_VERSION,
+    };
+    use crate::nfs_fs::{AuthSys, NfsFS};
+    use crate::util::test::read_directory;
+    use crate::FileSystem;
+    use std::io::{ErrorKind, Seek, SeekFrom, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread;
+
+    /// Serves `fs` in the background. Returns the address of the server.
+    fn serve(fs: MemoryFS) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve_listener(fs, listener));
+        addr
+    }
+
+    #[test]
+    fn read_write() {
+        let fs = MemoryFS::default();
+        fs.create_dir("export").unwrap();
+        let fs = NfsFS::connect(serve(fs), "/export", AuthSys::new(1000, 1000)).unwrap();
+
+        fs.create_dir("dir").unwrap();
+        fs.create_dir("dir/a").unwrap();
+        write!(fs.create_file("dir/file").unwrap(), "hello").unwrap();
+        let mut file = fs
+            .open_file_options("dir/file", &OpenOptions::default().append(true))
+            .unwrap();
+        write!(file, " world").unwrap();
+        drop(file);
+
+        let mut file = fs.open_file("dir/file").unwrap();
+        file.seek(SeekFrom::Start(6)).unwrap();
+        assert_eq!(file.read_into_string().unwrap(), "world");
+        drop(file);
+
+        // truncating a file rewrites it
+        assert_eq!(
+            fs.create_file("dir/file")
+                .unwrap()
+                .metadata()
+                .unwrap()
+                .len(),
+            0
+        );
+        write!(fs.create_file("dir/file").unwrap(), "hello world").unwrap();
+
+        // large files are read and written in pieces
+        let large: Vec<_> = (0..3_000_000).map(|n| n as u8).collect();
+        fs.create_file("dir/large")
+            .unwrap()
+            .write_all(&large)
+            .unwrap();
+        assert_eq!(
+            fs.open_file("dir/large").unwrap().read_into_vec().unwrap(),
+            large
+        );
+
+        // large directories are listed in pages
+        for n in 0..300 {
+            fs.create_file(&format!("dir/a/{n:03}")).unwrap();
+        }
+        let dir = read_directory(&fs, "dir/a");
+        assert_eq!(dir.len(), 300);
+        assert!(dir.keys().all(|path| path.starts_with("dir/a/")));
+
+        let dir = read_directory(&fs, "dir");
+        itertools::assert_equal(dir.keys(), vec!["dir/a", "dir/file", "dir/large"]);
+        assert!(dir["dir/a"].is_directory());
+        assert_eq!(dir["dir/file"].len(), 11);
+        assert_eq!(dir["dir/file"].mode, Some(0o644));
+
+        fs.rename("dir/file", "dir/moved").unwrap();
+        assert_eq!(fs.metadata("dir/moved").unwrap().len(), 11);
+        assert_eq!(
+            fs.metadata("dir/file").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            fs.remove_dir("dir/a").err().unwrap().kind(),
+            ErrorKind::DirectoryNotEmpty
+        );
+        for n in 0..300 {
+            fs.remove_file(&format!("dir/a/{n:03}")).unwrap();
+        }
+        fs.remove_dir("dir/a").unwrap();
+        fs.remove_file("dir/moved").unwrap();
+        itertools::assert_equal(read_directory(&fs, "dir").keys(), vec!["dir/large"]);
+    }
+
+    /// Calls a procedure, returning the reply.
+    fn call(
+        stream: &mut TcpStream,
+        program: u32,
+        version: u32,
+        procedure: u32,
+        args: &Encoder,
+    ) -> Vec<u8> {
+        let credentials = AuthSys::new(1000, 1000).encode();
+        let mut call = call_header(7, program, version, procedure, &credentials);
+        call.0.extend_from_slice(args.bytes());
+        write_record(stream, call.bytes()).unwrap();
+        read_record(stream).unwrap()
+    }
+
+    #[test]
+    fn rpc() {
+        let mut stream = TcpStream::connect(serve(MemoryFS::default())).unwrap();
+
+        // mounting a missing directory fails
+        let mut mnt = Encoder::default();
+        mnt.str("/missing");
+        let reply = call(
+            &mut stream,
+            MOUNT_PROGRAM,
+            MOUNT_VERSION,
+            procedure::MOUNT_MNT,
+            &mnt,
+        );
+        assert_eq!(
+            reply_results(&reply, 7).unwrap().u32().unwrap(),
+            status::NOENT
+        );
+
+        // the root is exported
+        let reply = call(
+            &mut stream,
+            MOUNT_PROGRAM,
+            MOUNT_VERSION,
+            procedure::MOUNT_EXPORT,
+            &Encoder::default(),
+        );
+        let mut exports = reply_results(&reply, 7).unwrap();
+        assert!(exports.bool().unwrap());
+        assert_eq!(exports.str().unwrap(), "/");
+
+        // handles that weren't handed out are stale
+        let mut getattr = Encoder::default();
+        getattr.opaque(&[0; 16]);
+        let reply = call(
+            &mut stream,
+            NFS_PROGRAM,
+            NFS_VERSION,
+            procedure::GETATTR,
+            &getattr,
+        );
+        assert_eq!(
+            reply_results(&reply, 7).unwrap().u32().unwrap(),
+            status::STALE
+        );
+
+        // other versions, procedures and programs aren't served
+        let reply = call(
+            &mut stream,
+            NFS_PROGRAM,
+            4,
+            procedure::NULL,
+            &Encoder::default(),
+        );
+        assert_eq!(
+            reply_results(&reply, 7).err().unwrap().kind(),
+            ErrorKind::Unsupported
+        );
+        let reply = call(
+            &mut stream,
+            NFS_PROGRAM,
+            NFS_VERSION,
+            22,
+            &Encoder::default(),
+        );
+        assert_eq!(
+            reply_results(&reply, 7).err().unwrap().kind(),
+            ErrorKind::Unsupported
+        );
+        let reply = call(
+            &mut stream,
+            100_099,
+            1,
+            procedure::NULL,
+            &Encoder::default(),
+        );
+        let mut reply = Decoder::new(&reply);
+        let header = (0..6).map(|_| reply.u32().unwrap()).collect::<Vec<_>>();
+        assert_eq!(header, [7, 1, 0, 0, 0, 1]);
+
+        // malformed credentials are denied
+        let call = call_header(7, NFS_PROGRAM, NFS_VERSION, procedure::NULL, &[]);
+        write_record(&mut stream, call.bytes()).unwrap();
+        let reply = read_record(&mut stream).unwrap();
+        assert_eq!(
+            reply_results(&reply, 7).err().unwrap().kind(),
+            ErrorKind::PermissionDenied
+        );
+    }
+}
@@ -0,0 +1,1176 @@
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::nfs::{
+    call_header, procedure, read_record, reply_results, status, status_error, write_record,
+    Decoder, Encoder, FILE_SYNC, GUARDED, IPPROTO_TCP, MOUNT_PROGRAM, MOUNT_VERSION, NFS_PROGRAM,
+    NFS_VERSION, PORTMAP_PROGRAM, PORTMAP_VERSION, UNCHECKED,
+};
+use crate::tree::normalize_and_relativize;
+use crate::util::{
+    already_exists, component_iter, invalid_input, invalid_path, not_found, not_writable,
+};
+use crate::FileSystem;
+use parking_lot::Mutex;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+
+/// The most bytes a single read or write transfers, whatever the server allows.
+const MAX_IO_SIZE: u32 = 1024 * 1024;
+/// The mode directories are created with by `create_dir`.
+const DEFAULT_DIR_MODE: u32 = 0o755;
+/// The mode files are created with unless the open options have one.
+const DEFAULT_FILE_MODE: u32 = 0o644;
+
+/// The `AUTH_SYS` credentials calls are made with, which the server trusts to identify the caller.
+#[derive(Clone, Debug)]
+pub struct AuthSys {
+    /// The name of the calling machine.
+    pub machine_name: String,
+    pub uid: u32,
+    pub gid: u32,
+    /// The supplementary groups, of which at most 16 are sent.
+    pub gids: Vec<u32>,
+}
+
+impl AuthSys {
+    /// Creates the credentials of a user without supplementary groups.
+    ///
+    /// # Arguments
+    /// `uid`: The numeric user.  
+    /// `gid`: The numeric group.  
+    pub fn new(uid: u32, gid: u32) -> Self {
+        Self {
+            machine_name: String::from("localhost"),
+            uid,
+            gid,
+            gids: Vec::new(),
+        }
+    }
+
+    /// # Arguments
+    /// `machine_name`: The name of the calling machine.  
+    pub fn machine_name<S: Into<String>>(mut self, machine_name: S) -> Self {
+        self.machine_name = machine_name.into();
+        self
+    }
+
+    /// # Arguments
+    /// `gids`: The supplementary groups.  
+    pub fn gids(mut self, gids: Vec<u32>) -> Self {
+        self.gids = gids;
+        self
+    }
+
+    /// Returns the encoded body of the credentials.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let gids = &self.gids[..self.gids.len().min(16)];
+        let mut credentials = Encoder::default();
+        credentials
+            .u32(0)
+            .str(&self.machine_name)
+            .u32(self.uid)
+            .u32(self.gid)
+            .u32(gids.len() as u32);
+        for gid in gids {
+            credentials.u32(*gid);
+        }
+        credentials.bytes().to_vec()
+    }
+}
+
+/// A read-write filesystem on an export of an NFSv3 server over TCP, spoken to entirely in userspace, so nothing is
+/// mounted by the kernel.
+///
+/// The ports of the mount protocol and NFS are looked up with the server's portmapper. Since the client doesn't bind
+/// a privileged port, servers that only accept those, such as Linux by default, need the export to be `insecure`.
+///
+/// Only NFSv3 is spoken. NFSv4 isn't supported, so servers that only export over NFSv4 fail to connect with
+/// [`ErrorKind::Unsupported`].
+pub struct NfsFS {
+    connection: Arc<Mutex<Connection>>,
+    /// The file handle of the root of the export.
+    root: Vec<u8>,
+    /// The preferred size of writes, which is reported as the block size.
+    block_size: u32,
+}
+
+impl NfsFS {
+    /// Mounts an export of an NFS server.
+    ///
+    /// # Arguments
+    /// `portmapper`: The address of the server's portmapper, which is usually on port 111.  
+    /// `export`: The path of the export.  
+    /// `auth`: The credentials to call the server with.  
+    pub fn connect<A: ToSocketAddrs>(
+        portmapper: A,
+        export: &str,
+        auth: AuthSys,
+    ) -> crate::Result<Self> {
+        let portmapper = portmapper
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| invalid_input("The address of the portmapper didn't resolve"))?;
+        let credentials = auth.encode();
+
+        let mut portmap = Connection::open(portmapper, credentials.clone())?;
+        let mount_port = portmap.port(MOUNT_PROGRAM, MOUNT_VERSION)?;
+        let nfs_port = portmap
+            .port(NFS_PROGRAM, NFS_VERSION)
+            .map_err(|err| match err.kind() {
+                ErrorKind::Unsupported => io::Error::new(
+                    ErrorKind::Unsupported,
+                    "The server doesn't export over NFSv3",
+                ),
+                _ => err,
+            })?;
+        drop(portmap);
+
+        let mut mount = Connection::open(
+            SocketAddr::new(portmapper.ip(), mount_port),
+            credentials.clone(),
+        )?;
+        let mut mnt = Encoder::default();
+        mnt.str(export);
+        let reply = mount.call(MOUNT_PROGRAM, MOUNT_VERSION, procedure::MOUNT_MNT, &mnt)?;
+        let mut reply = Decoder::new(&reply);
+        // the status codes of the mount protocol are a subset of those of NFS
+        match reply.u32()? {
+            status::OK => {}
+            status => return Err(status_error(status)),
+        }
+        let root = reply.opaque()?.to_vec();
+        drop(mount);
+
+        let mut connection =
+            Connection::open(SocketAddr::new(portmapper.ip(), nfs_port), credentials)?;
+        let mut fsinfo = Encoder::default();
+        fsinfo.opaque(&root);
+        let reply = connection.nfs(procedure::FSINFO, &fsinfo)?;
+        let mut reply = Decoder::new(&reply);
+        reply.post_op_attr()?;
+        let (read_max, _read_preferred, _read_multiple) =
+            (reply.u32()?, reply.u32()?, reply.u32()?);
+        let (write_max, write_preferred) = (reply.u32()?, reply.u32()?);
+        connection.read_size = read_max.clamp(1, MAX_IO_SIZE);
+        connection.write_size = write_max.clamp(1, MAX_IO_SIZE);
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            root,
+            block_size: write_preferred.max(1),
+        })
+    }
+
+    /// Calls `f` with the file handle of the parent directory of `path` and the name of the entry in it.
+    ///
+    /// # Arguments
+    /// `path`: The virtual path of the entry.  
+    /// `f`: The function.  
+    fn with_parent<R>(
+        &self,
+        path: &str,
+        f: impl FnOnce(&mut Connection, &[u8], &str) -> crate::Result<R>,
+    ) -> crate::Result<R> {
+        let path = normalize_and_relativize(path);
+        let names: Vec<_> = component_iter(&path).collect();
+        let Some((name, parent)) = names.split_last() else {
+            return Err(invalid_path());
+        };
+
+        let mut connection = self.connection.lock();
+        let (parent, _) = connection.walk(&self.root, parent)?;
+        f(&mut connection, &parent, name)
+    }
+}
+
+impl FileSystem for NfsFS {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.create_dir_with(path, DEFAULT_DIR_MODE)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        let path = normalize_and_relativize(path);
+        let names: Vec<_> = component_iter(&path).collect();
+
+        let mut connection = self.connection.lock();
+        match connection.walk(&self.root, &names)? {
+            (_, Some(metadata)) => Ok(metadata),
+            (handle, None) => connection.getattr(&handle),
+        }
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        let normalized_path = normalize_and_relativize(path);
+        let names: Vec<_> = component_iter(&normalized_path).collect();
+        let Some((name, parent)) = names.split_last() else {
+            return Err(not_found());
+        };
+
+        let mut connection = self.connection.lock();
+        let (parent, _) = connection.walk(&self.root, parent)?;
+        let handle = match connection.lookup(&parent, name) {
+            Ok(_) if options.create_new => return Err(already_exists()),
+            Ok((handle, metadata)) => {
+                let metadata = match metadata {
+                    Some(metadata) => metadata,
+                    None => connection.getattr(&handle)?,
+                };
+                if metadata.is_directory() {
+                    return Err(invalid_input("path is a directory"));
+                }
+                if options.truncate && metadata.len() > 0 {
+                    connection.set_len(&handle, 0)?;
+                }
+                handle
+            }
+            Err(err)
+                if err.kind() == ErrorKind::NotFound && (options.create || options.create_new) =>
+            {
+                // exclusive creations fail on the server if the file appeared in the meantime
+                let mode = if options.create_new {
+                    GUARDED
+                } else {
+                    UNCHECKED
+                };
+                let mut create = Encoder::default();
+                create.opaque(&parent).str(name).u32(mode);
+                sattr(&mut create, options.mode.or(Some(DEFAULT_FILE_MODE)), None);
+                let reply = connection.nfs(procedure::CREATE, &create)?;
+                // servers may leave the handle out, in which case it's looked up
+                match Decoder::new(&reply).post_op_fh()? {
+                    Some(handle) => handle.to_vec(),
+                    None => connection.lookup(&parent, name)?.0,
+                }
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(Box::new(NfsFile {
+            connection: self.connection.clone(),
+            handle,
+            position: 0,
+            append: options.append,
+            writable: options.writable(),
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let directory = normalize_and_relativize(path);
+        let names: Vec<_> = component_iter(&directory).collect();
+
+        let mut connection = self.connection.lock();
+        let (handle, _) = connection.walk(&self.root, &names)?;
+        let entries = connection.read_dir(&handle)?;
+
+        Ok(Box::new(entries.into_iter().map(
+            move |(name, metadata)| {
+                Ok(DirEntry {
+                    path: directory.join(name),
+                    metadata,
+                })
+            },
+        )))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.with_parent(path, |connection, parent, name| {
+            connection.remove(procedure::RMDIR, parent, name)
+        })
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.with_parent(path, |connection, parent, name| {
+            connection.remove(procedure::REMOVE, parent, name)
+        })
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        self.with_parent(path, |connection, parent, name| {
+            let mut mkdir = Encoder::default();
+            mkdir.opaque(parent).str(name);
+            sattr(&mut mkdir, Some(mode), None);
+            connection.nfs(procedure::MKDIR, &mkdir)?;
+            Ok(())
+        })
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        self.with_parent(to, |connection, to_parent, to_name| {
+            let from = normalize_and_relativize(from);
+            let names: Vec<_> = component_iter(&from).collect();
+            let Some((from_name, from_parent)) = names.split_last() else {
+                return Err(invalid_path());
+            };
+
+            let (from_parent, _) = connection.walk(&self.root, from_parent)?;
+            let mut rename = Encoder::default();
+            rename
+                .opaque(&from_parent)
+                .str(from_name)
+                .opaque(to_parent)
+                .str(to_name);
+            connection.nfs(procedure::RENAME, &rename)?;
+            Ok(())
+        })
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        let mut fsstat = Encoder::default();
+        fsstat.opaque(&self.root);
+        let reply = self.connection.lock().nfs(procedure::FSSTAT, &fsstat)?;
+
+        let mut reply = Decoder::new(&reply);
+        reply.post_op_attr()?;
+        Ok(FileSystemStats {
+            total_space: reply.u64()?,
+            free_space: reply.u64()?,
+            available_space: reply.u64()?,
+            block_size: self.block_size as u64,
+        })
+    }
+}
+
+/// Encodes the attributes to set on a file.
+///
+/// # Arguments
+/// `encoder`: The encoder of the arguments.  
+/// `mode`: The mode to set, if any.  
+/// `size`: The size to set, if any.  
+fn sattr(encoder: &mut Encoder, mode: Option<u32>, size: Option<u64>) {
+    encoder.bool(mode.is_some());
+    if let Some(mode) = mode {
+        encoder.u32(mode);
+    }
+    // the user and group aren't set
+    encoder.bool(false).bool(false);
+    encoder.bool(size.is_some());
+    if let Some(size) = size {
+        encoder.u64(size);
+    }
+    // the access and modification times aren't changed
+    encoder.u32(0).u32(0);
+}
+
+/// A connection to an RPC server.
+struct Connection {
+    stream: TcpStream,
+    next_xid: u32,
+    /// The encoded body of the credentials of every call.
+    credentials: Vec<u8>,
+    /// The most bytes a single read transfers.
+    read_size: u32,
+    /// The most bytes a single write transfers.
+    write_size: u32,
+}
+
+impl Connection {
+    /// Connects to an RPC server.
+    ///
+    /// # Arguments
+    /// `addr`: The address of the server.  
+    /// `credentials`: The encoded body of the credentials of every call.  
+    fn open(addr: SocketAddr, credentials: Vec<u8>) -> crate::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            next_xid: 1,
+            credentials,
+            read_size: MAX_IO_SIZE,
+            write_size: MAX_IO_SIZE,
+        })
+    }
+
+    /// Calls a procedure and waits for its reply. Returns the results.
+    ///
+    /// # Arguments
+    /// `program`: The program.  
+    /// `version`: The version of the program.  
+    /// `procedure`: The procedure.  
+    /// `args`: The arguments.  
+    fn call(
+        &mut self,
+        program: u32,
+        version: u32,
+        procedure: u32,
+        args: &Encoder,
+    ) -> crate::Result<Vec<u8>> {
+        let xid = self.next_xid;
+        self.next_xid = self.next_xid.wrapping_add(1);
+
+        let call = call_header(xid, program, version, procedure, &self.credentials);
+        write_record(&mut self.stream, &[call.bytes(), args.bytes()].concat())?;
+        let reply = read_record(&mut self.stream)?;
+        Ok(reply_results(&reply, xid)?.rest().to_vec())
+    }
+
+    /// Calls an NFS procedure, failing unless it succeeded. Returns the results after the status.
+    ///
+    /// # Arguments
+    /// `procedure`: The procedure.  
+    /// `args`: The arguments.  
+    fn nfs(&mut self, procedure: u32, args: &Encoder) -> crate::Result<Vec<u8>> {
+        let mut results = self.call(NFS_PROGRAM, NFS_VERSION, procedure, args)?;
+        match Decoder::new(&results).u32()? {
+            status::OK => Ok(results.split_off(4)),
+            status => Err(status_error(status)),
+        }
+    }
+
+    /// Looks up the TCP port of a program with the portmapper.
+    ///
+    /// # Arguments
+    /// `program`: The program.  
+    /// `version`: The version of the program.  
+    fn port(&mut self, program: u32, version: u32) -> crate::Result<u16> {
+        let mut getport = Encoder::default();
+        getport.u32(program).u32(version).u32(IPPROTO_TCP).u32(0);
+        let reply = self.call(
+            PORTMAP_PROGRAM,
+            PORTMAP_VERSION,
+            procedure::PMAP_GETPORT,
+            &getport,
+        )?;
+
+        match Decoder::new(&reply).u32()? {
+            0 => Err(io::Error::new(
+                ErrorKind::Unsupported,
+                format!("RPC program {program} version {version} isn't registered over TCP"),
+            )),
+            port => u16::try_from(port).map_err(|_| invalid_input("The port is out of range")),
+        }
+    }
+
+    /// Looks up the entry `name` in the directory `parent`. Returns its file handle and metadata, which servers may
+    /// leave out.
+    ///
+    /// # Arguments
+    /// `parent`: The file handle of the directory.  
+    /// `name`: The name of the entry.  
+    fn lookup(&mut self, parent: &[u8], name: &str) -> crate::Result<(Vec<u8>, Option<Metadata>)> {
+        let mut lookup = Encoder::default();
+        lookup.opaque(parent).str(name);
+        let reply = self.nfs(procedure::LOOKUP, &lookup)?;
+
+        let mut reply = Decoder::new(&reply);
+        let handle = reply.opaque()?.to_vec();
+        Ok((handle, reply.post_op_attr()?))
+    }
+
+    /// Walks from the directory `from` through `names`. Returns the file handle of the entry and its metadata, which
+    /// is unknown if there are no names.
+    ///
+    /// # Arguments
+    /// `from`: The file handle of the directory to walk from.  
+    /// `names`: The names of the entries to walk through.  
+    fn walk(&mut self, from: &[u8], names: &[&str]) -> crate::Result<(Vec<u8>, Option<Metadata>)> {
+        let mut current = (from.to_vec(), None);
+        for name in names {
+            current = self.lookup(&current.0, name)?;
+        }
+        Ok(current)
+    }
+
+    /// Returns the metadata of the entry `handle` refers to.
+    ///
+    /// # Arguments
+    /// `handle`: The file handle.  
+    fn getattr(&mut self, handle: &[u8]) -> crate::Result<Metadata> {
+        let mut getattr = Encoder::default();
+        getattr.opaque(handle);
+        let reply = self.nfs(procedure::GETATTR, &getattr)?;
+        Decoder::new(&reply).fattr()
+    }
+
+    /// Truncates or extends the file `handle` refers to.
+    ///
+    /// # Arguments
+    /// `handle`: The file handle.  
+    /// `len`: The new length of the file.  
+    fn set_len(&mut self, handle: &[u8], len: u64) -> crate::Result<()> {
+        let mut setattr = Encoder::default();
+        setattr.opaque(handle);
+        sattr(&mut setattr, None, Some(len));
+        // the change isn't guarded by the time the file last changed
+        setattr.bool(false);
+        self.nfs(procedure::SETATTR, &setattr)?;
+        Ok(())
+    }
+
+    /// Lists the directory `handle` refers to, with the metadata of every entry.
+    ///
+    /// # Arguments
+    /// `handle`: The file handle of the directory.  
+    fn read_dir(&mut self, handle: &[u8]) -> crate::Result<Vec<(String, Metadata)>> {
+        let mut entries = Vec::new();
+        let mut unknown = Vec::new();
+        let (mut cookie, mut verifier) = (0, 0);
+        loop {
+            let mut readdirplus = Encoder::default();
+            readdirplus
+                .opaque(handle)
+                .u64(cookie)
+                .u64(verifier)
+                .u32(self.read_size)
+                .u32(self.read_size);
+            let reply = self.nfs(procedure::READDIRPLUS, &readdirplus)?;
+
+            let mut reply = Decoder::new(&reply);
+            reply.post_op_attr()?;
+            verifier = reply.u64()?;
+            while reply.bool()? {
+                let _file_id = reply.u64()?;
+                let name = reply.str()?.to_owned();
+                cookie = reply.u64()?;
+                let metadata = reply.post_op_attr()?;
+                reply.post_op_fh()?;
+                match (name.as_str(), metadata) {
+                    ("." | "..", _) => {}
+                    (_, Some(metadata)) => entries.push((name, metadata)),
+                    (_, None) => unknown.push(name),
+                }
+            }
+            if reply.bool()? {
+                break;
+            }
+        }
+
+        // servers may leave the attributes of entries out, so look those up
+        for name in unknown {
+            let (entry, metadata) = self.lookup(handle, &name)?;
+            let metadata = match metadata {
+                Some(metadata) => metadata,
+                None => self.getattr(&entry)?,
+            };
+            entries.push((name, metadata));
+        }
+        Ok(entries)
+    }
+
+    /// Removes the entry `name` from the directory `parent`.
+    ///
+    /// # Arguments
+    /// `procedure`: `RMDIR` to remove a directory, or `REMOVE` to remove a file.  
+    /// `parent`: The file handle of the directory.  
+    /// `name`: The name of the entry.  
+    fn remove(&mut self, procedure: u32, parent: &[u8], name: &str) -> crate::Result<()> {
+        let mut remove = Encoder::default();
+        remove.opaque(parent).str(name);
+        self.nfs(procedure, &remove)?;
+        Ok(())
+    }
+}
+
+/// A file on the server, which is read and written in place. NFS is stateless, so nothing is held open.
+struct NfsFile {
+    connection: Arc<Mutex<Connection>>,
+    handle: Vec<u8>,
+    position: u64,
+    append: bool,
+    writable: bool,
+}
+
+impl File for NfsFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        self.connection.lock().getattr(&self.handle)
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        if !self.writable {
+            return Err(not_writable());
+        }
+        self.connection.lock().set_len(&self.handle, len)
+    }
+}
+
+impl Read for NfsFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut connection = self.connection.lock();
+        let count = buf.len().min(connection.read_size as usize);
+        let mut read = Encoder::default();
+        read.opaque(&self.handle)
+            .u64(self.position)
+            .u32(count as u32);
+        let reply = connection.nfs(procedure::READ, &read)?;
+
+        let mut reply = Decoder::new(&reply);
+        reply.post_op_attr()?;
+        let (_count, _eof) = (reply.u32()?, reply.bool()?);
+        let data = reply.opaque()?;
+        let read_len = data.len().min(count);
+        buf[..read_len].copy_from_slice(&data[..read_len]);
+        self.position += read_len as u64;
+        Ok(read_len)
+    }
+}
+
+impl Seek for NfsFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.metadata()?.len().checked_add_signed(offset),
+        };
+        self.position =
+            position.ok_or_else(|| invalid_input("seek to a negative or overflowing position"))?;
+        Ok(self.position)
+    }
+}
+
+impl Write for NfsFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.writable {
+            return Err(not_writable());
+        }
+        if self.append {
+            self.position = self.metadata()?.len();
+        }
+
+        let mut connection = self.connection.lock();
+        let count = buf.len().min(connection.write_size as usize);
+        let mut write = Encoder::default();
+        write
+            .opaque(&self.handle)
+            .u64(self.position)
+            .u32(count as u32)
+            .u32(FILE_SYNC)
+            .opaque(&buf[..count]);
+        let reply = connection.nfs(procedure::WRITE, &write)?;
+
+        let mut reply = Decoder::new(&reply);
+        reply.wcc_data()?;
+        let written = (reply.u32()? as usize).min(count);
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // every write is committed before it's acknowledged
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::{Metadata, OpenOptions};
+    use crate::memory_fs::MemoryFS;
+    use crate::nfs::{
+        procedure, read_record, status, status_error, write_record, Decoder, Encoder, AUTH_SYS,
+        FILE_SYNC, MOUNT_PROGRAM, MOUNT_VERSION, NF3DIR, NF3REG, NFS_PROGRAM, NFS_VERSION,
+        PORTMAP_PROGRAM,
+    };
+    use crate::nfs_fs::{AuthSys, NfsFS};
+    use crate::util::test::{check_open_options, check_set_len, read_directory};
+    use crate::{util, FileSystem};
+    use std::hash::{DefaultHasher, Hash, Hasher};
+    use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// The user the test server serves.
+    const UID: u32 = 1000;
+    /// The verifier of the directory listings of the test server.
+    const COOKIE_VERIFIER: u64 = 0x5eed;
+    /// The prefix of names whose lookups fail with the status that follows it.
+    const STATUS_PREFIX: &str = "status-";
+
+    /// Serves `fs` as the export `/export`, with the portmapper, the mount protocol and NFS on the same port.
+    /// Returns the address of the server.
+    fn serve(fs: MemoryFS) -> SocketAddr {
+        serve_versions(fs, NFS_VERSION)
+    }
+
+    /// Serves `fs` like [`serve`], registering NFS with the portmapper as `nfs_version`.
+    fn serve_versions(fs: MemoryFS, nfs_version: u32) -> SocketAddr {
+        let fs = Arc::new(fs);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let fs = fs.clone();
+                thread::spawn(move || session(&fs, addr.port(), nfs_version, stream.unwrap()));
+            }
+        });
+
+        addr
+    }
+
+    /// Serves a single connection until it's closed.
+    fn session(fs: &MemoryFS, port: u16, nfs_version: u32, mut stream: TcpStream) {
+        while let Ok(call) = read_record(&mut stream) {
+            let mut call = Decoder::new(&call);
+            let xid = call.u32().unwrap();
+            let (_msg_type, _rpc_version) = (call.u32().unwrap(), call.u32().unwrap());
+            let (program, _version, procedure) = (
+                call.u32().unwrap(),
+                call.u32().unwrap(),
+                call.u32().unwrap(),
+            );
+            assert_eq!(call.u32().unwrap(), AUTH_SYS);
+            let mut credentials = Decoder::new(call.opaque().unwrap());
+            let (_stamp, _machine_name) = (credentials.u32().unwrap(), credentials.str().unwrap());
+            let uid = credentials.u32().unwrap();
+            let (_verifier_flavor, _verifier) = (call.u32().unwrap(), call.opaque().unwrap());
+
+            let mut reply = Encoder::default();
+            reply.u32(xid).u32(1).u32(0).u32(0).u32(0).u32(0);
+            match program {
+                PORTMAP_PROGRAM => {
+                    let (program, version) = (call.u32().unwrap(), call.u32().unwrap());
+                    let served = [(MOUNT_PROGRAM, MOUNT_VERSION), (NFS_PROGRAM, nfs_version)]
+                        .contains(&(program, version));
+                    reply.u32(if served { port as u32 } else { 0 });
+                }
+                MOUNT_PROGRAM => match call.str().unwrap() {
+                    "/export" => {
+                        reply
+                            .u32(status::OK)
+                            .opaque(&handle(""))
+                            .u32(1)
+                            .u32(AUTH_SYS);
+                    }
+                    _ => {
+                        reply.u32(status::NOENT);
+                    }
+                },
+                _ if uid != UID => {
+                    reply.u32(status::ACCES);
+                }
+                _ => {
+                    if let Err(status) = handle_nfs(fs, procedure, &mut call, &mut reply) {
+                        reply = Encoder::default();
+                        reply
+                            .u32(xid)
+                            .u32(1)
+                            .u32(0)
+                            .u32(0)
+                            .u32(0)
+                            .u32(0)
+                            .u32(status);
+                    }
+                }
+            }
+            if write_record(&mut stream, reply.bytes()).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Returns the file handle of `path`.
+    fn handle(path: &str) -> Vec<u8> {
+        format!("fh:{path}").into_bytes()
+    }
+
+    /// Decodes a file handle. Returns its path.
+    fn path(call: &mut Decoder) -> String {
+        let handle = std::str::from_utf8(call.opaque().unwrap()).unwrap();
+        handle.strip_prefix("fh:").unwrap().to_owned()
+    }
+
+    /// Decodes the handle of a directory and a name. Returns the path of the entry.
+    fn entry_path(call: &mut Decoder) -> String {
+        let parent = path(call);
+        match (parent.as_str(), call.str().unwrap()) {
+            ("", name) => name.to_owned(),
+            (parent, name) => format!("{parent}/{name}"),
+        }
+    }
+
+    /// Returns the metadata of the entry at `path`, since the memory filesystem has none for its root.
+    fn metadata(fs: &MemoryFS, path: &str) -> crate::Result<Metadata> {
+        match path {
+            "" => Ok(Metadata::directory()),
+            path => fs.metadata(path),
+        }
+    }
+
+    /// Encodes the attributes of the entry at `path`.
+    fn fattr(reply: &mut Encoder, path: &str, metadata: &Metadata) {
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        let (ty, mode) = if metadata.is_directory() {
+            (NF3DIR, 0o755)
+        } else {
+            (NF3REG, 0o644)
+        };
+        reply.u32(ty).u32(mode).u32(1).u32(UID).u32(UID);
+        reply.u64(metadata.len()).u64(metadata.len()).u32(0).u32(0);
+        reply.u64(0).u64(hasher.finish());
+        // the access, modification and change times
+        reply.u32(0).u32(0).u32(1_000).u32(5).u32(0).u32(0);
+    }
+
+    /// Converts an error of `fs` to a status.
+    fn error_status(err: std::io::Error) -> u32 {
+        match err.kind() {
+            ErrorKind::NotFound => status::NOENT,
+            ErrorKind::AlreadyExists => status::EXIST,
+            ErrorKind::DirectoryNotEmpty => status::NOTEMPTY,
+            _ => status::INVAL,
+        }
+    }
+
+    /// Handles an NFS call, encoding its results.
+    fn handle_nfs(
+        fs: &MemoryFS,
+        procedure: u32,
+        call: &mut Decoder,
+        reply: &mut Encoder,
+    ) -> Result<(), u32> {
+        // no attributes of directories before or after a change
+        let wcc_data = |reply: &mut Encoder| {
+            reply.bool(false).bool(false);
+        };
+
+        match procedure {
+            procedure::GETATTR => {
+                let path = path(call);
+                let metadata = metadata(fs, &path).map_err(error_status)?;
+                reply.u32(status::OK);
+                fattr(reply, &path, &metadata);
+            }
+            procedure::SETATTR => {
+                let path = path(call);
+                // only the size is ever set, to resize files
+                let (set_mode, _set_uid, _set_gid, set_size) = (
+                    call.bool().unwrap(),
+                    call.bool().unwrap(),
+                    call.bool().unwrap(),
+                    call.bool().unwrap(),
+                );
+                assert!(!set_mode && set_size);
+                util::set_len(fs, &path, call.u64().unwrap()).map_err(error_status)?;
+                reply.u32(status::OK);
+                wcc_data(reply);
+            }
+            procedure::LOOKUP => {
+                let path = entry_path(call);
+                if let Some(status) = path.strip_prefix(STATUS_PREFIX) {
+                    return Err(status.parse().unwrap());
+                }
+                let metadata = metadata(fs, &path).map_err(error_status)?;
+                reply.u32(status::OK).opaque(&handle(&path)).bool(true);
+                fattr(reply, &path, &metadata);
+                reply.bool(false);
+            }
+            procedure::READ => {
+                let path = path(call);
+                let (offset, count) = (call.u64().unwrap(), call.u32().unwrap());
+                let mut file = fs.open_file(&path).map_err(error_status)?;
+                file.seek(SeekFrom::Start(offset)).unwrap();
+                let mut data = Vec::new();
+                file.take(count as u64).read_to_end(&mut data).unwrap();
+                reply.u32(status::OK).bool(false).u32(data.len() as u32);
+                reply.bool(data.len() < count as usize).opaque(&data);
+            }
+            procedure::WRITE => {
+                let path = path(call);
+                let (offset, _count, stable) = (
+                    call.u64().unwrap(),
+                    call.u32().unwrap(),
+                    call.u32().unwrap(),
+                );
+                assert_eq!(stable, FILE_SYNC);
+                let data = call.opaque().unwrap();
+                let options = OpenOptions {
+                    write: true,
+                    ..OpenOptions::default()
+                };
+                let mut file = fs
+                    .open_file_options(&path, &options)
+                    .map_err(error_status)?;
+                file.seek(SeekFrom::Start(offset)).unwrap();
+                file.write_all(data).unwrap();
+                reply.u32(status::OK);
+                wcc_data(reply);
+                reply.u32(data.len() as u32).u32(FILE_SYNC).u64(0);
+            }
+            procedure::CREATE | procedure::MKDIR => {
+                let path = entry_path(call);
+                if procedure == procedure::CREATE {
+                    fs.create_file(&path).map_err(error_status)?;
+                } else {
+                    fs.create_dir(&path).map_err(error_status)?;
+                }
+                reply.u32(status::OK).bool(true).opaque(&handle(&path));
+                reply.bool(false);
+                wcc_data(reply);
+            }
+            procedure::REMOVE | procedure::RMDIR => {
+                let path = entry_path(call);
+                if procedure == procedure::REMOVE {
+                    fs.remove_file(&path).map_err(error_status)?;
+                } else if !read_directory(fs, &path).is_empty() {
+                    return Err(status::NOTEMPTY);
+                } else {
+                    fs.remove_dir(&path).map_err(error_status)?;
+                }
+                reply.u32(status::OK);
+                wcc_data(reply);
+            }
+            procedure::RENAME => {
+                let (from, to) = (entry_path(call), entry_path(call));
+                fs.rename(&from, &to).map_err(error_status)?;
+                reply.u32(status::OK);
+                wcc_data(reply);
+                wcc_data(reply);
+            }
+            procedure::READDIRPLUS => {
+                let path = path(call);
+                let (cookie, verifier) = (call.u64().unwrap() as usize, call.u64().unwrap());
+                // the first page has no verifier, and later pages send back the one it returned
+                if verifier != if cookie == 0 { 0 } else { COOKIE_VERIFIER } {
+                    return Err(status::INVAL);
+                }
+                let entries: Vec<_> = read_directory(fs, &path).into_iter().collect();
+                reply.u32(status::OK).bool(false).u64(COOKIE_VERIFIER);
+
+                // two entries at a time, and only the attributes of every other entry
+                for (index, (entry, metadata)) in entries.iter().enumerate().skip(cookie).take(2) {
+                    let name = entry.rsplit('/').next().unwrap();
+                    reply
+                        .bool(true)
+                        .u64(index as u64)
+                        .str(name)
+                        .u64(index as u64 + 1);
+                    reply.bool(index % 2 == 0);
+                    if index % 2 == 0 {
+                        fattr(reply, entry, metadata);
+                    }
+                    reply.bool(false);
+                }
+                reply.bool(false).bool(cookie + 2 >= entries.len());
+            }
+            procedure::FSSTAT => {
+                reply.u32(status::OK).bool(false);
+                reply.u64(1_000_000).u64(400_000).u64(300_000);
+                reply.u64(100).u64(40).u64(30).u32(0);
+            }
+            procedure::FSINFO => {
+                // small reads and writes, so that they're split
+                reply.u32(status::OK).bool(false);
+                reply
+                    .u32(4096)
+                    .u32(4096)
+                    .u32(512)
+                    .u32(4096)
+                    .u32(4096)
+                    .u32(512);
+                reply.u32(4096).u64(u64::MAX).u32(0).u32(1).u32(0);
+            }
+            _ => return Err(status::NOTSUPP),
+        }
+
+        Ok(())
+    }
+
+    fn connect(auth: AuthSys) -> crate::Result<NfsFS> {
+        NfsFS::connect(serve(MemoryFS::default()), "/export", auth)
+    }
+
+    #[test]
+    fn read_write() {
+        let fs = connect(AuthSys::new(UID, UID).gids(vec![UID, 10])).unwrap();
+        fs.create_dir("dir").unwrap();
+        fs.create_dir("dir/a").unwrap();
+        write!(fs.create_file("dir/file").unwrap(), "hello").unwrap();
+
+        let mut file = fs
+            .open_file_options("dir/file", &OpenOptions::default().append(true))
+            .unwrap();
+        write!(file, " world").unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 11);
+        drop(file);
+
+        let mut file = fs.open_file("dir/file").unwrap();
+        file.seek(SeekFrom::Start(6)).unwrap();
+        assert_eq!(file.read_into_string().unwrap(), "world");
+        assert_eq!(
+            file.write(b"read-only").err().unwrap().kind(),
+            ErrorKind::PermissionDenied
+        );
+        drop(file);
+
+        // reads and writes larger than the server allows are split
+        let large: Vec<_> = (0..10_000).map(|n| n as u8).collect();
+        fs.create_file("dir/large")
+            .unwrap()
+            .write_all(&large)
+            .unwrap();
+        assert_eq!(
+            fs.open_file("dir/large").unwrap().read_into_vec().unwrap(),
+            large
+        );
+        assert_eq!(
+            fs.create_file("dir/large")
+                .unwrap()
+                .metadata()
+                .unwrap()
+                .len(),
+            0
+        );
+
+        let dir = read_directory(&fs, "dir");
+        itertools::assert_equal(dir.keys(), vec!["dir/a", "dir/file", "dir/large"]);
+        assert!(dir["dir/a"].is_directory());
+        assert_eq!(dir["dir/file"].len(), 11);
+        assert_eq!(dir["dir/file"].mode, Some(0o644));
+        assert!(dir["dir/file"].modified.is_some());
+
+        fs.rename("dir/file", "dir/a/moved").unwrap();
+        assert_eq!(fs.metadata("dir/a/moved").unwrap().len(), 11);
+        assert_eq!(
+            fs.open_file("dir/file").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            fs.open_file("dir/a").err().unwrap().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            fs.remove_dir("dir/a").err().unwrap().kind(),
+            ErrorKind::DirectoryNotEmpty
+        );
+        fs.remove_file("dir/a/moved").unwrap();
+        fs.remove_dir("dir/a").unwrap();
+        itertools::assert_equal(read_directory(&fs, "dir").keys(), vec!["dir/large"]);
+
+        let stats = fs.stats().unwrap();
+        assert_eq!(stats.total_space, 1_000_000);
+        assert_eq!(stats.available_space, 300_000);
+        assert_eq!(stats.block_size, 4096);
+    }
+
+    #[test]
+    fn mount_errors() {
+        assert_eq!(
+            NfsFS::connect(
+                serve(MemoryFS::default()),
+                "/missing",
+                AuthSys::new(UID, UID)
+            )
+            .err()
+            .unwrap()
+            .kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            connect(AuthSys::new(0, 0)).err().unwrap().kind(),
+            ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn read_dir_paging() {
+        // the test server lists two entries a page, with the attributes of every other entry
+        let fs = connect(AuthSys::new(UID, UID)).unwrap();
+        fs.create_dir("dir").unwrap();
+        for index in 0..7 {
+            if index % 3 == 0 {
+                fs.create_dir(&format!("dir/{index}")).unwrap();
+            } else {
+                write!(
+                    fs.create_file(&format!("dir/{index}")).unwrap(),
+                    "{index:index$}"
+                )
+                .unwrap();
+            }
+        }
+
+        let dir = read_directory(&fs, "dir");
+        itertools::assert_equal(
+            dir.keys(),
+            (0..7)
+                .map(|index| format!("dir/{index}"))
+                .collect::<Vec<_>>()
+                .iter(),
+        );
+        for (index, metadata) in dir.values().enumerate() {
+            assert_eq!(metadata.is_directory(), index % 3 == 0);
+            if !metadata.is_directory() {
+                assert_eq!(metadata.len(), index as u64);
+            }
+        }
+
+        // a single page, and an empty directory
+        fs.create_dir("dir/0/sub").unwrap();
+        itertools::assert_equal(read_directory(&fs, "dir/0").keys(), vec!["dir/0/sub"]);
+        assert!(read_directory(&fs, "dir/0/sub").is_empty());
+        assert_eq!(
+            fs.read_dir("dir/missing").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn status_errors() {
+        let fs = connect(AuthSys::new(UID, UID)).unwrap();
+        for (status, kind) in [
+            (status::PERM, ErrorKind::PermissionDenied),
+            (status::NOENT, ErrorKind::NotFound),
+            (status::IO, ErrorKind::Other),
+            (status::ACCES, ErrorKind::PermissionDenied),
+            (status::EXIST, ErrorKind::AlreadyExists),
+            (status::XDEV, ErrorKind::CrossesDevices),
+            (status::NOTDIR, ErrorKind::NotADirectory),
+            (status::ISDIR, ErrorKind::IsADirectory),
+            (status::INVAL, ErrorKind::InvalidInput),
+            (status::NOSPC, ErrorKind::StorageFull),
+            (status::ROFS, ErrorKind::ReadOnlyFilesystem),
+            (status::NAMETOOLONG, ErrorKind::InvalidInput),
+            (status::NOTEMPTY, ErrorKind::DirectoryNotEmpty),
+            (status::DQUOT, ErrorKind::QuotaExceeded),
+            (status::STALE, ErrorKind::NotFound),
+            (status::BADHANDLE, ErrorKind::Other),
+            (status::NOTSUPP, ErrorKind::Unsupported),
+            (status::TOOSMALL, ErrorKind::Other),
+        ] {
+            assert_eq!(status_error(status).kind(), kind, "{status}");
+            // the status of a failed call surfaces as the same error
+            let path = format!("{STATUS_PREFIX}{status}");
+            assert_eq!(fs.metadata(&path).err().unwrap().kind(), kind, "{status}");
+        }
+
+        assert_eq!(
+            fs.create_dir("").err().unwrap().kind(),
+            ErrorKind::InvalidInput
+        );
+        fs.create_dir("dir").unwrap();
+        assert_eq!(
+            fs.create_dir("dir").err().unwrap().kind(),
+            ErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            fs.remove_file("missing").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn nfsv4_only() {
+        let server = serve_versions(MemoryFS::default(), 4);
+        let err = NfsFS::connect(server, "/export", AuthSys::new(UID, UID))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn open_options() {
+        let fs = connect(AuthSys::new(UID, UID)).unwrap();
+        check_open_options(&fs, "options");
+    }
+
+    #[test]
+    fn set_len() {
+        let fs = connect(AuthSys::new(UID, UID)).unwrap();
+        check_set_len(&fs, "set_len");
+    }
+}
@@ -0,0 +1,320 @@
+mod server;
+
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+
+#[cfg(unix)]
+pub use server::serve_unix;
+pub use server::{serve, serve_connection};
+
+/// The protocol version spoken.
+pub(crate) const VERSION: &str = "9P2000.L";
+/// The tag of `Tversion`, which is sent before any other message.
+pub(crate) const NOTAG: u16 = u16::MAX;
+/// The fid of a missing fid, such as the authentication fid when there's no authentication.
+pub(crate) const NOFID: u32 = u32::MAX;
+/// The most names walked by a single `Twalk`.
+pub(crate) const MAX_WALK: usize = 16;
+/// The size of the header of `Rread` and `Twrite`, which is subtracted from the message size for their payload.
+pub(crate) const IO_HEADER_SIZE: u32 = 4 + 1 + 2 + 4 + 8 + 4;
+
+/// The type of a message.
+pub(crate) mod message {
+    pub(crate) const RLERROR: u8 = 7;
+    pub(crate) const TSTATFS: u8 = 8;
+    pub(crate) const TLOPEN: u8 = 12;
+    pub(crate) const TLCREATE: u8 = 14;
+    pub(crate) const TSYMLINK: u8 = 16;
+    pub(crate) const TMKNOD: u8 = 18;
+    pub(crate) const TRENAME: u8 = 20;
+    pub(crate) const TREADLINK: u8 = 22;
+    pub(crate) const TGETATTR: u8 = 24;
+    pub(crate) const TSETATTR: u8 = 26;
+    pub(crate) const TXATTRWALK: u8 = 30;
+    pub(crate) const TXATTRCREATE: u8 = 32;
+    pub(crate) const TREADDIR: u8 = 40;
+    pub(crate) const TFSYNC: u8 = 50;
+    pub(crate) const TLOCK: u8 = 52;
+    pub(crate) const TGETLOCK: u8 = 54;
+    pub(crate) const TLINK: u8 = 70;
+    pub(crate) const TMKDIR: u8 = 72;
+    pub(crate) const TRENAMEAT: u8 = 74;
+    pub(crate) const TUNLINKAT: u8 = 76;
+    pub(crate) const TVERSION: u8 = 100;
+    pub(crate) const TAUTH: u8 = 102;
+    pub(crate) const TATTACH: u8 = 104;
+    pub(crate) const TFLUSH: u8 = 108;
+    pub(crate) const TWALK: u8 = 110;
+    pub(crate) const TREAD: u8 = 116;
+    pub(crate) const TWRITE: u8 = 118;
+    pub(crate) const TCLUNK: u8 = 120;
+    pub(crate) const TREMOVE: u8 = 122;
+}
+
+/// The Linux open flags of `Tlopen` and `Tlcreate`.
+pub(crate) mod flags {
+    pub(crate) const O_RDONLY: u32 = 0;
+    pub(crate) const O_WRONLY: u32 = 0o1;
+    pub(crate) const O_RDWR: u32 = 0o2;
+    pub(crate) const O_ACCMODE: u32 = 0o3;
+    pub(crate) const O_CREAT: u32 = 0o100;
+    pub(crate) const O_EXCL: u32 = 0o200;
+    pub(crate) const O_TRUNC: u32 = 0o1000;
+    pub(crate) const O_APPEND: u32 = 0o2000;
+    pub(crate) const O_DIRECTORY: u32 = 0o200000;
+    /// The flag of `Tunlinkat` that removes a directory.
+    pub(crate) const AT_REMOVEDIR: u32 = 0x200;
+}
+
+/// The `Tgetattr` mask of the basic attributes, which are those of `stat`.
+pub(crate) const GETATTR_BASIC: u64 = 0x7ff;
+/// The `Tsetattr` bit that sets the size.
+pub(crate) const SETATTR_SIZE: u32 = 0x8;
+/// The bit of the type of a qid that marks a directory.
+pub(crate) const QTDIR: u8 = 0x80;
+/// The bits of a mode that hold the file type.
+pub(crate) const S_IFMT: u32 = 0o170000;
+/// The file type bits of a directory.
+pub(crate) const S_IFDIR: u32 = 0o040000;
+/// The file type bits of a regular file.
+pub(crate) const S_IFREG: u32 = 0o100000;
+
+/// The unique identity of a file on the server.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct Qid {
+    pub(crate) ty: u8,
+    pub(crate) version: u32,
+    pub(crate) path: u64,
+}
+
+impl Qid {
+    /// Returns true if the qid is of a directory.
+    pub(crate) fn is_directory(&self) -> bool {
+        self.ty & QTDIR != 0
+    }
+}
+
+/// Encodes a message.
+pub(crate) struct Encoder(Vec<u8>);
+
+impl Encoder {
+    /// Starts a message.
+    ///
+    /// # Arguments
+    /// `ty`: The type of the message.  
+    /// `tag`: The tag the message is matched with its reply by.  
+    pub(crate) fn new(ty: u8, tag: u16) -> Self {
+        // the size is filled in when the message is finished
+        let mut encoder = Self(vec![0; 4]);
+        encoder.u8(ty).u16(tag);
+        encoder
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) -> &mut Self {
+        self.0.push(value);
+        self
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    /// Appends a string, prefixed with its length.
+    ///
+    /// # Arguments
+    /// `value`: The string, which must be shorter than 64 KiB.  
+    pub(crate) fn str(&mut self, value: &str) -> &mut Self {
+        self.u16(value.len() as u16);
+        self.0.extend_from_slice(value.as_bytes());
+        self
+    }
+
+    /// Appends data, prefixed with its length.
+    ///
+    /// # Arguments
+    /// `value`: The data.  
+    pub(crate) fn data(&mut self, value: &[u8]) -> &mut Self {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    /// Appends a qid.
+    pub(crate) fn qid(&mut self, qid: &Qid) -> &mut Self {
+        self.u8(qid.ty).u32(qid.version).u64(qid.path)
+    }
+
+    /// Returns the type of the message.
+    pub(crate) fn ty(&self) -> u8 {
+        self.0[4]
+    }
+
+    /// Finishes the message, returning its bytes.
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        let mut message = std::mem::take(&mut self.0);
+        let size = message.len() as u32;
+        message[..4].copy_from_slice(&size.to_le_bytes());
+        message
+    }
+}
+
+/// Decodes the body of a message.
+pub(crate) struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    /// Creates a decoder of the body of a message.
+    ///
+    /// # Arguments
+    /// `body`: The body of the message, after its type and tag.  
+    pub(crate) fn new(body: &'a [u8]) -> Self {
+        Self(body)
+    }
+
+    /// Returns true if the whole body was decoded.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Takes `len` bytes from the body.
+    ///
+    /// # Arguments
+    /// `len`: The number of bytes.  
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Truncated 9P message",
+            ));
+        }
+
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Takes a string prefixed with its length.
+    pub(crate) fn str(&mut self) -> io::Result<&'a str> {
+        let len = self.u16()? as usize;
+        std::str::from_utf8(self.take(len)?)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "9P string isn't UTF-8"))
+    }
+
+    /// Takes data prefixed with its length.
+    pub(crate) fn data(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub(crate) fn qid(&mut self) -> io::Result<Qid> {
+        Ok(Qid {
+            ty: self.u8()?,
+            version: self.u32()?,
+            path: self.u64()?,
+        })
+    }
+}
+
+/// Reads a message. Returns its type, tag and body.
+///
+/// # Arguments
+/// `reader`: The connection.  
+/// `msize`: The largest message size that may be received.  
+pub(crate) fn read_message<R: Read>(reader: &mut R, msize: u32) -> io::Result<(u8, u16, Vec<u8>)> {
+    let mut header = [0; 7];
+    reader.read_exact(&mut header)?;
+    let size = u32::from_le_bytes(header[..4].try_into().unwrap());
+    if !(7..=msize).contains(&size) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("9P message of {size} bytes is out of bounds"),
+        ));
+    }
+
+    let mut body = vec![0; size as usize - header.len()];
+    reader.read_exact(&mut body)?;
+    Ok((header[4], u16::from_le_bytes([header[5], header[6]]), body))
+}
+
+/// Writes a message.
+///
+/// # Arguments
+/// `writer`: The connection.  
+/// `message`: The encoded message.  
+pub(crate) fn write_message<W: Write>(writer: &mut W, message: &mut Encoder) -> io::Result<()> {
+    writer.write_all(&message.finish())?;
+    writer.flush()
+}
+
+/// Converts a Linux error number from `Rlerror` to an IO error.
+///
+/// # Arguments
+/// `errno`: The error number.  
+pub(crate) fn errno_error(errno: u32) -> io::Error {
+    let kind = match errno {
+        2 => ErrorKind::NotFound,
+        1 | 13 => ErrorKind::PermissionDenied,
+        17 => ErrorKind::AlreadyExists,
+        18 => ErrorKind::CrossesDevices,
+        20 => ErrorKind::NotADirectory,
+        21 => ErrorKind::IsADirectory,
+        22 => ErrorKind::InvalidInput,
+        28 => ErrorKind::StorageFull,
+        30 => ErrorKind::ReadOnlyFilesystem,
+        38 | 95 => ErrorKind::Unsupported,
+        39 => ErrorKind::DirectoryNotEmpty,
+        122 => ErrorKind::QuotaExceeded,
+        _ => ErrorKind::Other,
+    };
+
+    io::Error::new(kind, format!("9P error {errno}"))
+}
+
+/// Converts an IO error to a Linux error number for `Rlerror`.
+///
+/// # Arguments
+/// `err`: The error.  
+pub(crate) fn error_errno(err: &io::Error) -> u32 {
+    match err.kind() {
+        ErrorKind::NotFound => 2,
+        ErrorKind::PermissionDenied => 13,
+        ErrorKind::AlreadyExists => 17,
+        ErrorKind::CrossesDevices => 18,
+        ErrorKind::NotADirectory => 20,
+        ErrorKind::IsADirectory => 21,
+        ErrorKind::InvalidInput => 22,
+        ErrorKind::StorageFull => 28,
+        ErrorKind::ReadOnlyFilesystem => 30,
+        ErrorKind::DirectoryNotEmpty => 39,
+        ErrorKind::Unsupported => 95,
+        ErrorKind::QuotaExceeded => 122,
+        // EIO
+        _ => 5,
+    }
+}
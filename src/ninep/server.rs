@@ -0,0 +1,812 @@
+use crate::file::{FileType, Metadata, OpenOptions};
+use crate::ninep::{
+    error_errno, flags, message, read_message, write_message, Decoder, Encoder, Qid, GETATTR_BASIC,
+    IO_HEADER_SIZE, MAX_WALK, NOFID, QTDIR, SETATTR_SIZE, S_IFDIR, S_IFREG, VERSION,
+};
+use crate::util::component_iter;
+use crate::{util, FileSystem};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+/// The largest message size negotiated with clients.
+const MAX_MESSAGE_SIZE: u32 = 1024 * 1024;
+/// The numeric user of attaches by name only.
+const NONUNAME: u32 = u32::MAX;
+/// The user that files are owned by for attaches by name only.
+const NOBODY: u32 = 65534;
+/// The magic number `statfs` reports for 9P filesystems.
+const V9FS_MAGIC: u32 = 0x0102_1997;
+/// The directory entry types of `Treaddir`.
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+/// The lock type `Tgetlock` reports when a lock could be taken.
+const F_UNLCK: u8 = 2;
+
+/// The Linux error numbers that aren't converted from IO errors.
+mod errno {
+    pub(super) const EBADF: u32 = 9;
+    pub(super) const EEXIST: u32 = 17;
+    pub(super) const ENOTDIR: u32 = 20;
+    pub(super) const EISDIR: u32 = 21;
+    pub(super) const EINVAL: u32 = 22;
+    pub(super) const ENAMETOOLONG: u32 = 36;
+    pub(super) const ENOSYS: u32 = 38;
+    pub(super) const ENOTEMPTY: u32 = 39;
+    pub(super) const EOPNOTSUPP: u32 = 95;
+}
+
+/// Serves `fs` over 9P2000.L on the TCP connections accepted by `listener` until accepting one fails, such as to a
+/// virtual machine that mounts it with `mount -t 9p -o trans=tcp,port=564,version=9p2000.L host /mnt`.
+///
+/// The root of the filesystem is attached to by an empty tree name, and any of its directories by their paths.
+/// Attaches aren't authenticated, and files are reported as owned by the attaching user. Files are opened for each
+/// read and write rather than held open, so a file that's removed while it's open can't be read anymore.
+///
+/// # Arguments
+/// `fs`: The filesystem to serve.  
+/// `listener`: The listener to accept connections from.  
+pub fn serve<FS: FileSystem + Sync>(fs: FS, listener: TcpListener) -> io::Result<()> {
+    let server = Server::new(&fs);
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            stream.set_nodelay(true)?;
+            let server = &server;
+            scope.spawn(move || server.session(stream));
+        }
+        Ok(())
+    })
+}
+
+/// Serves `fs` over 9P2000.L on the connections accepted by a Unix socket `listener` until accepting one fails.
+///
+/// # Arguments
+/// `fs`: The filesystem to serve.  
+/// `listener`: The listener to accept connections from.  
+#[cfg(unix)]
+pub fn serve_unix<FS: FileSystem + Sync>(
+    fs: FS,
+    listener: std::os::unix::net::UnixListener,
+) -> io::Result<()> {
+    let server = Server::new(&fs);
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let server = &server;
+            scope.spawn(move || server.session(stream));
+        }
+        Ok(())
+    })
+}
+
+/// Serves `fs` over 9P2000.L on a single connection until it's closed, such as a pair of pipes mounted with
+/// `trans=fd`.
+///
+/// # Arguments
+/// `fs`: The filesystem to serve.  
+/// `stream`: The connection.  
+pub fn serve_connection<FS: FileSystem, S: Read + Write>(fs: &FS, stream: S) {
+    Server::new(fs).session(stream);
+}
+
+/// A Linux error number that a request failed with.
+struct Errno(u32);
+
+impl From<io::Error> for Errno {
+    fn from(err: io::Error) -> Self {
+        Self(error_errno(&err))
+    }
+}
+
+/// What a fid refers to.
+#[derive(Clone)]
+struct Fid {
+    /// The path of the attached directory, which walks can't leave.
+    root: String,
+    path: String,
+    /// The numeric user that attached.
+    uid: u32,
+    open: Option<Open>,
+}
+
+/// How a fid was opened.
+#[derive(Clone)]
+enum Open {
+    /// A directory, with the entries that were in it by name and their qids.
+    Directory(Vec<(String, Qid)>),
+    File {
+        read: bool,
+        write: bool,
+        append: bool,
+    },
+}
+
+/// The qid paths of the entries that were seen, which identify them for as long as the server runs.
+#[derive(Default)]
+struct QidPaths {
+    paths: HashMap<String, u64>,
+    next: u64,
+}
+
+impl QidPaths {
+    /// Moves the qid paths of `from` and the entries under it to `to`, so that they follow the renamed entries.
+    fn rename(&mut self, from: &str, to: &str) {
+        self.paths.remove(to);
+        let prefix = format!("{from}/");
+        let moved = self
+            .paths
+            .iter()
+            .filter(|(path, _)| *path == from || path.starts_with(&prefix))
+            .map(|(path, id)| (path.clone(), *id))
+            .collect::<Vec<_>>();
+        for (path, id) in moved {
+            self.paths.remove(&path);
+            self.paths
+                .insert(format!("{to}{}", &path[from.len()..]), id);
+        }
+    }
+}
+
+/// Serves a filesystem to every connection.
+struct Server<'a, FS: ?Sized> {
+    fs: &'a FS,
+    qid_paths: Mutex<QidPaths>,
+}
+
+impl<'a, FS: FileSystem + ?Sized> Server<'a, FS> {
+    fn new(fs: &'a FS) -> Self {
+        Self {
+            fs,
+            qid_paths: Mutex::default(),
+        }
+    }
+
+    /// Serves a connection until it's closed.
+    fn session<S: Read + Write>(&self, mut stream: S) {
+        let mut session = Session {
+            msize: MAX_MESSAGE_SIZE,
+            fids: HashMap::new(),
+        };
+        while let Ok((ty, tag, body)) = read_message(&mut stream, session.msize) {
+            let mut reply = Encoder::new(ty + 1, tag);
+            if let Err(Errno(errno)) = self.handle(&mut session, ty, &body, &mut reply) {
+                reply = Encoder::new(message::RLERROR, tag);
+                reply.u32(errno);
+            }
+            if write_message(&mut stream, &mut reply).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Handles a request, encoding the body of its reply.
+    fn handle(
+        &self,
+        session: &mut Session,
+        ty: u8,
+        body: &[u8],
+        reply: &mut Encoder,
+    ) -> Result<(), Errno> {
+        let mut request = Decoder::new(body);
+        match ty {
+            message::TVERSION => {
+                let (msize, version) = (request.u32()?, request.str()?);
+                // the payload of reads and writes has to fit in a message
+                if msize <= IO_HEADER_SIZE {
+                    return Err(Errno(errno::EINVAL));
+                }
+                session.msize = msize.min(MAX_MESSAGE_SIZE);
+                session.fids.clear();
+                let version = match version.starts_with(VERSION) {
+                    true => VERSION,
+                    false => "unknown",
+                };
+                reply.u32(session.msize).str(version);
+            }
+            message::TATTACH => {
+                let (fid, _afid, _uname, aname) = (
+                    request.u32()?,
+                    request.u32()?,
+                    request.str()?,
+                    request.str()?,
+                );
+                let uid = match request.u32()? {
+                    NONUNAME => NOBODY,
+                    uid => uid,
+                };
+                if session.fids.contains_key(&fid) {
+                    return Err(Errno(errno::EBADF));
+                }
+                let root = component_iter(Path::new(aname))
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let metadata = util::metadata_or_root(self.fs, &root)?;
+                if !metadata.is_directory() {
+                    return Err(Errno(errno::ENOTDIR));
+                }
+                reply.qid(&self.qid(&root, &metadata));
+                session.fids.insert(
+                    fid,
+                    Fid {
+                        root: root.clone(),
+                        path: root,
+                        uid,
+                        open: None,
+                    },
+                );
+            }
+            message::TWALK => {
+                let (fid, new_fid, count) = (request.u32()?, request.u32()?, request.u16()?);
+                let from = session.fid(fid)?.clone();
+                if count as usize > MAX_WALK {
+                    return Err(Errno(errno::EINVAL));
+                }
+                if new_fid != fid && session.fids.contains_key(&new_fid) {
+                    return Err(Errno(errno::EBADF));
+                }
+
+                let mut path = from.path.clone();
+                let mut qids = Vec::new();
+                for _ in 0..count {
+                    let name = request.str()?;
+                    let walked = util::metadata_or_root(self.fs, &path)
+                        .map_err(Errno::from)
+                        .and_then(|metadata| {
+                            if !metadata.is_directory() {
+                                return Err(Errno(errno::ENOTDIR));
+                            }
+                            let next = match name {
+                                // walks can't leave the attached directory
+                                ".." if path == from.root => path.clone(),
+                                ".." => parent(&path).to_owned(),
+                                "." => path.clone(),
+                                name => child(&path, name)?,
+                            };
+                            let metadata = util::metadata_or_root(self.fs, &next)?;
+                            Ok((next, metadata))
+                        });
+                    match walked {
+                        Ok((next, metadata)) => {
+                            qids.push(self.qid(&next, &metadata));
+                            path = next;
+                        }
+                        // only the first name failing to be walked is an error
+                        Err(err) if qids.is_empty() => return Err(err),
+                        Err(_) => break,
+                    }
+                }
+
+                // a partial walk leaves the new fid unused
+                if qids.len() == count as usize {
+                    session.fids.insert(
+                        new_fid,
+                        Fid {
+                            path,
+                            open: None,
+                            ..from
+                        },
+                    );
+                }
+                reply.u16(qids.len() as u16);
+                for qid in qids {
+                    reply.qid(&qid);
+                }
+            }
+            message::TLOPEN => {
+                let (fid, open_flags) = (request.u32()?, request.u32()?);
+                let path = session.unopened_fid(fid)?.path.clone();
+                let metadata = util::metadata_or_root(self.fs, &path)?;
+                let open = if metadata.is_directory() {
+                    if open_flags & flags::O_ACCMODE != flags::O_RDONLY {
+                        return Err(Errno(errno::EISDIR));
+                    }
+                    Open::Directory(self.list(&path)?)
+                } else {
+                    if open_flags & flags::O_DIRECTORY != 0 {
+                        return Err(Errno(errno::ENOTDIR));
+                    }
+                    self.open(&path, open_flags, false)?
+                };
+                session.fids.get_mut(&fid).unwrap().open = Some(open);
+                reply
+                    .qid(&self.qid(&path, &metadata))
+                    .u32(session.io_size());
+            }
+            message::TLCREATE => {
+                let fid = request.u32()?;
+                let (name, open_flags, mode, _gid) = (
+                    request.str()?,
+                    request.u32()?,
+                    request.u32()?,
+                    request.u32()?,
+                );
+                let dir = session.unopened_fid(fid)?.path.clone();
+                if !util::metadata_or_root(self.fs, &dir)?.is_directory() {
+                    return Err(Errno(errno::ENOTDIR));
+                }
+                let path = child(&dir, name)?;
+                if open_flags & flags::O_EXCL != 0 && self.fs.exists(&path)? {
+                    return Err(Errno(errno::EEXIST));
+                }
+
+                let options = OpenOptions {
+                    create: true,
+                    write: true,
+                    truncate: open_flags & flags::O_TRUNC != 0,
+                    mode: Some(mode & 0o7777),
+                    ..OpenOptions::default()
+                };
+                drop(self.fs.open_file_options(&path, &options)?);
+                // creating a file turns the fid of its directory into the fid of the open file
+                let open = self.open(&path, open_flags, true)?;
+                let fid = session.fids.get_mut(&fid).unwrap();
+                fid.path.clone_from(&path);
+                fid.open = Some(open);
+                let metadata = util::metadata_or_root(self.fs, &path)?;
+                reply
+                    .qid(&self.qid(&path, &metadata))
+                    .u32(session.io_size());
+            }
+            message::TGETATTR => {
+                let fid = session.fid(request.u32()?)?;
+                let metadata = util::metadata_or_root(self.fs, &fid.path)?;
+                let mode = match metadata.file_type {
+                    FileType::Directory => S_IFDIR | metadata.mode.unwrap_or(0o755),
+                    _ => S_IFREG | metadata.mode.unwrap_or(0o644),
+                };
+                let links = if metadata.is_directory() { 2 } else { 1 };
+                let modified = metadata
+                    .modified
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .unwrap_or_default();
+
+                reply
+                    .u64(GETATTR_BASIC)
+                    .qid(&self.qid(&fid.path, &metadata))
+                    .u32(mode)
+                    .u32(fid.uid)
+                    .u32(fid.uid)
+                    .u64(links)
+                    .u64(0)
+                    .u64(metadata.len());
+                reply.u64(4096).u64(metadata.len().div_ceil(512));
+                // the access, modification, change and birth times
+                for _ in 0..4 {
+                    reply
+                        .u64(modified.as_secs())
+                        .u64(modified.subsec_nanos() as u64);
+                }
+                // the generation and data version
+                reply.u64(0).u64(0);
+            }
+            message::TSETATTR => {
+                let (fid, valid) = (request.u32()?, request.u32()?);
+                let (_mode, _uid, _gid, size) = (
+                    request.u32()?,
+                    request.u32()?,
+                    request.u32()?,
+                    request.u64()?,
+                );
+                let path = session.fid(fid)?.path.clone();
+                // modes, owners and times are ignored, since filesystems can't change them
+                match valid & SETATTR_SIZE {
+                    0 => {
+                        util::metadata_or_root(self.fs, &path)?;
+                    }
+                    _ => util::set_len(self.fs, &path, size)?,
+                }
+            }
+            message::TREADDIR => {
+                let (fid, offset, count) = (request.u32()?, request.u64()?, request.u32()?);
+                let Some(Open::Directory(entries)) = &session.fid(fid)?.open else {
+                    return Err(Errno(errno::EBADF));
+                };
+                let count = count.min(session.io_size()) as usize;
+
+                let mut data = Encoder::new(0, 0);
+                let mut len = 0;
+                for (index, (name, qid)) in entries.iter().enumerate().skip(offset as usize) {
+                    let entry_len = 13 + 8 + 1 + 2 + name.len();
+                    if len + entry_len > count {
+                        break;
+                    }
+                    len += entry_len;
+                    let ty = if qid.is_directory() { DT_DIR } else { DT_REG };
+                    data.qid(qid).u64(index as u64 + 1).u8(ty).str(name);
+                }
+                // the entries follow the size, type and tag of the empty message
+                reply.data(&data.finish()[7..]);
+            }
+            message::TREAD => {
+                let (fid, offset, count) = (request.u32()?, request.u64()?, request.u32()?);
+                let fid = session.fid(fid)?;
+                let Some(Open::File { read: true, .. }) = fid.open else {
+                    return Err(Errno(match fid.open {
+                        Some(Open::Directory(_)) => errno::EISDIR,
+                        _ => errno::EBADF,
+                    }));
+                };
+
+                let mut file = self.fs.open_file(&fid.path)?;
+                file.seek(SeekFrom::Start(offset))?;
+                let mut data = Vec::new();
+                file.take(count.min(session.io_size()) as u64)
+                    .read_to_end(&mut data)?;
+                reply.data(&data);
+            }
+            message::TWRITE => {
+                let (fid, offset, data) = (request.u32()?, request.u64()?, request.data()?);
+                let fid = session.fid(fid)?;
+                let Some(Open::File {
+                    write: true,
+                    append,
+                    ..
+                }) = fid.open
+                else {
+                    return Err(Errno(errno::EBADF));
+                };
+
+                let options = OpenOptions {
+                    write: true,
+                    ..OpenOptions::default()
+                };
+                let mut file = self.fs.open_file_options(&fid.path, &options)?;
+                file.seek(match append {
+                    true => SeekFrom::End(0),
+                    false => SeekFrom::Start(offset),
+                })?;
+                file.write_all(data)?;
+                file.flush()?;
+                reply.u32(data.len() as u32);
+            }
+            message::TCLUNK => {
+                let fid = request.u32()?;
+                session.fids.remove(&fid).ok_or(Errno(errno::EBADF))?;
+            }
+            message::TREMOVE => {
+                // the fid is clunked even if the entry isn't removed
+                let fid = request.u32()?;
+                let fid = session.fids.remove(&fid).ok_or(Errno(errno::EBADF))?;
+                self.remove(
+                    &fid.path,
+                    util::metadata_or_root(self.fs, &fid.path)?.is_directory(),
+                )?;
+            }
+            message::TMKDIR => {
+                let (fid, name, mode, _gid) = (
+                    request.u32()?,
+                    request.str()?,
+                    request.u32()?,
+                    request.u32()?,
+                );
+                let path = child(&session.fid(fid)?.path, name)?;
+                self.fs.create_dir_with(&path, mode & 0o7777)?;
+                reply.qid(&self.qid(&path, &Metadata::directory()));
+            }
+            message::TUNLINKAT => {
+                let (fid, name, unlink_flags) = (request.u32()?, request.str()?, request.u32()?);
+                let path = child(&session.fid(fid)?.path, name)?;
+                self.remove(&path, unlink_flags & flags::AT_REMOVEDIR != 0)?;
+            }
+            message::TRENAME | message::TRENAMEAT => {
+                let (from, to) = match ty {
+                    message::TRENAME => {
+                        let from = session.fid(request.u32()?)?.path.clone();
+                        let to_dir = &session.fid(request.u32()?)?.path;
+                        (from, child(to_dir, request.str()?)?)
+                    }
+                    _ => {
+                        let from_dir = &session.fid(request.u32()?)?.path;
+                        let from = child(from_dir, request.str()?)?;
+                        let to_dir = &session.fid(request.u32()?)?.path;
+                        (from, child(to_dir, request.str()?)?)
+                    }
+                };
+                if from.is_empty() {
+                    return Err(Errno(errno::EINVAL));
+                }
+                self.fs.rename(&from, &to)?;
+                self.qid_paths.lock().rename(&from, &to);
+                // the fids of the renamed entries follow them
+                let prefix = format!("{from}/");
+                for fid in session.fids.values_mut() {
+                    if fid.path == from || fid.path.starts_with(&prefix) {
+                        fid.path = format!("{to}{}", &fid.path[from.len()..]);
+                    }
+                }
+            }
+            message::TSTATFS => {
+                session.fid(request.u32()?)?;
+                let stats = util::stats_or_empty(self.fs);
+                reply
+                    .u32(V9FS_MAGIC)
+                    .u32(stats.block_size as u32)
+                    .u64(stats.total_space / stats.block_size)
+                    .u64(stats.free_space / stats.block_size)
+                    .u64(stats.available_space / stats.block_size);
+                // the numbers of files are unknown, and names are up to 255 bytes long
+                reply.u64(0).u64(0).u64(0).u32(255);
+            }
+            // writes are flushed as they're made, and requests are answered in order, so there's nothing to flush
+            message::TFSYNC => {
+                session.fid(request.u32()?)?;
+            }
+            message::TFLUSH => {}
+            // locks are advisory, and always granted since they aren't shared with anything else
+            message::TLOCK => {
+                session.fid(request.u32()?)?;
+                reply.u8(0);
+            }
+            message::TGETLOCK => {
+                session.fid(request.u32()?)?;
+                let (_ty, start, length, proc_id, client_id) = (
+                    request.u8()?,
+                    request.u64()?,
+                    request.u64()?,
+                    request.u32()?,
+                    request.str()?,
+                );
+                reply
+                    .u8(F_UNLCK)
+                    .u64(start)
+                    .u64(length)
+                    .u32(proc_id)
+                    .str(client_id);
+            }
+            // authentication, links, special files and extended attributes aren't supported
+            message::TAUTH
+            | message::TSYMLINK
+            | message::TMKNOD
+            | message::TLINK
+            | message::TREADLINK
+            | message::TXATTRWALK
+            | message::TXATTRCREATE => return Err(Errno(errno::EOPNOTSUPP)),
+            _ => return Err(Errno(errno::ENOSYS)),
+        }
+        Ok(())
+    }
+
+    /// Returns the qid of the entry at `path`, whose metadata is `metadata`.
+    fn qid(&self, path: &str, metadata: &Metadata) -> Qid {
+        let mut qid_paths = self.qid_paths.lock();
+        let next = qid_paths.next;
+        let qid_path = *qid_paths.paths.entry(path.to_owned()).or_insert(next);
+        if qid_path == next {
+            qid_paths.next += 1;
+        }
+        Qid {
+            ty: if metadata.is_directory() { QTDIR } else { 0 },
+            version: 0,
+            path: qid_path,
+        }
+    }
+
+    /// Returns the entries of the directory at `path` by name, including `.` and `..`, with their qids.
+    fn list(&self, path: &str) -> crate::Result<Vec<(String, Qid)>> {
+        let directory = Metadata::directory();
+        let mut entries = vec![
+            (String::from("."), self.qid(path, &directory)),
+            (String::from(".."), self.qid(parent(path), &directory)),
+        ];
+        let mut children = Vec::new();
+        for entry in self.fs.read_dir(path)? {
+            let entry = entry?;
+            if let Some(name) = entry.path.file_name().and_then(|name| name.to_str()) {
+                children.push((name.to_owned(), entry.metadata));
+            }
+        }
+        // the entries are sorted, so that offsets are positions in a stable order
+        children.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (name, metadata) in children {
+            let qid = match path {
+                "" => self.qid(&name, &metadata),
+                path => self.qid(&format!("{path}/{name}"), &metadata),
+            };
+            entries.push((name, qid));
+        }
+        Ok(entries)
+    }
+
+    /// Opens the file at `path` with the Linux open flags `open_flags`, truncating it if they say so unless it was
+    /// just created. The file is opened to check that it can be, and reopened for every read and write.
+    fn open(&self, path: &str, open_flags: u32, created: bool) -> crate::Result<Open> {
+        let access = open_flags & flags::O_ACCMODE;
+        let (read, write) = (access != flags::O_WRONLY, access != flags::O_RDONLY);
+        let options = OpenOptions {
+            read,
+            write,
+            truncate: write && !created && open_flags & flags::O_TRUNC != 0,
+            ..OpenOptions::default()
+        };
+        drop(self.fs.open_file_options(path, &options)?);
+        Ok(Open::File {
+            read,
+            write,
+            append: open_flags & flags::O_APPEND != 0,
+        })
+    }
+
+    /// Removes the file or directory at `path`. Directories must be empty, since some filesystems remove directories
+    /// along with their contents.
+    fn remove(&self, path: &str, directory: bool) -> Result<(), Errno> {
+        if path.is_empty() {
+            return Err(Errno(errno::EINVAL));
+        }
+        match (
+            directory,
+            util::metadata_or_root(self.fs, path)?.is_directory(),
+        ) {
+            (true, true) => {
+                if self.fs.read_dir(path)?.next().is_some() {
+                    return Err(Errno(errno::ENOTEMPTY));
+                }
+                self.fs.remove_dir(path)?;
+            }
+            (true, false) => return Err(Errno(errno::ENOTDIR)),
+            (false, true) => return Err(Errno(errno::EISDIR)),
+            (false, false) => self.fs.remove_file(path)?,
+        }
+        self.qid_paths.lock().paths.remove(path);
+        Ok(())
+    }
+}
+
+/// The state of a connection.
+struct Session {
+    /// The negotiated largest message size.
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+}
+
+impl Session {
+    /// Returns the most bytes a single read or write transfers.
+    fn io_size(&self) -> u32 {
+        self.msize - IO_HEADER_SIZE
+    }
+
+    /// Returns what `fid` refers to.
+    fn fid(&self, fid: u32) -> Result<&Fid, Errno> {
+        match fid {
+            NOFID => Err(Errno(errno::EBADF)),
+            fid => self.fids.get(&fid).ok_or(Errno(errno::EBADF)),
+        }
+    }
+
+    /// Returns what `fid` refers to, which must not be open yet.
+    fn unopened_fid(&self, fid: u32) -> Result<&Fid, Errno> {
+        let fid = self.fid(fid)?;
+        match fid.open {
+            Some(_) => Err(Errno(errno::EBADF)),
+            None => Ok(fid),
+        }
+    }
+}
+
+/// Returns the path of the parent of the entry at `path`.
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+/// Returns the path of the entry `name` in the directory at `dir`.
+fn child(dir: &str, name: &str) -> Result<String, Errno> {
+    match name {
+        "" | "." | ".." => Err(Errno(errno::EINVAL)),
+        name if name.contains('/') => Err(Errno(errno::EINVAL)),
+        name if name.len() > 255 => Err(Errno(errno::ENAMETOOLONG)),
+        name if dir.is_empty() => Ok(name.to_owned()),
+        name => Ok(format!("{dir}/{name}")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::OpenOptions;
+    use crate::memory_fs::MemoryFS;
+    use crate::ninep::serve;
+    use crate::ninep_fs::NinePFS;
+    use crate::util::test::read_directory;
+    use crate::FileSystem;
+    use std::io::{ErrorKind, Seek, SeekFrom, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
+
+    /// Serves `fs` in the background. Returns the address of the server.
+    fn serve_tcp(fs: MemoryFS) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve(fs, listener));
+        addr
+    }
+
+    #[test]
+    fn read_write() {
+        let fs = NinePFS::connect(serve_tcp(MemoryFS::default()), "user", "").unwrap();
+        fs.create_dir("dir").unwrap();
+        fs.create_dir_all("dir/a/b").unwrap();
+        write!(fs.create_file("dir/file").unwrap(), "hello").unwrap();
+
+        let mut file = fs
+            .open_file_options("dir/file", &OpenOptions::default().append(true))
+            .unwrap();
+        write!(file, " world").unwrap();
+        drop(file);
+        let mut file = fs.open_file("dir/file").unwrap();
+        file.seek(SeekFrom::Start(6)).unwrap();
+        assert_eq!(file.read_into_string().unwrap(), "world");
+        drop(file);
+
+        let dir = read_directory(&fs, "dir");
+        itertools::assert_equal(dir.keys(), vec!["dir/a", "dir/file"]);
+        assert!(dir["dir/a"].is_directory());
+        assert_eq!(dir["dir/file"].len(), 11);
+
+        fs.rename("dir/file", "dir/a/moved").unwrap();
+        assert_eq!(fs.metadata("dir/a/moved").unwrap().len(), 11);
+        assert_eq!(
+            fs.open_file("dir/file").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        // directories are only removed once they're empty
+        assert_eq!(
+            fs.remove_dir("dir/a").err().unwrap().kind(),
+            ErrorKind::DirectoryNotEmpty
+        );
+        fs.remove_file("dir/a/moved").unwrap();
+        fs.remove_dir("dir/a/b").unwrap();
+        fs.remove_dir("dir/a").unwrap();
+        itertools::assert_equal(read_directory(&fs, "dir").keys(), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn attach() {
+        let memory_fs = MemoryFS::default();
+        memory_fs.create_dir("export").unwrap();
+        write!(memory_fs.create_file("secret").unwrap(), "hidden").unwrap();
+        write!(memory_fs.create_file("export/file").unwrap(), "shared").unwrap();
+        let addr = serve_tcp(memory_fs);
+
+        // walks can't leave the attached directory
+        let fs = NinePFS::connect(addr, "user", "export").unwrap();
+        assert_eq!(
+            fs.open_file("file").unwrap().read_into_string().unwrap(),
+            "shared"
+        );
+        assert_eq!(
+            fs.open_file("../secret").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        // deep paths are walked in parts
+        let mut deep = String::from("deep");
+        fs.create_dir(&deep).unwrap();
+        for n in 0..20 {
+            deep = format!("{deep}/{n}");
+            fs.create_dir(&deep).unwrap();
+        }
+        assert!(fs.metadata(&deep).unwrap().is_directory());
+
+        // files can't be attached to
+        assert!(NinePFS::connect(addr, "user", "export/file").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix() {
+        let path = std::env::temp_dir().join(format!("ninep-serve-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        thread::spawn(move || crate::ninep::serve_unix(MemoryFS::default(), listener));
+
+        let fs = NinePFS::connect_unix(&path, "user", "").unwrap();
+        write!(fs.create_file("file").unwrap(), "hello").unwrap();
+        assert_eq!(fs.metadata("file").unwrap().len(), 5);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,1023 @@
+use crate::file::{DirEntry, File, FileSystemStats, FileType, Metadata, OpenOptions};
+use crate::ninep::{
+    errno_error, flags, message, read_message, write_message, Decoder, Encoder, Qid, GETATTR_BASIC,
+    IO_HEADER_SIZE, MAX_WALK, NOFID, NOTAG, SETATTR_SIZE, S_IFDIR, S_IFMT, S_IFREG, VERSION,
+};
+use crate::tree::normalize_and_relativize;
+use crate::util::{already_exists, component_iter, invalid_input, invalid_path, not_found};
+use crate::FileSystem;
+use parking_lot::Mutex;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The largest message size negotiated with the server.
+const MAX_MESSAGE_SIZE: u32 = 256 * 1024;
+/// The tag of every request, since only one is in flight at a time.
+const TAG: u16 = 0;
+/// The mode directories are created with by `create_dir`.
+const DEFAULT_DIR_MODE: u32 = 0o755;
+/// The mode files are created with unless the open options have one.
+const DEFAULT_FILE_MODE: u32 = 0o644;
+
+/// A read-write filesystem on a 9P2000.L server, such as a host directory shared with a virtual machine or a
+/// Plan 9-style service, over TCP or a Unix socket.
+///
+/// Files are read and written directly on the server, one request at a time per connection.
+pub struct NinePFS {
+    connection: Arc<Mutex<Connection>>,
+    /// The fid of the attached root directory.
+    root: u32,
+}
+
+impl NinePFS {
+    /// Connects to a 9P server over TCP and attaches to a tree as `user`.
+    ///
+    /// # Arguments
+    /// `addr`: The address of the server.  
+    /// `user`: The user to attach as.  
+    /// `aname`: The tree to attach to, which servers that export a single tree ignore.  
+    pub fn connect<A: ToSocketAddrs>(addr: A, user: &str, aname: &str) -> crate::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Self::attach(Stream::Tcp(stream), user, aname)
+    }
+
+    /// Connects to a 9P server over a Unix socket and attaches to a tree as `user`.
+    ///
+    /// # Arguments
+    /// `path`: The path of the socket.  
+    /// `user`: The user to attach as.  
+    /// `aname`: The tree to attach to, which servers that export a single tree ignore.  
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<std::path::Path>>(
+        path: P,
+        user: &str,
+        aname: &str,
+    ) -> crate::Result<Self> {
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
+        Self::attach(Stream::Unix(stream), user, aname)
+    }
+
+    /// Negotiates the protocol version and attaches to a tree.
+    ///
+    /// # Arguments
+    /// `stream`: The connection.  
+    /// `user`: The user to attach as.  
+    /// `aname`: The tree to attach to.  
+    fn attach(stream: Stream, user: &str, aname: &str) -> crate::Result<Self> {
+        let mut connection = Connection {
+            stream,
+            msize: MAX_MESSAGE_SIZE,
+            next_fid: 0,
+            free_fids: Vec::new(),
+        };
+
+        let mut version = Encoder::new(message::TVERSION, NOTAG);
+        version.u32(MAX_MESSAGE_SIZE).str(VERSION);
+        let reply = connection.call(&mut version)?;
+        let mut reply = Decoder::new(&reply);
+        let msize = reply.u32()?;
+        if reply.str()? != VERSION {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "The server doesn't speak 9P2000.L",
+            ));
+        }
+        // the payload of reads and writes has to fit in a message
+        if msize <= IO_HEADER_SIZE {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "The negotiated message size is too small",
+            ));
+        }
+        connection.msize = msize.min(MAX_MESSAGE_SIZE);
+
+        let root = connection.fid();
+        // the numeric user is unknown, so the server goes by the name
+        let mut attach = Encoder::new(message::TATTACH, TAG);
+        attach
+            .u32(root)
+            .u32(NOFID)
+            .str(user)
+            .str(aname)
+            .u32(u32::MAX);
+        connection.call(&mut attach)?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            root,
+        })
+    }
+
+    /// Calls `f` with the fid of the parent directory of `path` and the name of the entry in it.
+    ///
+    /// # Arguments
+    /// `path`: The virtual path of the entry.  
+    /// `f`: The function.  
+    fn with_parent<R>(
+        &self,
+        path: &str,
+        f: impl FnOnce(&mut Connection, u32, &str) -> crate::Result<R>,
+    ) -> crate::Result<R> {
+        let path = normalize_and_relativize(path);
+        let names: Vec<_> = component_iter(&path).collect();
+        let Some((name, parent)) = names.split_last() else {
+            return Err(invalid_path());
+        };
+
+        let mut connection = self.connection.lock();
+        let (parent, _) = connection.walk(self.root, parent)?;
+        let result = f(&mut connection, parent, name);
+        connection.clunk(parent);
+        result
+    }
+}
+
+impl Drop for NinePFS {
+    fn drop(&mut self) {
+        self.connection.lock().clunk(self.root);
+    }
+}
+
+impl FileSystem for NinePFS {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.create_dir_with(path, DEFAULT_DIR_MODE)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        let path = normalize_and_relativize(path);
+        let names: Vec<_> = component_iter(&path).collect();
+
+        let mut connection = self.connection.lock();
+        let (fid, _) = connection.walk(self.root, &names)?;
+        let metadata = connection.getattr(fid);
+        connection.clunk(fid);
+        metadata
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        let writes = options.writable();
+        let mut open_flags = match (options.read, writes) {
+            (_, false) => flags::O_RDONLY,
+            (false, true) => flags::O_WRONLY,
+            (true, true) => flags::O_RDWR,
+        };
+        if options.truncate {
+            open_flags |= flags::O_TRUNC;
+        }
+        if options.append {
+            open_flags |= flags::O_APPEND;
+        }
+
+        let normalized_path = normalize_and_relativize(path);
+        let names: Vec<_> = component_iter(&normalized_path).collect();
+        if names.is_empty() {
+            return Err(not_found());
+        }
+
+        let mut connection = self.connection.lock();
+        let fid = match connection.walk(self.root, &names) {
+            Ok((fid, qid)) => {
+                let opened = if options.create_new {
+                    Err(already_exists())
+                } else if qid.is_some_and(|qid| qid.is_directory()) {
+                    Err(invalid_input("path is a directory"))
+                } else {
+                    let mut lopen = Encoder::new(message::TLOPEN, TAG);
+                    lopen.u32(fid).u32(open_flags);
+                    connection.call(&mut lopen)
+                };
+                if let Err(err) = opened {
+                    connection.clunk(fid);
+                    return Err(err);
+                }
+                fid
+            }
+            Err(err)
+                if err.kind() == ErrorKind::NotFound && (options.create || options.create_new) =>
+            {
+                let (name, parent) = names.split_last().unwrap();
+                // creating a file turns the fid of its directory into the fid of the open file
+                let (fid, _) = connection.walk(self.root, parent)?;
+                let mut lcreate = Encoder::new(message::TLCREATE, TAG);
+                lcreate
+                    .u32(fid)
+                    .str(name)
+                    .u32(open_flags | flags::O_CREAT)
+                    .u32(options.mode.unwrap_or(DEFAULT_FILE_MODE))
+                    .u32(0);
+                if let Err(err) = connection.call(&mut lcreate) {
+                    connection.clunk(fid);
+                    return Err(err);
+                }
+                fid
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(Box::new(NinePFile {
+            connection: self.connection.clone(),
+            fid,
+            io_size: connection.msize - IO_HEADER_SIZE,
+            position: 0,
+            append: options.append,
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let directory = normalize_and_relativize(path);
+        let names: Vec<_> = component_iter(&directory).collect();
+
+        let mut connection = self.connection.lock();
+        let (fid, _) = connection.walk(self.root, &names)?;
+        let entries = connection.read_dir(fid);
+        connection.clunk(fid);
+
+        let entries = entries?;
+        Ok(Box::new(entries.into_iter().map(
+            move |(name, metadata)| {
+                Ok(DirEntry {
+                    path: directory.join(name),
+                    metadata,
+                })
+            },
+        )))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.with_parent(path, |connection, parent, name| {
+            connection.unlink(parent, name, flags::AT_REMOVEDIR)
+        })
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.with_parent(path, |connection, parent, name| {
+            connection.unlink(parent, name, 0)
+        })
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        self.with_parent(path, |connection, parent, name| {
+            let mut mkdir = Encoder::new(message::TMKDIR, TAG);
+            mkdir.u32(parent).str(name).u32(mode).u32(0);
+            connection.call(&mut mkdir)?;
+            Ok(())
+        })
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        self.with_parent(to, |connection, to_parent, to_name| {
+            let from = normalize_and_relativize(from);
+            let names: Vec<_> = component_iter(&from).collect();
+            let Some((from_name, from_parent)) = names.split_last() else {
+                return Err(invalid_path());
+            };
+
+            let (from_parent, _) = connection.walk(self.root, from_parent)?;
+            let mut renameat = Encoder::new(message::TRENAMEAT, TAG);
+            renameat
+                .u32(from_parent)
+                .str(from_name)
+                .u32(to_parent)
+                .str(to_name);
+            let result = connection.call(&mut renameat);
+            connection.clunk(from_parent);
+            result.map(|_| ())
+        })
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        let mut statfs = Encoder::new(message::TSTATFS, TAG);
+        statfs.u32(self.root);
+        let reply = self.connection.lock().call(&mut statfs)?;
+
+        let mut reply = Decoder::new(&reply);
+        let _fs_type = reply.u32()?;
+        let block_size = reply.u32()? as u64;
+        let (blocks, free, available) = (reply.u64()?, reply.u64()?, reply.u64()?);
+        Ok(FileSystemStats {
+            total_space: blocks * block_size,
+            free_space: free * block_size,
+            available_space: available * block_size,
+            block_size,
+        })
+    }
+}
+
+/// A connection to a 9P server.
+struct Connection {
+    stream: Stream,
+    /// The negotiated largest message size.
+    msize: u32,
+    /// The next fid that was never used.
+    next_fid: u32,
+    /// The fids that were clunked, which may be reused.
+    free_fids: Vec<u32>,
+}
+
+impl Connection {
+    /// Sends a request and waits for its reply. Returns the body of the reply.
+    ///
+    /// # Arguments
+    /// `request`: The request.  
+    fn call(&mut self, request: &mut Encoder) -> crate::Result<Vec<u8>> {
+        let request_ty = request.ty();
+        write_message(&mut self.stream, request)?;
+
+        let (ty, _, body) = read_message(&mut self.stream, self.msize)?;
+        if ty == message::RLERROR {
+            return Err(errno_error(Decoder::new(&body).u32()?));
+        }
+        // every reply has the type of its request plus one
+        if ty != request_ty + 1 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Unexpected 9P reply {ty}"),
+            ));
+        }
+
+        Ok(body)
+    }
+
+    /// Returns an unused fid.
+    fn fid(&mut self) -> u32 {
+        self.free_fids.pop().unwrap_or_else(|| {
+            self.next_fid += 1;
+            self.next_fid - 1
+        })
+    }
+
+    /// Releases a fid. Errors are ignored, since the server forgets the fid either way.
+    ///
+    /// # Arguments
+    /// `fid`: The fid.  
+    fn clunk(&mut self, fid: u32) {
+        let mut clunk = Encoder::new(message::TCLUNK, TAG);
+        clunk.u32(fid);
+        let _ = self.call(&mut clunk);
+        self.free_fids.push(fid);
+    }
+
+    /// Walks from the directory `from` through `names` to a new fid. Returns the fid and the qid of the entry it
+    /// refers to, which is unknown if there are no names.
+    ///
+    /// # Arguments
+    /// `from`: The fid of the directory to walk from.  
+    /// `names`: The names of the entries to walk through.  
+    fn walk(&mut self, from: u32, names: &[&str]) -> crate::Result<(u32, Option<Qid>)> {
+        let fid = self.fid();
+        let mut qid = None;
+        let mut current = from;
+
+        // the first walk clones `from` even without names, and later walks move the new fid along
+        for chunk in names
+            .chunks(MAX_WALK)
+            .chain(names.is_empty().then_some(&[][..]))
+        {
+            let mut walk = Encoder::new(message::TWALK, TAG);
+            walk.u32(current).u32(fid).u16(chunk.len() as u16);
+            for name in chunk {
+                walk.str(name);
+            }
+
+            let walked = self.call(&mut walk).and_then(|reply| {
+                let mut reply = Decoder::new(&reply);
+                let count = reply.u16()? as usize;
+                let qids = (0..count)
+                    .map(|_| reply.qid())
+                    .collect::<io::Result<Vec<_>>>()?;
+                // a partial walk means an entry is missing, and leaves the new fid unused
+                if qids.len() < chunk.len() {
+                    return Err(not_found());
+                }
+                Ok(qids.last().copied())
+            });
+            match walked {
+                Ok(last) => qid = last.or(qid),
+                Err(err) => {
+                    if current == fid {
+                        self.clunk(fid);
+                    } else {
+                        self.free_fids.push(fid);
+                    }
+                    return Err(err);
+                }
+            }
+            current = fid;
+        }
+
+        Ok((fid, qid))
+    }
+
+    /// Returns the metadata of the entry `fid` refers to.
+    ///
+    /// # Arguments
+    /// `fid`: The fid.  
+    fn getattr(&mut self, fid: u32) -> crate::Result<Metadata> {
+        let mut getattr = Encoder::new(message::TGETATTR, TAG);
+        getattr.u32(fid).u64(GETATTR_BASIC);
+        let reply = self.call(&mut getattr)?;
+
+        let mut reply = Decoder::new(&reply);
+        let _valid = reply.u64()?;
+        let _qid = reply.qid()?;
+        let mode = reply.u32()?;
+        let (uid, gid, _nlink, _rdev) = (reply.u32()?, reply.u32()?, reply.u64()?, reply.u64()?);
+        let size = reply.u64()?;
+        let (_blksize, _blocks) = (reply.u64()?, reply.u64()?);
+        let time = |secs: u64, nsecs: u64| {
+            SystemTime::UNIX_EPOCH.checked_add(Duration::new(secs, nsecs.min(999_999_999) as u32))
+        };
+        let accessed = time(reply.u64()?, reply.u64()?);
+        let modified = time(reply.u64()?, reply.u64()?);
+
+        let file_type = match mode & S_IFMT {
+            S_IFDIR => FileType::Directory,
+            S_IFREG => FileType::File,
+            _ => FileType::Unknown,
+        };
+        let mut metadata = Metadata::new(
+            file_type,
+            if file_type == FileType::Directory {
+                0
+            } else {
+                size
+            },
+        );
+        metadata.mode = Some(mode & 0o7777);
+        metadata.modified = modified;
+        metadata.accessed = accessed;
+        metadata.uid = Some(uid);
+        metadata.gid = Some(gid);
+        Ok(metadata)
+    }
+
+    /// Lists the directory `fid` refers to, with the metadata of every entry.
+    ///
+    /// # Arguments
+    /// `fid`: The fid, which isn't open yet.  
+    fn read_dir(&mut self, fid: u32) -> crate::Result<Vec<(String, Metadata)>> {
+        let mut lopen = Encoder::new(message::TLOPEN, TAG);
+        lopen.u32(fid).u32(flags::O_RDONLY | flags::O_DIRECTORY);
+        self.call(&mut lopen)?;
+
+        let mut names = Vec::new();
+        let mut offset = 0;
+        loop {
+            let mut readdir = Encoder::new(message::TREADDIR, TAG);
+            readdir
+                .u32(fid)
+                .u64(offset)
+                .u32(self.msize - IO_HEADER_SIZE);
+            let reply = self.call(&mut readdir)?;
+            let mut entries = Decoder::new(Decoder::new(&reply).data()?);
+            if entries.is_empty() {
+                break;
+            }
+
+            while !entries.is_empty() {
+                let _qid = entries.qid()?;
+                offset = entries.u64()?;
+                let _ty = entries.u8()?;
+                let name = entries.str()?;
+                if name != "." && name != ".." {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+
+        // listings only have the types of the entries, so look up the rest
+        names
+            .into_iter()
+            .map(|name| {
+                let (entry, _) = self.walk(fid, &[&name])?;
+                let metadata = self.getattr(entry);
+                self.clunk(entry);
+                Ok((name, metadata?))
+            })
+            .collect()
+    }
+
+    /// Removes the entry `name` from the directory `parent`.
+    ///
+    /// # Arguments
+    /// `parent`: The fid of the directory.  
+    /// `name`: The name of the entry.  
+    /// `unlink_flags`: `AT_REMOVEDIR` to remove a directory, or zero to remove a file.  
+    fn unlink(&mut self, parent: u32, name: &str, unlink_flags: u32) -> crate::Result<()> {
+        let mut unlinkat = Encoder::new(message::TUNLINKAT, TAG);
+        unlinkat.u32(parent).str(name).u32(unlink_flags);
+        self.call(&mut unlinkat)?;
+        Ok(())
+    }
+}
+
+/// A connection to a server over TCP or a Unix socket.
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixStream),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A file open on the server, which is read and written in place.
+struct NinePFile {
+    connection: Arc<Mutex<Connection>>,
+    fid: u32,
+    /// The most bytes a single read or write transfers.
+    io_size: u32,
+    position: u64,
+    append: bool,
+}
+
+impl File for NinePFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        self.connection.lock().getattr(self.fid)
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        // only the size is set, so the mode, owners and times are zero
+        let mut setattr = Encoder::new(message::TSETATTR, TAG);
+        setattr
+            .u32(self.fid)
+            .u32(SETATTR_SIZE)
+            .u32(0)
+            .u32(0)
+            .u32(0)
+            .u64(len)
+            .u64(0)
+            .u64(0)
+            .u64(0)
+            .u64(0);
+        self.connection.lock().call(&mut setattr)?;
+        Ok(())
+    }
+}
+
+impl Read for NinePFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = buf.len().min(self.io_size as usize);
+        let mut read = Encoder::new(message::TREAD, TAG);
+        read.u32(self.fid).u64(self.position).u32(count as u32);
+        let reply = self.connection.lock().call(&mut read)?;
+
+        let data = Decoder::new(&reply).data()?;
+        let read_len = data.len().min(count);
+        buf[..read_len].copy_from_slice(&data[..read_len]);
+        self.position += read_len as u64;
+        Ok(read_len)
+    }
+}
+
+impl Seek for NinePFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.metadata()?.len().checked_add_signed(offset),
+        };
+        self.position =
+            position.ok_or_else(|| invalid_input("seek to a negative or overflowing position"))?;
+        Ok(self.position)
+    }
+}
+
+impl Write for NinePFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.append {
+            self.position = self.metadata()?.len();
+        }
+
+        let count = buf.len().min(self.io_size as usize);
+        let mut write = Encoder::new(message::TWRITE, TAG);
+        write.u32(self.fid).u64(self.position).data(&buf[..count]);
+        let reply = self.connection.lock().call(&mut write)?;
+
+        let written = (Decoder::new(&reply).u32()? as usize).min(count);
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for NinePFile {
+    fn drop(&mut self) {
+        self.connection.lock().clunk(self.fid);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::{File, Metadata, OpenOptions};
+    use crate::memory_fs::MemoryFS;
+    use crate::ninep::{
+        flags, message, read_message, write_message, Decoder, Encoder, Qid, QTDIR, S_IFDIR, S_IFREG,
+    };
+    use crate::ninep_fs::NinePFS;
+    use crate::util::test::{check_open_options, check_set_len, read_directory};
+    use crate::FileSystem;
+    use std::collections::HashMap;
+    use std::hash::{DefaultHasher, Hash, Hasher};
+    use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// What a fid of the test server refers to.
+    #[derive(Default)]
+    struct Fid {
+        path: String,
+        file: Option<Box<dyn File>>,
+        listing: Vec<(String, Metadata)>,
+    }
+
+    /// Serves `fs` over 9P on TCP. Returns the address of the server.
+    fn serve(fs: MemoryFS) -> SocketAddr {
+        let fs = Arc::new(fs);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let fs = fs.clone();
+                thread::spawn(move || session(&fs, stream.unwrap()));
+            }
+        });
+
+        addr
+    }
+
+    /// Serves a single connection until it's closed.
+    fn session<S: Read + Write>(fs: &MemoryFS, mut stream: S) {
+        let mut fids: HashMap<u32, Fid> = HashMap::new();
+        while let Ok((ty, tag, body)) = read_message(&mut stream, u32::MAX) {
+            let mut reply = Encoder::new(ty + 1, tag);
+            if let Err(err) = handle(fs, &mut fids, ty, &body, &mut reply) {
+                let errno = match err.kind() {
+                    ErrorKind::NotFound => 2,
+                    ErrorKind::AlreadyExists => 17,
+                    ErrorKind::InvalidInput => 22,
+                    _ => 5,
+                };
+                reply = Encoder::new(message::RLERROR, tag);
+                reply.u32(errno);
+            }
+            write_message(&mut stream, &mut reply).unwrap();
+        }
+    }
+
+    /// Returns the metadata of the entry at `path`, since the memory filesystem has none for its root.
+    fn metadata(fs: &MemoryFS, path: &str) -> crate::Result<Metadata> {
+        match path {
+            "" => Ok(Metadata::directory()),
+            path => fs.metadata(path),
+        }
+    }
+
+    /// Returns the qid of the entry with `metadata` at `path`.
+    fn qid(path: &str, metadata: &Metadata) -> Qid {
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        Qid {
+            ty: if metadata.is_directory() { QTDIR } else { 0 },
+            version: 0,
+            path: hasher.finish(),
+        }
+    }
+
+    /// Handles a request, encoding the body of its reply.
+    fn handle(
+        fs: &MemoryFS,
+        fids: &mut HashMap<u32, Fid>,
+        ty: u8,
+        body: &[u8],
+        reply: &mut Encoder,
+    ) -> crate::Result<()> {
+        let mut request = Decoder::new(body);
+        let join = |parent: &str, name: &str| match parent {
+            "" => name.to_owned(),
+            parent => format!("{parent}/{name}"),
+        };
+        let path = |fids: &HashMap<u32, Fid>, fid| {
+            fids.get(&fid)
+                .map(|fid| fid.path.clone())
+                .ok_or_else(|| std::io::Error::from(ErrorKind::InvalidInput))
+        };
+
+        match ty {
+            message::TVERSION => {
+                let msize = request.u32()?;
+                reply.u32(msize.min(8192)).str(request.str()?);
+            }
+            message::TATTACH => {
+                fids.insert(request.u32()?, Fid::default());
+                reply.qid(&qid("", &Metadata::directory()));
+            }
+            message::TWALK => {
+                let (fid, new_fid) = (request.u32()?, request.u32()?);
+                let mut current = path(fids, fid)?;
+                let count = request.u16()?;
+                let mut qids = Vec::new();
+                for _ in 0..count {
+                    let next = join(&current, request.str()?);
+                    let Ok(metadata) = metadata(fs, &next) else {
+                        break;
+                    };
+                    qids.push(qid(&next, &metadata));
+                    current = next;
+                }
+                if count > 0 && qids.is_empty() {
+                    return Err(ErrorKind::NotFound.into());
+                }
+                if qids.len() == count as usize {
+                    fids.insert(
+                        new_fid,
+                        Fid {
+                            path: current,
+                            ..Fid::default()
+                        },
+                    );
+                }
+                reply.u16(qids.len() as u16);
+                for qid in qids {
+                    reply.qid(&qid);
+                }
+            }
+            message::TLOPEN | message::TLCREATE => {
+                let fid = request.u32()?;
+                let mut path = path(fids, fid)?;
+                if ty == message::TLCREATE {
+                    path = join(&path, request.str()?);
+                }
+                let open_flags = request.u32()?;
+                let metadata = match metadata(fs, &path) {
+                    Ok(metadata) if metadata.is_directory() => {
+                        fids.get_mut(&fid).unwrap().listing = read_directory(fs, &path)
+                            .into_iter()
+                            .map(|(entry, metadata)| {
+                                let name = entry.rsplit('/').next().unwrap().to_owned();
+                                (name, metadata)
+                            })
+                            .collect();
+                        metadata
+                    }
+                    metadata => {
+                        let options = OpenOptions {
+                            read: true,
+                            write: open_flags & (flags::O_WRONLY | flags::O_RDWR) != 0,
+                            truncate: open_flags & flags::O_TRUNC != 0,
+                            create: open_flags & flags::O_CREAT != 0,
+                            ..OpenOptions::default()
+                        };
+                        if metadata.is_err() && !options.create {
+                            return Err(ErrorKind::NotFound.into());
+                        }
+                        let file = fs.open_file_options(&path, &options)?;
+                        let fid = fids.get_mut(&fid).unwrap();
+                        fid.file = Some(file);
+                        fid.path.clone_from(&path);
+                        Metadata::file(0)
+                    }
+                };
+                reply.qid(&qid(&path, &metadata)).u32(0);
+            }
+            message::TGETATTR => {
+                let fid = request.u32()?;
+                let path = path(fids, fid)?;
+                let metadata = match &fids[&fid].file {
+                    Some(file) => file.metadata()?,
+                    None => metadata(fs, &path)?,
+                };
+                let mode = if metadata.is_directory() {
+                    S_IFDIR | 0o755
+                } else {
+                    S_IFREG | 0o644
+                };
+                reply
+                    .u64(request.u64()?)
+                    .qid(&qid(&path, &metadata))
+                    .u32(mode)
+                    .u32(0)
+                    .u32(0)
+                    .u64(1)
+                    .u64(0)
+                    .u64(metadata.len());
+                // the block size and count, and the times of access, modification, change and birth
+                for value in [
+                    512,
+                    metadata.len().div_ceil(512),
+                    0,
+                    0,
+                    1_000,
+                    5,
+                    0,
+                    0,
+                    0,
+                    0,
+                ] {
+                    reply.u64(value);
+                }
+                reply.u64(0).u64(0);
+            }
+            message::TSETATTR => {
+                // only sizes of open files are set
+                let fid = request.u32()?;
+                let (_valid, _mode, _uid, _gid) = (
+                    request.u32()?,
+                    request.u32()?,
+                    request.u32()?,
+                    request.u32()?,
+                );
+                let file = fids.get_mut(&fid).unwrap().file.as_mut().unwrap();
+                file.set_len(request.u64()?)?;
+            }
+            message::TREADDIR => {
+                let (fid, offset, count) = (request.u32()?, request.u64()?, request.u32()?);
+                let mut entries = Encoder::new(0, 0);
+                let mut len = 0;
+                for (index, (name, metadata)) in
+                    fids[&fid].listing.iter().enumerate().skip(offset as usize)
+                {
+                    let entry_len = 13 + 8 + 1 + 2 + name.len();
+                    if len + entry_len > count as usize {
+                        break;
+                    }
+                    len += entry_len;
+                    entries
+                        .qid(&qid(name, metadata))
+                        .u64(index as u64 + 1)
+                        .u8(0)
+                        .str(name);
+                }
+                reply.data(&entries.finish()[7..]);
+            }
+            message::TREAD => {
+                let (fid, offset, count) = (request.u32()?, request.u64()?, request.u32()?);
+                let file = fids.get_mut(&fid).unwrap().file.as_mut().unwrap();
+                file.seek(SeekFrom::Start(offset))?;
+                let mut data = vec![0; count as usize];
+                let read_len = file.read(&mut data)?;
+                reply.data(&data[..read_len]);
+            }
+            message::TWRITE => {
+                let (fid, offset) = (request.u32()?, request.u64()?);
+                let file = fids.get_mut(&fid).unwrap().file.as_mut().unwrap();
+                file.seek(SeekFrom::Start(offset))?;
+                let data = request.data()?;
+                file.write_all(data)?;
+                reply.u32(data.len() as u32);
+            }
+            message::TCLUNK => {
+                fids.remove(&request.u32()?);
+            }
+            message::TMKDIR => {
+                let path = join(&path(fids, request.u32()?)?, request.str()?);
+                fs.create_dir(&path)?;
+                reply.qid(&qid(&path, &Metadata::directory()));
+            }
+            message::TUNLINKAT => {
+                let path = join(&path(fids, request.u32()?)?, request.str()?);
+                if request.u32()? & flags::AT_REMOVEDIR != 0 {
+                    fs.remove_dir(&path)?;
+                } else {
+                    fs.remove_file(&path)?;
+                }
+            }
+            message::TRENAMEAT => {
+                let from = join(&path(fids, request.u32()?)?, request.str()?);
+                let to = join(&path(fids, request.u32()?)?, request.str()?);
+                fs.rename(&from, &to)?;
+            }
+            message::TSTATFS => {
+                reply.u32(0).u32(4096).u64(100).u64(40).u64(30);
+                reply.u64(0).u64(0).u64(0).u32(255);
+            }
+            _ => return Err(ErrorKind::Unsupported.into()),
+        }
+
+        Ok(())
+    }
+
+    /// Checks reading and writing through `fs`, which is served from an empty memory filesystem.
+    fn check(fs: &NinePFS) {
+        fs.create_dir("dir").unwrap();
+        fs.create_dir_all("dir/a/b").unwrap();
+        write!(fs.create_file("dir/file").unwrap(), "hello").unwrap();
+
+        let mut file = fs
+            .open_file_options("dir/file", &OpenOptions::default().append(true))
+            .unwrap();
+        write!(file, " world").unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 11);
+        drop(file);
+
+        let mut file = fs.open_file("dir/file").unwrap();
+        file.seek(SeekFrom::Start(6)).unwrap();
+        assert_eq!(file.read_into_string().unwrap(), "world");
+        drop(file);
+
+        let dir = read_directory(fs, "dir");
+        itertools::assert_equal(dir.keys(), vec!["dir/a", "dir/file"]);
+        assert!(dir["dir/a"].is_directory());
+        assert_eq!(dir["dir/file"].len(), 11);
+        assert_eq!(dir["dir/file"].mode, Some(0o644));
+
+        fs.rename("dir/file", "dir/a/moved").unwrap();
+        assert_eq!(fs.metadata("dir/a/moved").unwrap().len(), 11);
+        assert_eq!(
+            fs.open_file("dir/file").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            fs.open_file("dir/a").err().unwrap().kind(),
+            ErrorKind::InvalidInput
+        );
+        fs.remove_file("dir/a/moved").unwrap();
+        fs.remove_dir("dir/a/b").unwrap();
+        itertools::assert_equal(read_directory(fs, "dir/a").keys(), Vec::<&str>::new());
+        assert_eq!(fs.stats().unwrap().available_space, 30 * 4096);
+    }
+
+    #[test]
+    fn tcp() {
+        let fs = NinePFS::connect(serve(MemoryFS::default()), "user", "").unwrap();
+        check(&fs);
+
+        // paths deeper than a single walk are walked in parts
+        let mut deep = String::from("deep");
+        fs.create_dir(&deep).unwrap();
+        for n in 0..20 {
+            deep = format!("{deep}/{n}");
+            fs.create_dir(&deep).unwrap();
+        }
+        assert!(fs.metadata(&deep).unwrap().is_directory());
+        assert_eq!(
+            fs.metadata(&format!("{deep}/missing"))
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix() {
+        let dir = std::env::temp_dir().join(format!("ninep-{}", std::process::id()));
+        let _ = std::fs::remove_file(&dir);
+        let listener = std::os::unix::net::UnixListener::bind(&dir).unwrap();
+        thread::spawn(move || session(&MemoryFS::default(), listener.accept().unwrap().0));
+
+        check(&NinePFS::connect_unix(&dir, "user", "").unwrap());
+        std::fs::remove_file(&dir).unwrap();
+    }
+
+    #[test]
+    fn open_options() {
+        let fs = NinePFS::connect(serve(MemoryFS::default()), "user", "").unwrap();
+        check_open_options(&fs, "options");
+    }
+
+    #[test]
+    fn set_len() {
+        let fs = NinePFS::connect(serve(MemoryFS::default()), "user", "").unwrap();
+        check_set_len(&fs, "set_len");
+    }
+}
@@ -0,0 +1,448 @@
+use crate::file::{DirEntry, File, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::{
+    component_iter, is_a_directory, not_a_directory, not_found, not_supported, read_only,
+};
+use crate::FileSystem;
+use ntfs::indexes::NtfsFileNameIndex;
+use ntfs::structured_values::NtfsFileNamespace;
+use ntfs::{Ntfs, NtfsAttributeType, NtfsFile, NtfsReadSeek, NtfsTime};
+use parking_lot::Mutex;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The number of seconds between the NTFS epoch in 1601 and the Unix epoch.
+const EPOCH_DIFFERENCE: u64 = 11644473600;
+
+/// A read-only filesystem on an NTFS partition image, backed by the `ntfs` crate. Names are looked up
+/// case-insensitively, and the metadata files in the root such as `$MFT` are listed like any other file.
+///
+/// Directory listings report the sizes and times recorded in the directory index, rather than loading every file
+/// record. Files are read from their unnamed data stream. Alternate data streams are listed with `streams` and opened with
+/// `open_stream`.
+pub struct NtfsFS<T: Read + Seek> {
+    volume: Arc<Mutex<Volume<T>>>,
+}
+
+/// A mounted volume and the image it's read from.
+struct Volume<T> {
+    ntfs: Ntfs,
+    disk: T,
+}
+
+impl<T: Read + Seek> NtfsFS<T> {
+    /// Mounts the NTFS partition in `disk`.
+    ///
+    /// # Arguments
+    /// `disk`: The partition image.  
+    pub fn new(mut disk: T) -> crate::Result<Self> {
+        let mut ntfs = Ntfs::new(&mut disk)?;
+        ntfs.read_upcase_table(&mut disk)?;
+
+        Ok(Self {
+            volume: Arc::new(Mutex::new(Volume { ntfs, disk })),
+        })
+    }
+
+    /// Returns the name of the volume, if it has one.
+    pub fn volume_name(&self) -> crate::Result<Option<String>> {
+        let Volume { ntfs, disk } = &mut *self.volume.lock();
+        match ntfs.volume_name(disk) {
+            Some(name) => Ok(Some(name?.name().to_string_lossy())),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the names of the alternate data streams of the file or directory at `path`, in the order they're stored.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file or directory.  
+    pub fn streams(&self, path: &str) -> crate::Result<Vec<String>> {
+        let Volume { ntfs, disk } = &mut *self.volume.lock();
+        let file = find_file(ntfs, disk, &normalize_and_relativize(path))?;
+
+        let mut streams = Vec::new();
+        let mut attributes = file.attributes();
+        while let Some(item) = attributes.next(disk) {
+            let item = item?;
+            let attribute = item.to_attribute()?;
+            if attribute.ty()? == NtfsAttributeType::Data && attribute.name_length() > 0 {
+                streams.push(attribute.name()?.to_string_lossy());
+            }
+        }
+
+        Ok(streams)
+    }
+
+    /// Opens the alternate data stream `stream` of the file or directory at `path` for reading. An empty name opens the
+    /// unnamed data stream, like `open_file`.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file or directory.  
+    /// `stream`: The name of the stream, without a leading colon.  
+    pub fn open_stream(&self, path: &str, stream: &str) -> crate::Result<Box<dyn File>>
+    where
+        T: 'static,
+    {
+        let Volume { ntfs, disk } = &mut *self.volume.lock();
+        let file = find_file(ntfs, disk, &normalize_and_relativize(path))?;
+        let len = stream_len(&file, disk, stream)?;
+        let modified = modified(&file);
+
+        Ok(Box::new(NtfsFileHandle {
+            volume: self.volume.clone(),
+            record: file.file_record_number(),
+            stream: stream.to_owned(),
+            position: 0,
+            metadata: Metadata {
+                modified,
+                ..Metadata::file(len)
+            },
+        }))
+    }
+}
+
+impl<T: Read + Seek + 'static> FileSystem for NtfsFS<T> {
+    fn create_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        let Volume { ntfs, disk } = &mut *self.volume.lock();
+        let file = find_file(ntfs, disk, &normalize_and_relativize(path))?;
+        file_metadata(&file, disk)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        // ensure we only want to read
+        options.validate()?;
+        if options.writable() {
+            return Err(read_only());
+        }
+        if self.metadata(path)?.is_directory() {
+            return Err(is_a_directory());
+        }
+
+        self.open_stream(path, "")
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let directory = normalize_and_relativize(path);
+        let Volume { ntfs, disk } = &mut *self.volume.lock();
+        let file = find_file(ntfs, disk, &directory)?;
+        if !file.is_directory() {
+            return Err(not_a_directory());
+        }
+
+        let index = file.directory_index(disk)?;
+        let mut entries = index.entries();
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next(disk) {
+            let entry = entry?;
+            let Some(file_name) = entry.key() else {
+                continue;
+            };
+            let file_name = file_name?;
+
+            // short names are aliases of the long names, and the root lists itself as `.`
+            let name = file_name.name().to_string_lossy();
+            if file_name.namespace() == NtfsFileNamespace::Dos || name == "." {
+                continue;
+            }
+
+            // the index repeats the size and time of each entry, which saves loading every file record
+            let metadata = if file_name.is_directory() {
+                Metadata::directory()
+            } else {
+                Metadata::file(file_name.data_size())
+            };
+            files.push(Ok(DirEntry {
+                path: directory.join(name),
+                metadata: Metadata {
+                    modified: system_time(file_name.modification_time()),
+                    accessed: system_time(file_name.access_time()),
+                    created: system_time(file_name.creation_time()),
+                    ..metadata
+                },
+            }));
+        }
+
+        Ok(Box::new(files.into_iter()))
+    }
+
+    fn remove_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn remove_file(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+}
+
+/// Finds the file or directory at `path` by walking the directory indexes from the root.
+///
+/// # Arguments
+/// `ntfs`: The volume.  
+/// `disk`: The partition image.  
+/// `path`: The normalized path of the file.  
+fn find_file<'n, T: Read + Seek>(
+    ntfs: &'n Ntfs,
+    disk: &mut T,
+    path: &Path,
+) -> crate::Result<NtfsFile<'n>> {
+    let mut file = ntfs.root_directory(disk)?;
+    for name in component_iter(path) {
+        if !file.is_directory() {
+            return Err(not_a_directory());
+        }
+
+        let index = file.directory_index(disk)?;
+        let mut finder = index.finder();
+        let entry =
+            NtfsFileNameIndex::find(&mut finder, ntfs, disk, name).ok_or_else(not_found)??;
+        let next = entry.to_file(ntfs, disk)?;
+        drop(index);
+        file = next;
+    }
+
+    Ok(file)
+}
+
+/// Returns the metadata of a file or directory.
+///
+/// # Arguments
+/// `file`: The file record.  
+/// `disk`: The partition image.  
+fn file_metadata<T: Read + Seek>(file: &NtfsFile, disk: &mut T) -> crate::Result<Metadata> {
+    let metadata = if file.is_directory() {
+        Metadata::directory()
+    } else {
+        Metadata::file(stream_len(file, disk, "")?)
+    };
+
+    let info = file.info().ok();
+    Ok(Metadata {
+        modified: modified(file),
+        accessed: info
+            .as_ref()
+            .and_then(|info| system_time(info.access_time())),
+        created: info.and_then(|info| system_time(info.creation_time())),
+        ..metadata
+    })
+}
+
+/// Returns the length of the data stream `stream` of a file.
+///
+/// # Arguments
+/// `file`: The file record.  
+/// `disk`: The partition image.  
+/// `stream`: The name of the stream, which is empty for the unnamed stream.  
+fn stream_len<T: Read + Seek>(file: &NtfsFile, disk: &mut T, stream: &str) -> crate::Result<u64> {
+    match file.data(disk, stream) {
+        Some(item) => Ok(item?.to_attribute()?.value_length()),
+        // some metadata files have no unnamed stream, so they read as empty
+        None if stream.is_empty() => Ok(0),
+        None => Err(not_found()),
+    }
+}
+
+/// Returns the last modification time of a file, from its standard information.
+///
+/// # Arguments
+/// `file`: The file record.  
+fn modified(file: &NtfsFile) -> Option<SystemTime> {
+    system_time(file.info().ok()?.modification_time())
+}
+
+/// Converts an NTFS timestamp to a `SystemTime`, or `None` if it's before the Unix epoch.
+///
+/// # Arguments
+/// `time`: The timestamp.  
+fn system_time(time: NtfsTime) -> Option<SystemTime> {
+    // timestamps count 100 nanosecond intervals
+    let timestamp = time.nt_timestamp();
+    let seconds = (timestamp / 10_000_000).checked_sub(EPOCH_DIFFERENCE)?;
+    Some(SystemTime::UNIX_EPOCH + Duration::new(seconds, (timestamp % 10_000_000) as u32 * 100))
+}
+
+/// A data stream of a file on an NTFS volume. The file record is reloaded for each read, because it can't be borrowed
+/// past the lock on the volume.
+struct NtfsFileHandle<T> {
+    volume: Arc<Mutex<Volume<T>>>,
+    record: u64,
+    stream: String,
+    position: u64,
+    metadata: Metadata,
+}
+
+impl<T: Read + Seek> File for NtfsFileHandle<T> {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(self.metadata.clone())
+    }
+}
+
+impl<T: Read + Seek> Read for NtfsFileHandle<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.metadata.len() {
+            return Ok(0);
+        }
+
+        let Volume { ntfs, disk } = &mut *self.volume.lock();
+        let file = ntfs.file(disk, self.record)?;
+        let item = file.data(disk, &self.stream).ok_or_else(not_found)??;
+        let attribute = item.to_attribute()?;
+        let mut value = attribute.value(disk)?;
+        value.seek(disk, SeekFrom::Start(self.position))?;
+
+        let read = value.read(disk, buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<T: Read + Seek> Seek for NtfsFileHandle<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.metadata.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Seek to a negative offset"))?;
+        Ok(self.position)
+    }
+}
+
+impl<T: Read + Seek> Write for NtfsFileHandle<T> {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(not_supported())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(not_supported())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::{FileType, OpenOptions};
+    use crate::ntfs_fs::NtfsFS;
+    use crate::util::test::read_directory;
+    use crate::FileSystem;
+    use std::fs::File;
+    use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom};
+    use std::time::{Duration, UNIX_EPOCH};
+    use xz::read::XzDecoder;
+
+    fn ntfs_fs() -> NtfsFS<Cursor<Vec<u8>>> {
+        let mut image = Vec::new();
+        XzDecoder::new(File::open("test/ntfs.img.xz").unwrap())
+            .read_to_end(&mut image)
+            .unwrap();
+        NtfsFS::new(Cursor::new(image)).unwrap()
+    }
+
+    #[test]
+    fn read() {
+        let ntfs_fs = ntfs_fs();
+        assert_eq!(ntfs_fs.volume_name().unwrap().as_deref(), Some("mylabel"));
+
+        assert_eq!(
+            ntfs_fs
+                .open_file("/File-With-12345")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "12345"
+        );
+        let contents = ntfs_fs
+            .open_file("1000-bytes-file")
+            .unwrap()
+            .read_into_string()
+            .unwrap();
+        assert_eq!(contents, "12345".repeat(200));
+
+        // the hole of a sparse file reads as zeroes
+        let mut file = ntfs_fs.open_file("sparse-file").unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 500005);
+        file.seek(SeekFrom::Start(499999)).unwrap();
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, b"\x0011111");
+
+        assert_eq!(
+            ntfs_fs.metadata("empty-file").unwrap().modified,
+            // 2021-01-01 13:37 in the timezone the image was created in
+            Some(UNIX_EPOCH + Duration::from_secs(1609504620))
+        );
+
+        assert_eq!(
+            ntfs_fs.open_file("missing").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert!(ntfs_fs.open_file("many_subdirs").is_err());
+        assert!(ntfs_fs
+            .open_file_options("file-with-12345", &OpenOptions::default().write(true))
+            .is_err());
+        assert!(ntfs_fs.create_dir("new").is_err());
+    }
+
+    #[test]
+    fn read_dir() {
+        let ntfs_fs = ntfs_fs();
+
+        let root = read_directory(&ntfs_fs, "");
+        for name in ["$MFT", "1000-bytes-file", "empty-file", "many_subdirs"] {
+            assert!(root.contains_key(name), "{name}");
+        }
+        assert!(!root.contains_key("."));
+        assert_eq!(root["many_subdirs"].file_type, FileType::Directory);
+        assert_eq!(root["file-with-12345"].len(), 5);
+
+        // large directories spill out of the index root
+        let subdirs = read_directory(&ntfs_fs, "many_subdirs");
+        assert_eq!(subdirs.len(), 512);
+        assert!(subdirs.contains_key("many_subdirs/512"));
+        assert!(ntfs_fs.exists("many_subdirs/256").unwrap());
+        assert!(ntfs_fs.read_dir("empty-file").is_err());
+    }
+
+    #[test]
+    fn streams() {
+        let ntfs_fs = ntfs_fs();
+
+        // security descriptors are kept in a named stream
+        assert_eq!(ntfs_fs.streams("$Secure").unwrap(), vec!["$SDS"]);
+        let mut descriptors = ntfs_fs.open_stream("$secure", "$sds").unwrap();
+        assert!(descriptors.metadata().unwrap().len() > 0);
+        assert_eq!(
+            descriptors.read_into_vec().unwrap().len() as u64,
+            descriptors.metadata().unwrap().len()
+        );
+
+        assert!(ntfs_fs.streams("file-with-12345").unwrap().is_empty());
+        assert_eq!(
+            ntfs_fs
+                .open_stream("file-with-12345", "")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "12345"
+        );
+        assert_eq!(
+            ntfs_fs
+                .open_stream("file-with-12345", "missing")
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::NotFound
+        );
+    }
+}
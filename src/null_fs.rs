@@ -0,0 +1,296 @@
+use crate::file::{DirEntry, File, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::{already_exists, invalid_input, not_found};
+use crate::FileSystem;
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// A filesystem that discards every write and reads from a synthetic directory tree of zero-filled files, so that an
+/// application can measure its own overhead apart from the cost of a real backend.
+///
+/// Creating, removing, copying and renaming always succeed without changing the tree, and files opened for writing
+/// accept and drop everything written to them. Nothing is locked, so operations never block each other.
+#[derive(Clone)]
+pub struct NullFS {
+    entries: HashMap<String, Metadata>,
+    children: HashMap<String, Vec<String>>,
+}
+
+impl NullFS {
+    /// Creates a new null filesystem with an empty root.
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::from([(String::new(), Metadata::directory())]),
+            children: HashMap::default(),
+        }
+    }
+
+    /// Adds a synthetic directory and its parents to the tree.
+    ///
+    /// # Arguments
+    /// `path`: The path of the directory.  
+    pub fn dir(mut self, path: &str) -> Self {
+        self.insert(path, Metadata::directory());
+        self
+    }
+
+    /// Adds a synthetic file of zeros and its parent directories to the tree. A file replaces any entry at its path.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file.  
+    /// `len`: The length of the file.  
+    pub fn file(mut self, path: &str, len: u64) -> Self {
+        self.insert(path, Metadata::file(len));
+        self
+    }
+
+    /// Adds a synthetic tree below the root, in which every directory down to `depth` levels has `dirs` directories
+    /// named `dir_<n>` and `files` files named `file_<n>`.
+    ///
+    /// # Arguments
+    /// `depth`: The number of levels of directories.  
+    /// `dirs`: The number of directories in each directory above the last level.  
+    /// `files`: The number of files in each directory.  
+    /// `len`: The length of each file.  
+    pub fn tree(mut self, depth: usize, dirs: usize, files: usize, len: u64) -> Self {
+        let mut level = vec![String::new()];
+        for current_depth in 0..=depth {
+            let mut next_level = Vec::new();
+            for dir in level {
+                for n in 0..files {
+                    self.insert(&format!("{dir}/file_{n}"), Metadata::file(len));
+                }
+                if current_depth < depth {
+                    for n in 0..dirs {
+                        let child = format!("{dir}/dir_{n}");
+                        self.insert(&child, Metadata::directory());
+                        next_level.push(child);
+                    }
+                }
+            }
+            level = next_level;
+        }
+
+        self
+    }
+
+    /// Inserts an entry and its parent directories into the tree.
+    ///
+    /// # Arguments
+    /// `path`: The path of the entry.  
+    /// `metadata`: The metadata of the entry.  
+    fn insert(&mut self, path: &str, metadata: Metadata) {
+        let key = entry_key(path);
+        let mut child = key.clone();
+        self.entries.insert(key, metadata);
+
+        // link the entry into its parents, creating those that are missing
+        while !child.is_empty() {
+            let (parent, name) = child.rsplit_once('/').unwrap_or(("", &child));
+            let names = self.children.entry(parent.to_owned()).or_default();
+            if !names.iter().any(|existing| existing == name) {
+                names.push(name.to_owned());
+            }
+            match self.entries.get(parent) {
+                Some(existing) if existing.is_directory() => break,
+                _ => {
+                    self.entries
+                        .insert(parent.to_owned(), Metadata::directory());
+                }
+            }
+            child = parent.to_owned();
+        }
+    }
+}
+
+impl Default for NullFS {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileSystem for NullFS {
+    fn create_dir(&self, _path: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.entries
+            .get(&entry_key(path))
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        let len = match self.entries.get(&entry_key(path)) {
+            Some(metadata) if metadata.is_directory() => {
+                return Err(invalid_input("path is a directory"))
+            }
+            Some(_) if options.create_new => return Err(already_exists()),
+            Some(_) if options.truncate => 0,
+            Some(metadata) => metadata.len(),
+            None if options.create || options.create_new => 0,
+            None => return Err(not_found()),
+        };
+
+        Ok(Box::new(NullFile { len, position: 0 }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let key = entry_key(path);
+        if !self.metadata(&key)?.is_directory() {
+            return Err(not_found());
+        }
+
+        let entries: Vec<_> = self
+            .children
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .filter_map(|name| {
+                // entries replaced by files leave their names behind
+                let child = if key.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{key}/{name}")
+                };
+                let metadata = self.entries.get(&child)?.clone();
+                Some(Ok(DirEntry {
+                    path: child.into(),
+                    metadata,
+                }))
+            })
+            .collect();
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn remove_dir(&self, _path: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn remove_file(&self, _path: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn copy_file(&self, from: &str, _to: &str) -> crate::Result<u64> {
+        Ok(self.metadata(from)?.len())
+    }
+
+    fn rename(&self, _from: &str, _to: &str) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the key of the entry at `path` in the tree, which is its normalized path.
+///
+/// # Arguments
+/// `path`: The virtual path.  
+fn entry_key(path: &str) -> String {
+    normalize_and_relativize(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// A file that reads as zeros and discards writes.
+struct NullFile {
+    len: u64,
+    position: u64,
+}
+
+impl Read for NullFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_len = buf
+            .len()
+            .min(self.len.saturating_sub(self.position) as usize);
+        buf[..read_len].fill(0);
+        self.position += read_len as u64;
+        Ok(read_len)
+    }
+}
+
+impl Seek for NullFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+        };
+        self.position =
+            position.ok_or_else(|| invalid_input("seek to a negative or overflowing position"))?;
+        Ok(self.position)
+    }
+}
+
+impl Write for NullFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl File for NullFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(Metadata::file(self.len))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::null_fs::NullFS;
+    use crate::util::test::read_directory;
+    use crate::FileSystem;
+    use std::io::{ErrorKind, Write};
+
+    #[test]
+    fn synthetic_tree() {
+        let fs = NullFS::new().tree(2, 2, 3, 4).file("a/b/c", 10);
+        itertools::assert_equal(
+            read_directory(&fs, "").keys(),
+            vec!["a", "dir_0", "dir_1", "file_0", "file_1", "file_2"],
+        );
+        itertools::assert_equal(
+            read_directory(&fs, "dir_1/dir_0").keys(),
+            vec![
+                "dir_1/dir_0/file_0",
+                "dir_1/dir_0/file_1",
+                "dir_1/dir_0/file_2",
+            ],
+        );
+        assert_eq!(fs.walk_dir("").unwrap().count(), 2 + 4 + 3 * 7 + 3);
+        assert!(fs.metadata("a/b").unwrap().is_directory());
+        assert_eq!(
+            fs.open_file("a/b/c").unwrap().read_into_vec().unwrap(),
+            vec![0; 10]
+        );
+        assert_eq!(
+            fs.open_file("missing").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn discard_writes() {
+        let fs = NullFS::new().file("file", 4);
+        let mut file = fs.create_file("new").unwrap();
+        file.write_all(b"discarded").unwrap();
+        drop(file);
+        fs.create_dir("dir").unwrap();
+        fs.remove_file("file").unwrap();
+
+        itertools::assert_equal(read_directory(&fs, "").keys(), vec!["file"]);
+        assert_eq!(
+            fs.open_file("file").unwrap().read_into_vec().unwrap(),
+            [0; 4]
+        );
+        assert!(fs.open_file("new").is_err());
+    }
+}
@@ -0,0 +1,806 @@
+use crate::cloud_drive::{
+    convert_error, parse_time, Body, Client, DriveFile, TokenProvider, Upload,
+};
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::{already_exists, component_iter, invalid_input, not_found, not_supported, path};
+use crate::FileSystem;
+use serde_json::{json, Value};
+use std::io;
+use std::io::{Cursor, ErrorKind, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The URL of the Microsoft Graph API.
+const DEFAULT_ENDPOINT: &str = "https://graph.microsoft.com/v1.0";
+/// The size of the chunks large files are uploaded in, which must be a multiple of 320 KiB.
+const DEFAULT_CHUNK_SIZE: usize = 32 * 320 * 1024;
+/// The properties of an item that are requested.
+const ITEM_FIELDS: &str = "id,name,size,folder,lastModifiedDateTime,createdDateTime";
+
+/// A read-write filesystem on a OneDrive or SharePoint document library, through the Microsoft Graph API. Items are
+/// addressed by their paths, so each operation takes a single request or a few.
+///
+/// Files are downloaded when they're opened, and uploaded when they're flushed or dropped. Files larger than the
+/// chunk size are uploaded in chunks through an upload session. Deleted items go to the recycle bin.
+///
+/// Errors uploading a file that's dropped are lost, so files should be flushed before they're dropped to see them.
+pub struct OneDriveFS {
+    client: Client,
+    endpoint: String,
+    /// The path of the drive, relative to the endpoint.
+    drive: String,
+    chunk_size: usize,
+}
+
+/// A file or folder on the drive.
+struct Item {
+    id: String,
+    name: String,
+    metadata: Metadata,
+    /// The number of items in a folder.
+    children: u64,
+}
+
+impl OneDriveFS {
+    /// Creates a filesystem on the OneDrive of the user the tokens are issued to.
+    ///
+    /// # Arguments
+    /// `tokens`: The provider of access tokens with a `Files` scope.  
+    pub fn new<T: TokenProvider + 'static>(tokens: T) -> Self {
+        Self::with_endpoint(DEFAULT_ENDPOINT, tokens)
+    }
+
+    /// Creates a filesystem on a drive served at `endpoint` instead of by Microsoft Graph.
+    ///
+    /// # Arguments
+    /// `endpoint`: The URL the Graph API is served at, including its version.  
+    /// `tokens`: The provider of access tokens.  
+    pub fn with_endpoint<T: TokenProvider + 'static>(endpoint: &str, tokens: T) -> Self {
+        Self {
+            client: Client {
+                agent: ureq::Agent::new(),
+                tokens: Arc::new(tokens),
+            },
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            drive: "/me/drive".to_owned(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// # Arguments
+    /// `agent`: The agent used to issue requests.  
+    pub fn agent(mut self, agent: ureq::Agent) -> Self {
+        self.client.agent = agent;
+        self
+    }
+
+    /// # Arguments
+    /// `drive_id`: The identifier of the drive to use instead of the user's own, such as a SharePoint document
+    /// library.  
+    pub fn drive(mut self, drive_id: &str) -> Self {
+        self.drive = format!("/drives/{}", path::encode_component(drive_id));
+        self
+    }
+
+    /// # Arguments
+    /// `chunk_size`: The size of the chunks files are uploaded in once they outgrow a chunk, which Graph requires to
+    /// be a multiple of 320 KiB.  
+    pub fn upload_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Returns the URL of the item at a path, followed by `suffix`.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path of the item.  
+    /// `suffix`: The suffix, such as `/children`, which is empty for the item itself.  
+    fn item_url(&self, path: &Path, suffix: &str) -> String {
+        let names: Vec<_> = component_iter(path).map(path::encode_component).collect();
+        match names.is_empty() {
+            true => format!("{}{}/root{suffix}", self.endpoint, self.drive),
+            false => format!(
+                "{}{}/root:/{}:{suffix}",
+                self.endpoint,
+                self.drive,
+                names.join("/")
+            ),
+        }
+    }
+
+    /// Fetches the item at a path.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path of the item.  
+    fn item(&self, path: &Path) -> crate::Result<Item> {
+        let item = self.client.call_json(
+            "GET",
+            &self.item_url(path, ""),
+            &[("$select", ITEM_FIELDS)],
+            Body::Empty,
+        )?;
+        parse_item(&item).ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Malformed item"))
+    }
+
+    /// Fetches the folder a path is in, failing if it's missing or a file.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path of the entry in the folder.  
+    fn parent(&self, path: &Path) -> crate::Result<Item> {
+        let parent = self.item(path.parent().unwrap_or(Path::new("")))?;
+        if !parent.metadata.is_directory() {
+            return Err(not_found());
+        }
+        Ok(parent)
+    }
+}
+
+impl FileSystem for OneDriveFS {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        let path = normalize_and_relativize(path);
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            // the root always exists
+            return Err(already_exists());
+        };
+        self.parent(&path)?;
+
+        let folder = json!({
+            "name": name,
+            "folder": {},
+            "@microsoft.graph.conflictBehavior": "fail",
+        });
+        self.client.call(
+            "POST",
+            &self.item_url(path.parent().unwrap_or(Path::new("")), "/children"),
+            &[],
+            Body::Json(&folder),
+        )?;
+        Ok(())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        Ok(self.item(&normalize_and_relativize(path))?.metadata)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        let path = normalize_and_relativize(path);
+        let content_url = self.item_url(&path, "/content");
+        let writable = options.writable();
+
+        let (contents, modified) = match self.item(&path) {
+            Ok(_) if options.create_new => return Err(already_exists()),
+            Ok(item) => {
+                if item.metadata.is_directory() {
+                    return Err(invalid_input("path is a directory"));
+                }
+
+                let contents = if writable && options.truncate {
+                    // the file is truncated from the moment it's opened, even if it's never written
+                    if item.metadata.len() > 0 {
+                        self.client
+                            .call("PUT", &content_url, &[], Body::Bytes(&[]))?;
+                    }
+                    Vec::new()
+                } else {
+                    self.client.download(&content_url, &[])?
+                };
+                (contents, item.metadata.modified)
+            }
+            Err(err)
+                if err.kind() == ErrorKind::NotFound && (options.create || options.create_new) =>
+            {
+                // uploads create any missing folders, which files shouldn't
+                self.parent(&path)?;
+                self.client
+                    .call("PUT", &content_url, &[], Body::Bytes(&[]))?;
+                (Vec::new(), None)
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mut contents = Cursor::new(contents);
+        if options.append {
+            contents.seek(SeekFrom::End(0))?;
+        }
+        let upload = writable.then(|| {
+            let client = self.client.clone();
+            let session_url = self.item_url(&path, "/createUploadSession");
+            let chunk_size = self.chunk_size;
+            Box::new(move |contents: &[u8]| {
+                if contents.len() <= chunk_size {
+                    client.call("PUT", &content_url, &[], Body::Bytes(contents))?;
+                    Ok(())
+                } else {
+                    upload_session(&client, &session_url, chunk_size, contents)
+                }
+            }) as Upload
+        });
+
+        Ok(Box::new(DriveFile {
+            contents,
+            modified,
+            upload,
+            append: options.append,
+            dirty: false,
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let directory = normalize_and_relativize(path);
+        if !self.item(&directory)?.metadata.is_directory() {
+            return Err(invalid_input("path is not a directory"));
+        }
+
+        let mut items = Vec::new();
+        let mut page = self.client.call_json(
+            "GET",
+            &self.item_url(&directory, "/children"),
+            &[("$select", ITEM_FIELDS)],
+            Body::Empty,
+        )?;
+        loop {
+            items.extend(
+                page["value"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(parse_item),
+            );
+            let Some(next) = page["@odata.nextLink"].as_str() else {
+                break;
+            };
+            page = self.client.call_json("GET", next, &[], Body::Empty)?;
+        }
+
+        Ok(Box::new(items.into_iter().map(move |item| {
+            Ok(DirEntry {
+                path: directory.join(item.name),
+                metadata: item.metadata,
+            })
+        })))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        let path = normalize_and_relativize(path);
+        if component_iter(&path).next().is_none() {
+            return Err(not_supported());
+        }
+        let folder = self.item(&path)?;
+        if !folder.metadata.is_directory() {
+            return Err(invalid_input("path is not a directory"));
+        }
+        // deleting a folder would delete everything in it
+        if folder.children > 0 {
+            return Err(io::Error::new(
+                ErrorKind::DirectoryNotEmpty,
+                "Directory not empty",
+            ));
+        }
+
+        self.client
+            .call("DELETE", &self.item_url(&path, ""), &[], Body::Empty)?;
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        let path = normalize_and_relativize(path);
+        if self.item(&path)?.metadata.is_directory() {
+            return Err(invalid_input("path is a directory"));
+        }
+
+        self.client
+            .call("DELETE", &self.item_url(&path, ""), &[], Body::Empty)?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        let (from, to) = (normalize_and_relativize(from), normalize_and_relativize(to));
+        let item = self.item(&from)?;
+        let Some(to_name) = to.file_name().and_then(|name| name.to_str()) else {
+            return Err(already_exists());
+        };
+        let to_parent = self.parent(&to)?;
+
+        // an existing file is replaced, as it would be by a local rename
+        match self.item(&to) {
+            Ok(existing) if existing.id == item.id => return Ok(()),
+            Ok(existing) if existing.metadata.is_directory() => return Err(already_exists()),
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+
+        let update = json!({
+            "name": to_name,
+            "parentReference": { "id": to_parent.id },
+        });
+        self.client.call(
+            "PATCH",
+            &self.item_url(&from, ""),
+            &[("@microsoft.graph.conflictBehavior", "replace")],
+            Body::Json(&update),
+        )?;
+        Ok(())
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        let drive = self.client.call_json(
+            "GET",
+            &format!("{}{}", self.endpoint, self.drive),
+            &[("$select", "quota")],
+            Body::Empty,
+        )?;
+        let quota = &drive["quota"];
+        let free_space = quota["remaining"].as_u64().unwrap_or(0);
+        Ok(FileSystemStats {
+            total_space: quota["total"].as_u64().unwrap_or(0),
+            free_space,
+            available_space: free_space,
+            block_size: 1,
+        })
+    }
+}
+
+/// Parses a drive item.
+///
+/// # Arguments
+/// `item`: The drive item.  
+fn parse_item(item: &Value) -> Option<Item> {
+    let folder = &item["folder"];
+    let metadata = match folder.is_object() {
+        true => Metadata::directory(),
+        false => Metadata::file(item["size"].as_u64().unwrap_or(0)),
+    };
+
+    Some(Item {
+        id: item["id"].as_str()?.to_owned(),
+        name: item["name"].as_str()?.to_owned(),
+        metadata: Metadata {
+            modified: item["lastModifiedDateTime"].as_str().and_then(parse_time),
+            created: item["createdDateTime"].as_str().and_then(parse_time),
+            ..metadata
+        },
+        children: folder["childCount"].as_u64().unwrap_or(0),
+    })
+}
+
+/// Uploads a file in chunks through an upload session, replacing it.
+///
+/// # Arguments
+/// `client`: The client.  
+/// `session_url`: The URL upload sessions for the file are created at.  
+/// `chunk_size`: The size of the chunks.  
+/// `contents`: The contents of the file.  
+fn upload_session(
+    client: &Client,
+    session_url: &str,
+    chunk_size: usize,
+    contents: &[u8],
+) -> crate::Result<()> {
+    let session = json!({ "item": { "@microsoft.graph.conflictBehavior": "replace" } });
+    let session = client.call_json("POST", session_url, &[], Body::Json(&session))?;
+    let upload_url = session["uploadUrl"]
+        .as_str()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Missing upload URL"))?;
+
+    let mut start = 0;
+    for chunk in contents.chunks(chunk_size) {
+        // the upload URL is authorized by itself, and mustn't be sent a token
+        let range = format!(
+            "bytes {start}-{}/{}",
+            start + chunk.len() - 1,
+            contents.len()
+        );
+        if let Err(err) = client
+            .agent
+            .put(upload_url)
+            .set("Content-Range", &range)
+            .send_bytes(chunk)
+        {
+            let _ = client.agent.delete(upload_url).call();
+            return Err(convert_error(err));
+        }
+        start += chunk.len();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cloud_drive::test::serve;
+    use crate::file::{FileSystemStats, OpenOptions};
+    use crate::onedrive_fs::OneDriveFS;
+    use crate::util::test::{check_open_options, read_directory};
+    use crate::FileSystem;
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+    use std::io::{ErrorKind, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// An item on the test server.
+    struct ServedItem {
+        id: String,
+        /// The contents of a file, or `None` for a folder.
+        contents: Option<Vec<u8>>,
+    }
+
+    /// Serves a drive holding `items`, which are paths and contents or `None` for folders. Listings return at most two
+    /// items per page, and every request but chunk uploads must carry the access token `token`. Returns the
+    /// filesystem and the requests served.
+    fn onedrive_fs(items: &[(&str, Option<&str>)]) -> (OneDriveFS, Arc<Mutex<Vec<String>>>) {
+        let mut items: BTreeMap<String, ServedItem> = items
+            .iter()
+            .enumerate()
+            .map(|(n, (path, contents))| {
+                let item = ServedItem {
+                    id: n.to_string(),
+                    contents: contents.map(|contents| contents.as_bytes().to_vec()),
+                };
+                (path.to_string(), item)
+            })
+            .collect();
+        items.insert(
+            String::new(),
+            ServedItem {
+                id: "root".to_owned(),
+                contents: None,
+            },
+        );
+        let mut next_id = items.len();
+        let mut session: Option<(String, Vec<u8>)> = None;
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let served = requests.clone();
+
+        let endpoint = serve(move |url, request| {
+            let method = request.method.as_str();
+            served
+                .lock()
+                .unwrap()
+                .push(format!("{method} {}", request.path));
+            let ok = |value: Value| ("200 OK", String::new(), value.to_string().into_bytes());
+            let not_found = ("404 Not Found", String::new(), Vec::new());
+            let children = |items: &BTreeMap<String, ServedItem>, path: &str| {
+                items
+                    .keys()
+                    .filter(|child| {
+                        !child.is_empty()
+                            && child.rsplit_once('/').map_or("", |(parent, _)| parent) == path
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+            let resource = |items: &BTreeMap<String, ServedItem>, path: &str| {
+                let item = &items[path];
+                let mut resource = json!({
+                    "id": item.id,
+                    "name": path.rsplit('/').next().unwrap(),
+                    "lastModifiedDateTime": "2024-01-02T03:04:05Z",
+                });
+                match &item.contents {
+                    Some(contents) => resource["size"] = contents.len().into(),
+                    None => {
+                        resource["folder"] = json!({ "childCount": children(items, path).len() })
+                    }
+                }
+                resource
+            };
+
+            // chunks are uploaded without a token
+            if let Some(upload) = request.path.strip_prefix("/upload/") {
+                let Some((path, contents)) = &mut session else {
+                    return not_found;
+                };
+                assert_eq!(upload, path);
+                let range = request.headers["content-range"]
+                    .strip_prefix("bytes ")
+                    .unwrap();
+                let (range, total) = range.split_once('/').unwrap();
+                let start: usize = range.split_once('-').unwrap().0.parse().unwrap();
+                assert_eq!(start, contents.len());
+                contents.extend(request.body);
+                if contents.len() < total.parse().unwrap() {
+                    return ("202 Accepted", String::new(), b"{}".to_vec());
+                }
+                let (path, contents) = session.take().unwrap();
+                let id = items.get(&path).map_or_else(
+                    || {
+                        next_id += 1;
+                        next_id.to_string()
+                    },
+                    |item| item.id.clone(),
+                );
+                items.insert(
+                    path.clone(),
+                    ServedItem {
+                        id,
+                        contents: Some(contents),
+                    },
+                );
+                return ok(resource(&items, &path));
+            }
+            if request.headers.get("authorization").map(String::as_str) != Some("Bearer token") {
+                return ("401 Unauthorized", String::new(), Vec::new());
+            }
+
+            if let Some(rest) = request.path.strip_prefix("/next/") {
+                let path = rest.to_owned();
+                let start: usize = request.query["start"].parse().unwrap();
+                let children = children(&items, &path);
+                let mut page = json!({
+                    "value": children
+                        .iter()
+                        .skip(start)
+                        .take(2)
+                        .map(|child| resource(&items, child))
+                        .collect::<Vec<_>>(),
+                });
+                if children.len() > start + 2 {
+                    page["@odata.nextLink"] = format!(
+                        "{url}/next/{}?start={}",
+                        path.replace(' ', "%20"),
+                        start + 2
+                    )
+                    .into();
+                }
+                return ok(page);
+            }
+            let Some(rest) = request.path.strip_prefix("/me/drive") else {
+                return not_found;
+            };
+            if rest.is_empty() {
+                return ok(json!({ "quota": { "total": 1000, "remaining": 990 } }));
+            }
+            // items look like `/root`, `/root/children` or `/root:/path:/children`
+            let (path, suffix) = match rest.strip_prefix("/root:/") {
+                Some(rest) => rest.split_once(':').unwrap(),
+                None => ("", rest.strip_prefix("/root").unwrap()),
+            };
+            let path = path.to_owned();
+
+            match (method, suffix) {
+                ("GET", "") if items.contains_key(&path) => ok(resource(&items, &path)),
+                ("GET", "/children") => {
+                    let children = children(&items, &path);
+                    let mut page = json!({
+                        "value": children
+                            .iter()
+                            .take(2)
+                            .map(|child| resource(&items, child))
+                            .collect::<Vec<_>>(),
+                    });
+                    if children.len() > 2 {
+                        page["@odata.nextLink"] =
+                            format!("{url}/next/{}?start=2", path.replace(' ', "%20")).into();
+                    }
+                    ok(page)
+                }
+                ("GET", "/content") => {
+                    match items.get(&path).and_then(|item| item.contents.clone()) {
+                        Some(contents) => ("200 OK", String::new(), contents),
+                        None => not_found,
+                    }
+                }
+                ("PUT", "/content") => {
+                    let id = items.get(&path).map_or_else(
+                        || {
+                            next_id += 1;
+                            next_id.to_string()
+                        },
+                        |item| item.id.clone(),
+                    );
+                    items.insert(
+                        path.clone(),
+                        ServedItem {
+                            id,
+                            contents: Some(request.body),
+                        },
+                    );
+                    ok(resource(&items, &path))
+                }
+                ("POST", "/createUploadSession") => {
+                    session = Some((path.clone(), Vec::new()));
+                    ok(json!({ "uploadUrl": format!("{url}/upload/{}", path.replace(' ', "%20")) }))
+                }
+                ("POST", "/children") => {
+                    let folder = request.json();
+                    let name = folder["name"].as_str().unwrap();
+                    let child = match path.is_empty() {
+                        true => name.to_owned(),
+                        false => format!("{path}/{name}"),
+                    };
+                    if items.contains_key(&child) {
+                        return ("409 Conflict", String::new(), Vec::new());
+                    }
+                    next_id += 1;
+                    items.insert(
+                        child.clone(),
+                        ServedItem {
+                            id: next_id.to_string(),
+                            contents: None,
+                        },
+                    );
+                    ok(resource(&items, &child))
+                }
+                ("PATCH", "") if items.contains_key(&path) => {
+                    let update = request.json();
+                    let parent_id = update["parentReference"]["id"].as_str().unwrap();
+                    let (parent, _) = items.iter().find(|(_, item)| item.id == parent_id).unwrap();
+                    let name = update["name"].as_str().unwrap();
+                    let to = match parent.is_empty() {
+                        true => name.to_owned(),
+                        false => format!("{parent}/{name}"),
+                    };
+                    let item = items.remove(&path).unwrap();
+                    items.insert(to.clone(), item);
+                    ok(resource(&items, &to))
+                }
+                ("DELETE", "") if items.remove(&path).is_some() => {
+                    ("204 No Content", String::new(), Vec::new())
+                }
+                _ => not_found,
+            }
+        });
+
+        (
+            OneDriveFS::with_endpoint(&endpoint, "token".to_owned()),
+            requests,
+        )
+    }
+
+    #[test]
+    fn read() {
+        let (fs, _) = onedrive_fs(&[
+            ("readme", Some("readme")),
+            ("my docs", None),
+            ("my docs/a", Some("a")),
+            ("my docs/b", Some("bb")),
+            ("my docs/c d", Some("ccc")),
+            ("my docs/nested", None),
+        ]);
+
+        assert_eq!(
+            fs.open_file("my docs/c d")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "ccc"
+        );
+        let metadata = fs.metadata("my docs/b").unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert!(metadata.modified.is_some());
+        assert!(fs.metadata("").unwrap().is_directory());
+        assert!(fs.metadata("my docs/nested").unwrap().is_directory());
+        assert_eq!(
+            fs.metadata("missing").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        // the listing spans several pages
+        let docs = read_directory(&fs, "my docs");
+        itertools::assert_equal(
+            docs.keys(),
+            vec!["my docs/a", "my docs/b", "my docs/c d", "my docs/nested"],
+        );
+        assert!(docs["my docs/nested"].is_directory());
+        itertools::assert_equal(read_directory(&fs, "").keys(), vec!["my docs", "readme"]);
+        assert!(fs.read_dir("readme").is_err());
+
+        // requests without the token are rejected
+        let unauthorized = OneDriveFS::with_endpoint(
+            &serve(|_, _| ("401 Unauthorized", String::new(), Vec::new())),
+            "token".to_owned(),
+        );
+        assert_eq!(
+            unauthorized.metadata("readme").err().unwrap().kind(),
+            ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn write() {
+        let (fs, requests) = onedrive_fs(&[
+            ("readme", Some("readme")),
+            ("docs", None),
+            ("docs/file", Some("file")),
+        ]);
+        let fs = fs.upload_chunk_size(4);
+
+        let mut file = fs.create_file("docs/new").unwrap();
+        assert_eq!(fs.metadata("docs/new").unwrap().len(), 0);
+        write!(file, "new").unwrap();
+        file.flush().unwrap();
+        assert_eq!(fs.metadata("docs/new").unwrap().len(), 3);
+
+        // larger files are uploaded in chunks
+        requests.lock().unwrap().clear();
+        write!(file, " file").unwrap();
+        drop(file);
+        itertools::assert_equal(
+            requests.lock().unwrap().iter(),
+            &[
+                "POST /me/drive/root:/docs/new:/createUploadSession",
+                "PUT /upload/docs/new",
+                "PUT /upload/docs/new",
+            ],
+        );
+        assert_eq!(
+            fs.open_file("docs/new")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "new file"
+        );
+        assert_eq!(
+            fs.create_file("missing/new").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        let mut file = fs
+            .open_file_options("readme", &OpenOptions::default().append(true))
+            .unwrap();
+        write!(file, "!").unwrap();
+        drop(file);
+        assert_eq!(
+            fs.open_file("readme").unwrap().read_into_string().unwrap(),
+            "readme!"
+        );
+        drop(fs.create_file("readme").unwrap());
+        assert_eq!(fs.metadata("readme").unwrap().len(), 0);
+
+        // read-only files can't be written
+        assert!(write!(fs.open_file("docs/file").unwrap(), "file").is_err());
+
+        fs.create_dir("docs/dir").unwrap();
+        assert_eq!(
+            fs.create_dir("docs/dir").err().unwrap().kind(),
+            ErrorKind::AlreadyExists
+        );
+        assert!(fs.metadata("docs/dir").unwrap().is_directory());
+
+        // files replace the files they're renamed to
+        fs.rename("docs/new", "docs/dir/renamed").unwrap();
+        fs.rename("docs/file", "readme").unwrap();
+        assert_eq!(
+            fs.open_file("readme").unwrap().read_into_string().unwrap(),
+            "file"
+        );
+        itertools::assert_equal(read_directory(&fs, "docs").keys(), vec!["docs/dir"]);
+        itertools::assert_equal(
+            read_directory(&fs, "docs/dir").keys(),
+            vec!["docs/dir/renamed"],
+        );
+
+        assert_eq!(
+            fs.remove_dir("docs/dir").err().unwrap().kind(),
+            ErrorKind::DirectoryNotEmpty
+        );
+        assert!(fs.remove_file("docs/dir").is_err());
+        fs.remove_file("docs/dir/renamed").unwrap();
+        fs.remove_dir("docs/dir").unwrap();
+        assert!(read_directory(&fs, "docs").is_empty());
+        assert_eq!(
+            fs.remove_file("docs/dir").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        assert_eq!(
+            fs.stats().unwrap(),
+            FileSystemStats {
+                total_space: 1000,
+                free_space: 990,
+                available_space: 990,
+                block_size: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn open_options() {
+        let (fs, _) = onedrive_fs(&[]);
+        check_open_options(&fs, "options");
+    }
+}
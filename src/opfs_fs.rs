@@ -0,0 +1,330 @@
+use crate::file::{DirEntry, File, Metadata, OpenOptions};
+use crate::memory_fs::MemoryFS;
+use crate::tree::normalize_and_relativize;
+use crate::util::journal::Journal;
+use crate::util::web::{global, js_error, property, settle};
+use crate::util::{component_iter, invalid_path};
+use crate::FileSystem;
+use js_sys::{AsyncIterator, IteratorNext, Uint8Array};
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{
+    FileSystemDirectoryHandle, FileSystemFileHandle, FileSystemGetDirectoryOptions,
+    FileSystemGetFileOptions, FileSystemHandle, FileSystemHandleKind, FileSystemRemoveOptions,
+    FileSystemWritableFileStream, StorageManager,
+};
+
+/// A change to the origin private file system.
+enum Change {
+    CreateDir(PathBuf),
+    /// Writes the file with its contents at the time the change is persisted.
+    Write(PathBuf),
+    Remove(PathBuf),
+}
+
+/// The state shared by the filesystem, its open files and the task persisting their changes.
+struct Shared {
+    memory: MemoryFS,
+    journal: Journal<Change>,
+}
+
+impl Shared {
+    /// Queues a change to the origin private file system, starting a writer if there isn't one.
+    ///
+    /// # Arguments
+    /// `shared`: The shared state.  
+    /// `change`: The change.  
+    fn persist(shared: &Arc<Self>, change: Change) {
+        if shared.journal.push(change) {
+            spawn_local(write_changes(shared.clone()));
+        }
+    }
+}
+
+/// A read-write filesystem in the origin private file system (OPFS) of the browser, which persists across page loads.
+///
+/// The OPFS is asynchronous and filesystems aren't, so the whole tree is read into memory when it's opened. Reads are
+/// served from memory, and changes are made to memory at once and to the OPFS in the background, in order. Files are
+/// written to the OPFS when they're closed. `flush` waits until every change has reached the OPFS.
+///
+/// # Example
+/// ```no_run
+/// use virtual_filesystem::opfs_fs::OpfsFS;
+/// use virtual_filesystem::FileSystem;
+/// use std::io::Write;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let fs = OpfsFS::open().await?;
+/// fs.create_file("notes.txt")?.write_all(b"hello")?;
+/// fs.flush().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct OpfsFS {
+    shared: Arc<Shared>,
+}
+
+impl OpfsFS {
+    /// Opens the origin private file system and reads it into memory.
+    pub async fn open() -> crate::Result<Self> {
+        let memory = MemoryFS::default();
+        let mut directories = vec![(root().await?, PathBuf::new())];
+        while let Some((directory, path)) = directories.pop() {
+            let entries: AsyncIterator = directory.values().unchecked_into();
+            loop {
+                let next: IteratorNext = settle(entries.next().map_err(js_error)?)
+                    .await?
+                    .unchecked_into();
+                if next.done() {
+                    break;
+                }
+
+                let handle: FileSystemHandle = next.value().unchecked_into();
+                let path = path.join(handle.name());
+                let path_str = path.to_str().ok_or_else(invalid_path)?;
+                if handle.kind() == FileSystemHandleKind::Directory {
+                    memory.create_dir(path_str)?;
+                    directories.push((handle.unchecked_into(), path));
+                } else {
+                    let file: web_sys::File =
+                        settle(handle.unchecked_into::<FileSystemFileHandle>().get_file())
+                            .await?
+                            .unchecked_into();
+                    let contents = Uint8Array::new(&settle(file.array_buffer()).await?).to_vec();
+                    memory.create_file(path_str)?.write_all(&contents)?;
+                }
+            }
+        }
+
+        Ok(Self {
+            shared: Arc::new(Shared {
+                memory,
+                journal: Journal::default(),
+            }),
+        })
+    }
+
+    /// Waits until every change has reached the origin private file system. Returns the first error that writing a
+    /// change failed with since the last flush, after which changes may have been lost.
+    pub async fn flush(&self) -> crate::Result<()> {
+        self.shared.journal.flush().await
+    }
+}
+
+impl FileSystem for OpfsFS {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.shared.memory.create_dir(path)?;
+        Shared::persist(
+            &self.shared,
+            Change::CreateDir(normalize_and_relativize(path)),
+        );
+        Ok(())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.shared.memory.metadata(path)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        let file = self.shared.memory.open_file_options(path, options)?;
+        if !options.writable() {
+            return Ok(file);
+        }
+
+        Ok(Box::new(OpfsFile {
+            inner: file,
+            path: normalize_and_relativize(path),
+            shared: self.shared.clone(),
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        self.shared.memory.read_dir(path)
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.shared.memory.remove_dir(path)?;
+        Shared::persist(&self.shared, Change::Remove(normalize_and_relativize(path)));
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.shared.memory.remove_file(path)?;
+        Shared::persist(&self.shared, Change::Remove(normalize_and_relativize(path)));
+        Ok(())
+    }
+}
+
+/// A file opened for writing, which is written to the origin private file system when it's closed.
+struct OpfsFile {
+    inner: Box<dyn File>,
+    path: PathBuf,
+    shared: Arc<Shared>,
+}
+
+impl Read for OpfsFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for OpfsFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl Write for OpfsFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl File for OpfsFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        self.inner.metadata()
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        self.inner.set_len(len)
+    }
+}
+
+impl Drop for OpfsFile {
+    fn drop(&mut self) {
+        Shared::persist(&self.shared, Change::Write(std::mem::take(&mut self.path)));
+    }
+}
+
+/// Writes the changes in the journal to the origin private file system until there are none left.
+///
+/// # Arguments
+/// `shared`: The shared state.  
+async fn write_changes(shared: Arc<Shared>) {
+    loop {
+        let changes = shared.journal.take();
+        if changes.is_empty() {
+            return;
+        }
+        for change in changes {
+            if let Err(err) = write(&shared.memory, change).await {
+                shared.journal.fail(err);
+            }
+        }
+    }
+}
+
+/// Writes a change to the origin private file system.
+///
+/// # Arguments
+/// `memory`: The tree in memory, which files are written from.  
+/// `change`: The change.  
+async fn write(memory: &MemoryFS, change: Change) -> crate::Result<()> {
+    let root = root().await?;
+    match change {
+        Change::CreateDir(path) => directory(&root, component_iter(&path), true)
+            .await
+            .map(|_| ()),
+        Change::Write(path) => {
+            // the file was removed or replaced by a directory since, which a later change persists
+            let contents = match memory
+                .open_file(path.to_str().ok_or_else(invalid_path)?)
+                .and_then(|mut file| file.read_into_vec())
+            {
+                Ok(contents) => contents,
+                Err(err)
+                    if err.kind() == ErrorKind::NotFound
+                        || err.kind() == ErrorKind::IsADirectory =>
+                {
+                    return Ok(())
+                }
+                Err(err) => return Err(err),
+            };
+
+            let (parent, name) = split(&path)?;
+            let parent = directory(&root, component_iter(parent), true).await?;
+            let options = FileSystemGetFileOptions::new();
+            options.set_create(true);
+            let file: FileSystemFileHandle =
+                settle(parent.get_file_handle_with_options(name, &options))
+                    .await?
+                    .unchecked_into();
+            let stream: FileSystemWritableFileStream =
+                settle(file.create_writable()).await?.unchecked_into();
+            settle(stream.write_with_u8_array(&contents).map_err(js_error)?).await?;
+            settle(stream.close()).await.map(|_| ())
+        }
+        // an entry that was never persisted has nothing to remove
+        Change::Remove(path) => match remove(&root, &path).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        },
+    }
+}
+
+/// Removes an entry of the origin private file system, with its contents if it's a directory.
+///
+/// # Arguments
+/// `root`: The root directory.  
+/// `path`: The path of the entry.  
+async fn remove(root: &FileSystemDirectoryHandle, path: &Path) -> crate::Result<()> {
+    let (parent, name) = split(path)?;
+    let parent = directory(root, component_iter(parent), false).await?;
+    let options = FileSystemRemoveOptions::new();
+    options.set_recursive(true);
+    settle(parent.remove_entry_with_options(name, &options)).await?;
+    Ok(())
+}
+
+/// Returns the root directory of the origin private file system.
+async fn root() -> crate::Result<FileSystemDirectoryHandle> {
+    let navigator: JsValue = global("navigator")?;
+    let storage: StorageManager = property(&navigator, "storage")?;
+    Ok(settle(storage.get_directory()).await?.unchecked_into())
+}
+
+/// Returns a directory of the origin private file system.
+///
+/// # Arguments
+/// `root`: The root directory.  
+/// `components`: The components of the path of the directory.  
+/// `create`: Whether the directory and its parents are created if they don't exist.  
+async fn directory<'a>(
+    root: &FileSystemDirectoryHandle,
+    components: impl Iterator<Item = &'a str>,
+    create: bool,
+) -> crate::Result<FileSystemDirectoryHandle> {
+    let options = FileSystemGetDirectoryOptions::new();
+    options.set_create(create);
+    let mut directory = root.clone();
+    for component in components {
+        directory = settle(directory.get_directory_handle_with_options(component, &options))
+            .await?
+            .unchecked_into();
+    }
+    Ok(directory)
+}
+
+/// Splits a path into its parent and file name.
+///
+/// # Arguments
+/// `path`: The path.  
+fn split(path: &Path) -> crate::Result<(&Path, &str)> {
+    let parent = path.parent().ok_or_else(invalid_path)?;
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(invalid_path)?;
+    Ok((parent, name))
+}
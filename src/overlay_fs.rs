@@ -0,0 +1,408 @@
+use crate::file::{DirEntry, File, Metadata, OpenOptions};
+use crate::roc_fs::RocFS;
+use crate::tree::normalize_and_relativize;
+use crate::util::{already_exists, create_dir_all, invalid_path, not_found};
+use crate::FileSystem;
+use itertools::Itertools;
+use parking_lot::RwLock;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::io;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// A writable filesystem layered over read-only ones. Reads are served by the upper layer if it
+/// contains the entry, and by the lower layers otherwise. Writing to a file from a lower layer
+/// copies it up to the upper layer first, so the lower layers are never modified.
+///
+/// Removing an entry from a lower layer records an in-memory whiteout that hides it, and
+/// everything within it, from the lower layers. Entries created in its place afterwards only
+/// contain what is written to the upper layer. Whiteouts aren't persisted, so the upper layer can
+/// be a `MemoryFS`.
+pub struct OverlayFS<FS> {
+    upper: FS,
+    lower: RocFS,
+    whiteouts: RwLock<HashSet<PathBuf>>,
+}
+
+impl<FS: FileSystem> OverlayFS<FS> {
+    /// Creates a new overlay filesystem.
+    ///
+    /// # Arguments
+    /// `upper`: The writable layer, which receives all writes.  
+    /// `lower`: The read-only layers.  
+    pub fn new(upper: FS, lower: RocFS) -> Self {
+        Self {
+            upper,
+            lower,
+            whiteouts: RwLock::default(),
+        }
+    }
+
+    /// Returns the writable layer.
+    pub fn upper(&self) -> &FS {
+        &self.upper
+    }
+
+    /// Returns the read-only layers.
+    pub fn lower(&self) -> &RocFS {
+        &self.lower
+    }
+
+    /// Returns true if the entry at `path` in the lower layers is hidden by a whiteout.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn is_whited_out(&self, path: &Path) -> bool {
+        let whiteouts = self.whiteouts.read();
+        path.ancestors()
+            .any(|ancestor| whiteouts.contains(ancestor))
+    }
+
+    /// Returns the metadata of the entry at `path` in the upper layer, if present.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn upper_metadata(&self, path: &str) -> crate::Result<Option<Metadata>> {
+        // the root is a directory in every layer
+        if path.is_empty() {
+            return Ok(Some(Metadata::directory()));
+        }
+
+        match self.upper.metadata(path) {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns the metadata of the entry at `path` in the lower layers, if present and not hidden
+    /// by a whiteout.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn lower_metadata(&self, path: &Path) -> crate::Result<Option<Metadata>> {
+        if self.is_whited_out(path) {
+            return Ok(None);
+        }
+        if path.as_os_str().is_empty() {
+            return Ok(Some(Metadata::directory()));
+        }
+
+        match self.lower.metadata(path.to_str().ok_or_else(invalid_path)?) {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Creates the parent directories of `path` in the upper layer, if they aren't already there.
+    /// The parent must be a directory in the overlay.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn copy_up_parent(&self, path: &Path) -> crate::Result<()> {
+        let parent = path.parent().ok_or_else(invalid_path)?;
+        let parent = parent.to_str().ok_or_else(invalid_path)?;
+        if parent.is_empty() {
+            return Ok(());
+        }
+        if !self.metadata(parent)?.is_directory() {
+            return Err(not_found());
+        }
+
+        create_dir_all(&self.upper, parent)
+    }
+
+    /// Copies the file at `path` from the lower layers to the upper layer.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    /// `truncate`: True if the contents don't need to be copied, because they'd be truncated.  
+    fn copy_up_file(&self, path: &Path, truncate: bool) -> crate::Result<()> {
+        self.copy_up_parent(path)?;
+
+        let path = path.to_str().ok_or_else(invalid_path)?;
+        let mut upper_file = self.upper.create_file(path)?;
+        if !truncate {
+            io::copy(&mut self.lower.open_file(path)?, &mut upper_file)?;
+        }
+
+        Ok(())
+    }
+
+    /// Hides the entry at `path` in the lower layers, if there is one.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn white_out(&self, path: &Path) -> crate::Result<()> {
+        if self.lower_metadata(path)?.is_some() {
+            self.whiteouts.write().insert(path.to_owned());
+        }
+
+        Ok(())
+    }
+}
+
+impl<FS: FileSystem> FileSystem for OverlayFS<FS> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        let normalized_path = normalize_and_relativize(path);
+        if self.exists(path)? {
+            return Err(already_exists());
+        }
+
+        self.copy_up_parent(&normalized_path)?;
+        self.upper
+            .create_dir(normalized_path.to_str().ok_or_else(invalid_path)?)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        let normalized_path = normalize_and_relativize(path);
+        if let Some(metadata) =
+            self.upper_metadata(normalized_path.to_str().ok_or_else(invalid_path)?)?
+        {
+            return Ok(metadata);
+        }
+        self.lower_metadata(&normalized_path)?.ok_or_else(not_found)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        let normalized_path = normalize_and_relativize(path);
+        let path = normalized_path.to_str().ok_or_else(invalid_path)?;
+        options.validate()?;
+
+        if self.upper_metadata(path)?.is_none() {
+            match self.lower_metadata(&normalized_path)? {
+                Some(metadata) if !metadata.is_file() => return Err(not_found()),
+                Some(_) if options.create_new => return Err(already_exists()),
+                Some(_) if !options.writable() => {
+                    return self.lower.open_file_options(path, options)
+                }
+                Some(_) => self.copy_up_file(&normalized_path, options.truncate)?,
+                None if options.create || options.create_new => {
+                    self.copy_up_parent(&normalized_path)?
+                }
+                None => return Err(not_found()),
+            }
+        }
+
+        self.upper.open_file_options(path, options)
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let normalized_path = normalize_and_relativize(path);
+        let path = normalized_path.to_str().ok_or_else(invalid_path)?;
+        if !self.metadata(path)?.is_directory() {
+            return Err(not_found());
+        }
+
+        let mut entries = match self.upper_metadata(path)? {
+            Some(_) => self.upper.read_dir(path)?.try_collect::<_, Vec<_>, _>()?,
+            None => Vec::new(),
+        };
+
+        // entries in the upper layer shadow those with the same name in the lower layers
+        let mut names = entries
+            .iter()
+            .map(|entry| entry.path.file_name().map(OsStr::to_owned))
+            .collect::<HashSet<_>>();
+        if self
+            .lower_metadata(&normalized_path)?
+            .is_some_and(|metadata| metadata.is_directory())
+        {
+            for entry in self.lower.read_dir(path)? {
+                let entry = entry?;
+                let name = entry.path.file_name().map(OsStr::to_owned);
+                let hidden = name
+                    .as_ref()
+                    .is_some_and(|name| self.is_whited_out(&normalized_path.join(name)));
+                if !hidden && names.insert(name) {
+                    entries.push(entry);
+                }
+            }
+        }
+
+        Ok(Box::new(entries.into_iter().map(Ok)))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        let normalized_path = normalize_and_relativize(path);
+        let path = normalized_path.to_str().ok_or_else(invalid_path)?;
+        if !self.metadata(path)?.is_directory() {
+            return Err(not_found());
+        }
+        if self.read_dir(path)?.next().is_some() {
+            return Err(io::Error::new(
+                ErrorKind::DirectoryNotEmpty,
+                "Directory not empty",
+            ));
+        }
+
+        if self.upper_metadata(path)?.is_some() {
+            self.upper.remove_dir(path)?;
+        }
+        self.white_out(&normalized_path)
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        let normalized_path = normalize_and_relativize(path);
+        let path = normalized_path.to_str().ok_or_else(invalid_path)?;
+        if !self.metadata(path)?.is_file() {
+            return Err(not_found());
+        }
+
+        if self.upper_metadata(path)?.is_some() {
+            self.upper.remove_file(path)?;
+        }
+        self.white_out(&normalized_path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::{Metadata, OpenOptions};
+    use crate::memory_fs::MemoryFS;
+    use crate::overlay_fs::OverlayFS;
+    use crate::physical_fs::PhysicalFS;
+    use crate::roc_fs::RocFS;
+    use crate::util::test::{check_open_options, read_directory};
+    use crate::FileSystem;
+    use std::io::{ErrorKind, Write};
+
+    fn overlay_fs() -> OverlayFS<MemoryFS> {
+        let base = MemoryFS::default();
+        base.create_dir_all("textures/hd").unwrap();
+        write!(base.create_file("textures/hd/stone").unwrap(), "stone").unwrap();
+        write!(base.create_file("config").unwrap(), "base config").unwrap();
+
+        OverlayFS::new(
+            MemoryFS::default(),
+            RocFS::new(vec![
+                Box::new(base),
+                Box::new(PhysicalFS::new("test/folder_a")),
+            ]),
+        )
+    }
+
+    #[test]
+    fn read() {
+        let fs = overlay_fs();
+
+        itertools::assert_equal(
+            read_directory(&fs, "").keys(),
+            vec!["config", "file_a", "textures"],
+        );
+        assert_eq!(fs.metadata("textures/hd").unwrap(), Metadata::directory());
+        assert_eq!(
+            fs.open_file("/file_a").unwrap().read_into_string().unwrap(),
+            "file a"
+        );
+        assert!(!fs.exists("nonsense").unwrap());
+    }
+
+    #[test]
+    fn copy_up() {
+        let fs = overlay_fs();
+
+        // writes copy the file up, leaving the lower layers untouched
+        fs.open_file_options("textures/hd/stone", &OpenOptions::default().write(true))
+            .unwrap()
+            .write_all(b"S")
+            .unwrap();
+        write!(fs.create_file("config").unwrap(), "new").unwrap();
+        write!(fs.create_file("textures/hd/brick").unwrap(), "brick").unwrap();
+
+        assert_eq!(
+            fs.open_file("config").unwrap().read_into_string().unwrap(),
+            "new"
+        );
+        assert_eq!(
+            fs.lower()
+                .open_file("config")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "base config"
+        );
+        assert_eq!(
+            fs.open_file("textures/hd/stone")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "Stone"
+        );
+        assert!(fs.upper().exists("textures/hd/brick").unwrap());
+        assert!(!fs.lower().exists("textures/hd/brick").unwrap());
+        itertools::assert_equal(
+            read_directory(&fs, "textures/hd").keys(),
+            vec!["textures/hd/brick", "textures/hd/stone"],
+        );
+
+        assert!(fs.create_file("nonsense/file").is_err());
+        assert!(fs.create_dir("textures").is_err());
+        fs.create_dir("textures/sd").unwrap();
+        assert!(fs.upper().exists("textures/sd").unwrap());
+    }
+
+    #[test]
+    fn open_options() {
+        let fs = overlay_fs();
+        check_open_options(&fs, "textures/options");
+
+        // files of the lower layers already exist, and are copied up to be appended to
+        let create_new = OpenOptions::new().write(true).create_new(true);
+        assert_eq!(
+            fs.open_file_options("config", &create_new)
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::AlreadyExists
+        );
+        fs.open_file_options("config", &OpenOptions::new().append(true))
+            .unwrap()
+            .write_all(b"!")
+            .unwrap();
+        assert_eq!(
+            fs.open_file("config").unwrap().read_into_string().unwrap(),
+            "base config!"
+        );
+        assert!(fs.upper().exists("config").unwrap());
+    }
+
+    #[test]
+    fn whiteouts() {
+        let fs = overlay_fs();
+
+        fs.remove_file("file_a").unwrap();
+        assert!(!fs.exists("file_a").unwrap());
+        assert!(fs.remove_file("file_a").is_err());
+        assert!(fs.lower().exists("file_a").unwrap());
+
+        // a copied up file is removed from both layers
+        write!(fs.create_file("config").unwrap(), "new").unwrap();
+        fs.remove_file("config").unwrap();
+        assert!(!fs.exists("config").unwrap());
+
+        assert_eq!(
+            fs.remove_dir("textures").unwrap_err().kind(),
+            ErrorKind::DirectoryNotEmpty
+        );
+        fs.remove_file("textures/hd/stone").unwrap();
+        fs.remove_dir("textures/hd").unwrap();
+        fs.remove_dir("textures").unwrap();
+        assert!(read_directory(&fs, "").is_empty());
+
+        // recreated directories don't reveal the lower layers' contents
+        fs.create_dir_all("textures/hd").unwrap();
+        assert!(read_directory(&fs, "textures/hd").is_empty());
+        assert!(!fs.exists("textures/hd/stone").unwrap());
+        write!(fs.create_file("file_a").unwrap(), "new").unwrap();
+        assert_eq!(
+            fs.open_file("file_a").unwrap().read_into_string().unwrap(),
+            "new"
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use crate::file::{DirEntry, File, FileType, Metadata, OpenOptions};
+use crate::physical_fs::path_resolver::host_relative;
+use crate::tree::normalize_and_relativize;
+use crate::FileSystem;
+use cap_std::ambient_authority;
+use cap_std::fs::Dir;
+use cap_std::time::SystemTime;
+use std::path::{Path, PathBuf};
+
+/// A sandboxed physical filesystem that holds an open handle to its root, and resolves every path relative to that
+/// handle through `cap-std`. Unlike `SandboxedPhysicalFS`, paths aren't canonicalized before they're opened, so a
+/// symbolic link swapped in between resolving a path and opening it can't escape the root.
+///
+/// `cap_std::fs::Dir` implements `FileSystem` itself the same way, so directory handles that are already sandboxed
+/// can be mounted or layered directly.
+pub struct CapPhysicalFS {
+    root: Dir,
+}
+
+impl CapPhysicalFS {
+    /// Creates a new physical file system at the given root, opening a handle to it.
+    ///
+    /// # Arguments
+    /// `root`: The root directory on the host.  
+    pub fn new<P: AsRef<Path>>(root: P) -> crate::Result<Self> {
+        Ok(Self::from_dir(Dir::open_ambient_dir(
+            root,
+            ambient_authority(),
+        )?))
+    }
+
+    /// Creates a new physical file system from an open handle to its root.
+    ///
+    /// # Arguments
+    /// `root`: The handle to the root directory.  
+    pub fn from_dir(root: Dir) -> Self {
+        Self { root }
+    }
+
+    /// Returns the handle to the root directory.
+    pub fn root(&self) -> &Dir {
+        &self.root
+    }
+}
+
+impl FileSystem for CapPhysicalFS {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        FileSystem::create_dir(&self.root, path)
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        FileSystem::create_dir_with(&self.root, path, mode)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        FileSystem::metadata(&self.root, path)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        self.root.open_file_options(path, options)
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        FileSystem::read_dir(&self.root, path)
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        FileSystem::remove_dir(&self.root, path)
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        FileSystem::remove_file(&self.root, path)
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> crate::Result<u64> {
+        self.root.copy_file(from, to)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        FileSystem::rename(&self.root, from, to)
+    }
+}
+
+impl FileSystem for Dir {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        Dir::create_dir(self, host_path(path))
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        #[cfg_attr(not(unix), allow(unused_mut))]
+        let mut builder = cap_std::fs::DirBuilder::new();
+
+        #[cfg(unix)]
+        cap_std::fs::DirBuilderExt::mode(&mut builder, mode);
+        #[cfg(not(unix))]
+        let _ = mode;
+
+        Dir::create_dir_with(self, host_path(path), &builder)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        Dir::metadata(self, host_path(path)).map(convert_metadata)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        let mut cap_options = cap_std::fs::OpenOptions::new();
+        cap_options
+            .create(options.create)
+            .create_new(options.create_new)
+            .append(options.append)
+            .truncate(options.truncate)
+            .read(options.read)
+            .write(options.write);
+
+        #[cfg(unix)]
+        if let Some(mode) = options.mode {
+            cap_std::fs::OpenOptionsExt::mode(&mut cap_options, mode);
+        }
+
+        Dir::open_with(self, host_path(path), &cap_options)
+            .map::<Box<dyn File>, _>(|file| Box::new(file.into_std()))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let dir_path = normalize_and_relativize(path);
+        Ok(Box::new(Dir::read_dir(self, host_path(path))?.map(
+            move |entry| {
+                entry.and_then(|entry| {
+                    Ok(DirEntry {
+                        path: dir_path.join(entry.file_name()),
+                        metadata: convert_metadata(entry.metadata()?),
+                    })
+                })
+            },
+        )))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        Dir::remove_dir(self, host_path(path))
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        Dir::remove_file(self, host_path(path))
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> crate::Result<u64> {
+        Dir::copy(self, host_path(from), self, host_path(to))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        Dir::rename(self, host_path(from), self, host_path(to))
+    }
+}
+
+/// Converts a virtual path to a path relative to the root handle. Backtracking is left to `cap-std`, which rejects
+/// paths that escape the root.
+///
+/// # Arguments
+/// `path`: The virtual path.  
+fn host_path(path: &str) -> PathBuf {
+    let path = host_relative(path);
+    if path.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        path
+    }
+}
+
+/// Converts `cap-std` metadata to virtual metadata.
+///
+/// # Arguments
+/// `metadata`: The `cap-std` metadata.  
+fn convert_metadata(metadata: cap_std::fs::Metadata) -> Metadata {
+    let file_type = if metadata.is_dir() {
+        FileType::Directory
+    } else if metadata.is_file() {
+        FileType::File
+    } else {
+        FileType::Unknown
+    };
+
+    // directory sizes are host-specific, so report them as empty like every other filesystem
+    let len = if file_type == FileType::Directory {
+        0
+    } else {
+        metadata.len()
+    };
+    let mut converted = Metadata::new(file_type, len);
+    converted.modified = metadata.modified().ok().map(SystemTime::into_std);
+    converted.accessed = metadata.accessed().ok().map(SystemTime::into_std);
+    converted.created = metadata.created().ok().map(SystemTime::into_std);
+
+    #[cfg(unix)]
+    {
+        use cap_std::fs::MetadataExt;
+
+        converted.mode = Some(metadata.mode() & 0o7777);
+        converted.uid = Some(metadata.uid());
+        converted.gid = Some(metadata.gid());
+    }
+
+    converted
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::FileType;
+    use crate::mountable_fs::MountableFS;
+    use crate::physical_fs::CapPhysicalFS;
+    use crate::util::test::{check_open_options, read_directory};
+    use crate::FileSystem;
+    use cap_std::ambient_authority;
+    use cap_std::fs::Dir;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn cap_physical_fs() {
+        let root = std::env::temp_dir().join(format!("cap-{}", std::process::id()));
+        fs::create_dir_all(root.join("dir")).unwrap();
+        let cap_fs = CapPhysicalFS::new(&root).unwrap();
+
+        write!(cap_fs.create_file("/dir/file").unwrap(), "file").unwrap();
+        assert_eq!(
+            cap_fs
+                .open_file("dir/../dir/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "file"
+        );
+        let metadata = cap_fs.metadata("dir/file").unwrap();
+        assert_eq!((metadata.file_type, metadata.len), (FileType::File, 4));
+        assert!(metadata.modified.is_some());
+        assert!(cap_fs.metadata("").unwrap().is_directory());
+        itertools::assert_equal(read_directory(&cap_fs, "dir").keys(), vec!["dir/file"]);
+
+        cap_fs.rename("dir/file", "file").unwrap();
+        assert_eq!(cap_fs.copy_file("file", "dir/copy").unwrap(), 4);
+        cap_fs.remove_file("dir/copy").unwrap();
+        cap_fs.remove_dir("dir").unwrap();
+        itertools::assert_equal(read_directory(&cap_fs, "/").keys(), vec!["file"]);
+
+        // traversal out of the root
+        assert!(cap_fs.metadata("..").is_err());
+        assert!(cap_fs.create_file("dir/../../escaped").is_err());
+        #[cfg(unix)]
+        {
+            use std::io::ErrorKind;
+
+            std::os::unix::fs::symlink(std::env::temp_dir(), root.join("link")).unwrap();
+            let err = cap_fs.read_dir("link").err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn open_options() {
+        let root = std::env::temp_dir().join(format!("cap-options-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        check_open_options(&CapPhysicalFS::new(&root).unwrap(), "options");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn dir() {
+        let root = std::env::temp_dir().join(format!("cap-dir-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let dir = Dir::open_ambient_dir(&root, ambient_authority()).unwrap();
+        write!(FileSystem::create_file(&dir, "file").unwrap(), "file").unwrap();
+
+        // the handle can be mounted like any other filesystem
+        let mountable_fs = MountableFS::default();
+        mountable_fs.mount("/mnt", dir).unwrap();
+        assert_eq!(
+            mountable_fs
+                .open_file("mnt/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "file"
+        );
+        mountable_fs.create_dir("mnt/dir").unwrap();
+        assert!(root.join("dir").is_dir());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
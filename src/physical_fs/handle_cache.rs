@@ -0,0 +1,156 @@
+use crate::file::{File, Metadata};
+use crate::util::{invalid_input, read_only};
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A bounded cache of handles to files opened read-only. The least recently used handles are evicted first.
+#[derive(Default)]
+pub(super) struct HandleCache {
+    capacity: usize,
+    /// The cached handles by resolved path, from least to most recently used.
+    handles: VecDeque<(PathBuf, Arc<fs::File>)>,
+}
+
+impl HandleCache {
+    /// Sets the maximum number of cached handles, removing every cached handle.
+    ///
+    /// # Arguments
+    /// `capacity`: The maximum number of cached handles. Zero disables the cache.  
+    pub(super) fn set_capacity(&mut self, capacity: usize) {
+        // reads through cached handles are positional, which isn't supported everywhere
+        self.capacity = if cfg!(any(unix, windows)) {
+            capacity
+        } else {
+            0
+        };
+        self.handles.clear();
+    }
+
+    /// Returns true if handles are cached.
+    pub(super) fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Returns the handle to the file at `path`, opening and caching it if it isn't already cached.
+    ///
+    /// # Arguments
+    /// `path`: The resolved path of the file.  
+    pub(super) fn open(&mut self, path: &Path) -> crate::Result<PooledFile> {
+        let file = match self
+            .handles
+            .iter()
+            .position(|(cached_path, _)| cached_path == path)
+        {
+            Some(index) => {
+                // move the handle to the most recently used position
+                let handle = self.handles.remove(index).unwrap();
+                let file = handle.1.clone();
+                self.handles.push_back(handle);
+                file
+            }
+            None => {
+                let file = Arc::new(fs::File::open(path)?);
+                if self.handles.len() == self.capacity {
+                    self.handles.pop_front();
+                }
+                self.handles.push_back((path.to_owned(), file.clone()));
+                file
+            }
+        };
+
+        Ok(PooledFile { file, pos: 0 })
+    }
+
+    /// Removes the handles to the file at `path` and everything beneath it.
+    ///
+    /// # Arguments
+    /// `path`: The resolved path.  
+    pub(super) fn invalidate(&mut self, path: &Path) {
+        self.handles
+            .retain(|(cached_path, _)| !cached_path.starts_with(path));
+    }
+
+    /// Removes every cached handle.
+    pub(super) fn clear(&mut self) {
+        self.handles.clear();
+    }
+}
+
+/// A read-only file sharing a cached handle. Reads are positional, so every file has its own cursor.
+pub(super) struct PooledFile {
+    file: Arc<fs::File>,
+    pos: u64,
+}
+
+impl File for PooledFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        self.file.metadata().map(Metadata::from)
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        self.file.set_len(len)
+    }
+}
+
+impl Read for PooledFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = read_at(&self.file, buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for PooledFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base_pos, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::Current(n) => (self.pos, n),
+            SeekFrom::End(n) => (self.file.metadata()?.len(), n),
+        };
+
+        if let Some(n) = base_pos.checked_add_signed(offset) {
+            self.pos = n;
+            Ok(n)
+        } else {
+            Err(invalid_input(
+                "Invalid seek to a negative or overflowing position",
+            ))
+        }
+    }
+}
+
+impl Write for PooledFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads from `file` at `offset` without using its cursor.
+#[cfg(unix)]
+fn read_at(file: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+/// Reads from `file` at `offset` without using its cursor.
+#[cfg(windows)]
+fn read_at(file: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    // this moves the cursor, but every pooled file tracks its own
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+/// Reads from `file` at `offset` without using its cursor.
+#[cfg(not(any(unix, windows)))]
+fn read_at(_file: &fs::File, _buf: &mut [u8], _offset: u64) -> io::Result<usize> {
+    Err(crate::util::not_supported())
+}
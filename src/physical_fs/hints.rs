@@ -0,0 +1,71 @@
+use crate::file::OpenOptions;
+use std::fs;
+
+/// Applies the hints in `options` that can only be applied to an open file. The rest are applied as flags when the
+/// file is opened.
+///
+/// # Arguments
+/// `file`: The open file.  
+/// `options`: The options the file was opened with.  
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub(super) fn apply(file: &fs::File, options: &OpenOptions) -> crate::Result<()> {
+    use crate::file::AccessPattern;
+    use std::io;
+    use std::os::fd::AsRawFd;
+
+    let advice = match options.access {
+        AccessPattern::Normal => return Ok(()),
+        AccessPattern::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+        AccessPattern::Random => libc::POSIX_FADV_RANDOM,
+    };
+
+    // SAFETY: the descriptor is owned by `file`, which outlives the call
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, advice) } {
+        0 => Ok(()),
+        err => Err(io::Error::from_raw_os_error(err)),
+    }
+}
+
+/// Applies the hints in `options` that can only be applied to an open file. The rest are applied as flags when the
+/// file is opened.
+///
+/// # Arguments
+/// `file`: The open file.  
+/// `options`: The options the file was opened with.  
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub(super) fn apply(file: &fs::File, options: &OpenOptions) -> crate::Result<()> {
+    use crate::file::AccessPattern;
+    use std::io;
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: the descriptor is owned by `file`, which outlives the calls
+    unsafe {
+        if options.direct && libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if options.access == AccessPattern::Random
+            && libc::fcntl(file.as_raw_fd(), libc::F_RDAHEAD, 0) == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies the hints in `options` that can only be applied to an open file. The rest are applied as flags when the
+/// file is opened.
+///
+/// # Arguments
+/// `file`: The open file.  
+/// `options`: The options the file was opened with.  
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+pub(super) fn apply(_file: &fs::File, _options: &OpenOptions) -> crate::Result<()> {
+    Ok(())
+}
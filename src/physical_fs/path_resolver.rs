@@ -1,7 +1,6 @@
-use crate::util::make_relative;
 use std::io;
 use std::io::ErrorKind;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 /// Resolves paths to their respective host paths.
 pub trait PathResolver {
@@ -10,37 +9,132 @@ pub trait PathResolver {
 }
 
 /// A resolver that ensures that paths have not been traversed, either through backtracking or symbolic links.
+/// Paths that don't exist yet are resolved through their deepest existing ancestor, so that they can be created.
 pub struct SandboxedPathResolver {}
 impl PathResolver for SandboxedPathResolver {
     fn resolve_path(root: &Path, path: &str) -> crate::Result<PathBuf> {
         // root is already normalized by `PhysicalFSImpl`
         let root = root.canonicalize()?;
-        let host_path = root.join(make_relative(path)).canonicalize()?;
+        let host_path =
+            canonicalize_existing(&root.join(resolve_backtracking(&root, &host_relative(path))))?;
 
         if !host_path.starts_with(root) {
-            return Err(io::Error::new(
-                ErrorKind::PermissionDenied,
-                "Traversal prevented",
-            ));
+            return Err(traversal_prevented());
         }
 
         Ok(host_path)
     }
 }
 
+/// Canonicalizes the deepest existing ancestor of `path`, and appends the components that don't exist yet. The
+/// components that don't exist can't backtrack, and dangling symbolic links are rejected, since the host would
+/// follow them to wherever they point when the path is created.
+///
+/// # Arguments
+/// `path`: The path to canonicalize.  
+fn canonicalize_existing(path: &Path) -> crate::Result<PathBuf> {
+    let mut missing_components = Vec::new();
+    let mut existing_path = path;
+
+    let canonical_path = loop {
+        match existing_path.canonicalize() {
+            Ok(canonical_path) => break canonical_path,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                // a path that can't be canonicalized but still has metadata is a dangling symbolic link
+                if existing_path.symlink_metadata().is_ok() {
+                    return Err(traversal_prevented());
+                }
+
+                // `..` has no file name, and can only be resolved through an existing directory
+                missing_components.push(existing_path.file_name().ok_or_else(traversal_prevented)?);
+                existing_path = existing_path.parent().ok_or(err)?;
+            }
+            Err(err) => return Err(err),
+        }
+    };
+
+    Ok(missing_components
+        .into_iter()
+        .rev()
+        .fold(canonical_path, |path, component| path.join(component)))
+}
+
+/// Returns an error for a path that escapes the root.
+fn traversal_prevented() -> io::Error {
+    io::Error::new(ErrorKind::PermissionDenied, "Traversal prevented")
+}
+
 /// An unrestricted path resolver that simply appends the desired path to the root without checking for bounds.
 pub struct UnrestrictedPathResolver {}
 impl PathResolver for UnrestrictedPathResolver {
     fn resolve_path(root: &Path, path: &str) -> crate::Result<PathBuf> {
-        Ok(root.join(make_relative(path)))
+        Ok(root.join(resolve_backtracking(root, &host_relative(path))))
     }
 }
 
+/// Trims the root off of a virtual path so that it can be joined to a host path. Backslashes are only separators on
+/// Windows, and are kept as part of file names everywhere else, like the host does.
+///
+/// # Arguments
+/// `path`: The virtual path.  
+pub(crate) fn host_relative(path: &str) -> PathBuf {
+    #[cfg(windows)]
+    return crate::util::make_relative(path);
+    #[cfg(not(windows))]
+    PathBuf::from(path.trim_start_matches('/'))
+}
+
+/// Lexically resolves the `..` components of `path`, keeping any leading `..` that escape it. Some hosts only
+/// resolve `..` through directories that actually exist, so this keeps traversal consistent across platforms. `..`
+/// after a symbolic link refers to the parent of its target, so the rest of the path is left to the host once one is
+/// backtracked through. The components are pushed one at a time, so the result uses the host's separators, which
+/// keeps it valid when it's joined to a Windows root with an extended-length `\\?\` prefix.
+///
+/// # Arguments
+/// `root`: The host path that `path` is relative to.  
+/// `path`: The relative path.  
+fn resolve_backtracking(root: &Path, path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    let mut components = path.components();
+    while let Some(component) = components.next() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    resolved.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                let is_symlink = root
+                    .join(&resolved)
+                    .symlink_metadata()
+                    .is_ok_and(|metadata| metadata.file_type().is_symlink());
+                if is_symlink {
+                    resolved.push(component);
+                    resolved.extend(components);
+                    break;
+                }
+
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+
+    resolved
+}
+
 #[cfg(test)]
 mod test {
     use crate::physical_fs::path_resolver::{
         PathResolver, SandboxedPathResolver, UnrestrictedPathResolver,
     };
+    #[cfg(unix)]
+    use crate::physical_fs::SandboxedPhysicalFS;
+    #[cfg(unix)]
+    use crate::FileSystem;
+    #[cfg(unix)]
+    use std::fs;
     use std::path::Path;
 
     #[test]
@@ -49,10 +143,6 @@ mod test {
             SandboxedPathResolver::resolve_path(Path::new("test/a/b/c"), "/d/e/f").unwrap(),
             Path::new("test/a/b/c/d/e/f").canonicalize().unwrap()
         );
-        assert_eq!(
-            SandboxedPathResolver::resolve_path(Path::new("test/a/b/c"), "\\d//\\e/f").unwrap(),
-            Path::new("test/a/b/c/d/e/f").canonicalize().unwrap()
-        );
         assert_eq!(
             SandboxedPathResolver::resolve_path(Path::new("test/a/b/c"), "./d/e/f").unwrap(),
             Path::new("test/a/b/c/d/e/f").canonicalize().unwrap()
@@ -71,8 +161,111 @@ mod test {
             "d/e/f/g/../../../../.."
         )
         .is_err());
-        // symlink
-        assert!(SandboxedPathResolver::resolve_path(Path::new("test"), "virtual-fs").is_err());
+        // paths that don't exist yet
+        assert_eq!(
+            SandboxedPathResolver::resolve_path(Path::new("test/a"), "b/new/../newer/file")
+                .unwrap(),
+            Path::new("test/a/b")
+                .canonicalize()
+                .unwrap()
+                .join("newer/file")
+        );
+        assert!(
+            SandboxedPathResolver::resolve_path(Path::new("test/a/b/c"), "../../../new").is_err()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sandboxed_resolver_symlink() {
+        let root = std::env::temp_dir().join(format!("symlink-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        std::os::unix::fs::symlink("..", root.join("virtual-fs")).unwrap();
+
+        assert!(SandboxedPathResolver::resolve_path(&root, "virtual-fs").is_err());
+        assert!(SandboxedPathResolver::resolve_path(&root, "virtual-fs/new").is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sandboxed_resolver_dangling_symlink() {
+        let parent = std::env::temp_dir().join(format!("dangling-{}", std::process::id()));
+        let root = parent.join("root");
+        fs::create_dir_all(&root).unwrap();
+        std::os::unix::fs::symlink("../outside/pwned", root.join("link")).unwrap();
+        std::os::unix::fs::symlink("missing", root.join("inner-link")).unwrap();
+
+        assert!(SandboxedPathResolver::resolve_path(&root, "link").is_err());
+        assert!(SandboxedPathResolver::resolve_path(&root, "link/new").is_err());
+        assert!(SandboxedPathResolver::resolve_path(&root, "inner-link").is_err());
+
+        // creating a file through the link must not create its target
+        fs::create_dir(parent.join("outside")).unwrap();
+        let physical_fs = SandboxedPhysicalFS::new(&root);
+        assert!(physical_fs.create_file("link").is_err());
+        assert!(!parent.join("outside/pwned").exists());
+
+        fs::remove_dir_all(parent).unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn sandboxed_resolver_backslashes() {
+        assert_eq!(
+            SandboxedPathResolver::resolve_path(Path::new("test/a/b/c"), "\\d//\\e/f").unwrap(),
+            Path::new("test/a/b/c/d/e/f").canonicalize().unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn backslashes_in_names() {
+        // backslashes are valid in the names of files on unix, so they aren't separators
+        assert_eq!(
+            UnrestrictedPathResolver::resolve_path(Path::new("/a"), "/b\\c").unwrap(),
+            Path::new("/a").join("b\\c")
+        );
+
+        let root = std::env::temp_dir().join(format!("backslash-{}", std::process::id()));
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::write(root.join("dir").join("a\\b"), "contents").unwrap();
+
+        let physical_fs = SandboxedPhysicalFS::new(&root);
+        assert_eq!(physical_fs.metadata("dir/a\\b").unwrap().len, 8);
+        assert!(!physical_fs.exists("dir/a/b").unwrap());
+        assert!(physical_fs.metadata("dir\\a\\b").is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn sandboxed_resolver_verbatim() {
+        // canonical roots have a `\\?\` prefix
+        let root = Path::new("test").canonicalize().unwrap();
+        assert_eq!(
+            SandboxedPathResolver::resolve_path(&root, "a/b/new").unwrap(),
+            root.join("a").join("b").join("new")
+        );
+        assert!(SandboxedPathResolver::resolve_path(&root, "../new").is_err());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn unrestricted_resolver_prefixes() {
+        // virtual paths are joined component by component, since forward slashes aren't separators within
+        // extended-length paths
+        assert_eq!(
+            UnrestrictedPathResolver::resolve_path(Path::new(r"\\?\C:\long"), "a/../b/c").unwrap(),
+            Path::new(r"\\?\C:\long\b\c")
+        );
+        assert_eq!(
+            UnrestrictedPathResolver::resolve_path(Path::new(r"\\server\share"), "/dir/file")
+                .unwrap(),
+            Path::new(r"\\server\share\dir\file")
+        );
     }
 
     #[test]
@@ -89,5 +282,45 @@ mod test {
             UnrestrictedPathResolver::resolve_path(Path::new("/a/b/c"), "../d/e/f").unwrap(),
             Path::new("/a/b/c/../d/e/f")
         );
+        // backtracking is resolved lexically, even through directories that don't exist
+        assert_eq!(
+            UnrestrictedPathResolver::resolve_path(Path::new("/a/b/c"), "missing/../d").unwrap(),
+            Path::new("/a/b/c/d")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn backtracking_through_symlink() {
+        let root = std::env::temp_dir().join(format!("backtracking-{}", std::process::id()));
+        fs::create_dir_all(root.join("dir/nested")).unwrap();
+        fs::write(root.join("dir/file"), "in dir").unwrap();
+        fs::write(root.join("file"), "in root").unwrap();
+        std::os::unix::fs::symlink("dir/nested", root.join("link")).unwrap();
+
+        // `..` after a symbolic link is the parent of its target, like it is on the host
+        assert_eq!(
+            UnrestrictedPathResolver::resolve_path(&root, "link/../file").unwrap(),
+            root.join("link/../file")
+        );
+        let physical_fs = SandboxedPhysicalFS::new(&root);
+        assert_eq!(
+            physical_fs
+                .open_file("link/../file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "in dir"
+        );
+        assert_eq!(
+            physical_fs
+                .open_file("dir/../file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "in root"
+        );
+
+        fs::remove_dir_all(root).unwrap();
     }
 }
@@ -0,0 +1,201 @@
+use crate::async_fs::{AsyncFile, AsyncFileSystem, ReadDirStream};
+use crate::file::{DirEntry, Metadata, OpenOptions};
+use crate::physical_fs::path_resolver::{PathResolver, UnrestrictedPathResolver};
+use crate::util::invalid_path;
+use async_trait::async_trait;
+use futures_util::stream;
+use normalize_path::NormalizePath;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The physical filesystem, backed by a root on the drive, whose operations are run by `tokio::fs` instead of
+/// blocking the task. Like `PhysicalFS`, this filesystem will not protect against directory traversal and very simply
+/// appends the target path to the root.
+pub struct TokioPhysicalFS {
+    root: PathBuf,
+}
+
+impl TokioPhysicalFS {
+    /// Creates a new physical file system at the given root.
+    ///
+    /// # Arguments
+    /// `root`: The root directory on the host.  
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().normalize(),
+        }
+    }
+
+    /// Returns the root directory on the host.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolves `path` to a host path.
+    ///
+    /// # Arguments
+    /// `path`: The path to resolve.  
+    fn resolve_path(&self, path: &str) -> crate::Result<PathBuf> {
+        UnrestrictedPathResolver::resolve_path(&self.root, path)
+    }
+}
+
+#[async_trait]
+impl AsyncFileSystem for TokioPhysicalFS {
+    async fn create_dir(&self, path: &str) -> crate::Result<()> {
+        tokio::fs::create_dir(self.resolve_path(path)?).await
+    }
+
+    async fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        tokio::fs::metadata(self.resolve_path(path)?)
+            .await
+            .map(Metadata::from)
+    }
+
+    async fn open_file_options(
+        &self,
+        path: &str,
+        options: &OpenOptions,
+    ) -> crate::Result<Box<dyn AsyncFile>> {
+        options.validate()?;
+        let file = tokio::fs::OpenOptions::from(fs::OpenOptions::from(options))
+            .open(self.resolve_path(path)?)
+            .await?;
+        Ok(Box::new(file))
+    }
+
+    async fn read_dir(&self, path: &str) -> crate::Result<ReadDirStream> {
+        let entries = tokio::fs::read_dir(self.resolve_path(path)?).await?;
+        Ok(Box::pin(stream::unfold(
+            (entries, self.root.clone()),
+            |(mut entries, root)| async move {
+                let entry = match entries.next_entry().await {
+                    Ok(entry) => entry?,
+                    Err(err) => return Some((Err(err), (entries, root))),
+                };
+                let entry = match entry.metadata().await {
+                    Ok(metadata) => dir_entry(&root, &entry, metadata),
+                    Err(err) => Err(err),
+                };
+                Some((entry, (entries, root)))
+            },
+        )))
+    }
+
+    async fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        tokio::fs::remove_dir(self.resolve_path(path)?).await
+    }
+
+    async fn remove_file(&self, path: &str) -> crate::Result<()> {
+        tokio::fs::remove_file(self.resolve_path(path)?).await
+    }
+
+    async fn copy_file(&self, from: &str, to: &str) -> crate::Result<u64> {
+        tokio::fs::copy(self.resolve_path(from)?, self.resolve_path(to)?).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        tokio::fs::rename(self.resolve_path(from)?, self.resolve_path(to)?).await
+    }
+}
+
+/// Converts a host directory entry to a directory entry with a path relative to the root.
+///
+/// # Arguments
+/// `root`: The root of the filesystem.  
+/// `entry`: The host directory entry.  
+/// `metadata`: The metadata of the entry.  
+fn dir_entry(
+    root: &Path,
+    entry: &tokio::fs::DirEntry,
+    metadata: fs::Metadata,
+) -> crate::Result<DirEntry> {
+    Ok(DirEntry {
+        path: entry
+            .path()
+            .strip_prefix(root)
+            .map_err(|_| invalid_path())?
+            .into(),
+        metadata: metadata.into(),
+    })
+}
+
+#[async_trait]
+impl AsyncFile for tokio::fs::File {
+    async fn metadata(&self) -> crate::Result<Metadata> {
+        tokio::fs::File::metadata(self).await.map(Metadata::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::async_fs::AsyncFileSystem;
+    use crate::file::FileType;
+    use crate::physical_fs::TokioPhysicalFS;
+    use futures_util::TryStreamExt;
+    use std::fs;
+    use std::future::Future;
+    use std::path::PathBuf;
+    use tokio::io::AsyncWriteExt;
+
+    /// Runs a future on a runtime with its I/O driven by blocking threads, as `tokio::fs` requires.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn tokio_physical_fs() {
+        let root = std::env::temp_dir().join(format!("tokio-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let tokio_fs = TokioPhysicalFS::new(&root);
+
+        block_on(async {
+            tokio_fs.create_dir_all("/dir/sub").await.unwrap();
+            let mut file = tokio_fs.create_file("dir/file").await.unwrap();
+            file.write_all(b"file").await.unwrap();
+            file.flush().await.unwrap();
+            drop(file);
+
+            assert_eq!(
+                tokio_fs
+                    .open_file("dir/../dir/file")
+                    .await
+                    .unwrap()
+                    .read_into_string()
+                    .await
+                    .unwrap(),
+                "file"
+            );
+            let metadata = tokio_fs.metadata("dir/file").await.unwrap();
+            assert_eq!((metadata.file_type, metadata.len), (FileType::File, 4));
+            assert!(!tokio_fs.exists("missing").await.unwrap());
+
+            let mut entries = tokio_fs
+                .read_dir("dir")
+                .await
+                .unwrap()
+                .map_ok(|entry| entry.path)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            entries.sort();
+            assert_eq!(
+                entries,
+                vec![PathBuf::from("dir/file"), PathBuf::from("dir/sub")]
+            );
+
+            assert_eq!(tokio_fs.copy_file("dir/file", "copy").await.unwrap(), 4);
+            tokio_fs.rename("copy", "dir/renamed").await.unwrap();
+            tokio_fs.remove_file("dir/renamed").await.unwrap();
+            tokio_fs.remove_file("dir/file").await.unwrap();
+            tokio_fs.remove_dir("dir/sub").await.unwrap();
+            tokio_fs.remove_dir("dir").await.unwrap();
+            assert!(tokio_fs.read_dir("dir").await.is_err());
+        });
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
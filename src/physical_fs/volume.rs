@@ -0,0 +1,102 @@
+use crate::file::FileSystemStats;
+#[cfg(any(unix, windows))]
+use std::io;
+use std::path::Path;
+
+/// Returns the capacity of the volume containing `path`.
+///
+/// # Arguments
+/// `path`: A path on the volume.  
+#[cfg(unix)]
+// the field types vary between platforms
+#[allow(clippy::useless_conversion)]
+pub(super) fn stats(path: &Path) -> crate::Result<FileSystemStats> {
+    use crate::util::invalid_path;
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|_| invalid_path())?;
+    let mut stats = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is nul-terminated and `stats` is only read after it's initialized
+    let stats = unsafe {
+        if libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stats.assume_init()
+    };
+
+    // block counts are in units of the fragment size
+    let fragment_size = u64::from(stats.f_frsize);
+    Ok(FileSystemStats {
+        total_space: u64::from(stats.f_blocks) * fragment_size,
+        free_space: u64::from(stats.f_bfree) * fragment_size,
+        available_space: u64::from(stats.f_bavail) * fragment_size,
+        block_size: u64::from(stats.f_bsize),
+    })
+}
+
+/// Returns the capacity of the volume containing `path`.
+///
+/// # Arguments
+/// `path`: A path on the volume.  
+#[cfg(windows)]
+pub(super) fn stats(path: &Path) -> crate::Result<FileSystemStats> {
+    use std::iter::once;
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetDiskFreeSpaceExW, GetDiskFreeSpaceW, GetVolumePathNameW,
+    };
+
+    let path = path
+        .as_os_str()
+        .encode_wide()
+        .chain(once(0))
+        .collect::<Vec<_>>();
+    let (mut available_space, mut total_space, mut free_space) = (0, 0, 0);
+    // the cluster size can only be queried through the root of the volume
+    let mut volume_path = vec![0u16; path.len().max(261)];
+    let (mut sectors_per_cluster, mut bytes_per_sector, mut free_clusters, mut total_clusters) =
+        (0, 0, 0, 0);
+
+    // SAFETY: `path` is nul-terminated, and `volume_path` is as long as the length it's passed with
+    unsafe {
+        if GetDiskFreeSpaceExW(
+            path.as_ptr(),
+            &mut available_space,
+            &mut total_space,
+            &mut free_space,
+        ) == 0
+            || GetVolumePathNameW(
+                path.as_ptr(),
+                volume_path.as_mut_ptr(),
+                volume_path.len() as u32,
+            ) == 0
+            || GetDiskFreeSpaceW(
+                volume_path.as_ptr(),
+                &mut sectors_per_cluster,
+                &mut bytes_per_sector,
+                &mut free_clusters,
+                &mut total_clusters,
+            ) == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(FileSystemStats {
+        total_space,
+        free_space,
+        available_space,
+        block_size: u64::from(sectors_per_cluster) * u64::from(bytes_per_sector),
+    })
+}
+
+/// Returns the capacity of the volume containing `path`.
+///
+/// # Arguments
+/// `path`: A path on the volume.  
+#[cfg(not(any(unix, windows)))]
+pub(super) fn stats(_path: &Path) -> crate::Result<FileSystemStats> {
+    Err(crate::util::not_supported())
+}
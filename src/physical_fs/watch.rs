@@ -0,0 +1,324 @@
+use crate::physical_fs::path_resolver::PathResolver;
+use crate::physical_fs::PhysicalFSImpl;
+use crate::util::{not_found, Instant};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::Duration;
+
+/// The kind of change made to a watched path.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WatchEventKind {
+    /// The entry was created, or renamed to the path.
+    Create,
+    /// The entry's contents or metadata were modified.
+    Modify,
+    /// The entry was removed, or renamed away from the path.
+    Remove,
+}
+
+/// A change made to a watched path.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WatchEvent {
+    /// The kind of change.
+    pub kind: WatchEventKind,
+    /// The path of the changed entry, relative to the root of the filesystem.
+    pub path: PathBuf,
+}
+
+/// Options for watching a path.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WatchOptions {
+    /// True if changes within subdirectories should be reported.
+    pub recursive: bool,
+    /// The time to wait for a path to settle before its changes are reported, if any.
+    pub debounce: Option<Duration>,
+}
+
+impl WatchOptions {
+    /// # Arguments
+    /// `recursive`: True if changes within subdirectories should be reported.  
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// # Arguments
+    /// `debounce`: The time to wait after the last change to a path before it's reported. Changes made to the same
+    /// path within that time are coalesced into a single event.  
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = Some(debounce);
+        self
+    }
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            recursive: true,
+            debounce: None,
+        }
+    }
+}
+
+/// A handle to a watch. Changes stop being reported when it's dropped.
+pub struct Watcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl<R: PathResolver> PhysicalFSImpl<R> {
+    /// Watches `path` for changes, calling `handler` with each change from a background thread.
+    ///
+    /// # Arguments
+    /// `path`: The path to watch.  
+    /// `options`: The options to watch with.  
+    /// `handler`: The handler changes are reported to.  
+    pub fn watch<F>(&self, path: &str, options: WatchOptions, handler: F) -> crate::Result<Watcher>
+    where
+        F: FnMut(crate::Result<WatchEvent>) + Send + 'static,
+    {
+        // the OS reports canonical paths
+        let root = self.root.canonicalize()?;
+        let path = self.resolve_path(path)?.canonicalize()?;
+
+        let mut handler = handler;
+        let mut watcher = match options.debounce {
+            Some(delay) => {
+                let (sender, receiver) = mpsc::channel();
+                thread::spawn(move || debounce(receiver, delay, handler));
+                notify::recommended_watcher(move |event| {
+                    for event in translate(&root, event) {
+                        let _ = sender.send(event);
+                    }
+                })
+            }
+            None => notify::recommended_watcher(move |event| {
+                translate(&root, event).into_iter().for_each(&mut handler)
+            }),
+        }
+        .map_err(convert_error)?;
+
+        let mode = if options.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher.watch(&path, mode).map_err(convert_error)?;
+
+        Ok(Watcher { _watcher: watcher })
+    }
+}
+
+/// Translates an OS event into events with paths relative to `root`.
+///
+/// # Arguments
+/// `root`: The canonical root of the filesystem.  
+/// `event`: The OS event.  
+fn translate(root: &Path, event: notify::Result<notify::Event>) -> Vec<crate::Result<WatchEvent>> {
+    let event = match event {
+        Ok(event) => event,
+        Err(err) => return vec![Err(convert_error(err))],
+    };
+
+    let kinds = match event.kind {
+        EventKind::Access(_) => return Vec::new(),
+        EventKind::Create(_) => vec![WatchEventKind::Create],
+        EventKind::Remove(_) => vec![WatchEventKind::Remove],
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => vec![WatchEventKind::Remove],
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => vec![WatchEventKind::Create],
+        // both paths of a rename are reported together
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            vec![WatchEventKind::Remove, WatchEventKind::Create]
+        }
+        _ => vec![WatchEventKind::Modify],
+    };
+
+    event
+        .paths
+        .iter()
+        .zip(kinds.into_iter().cycle())
+        // paths outside of the root can't be represented
+        .filter_map(|(path, kind)| {
+            path.strip_prefix(root).ok().map(|path| {
+                Ok(WatchEvent {
+                    kind,
+                    path: path.to_owned(),
+                })
+            })
+        })
+        .collect()
+}
+
+/// Coalesces the changes to each path, reporting them once the path has settled for `delay`.
+///
+/// # Arguments
+/// `receiver`: The receiver of the raw events.  
+/// `delay`: The time a path must settle for.  
+/// `handler`: The handler changes are reported to.  
+fn debounce<F: FnMut(crate::Result<WatchEvent>)>(
+    receiver: mpsc::Receiver<crate::Result<WatchEvent>>,
+    delay: Duration,
+    mut handler: F,
+) {
+    // pending changes in the order they were first seen, along with the time they were last seen
+    let mut pending: Vec<(WatchEvent, Instant)> = Vec::new();
+
+    loop {
+        let received = match pending.iter().map(|(_, last_seen)| *last_seen).min() {
+            Some(last_seen) => {
+                receiver.recv_timeout((last_seen + delay).duration_since(Instant::now()))
+            }
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match received {
+            Ok(Ok(event)) => {
+                let now = Instant::now();
+                match pending
+                    .iter()
+                    .position(|(pending, _)| pending.path == event.path)
+                {
+                    Some(index) => match coalesce(pending[index].0.kind, event.kind) {
+                        Some(kind) => pending[index] = (WatchEvent { kind, ..event }, now),
+                        None => {
+                            pending.remove(index);
+                        }
+                    },
+                    None => pending.push((event, now)),
+                }
+            }
+            Ok(Err(err)) => handler(Err(err)),
+            Err(RecvTimeoutError::Timeout) => {}
+            // the watcher was dropped
+            Err(RecvTimeoutError::Disconnected) => {
+                pending
+                    .into_iter()
+                    .for_each(|(event, _)| handler(Ok(event)));
+                return;
+            }
+        }
+
+        // report the paths that have settled
+        let now = Instant::now();
+        let (settled, unsettled) = pending
+            .into_iter()
+            .partition(|(_, last_seen)| now.duration_since(*last_seen) >= delay);
+        pending = unsettled;
+        settled
+            .into_iter()
+            .for_each(|(event, _): (WatchEvent, _)| handler(Ok(event)));
+    }
+}
+
+/// Coalesces two consecutive changes to the same path. Returns `None` if they cancel out.
+///
+/// # Arguments
+/// `first`: The earlier change.  
+/// `second`: The later change.  
+fn coalesce(first: WatchEventKind, second: WatchEventKind) -> Option<WatchEventKind> {
+    match (first, second) {
+        (WatchEventKind::Create, WatchEventKind::Modify) => Some(WatchEventKind::Create),
+        (WatchEventKind::Create, WatchEventKind::Remove) => None,
+        (WatchEventKind::Remove, WatchEventKind::Create) => Some(WatchEventKind::Modify),
+        (_, second) => Some(second),
+    }
+}
+
+/// Converts a watcher error to an IO error.
+fn convert_error(err: notify::Error) -> io::Error {
+    match err.kind {
+        notify::ErrorKind::Io(err) => err,
+        notify::ErrorKind::PathNotFound | notify::ErrorKind::WatchNotFound => not_found(),
+        _ => io::Error::other(err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::physical_fs::{PhysicalFS, WatchEvent, WatchEventKind, WatchOptions};
+    use crate::FileSystem;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn event(kind: WatchEventKind, path: &str) -> WatchEvent {
+        WatchEvent {
+            kind,
+            path: PathBuf::from(path),
+        }
+    }
+
+    #[test]
+    fn watch() {
+        let root = std::env::temp_dir().join(format!("watch-{}", std::process::id()));
+        fs::create_dir_all(root.join("dir")).unwrap();
+        let physical_fs = PhysicalFS::new(&root);
+
+        let (sender, receiver) = mpsc::channel();
+        let _watcher = physical_fs
+            .watch("dir", WatchOptions::default(), move |event| {
+                sender.send(event.unwrap()).unwrap();
+            })
+            .unwrap();
+
+        physical_fs.create_file("dir/file").unwrap();
+        assert_eq!(
+            receiver.recv_timeout(TIMEOUT).unwrap(),
+            event(WatchEventKind::Create, "dir/file")
+        );
+
+        physical_fs.remove_file("dir/file").unwrap();
+        assert_eq!(
+            receiver.recv_timeout(TIMEOUT).unwrap(),
+            event(WatchEventKind::Remove, "dir/file")
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn debounce() {
+        let root = std::env::temp_dir().join(format!("debounce-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let physical_fs = PhysicalFS::new(&root);
+
+        let (sender, receiver) = mpsc::channel();
+        let watcher = physical_fs
+            .watch(
+                "",
+                WatchOptions::default().debounce(Duration::from_millis(200)),
+                move |event| {
+                    sender.send(event.unwrap()).unwrap();
+                },
+            )
+            .unwrap();
+
+        // the creation and writes are coalesced
+        let mut file = physical_fs.create_file("file").unwrap();
+        for _ in 0..10 {
+            file.write_all(b"data").unwrap();
+            file.flush().unwrap();
+        }
+        drop(file);
+        assert_eq!(
+            receiver.recv_timeout(TIMEOUT).unwrap(),
+            event(WatchEventKind::Create, "file")
+        );
+
+        // a transient file isn't reported at all
+        physical_fs.create_file("transient").unwrap();
+        physical_fs.remove_file("transient").unwrap();
+        drop(watcher);
+        assert!(receiver.recv_timeout(TIMEOUT).is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
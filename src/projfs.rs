@@ -0,0 +1,770 @@
+use crate::file::{Metadata, OpenOptions};
+use crate::FileSystem;
+use parking_lot::Mutex;
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::hash::{Hash, Hasher};
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::iter::once;
+use std::os::windows::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io, mem, ptr, slice};
+use windows_sys::core::{GUID, HRESULT, PCWSTR};
+use windows_sys::Win32::Foundation::{
+    ERROR_ACCESS_DENIED, ERROR_FILE_NOT_FOUND, ERROR_INSUFFICIENT_BUFFER, E_FAIL, E_OUTOFMEMORY,
+    S_OK, WIN32_ERROR,
+};
+use windows_sys::Win32::Storage::FileSystem::{
+    FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_NORMAL, FILE_ATTRIBUTE_READONLY,
+};
+use windows_sys::Win32::Storage::ProjectedFileSystem::{
+    PrjAllocateAlignedBuffer, PrjFileNameCompare, PrjFileNameMatch, PrjFillDirEntryBuffer,
+    PrjFreeAlignedBuffer, PrjMarkDirectoryAsPlaceholder, PrjStartVirtualizing, PrjStopVirtualizing,
+    PrjWriteFileData, PrjWritePlaceholderInfo, PRJ_CALLBACKS, PRJ_CALLBACK_DATA,
+    PRJ_CB_DATA_FLAG_ENUM_RESTART_SCAN, PRJ_CB_DATA_FLAG_ENUM_RETURN_SINGLE_ENTRY,
+    PRJ_DIR_ENTRY_BUFFER_HANDLE, PRJ_FILE_BASIC_INFO, PRJ_NAMESPACE_VIRTUALIZATION_CONTEXT,
+    PRJ_PLACEHOLDER_INFO,
+};
+
+/// The largest chunk of a file hydrated with a single write, which is a multiple of every sector size.
+const CHUNK_LEN: u32 = 1024 * 1024;
+/// The Unix epoch as a `FILETIME`, in 100-nanosecond intervals since 1601.
+const UNIX_EPOCH_FILE_TIME: i64 = 116_444_736_000_000_000;
+
+/// Projects `fs` into the directory `root` through the Windows Projected File System until the returned
+/// `Projection` is stopped or dropped. Entries appear under `root` as placeholders when they're listed or opened,
+/// and the contents of files are read from `fs` the first time they're read, so tools that need real paths can
+/// open files in a `ZipFS` or `MemoryFS` without it being extracted.
+///
+/// Changes made under `root` stay on the disk and aren't written to `fs`, and files that were read stay on the disk
+/// after the projection stops. The "Windows Projected File System" optional feature must be enabled.
+///
+/// # Arguments
+/// `fs`: The filesystem to project.  
+/// `root`: The directory to project the filesystem into, which is created if it doesn't exist. It should be empty,
+/// or have been projected from the same filesystem before.  
+pub fn project<FS: FileSystem + Send + Sync + 'static, P: AsRef<Path>>(
+    fs: FS,
+    root: P,
+) -> io::Result<Projection> {
+    let root = root.as_ref().to_owned();
+    fs::create_dir_all(&root)?;
+    let wide_root = root
+        .as_os_str()
+        .encode_wide()
+        .chain(once(0))
+        .collect::<Vec<_>>();
+
+    // a root can only be marked once, so a root that was projected before fails here but still starts below
+    let id = instance_id(&root);
+    // SAFETY: `wide_root` is nul-terminated, and no version info or target is passed
+    let marked =
+        unsafe { PrjMarkDirectoryAsPlaceholder(wide_root.as_ptr(), ptr::null(), ptr::null(), &id) };
+
+    let provider = Box::new(Provider {
+        fs,
+        enumerations: Mutex::new(HashMap::new()),
+    });
+    // SAFETY: all-zero callbacks are absent
+    let mut callbacks: PRJ_CALLBACKS = unsafe { mem::zeroed() };
+    callbacks.StartDirectoryEnumerationCallback = Some(start_enumeration::<FS>);
+    callbacks.EndDirectoryEnumerationCallback = Some(end_enumeration::<FS>);
+    callbacks.GetDirectoryEnumerationCallback = Some(get_enumeration::<FS>);
+    callbacks.GetPlaceholderInfoCallback = Some(get_placeholder_info::<FS>);
+    callbacks.GetFileDataCallback = Some(get_file_data::<FS>);
+    callbacks.QueryFileNameCallback = Some(query_file_name::<FS>);
+
+    let mut context = 0;
+    // SAFETY: the provider outlives the virtualization instance, which is stopped before the provider is dropped
+    let started = unsafe {
+        PrjStartVirtualizing(
+            wide_root.as_ptr(),
+            &callbacks,
+            &*provider as *const Provider<FS> as *const c_void,
+            ptr::null(),
+            &mut context,
+        )
+    };
+    if started != S_OK {
+        return Err(io::Error::from_raw_os_error(if marked != S_OK {
+            marked
+        } else {
+            started
+        }));
+    }
+
+    Ok(Projection {
+        context,
+        root,
+        _provider: provider,
+    })
+}
+
+/// A filesystem projected by `project`, which stops being projected when dropped.
+pub struct Projection {
+    context: PRJ_NAMESPACE_VIRTUALIZATION_CONTEXT,
+    root: PathBuf,
+    /// The provider the callbacks are passed, which must outlive the virtualization instance.
+    _provider: Box<dyn Any + Send + Sync>,
+}
+
+impl Projection {
+    /// Returns the directory the filesystem is projected into.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Stops projecting the filesystem, waiting for the callbacks in progress to return.
+    pub fn stop(self) {}
+}
+
+impl Drop for Projection {
+    fn drop(&mut self) {
+        // SAFETY: the context was returned by `PrjStartVirtualizing` and is only stopped once
+        unsafe { PrjStopVirtualizing(self.context) };
+    }
+}
+
+/// The state the callbacks of a projection share.
+struct Provider<FS: FileSystem> {
+    fs: FS,
+    /// The directory enumerations in progress, by their IDs.
+    enumerations: Mutex<HashMap<u128, Enumeration>>,
+}
+
+/// A directory enumeration in progress.
+struct Enumeration {
+    /// The nul-terminated names and information of the entries of the directory, in the order ProjFS sorts them.
+    entries: Vec<(Vec<u16>, PRJ_FILE_BASIC_INFO)>,
+    /// The index of the next entry to return.
+    next: usize,
+    /// The nul-terminated expression that entries are matched against, which is fixed by the first request.
+    search: Option<Vec<u16>>,
+}
+
+impl<FS: FileSystem> Provider<FS> {
+    /// Returns the provider passed to the callback.
+    ///
+    /// # Safety
+    /// `data` must be passed to a callback of a projection of an `FS`.
+    unsafe fn from_callback<'a>(data: &PRJ_CALLBACK_DATA) -> &'a Self {
+        &*(data.InstanceContext as *const Self)
+    }
+
+    /// Returns the path in `fs` and the metadata of the entry at `path`, a path relative to the root that ProjFS
+    /// compares case-insensitively.
+    ///
+    /// # Arguments
+    /// `path`: The nul-terminated path relative to the root.  
+    unsafe fn resolve(&self, path: PCWSTR) -> Result<(String, Metadata), HRESULT> {
+        let mut resolved = String::new();
+        let mut metadata = Metadata::directory();
+        let path = String::from_utf16_lossy(wide_str(path));
+        for name in path.split('\\').filter(|name| !name.is_empty()) {
+            if !metadata.is_directory() {
+                return Err(win32_error(ERROR_FILE_NOT_FOUND));
+            }
+            let candidate = join(&resolved, name);
+            match self.fs.metadata(&candidate) {
+                Ok(candidate_metadata) => {
+                    resolved = candidate;
+                    metadata = candidate_metadata;
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    // the case of the name may differ from the entry's
+                    let wide_name = wide(name);
+                    let entry = self
+                        .fs
+                        .read_dir(&resolved)
+                        .map_err(|err| io_error(&err))?
+                        .filter_map(Result::ok)
+                        .find(|entry| {
+                            entry_name(&entry.path).is_some_and(|entry_name| {
+                                PrjFileNameCompare(wide(entry_name).as_ptr(), wide_name.as_ptr())
+                                    == 0
+                            })
+                        })
+                        .ok_or(win32_error(ERROR_FILE_NOT_FOUND))?;
+                    // entry names are checked above
+                    resolved = join(&resolved, entry_name(&entry.path).unwrap());
+                    metadata = entry.metadata;
+                }
+                Err(err) => return Err(io_error(&err)),
+            }
+        }
+        Ok((resolved, metadata))
+    }
+
+    /// Starts enumerating the directory at the callback's path.
+    unsafe fn start_enumeration(&self, data: &PRJ_CALLBACK_DATA, id: u128) -> Result<(), HRESULT> {
+        let (path, metadata) = self.resolve(data.FilePathName)?;
+        if !metadata.is_directory() {
+            return Err(win32_error(ERROR_FILE_NOT_FOUND));
+        }
+        let mut entries = self
+            .fs
+            .read_dir(&path)
+            .map_err(|err| io_error(&err))?
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = wide(entry_name(&entry.path)?);
+                Some((name, basic_info(&entry.metadata)))
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|(a, _), (b, _)| PrjFileNameCompare(a.as_ptr(), b.as_ptr()).cmp(&0));
+
+        self.enumerations.lock().insert(
+            id,
+            Enumeration {
+                entries,
+                next: 0,
+                search: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Fills `buffer` with the next entries of an enumeration that match the search expression.
+    unsafe fn get_enumeration(
+        &self,
+        data: &PRJ_CALLBACK_DATA,
+        id: u128,
+        search: PCWSTR,
+        buffer: PRJ_DIR_ENTRY_BUFFER_HANDLE,
+    ) -> Result<(), HRESULT> {
+        let mut enumerations = self.enumerations.lock();
+        let enumeration = enumerations.get_mut(&id).ok_or(E_FAIL)?;
+        if data.Flags & PRJ_CB_DATA_FLAG_ENUM_RESTART_SCAN != 0 {
+            enumeration.next = 0;
+            enumeration.search = None;
+        }
+        let search = enumeration
+            .search
+            .get_or_insert_with(|| match search.is_null() {
+                true => wide("*"),
+                false => wide_str(search).iter().copied().chain(once(0)).collect(),
+            });
+
+        let mut filled = false;
+        while let Some((name, info)) = enumeration.entries.get(enumeration.next) {
+            if PrjFileNameMatch(name.as_ptr(), search.as_ptr()) != 0 {
+                let result = PrjFillDirEntryBuffer(name.as_ptr(), info, buffer);
+                if result == win32_error(ERROR_INSUFFICIENT_BUFFER) && filled {
+                    // the rest of the entries are returned by the next request
+                    break;
+                } else if result != S_OK {
+                    return Err(result);
+                }
+                filled = true;
+            }
+            enumeration.next += 1;
+            if filled && data.Flags & PRJ_CB_DATA_FLAG_ENUM_RETURN_SINGLE_ENTRY != 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the placeholder of the entry at the callback's path.
+    unsafe fn get_placeholder_info(&self, data: &PRJ_CALLBACK_DATA) -> Result<(), HRESULT> {
+        let (path, metadata) = self.resolve(data.FilePathName)?;
+        // SAFETY: all-zero placeholder info has no extended attributes, security descriptor or streams
+        let mut info: PRJ_PLACEHOLDER_INFO = mem::zeroed();
+        info.FileBasicInfo = basic_info(&metadata);
+        // the placeholder is named with the case of the entry in the filesystem
+        let name = wide(&path.replace('/', "\\"));
+        result(PrjWritePlaceholderInfo(
+            data.NamespaceVirtualizationContext,
+            name.as_ptr(),
+            &info,
+            mem::size_of::<PRJ_PLACEHOLDER_INFO>() as u32,
+        ))
+    }
+
+    /// Hydrates `length` bytes of the file at the callback's path, starting at `offset`.
+    unsafe fn get_file_data(
+        &self,
+        data: &PRJ_CALLBACK_DATA,
+        offset: u64,
+        length: u32,
+    ) -> Result<(), HRESULT> {
+        let (path, _) = self.resolve(data.FilePathName)?;
+        let mut file = self
+            .fs
+            .open_file_options(&path, &OpenOptions::default())
+            .map_err(|err| io_error(&err))?;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|err| io_error(&err))?;
+
+        let context = data.NamespaceVirtualizationContext;
+        let buffer = AlignedBuffer::new(context, length.min(CHUNK_LEN))?;
+        let mut written = 0;
+        while written < length {
+            let chunk_len = (length - written).min(CHUNK_LEN) as usize;
+            let chunk = &mut buffer.as_slice()[..chunk_len];
+            let mut filled = 0;
+            while filled < chunk_len {
+                match file.read(&mut chunk[filled..]) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(err) => return Err(io_error(&err)),
+                }
+            }
+            if filled == 0 {
+                break;
+            }
+
+            result(PrjWriteFileData(
+                context,
+                &data.DataStreamId,
+                chunk.as_ptr() as *const c_void,
+                offset + u64::from(written),
+                filled as u32,
+            ))?;
+            written += filled as u32;
+            // the file ended early
+            if filled < chunk_len {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A buffer allocated with the alignment that hydrated data needs.
+struct AlignedBuffer {
+    buffer: *mut u8,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    /// Allocates a buffer of `len` bytes.
+    ///
+    /// # Arguments
+    /// `context`: The virtualization instance the buffer is written to.  
+    /// `len`: The length of the buffer.  
+    fn new(context: PRJ_NAMESPACE_VIRTUALIZATION_CONTEXT, len: u32) -> Result<Self, HRESULT> {
+        // SAFETY: the context is the one the callback was called for
+        let buffer = unsafe { PrjAllocateAlignedBuffer(context, len as usize) } as *mut u8;
+        if buffer.is_null() {
+            return Err(E_OUTOFMEMORY);
+        }
+        Ok(Self {
+            buffer,
+            len: len as usize,
+        })
+    }
+
+    /// Returns the contents of the buffer.
+    #[allow(clippy::mut_from_ref)]
+    fn as_slice(&self) -> &mut [u8] {
+        // SAFETY: the buffer is `len` bytes long, and is only borrowed once at a time
+        unsafe { slice::from_raw_parts_mut(self.buffer, self.len) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        // SAFETY: the buffer was allocated by `PrjAllocateAlignedBuffer`
+        unsafe { PrjFreeAlignedBuffer(self.buffer as *const c_void) };
+    }
+}
+
+unsafe extern "system" fn start_enumeration<FS: FileSystem>(
+    data: *const PRJ_CALLBACK_DATA,
+    id: *const GUID,
+) -> HRESULT {
+    let data = &*data;
+    to_hresult(Provider::<FS>::from_callback(data).start_enumeration(data, guid(&*id)))
+}
+
+unsafe extern "system" fn end_enumeration<FS: FileSystem>(
+    data: *const PRJ_CALLBACK_DATA,
+    id: *const GUID,
+) -> HRESULT {
+    let provider = Provider::<FS>::from_callback(&*data);
+    provider.enumerations.lock().remove(&guid(&*id));
+    S_OK
+}
+
+unsafe extern "system" fn get_enumeration<FS: FileSystem>(
+    data: *const PRJ_CALLBACK_DATA,
+    id: *const GUID,
+    search: PCWSTR,
+    buffer: PRJ_DIR_ENTRY_BUFFER_HANDLE,
+) -> HRESULT {
+    let data = &*data;
+    to_hresult(Provider::<FS>::from_callback(data).get_enumeration(
+        data,
+        guid(&*id),
+        search,
+        buffer,
+    ))
+}
+
+unsafe extern "system" fn get_placeholder_info<FS: FileSystem>(
+    data: *const PRJ_CALLBACK_DATA,
+) -> HRESULT {
+    let data = &*data;
+    to_hresult(Provider::<FS>::from_callback(data).get_placeholder_info(data))
+}
+
+unsafe extern "system" fn get_file_data<FS: FileSystem>(
+    data: *const PRJ_CALLBACK_DATA,
+    offset: u64,
+    length: u32,
+) -> HRESULT {
+    let data = &*data;
+    to_hresult(Provider::<FS>::from_callback(data).get_file_data(data, offset, length))
+}
+
+unsafe extern "system" fn query_file_name<FS: FileSystem>(
+    data: *const PRJ_CALLBACK_DATA,
+) -> HRESULT {
+    let data = &*data;
+    to_hresult(
+        Provider::<FS>::from_callback(data)
+            .resolve(data.FilePathName)
+            .map(|_| ()),
+    )
+}
+
+/// Returns the ID the root at `root` is marked with, which is stable for the path so that it can be projected into
+/// again.
+///
+/// # Arguments
+/// `root`: The root of the projection.  
+fn instance_id(root: &Path) -> GUID {
+    let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_owned());
+    let mut id = 0;
+    for half in 0..2u8 {
+        let mut hasher = DefaultHasher::new();
+        half.hash(&mut hasher);
+        root.hash(&mut hasher);
+        id = id << 64 | u128::from(hasher.finish());
+    }
+    GUID::from_u128(id)
+}
+
+/// Returns the information ProjFS lists for an entry with `metadata`.
+///
+/// # Arguments
+/// `metadata`: The metadata of the entry.  
+fn basic_info(metadata: &Metadata) -> PRJ_FILE_BASIC_INFO {
+    let modified = metadata.modified.map_or(0, file_time);
+    let attributes = if metadata.is_directory() {
+        FILE_ATTRIBUTE_DIRECTORY
+    } else if metadata.mode.is_some_and(|mode| mode & 0o200 == 0) {
+        FILE_ATTRIBUTE_READONLY
+    } else {
+        FILE_ATTRIBUTE_NORMAL
+    };
+    PRJ_FILE_BASIC_INFO {
+        IsDirectory: metadata.is_directory().into(),
+        FileSize: if metadata.is_directory() {
+            0
+        } else {
+            metadata.len as i64
+        },
+        CreationTime: modified,
+        LastAccessTime: modified,
+        LastWriteTime: modified,
+        ChangeTime: modified,
+        FileAttributes: attributes,
+    }
+}
+
+/// Returns the name of the entry at `path`, if it's valid UTF-8.
+fn entry_name(path: &Path) -> Option<&str> {
+    path.file_name().and_then(|name| name.to_str())
+}
+
+/// Returns the path of `name` in the directory at `parent`.
+fn join(parent: &str, name: &str) -> String {
+    match parent {
+        "" => name.to_owned(),
+        parent => format!("{parent}/{name}"),
+    }
+}
+
+/// Returns `string` as a nul-terminated wide string.
+fn wide(string: &str) -> Vec<u16> {
+    string.encode_utf16().chain(once(0)).collect()
+}
+
+/// Returns the characters of the nul-terminated wide string at `string`.
+///
+/// # Safety
+/// `string` must be null or point to a nul-terminated wide string that outlives the returned slice.
+unsafe fn wide_str<'a>(string: PCWSTR) -> &'a [u16] {
+    if string.is_null() {
+        return &[];
+    }
+    let mut len = 0;
+    while *string.add(len) != 0 {
+        len += 1;
+    }
+    slice::from_raw_parts(string, len)
+}
+
+/// Returns the ID of an enumeration as an integer.
+fn guid(id: &GUID) -> u128 {
+    u128::from(id.data1) << 96
+        | u128::from(id.data2) << 80
+        | u128::from(id.data3) << 64
+        | u128::from(u64::from_be_bytes(id.data4))
+}
+
+/// Returns the `HRESULT` of a Win32 error.
+fn win32_error(error: WIN32_ERROR) -> HRESULT {
+    (error & 0xFFFF | 7 << 16 | 0x8000_0000) as HRESULT
+}
+
+/// Returns the `HRESULT` that ProjFS is given for an error from the filesystem.
+fn io_error(error: &io::Error) -> HRESULT {
+    match error.kind() {
+        ErrorKind::NotFound => win32_error(ERROR_FILE_NOT_FOUND),
+        ErrorKind::PermissionDenied => win32_error(ERROR_ACCESS_DENIED),
+        _ => error
+            .raw_os_error()
+            .map_or(E_FAIL, |code| win32_error(code as u32)),
+    }
+}
+
+/// Returns `Ok` if `result` is `S_OK`, or `result` as the error otherwise.
+fn result(result: HRESULT) -> Result<(), HRESULT> {
+    match result {
+        S_OK => Ok(()),
+        result => Err(result),
+    }
+}
+
+/// Returns the `HRESULT` a callback returns for `result`.
+fn to_hresult(result: Result<(), HRESULT>) -> HRESULT {
+    result.err().unwrap_or(S_OK)
+}
+
+/// Returns the `FILETIME` of `time`, or zero if it's before the Unix epoch.
+fn file_time(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |time| {
+        UNIX_EPOCH_FILE_TIME + (time.as_nanos() / 100) as i64
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::Metadata;
+    use crate::memory_fs::MemoryFS;
+    use crate::projfs::{
+        basic_info, file_time, guid, instance_id, io_error, project, wide, win32_error, CHUNK_LEN,
+        UNIX_EPOCH_FILE_TIME,
+    };
+    use crate::FileSystem;
+    use std::io::{ErrorKind, Write};
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, UNIX_EPOCH};
+    use std::{fs, io, mem};
+    use windows_sys::core::GUID;
+    use windows_sys::Win32::Foundation::{
+        ERROR_ACCESS_DENIED, ERROR_FILE_NOT_FOUND, E_FAIL, INVALID_HANDLE_VALUE,
+    };
+    use windows_sys::Win32::Storage::FileSystem::{
+        FindClose, FindFirstFileW, FindNextFileW, FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_NORMAL,
+        FILE_ATTRIBUTE_READONLY, WIN32_FIND_DATAW,
+    };
+
+    /// The contents of `Dir/big.bin`, which is hydrated in more than one chunk.
+    fn big() -> Vec<u8> {
+        (0..CHUNK_LEN * 5 / 2).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn memory_fs() -> MemoryFS {
+        let fs = MemoryFS::default();
+        fs.create_dir("Dir").unwrap();
+        write!(fs.create_file("Dir/File.txt").unwrap(), "hello world").unwrap();
+        fs.create_file("Dir/big.bin")
+            .unwrap()
+            .write_all(&big())
+            .unwrap();
+        fs.create_file("Dir/empty.txt").unwrap();
+        fs.create_dir("Dir/sub").unwrap();
+        fs
+    }
+
+    /// Returns a directory to project into, which doesn't exist yet.
+    fn temp_root(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("projfs-{}-{name}", std::process::id()))
+    }
+
+    /// Returns the names of the entries of the directory at `path`, sorted.
+    fn names(path: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Returns the names other than `.` and `..` that `FindFirstFileW` and `FindNextFileW` find for `pattern`, in the
+    /// order they're found.
+    fn find(pattern: &Path) -> Vec<String> {
+        let pattern = wide(pattern.to_str().unwrap());
+        let mut names = Vec::new();
+        // SAFETY: the pattern is nul-terminated, and all-zero find data is valid
+        unsafe {
+            let mut data: WIN32_FIND_DATAW = mem::zeroed();
+            let handle = FindFirstFileW(pattern.as_ptr(), &mut data);
+            if handle == INVALID_HANDLE_VALUE {
+                return names;
+            }
+            loop {
+                let len = data.cFileName.iter().position(|&c| c == 0).unwrap();
+                let name = String::from_utf16(&data.cFileName[..len]).unwrap();
+                if name != "." && name != ".." {
+                    names.push(name);
+                }
+                if FindNextFileW(handle, &mut data) == 0 {
+                    break;
+                }
+            }
+            FindClose(handle);
+        }
+        names
+    }
+
+    #[test]
+    fn enumeration() {
+        let root = temp_root("enumeration");
+        let projection = project(memory_fs(), &root).unwrap();
+
+        assert_eq!(names(&root), ["Dir"]);
+        assert_eq!(
+            names(&root.join("Dir")),
+            ["File.txt", "big.bin", "empty.txt", "sub"]
+        );
+        assert!(names(&root.join("Dir/sub")).is_empty());
+        // directories are looked up case-insensitively
+        assert_eq!(names(&root.join("dIR")).len(), 4);
+        // entries are returned in the order ProjFS sorts them, and filtered by the search expression
+        assert_eq!(
+            find(&root.join("Dir\\*")),
+            ["big.bin", "empty.txt", "File.txt", "sub"]
+        );
+        assert_eq!(find(&root.join("Dir\\*.TXT")), ["empty.txt", "File.txt"]);
+        assert_eq!(find(&root.join("Dir\\f*")), ["File.txt"]);
+        assert!(find(&root.join("Dir\\missing*")).is_empty());
+
+        assert_eq!(
+            fs::read_dir(root.join("missing")).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+
+        projection.stop();
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn placeholder_info() {
+        let root = temp_root("placeholder_info");
+        let projection = project(memory_fs(), &root).unwrap();
+
+        let metadata = fs::metadata(root.join("Dir/File.txt")).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.len(), 11);
+        assert!(fs::metadata(root.join("Dir/sub")).unwrap().is_dir());
+        assert_eq!(fs::metadata(root.join("Dir/empty.txt")).unwrap().len(), 0);
+
+        // the placeholder is named with the case of the entry in the filesystem
+        assert_eq!(fs::metadata(root.join("DIR/FILE.TXT")).unwrap().len(), 11);
+        assert_eq!(names(&root.join("Dir"))[0], "File.txt");
+
+        for missing in ["missing", "Dir/missing", "Dir/sub/missing"] {
+            assert_eq!(
+                fs::metadata(root.join(missing)).unwrap_err().kind(),
+                ErrorKind::NotFound
+            );
+        }
+
+        projection.stop();
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn file_data() {
+        let root = temp_root("file_data");
+        let projection = project(memory_fs(), &root).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(root.join("Dir/File.txt")).unwrap(),
+            "hello world"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("dir/file.txt")).unwrap(),
+            "hello world"
+        );
+        assert!(fs::read(root.join("Dir/empty.txt")).unwrap().is_empty());
+        assert_eq!(fs::read(root.join("Dir/big.bin")).unwrap(), big());
+
+        // files that were read stay on the disk
+        projection.stop();
+        assert_eq!(
+            fs::read_to_string(root.join("Dir/File.txt")).unwrap(),
+            "hello world"
+        );
+
+        // a root that was projected into before can be projected into again
+        let projection = project(memory_fs(), &root).unwrap();
+        assert_eq!(names(&root.join("Dir")).len(), 4);
+        assert_eq!(fs::read(root.join("Dir/big.bin")).unwrap(), big());
+        projection.stop();
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn conversions() {
+        let root = temp_root("conversions");
+        assert_eq!(guid(&instance_id(&root)), guid(&instance_id(&root)));
+        assert_eq!(
+            guid(&GUID::from_u128(0x0123_4567_89ab_cdef_fedc_ba98_7654_3210)),
+            0x0123_4567_89ab_cdef_fedc_ba98_7654_3210
+        );
+
+        assert_eq!(win32_error(ERROR_FILE_NOT_FOUND), 0x8007_0002_u32 as i32);
+        assert_eq!(
+            io_error(&io::Error::from(ErrorKind::PermissionDenied)),
+            win32_error(ERROR_ACCESS_DENIED)
+        );
+        assert_eq!(io_error(&io::Error::other("other")), E_FAIL);
+
+        assert_eq!(file_time(UNIX_EPOCH), UNIX_EPOCH_FILE_TIME);
+        assert_eq!(
+            file_time(UNIX_EPOCH + Duration::from_secs(1)),
+            UNIX_EPOCH_FILE_TIME + 10_000_000
+        );
+        assert_eq!(file_time(UNIX_EPOCH - Duration::from_secs(1)), 0);
+
+        let info = basic_info(&Metadata::directory());
+        assert_eq!(
+            (info.IsDirectory, info.FileSize, info.FileAttributes),
+            (1, 0, FILE_ATTRIBUTE_DIRECTORY)
+        );
+        let mut metadata = Metadata::file(11);
+        metadata.modified = Some(UNIX_EPOCH);
+        let info = basic_info(&metadata);
+        assert_eq!(
+            (info.IsDirectory, info.FileSize, info.FileAttributes),
+            (0, 11, FILE_ATTRIBUTE_NORMAL)
+        );
+        assert_eq!(info.LastWriteTime, UNIX_EPOCH_FILE_TIME);
+        metadata.mode = Some(0o444);
+        assert_eq!(
+            basic_info(&metadata).FileAttributes,
+            FILE_ATTRIBUTE_READONLY
+        );
+    }
+}
@@ -0,0 +1,617 @@
+use crate::error::{VfsError, VfsErrorKind};
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::invalid_path;
+use crate::FileSystem;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+/// The limits of a `QuotaFS`. Unset limits aren't enforced.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Quota {
+    /// The maximum number of bytes stored across all files.
+    pub max_bytes: Option<u64>,
+    /// The maximum number of files.
+    pub max_files: Option<u64>,
+}
+
+/// The storage used within a `QuotaFS`.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Usage {
+    /// The number of bytes stored across all files.
+    pub bytes: u64,
+    /// The number of files.
+    pub files: u64,
+}
+
+/// The quota and usage shared between the filesystem and its open files.
+struct Accounting {
+    quota: Quota,
+    usage: Usage,
+    /// The lengths of the files open for writing by their normalized paths, which are shared between the handles of
+    /// each file so that its growth is only counted once.
+    lens: HashMap<String, Arc<Mutex<u64>>>,
+}
+
+impl Accounting {
+    /// Accounts for `bytes` more bytes, failing if they would exceed the quota.
+    ///
+    /// # Arguments
+    /// `bytes`: The number of bytes to add.  
+    fn add_bytes(&mut self, bytes: u64) -> io::Result<()> {
+        let used = self.usage.bytes.saturating_add(bytes);
+        if self
+            .quota
+            .max_bytes
+            .is_some_and(|max_bytes| used > max_bytes)
+        {
+            return Err(quota_exceeded("Byte quota exceeded"));
+        }
+
+        self.usage.bytes = used;
+        Ok(())
+    }
+
+    /// Accounts for one more file, failing if it would exceed the quota.
+    fn add_file(&mut self) -> io::Result<()> {
+        if self
+            .quota
+            .max_files
+            .is_some_and(|max_files| self.usage.files >= max_files)
+        {
+            return Err(quota_exceeded("File quota exceeded"));
+        }
+
+        self.usage.files += 1;
+        Ok(())
+    }
+
+    /// Releases the usage of removed files.
+    ///
+    /// # Arguments
+    /// `usage`: The usage of the removed files.  
+    fn release(&mut self, usage: Usage) {
+        self.usage.bytes = self.usage.bytes.saturating_sub(usage.bytes);
+        self.usage.files = self.usage.files.saturating_sub(usage.files);
+    }
+}
+
+/// A filesystem that limits the number of bytes and files stored in another filesystem, such as a directory of user
+/// uploads. Writes that would exceed the quota fail with `QuotaExceeded`, without writing anything.
+///
+/// The usage is computed by walking the inner filesystem when the quota filesystem is created, and is kept up to date
+/// as files are written, truncated, replaced and removed through it. Changes made to the inner filesystem directly
+/// aren't accounted for until `recount` is called.
+pub struct QuotaFS<FS> {
+    inner: FS,
+    accounting: Arc<Mutex<Accounting>>,
+}
+
+impl<FS: FileSystem> QuotaFS<FS> {
+    /// Creates a new quota filesystem, counting the usage of the files already present. Existing files are never
+    /// rejected, even if they exceed the quota.
+    ///
+    /// # Arguments
+    /// `inner`: The filesystem to limit.  
+    /// `quota`: The limits to enforce.  
+    pub fn new(inner: FS, quota: Quota) -> crate::Result<Self> {
+        let usage = usage_of(&inner, "")?;
+        Ok(Self {
+            inner,
+            accounting: Arc::new(Mutex::new(Accounting {
+                quota,
+                usage,
+                lens: HashMap::new(),
+            })),
+        })
+    }
+
+    /// Returns the limited filesystem.
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Returns the enforced limits.
+    pub fn quota(&self) -> Quota {
+        self.accounting.lock().quota
+    }
+
+    /// Replaces the enforced limits. Lowering a limit below the current usage only rejects further growth.
+    ///
+    /// # Arguments
+    /// `quota`: The new limits.  
+    pub fn set_quota(&self, quota: Quota) {
+        self.accounting.lock().quota = quota;
+    }
+
+    /// Returns the current usage.
+    pub fn usage(&self) -> Usage {
+        self.accounting.lock().usage
+    }
+
+    /// Recounts the usage by walking the inner filesystem.
+    pub fn recount(&self) -> crate::Result<()> {
+        let usage = usage_of(&self.inner, "")?;
+        self.accounting.lock().usage = usage;
+        Ok(())
+    }
+
+    /// Returns the metadata of the file at `path`, or `None` if there is no file there.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file.  
+    fn file_metadata(&self, path: &str) -> crate::Result<Option<Metadata>> {
+        match self.inner.metadata(path) {
+            Ok(metadata) if metadata.is_file() => Ok(Some(metadata)),
+            Ok(_) => Ok(None),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl<FS: FileSystem> FileSystem for QuotaFS<FS> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.inner.create_dir(path)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.inner.metadata(path)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        if !options.writable() {
+            return self.inner.open_file_options(path, options);
+        }
+
+        let existing = self.file_metadata(path)?;
+        let creates = existing.is_none() && (options.create || options.create_new);
+        if creates {
+            // the file is reserved before it's created, so that concurrent creations can't exceed the quota
+            self.accounting.lock().add_file()?;
+        }
+
+        let file = match self.inner.open_file_options(path, options) {
+            Ok(file) => file,
+            Err(err) => {
+                if creates {
+                    self.accounting.lock().release(Usage { bytes: 0, files: 1 });
+                }
+                return Err(err);
+            }
+        };
+
+        // handles that are already open know the length better than the inner filesystem
+        let path = normalized(path)?;
+        let len = self
+            .accounting
+            .lock()
+            .lens
+            .entry(path.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(existing.map_or(0, |metadata| metadata.len))))
+            .clone();
+        let file = QuotaFile {
+            inner: file,
+            accounting: self.accounting.clone(),
+            path,
+            len,
+            position: 0,
+            append: options.append,
+        };
+        if options.truncate {
+            let mut len = file.len.lock();
+            self.accounting.lock().release(Usage {
+                bytes: *len,
+                files: 0,
+            });
+            *len = 0;
+        }
+
+        Ok(Box::new(file))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        self.inner.read_dir(path)
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        // some filesystems remove directories along with their contents
+        let usage = usage_of(&self.inner, path)?;
+        self.inner.remove_dir(path)?;
+        self.accounting.lock().release(usage);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        let metadata = self.file_metadata(path)?;
+        self.inner.remove_file(path)?;
+        if let Some(metadata) = metadata {
+            self.accounting.lock().release(Usage {
+                bytes: metadata.len,
+                files: 1,
+            });
+        }
+        Ok(())
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        self.inner.create_dir_with(path, mode)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        // a file renamed onto itself isn't replaced
+        let replaced = if normalized(from)? == normalized(to)? {
+            None
+        } else {
+            self.file_metadata(to)?
+        };
+        self.inner.rename(from, to)?;
+        if let Some(metadata) = replaced {
+            self.accounting.lock().release(Usage {
+                bytes: metadata.len,
+                files: 1,
+            });
+        }
+        Ok(())
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        self.inner.stats()
+    }
+}
+
+/// A file opened for writing, which accounts for its growth before it's written. The length is locked while the file
+/// is written, so that handles of the same file don't count the same growth twice.
+struct QuotaFile {
+    inner: Box<dyn File>,
+    accounting: Arc<Mutex<Accounting>>,
+    path: String,
+    len: Arc<Mutex<u64>>,
+    position: u64,
+    append: bool,
+}
+
+impl Drop for QuotaFile {
+    fn drop(&mut self) {
+        // the accounting holds the length as long as another handle does
+        let mut accounting = self.accounting.lock();
+        if Arc::strong_count(&self.len) == 2 {
+            accounting.lens.remove(&self.path);
+        }
+    }
+}
+
+impl Read for QuotaFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_len = self.inner.read(buf)?;
+        self.position += read_len as u64;
+        Ok(read_len)
+    }
+}
+
+impl Seek for QuotaFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = self.inner.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+impl Write for QuotaFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut len = self.len.lock();
+        if self.append {
+            self.position = *len;
+        }
+
+        // growth is reserved up front, and whatever wasn't written is released afterwards
+        let reserved = (self.position + buf.len() as u64).saturating_sub(*len);
+        self.accounting.lock().add_bytes(reserved)?;
+
+        let result = self.inner.write(buf);
+        let written = *result.as_ref().unwrap_or(&0) as u64;
+        let end = self.position + written;
+        let grown = end.saturating_sub(*len);
+        self.accounting.lock().release(Usage {
+            bytes: reserved - grown,
+            files: 0,
+        });
+
+        self.position = end;
+        *len = len.max(end);
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl File for QuotaFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        self.inner.metadata()
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        let mut current = self.len.lock();
+        // growth is reserved before the file is extended, and shrinking is released once it's done
+        let grown = len.saturating_sub(*current);
+        self.accounting.lock().add_bytes(grown)?;
+        if let Err(err) = self.inner.set_len(len) {
+            self.accounting.lock().release(Usage {
+                bytes: grown,
+                files: 0,
+            });
+            return Err(err);
+        }
+
+        self.accounting.lock().release(Usage {
+            bytes: current.saturating_sub(len),
+            files: 0,
+        });
+        *current = len;
+        Ok(())
+    }
+}
+
+/// Returns the usage of the files beneath a directory.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `path`: The path of the directory.  
+fn usage_of<FS: FileSystem + ?Sized>(fs: &FS, path: &str) -> crate::Result<Usage> {
+    let mut usage = Usage::default();
+    for entry in fs.walk_dir(path)? {
+        let entry = entry?;
+        if entry.metadata.is_file() {
+            usage.bytes += entry.metadata.len;
+            usage.files += 1;
+        }
+    }
+
+    Ok(usage)
+}
+
+/// Returns the normalized form of `path`, which identifies a file regardless of how its path is written.
+///
+/// # Arguments
+/// `path`: The path.  
+fn normalized(path: &str) -> crate::Result<String> {
+    normalize_and_relativize(path)
+        .to_str()
+        .map(str::to_owned)
+        .ok_or_else(invalid_path)
+}
+
+/// Returns an error indicating that a quota was exceeded.
+///
+/// # Arguments
+/// `error`: The description of the error.  
+fn quota_exceeded(error: &str) -> io::Error {
+    VfsError::new(VfsErrorKind::QuotaExceeded, error).into()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::{ErrorExt, VfsErrorKind};
+    use crate::file::OpenOptions;
+    use crate::kv_fs::KvFS;
+    use crate::memory_fs::MemoryFS;
+    use crate::quota_fs::{Quota, QuotaFS, Usage};
+    use crate::util::test::{check_open_options, check_set_len};
+    use crate::FileSystem;
+    use parking_lot::RwLock;
+    use std::collections::BTreeMap;
+    use std::io::{ErrorKind, Seek, SeekFrom, Write};
+
+    fn quota_fs(max_bytes: u64, max_files: u64) -> QuotaFS<MemoryFS> {
+        let inner = MemoryFS::default();
+        inner.create_dir_all("uploads/old").unwrap();
+        inner
+            .create_file("uploads/old/file")
+            .unwrap()
+            .write_all(&[0; 100])
+            .unwrap();
+
+        QuotaFS::new(
+            inner,
+            Quota {
+                max_bytes: Some(max_bytes),
+                max_files: Some(max_files),
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn counts_usage() {
+        let fs = quota_fs(1000, 10);
+        assert_eq!(
+            fs.usage(),
+            Usage {
+                bytes: 100,
+                files: 1
+            }
+        );
+
+        fs.create_file("a").unwrap().write_all(&[1; 300]).unwrap();
+        assert_eq!(
+            fs.usage(),
+            Usage {
+                bytes: 400,
+                files: 2
+            }
+        );
+
+        // overwriting in place doesn't grow, and extending past the end does
+        let mut file = fs
+            .open_file_options("a", &OpenOptions::default().write(true))
+            .unwrap();
+        file.write_all(&[2; 200]).unwrap();
+        file.seek(SeekFrom::Start(250)).unwrap();
+        file.write_all(&[2; 100]).unwrap();
+        drop(file);
+        assert_eq!(
+            fs.usage(),
+            Usage {
+                bytes: 450,
+                files: 2
+            }
+        );
+
+        // truncating, replacing and removing release usage
+        fs.create_file("a").unwrap().write_all(&[3; 10]).unwrap();
+        assert_eq!(
+            fs.usage(),
+            Usage {
+                bytes: 110,
+                files: 2
+            }
+        );
+        fs.copy_file("a", "b").unwrap();
+        fs.rename("b", "uploads/old/file").unwrap();
+        assert_eq!(
+            fs.usage(),
+            Usage {
+                bytes: 20,
+                files: 2
+            }
+        );
+        fs.remove_file("a").unwrap();
+        fs.remove_dir("uploads/old").unwrap();
+        assert_eq!(fs.usage(), Usage::default());
+    }
+
+    #[test]
+    fn rename_onto_itself() {
+        let fs = quota_fs(1000, 10);
+        for to in ["uploads/old/file", "/uploads/./old/file"] {
+            fs.rename("uploads/old/file", to).unwrap();
+            assert_eq!(
+                fs.usage(),
+                Usage {
+                    bytes: 100,
+                    files: 1
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn shared_growth() {
+        // the memory filesystem locks open files, so the handles are opened on a key-value store
+        let fs = QuotaFS::new(
+            KvFS::new(RwLock::<BTreeMap<_, _>>::default()),
+            Quota::default(),
+        )
+        .unwrap();
+        let options = OpenOptions::default().write(true).create(true);
+        let mut first = fs.open_file_options("file", &options).unwrap();
+        let mut second = fs.open_file_options("./file", &options).unwrap();
+
+        // the same growth through either handle is only counted once
+        first.write_all(&[1; 100]).unwrap();
+        second.write_all(&[2; 150]).unwrap();
+        first.set_len(120).unwrap();
+        assert_eq!(
+            fs.usage(),
+            Usage {
+                bytes: 120,
+                files: 1
+            }
+        );
+
+        // appends land at the end that the other handle wrote
+        drop(first);
+        let mut appended = fs
+            .open_file_options("file", &OpenOptions::default().append(true))
+            .unwrap();
+        appended.write_all(&[3; 10]).unwrap();
+        assert_eq!(fs.usage().bytes, 130);
+        drop((second, appended));
+        assert_eq!(fs.metadata("file").unwrap().len(), 130);
+    }
+
+    #[test]
+    fn enforces_quota() {
+        let fs = quota_fs(500, 3);
+
+        let mut file = fs.create_file("a").unwrap();
+        file.write_all(&[0; 350]).unwrap();
+        let err = file.write_all(&[0; 100]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::QuotaExceeded);
+        file.write_all(&[0; 50]).unwrap();
+        drop(file);
+        assert_eq!(fs.metadata("a").unwrap().len(), 400);
+        assert_eq!(fs.usage().bytes, 500);
+
+        fs.create_file("b").unwrap();
+        let err = fs.create_file("c").err().unwrap();
+        assert_eq!(err.vfs_kind(), Some(VfsErrorKind::QuotaExceeded));
+        assert!(!fs.exists("c").unwrap());
+
+        // raising the quota allows further growth
+        fs.set_quota(Quota {
+            max_bytes: None,
+            max_files: None,
+        });
+        fs.create_file("c").unwrap().write_all(&[0; 1000]).unwrap();
+        assert_eq!(
+            fs.usage(),
+            Usage {
+                bytes: 1500,
+                files: 4
+            }
+        );
+    }
+
+    #[test]
+    fn recount() {
+        let fs = quota_fs(1000, 10);
+
+        fs.inner()
+            .create_file("uploads/direct")
+            .unwrap()
+            .write_all(&[0; 50])
+            .unwrap();
+        assert_eq!(fs.usage().files, 1);
+        fs.recount().unwrap();
+        assert_eq!(
+            fs.usage(),
+            Usage {
+                bytes: 150,
+                files: 2
+            }
+        );
+    }
+
+    #[test]
+    fn open_options() {
+        check_open_options(&quota_fs(1000, 10), "uploads/options");
+    }
+
+    #[test]
+    fn set_len() {
+        let fs = quota_fs(1000, 10);
+        check_set_len(&fs, "uploads/set_len");
+        assert_eq!(fs.usage().bytes, 100);
+
+        // resizing counts against the quota, like writing does
+        let mut file = fs.create_file("uploads/file").unwrap();
+        file.set_len(900).unwrap();
+        assert_eq!(fs.usage().bytes, 1000);
+        assert_eq!(
+            file.set_len(901).unwrap_err().kind(),
+            ErrorKind::QuotaExceeded
+        );
+        file.set_len(400).unwrap();
+        assert_eq!(fs.usage().bytes, 500);
+        drop(file);
+        assert_eq!(fs.metadata("uploads/file").unwrap().len(), 400);
+    }
+}
@@ -0,0 +1,280 @@
+use crate::util::{invalid_input, not_found, not_supported};
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+/// The default number of bytes fetched per range request.
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A reader over a remote resource that fetches its contents lazily through HTTP range requests.
+/// Only the chunks that are actually read are downloaded, so a `ZipFS` mounted on a `RangeReader`
+/// fetches the central directory and the entries that are opened, rather than the whole archive.
+///
+/// Cloning the reader is cheap and each clone has an independent cursor, so it can be mounted
+/// with `ZipFS::new_cloneable`.
+#[derive(Clone)]
+pub struct RangeReader {
+    agent: ureq::Agent,
+    url: String,
+    len: u64,
+    pos: u64,
+    chunk: Vec<u8>,
+    chunk_start: u64,
+    chunk_size: usize,
+}
+
+impl RangeReader {
+    /// Creates a new range reader over the resource at `url`. The server must support range
+    /// requests.
+    ///
+    /// # Arguments
+    /// `url`: The URL of the resource.  
+    pub fn new<S: Into<String>>(url: S) -> crate::Result<Self> {
+        Self::with_agent(ureq::Agent::new(), url)
+    }
+
+    /// Creates a new range reader over the resource at `url`, issuing requests through `agent`.
+    ///
+    /// # Arguments
+    /// `agent`: The agent used to issue requests.  
+    /// `url`: The URL of the resource.  
+    pub fn with_agent<S: Into<String>>(agent: ureq::Agent, url: S) -> crate::Result<Self> {
+        let url = url.into();
+
+        // probe the first byte to find the total length and confirm range support
+        let response = agent
+            .get(&url)
+            .set("Range", "bytes=0-0")
+            .call()
+            .map_err(convert_error)?;
+        let len = total_len(&response)?;
+
+        Ok(Self {
+            agent,
+            url,
+            len,
+            pos: 0,
+            chunk: Vec::new(),
+            chunk_start: 0,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        })
+    }
+
+    /// Sets the size of the chunks fetched from the remote resource.
+    ///
+    /// # Arguments
+    /// `chunk_size`: The minimum number of bytes fetched per range request. Larger chunks mean
+    /// fewer round trips at the cost of fetching data that may not be needed.  
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Returns the total length of the remote resource, in bytes.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Fetches the chunk starting at `start`.
+    fn fetch_chunk(&mut self, start: u64) -> io::Result<()> {
+        let end = start.saturating_add(self.chunk_size as u64).min(self.len) - 1;
+        let response = self
+            .agent
+            .get(&self.url)
+            .set("Range", &format!("bytes={start}-{end}"))
+            .call()
+            .map_err(convert_error)?;
+
+        // a server ignoring the range would send the whole resource
+        if response.status() != 206 {
+            return Err(not_supported());
+        }
+
+        self.chunk.clear();
+        response.into_reader().read_to_end(&mut self.chunk)?;
+        self.chunk_start = start;
+
+        Ok(())
+    }
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+
+        // fetch a new chunk if the position is outside of the current one
+        let chunk_end = self.chunk_start + self.chunk.len() as u64;
+        if self.pos < self.chunk_start || self.pos >= chunk_end {
+            self.fetch_chunk(self.pos)?;
+        }
+
+        let offset = (self.pos - self.chunk_start) as usize;
+        let n = (&self.chunk[offset..]).read(buf)?;
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl Seek for RangeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base_pos, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::Current(n) => (self.pos, n),
+            SeekFrom::End(n) => (self.len, n),
+        };
+
+        if let Some(n) = base_pos.checked_add_signed(offset) {
+            self.pos = n;
+            Ok(n)
+        } else {
+            Err(invalid_input(
+                "Invalid seek to a negative or overflowing position",
+            ))
+        }
+    }
+}
+
+/// Parses the total length of the resource from a `Content-Range` header.
+fn total_len(response: &ureq::Response) -> crate::Result<u64> {
+    if response.status() != 206 {
+        return Err(not_supported());
+    }
+
+    response
+        .header("Content-Range")
+        .and_then(|range| range.rsplit_once('/'))
+        .and_then(|(_, len)| len.parse().ok())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Invalid Content-Range header"))
+}
+
+/// Converts a request error to an IO error.
+pub(crate) fn convert_error(err: ureq::Error) -> io::Error {
+    convert_error_with(err, |code, response| {
+        io::Error::other(ureq::Error::Status(code, response))
+    })
+}
+
+/// Converts a request error to an IO error, decoding the response body of error statuses that
+/// aren't common to every service.
+///
+/// # Arguments
+/// `err`: The request error.  
+/// `decode_body`: Converts the status code and response of the error to an IO error.  
+pub(crate) fn convert_error_with<D: FnOnce(u16, ureq::Response) -> io::Error>(
+    err: ureq::Error,
+    decode_body: D,
+) -> io::Error {
+    match err {
+        ureq::Error::Status(404, _) => not_found(),
+        ureq::Error::Status(416, _) => invalid_input("Range not satisfiable"),
+        ureq::Error::Status(code, response) => decode_body(code, response),
+        err => io::Error::other(err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::range_reader::RangeReader;
+    use crate::zip_fs::ZipFS;
+    use crate::FileSystem;
+    use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::{fs, thread};
+
+    /// Serves `contents` over HTTP with range support, returning the URL and the request counter.
+    fn serve(contents: Vec<u8>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/archive.zip", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        thread::spawn({
+            let requests = requests.clone();
+            move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                    // find the range header
+                    let mut range = None;
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                            break;
+                        }
+                        if let Some(value) = line.to_lowercase().strip_prefix("range: bytes=") {
+                            let (start, end) = value.trim().split_once('-').unwrap();
+                            range = Some((
+                                start.parse::<usize>().unwrap(),
+                                end.parse::<usize>().unwrap(),
+                            ));
+                        }
+                    }
+                    requests.fetch_add(1, Ordering::SeqCst);
+
+                    let (start, end) = range.unwrap();
+                    let end = end.min(contents.len() - 1);
+                    let body = &contents[start..=end];
+                    write!(
+                        stream,
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {start}-{end}/{}\r\nConnection: close\r\n\r\n",
+                        body.len(),
+                        contents.len()
+                    )
+                    .unwrap();
+                    stream.write_all(body).unwrap();
+                }
+            }
+        });
+
+        (url, requests)
+    }
+
+    #[test]
+    fn read_and_seek() {
+        let contents = (0..=255u8).cycle().take(1000).collect::<Vec<_>>();
+        let (url, _) = serve(contents.clone());
+
+        let mut reader = RangeReader::new(url).unwrap().chunk_size(64);
+        assert_eq!(reader.len(), 1000);
+
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, contents);
+
+        reader.seek(SeekFrom::End(-10)).unwrap();
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &contents[990..]);
+
+        reader.seek(SeekFrom::Start(300)).unwrap();
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, &contents[300..304]);
+    }
+
+    #[test]
+    fn mount_zip() {
+        let (url, requests) = serve(fs::read("test/deep_fs.zip").unwrap());
+
+        let fs = ZipFS::new_cloneable(RangeReader::new(url).unwrap().chunk_size(512)).unwrap();
+        let mounted_requests = requests.load(Ordering::SeqCst);
+
+        let nested_file = fs
+            .open_file("folder/and/it/goes/deeper/desc")
+            .unwrap()
+            .read_into_string()
+            .unwrap();
+        assert_eq!(nested_file, "deeper\n");
+
+        // only the chunks around the entry should have been fetched
+        assert!(requests.load(Ordering::SeqCst) - mounted_requests <= 2);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::util::read_only;
+use crate::FileSystem;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// A filesystem that exposes another filesystem read-only, so that a writable filesystem can be handed out without
+/// it being modified. Every method that would modify the filesystem fails with `ReadOnlyFilesystem`, as does opening a
+/// file for writing, appending, creation or truncation.
+pub struct ReadOnlyFS<FS> {
+    inner: FS,
+}
+
+impl<FS: FileSystem> ReadOnlyFS<FS> {
+    /// Creates a new read-only filesystem.
+    ///
+    /// # Arguments
+    /// `inner`: The filesystem to expose.  
+    pub fn new(inner: FS) -> Self {
+        Self { inner }
+    }
+
+    /// Returns the exposed filesystem.
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Consumes the read-only filesystem, returning the exposed filesystem.
+    pub fn into_inner(self) -> FS {
+        self.inner
+    }
+}
+
+impl<FS: FileSystem> FileSystem for ReadOnlyFS<FS> {
+    fn create_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.inner.metadata(path)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        if options.writable() {
+            return Err(read_only());
+        }
+
+        // the inner file is opened with only the options that can't modify it
+        let options = OpenOptions::default()
+            .direct(options.direct)
+            .access(options.access);
+        Ok(Box::new(ReadOnlyFile(
+            self.inner.open_file_options(path, &options)?,
+        )))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        self.inner.read_dir(path)
+    }
+
+    fn remove_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn remove_file(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn create_dir_with(&self, _path: &str, _mode: u32) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn create_dir_all(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn create_file(&self, _path: &str) -> crate::Result<Box<dyn File>> {
+        Err(read_only())
+    }
+
+    fn exists(&self, path: &str) -> crate::Result<bool> {
+        self.inner.exists(path)
+    }
+
+    fn copy_file(&self, _from: &str, _to: &str) -> crate::Result<u64> {
+        Err(read_only())
+    }
+
+    fn rename(&self, _from: &str, _to: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn walk_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>> + '_>> {
+        self.inner.walk_dir(path)
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        self.inner.stats()
+    }
+}
+
+/// A file that can only be read, regardless of what the inner file allows.
+struct ReadOnlyFile(Box<dyn File>);
+
+impl Read for ReadOnlyFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Seek for ReadOnlyFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl Write for ReadOnlyFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl File for ReadOnlyFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        self.0.metadata()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::{ErrorExt, VfsErrorKind};
+    use crate::file::OpenOptions;
+    use crate::memory_fs::MemoryFS;
+    use crate::read_only_fs::ReadOnlyFS;
+    use crate::util::test::read_directory;
+    use crate::FileSystem;
+    use std::io::Write;
+
+    fn read_only_fs() -> ReadOnlyFS<MemoryFS> {
+        let fs = MemoryFS::default();
+        fs.create_dir_all("dir/nested").unwrap();
+        write!(fs.create_file("dir/file").unwrap(), "contents").unwrap();
+        ReadOnlyFS::new(fs)
+    }
+
+    #[test]
+    fn reads() {
+        let fs = read_only_fs();
+
+        itertools::assert_equal(
+            read_directory(&fs, "dir").keys(),
+            vec!["dir/file", "dir/nested"],
+        );
+        assert_eq!(fs.walk_dir("").unwrap().count(), 3);
+        assert!(fs.exists("dir/nested").unwrap());
+        assert_eq!(fs.metadata("dir/file").unwrap().len(), 8);
+        assert_eq!(
+            fs.open_file("dir/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "contents"
+        );
+    }
+
+    #[test]
+    fn rejects_modifications() {
+        let fs = read_only_fs();
+        let denied = |result: crate::Result<()>| {
+            assert_eq!(
+                result.err().unwrap().vfs_kind(),
+                Some(VfsErrorKind::ReadOnlyFilesystem)
+            )
+        };
+
+        denied(fs.create_dir("new"));
+        denied(fs.create_dir_with("new", 0o755));
+        denied(fs.create_dir_all("new/nested"));
+        denied(fs.create_file("new").map(drop));
+        denied(fs.remove_dir("dir/nested"));
+        denied(fs.remove_file("dir/file"));
+        denied(fs.copy_file("dir/file", "copy").map(drop));
+        denied(fs.rename("dir/file", "renamed"));
+        for options in [
+            OpenOptions::default().write(true),
+            OpenOptions::default().append(true),
+            OpenOptions::default().write(true).create(true),
+            OpenOptions::default().write(true).create_new(true),
+            OpenOptions::default().write(true).truncate(true),
+        ] {
+            denied(fs.open_file_options("dir/file", &options).map(drop));
+        }
+        denied(fs.open_file("dir/file").unwrap().write_all(b"sneaky"));
+
+        assert_eq!(fs.walk_dir("").unwrap().count(), 3);
+        assert_eq!(
+            fs.open_file("dir/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "contents"
+        );
+    }
+}
@@ -0,0 +1,406 @@
+//! The protocol that `RemoteFS` and `serve_remote` speak, which carries the operations of the `FileSystem` trait over
+//! any reliable byte stream.
+//!
+//! Every message is a little-endian `u32` length, followed by the type of the message and its body. The client sends
+//! `HELLO` with its authentication token first, and then one request at a time, each of which the server answers
+//! with `OK` and the result of the operation, or `ERROR` with the kind and description of the error. Files opened by
+//! `OPEN` are held open on the server and read and written by their handles in chunks, so large files are streamed
+//! rather than transferred at once.
+
+mod server;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+use crate::file::{AccessPattern, FileType, Metadata, OpenOptions};
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub use server::{
+    serve_remote, serve_remote_authenticated, serve_remote_connection, Authenticator,
+};
+
+/// The magic number that starts `HELLO`.
+pub(crate) const MAGIC: u32 = u32::from_le_bytes(*b"VFSR");
+/// The version of the protocol spoken.
+pub(crate) const VERSION: u32 = 2;
+/// The most bytes a single read or write transfers.
+pub(crate) const MAX_IO_LEN: u32 = 1024 * 1024;
+/// The largest message that's accepted, which fits the largest read or write and long directory listings.
+pub(crate) const MAX_MESSAGE_LEN: u32 = 16 * 1024 * 1024;
+
+/// The type of a message.
+pub(crate) mod message {
+    pub(crate) const HELLO: u8 = 1;
+    pub(crate) const CREATE_DIR: u8 = 2;
+    pub(crate) const METADATA: u8 = 3;
+    pub(crate) const OPEN: u8 = 4;
+    pub(crate) const READ: u8 = 5;
+    pub(crate) const WRITE: u8 = 6;
+    pub(crate) const FLUSH: u8 = 7;
+    pub(crate) const FILE_METADATA: u8 = 8;
+    pub(crate) const CLOSE: u8 = 9;
+    pub(crate) const READ_DIR: u8 = 10;
+    pub(crate) const REMOVE_DIR: u8 = 11;
+    pub(crate) const REMOVE_FILE: u8 = 12;
+    pub(crate) const RENAME: u8 = 13;
+    pub(crate) const STATS: u8 = 14;
+    pub(crate) const OK: u8 = 128;
+    pub(crate) const ERROR: u8 = 129;
+}
+
+/// The flags of `OPEN`.
+pub(crate) mod open_flags {
+    pub(crate) const READ: u8 = 0x1;
+    pub(crate) const WRITE: u8 = 0x2;
+    pub(crate) const APPEND: u8 = 0x4;
+    pub(crate) const CREATE: u8 = 0x8;
+    pub(crate) const TRUNCATE: u8 = 0x10;
+    pub(crate) const DIRECT: u8 = 0x20;
+    pub(crate) const CREATE_NEW: u8 = 0x40;
+}
+
+/// The kinds of errors that are carried by `ERROR`, by their codes. Unknown codes are `Other`.
+const ERROR_KINDS: [ErrorKind; 20] = [
+    ErrorKind::Other,
+    ErrorKind::NotFound,
+    ErrorKind::PermissionDenied,
+    ErrorKind::AlreadyExists,
+    ErrorKind::NotADirectory,
+    ErrorKind::IsADirectory,
+    ErrorKind::DirectoryNotEmpty,
+    ErrorKind::InvalidInput,
+    ErrorKind::InvalidData,
+    ErrorKind::Unsupported,
+    ErrorKind::ReadOnlyFilesystem,
+    ErrorKind::StorageFull,
+    ErrorKind::QuotaExceeded,
+    ErrorKind::CrossesDevices,
+    ErrorKind::UnexpectedEof,
+    ErrorKind::TimedOut,
+    ErrorKind::Interrupted,
+    ErrorKind::WouldBlock,
+    ErrorKind::ConnectionRefused,
+    ErrorKind::FileTooLarge,
+];
+
+/// Encodes a message.
+pub(crate) struct Encoder(Vec<u8>);
+
+impl Encoder {
+    /// Starts a message.
+    ///
+    /// # Arguments
+    /// `ty`: The type of the message.  
+    pub(crate) fn new(ty: u8) -> Self {
+        // the length is filled in when the message is finished
+        let mut encoder = Self(vec![0; 4]);
+        encoder.u8(ty);
+        encoder
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) -> &mut Self {
+        self.0.push(value);
+        self
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    /// Appends an optional value, prefixed with whether it's present.
+    ///
+    /// # Arguments
+    /// `value`: The value.  
+    /// `f`: Encodes the value if it's present.  
+    fn option<T>(&mut self, value: Option<T>, f: impl FnOnce(&mut Self, T)) -> &mut Self {
+        self.u8(value.is_some().into());
+        if let Some(value) = value {
+            f(self, value);
+        }
+        self
+    }
+
+    /// Appends data, prefixed with its length.
+    ///
+    /// # Arguments
+    /// `value`: The data.  
+    pub(crate) fn data(&mut self, value: &[u8]) -> &mut Self {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    /// Appends a string, prefixed with its length.
+    ///
+    /// # Arguments
+    /// `value`: The string.  
+    pub(crate) fn str(&mut self, value: &str) -> &mut Self {
+        self.data(value.as_bytes())
+    }
+
+    /// Appends the metadata of an entry. Times before the Unix epoch and link targets that aren't UTF-8 are left
+    /// out, as are extensions.
+    ///
+    /// # Arguments
+    /// `metadata`: The metadata.  
+    pub(crate) fn metadata(&mut self, metadata: &Metadata) -> &mut Self {
+        self.u8(match metadata.file_type {
+            FileType::Directory => 0,
+            FileType::File => 1,
+            FileType::Unknown => 2,
+        })
+        .u64(metadata.len)
+        .option(metadata.mode, |encoder, mode| {
+            encoder.u32(mode);
+        })
+        .time(metadata.modified)
+        .time(metadata.accessed)
+        .time(metadata.created)
+        .option(metadata.uid, |encoder, uid| {
+            encoder.u32(uid);
+        })
+        .option(metadata.gid, |encoder, gid| {
+            encoder.u32(gid);
+        })
+        .option(
+            metadata
+                .link_target
+                .as_ref()
+                .and_then(|target| target.to_str()),
+            |encoder, target| {
+                encoder.str(target);
+            },
+        )
+    }
+
+    /// Appends a time that may be missing. Times before the Unix epoch are left out.
+    ///
+    /// # Arguments
+    /// `time`: The time.  
+    fn time(&mut self, time: Option<SystemTime>) -> &mut Self {
+        self.option(
+            time.and_then(|time| time.duration_since(UNIX_EPOCH).ok()),
+            |encoder, time| {
+                encoder.u64(time.as_secs()).u32(time.subsec_nanos());
+            },
+        )
+    }
+
+    /// Appends the options a file is opened with.
+    ///
+    /// # Arguments
+    /// `options`: The options.  
+    pub(crate) fn open_options(&mut self, options: &OpenOptions) -> &mut Self {
+        let flags = [
+            (options.read, open_flags::READ),
+            (options.write, open_flags::WRITE),
+            (options.append, open_flags::APPEND),
+            (options.create, open_flags::CREATE),
+            (options.truncate, open_flags::TRUNCATE),
+            (options.direct, open_flags::DIRECT),
+            (options.create_new, open_flags::CREATE_NEW),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |flags, (_, flag)| flags | flag);
+
+        self.u8(flags)
+            .option(options.mode, |encoder, mode| {
+                encoder.u32(mode);
+            })
+            .u8(match options.access {
+                AccessPattern::Normal => 0,
+                AccessPattern::Sequential => 1,
+                AccessPattern::Random => 2,
+            })
+    }
+
+    /// Appends an error, as its code and its description.
+    ///
+    /// # Arguments
+    /// `err`: The error.  
+    pub(crate) fn error(&mut self, err: &io::Error) -> &mut Self {
+        let code = ERROR_KINDS
+            .iter()
+            .position(|kind| *kind == err.kind())
+            .unwrap_or_default();
+        self.u8(code as u8).str(&err.to_string())
+    }
+
+    /// Finishes the message, returning its bytes.
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        let mut message = std::mem::take(&mut self.0);
+        let len = message.len() as u32 - 4;
+        message[..4].copy_from_slice(&len.to_le_bytes());
+        message
+    }
+}
+
+/// Decodes the body of a message.
+pub(crate) struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    /// Creates a decoder of the body of a message.
+    ///
+    /// # Arguments
+    /// `body`: The body of the message, after its type.  
+    pub(crate) fn new(body: &'a [u8]) -> Self {
+        Self(body)
+    }
+
+    /// Takes `len` bytes from the body.
+    ///
+    /// # Arguments
+    /// `len`: The number of bytes.  
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Truncated remote filesystem message",
+            ));
+        }
+
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Takes an optional value prefixed with whether it's present.
+    ///
+    /// # Arguments
+    /// `f`: Decodes the value if it's present.  
+    fn option<T>(&mut self, f: impl FnOnce(&mut Self) -> io::Result<T>) -> io::Result<Option<T>> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => f(self).map(Some),
+        }
+    }
+
+    /// Takes data prefixed with its length.
+    pub(crate) fn data(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Takes a string prefixed with its length.
+    pub(crate) fn str(&mut self) -> io::Result<&'a str> {
+        std::str::from_utf8(self.data()?).map_err(|_| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                "Remote filesystem string isn't UTF-8",
+            )
+        })
+    }
+
+    /// Takes the metadata of an entry.
+    pub(crate) fn metadata(&mut self) -> io::Result<Metadata> {
+        let file_type = match self.u8()? {
+            0 => FileType::Directory,
+            1 => FileType::File,
+            _ => FileType::Unknown,
+        };
+        let mut metadata = Metadata::new(file_type, self.u64()?);
+        metadata.mode = self.option(Self::u32)?;
+        metadata.modified = self.time()?;
+        metadata.accessed = self.time()?;
+        metadata.created = self.time()?;
+        metadata.uid = self.option(Self::u32)?;
+        metadata.gid = self.option(Self::u32)?;
+        metadata.link_target = self.option(|decoder| decoder.str().map(PathBuf::from))?;
+        Ok(metadata)
+    }
+
+    /// Takes a time that may be missing.
+    fn time(&mut self) -> io::Result<Option<SystemTime>> {
+        self.option(|decoder| {
+            let duration = Duration::new(decoder.u64()?, decoder.u32()?);
+            Ok(UNIX_EPOCH + duration)
+        })
+    }
+
+    /// Takes the options a file is opened with.
+    pub(crate) fn open_options(&mut self) -> io::Result<OpenOptions> {
+        let flags = self.u8()?;
+        Ok(OpenOptions {
+            read: flags & open_flags::READ != 0,
+            write: flags & open_flags::WRITE != 0,
+            append: flags & open_flags::APPEND != 0,
+            create: flags & open_flags::CREATE != 0,
+            create_new: flags & open_flags::CREATE_NEW != 0,
+            truncate: flags & open_flags::TRUNCATE != 0,
+            direct: flags & open_flags::DIRECT != 0,
+            mode: self.option(Self::u32)?,
+            access: match self.u8()? {
+                1 => AccessPattern::Sequential,
+                2 => AccessPattern::Random,
+                _ => AccessPattern::Normal,
+            },
+        })
+    }
+
+    /// Takes an error.
+    pub(crate) fn error(&mut self) -> io::Result<io::Error> {
+        let kind = ERROR_KINDS
+            .get(self.u8()? as usize)
+            .copied()
+            .unwrap_or(ErrorKind::Other);
+        Ok(io::Error::new(kind, self.str()?))
+    }
+}
+
+/// Reads a message. Returns its type and body, or `None` if the stream ended before it.
+///
+/// # Arguments
+/// `reader`: The connection.  
+pub(crate) fn read_message<R: Read + ?Sized>(reader: &mut R) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut header = [0; 5];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap());
+    if !(1..=MAX_MESSAGE_LEN).contains(&len) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Remote filesystem message of {len} bytes is out of bounds"),
+        ));
+    }
+
+    let mut body = vec![0; len as usize - 1];
+    reader.read_exact(&mut body)?;
+    Ok(Some((header[4], body)))
+}
+
+/// Writes a message.
+///
+/// # Arguments
+/// `writer`: The connection.  
+/// `message`: The encoded message.  
+pub(crate) fn write_message<W: Write + ?Sized>(
+    writer: &mut W,
+    message: &mut Encoder,
+) -> io::Result<()> {
+    writer.write_all(&message.finish())?;
+    writer.flush()
+}
@@ -0,0 +1,286 @@
+use crate::file::File;
+use crate::remote::{
+    message, read_message, write_message, Decoder, Encoder, MAGIC, MAX_IO_LEN, VERSION,
+};
+use crate::FileSystem;
+use std::collections::HashMap;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::TcpListener;
+use std::thread;
+
+/// The most files a connection may hold open at once.
+const MAX_OPEN_FILES: usize = 1024;
+
+/// Decides which clients may use a filesystem served by `serve_remote_authenticated`.
+pub trait Authenticator {
+    /// Returns true if a client that presented `token` when it connected may use the filesystem.
+    fn authenticate(&self, token: &[u8]) -> bool;
+}
+
+impl<F: Fn(&[u8]) -> bool> Authenticator for F {
+    fn authenticate(&self, token: &[u8]) -> bool {
+        self(token)
+    }
+}
+
+/// Serves `fs` to `RemoteFS` clients on the TCP connections accepted by `listener` until accepting one fails. Every
+/// client may use the filesystem, whatever token it presents; see `serve_remote_authenticated` to check them.
+///
+/// # Arguments
+/// `fs`: The filesystem to serve.  
+/// `listener`: The listener to accept connections from.  
+pub fn serve_remote<FS: FileSystem + Sync>(fs: FS, listener: TcpListener) -> io::Result<()> {
+    serve_remote_authenticated(fs, listener, |_: &[u8]| true)
+}
+
+/// Serves `fs` to `RemoteFS` clients on the TCP connections accepted by `listener` until accepting one fails. Clients
+/// whose tokens are rejected by `authenticator` are disconnected.
+///
+/// # Arguments
+/// `fs`: The filesystem to serve.  
+/// `listener`: The listener to accept connections from.  
+/// `authenticator`: Decides which clients may use the filesystem by their tokens.  
+pub fn serve_remote_authenticated<FS: FileSystem + Sync, A: Authenticator + Sync>(
+    fs: FS,
+    listener: TcpListener,
+    authenticator: A,
+) -> io::Result<()> {
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            stream.set_nodelay(true)?;
+            let (fs, authenticator) = (&fs, &authenticator);
+            scope.spawn(move || serve_remote_connection(fs, authenticator, stream));
+        }
+        Ok(())
+    })
+}
+
+/// Serves `fs` to a single `RemoteFS` client on `stream` until it disconnects, such as over a TLS stream or a Unix
+/// socket. Returns an error if the client's token is rejected by `authenticator` or the stream fails.
+///
+/// # Arguments
+/// `fs`: The filesystem to serve.  
+/// `authenticator`: Decides whether the client may use the filesystem by its token.  
+/// `stream`: The connection to the client.  
+pub fn serve_remote_connection<
+    FS: FileSystem + ?Sized,
+    A: Authenticator + ?Sized,
+    S: Read + Write,
+>(
+    fs: &FS,
+    authenticator: &A,
+    mut stream: S,
+) -> io::Result<()> {
+    let Some((message::HELLO, body)) = read_message(&mut stream)? else {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "The client didn't say hello",
+        ));
+    };
+    let mut hello = Decoder::new(&body);
+    let accepted = if hello.u32()? != MAGIC {
+        Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "The client doesn't speak the remote filesystem protocol",
+        ))
+    } else if hello.u32()? != VERSION {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "Unsupported protocol version",
+        ))
+    } else if !authenticator.authenticate(hello.data()?) {
+        Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            "The token was rejected",
+        ))
+    } else {
+        Ok(())
+    };
+    if let Err(err) = accepted {
+        let _ = write_message(&mut stream, Encoder::new(message::ERROR).error(&err));
+        return Err(err);
+    }
+    write_message(&mut stream, &mut Encoder::new(message::OK))?;
+
+    let mut session = Session {
+        fs,
+        files: HashMap::new(),
+        next_handle: 0,
+    };
+    while let Some((ty, body)) = read_message(&mut stream)? {
+        let mut reply = Encoder::new(message::OK);
+        if let Err(err) = session.handle(ty, &mut Decoder::new(&body), &mut reply) {
+            reply = Encoder::new(message::ERROR);
+            reply.error(&err);
+        }
+        write_message(&mut stream, &mut reply)?;
+    }
+    Ok(())
+}
+
+/// The state of a connection.
+struct Session<'a, FS: ?Sized> {
+    fs: &'a FS,
+    /// The files the client holds open, by their handles.
+    files: HashMap<u64, OpenFile>,
+    next_handle: u64,
+}
+
+/// A file a client holds open.
+struct OpenFile {
+    file: Box<dyn File>,
+    /// True if the file was opened to append.
+    append: bool,
+}
+
+impl<FS: FileSystem + ?Sized> Session<'_, FS> {
+    /// Performs a request, encoding its result into `reply`.
+    ///
+    /// # Arguments
+    /// `ty`: The type of the request.  
+    /// `request`: The body of the request.  
+    /// `reply`: The reply, which is `OK` so far.  
+    fn handle(&mut self, ty: u8, request: &mut Decoder, reply: &mut Encoder) -> io::Result<()> {
+        match ty {
+            message::CREATE_DIR => {
+                let path = request.str()?;
+                match request.u8()? {
+                    0 => self.fs.create_dir(path)?,
+                    _ => self.fs.create_dir_with(path, request.u32()?)?,
+                }
+            }
+            message::METADATA => {
+                reply.metadata(&self.fs.metadata(request.str()?)?);
+            }
+            message::OPEN => {
+                if self.files.len() >= MAX_OPEN_FILES {
+                    return Err(io::Error::other("Too many open files"));
+                }
+                let path = request.str()?;
+                let options = request.open_options()?;
+                let file = self.fs.open_file_options(path, &options)?;
+                let handle = self.next_handle;
+                self.next_handle += 1;
+                self.files.insert(
+                    handle,
+                    OpenFile {
+                        file,
+                        append: options.append,
+                    },
+                );
+                reply.u64(handle);
+            }
+            message::READ => {
+                let file = &mut self.file(request.u64()?)?.file;
+                file.seek(SeekFrom::Start(request.u64()?))?;
+                let mut data = vec![0; request.u32()?.min(MAX_IO_LEN) as usize];
+                let mut filled = 0;
+                while filled < data.len() {
+                    match file.read(&mut data[filled..]) {
+                        Ok(0) => break,
+                        Ok(read_len) => filled += read_len,
+                        Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                reply.data(&data[..filled]);
+            }
+            message::WRITE => {
+                let OpenFile { file, append } = self.file(request.u64()?)?;
+                let offset = request.u64()?;
+                // files opened to append are written at their end, which not every filesystem does itself
+                file.seek(match append {
+                    true => SeekFrom::End(0),
+                    false => SeekFrom::Start(offset),
+                })?;
+                file.write_all(request.data()?)?;
+                reply.u64(file.stream_position()?);
+            }
+            message::FLUSH => self.file(request.u64()?)?.file.flush()?,
+            message::FILE_METADATA => {
+                reply.metadata(&self.file(request.u64()?)?.file.metadata()?);
+            }
+            message::CLOSE => {
+                let mut open_file = self.files.remove(&request.u64()?).ok_or_else(bad_handle)?;
+                open_file.file.flush()?;
+            }
+            message::READ_DIR => {
+                let mut entries = Vec::new();
+                for entry in self.fs.read_dir(request.str()?)? {
+                    let entry = entry?;
+                    entries.push((entry.file_name().to_owned(), entry.metadata));
+                }
+                reply.u32(entries.len() as u32);
+                for (name, metadata) in entries {
+                    reply.str(&name).metadata(&metadata);
+                }
+            }
+            message::REMOVE_DIR => self.fs.remove_dir(request.str()?)?,
+            message::REMOVE_FILE => self.fs.remove_file(request.str()?)?,
+            message::RENAME => {
+                let from = request.str()?;
+                self.fs.rename(from, request.str()?)?;
+            }
+            message::STATS => {
+                let stats = self.fs.stats()?;
+                reply
+                    .u64(stats.total_space)
+                    .u64(stats.free_space)
+                    .u64(stats.available_space)
+                    .u64(stats.block_size);
+            }
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    format!("Unknown request {ty}"),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the open file with `handle`.
+    ///
+    /// # Arguments
+    /// `handle`: The handle of the file.  
+    fn file(&mut self, handle: u64) -> io::Result<&mut OpenFile> {
+        self.files.get_mut(&handle).ok_or_else(bad_handle)
+    }
+}
+
+/// Returns the error of a request for a file that isn't open.
+fn bad_handle() -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, "The file isn't open")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    use crate::remote::serve_remote_authenticated;
+    use crate::remote_fs::RemoteFS;
+    use crate::FileSystem;
+    use std::io::ErrorKind;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn authenticate() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            serve_remote_authenticated(MemoryFS::default(), listener, |token: &[u8]| {
+                token == b"secret"
+            })
+        });
+
+        assert_eq!(
+            RemoteFS::connect(addr, b"wrong").err().unwrap().kind(),
+            ErrorKind::PermissionDenied
+        );
+        let fs = RemoteFS::connect(addr, b"secret").unwrap();
+        fs.create_dir("dir").unwrap();
+        assert!(fs.metadata("dir").unwrap().is_directory());
+    }
+}
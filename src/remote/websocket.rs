@@ -0,0 +1,428 @@
+//! Carries the remote filesystem protocol over WebSocket, so that `RemoteFS` clients can reach a filesystem through
+//! HTTP proxies and load balancers, and browser frontends can speak the protocol with their own WebSocket.
+//!
+//! The connection is upgraded with the `vfs-remote` subprotocol, and every request and reply of the protocol is sent
+//! as a single binary message.
+
+use crate::remote::{serve_remote_connection, Authenticator, MAX_MESSAGE_LEN};
+use crate::util::encode_base64;
+use crate::FileSystem;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+/// The subprotocol the connection is upgraded with.
+const PROTOCOL: &str = "vfs-remote";
+/// The GUID that the key of the handshake is hashed with, from RFC 6455.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// The longest HTTP request or response that's accepted during the handshake.
+const MAX_HEAD_LEN: usize = 8 * 1024;
+
+/// The opcodes of frames.
+mod opcode {
+    pub(crate) const CONTINUATION: u8 = 0x0;
+    pub(crate) const TEXT: u8 = 0x1;
+    pub(crate) const BINARY: u8 = 0x2;
+    pub(crate) const CLOSE: u8 = 0x8;
+    pub(crate) const PING: u8 = 0x9;
+    pub(crate) const PONG: u8 = 0xa;
+}
+
+/// A WebSocket connection that reads and writes the payloads of binary messages as a byte stream. Every flush sends
+/// the bytes written since the last one as a single message, so it can be passed to `RemoteFS::new` and
+/// `serve_remote_connection`.
+///
+/// Pings are answered while reading, and a close frame ends the stream.
+pub struct WebSocket<S> {
+    stream: S,
+    /// True if this is the client's end, which masks the frames it sends.
+    client: bool,
+    /// The payload being read, which `read_offset` is within.
+    read_buffer: Vec<u8>,
+    read_offset: usize,
+    /// The payload of the message being written.
+    write_buffer: Vec<u8>,
+    /// True if a close frame was sent or received.
+    closed: bool,
+}
+
+impl<S: Read + Write> WebSocket<S> {
+    /// Opens a WebSocket connection to a server on an established stream, such as a TCP or TLS stream.
+    ///
+    /// # Arguments
+    /// `stream`: The connection to the server.  
+    /// `host`: The value of the `Host` header, such as `example.com:8080`.  
+    /// `path`: The path of the request, such as `/vfs`.  
+    pub fn connect(mut stream: S, host: &str, path: &str) -> io::Result<Self> {
+        let key = encode_base64(&random_bytes::<16>());
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: {host}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: {PROTOCOL}\r\n\r\n"
+        )?;
+        stream.flush()?;
+
+        let head = read_head(&mut stream)?;
+        if !head[0].starts_with("HTTP/1.1 101") {
+            return Err(io::Error::new(
+                ErrorKind::ConnectionRefused,
+                format!("The server refused to upgrade: {}", head[0]),
+            ));
+        }
+        if header(&head, "Sec-WebSocket-Accept") != Some(&accept_key(&key)) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "The server's WebSocket accept key is wrong",
+            ));
+        }
+        Ok(Self::new(stream, true))
+    }
+
+    /// Accepts a WebSocket connection from a client on an established stream, answering its upgrade request. Requests
+    /// that aren't WebSocket upgrades are answered with `400 Bad Request`.
+    ///
+    /// # Arguments
+    /// `stream`: The connection to the client.  
+    pub fn accept(mut stream: S) -> io::Result<Self> {
+        let head = read_head(&mut stream)?;
+        let upgrade =
+            header(&head, "Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+        let Some(key) = header(&head, "Sec-WebSocket-Key").filter(|_| upgrade) else {
+            stream.write_all(
+                b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            )?;
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "The request isn't a WebSocket upgrade",
+            ));
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Accept: {}\r\nSec-WebSocket-Protocol: {PROTOCOL}\r\n\r\n",
+            accept_key(key)
+        )?;
+        stream.flush()?;
+        Ok(Self::new(stream, false))
+    }
+
+    fn new(stream: S, client: bool) -> Self {
+        Self {
+            stream,
+            client,
+            read_buffer: Vec::new(),
+            read_offset: 0,
+            write_buffer: Vec::new(),
+            closed: false,
+        }
+    }
+
+    /// Reads a frame. Returns its opcode and payload.
+    fn read_frame(&mut self) -> io::Result<(u8, Vec<u8>)> {
+        let mut header = [0; 2];
+        self.stream.read_exact(&mut header)?;
+        let len = match header[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                self.stream.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0; 8];
+                self.stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        // a message of the protocol is its length, its type and its body
+        if len > MAX_MESSAGE_LEN as u64 + 5 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("WebSocket frame of {len} bytes is too large"),
+            ));
+        }
+
+        let mut mask = [0; 4];
+        if header[1] & 0x80 != 0 {
+            self.stream.read_exact(&mut mask)?;
+        }
+        let mut payload = vec![0; len as usize];
+        self.stream.read_exact(&mut payload)?;
+        payload
+            .iter_mut()
+            .zip(mask.iter().cycle())
+            .for_each(|(byte, mask)| *byte ^= mask);
+        Ok((header[0] & 0xf, payload))
+    }
+
+    /// Writes a final frame.
+    ///
+    /// # Arguments
+    /// `opcode`: The opcode of the frame.  
+    /// `payload`: The payload of the frame.  
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(0x80 | opcode);
+        let mask_bit = if self.client { 0x80 } else { 0 };
+        match payload.len() {
+            len @ 0..=125 => frame.push(mask_bit | len as u8),
+            len @ 126..=0xffff => {
+                frame.push(mask_bit | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(mask_bit | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+
+        // clients mask their frames so that proxies can't be confused by their contents
+        if self.client {
+            let mask = random_bytes::<4>();
+            frame.extend_from_slice(&mask);
+            frame.extend(
+                payload
+                    .iter()
+                    .zip(mask.iter().cycle())
+                    .map(|(byte, mask)| byte ^ mask),
+            );
+        } else {
+            frame.extend_from_slice(payload);
+        }
+        self.stream.write_all(&frame)?;
+        self.stream.flush()
+    }
+}
+
+impl<S: Read + Write> Read for WebSocket<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read_offset == self.read_buffer.len() {
+            if self.closed {
+                return Ok(0);
+            }
+            let (opcode, payload) = match self.read_frame() {
+                Ok(frame) => frame,
+                // a client that disconnects without closing ends the stream too
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                    self.closed = true;
+                    return Ok(0);
+                }
+                Err(err) => return Err(err),
+            };
+            match opcode {
+                opcode::BINARY | opcode::CONTINUATION => {
+                    self.read_buffer = payload;
+                    self.read_offset = 0;
+                }
+                opcode::PING => self.write_frame(opcode::PONG, &payload)?,
+                opcode::PONG => {}
+                opcode::CLOSE => {
+                    self.write_frame(opcode::CLOSE, &[])?;
+                    self.closed = true;
+                }
+                opcode::TEXT => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "Unexpected WebSocket text message",
+                    ))
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Unknown WebSocket opcode {opcode}"),
+                    ))
+                }
+            }
+        }
+
+        let read_len = buf.len().min(self.read_buffer.len() - self.read_offset);
+        buf[..read_len]
+            .copy_from_slice(&self.read_buffer[self.read_offset..self.read_offset + read_len]);
+        self.read_offset += read_len;
+        Ok(read_len)
+    }
+}
+
+impl<S: Read + Write> Write for WebSocket<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.write_buffer.is_empty() {
+            return Ok(());
+        }
+        let message = std::mem::take(&mut self.write_buffer);
+        self.write_frame(opcode::BINARY, &message)
+    }
+}
+
+/// Serves `fs` to `RemoteFS` clients that connect with `RemoteFS::connect_websocket` on the TCP connections accepted
+/// by `listener`, until accepting one fails. Every client may use the filesystem, whatever token it presents.
+///
+/// # Arguments
+/// `fs`: The filesystem to serve.  
+/// `listener`: The listener to accept connections from.  
+pub fn serve_websocket<FS: FileSystem + Sync>(fs: FS, listener: TcpListener) -> io::Result<()> {
+    serve_websocket_authenticated(fs, listener, |_: &[u8]| true)
+}
+
+/// Serves `fs` to `RemoteFS` clients that connect with `RemoteFS::connect_websocket` on the TCP connections accepted
+/// by `listener`, until accepting one fails. Clients whose tokens are rejected by `authenticator` are disconnected.
+///
+/// # Arguments
+/// `fs`: The filesystem to serve.  
+/// `listener`: The listener to accept connections from.  
+/// `authenticator`: Decides which clients may use the filesystem by their tokens.  
+pub fn serve_websocket_authenticated<FS: FileSystem + Sync, A: Authenticator + Sync>(
+    fs: FS,
+    listener: TcpListener,
+    authenticator: A,
+) -> io::Result<()> {
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            stream.set_nodelay(true)?;
+            let (fs, authenticator) = (&fs, &authenticator);
+            scope.spawn(move || {
+                serve_remote_connection(fs, authenticator, WebSocket::accept(stream)?)
+            });
+        }
+        Ok(())
+    })
+}
+
+/// Returns the key the server accepts the handshake with.
+///
+/// # Arguments
+/// `key`: The key the client sent.  
+fn accept_key(key: &str) -> String {
+    let mut sha1 = sha1_smol::Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    encode_base64(&sha1.digest().bytes())
+}
+
+/// Returns the value of the header `name` of an HTTP request or response.
+///
+/// # Arguments
+/// `head`: The lines of the request or response.  
+/// `name`: The name of the header, which is matched case-insensitively.  
+fn header<'a>(head: &'a [String], name: &str) -> Option<&'a str> {
+    head[1..].iter().find_map(|line| {
+        let (header, value) = line.split_once(':')?;
+        header
+            .trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
+
+/// Reads the head of an HTTP request or response byte by byte, so that nothing after it is consumed. Returns its
+/// lines, of which there's at least one.
+///
+/// # Arguments
+/// `stream`: The connection.  
+fn read_head<R: Read>(stream: &mut R) -> io::Result<Vec<String>> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD_LEN {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "The WebSocket handshake is too long",
+            ));
+        }
+        let mut byte = 0;
+        stream.read_exact(std::slice::from_mut(&mut byte))?;
+        head.push(byte);
+    }
+
+    let head = String::from_utf8(head).map_err(|_| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            "The WebSocket handshake isn't UTF-8",
+        )
+    })?;
+    let lines = head
+        .split("\r\n")
+        .filter(|line| !line.is_empty())
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    match lines.is_empty() {
+        true => Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "The WebSocket handshake is empty",
+        )),
+        false => Ok(lines),
+    }
+}
+
+/// Returns unpredictable bytes for the keys of the handshake and the masks of frames, which needn't be
+/// cryptographically secure.
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    for chunk in bytes.chunks_mut(8) {
+        let random = RandomState::new().build_hasher().finish().to_le_bytes();
+        chunk.copy_from_slice(&random[..chunk.len()]);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    use crate::remote::websocket::{accept_key, serve_websocket};
+    use crate::remote_fs::RemoteFS;
+    use crate::FileSystem;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread;
+
+    /// Serves `fs` in the background. Returns the address of the server.
+    fn serve(fs: MemoryFS) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve_websocket(fs, listener));
+        addr
+    }
+
+    #[test]
+    fn handshake() {
+        // the example of RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        let mut stream = TcpStream::connect(serve(MemoryFS::default())).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+    }
+
+    #[test]
+    fn read_write() {
+        let addr = serve(MemoryFS::default());
+        let fs = RemoteFS::connect_websocket(&format!("ws://{addr}/vfs"), b"").unwrap();
+        fs.create_dir("dir").unwrap();
+
+        // larger than a frame with a 16-bit length
+        let data = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        fs.create_file("dir/file")
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+        assert_eq!(fs.metadata("dir/file").unwrap().len(), data.len() as u64);
+        assert!(fs.open_file("dir/file").unwrap().read_into_vec().unwrap() == data);
+        fs.remove_file("dir/file").unwrap();
+        assert!(!fs.exists("dir/file").unwrap());
+    }
+}
@@ -0,0 +1,461 @@
+use crate::file::{AccessPattern, DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+#[cfg(feature = "websocket")]
+use crate::remote::websocket::WebSocket;
+use crate::remote::{
+    message, read_message, write_message, Decoder, Encoder, MAGIC, MAX_IO_LEN, VERSION,
+};
+use crate::tree::normalize_and_relativize;
+use crate::util::invalid_input;
+use crate::FileSystem;
+use parking_lot::Mutex;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+
+/// How much more than requested is read from files that aren't read at random offsets, so that small reads are
+/// served from memory.
+const READ_AHEAD_LEN: usize = 256 * 1024;
+/// How many written bytes are buffered before they're sent to the server.
+const WRITE_BUFFER_LEN: usize = 256 * 1024;
+
+/// A read-write filesystem served by another process with `serve_remote`, such as a composed virtual filesystem
+/// exposed over the network.
+///
+/// Requests are made one at a time per connection. Files are held open on the server and streamed in chunks: reads
+/// are read ahead unless the file was opened for random access, and writes are buffered until the file is flushed,
+/// sought, read or closed, so files must be flushed to see write errors.
+pub struct RemoteFS {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl RemoteFS {
+    /// Connects to a server over TCP, presenting `token` to its authenticator.
+    ///
+    /// # Arguments
+    /// `addr`: The address of the server.  
+    /// `token`: The token the server authenticates the client by, which is ignored by servers that don't.  
+    pub fn connect<A: ToSocketAddrs>(addr: A, token: &[u8]) -> crate::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Self::new(stream, token)
+    }
+
+    /// Connects to a server served by `serve_websocket` over WebSocket, presenting `token` to its authenticator. Only
+    /// `ws://` URLs are supported; connect over TLS by passing a `WebSocket` over a TLS stream to `new` instead.
+    ///
+    /// # Arguments
+    /// `url`: The URL of the server, such as `ws://example.com:8080/vfs`.  
+    /// `token`: The token the server authenticates the client by, which is ignored by servers that don't.  
+    #[cfg(feature = "websocket")]
+    pub fn connect_websocket(url: &str, token: &[u8]) -> crate::Result<Self> {
+        let url = url
+            .strip_prefix("ws://")
+            .ok_or_else(|| invalid_input("Only ws:// URLs are supported"))?;
+        let (host, path) = match url.find('/') {
+            Some(index) => url.split_at(index),
+            None => (url, "/"),
+        };
+        let stream = match host.contains(':') {
+            true => TcpStream::connect(host)?,
+            false => TcpStream::connect((host, 80))?,
+        };
+        stream.set_nodelay(true)?;
+        Self::new(WebSocket::connect(stream, host, path)?, token)
+    }
+
+    /// Connects to a server over an established stream, such as a TLS stream or a Unix socket, presenting `token` to
+    /// its authenticator.
+    ///
+    /// # Arguments
+    /// `stream`: The connection to the server.  
+    /// `token`: The token the server authenticates the client by, which is ignored by servers that don't.  
+    pub fn new<S: Read + Write + Send + 'static>(stream: S, token: &[u8]) -> crate::Result<Self> {
+        let mut connection = Connection {
+            stream: Box::new(stream),
+        };
+        let mut hello = Encoder::new(message::HELLO);
+        hello.u32(MAGIC).u32(VERSION).data(token);
+        connection.call(&mut hello)?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Makes a request about the entry at `path`, returning the body of the reply.
+    ///
+    /// # Arguments
+    /// `ty`: The type of the request.  
+    /// `path`: The path of the entry.  
+    fn call_path(&self, ty: u8, path: &str) -> crate::Result<Vec<u8>> {
+        let mut request = Encoder::new(ty);
+        request.str(path);
+        self.connection.lock().call(&mut request)
+    }
+}
+
+impl FileSystem for RemoteFS {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        let mut request = Encoder::new(message::CREATE_DIR);
+        request.str(path).u8(0);
+        self.connection.lock().call(&mut request).map(|_| ())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        Decoder::new(&self.call_path(message::METADATA, path)?).metadata()
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        let mut request = Encoder::new(message::OPEN);
+        request.str(path).open_options(options);
+        let reply = self.connection.lock().call(&mut request)?;
+
+        Ok(Box::new(RemoteFile {
+            connection: self.connection.clone(),
+            handle: Decoder::new(&reply).u64()?,
+            append: options.append,
+            position: 0,
+            read_ahead: options.access != AccessPattern::Random && !options.direct,
+            read_buffer: Vec::new(),
+            read_offset: 0,
+            write_buffer: Vec::new(),
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let reply = self.call_path(message::READ_DIR, path)?;
+        let mut reply = Decoder::new(&reply);
+        let parent = normalize_and_relativize(path);
+        let mut entries = Vec::new();
+        for _ in 0..reply.u32()? {
+            let name = reply.str()?;
+            entries.push(Ok(DirEntry {
+                path: parent.join(name),
+                metadata: reply.metadata()?,
+            }));
+        }
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.call_path(message::REMOVE_DIR, path).map(|_| ())
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.call_path(message::REMOVE_FILE, path).map(|_| ())
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        let mut request = Encoder::new(message::CREATE_DIR);
+        request.str(path).u8(1).u32(mode);
+        self.connection.lock().call(&mut request).map(|_| ())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        let mut request = Encoder::new(message::RENAME);
+        request.str(from).str(to);
+        self.connection.lock().call(&mut request).map(|_| ())
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        let reply = self
+            .connection
+            .lock()
+            .call(&mut Encoder::new(message::STATS))?;
+        let mut reply = Decoder::new(&reply);
+        Ok(FileSystemStats {
+            total_space: reply.u64()?,
+            free_space: reply.u64()?,
+            available_space: reply.u64()?,
+            block_size: reply.u64()?,
+        })
+    }
+}
+
+/// A byte stream to a server.
+trait Stream: Read + Write + Send {}
+
+impl<S: Read + Write + Send> Stream for S {}
+
+/// A connection to a server.
+struct Connection {
+    stream: Box<dyn Stream>,
+}
+
+impl Connection {
+    /// Sends a request and waits for its reply. Returns the body of the reply, or the error the server replied with.
+    ///
+    /// # Arguments
+    /// `request`: The request.  
+    fn call(&mut self, request: &mut Encoder) -> crate::Result<Vec<u8>> {
+        write_message(&mut self.stream, request)?;
+        match read_message(&mut self.stream)? {
+            Some((message::OK, body)) => Ok(body),
+            Some((message::ERROR, body)) => Err(Decoder::new(&body).error()?),
+            Some((ty, _)) => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Unexpected reply {ty}"),
+            )),
+            None => Err(io::Error::new(
+                ErrorKind::ConnectionAborted,
+                "The server disconnected",
+            )),
+        }
+    }
+}
+
+/// A file held open on the server.
+struct RemoteFile {
+    connection: Arc<Mutex<Connection>>,
+    handle: u64,
+    /// True if the file was opened to append, so writes land at its end.
+    append: bool,
+    /// The position of the next read or write, including the buffered writes.
+    position: u64,
+    /// True if reads are read ahead.
+    read_ahead: bool,
+    /// Data read ahead, which `position` is within.
+    read_buffer: Vec<u8>,
+    /// The index of the byte at `position` within the data read ahead.
+    read_offset: usize,
+    /// Data written, but not yet sent, that ends at `position`.
+    write_buffer: Vec<u8>,
+}
+
+impl RemoteFile {
+    /// Makes a request about the file, returning the body of the reply.
+    ///
+    /// # Arguments
+    /// `ty`: The type of the request.  
+    fn call(&self, ty: u8) -> crate::Result<Vec<u8>> {
+        let mut request = Encoder::new(ty);
+        request.u64(self.handle);
+        self.connection.lock().call(&mut request)
+    }
+
+    /// Reads up to `len` bytes at the position from the server.
+    ///
+    /// # Arguments
+    /// `len`: The most bytes to read.  
+    fn read_at_position(&self, len: usize) -> crate::Result<Vec<u8>> {
+        let mut request = Encoder::new(message::READ);
+        request
+            .u64(self.handle)
+            .u64(self.position)
+            .u32(len.min(MAX_IO_LEN as usize) as u32);
+        let reply = self.connection.lock().call(&mut request)?;
+        Ok(Decoder::new(&reply).data()?.to_vec())
+    }
+
+    /// Sends the buffered writes to the server.
+    fn flush_writes(&mut self) -> crate::Result<()> {
+        let mut offset = self.position - self.write_buffer.len() as u64;
+        for chunk in self.write_buffer.chunks(MAX_IO_LEN as usize) {
+            let mut request = Encoder::new(message::WRITE);
+            request.u64(self.handle).u64(offset).data(chunk);
+            let reply = self.connection.lock().call(&mut request)?;
+            // files opened to append may be written elsewhere than the position
+            offset = Decoder::new(&reply).u64()?;
+        }
+        if !self.write_buffer.is_empty() {
+            self.position = offset;
+            self.write_buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// Discards the data read ahead.
+    fn discard_read_ahead(&mut self) {
+        self.read_buffer.clear();
+        self.read_offset = 0;
+    }
+}
+
+impl File for RemoteFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        let mut metadata = Decoder::new(&self.call(message::FILE_METADATA)?).metadata()?;
+        // buffered writes may extend the file
+        if self.append {
+            metadata.len += self.write_buffer.len() as u64;
+        } else if !self.write_buffer.is_empty() {
+            metadata.len = metadata.len.max(self.position);
+        }
+        Ok(metadata)
+    }
+}
+
+impl Read for RemoteFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.flush_writes()?;
+        if self.read_offset == self.read_buffer.len() {
+            if !self.read_ahead || buf.len() >= READ_AHEAD_LEN {
+                let data = self.read_at_position(buf.len())?;
+                buf[..data.len()].copy_from_slice(&data);
+                self.position += data.len() as u64;
+                return Ok(data.len());
+            }
+            self.read_buffer = self.read_at_position(READ_AHEAD_LEN)?;
+            self.read_offset = 0;
+        }
+
+        let read_len = buf.len().min(self.read_buffer.len() - self.read_offset);
+        buf[..read_len]
+            .copy_from_slice(&self.read_buffer[self.read_offset..self.read_offset + read_len]);
+        self.read_offset += read_len;
+        self.position += read_len as u64;
+        Ok(read_len)
+    }
+}
+
+impl Seek for RemoteFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.flush_writes()?;
+        self.discard_read_ahead();
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.metadata()?.len().checked_add_signed(offset),
+        };
+        self.position =
+            position.ok_or_else(|| invalid_input("seek to a negative or overflowing position"))?;
+        Ok(self.position)
+    }
+}
+
+impl Write for RemoteFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.discard_read_ahead();
+        self.write_buffer.extend_from_slice(buf);
+        self.position += buf.len() as u64;
+        if self.write_buffer.len() >= WRITE_BUFFER_LEN {
+            self.flush_writes()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_writes()?;
+        self.call(message::FLUSH).map(|_| ())
+    }
+}
+
+impl Drop for RemoteFile {
+    fn drop(&mut self) {
+        let _ = self.flush_writes();
+        let _ = self.call(message::CLOSE);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::{AccessPattern, Metadata, OpenOptions};
+    use crate::memory_fs::MemoryFS;
+    use crate::remote::serve_remote;
+    use crate::remote_fs::RemoteFS;
+    use crate::util::test::{check_open_options, read_directory};
+    use crate::FileSystem;
+    use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
+
+    /// Serves `fs` in the background. Returns the address of the server.
+    fn serve_tcp(fs: MemoryFS) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve_remote(fs, listener));
+        addr
+    }
+
+    #[test]
+    fn read_write() {
+        let fs = RemoteFS::connect(serve_tcp(MemoryFS::default()), b"").unwrap();
+        fs.create_dir("dir").unwrap();
+        fs.create_dir_all("dir/a/b").unwrap();
+        write!(fs.create_file("dir/file").unwrap(), "hello").unwrap();
+
+        let mut file = fs
+            .open_file_options("dir/file", &OpenOptions::default().append(true))
+            .unwrap();
+        write!(file, " world").unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 11);
+        drop(file);
+        let mut file = fs.open_file("dir/file").unwrap();
+        file.seek(SeekFrom::Start(6)).unwrap();
+        assert_eq!(file.read_into_string().unwrap(), "world");
+        drop(file);
+
+        let dir = read_directory(&fs, "dir");
+        itertools::assert_equal(dir.keys(), vec!["dir/a", "dir/file"]);
+        assert!(dir["dir/a"].is_directory());
+        assert_eq!(dir["dir/file"], Metadata::file(11));
+
+        fs.rename("dir/file", "dir/a/moved").unwrap();
+        assert_eq!(fs.metadata("dir/a/moved").unwrap().len(), 11);
+        assert_eq!(
+            fs.open_file("dir/file").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        fs.remove_file("dir/a/moved").unwrap();
+        fs.remove_dir("dir/a/b").unwrap();
+        assert!(!fs.exists("dir/a/b").unwrap());
+        assert_eq!(fs.stats().err().unwrap().kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn stream() {
+        let fs = RemoteFS::connect(serve_tcp(MemoryFS::default()), b"").unwrap();
+        // larger than a single read or write, and not a multiple of the buffers
+        let data = (0..3 * 1024 * 1024 + 7)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let mut file = fs.create_file("big").unwrap();
+        for chunk in data.chunks(1000) {
+            file.write_all(chunk).unwrap();
+        }
+        file.flush().unwrap();
+        drop(file);
+        assert_eq!(fs.metadata("big").unwrap().len(), data.len() as u64);
+
+        // small reads are served by reading ahead
+        let mut file = fs.open_file("big").unwrap();
+        let mut read = Vec::new();
+        let mut buf = [0; 999];
+        loop {
+            match file.read(&mut buf).unwrap() {
+                0 => break,
+                read_len => read.extend_from_slice(&buf[..read_len]),
+            }
+        }
+        assert!(read == data);
+        drop(file);
+
+        // writes in the middle of a file replace what's there, and reads see them
+        let mut file = fs
+            .open_file_options(
+                "big",
+                &OpenOptions::default()
+                    .write(true)
+                    .access(AccessPattern::Random),
+            )
+            .unwrap();
+        file.seek(SeekFrom::Start(10)).unwrap();
+        file.write_all(b"overwritten").unwrap();
+        let mut buf = [0; 5];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[21..26]);
+        file.seek(SeekFrom::Start(10)).unwrap();
+        let mut buf = [0; 11];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"overwritten");
+    }
+
+    #[test]
+    fn open_options() {
+        let fs = RemoteFS::connect(serve_tcp(MemoryFS::default()), b"").unwrap();
+        check_open_options(&fs, "options");
+    }
+}
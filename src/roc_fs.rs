@@ -1,14 +1,227 @@
 use crate::file::{DirEntry, File, Metadata, OpenOptions};
-use crate::util::{not_found, not_supported};
+use crate::tree::normalize_and_relativize;
+use crate::util::{invalid_input, not_found, read_only};
 use crate::FileSystem;
-use itertools::Itertools;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashSet, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::io;
 use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 /// "Read-only collection" filesystem. Does not support writing, but supports reading from any
-/// of the layers. Differs from `OverlayFS` in that it only supports reading and is much less
-/// complex and doesn't need to write a `.whiteout` directory that can sometimes prove problematic.
-pub struct RocFS {
-    pub layers: Vec<Box<dyn FileSystem>>,
+/// of the layers. Differs from `OverlayFS` in that it only supports reading, so it has no upper
+/// layer and entries are only hidden from later layers by the deletion masks set on a layer.
+///
+/// Layers can be added and removed while the filesystem is in use, including from other threads.
+/// Operations that are already in progress keep using the layers that were present when they
+/// started. A fixed list of concretely-typed layers can be used instead through
+/// `RocFS::from_list`, which avoids dynamic dispatch.
+pub struct RocFS<L = DynamicLayers> {
+    layers: L,
+}
+
+/// The layers of a `RocFS` that can be changed at runtime. They're replaced as a whole when they change, so that
+/// operations can hold on to the layers they started with without locking them.
+pub struct DynamicLayers {
+    layers: RwLock<Arc<[Layer]>>,
+    negative_cache: Mutex<NegativeCache>,
+}
+
+/// A bounded cache of paths that weren't found in any layer. The oldest paths are evicted first.
+#[derive(Default)]
+struct NegativeCache {
+    capacity: usize,
+    /// Incremented whenever the cache is invalidated, so that lookups that started before then
+    /// aren't cached.
+    generation: u64,
+    paths: HashSet<PathBuf>,
+    order: VecDeque<PathBuf>,
+}
+
+impl NegativeCache {
+    /// Caches a path that wasn't found, if the cache hasn't been invalidated since the lookup
+    /// started.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    /// `generation`: The generation of the cache when the lookup started.  
+    fn insert(&mut self, path: PathBuf, generation: u64) {
+        if self.capacity == 0 || generation != self.generation || self.paths.contains(&path) {
+            return;
+        }
+
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.paths.remove(&oldest);
+            }
+        }
+        self.paths.insert(path.clone());
+        self.order.push_back(path);
+    }
+
+    /// Removes a path from the cache.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path.  
+    fn remove(&mut self, path: &Path) {
+        if self.paths.remove(path) {
+            self.order.retain(|cached_path| cached_path != path);
+        }
+    }
+
+    /// Removes every path from the cache.
+    fn clear(&mut self) {
+        self.generation += 1;
+        self.paths.clear();
+        self.order.clear();
+    }
+}
+
+/// An operation performed on each layer of a `RocFS` in turn.
+pub trait LayerVisitor {
+    /// The result of the operation.
+    type Output;
+
+    /// Performs the operation on a layer. Returns `Some` to stop at the layer, or `None` to
+    /// continue with the next one.
+    ///
+    /// # Arguments
+    /// `layer`: The layer.  
+    fn visit<F: FileSystem + ?Sized>(&mut self, layer: &F) -> Option<Self::Output>;
+}
+
+/// A fixed list of layers for a `RocFS`. This is implemented for tuples of up to eight
+/// filesystems, as well as arrays and vectors of filesystems.
+pub trait FileSystemList {
+    /// Visits each layer in order until the visitor stops at one, returning the index of the layer
+    /// along with the visitor's output.
+    ///
+    /// # Arguments
+    /// `visitor`: The visitor.  
+    fn visit_layers<V: LayerVisitor>(&self, visitor: &mut V) -> Option<(usize, V::Output)>;
+}
+
+macro_rules! impl_file_system_list {
+    ($($layer:ident: $index:tt),+) => {
+        impl<$($layer: FileSystem),+> FileSystemList for ($($layer,)+) {
+            fn visit_layers<V: LayerVisitor>(&self, visitor: &mut V) -> Option<(usize, V::Output)> {
+                $(
+                    if let Some(output) = visitor.visit(&self.$index) {
+                        return Some(($index, output));
+                    }
+                )+
+                None
+            }
+        }
+    };
+}
+
+impl_file_system_list!(A: 0);
+impl_file_system_list!(A: 0, B: 1);
+impl_file_system_list!(A: 0, B: 1, C: 2);
+impl_file_system_list!(A: 0, B: 1, C: 2, D: 3);
+impl_file_system_list!(A: 0, B: 1, C: 2, D: 3, E: 4);
+impl_file_system_list!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
+impl_file_system_list!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6);
+impl_file_system_list!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7);
+
+impl<F: FileSystem, const N: usize> FileSystemList for [F; N] {
+    fn visit_layers<V: LayerVisitor>(&self, visitor: &mut V) -> Option<(usize, V::Output)> {
+        visit_slice(self, visitor)
+    }
+}
+
+impl<F: FileSystem> FileSystemList for Vec<F> {
+    fn visit_layers<V: LayerVisitor>(&self, visitor: &mut V) -> Option<(usize, V::Output)> {
+        visit_slice(self, visitor)
+    }
+}
+
+/// Visits each layer of a slice in order until the visitor stops at one.
+///
+/// # Arguments
+/// `layers`: The layers.  
+/// `visitor`: The visitor.  
+fn visit_slice<F: FileSystem, V: LayerVisitor>(
+    layers: &[F],
+    visitor: &mut V,
+) -> Option<(usize, V::Output)> {
+    layers
+        .iter()
+        .enumerate()
+        .find_map(|(index, layer)| visitor.visit(layer).map(|output| (index, output)))
+}
+
+/// Hides entries from the layers of a `RocFS`, as if they were deleted.
+pub trait DeletionMask {
+    /// Returns true if the entry at `path` is deleted. Entries within a deleted directory are
+    /// deleted as well.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path to the entry, relative to the root.  
+    fn is_deleted(&self, path: &Path) -> bool;
+}
+
+impl<F: Fn(&Path) -> bool> DeletionMask for F {
+    fn is_deleted(&self, path: &Path) -> bool {
+        self(path)
+    }
+}
+
+impl DeletionMask for HashSet<PathBuf> {
+    fn is_deleted(&self, path: &Path) -> bool {
+        self.contains(path)
+    }
+}
+
+/// Lookup statistics of a layer.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LayerStats {
+    /// The number of lookups the layer served.
+    pub hits: u64,
+    /// The number of lookups that reached the layer, but weren't found in it.
+    pub misses: u64,
+}
+
+/// A layer of the filesystem.
+#[derive(Clone)]
+struct Layer {
+    fs: Arc<dyn FileSystem + Send + Sync>,
+    /// The mask hiding entries from the layers after this one.
+    mask: Option<Arc<dyn DeletionMask + Send + Sync>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl Layer {
+    /// Creates a new layer without a deletion mask.
+    ///
+    /// # Arguments
+    /// `fs`: The filesystem of the layer.  
+    fn new(fs: Arc<dyn FileSystem + Send + Sync>) -> Self {
+        Self {
+            fs,
+            mask: None,
+            hits: Arc::default(),
+            misses: Arc::default(),
+        }
+    }
+}
+
+/// Returns true if the entry at `path` or one of its parents is deleted by one of `masks`.
+///
+/// # Arguments
+/// `masks`: The deletion masks.  
+/// `path`: The normalized path to the entry.  
+fn is_masked(masks: &[Arc<dyn DeletionMask + Send + Sync>], path: &Path) -> bool {
+    !masks.is_empty()
+        && path
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .any(|ancestor| masks.iter().any(|mask| mask.is_deleted(ancestor)))
 }
 
 impl RocFS {
@@ -17,82 +230,439 @@ impl RocFS {
     ///
     /// # Argument
     /// `layers`: The layers of the filesystem.
-    pub fn new(layers: Vec<Box<dyn FileSystem>>) -> Self {
-        Self { layers }
+    pub fn new(layers: Vec<Box<dyn FileSystem + Send + Sync>>) -> Self {
+        Self {
+            layers: DynamicLayers {
+                layers: RwLock::new(
+                    layers
+                        .into_iter()
+                        .map(|layer| Layer::new(Arc::from(layer)))
+                        .collect(),
+                ),
+                negative_cache: Mutex::default(),
+            },
+        }
+    }
+
+    /// Returns the number of layers.
+    pub fn layer_count(&self) -> usize {
+        self.layers.layers.read().len()
+    }
+
+    /// Adds a layer after all other layers, so that it is searched last.
+    ///
+    /// # Arguments
+    /// `fs`: The layer.  
+    pub fn push_layer<F: FileSystem + Send + Sync + 'static>(&self, fs: F) {
+        self.modify_layers(|layers| layers.push(Layer::new(Arc::new(fs))));
+    }
+
+    /// Inserts a layer at `index`, shifting the layers after it back. Layers at lower indices are
+    /// searched first.
+    ///
+    /// # Arguments
+    /// `index`: The index to insert the layer at, up to the number of layers.  
+    /// `fs`: The layer.  
+    pub fn insert_layer<F: FileSystem + Send + Sync + 'static>(
+        &self,
+        index: usize,
+        fs: F,
+    ) -> crate::Result<()> {
+        self.modify_layers(|layers| {
+            if index > layers.len() {
+                return Err(invalid_input("Layer index out of bounds"));
+            }
+
+            layers.insert(index, Layer::new(Arc::new(fs)));
+            Ok(())
+        })
+    }
+
+    /// Removes the layer at `index`, shifting the layers after it forward. Files that were already
+    /// opened from the layer remain open.
+    ///
+    /// # Arguments
+    /// `index`: The index of the layer.  
+    pub fn remove_layer(&self, index: usize) -> crate::Result<()> {
+        self.modify_layers(|layers| {
+            if index >= layers.len() {
+                return Err(not_found());
+            }
+
+            layers.remove(index);
+            Ok(())
+        })
+    }
+
+    /// Sets the deletion mask of the layer at `index`, which hides the entries it matches from the
+    /// layers after it. This lets a layer remove entries from the layers below without replacing
+    /// them. The layer's own entries are unaffected.
+    ///
+    /// # Arguments
+    /// `index`: The index of the layer.  
+    /// `mask`: The deletion mask, such as a set of paths or a predicate.  
+    pub fn set_deletion_mask<M: DeletionMask + Send + Sync + 'static>(
+        &self,
+        index: usize,
+        mask: M,
+    ) -> crate::Result<()> {
+        self.modify_layers(|layers| {
+            let layer = layers.get_mut(index).ok_or_else(not_found)?;
+            layer.mask = Some(Arc::new(mask));
+            Ok(())
+        })
+    }
+
+    /// Returns the index of the layer that serves the entry at `path` along with the entry's
+    /// metadata.
+    ///
+    /// # Arguments
+    /// `path`: The path to the entry.  
+    pub fn resolve(&self, path: &str) -> crate::Result<(usize, Metadata)> {
+        self.for_each_layer(|layer, path| layer.metadata(path), path, true)
     }
 
-    /// Checks each layer for a successful result.
+    /// Returns the lookup statistics of each layer, in layer order. Lookups are counted for
+    /// `metadata`, `open_file_options` and `resolve`, and lookups that fail with an error other
+    /// than `NotFound` aren't counted.
+    pub fn layer_stats(&self) -> Vec<LayerStats> {
+        self.layers
+            .layers
+            .read()
+            .iter()
+            .map(|layer| LayerStats {
+                hits: layer.hits.load(Ordering::Relaxed),
+                misses: layer.misses.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Resets the lookup statistics of every layer.
+    pub fn reset_layer_stats(&self) {
+        for layer in self.layers.layers.read().iter() {
+            layer.hits.store(0, Ordering::Relaxed);
+            layer.misses.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Caches up to `capacity` paths that weren't found in any layer, so that repeated lookups of
+    /// missing paths don't search every layer. The cache is cleared whenever the layers change, but
+    /// not when the contents of a layer change, so it should only be used if the layers are
+    /// immutable. Lookups answered by the cache aren't counted in the layer statistics.
+    ///
+    /// # Arguments
+    /// `capacity`: The maximum number of cached paths. Zero disables the cache.  
+    pub fn negative_cache(self, capacity: usize) -> Self {
+        {
+            let mut negative_cache = self.layers.negative_cache.lock();
+            negative_cache.clear();
+            negative_cache.capacity = capacity;
+        }
+        self
+    }
+
+    /// Removes every path from the cache of paths that weren't found, such as after the contents
+    /// of a layer change.
+    pub fn clear_negative_cache(&self) {
+        self.layers.negative_cache.lock().clear();
+    }
+
+    /// Modifies the layers, invalidating the cache of paths that weren't found.
+    ///
+    /// # Arguments
+    /// `f`: The function modifying the layers.  
+    fn modify_layers<R, F: FnOnce(&mut Vec<Layer>) -> R>(&self, f: F) -> R {
+        let mut layers = self.layers.layers.write();
+        self.layers.negative_cache.lock().clear();
+        let mut modified = layers.to_vec();
+        let result = f(&mut modified);
+        *layers = modified.into();
+        result
+    }
+
+    /// Returns a snapshot of the layers, so that they aren't locked while they're accessed.
+    fn layers(&self) -> Arc<[Layer]> {
+        self.layers.layers.read().clone()
+    }
+
+    /// Checks each layer for a successful result, skipping layers the entry is deleted from.
+    /// Returns the index of the layer along with the result.
     ///
     /// # Arguments
     /// `f`: The filesystem method.  
     /// `path`: The path invoked.  
+    /// `cacheable`: True if the lookup can be answered by the cache of paths that weren't found.  
     fn for_each_layer<R, F: Fn(&dyn FileSystem, &str) -> crate::Result<R>>(
         &self,
         f: F,
         path: &str,
-    ) -> crate::Result<R> {
-        for layer in &self.layers {
-            match f(&**layer, path) {
-                Ok(path) => return Ok(path),
-                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+        cacheable: bool,
+    ) -> crate::Result<(usize, R)> {
+        let normalized_path = normalize_and_relativize(path);
+        let (layers, generation) = {
+            let layers = self.layers.layers.read();
+            let negative_cache = self.layers.negative_cache.lock();
+            if cacheable && negative_cache.paths.contains(&normalized_path) {
+                return Err(not_found());
+            }
+            (layers.clone(), negative_cache.generation)
+        };
+        let mut masks = Vec::new();
+
+        for (index, layer) in layers.iter().enumerate() {
+            if is_masked(&masks, &normalized_path) {
+                break;
+            }
+
+            match f(&*layer.fs, path) {
+                Ok(res) => {
+                    // the path may have been created by the operation
+                    if !cacheable {
+                        self.layers.negative_cache.lock().remove(&normalized_path);
+                    }
+
+                    layer.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok((index, res));
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    layer.misses.fetch_add(1, Ordering::Relaxed);
+                }
                 Err(err) => return Err(err),
             }
+            masks.extend(layer.mask.clone());
         }
 
+        if cacheable {
+            self.layers
+                .negative_cache
+                .lock()
+                .insert(normalized_path, generation);
+        }
         Err(not_found())
     }
 }
 
 impl FileSystem for RocFS {
     fn create_dir(&self, _path: &str) -> crate::Result<()> {
-        Err(not_supported())
+        Err(read_only())
     }
 
     fn metadata(&self, path: &str) -> crate::Result<Metadata> {
-        self.for_each_layer(|layer, path| layer.metadata(path), path)
+        self.for_each_layer(|layer, path| layer.metadata(path), path, true)
+            .map(|(_, metadata)| metadata)
     }
 
     fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
-        self.for_each_layer(|layer, path| layer.open_file_options(path, options), path)
+        // creating a file would make the path exist
+        self.for_each_layer(
+            |layer, path| layer.open_file_options(path, options),
+            path,
+            !options.writable(),
+        )
+        .map(|(_, file)| file)
     }
 
     fn read_dir(
         &self,
         path: &str,
     ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
-        Ok(Box::new(
-            self.layers
-                .iter()
-                .map(|layer| layer.read_dir(path))
-                .filter(|res| {
-                    res.as_ref()
-                        .err()
-                        .map(|err| err.kind() != ErrorKind::NotFound)
-                        .unwrap_or(true)
-                })
-                .flatten_ok()
-                .try_collect::<_, Vec<_>, _>()?
-                .into_iter(),
-        ))
+        let normalized_path = normalize_and_relativize(path);
+        let mut masks = Vec::new();
+        let mut directory = MergedDirectory::default();
+
+        for layer in self.layers().iter() {
+            if is_masked(&masks, &normalized_path) {
+                break;
+            }
+
+            match found(layer.fs.read_dir(path)) {
+                Some(Ok(entries)) => directory.extend(entries, |name| {
+                    is_masked(&masks, &normalized_path.join(name))
+                }),
+                Some(Err(err)) if !directory.shadows(&err) => return Err(err),
+                _ => {}
+            }
+            masks.extend(layer.mask.clone());
+        }
+
+        Ok(Box::new(directory.entries.into_iter()))
+    }
+
+    fn remove_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn remove_file(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+}
+
+impl<L: FileSystemList> RocFS<L> {
+    /// Creates a new read-only collection filesystem from a fixed list of layers, such as a tuple
+    /// of filesystems. Layers will be traversed in order, and are accessed without dynamic
+    /// dispatch.
+    ///
+    /// # Arguments
+    /// `layers`: The layers of the filesystem.  
+    pub fn from_list(layers: L) -> Self {
+        Self { layers }
+    }
+
+    /// Returns the layers of the filesystem.
+    pub fn list(&self) -> &L {
+        &self.layers
+    }
+}
+
+/// Treats `NotFound` errors as a reason to continue with the next layer.
+///
+/// # Arguments
+/// `res`: The result of the operation on a layer.  
+fn found<R>(res: crate::Result<R>) -> Option<crate::Result<R>> {
+    match res {
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        res => Some(res),
+    }
+}
+
+/// Looks up the metadata of an entry.
+struct MetadataVisitor<'a>(&'a str);
+
+impl LayerVisitor for MetadataVisitor<'_> {
+    type Output = crate::Result<Metadata>;
+
+    fn visit<F: FileSystem + ?Sized>(&mut self, layer: &F) -> Option<Self::Output> {
+        found(layer.metadata(self.0))
+    }
+}
+
+/// Opens a file.
+struct OpenFileVisitor<'a>(&'a str, &'a OpenOptions);
+
+impl LayerVisitor for OpenFileVisitor<'_> {
+    type Output = crate::Result<Box<dyn File>>;
+
+    fn visit<F: FileSystem + ?Sized>(&mut self, layer: &F) -> Option<Self::Output> {
+        found(layer.open_file_options(self.0, self.1))
+    }
+}
+
+/// The entries of a directory, merged from each layer in turn.
+#[derive(Default)]
+struct MergedDirectory {
+    /// True if an earlier layer has the directory.
+    found: bool,
+    names: HashSet<Option<OsString>>,
+    entries: Vec<crate::Result<DirEntry>>,
+}
+
+impl MergedDirectory {
+    /// Adds the entries of the next layer. Entries in earlier layers shadow those with the same
+    /// name in later layers.
+    ///
+    /// # Arguments
+    /// `entries`: The entries of the directory in the layer.  
+    /// `is_hidden`: Returns true if the entry with the given name is hidden from the layer.  
+    fn extend<I: Iterator<Item = crate::Result<DirEntry>>, H: Fn(&OsStr) -> bool>(
+        &mut self,
+        entries: I,
+        is_hidden: H,
+    ) {
+        self.found = true;
+        for entry in entries {
+            if let Ok(entry) = &entry {
+                let name = entry.path.file_name().map(OsStr::to_owned);
+                if name.as_deref().is_some_and(&is_hidden) || !self.names.insert(name) {
+                    continue;
+                }
+            }
+            self.entries.push(entry);
+        }
+    }
+
+    /// Returns true if `err` is a later layer having something other than a directory at the path, which the directory
+    /// of an earlier layer shadows.
+    ///
+    /// # Arguments
+    /// `err`: The error reading the directory from the layer.  
+    fn shadows(&self, err: &io::Error) -> bool {
+        self.found && err.kind() == ErrorKind::NotADirectory
+    }
+}
+
+/// Collects the entries of a directory from every layer. Stops only on error.
+struct ReadDirVisitor<'a> {
+    path: &'a str,
+    directory: MergedDirectory,
+}
+
+impl LayerVisitor for ReadDirVisitor<'_> {
+    type Output = io::Error;
+
+    fn visit<F: FileSystem + ?Sized>(&mut self, layer: &F) -> Option<Self::Output> {
+        match found(layer.read_dir(self.path))? {
+            Ok(entries) => self.directory.extend(entries, |_| false),
+            Err(err) if !self.directory.shadows(&err) => return Some(err),
+            Err(_) => {}
+        }
+        None
+    }
+}
+
+impl<L: FileSystemList> FileSystem for RocFS<L> {
+    fn create_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.layers
+            .visit_layers(&mut MetadataVisitor(path))
+            .map_or_else(|| Err(not_found()), |(_, res)| res)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        self.layers
+            .visit_layers(&mut OpenFileVisitor(path, options))
+            .map_or_else(|| Err(not_found()), |(_, res)| res)
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let mut visitor = ReadDirVisitor {
+            path,
+            directory: MergedDirectory::default(),
+        };
+        if let Some((_, err)) = self.layers.visit_layers(&mut visitor) {
+            return Err(err);
+        }
+
+        Ok(Box::new(visitor.directory.entries.into_iter()))
     }
 
     fn remove_dir(&self, _path: &str) -> crate::Result<()> {
-        Err(not_supported())
+        Err(read_only())
     }
 
     fn remove_file(&self, _path: &str) -> crate::Result<()> {
-        Err(not_supported())
+        Err(read_only())
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::file::Metadata;
+    use crate::memory_fs::MemoryFS;
     use crate::physical_fs::PhysicalFS;
-    use crate::roc_fs::RocFS;
+    use crate::roc_fs::{LayerStats, RocFS};
     use crate::util::test::read_directory;
     use crate::FileSystem;
-    use std::io::ErrorKind;
+    use std::collections::HashSet;
+    use std::io::{ErrorKind, Write};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::thread;
 
     #[test]
     fn read_dir_happy_case() {
@@ -103,7 +673,7 @@ mod test {
         let root = read_directory(&roc_fs, "/");
 
         itertools::assert_equal(root.keys(), vec!["file_a", "file_b"]);
-        itertools::assert_equal(root.values(), vec![&Metadata::file(6), &Metadata::file(6)])
+        itertools::assert_equal(root.values().map(Metadata::len), vec![6, 6])
     }
 
     #[test]
@@ -115,7 +685,7 @@ mod test {
         let root = read_directory(&roc_fs, "/");
 
         itertools::assert_equal(root.keys(), vec!["file_a"]);
-        itertools::assert_equal(root.values(), vec![&Metadata::file(6)])
+        itertools::assert_equal(root.values().map(Metadata::len), vec![6])
     }
 
     #[test]
@@ -129,6 +699,267 @@ mod test {
         assert!(root.is_empty());
     }
 
+    #[test]
+    fn read_dir_shadowed() {
+        let upper = MemoryFS::default();
+        write!(upper.create_file("shared").unwrap(), "upper").unwrap();
+        let lower = MemoryFS::default();
+        write!(lower.create_file("shared").unwrap(), "lower file").unwrap();
+        write!(lower.create_file("lower").unwrap(), "lower").unwrap();
+
+        let roc_fs = RocFS::new(vec![
+            Box::new(upper),
+            Box::new(PhysicalFS::new("test/folder_a")),
+            Box::new(lower),
+        ]);
+        let entries = roc_fs
+            .read_dir("")
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect::<Vec<_>>();
+
+        // each name is listed once, with the metadata of the first layer containing it
+        assert_eq!(entries.len(), 3);
+        let shared = entries
+            .iter()
+            .find(|entry| entry.path.file_name().unwrap() == "shared")
+            .unwrap();
+        assert_eq!(shared.metadata, Metadata::file(5));
+        assert_eq!(roc_fs.metadata("shared").unwrap(), shared.metadata);
+    }
+
+    #[test]
+    fn read_dir_shadowed_file() {
+        let directory = || {
+            let fs = MemoryFS::default();
+            fs.create_dir("shared").unwrap();
+            write!(fs.create_file("shared/upper").unwrap(), "upper").unwrap();
+            fs
+        };
+        let file = || {
+            let fs = MemoryFS::default();
+            write!(fs.create_file("shared").unwrap(), "lower").unwrap();
+            fs
+        };
+
+        // a directory shadows files in later layers, but not in earlier ones
+        let roc_fs = RocFS::new(vec![Box::new(directory()), Box::new(file())]);
+        itertools::assert_equal(
+            read_directory(&roc_fs, "shared").keys(),
+            vec!["shared/upper"],
+        );
+        let roc_fs = RocFS::from_list((directory(), file()));
+        itertools::assert_equal(
+            read_directory(&roc_fs, "shared").keys(),
+            vec!["shared/upper"],
+        );
+
+        let roc_fs = RocFS::new(vec![Box::new(file()), Box::new(directory())]);
+        assert_eq!(
+            roc_fs.read_dir("shared").err().unwrap().kind(),
+            ErrorKind::NotADirectory
+        );
+        let roc_fs = RocFS::from_list((file(), directory()));
+        assert_eq!(
+            roc_fs.read_dir("shared").err().unwrap().kind(),
+            ErrorKind::NotADirectory
+        );
+    }
+
+    #[test]
+    fn layer_management() {
+        let patch = MemoryFS::default();
+        write!(patch.create_file("file_a").unwrap(), "patched").unwrap();
+
+        let roc_fs = RocFS::new(vec![Box::new(PhysicalFS::new("test/folder_a"))]);
+        roc_fs.push_layer(PhysicalFS::new("test/folder_b"));
+        assert!(roc_fs.insert_layer(3, MemoryFS::default()).is_err());
+        roc_fs.insert_layer(0, patch).unwrap();
+        assert_eq!(roc_fs.layer_count(), 3);
+
+        itertools::assert_equal(
+            read_directory(&roc_fs, "/").keys(),
+            vec!["file_a", "file_b"],
+        );
+        let mut patched_file = roc_fs.open_file("file_a").unwrap();
+
+        // removing the patch reveals the original file, and opened files are unaffected
+        roc_fs.remove_layer(0).unwrap();
+        assert_eq!(
+            roc_fs.remove_layer(2).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            roc_fs
+                .open_file("file_a")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "file a"
+        );
+        assert_eq!(patched_file.read_into_string().unwrap(), "patched");
+
+        roc_fs.remove_layer(1).unwrap();
+        assert!(!roc_fs.exists("file_b").unwrap());
+    }
+
+    #[test]
+    fn deletion_mask() {
+        let base = MemoryFS::default();
+        base.create_dir_all("textures/hd").unwrap();
+        write!(base.create_file("textures/hd/stone").unwrap(), "stone").unwrap();
+        write!(base.create_file("textures/brick").unwrap(), "brick").unwrap();
+        write!(base.create_file("intro").unwrap(), "intro").unwrap();
+
+        let roc_fs = RocFS::new(vec![
+            Box::new(PhysicalFS::new("test/folder_a")),
+            Box::new(MemoryFS::default()),
+            Box::new(base),
+        ]);
+        roc_fs
+            .set_deletion_mask(0, HashSet::from([PathBuf::from("textures/hd")]))
+            .unwrap();
+        roc_fs
+            .set_deletion_mask(1, |path: &Path| path == Path::new("intro"))
+            .unwrap();
+        assert!(roc_fs.set_deletion_mask(3, HashSet::new()).is_err());
+
+        // deleted directories hide their contents
+        for path in ["textures/hd", "/textures/hd/stone", "intro"] {
+            assert!(!roc_fs.exists(path).unwrap());
+        }
+        itertools::assert_equal(
+            read_directory(&roc_fs, "").keys(),
+            vec!["file_a", "textures"],
+        );
+        itertools::assert_equal(
+            read_directory(&roc_fs, "textures").keys(),
+            vec!["textures/brick"],
+        );
+        assert!(read_directory(&roc_fs, "textures/hd").is_empty());
+
+        // the masking layer's own entries are unaffected
+        roc_fs
+            .set_deletion_mask(0, HashSet::from([PathBuf::from("file_a")]))
+            .unwrap();
+        assert!(roc_fs.exists("file_a").unwrap());
+        assert!(roc_fs.exists("textures/hd/stone").unwrap());
+    }
+
+    #[test]
+    fn resolve() {
+        let roc_fs = RocFS::new(vec![
+            Box::new(PhysicalFS::new("test/folder_a")),
+            Box::new(PhysicalFS::new("test/folder_b")),
+            Box::new(MemoryFS::default()),
+        ]);
+
+        let (layer, metadata) = roc_fs.resolve("file_b").unwrap();
+        assert_eq!((layer, metadata.len()), (1, 6));
+        assert_eq!(roc_fs.resolve("/file_a").unwrap().0, 0);
+        roc_fs.open_file("file_b").unwrap();
+        assert!(roc_fs.resolve("nonsense").is_err());
+
+        let stats = roc_fs.layer_stats();
+        assert_eq!(stats[0], LayerStats { hits: 1, misses: 3 });
+        assert_eq!(stats[1], LayerStats { hits: 2, misses: 1 });
+        assert_eq!(stats[2], LayerStats { hits: 0, misses: 1 });
+
+        roc_fs.reset_layer_stats();
+        assert!(roc_fs
+            .layer_stats()
+            .iter()
+            .all(|stats| *stats == LayerStats::default()));
+    }
+
+    #[test]
+    fn from_list() {
+        let upper = MemoryFS::default();
+        write!(upper.create_file("file_b").unwrap(), "upper").unwrap();
+
+        let roc_fs = RocFS::from_list((
+            upper,
+            PhysicalFS::new("test/folder_a"),
+            PhysicalFS::new("test/folder_b"),
+        ));
+        let root = read_directory(&roc_fs, "/");
+        itertools::assert_equal(root.keys(), vec!["file_a", "file_b"]);
+        itertools::assert_equal(root.values().map(Metadata::len), vec![6, 5]);
+        assert_eq!(
+            roc_fs
+                .open_file("file_b")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "upper"
+        );
+        assert_eq!(
+            roc_fs.open_file("nonsense").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        let roc_fs = RocFS::from_list([
+            PhysicalFS::new("test/folder_c"),
+            PhysicalFS::new("test/folder_b"),
+        ]);
+        assert_eq!(roc_fs.metadata("file_b").unwrap().len(), 6);
+        itertools::assert_equal(read_directory(&roc_fs, "").keys(), vec!["file_b"]);
+        assert!(roc_fs.create_dir("folder").is_err());
+    }
+
+    #[test]
+    fn negative_cache() {
+        let layer = Arc::new(MemoryFS::default());
+        let roc_fs = RocFS::new(vec![Box::new(layer.clone())]).negative_cache(2);
+
+        for path in ["a", "/b", "c", "./c"] {
+            assert!(!roc_fs.exists(path).unwrap());
+        }
+        assert_eq!(roc_fs.layer_stats()[0].misses, 3);
+
+        // cached paths aren't looked up again until they're evicted
+        write!(layer.create_file("b").unwrap(), "b").unwrap();
+        write!(layer.create_file("c").unwrap(), "c").unwrap();
+        assert!(!roc_fs.exists("c").unwrap());
+        assert!(!roc_fs.exists("a").unwrap());
+        assert!(roc_fs.exists("b").unwrap());
+        assert_eq!(roc_fs.layer_stats()[0].misses, 4);
+
+        // changing the layers invalidates the cache
+        roc_fs.push_layer(MemoryFS::default());
+        assert!(roc_fs.exists("c").unwrap());
+        assert!(roc_fs.open_file("d").is_err());
+        roc_fs.create_file("d").unwrap();
+        assert!(roc_fs.exists("d").unwrap());
+
+        roc_fs.clear_negative_cache();
+        assert!(roc_fs.exists("c").unwrap());
+    }
+
+    #[test]
+    fn threads() {
+        let roc_fs = Arc::new(RocFS::new(vec![Box::new(PhysicalFS::new("test/folder_a"))]));
+
+        let handles = (0..4)
+            .map(|_| {
+                let roc_fs = roc_fs.clone();
+                thread::spawn(move || {
+                    roc_fs
+                        .open_file("file_a")
+                        .unwrap()
+                        .read_into_string()
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        roc_fs.push_layer(PhysicalFS::new("test/folder_b"));
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), "file a");
+        }
+        assert!(roc_fs.exists("file_b").unwrap());
+    }
+
     #[test]
     fn open_file_happy_case() {
         let folder_a = PhysicalFS::new("test/folder_a");
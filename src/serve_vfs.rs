@@ -0,0 +1,549 @@
+use crate::file::Metadata;
+use crate::tree::normalize_and_relativize;
+use crate::util::path;
+use crate::{util, FileSystem};
+use bytes::Bytes;
+use http::header::{
+    ACCEPT_RANGES, ALLOW, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+    IF_RANGE, LOCATION, RANGE,
+};
+use http::{HeaderValue, Method, Request, Response, StatusCode};
+use http_body::{Frame, SizeHint};
+use std::convert::Infallible;
+use std::future::{ready, Ready};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::UNIX_EPOCH;
+use tower_service::Service;
+
+/// A `tower` service that serves the files of a filesystem over HTTP, like `tower_http`'s `ServeDir` does for a
+/// directory on disk. It can be routed to by `axum` with `Router::nest_service`, or served by `hyper` directly.
+///
+/// `GET` and `HEAD` requests are answered with the file at the path of the request, supporting single range requests
+/// and conditional requests on ETags derived from the length and modification time of the file. Directories are
+/// answered with their index file if they have one, and otherwise with a generated listing of their entries.
+///
+/// The file, or the range of it that was requested, is read into memory when the request is handled, since files
+/// can't be sent between threads. Files whose modification times are unknown are read entirely, since their ETags are
+/// derived from their contents instead.
+pub struct ServeVfs<FS> {
+    fs: Arc<FS>,
+    index_file: Option<String>,
+    list_directories: bool,
+}
+
+impl<FS> Clone for ServeVfs<FS> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: self.fs.clone(),
+            index_file: self.index_file.clone(),
+            list_directories: self.list_directories,
+        }
+    }
+}
+
+impl<FS: FileSystem> ServeVfs<FS> {
+    /// Creates a service that serves the files of `fs`. The root of the filesystem is served at `/`.
+    ///
+    /// # Arguments
+    /// `fs`: The filesystem to serve.  
+    pub fn new(fs: FS) -> Self {
+        Self {
+            fs: Arc::new(fs),
+            index_file: Some(String::from("index.html")),
+            list_directories: true,
+        }
+    }
+
+    /// # Arguments
+    /// `index_file`: The name of the file that's served for the directory it's in, or `None` to never serve one.
+    /// Defaults to `index.html`.  
+    pub fn index_file(mut self, index_file: Option<&str>) -> Self {
+        self.index_file = index_file.map(str::to_owned);
+        self
+    }
+
+    /// # Arguments
+    /// `list_directories`: True if directories without an index file are answered with a listing of their entries,
+    /// rather than as not found. Defaults to true.  
+    pub fn list_directories(mut self, list_directories: bool) -> Self {
+        self.list_directories = list_directories;
+        self
+    }
+
+    /// Handles a request, returning its response.
+    fn respond<B>(&self, request: &Request<B>) -> Response<ServeVfsBody> {
+        let head = request.method() == Method::HEAD;
+        if request.method() != Method::GET && !head {
+            return status_response(StatusCode::METHOD_NOT_ALLOWED)
+                .header(ALLOW, "GET, HEAD")
+                .body(ServeVfsBody::empty())
+                .unwrap();
+        }
+
+        let request_path = path::decode(request.uri().path());
+        let Some(path) = normalize_and_relativize(&request_path)
+            .to_str()
+            .map(|path| path.trim_end_matches('/').to_owned())
+        else {
+            return empty_response(StatusCode::BAD_REQUEST);
+        };
+        let mut response = match util::metadata_or_root(&*self.fs, &path) {
+            Ok(metadata) if metadata.is_directory() => self.directory(request, &path),
+            Ok(metadata) => self.file(request, &path, &metadata),
+            Err(err) => Err(err),
+        }
+        .unwrap_or_else(|err| empty_response(error_status(&err)));
+
+        // the length of the body is kept, so that `HEAD` responses describe what `GET` would have returned
+        if head {
+            *response.body_mut() = ServeVfsBody::empty();
+        }
+        response
+    }
+
+    /// Answers a request for the directory at `path` with its index file or a listing of its entries.
+    fn directory<B>(
+        &self,
+        request: &Request<B>,
+        path: &str,
+    ) -> crate::Result<Response<ServeVfsBody>> {
+        // relative links only resolve within the directory if its URL ends with a slash
+        let uri_path = request.uri().path();
+        if !uri_path.ends_with('/') {
+            let location = match request.uri().query() {
+                Some(query) => format!("{uri_path}/?{query}"),
+                None => format!("{uri_path}/"),
+            };
+            return Ok(status_response(StatusCode::MOVED_PERMANENTLY)
+                .header(LOCATION, location)
+                .body(ServeVfsBody::empty())
+                .unwrap());
+        }
+
+        if let Some(index_file) = &self.index_file {
+            let index_path = match path {
+                "" => index_file.clone(),
+                path => format!("{path}/{index_file}"),
+            };
+            match self.fs.metadata(&index_path) {
+                Ok(metadata) if !metadata.is_directory() => {
+                    return self.file(request, &index_path, &metadata)
+                }
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if !self.list_directories {
+            return Ok(empty_response(StatusCode::NOT_FOUND));
+        }
+
+        let mut entries = Vec::new();
+        for entry in self.fs.read_dir(path)? {
+            let entry = entry?;
+            if let Some(name) = entry.path.file_name().and_then(|name| name.to_str()) {
+                entries.push((name.to_owned(), entry.metadata.is_directory()));
+            }
+        }
+        entries.sort();
+
+        let title = escape(&format!("/{path}"));
+        let mut html = format!("<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Index of {title}</title></head><body><h1>Index of {title}</h1><ul>");
+        if !path.is_empty() {
+            html.push_str("<li><a href=\"../\">../</a></li>");
+        }
+        for (name, is_directory) in entries {
+            let suffix = if is_directory { "/" } else { "" };
+            html.push_str(&format!(
+                "<li><a href=\"{}{suffix}\">{}{suffix}</a></li>",
+                path::encode(&name),
+                escape(&name)
+            ));
+        }
+        html.push_str("</ul></body></html>");
+
+        Ok(status_response(StatusCode::OK)
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(CONTENT_LENGTH, html.len())
+            .body(ServeVfsBody::new(Bytes::from(html)))
+            .unwrap())
+    }
+
+    /// Answers a request for the file at `path`, whose metadata is `metadata`.
+    fn file<B>(
+        &self,
+        request: &Request<B>,
+        path: &str,
+        metadata: &Metadata,
+    ) -> crate::Result<Response<ServeVfsBody>> {
+        let mut file = self.fs.open_file(path)?;
+        // files without modification times are identified by their contents, so they're read entirely
+        let (etag, contents) = match modified_etag(metadata) {
+            Some(etag) => (etag, None),
+            None => {
+                let contents = Bytes::from(file.read_into_vec()?);
+                (contents_etag(&contents), Some(contents))
+            }
+        };
+        let len = contents
+            .as_ref()
+            .map_or(metadata.len(), |contents| contents.len() as u64);
+        let mut response = status_response(StatusCode::OK)
+            .header(CONTENT_TYPE, content_type(path))
+            .header(ACCEPT_RANGES, "bytes")
+            .header(ETAG, &etag);
+
+        // the file is unchanged if its ETag is one the client has
+        let headers = request.headers();
+        if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+            let if_none_match = if_none_match.to_str().unwrap_or_default();
+            if if_none_match.trim() == "*"
+                || if_none_match
+                    .split(',')
+                    .any(|tag| tag.trim().trim_start_matches("W/") == etag)
+            {
+                return Ok(response
+                    .status(StatusCode::NOT_MODIFIED)
+                    .body(ServeVfsBody::empty())
+                    .unwrap());
+            }
+        }
+
+        // ranges are ignored if they're of another version of the file
+        let range = headers.get(RANGE).filter(|_| {
+            headers
+                .get(IF_RANGE)
+                .is_none_or(|if_range| if_range.to_str().is_ok_and(|tag| tag.trim() == etag))
+        });
+        let (start, end) =
+            match range.map(|range| parse_range(range.to_str().unwrap_or_default(), len)) {
+                Some(Some((start, end))) => {
+                    response = response
+                        .status(StatusCode::PARTIAL_CONTENT)
+                        .header(CONTENT_RANGE, format!("bytes {start}-{end}/{len}"));
+                    (start, end + 1)
+                }
+                Some(None) => {
+                    return Ok(status_response(StatusCode::RANGE_NOT_SATISFIABLE)
+                        .header(CONTENT_RANGE, format!("bytes */{len}"))
+                        .body(ServeVfsBody::empty())
+                        .unwrap())
+                }
+                None => (0, len),
+            };
+
+        let data = match contents {
+            Some(contents) => contents.slice(start as usize..end as usize),
+            None => {
+                file.seek(SeekFrom::Start(start))?;
+                let mut data = Vec::new();
+                file.take(end - start).read_to_end(&mut data)?;
+                if (data.len() as u64) < end - start {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+                Bytes::from(data)
+            }
+        };
+        Ok(response
+            .header(CONTENT_LENGTH, data.len())
+            .body(ServeVfsBody::new(data))
+            .unwrap())
+    }
+}
+
+impl<FS: FileSystem, B> Service<Request<B>> for ServeVfs<FS> {
+    type Response = Response<ServeVfsBody>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        ready(Ok(self.respond(&request)))
+    }
+}
+
+/// The body of a response of `ServeVfs`, which is sent as a single frame.
+#[derive(Debug, Default)]
+pub struct ServeVfsBody {
+    data: Option<Bytes>,
+}
+
+impl ServeVfsBody {
+    fn new(data: Bytes) -> Self {
+        Self { data: Some(data) }
+    }
+
+    fn empty() -> Self {
+        Self::default()
+    }
+}
+
+impl http_body::Body for ServeVfsBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        Poll::Ready(self.data.take().map(|data| Ok(Frame::data(data))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.data.as_ref().map_or(0, |data| data.len() as u64))
+    }
+}
+
+/// Returns the builder of a response with a status code.
+fn status_response(status: StatusCode) -> http::response::Builder {
+    Response::builder().status(status)
+}
+
+/// Returns a response with a status code and an empty body.
+fn empty_response(status: StatusCode) -> Response<ServeVfsBody> {
+    status_response(status)
+        .header(CONTENT_LENGTH, 0)
+        .body(ServeVfsBody::empty())
+        .unwrap()
+}
+
+/// Returns the strong ETag of a file from its length and modification time, or `None` if the modification time is
+/// unknown, since the length alone doesn't identify its contents.
+///
+/// # Arguments
+/// `metadata`: The metadata of the file.  
+fn modified_etag(metadata: &Metadata) -> Option<String> {
+    let modified = metadata.modified?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!(
+        "\"{:x}-{:x}.{:x}\"",
+        metadata.len(),
+        modified.as_secs(),
+        modified.subsec_nanos()
+    ))
+}
+
+/// Returns the strong ETag of a file from its contents.
+///
+/// # Arguments
+/// `contents`: The contents of the file.  
+fn contents_etag(contents: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    format!("\"{:x}-{:016x}\"", contents.len(), hasher.finish())
+}
+
+/// Returns the content type of the file at `path` by its extension.
+///
+/// # Arguments
+/// `path`: The path of the file.  
+fn content_type(path: &str) -> HeaderValue {
+    let extension = path
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    HeaderValue::from_static(match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json" | "map") => "application/json",
+        Some("txt" | "md") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("wasm") => "application/wasm",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("mp4") => "video/mp4",
+        _ => "application/octet-stream",
+    })
+}
+
+/// Parses a `Range` header of a file that's `len` bytes long. Returns the first and last byte of the range, or `None`
+/// if it can't be satisfied. Only single ranges are supported.
+///
+/// # Arguments
+/// `range`: The value of the header.  
+/// `len`: The length of the file.  
+fn parse_range(range: &str, len: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.trim().strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // the last bytes of the file
+        ("", suffix) => (
+            len.saturating_sub(suffix.parse().ok()?),
+            len.checked_sub(1)?,
+        ),
+        (start, "") => (start.parse().ok()?, len.checked_sub(1)?),
+        (start, end) => (
+            start.parse().ok()?,
+            end.parse::<u64>().ok()?.min(len.checked_sub(1)?),
+        ),
+    };
+    (start <= end && start < len).then_some((start, end))
+}
+
+/// Escapes the characters of `text` that are special in HTML.
+///
+/// # Arguments
+/// `text`: The text to escape.  
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Returns the status code of an error.
+fn error_status(err: &io::Error) -> StatusCode {
+    match err.kind() {
+        ErrorKind::NotFound | ErrorKind::NotADirectory => StatusCode::NOT_FOUND,
+        ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    use crate::serve_vfs::{parse_range, ServeVfs, ServeVfsBody};
+    use crate::FileSystem;
+    use http::{Method, Request, Response, StatusCode};
+    use http_body::Body;
+    use std::io::Write;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+    use tower_service::Service;
+
+    /// Makes a request, returning the response and its body.
+    fn request<FS: FileSystem>(
+        service: &mut ServeVfs<FS>,
+        method: Method,
+        uri: &str,
+        headers: &[(&str, &str)],
+    ) -> (Response<ServeVfsBody>, String) {
+        let mut request = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let mut response = service
+            .call(request.body(()).unwrap())
+            .into_inner()
+            .unwrap();
+
+        let mut body = Vec::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        while let Poll::Ready(Some(frame)) = Pin::new(response.body_mut()).poll_frame(&mut cx) {
+            body.extend_from_slice(frame.unwrap().data_ref().unwrap());
+        }
+        (response, String::from_utf8(body).unwrap())
+    }
+
+    /// Returns a service of a small tree in `fs`.
+    fn service<FS: FileSystem>(fs: FS) -> ServeVfs<FS> {
+        fs.create_dir("dir").unwrap();
+        fs.create_dir("site").unwrap();
+        write!(fs.create_file("dir/a file.txt").unwrap(), "hello world").unwrap();
+        write!(fs.create_file("site/index.html").unwrap(), "<h1>hi</h1>").unwrap();
+        ServeVfs::new(fs)
+    }
+
+    #[test]
+    fn files() {
+        let mut service = service(MemoryFS::default());
+
+        let (response, body) = request(&mut service, Method::GET, "/dir/a%20file.txt", &[]);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-length"], "11");
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(body, "hello world");
+        let etag = response.headers()["etag"].to_str().unwrap().to_owned();
+
+        let (response, body) = request(&mut service, Method::HEAD, "/dir/a%20file.txt", &[]);
+        assert_eq!(response.headers()["content-length"], "11");
+        assert_eq!(body, "");
+
+        // ranges are served unless they're of another version
+        let range = [("range", "bytes=6-")];
+        let (response, body) = request(&mut service, Method::GET, "/dir/a%20file.txt", &range);
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()["content-range"], "bytes 6-10/11");
+        assert_eq!(body, "world");
+        let range = [("range", "bytes=6-"), ("if-range", "\"other\"")];
+        let (response, body) = request(&mut service, Method::GET, "/dir/a%20file.txt", &range);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body, "hello world");
+        let range = [("range", "bytes=11-")];
+        let (response, _) = request(&mut service, Method::GET, "/dir/a%20file.txt", &range);
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+        let if_none_match = [("if-none-match", etag.as_str())];
+        let (response, body) = request(
+            &mut service,
+            Method::GET,
+            "/dir/a%20file.txt",
+            &if_none_match,
+        );
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(body, "");
+
+        let (response, _) = request(&mut service, Method::GET, "/missing", &[]);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let (response, _) = request(&mut service, Method::PUT, "/dir/a%20file.txt", &[]);
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn directories() {
+        let mut service = service(MemoryFS::default());
+
+        let (response, _) = request(&mut service, Method::GET, "/dir?sort=name", &[]);
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()["location"], "/dir/?sort=name");
+
+        let (response, body) = request(&mut service, Method::GET, "/", &[]);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body.contains(r#"<a href="dir/">dir/</a>"#));
+        let (_, body) = request(&mut service, Method::GET, "/dir/", &[]);
+        assert!(body.contains(r#"<a href="a%20file.txt">a file.txt</a>"#));
+
+        let (response, body) = request(&mut service, Method::GET, "/site/", &[]);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(body, "<h1>hi</h1>");
+
+        let mut service = service.index_file(None).list_directories(false);
+        let (response, _) = request(&mut service, Method::GET, "/site/", &[]);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("bytes=0-4", 10), Some((0, 4)));
+        assert_eq!(parse_range("bytes=-3", 10), Some((7, 9)));
+        assert_eq!(parse_range("bytes=5-100", 10), Some((5, 9)));
+        assert_eq!(parse_range("bytes=4-2", 10), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+    }
+}
@@ -0,0 +1,1067 @@
+mod subsystem;
+mod transport;
+
+use crate::sftp::subsystem::Subsystem;
+use crate::sftp::transport::Transport;
+use crate::util::{encode_base64, invalid_input, BASE64};
+use crate::FileSystem;
+use ed25519_dalek::{Signature, SigningKey, Verifier, VerifyingKey};
+use std::collections::HashMap;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::thread;
+
+/// The bytes a client may send on a channel before the window is adjusted.
+const WINDOW_SIZE: u32 = 2 * 1024 * 1024;
+/// The most bytes a client may send in a single data message.
+const MAX_DATA_LEN: u32 = 32 * 1024;
+/// The most channels a connection may have open at once.
+const MAX_CHANNELS: usize = 16;
+/// The most times a connection may fail to authenticate before it's disconnected.
+const MAX_AUTH_FAILURES: usize = 10;
+
+/// The name of Ed25519 keys and signatures.
+const ED25519: &str = "ssh-ed25519";
+
+/// SSH message numbers.
+pub(crate) mod message {
+    pub(crate) const DISCONNECT: u8 = 1;
+    pub(crate) const IGNORE: u8 = 2;
+    pub(crate) const UNIMPLEMENTED: u8 = 3;
+    pub(crate) const DEBUG: u8 = 4;
+    pub(crate) const SERVICE_REQUEST: u8 = 5;
+    pub(crate) const SERVICE_ACCEPT: u8 = 6;
+    pub(crate) const KEXINIT: u8 = 20;
+    pub(crate) const NEWKEYS: u8 = 21;
+    pub(crate) const KEX_ECDH_INIT: u8 = 30;
+    pub(crate) const KEX_ECDH_REPLY: u8 = 31;
+    pub(crate) const USERAUTH_REQUEST: u8 = 50;
+    pub(crate) const USERAUTH_FAILURE: u8 = 51;
+    pub(crate) const USERAUTH_SUCCESS: u8 = 52;
+    pub(crate) const USERAUTH_PK_OK: u8 = 60;
+    pub(crate) const GLOBAL_REQUEST: u8 = 80;
+    pub(crate) const REQUEST_FAILURE: u8 = 82;
+    pub(crate) const CHANNEL_OPEN: u8 = 90;
+    pub(crate) const CHANNEL_OPEN_CONFIRMATION: u8 = 91;
+    pub(crate) const CHANNEL_OPEN_FAILURE: u8 = 92;
+    pub(crate) const CHANNEL_WINDOW_ADJUST: u8 = 93;
+    pub(crate) const CHANNEL_DATA: u8 = 94;
+    pub(crate) const CHANNEL_EXTENDED_DATA: u8 = 95;
+    pub(crate) const CHANNEL_EOF: u8 = 96;
+    pub(crate) const CHANNEL_CLOSE: u8 = 97;
+    pub(crate) const CHANNEL_REQUEST: u8 = 98;
+    pub(crate) const CHANNEL_SUCCESS: u8 = 99;
+    pub(crate) const CHANNEL_FAILURE: u8 = 100;
+}
+
+/// Reasons for disconnecting.
+pub(crate) mod disconnect {
+    pub(crate) const PROTOCOL_ERROR: u32 = 2;
+    pub(crate) const KEY_EXCHANGE_FAILED: u32 = 3;
+    pub(crate) const SERVICE_NOT_AVAILABLE: u32 = 7;
+    pub(crate) const NO_MORE_AUTH_METHODS_AVAILABLE: u32 = 14;
+}
+
+/// Reasons for refusing to open channels.
+const OPEN_UNKNOWN_CHANNEL_TYPE: u32 = 3;
+const OPEN_RESOURCE_SHORTAGE: u32 = 4;
+
+/// An SSH server that serves a filesystem with the SFTP subsystem, so that it can be browsed and edited with `sftp`,
+/// `scp`, `sshfs` or any other SFTP client.
+///
+/// Users authenticate with passwords or Ed25519 keys, and have access to the whole filesystem, whose root is their
+/// home directory. The permissions of files are reported from their modes, without the write bits if the server is
+/// read-only, and errors are reported with the SFTP status closest to them. Only the SFTP subsystem is served, so
+/// shells, commands and forwarding are refused.
+///
+/// Connections are secured with `curve25519-sha256` key exchanges, the `ssh-ed25519` host key, `aes128-ctr` or
+/// `aes256-ctr`, and `hmac-sha2-256`, which every OpenSSH client since 6.5 supports.
+pub struct Server<FS> {
+    fs: FS,
+    host_key: SigningKey,
+    users: HashMap<String, User>,
+    read_only: bool,
+}
+
+/// How a user may authenticate.
+#[derive(Default)]
+struct User {
+    password: Option<String>,
+    keys: Vec<VerifyingKey>,
+}
+
+impl<FS: FileSystem + Sync> Server<FS> {
+    /// Creates a server for a filesystem, which no user may authenticate to until they're added.
+    ///
+    /// # Arguments
+    /// `fs`: The filesystem to serve.  
+    /// `host_key`: The secret key the server is identified by, which is the 32-byte seed of an Ed25519 key. Clients
+    /// remember it, so it should be kept between runs of the server.  
+    pub fn new(fs: FS, host_key: [u8; 32]) -> Self {
+        Self {
+            fs,
+            host_key: SigningKey::from_bytes(&host_key),
+            users: HashMap::new(),
+            read_only: false,
+        }
+    }
+
+    /// Lets `user` authenticate with `password`.
+    ///
+    /// # Arguments
+    /// `user`: The name of the user.  
+    /// `password`: The password of the user.  
+    pub fn password(mut self, user: &str, password: &str) -> Self {
+        self.users.entry(user.to_owned()).or_default().password = Some(password.to_owned());
+        self
+    }
+
+    /// Lets `user` authenticate with a public key, in the format of the lines of `authorized_keys` files without
+    /// options, such as `ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAI... user@host`. Only Ed25519 keys are supported.
+    ///
+    /// # Arguments
+    /// `user`: The name of the user.  
+    /// `key`: The public key.  
+    pub fn authorized_key(mut self, user: &str, key: &str) -> crate::Result<Self> {
+        let mut fields = key.split_whitespace();
+        if fields.next() != Some(ED25519) {
+            return Err(invalid_input("Only ssh-ed25519 keys are supported"));
+        }
+        let key = fields
+            .next()
+            .and_then(decode_base64)
+            .and_then(|blob| {
+                let mut decoder = Decoder::new(&blob);
+                if decoder.str().ok()? != ED25519 {
+                    return None;
+                }
+                VerifyingKey::from_bytes(decoder.string().ok()?.try_into().ok()?).ok()
+            })
+            .ok_or_else(|| invalid_input("Malformed public key"))?;
+        self.users
+            .entry(user.to_owned())
+            .or_default()
+            .keys
+            .push(key);
+        Ok(self)
+    }
+
+    /// Sets whether the filesystem is served read-only, so that opening files for writing and every change is denied.
+    ///
+    /// # Arguments
+    /// `read_only`: Whether the filesystem is served read-only.  
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Returns the public key of the host key in the format of `known_hosts` files, after the host names.
+    pub fn host_public_key(&self) -> String {
+        format!(
+            "{ED25519} {}",
+            encode_base64(&key_blob(&self.host_key.verifying_key()))
+        )
+    }
+
+    /// Serves the filesystem on `addr` until accepting a connection fails.
+    ///
+    /// # Arguments
+    /// `addr`: The address to listen on.  
+    pub fn serve<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        self.serve_listener(TcpListener::bind(addr)?)
+    }
+
+    /// Serves the filesystem on the connections accepted by `listener` until accepting one fails, each on its own
+    /// thread.
+    ///
+    /// # Arguments
+    /// `listener`: The listener to accept connections from.  
+    pub fn serve_listener(&self, listener: TcpListener) -> io::Result<()> {
+        thread::scope(|scope| {
+            for stream in listener.incoming() {
+                let stream = stream?;
+                stream.set_nodelay(true)?;
+                scope.spawn(move || self.serve_connection(stream));
+            }
+            Ok(())
+        })
+    }
+
+    /// Serves a single connection until it's closed, such as one that was accepted elsewhere.
+    ///
+    /// # Arguments
+    /// `stream`: The connection.  
+    pub fn serve_connection<S: Read + Write>(&self, stream: S) -> io::Result<()> {
+        let mut transport = Transport::accept(stream, &self.host_key)?;
+        let Some(user) = self.authenticate(&mut transport)? else {
+            return Ok(());
+        };
+        Connection {
+            server: self,
+            transport,
+            user,
+            channels: HashMap::new(),
+            next_channel: 0,
+        }
+        .run()
+    }
+
+    /// Authenticates the user of a connection. Returns their name, or `None` if the connection was closed first.
+    fn authenticate<S: Read + Write>(
+        &self,
+        transport: &mut Transport<S>,
+    ) -> io::Result<Option<String>> {
+        let mut failures = 0;
+        while let Some(request) = transport.read()? {
+            let mut decoder = Decoder::new(&request[1..]);
+            match request[0] {
+                message::SERVICE_REQUEST => {
+                    let service = decoder.str()?;
+                    if service != "ssh-userauth" {
+                        transport
+                            .disconnect(disconnect::SERVICE_NOT_AVAILABLE, "Unknown service")?;
+                        return Ok(None);
+                    }
+                    transport.write(
+                        &Encoder::message(message::SERVICE_ACCEPT)
+                            .str(service)
+                            .finish(),
+                    )?;
+                }
+                message::USERAUTH_REQUEST => {
+                    let (name, service, method) = (decoder.str()?, decoder.str()?, decoder.str()?);
+                    let user = self.users.get(name).filter(|_| service == "ssh-connection");
+                    let authenticated = match (user, method) {
+                        (Some(user), "password") => {
+                            let (_change, password) = (decoder.bool()?, decoder.string()?);
+                            user.password.as_ref().is_some_and(|expected| {
+                                hmac_sha256::Hash::hash(password)
+                                    == hmac_sha256::Hash::hash(expected.as_bytes())
+                            })
+                        }
+                        (Some(user), "publickey") => {
+                            let (signed, algorithm, blob) =
+                                (decoder.bool()?, decoder.str()?, decoder.string()?);
+                            let key = user.keys.iter().find(|key| key_blob(key) == blob);
+                            match key {
+                                Some(key) if algorithm == ED25519 && signed => {
+                                    let signed_data = Encoder::new()
+                                        .string(transport.session_id())
+                                        .u8(message::USERAUTH_REQUEST)
+                                        .str(name)
+                                        .str(service)
+                                        .str(method)
+                                        .bool(true)
+                                        .str(algorithm)
+                                        .string(blob)
+                                        .finish();
+                                    verify(key, &signed_data, decoder.string()?)
+                                }
+                                // the client asks whether the key would be accepted before signing with it
+                                Some(_) if algorithm == ED25519 => {
+                                    transport.write(
+                                        &Encoder::message(message::USERAUTH_PK_OK)
+                                            .str(algorithm)
+                                            .string(blob)
+                                            .finish(),
+                                    )?;
+                                    continue;
+                                }
+                                _ => false,
+                            }
+                        }
+                        _ => false,
+                    };
+
+                    if authenticated {
+                        transport.write(&Encoder::message(message::USERAUTH_SUCCESS).finish())?;
+                        return Ok(Some(name.to_owned()));
+                    }
+                    // clients ask which methods may be used with the `none` method
+                    if method != "none" {
+                        failures += 1;
+                    }
+                    if failures >= MAX_AUTH_FAILURES {
+                        transport.disconnect(
+                            disconnect::NO_MORE_AUTH_METHODS_AVAILABLE,
+                            "Too many authentication failures",
+                        )?;
+                        return Ok(None);
+                    }
+                    transport.write(
+                        &Encoder::message(message::USERAUTH_FAILURE)
+                            .str("publickey,password")
+                            .bool(false)
+                            .finish(),
+                    )?;
+                }
+                _ => {
+                    transport.disconnect(disconnect::PROTOCOL_ERROR, "Not authenticated")?;
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// An authenticated connection.
+struct Connection<'a, FS, S> {
+    server: &'a Server<FS>,
+    transport: Transport<'a, S>,
+    user: String,
+    channels: HashMap<u32, Channel<'a, FS>>,
+    next_channel: u32,
+}
+
+/// A session channel.
+struct Channel<'a, FS> {
+    /// The number the client refers to the channel by.
+    remote: u32,
+    /// The bytes that may be sent until the client adjusts the window.
+    remote_window: u32,
+    /// The most bytes the client accepts in a single data message.
+    remote_max_data_len: u32,
+    /// The bytes that were received since the window was last adjusted.
+    received: u32,
+    /// The SFTP subsystem, once the client starts it.
+    subsystem: Option<Subsystem<'a, FS>>,
+    /// The bytes that were received for the subsystem, but don't make up a whole request yet.
+    input: Vec<u8>,
+    /// The bytes of replies that don't fit in the window yet.
+    output: Vec<u8>,
+    /// Whether the client won't send any more data.
+    eof: bool,
+    /// Whether the server closed the channel.
+    closed: bool,
+}
+
+impl<FS: FileSystem + Sync, S: Read + Write> Connection<'_, FS, S> {
+    /// Handles the messages of the connection until it's closed.
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(request) = self.transport.read()? {
+            let mut decoder = Decoder::new(&request[1..]);
+            match request[0] {
+                message::GLOBAL_REQUEST => {
+                    let (_name, want_reply) = (decoder.str()?, decoder.bool()?);
+                    if want_reply {
+                        self.transport
+                            .write(&Encoder::message(message::REQUEST_FAILURE).finish())?;
+                    }
+                }
+                message::CHANNEL_OPEN => self.open(&mut decoder)?,
+                message::CHANNEL_REQUEST => {
+                    let (id, ty, want_reply) = (decoder.u32()?, decoder.str()?, decoder.bool()?);
+                    let channel = channel(&mut self.channels, id)?;
+                    let accepted = match ty {
+                        "subsystem" if channel.subsystem.is_none() => {
+                            decoder.str()? == "sftp" && {
+                                channel.subsystem = Some(Subsystem::new(
+                                    &self.server.fs,
+                                    &self.user,
+                                    self.server.read_only,
+                                ));
+                                true
+                            }
+                        }
+                        // environment variables are ignored, while shells, commands and terminals are refused
+                        _ => false,
+                    };
+                    if want_reply {
+                        let reply = match accepted {
+                            true => message::CHANNEL_SUCCESS,
+                            false => message::CHANNEL_FAILURE,
+                        };
+                        self.transport
+                            .write(&Encoder::message(reply).u32(channel.remote).finish())?;
+                    }
+                }
+                message::CHANNEL_DATA => {
+                    let (id, data) = (decoder.u32()?, decoder.string()?);
+                    let channel = channel(&mut self.channels, id)?;
+                    channel.receive(data, &mut self.transport)?;
+                }
+                // standard error isn't meaningful to the subsystem, but still counts against the window
+                message::CHANNEL_EXTENDED_DATA => {
+                    let (id, _ty, data) = (decoder.u32()?, decoder.u32()?, decoder.string()?);
+                    let channel = channel(&mut self.channels, id)?;
+                    channel.consume(data.len(), &mut self.transport)?;
+                }
+                message::CHANNEL_WINDOW_ADJUST => {
+                    let (id, len) = (decoder.u32()?, decoder.u32()?);
+                    let channel = channel(&mut self.channels, id)?;
+                    channel.remote_window = channel.remote_window.saturating_add(len);
+                    channel.flush(&mut self.transport)?;
+                }
+                message::CHANNEL_EOF => {
+                    let channel = channel(&mut self.channels, decoder.u32()?)?;
+                    channel.eof = true;
+                    channel.flush(&mut self.transport)?;
+                }
+                message::CHANNEL_CLOSE => {
+                    let id = decoder.u32()?;
+                    let channel = channel(&mut self.channels, id)?;
+                    if !channel.closed {
+                        self.transport.write(
+                            &Encoder::message(message::CHANNEL_CLOSE)
+                                .u32(channel.remote)
+                                .finish(),
+                        )?;
+                    }
+                    self.channels.remove(&id);
+                }
+                // the server doesn't make requests, so it doesn't expect replies
+                _ => self.transport.unimplemented()?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens a channel, if it's a session.
+    fn open(&mut self, request: &mut Decoder) -> io::Result<()> {
+        let (ty, remote, window, max_data_len) = (
+            request.str()?,
+            request.u32()?,
+            request.u32()?,
+            request.u32()?,
+        );
+        let failure = match ty {
+            "session" if self.channels.len() < MAX_CHANNELS => None,
+            "session" => Some((OPEN_RESOURCE_SHORTAGE, "Too many channels")),
+            _ => Some((OPEN_UNKNOWN_CHANNEL_TYPE, "Only sessions are supported")),
+        };
+        if let Some((reason, description)) = failure {
+            return self.transport.write(
+                &Encoder::message(message::CHANNEL_OPEN_FAILURE)
+                    .u32(remote)
+                    .u32(reason)
+                    .str(description)
+                    .str("")
+                    .finish(),
+            );
+        }
+
+        let id = self.next_channel;
+        self.next_channel = self.next_channel.wrapping_add(1);
+        self.channels.insert(
+            id,
+            Channel {
+                remote,
+                remote_window: window,
+                remote_max_data_len: max_data_len.max(1),
+                received: 0,
+                subsystem: None,
+                input: Vec::new(),
+                output: Vec::new(),
+                eof: false,
+                closed: false,
+            },
+        );
+        self.transport.write(
+            &Encoder::message(message::CHANNEL_OPEN_CONFIRMATION)
+                .u32(remote)
+                .u32(id)
+                .u32(WINDOW_SIZE)
+                .u32(MAX_DATA_LEN)
+                .finish(),
+        )
+    }
+}
+
+impl<FS: FileSystem> Channel<'_, FS> {
+    /// Handles data sent by the client, passing every whole request to the subsystem.
+    fn receive<S: Read + Write>(
+        &mut self,
+        data: &[u8],
+        transport: &mut Transport<S>,
+    ) -> io::Result<()> {
+        if let Some(subsystem) = &mut self.subsystem {
+            self.input.extend_from_slice(data);
+            while let Some(len) = self.input.get(..4) {
+                let len = u32::from_be_bytes(len.try_into().unwrap()) as usize + 4;
+                if len > subsystem::MAX_REQUEST_LEN {
+                    return Err(malformed("SFTP request is too long"));
+                }
+                if self.input.len() < len {
+                    break;
+                }
+                let reply = subsystem.handle(&self.input[4..len]);
+                self.output.extend_from_slice(&reply);
+                self.input.drain(..len);
+            }
+        }
+        self.consume(data.len(), transport)
+    }
+
+    /// Counts data received from the client against the window, adjusting it once half of it was used, and sends
+    /// what fits in the client's window.
+    fn consume<S: Read + Write>(
+        &mut self,
+        len: usize,
+        transport: &mut Transport<S>,
+    ) -> io::Result<()> {
+        if len > MAX_DATA_LEN as usize || self.received as usize + len > WINDOW_SIZE as usize {
+            return Err(malformed("Channel data exceeds the window"));
+        }
+        self.received += len as u32;
+        if self.received >= WINDOW_SIZE / 2 {
+            transport.write(
+                &Encoder::message(message::CHANNEL_WINDOW_ADJUST)
+                    .u32(self.remote)
+                    .u32(self.received)
+                    .finish(),
+            )?;
+            self.received = 0;
+        }
+        self.flush(transport)
+    }
+
+    /// Sends as much of the output as fits in the client's window, and closes the channel once everything was sent
+    /// after the client's end of data.
+    fn flush<S: Read + Write>(&mut self, transport: &mut Transport<S>) -> io::Result<()> {
+        while !self.output.is_empty() && self.remote_window > 0 {
+            let len = self
+                .output
+                .len()
+                .min(self.remote_window as usize)
+                .min(self.remote_max_data_len as usize);
+            transport.write(
+                &Encoder::message(message::CHANNEL_DATA)
+                    .u32(self.remote)
+                    .string(&self.output[..len])
+                    .finish(),
+            )?;
+            self.output.drain(..len);
+            self.remote_window -= len as u32;
+        }
+
+        if self.eof && self.output.is_empty() && !self.closed {
+            transport.write(
+                &Encoder::message(message::CHANNEL_EOF)
+                    .u32(self.remote)
+                    .finish(),
+            )?;
+            transport.write(
+                &Encoder::message(message::CHANNEL_CLOSE)
+                    .u32(self.remote)
+                    .finish(),
+            )?;
+            self.closed = true;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the channel numbered `id`.
+fn channel<'a, 'b, FS>(
+    channels: &'b mut HashMap<u32, Channel<'a, FS>>,
+    id: u32,
+) -> io::Result<&'b mut Channel<'a, FS>> {
+    channels
+        .get_mut(&id)
+        .ok_or_else(|| malformed("Unknown channel"))
+}
+
+/// Returns the SSH encoding of an Ed25519 public key.
+fn key_blob(key: &VerifyingKey) -> Vec<u8> {
+    Encoder::new().str(ED25519).string(key.as_bytes()).finish()
+}
+
+/// Returns true if `signature` is a valid Ed25519 signature of `data` by `key`, in the SSH encoding.
+fn verify(key: &VerifyingKey, data: &[u8], signature: &[u8]) -> bool {
+    let mut decoder = Decoder::new(signature);
+    let signature = match (decoder.str(), decoder.string()) {
+        (Ok(ED25519), Ok(signature)) => signature,
+        _ => return false,
+    };
+    signature
+        .try_into()
+        .is_ok_and(|signature| key.verify(data, &Signature::from_bytes(signature)).is_ok())
+}
+
+/// Returns an error indicating that a message was malformed.
+fn malformed(error: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, error)
+}
+
+/// Encodes a message, or any SSH data.
+pub(crate) struct Encoder(Vec<u8>);
+
+impl Encoder {
+    pub(crate) fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Starts a message.
+    ///
+    /// # Arguments
+    /// `ty`: The number of the message.  
+    pub(crate) fn message(ty: u8) -> Self {
+        Self(vec![ty])
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) -> &mut Self {
+        self.0.push(value);
+        self
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) -> &mut Self {
+        self.u8(value as u8)
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) -> &mut Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    /// Appends bytes without a length.
+    pub(crate) fn raw(&mut self, value: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    /// Appends bytes, prefixed with their length.
+    pub(crate) fn string(&mut self, value: &[u8]) -> &mut Self {
+        self.u32(value.len() as u32).raw(value)
+    }
+
+    /// Appends a string, prefixed with its length.
+    pub(crate) fn str(&mut self, value: &str) -> &mut Self {
+        self.string(value.as_bytes())
+    }
+
+    /// Appends an unsigned big-endian integer as a multiple precision integer.
+    pub(crate) fn mpint(&mut self, value: &[u8]) -> &mut Self {
+        let start = value
+            .iter()
+            .position(|byte| *byte != 0)
+            .unwrap_or(value.len());
+        let value = &value[start..];
+        // a leading zero keeps integers whose top bit is set positive
+        match value.first() {
+            Some(byte) if byte & 0x80 != 0 => self.u32(value.len() as u32 + 1).u8(0).raw(value),
+            _ => self.string(value),
+        }
+    }
+
+    /// Finishes the message, returning its bytes.
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+/// Decodes a message, or any SSH data.
+pub(crate) struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+
+    /// Takes `len` bytes.
+    ///
+    /// # Arguments
+    /// `len`: The number of bytes.  
+    pub(crate) fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(malformed("Truncated SSH message"));
+        }
+
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub(crate) fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Takes bytes prefixed with their length.
+    pub(crate) fn string(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Takes a string prefixed with its length.
+    pub(crate) fn str(&mut self) -> io::Result<&'a str> {
+        std::str::from_utf8(self.string()?).map_err(|_| malformed("SSH string isn't UTF-8"))
+    }
+}
+
+/// Decodes padded Base64. Returns `None` if it's malformed.
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.trim_end_matches('=').as_bytes();
+    let mut data = Vec::with_capacity(encoded.len() * 3 / 4);
+    let (mut bits, mut len) = (0u32, 0);
+    for byte in encoded {
+        bits = bits << 6 | BASE64.iter().position(|digit| digit == byte)? as u32;
+        len += 6;
+        if len >= 8 {
+            len -= 8;
+            data.push((bits >> len) as u8);
+        }
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    #[cfg(unix)]
+    use crate::physical_fs::PhysicalFS;
+    use crate::sftp::transport::test::{
+        client, close, connect, disconnect_reason, exchange_keys, host_key, receive,
+    };
+    use crate::sftp::transport::Transport;
+    use crate::sftp::{
+        decode_base64, disconnect, key_blob, message, Encoder, Server, ED25519, MAX_AUTH_FAILURES,
+    };
+    use crate::util::encode_base64;
+    #[cfg(unix)]
+    use crate::util::test::read_directory;
+    #[cfg(unix)]
+    use crate::FileSystem;
+    use ed25519_dalek::{Signer, SigningKey};
+    #[cfg(unix)]
+    use std::io::Write;
+    use std::net::TcpStream;
+    #[cfg(unix)]
+    use std::net::{SocketAddr, TcpListener};
+    #[cfg(unix)]
+    use std::process::{Command, Output, Stdio};
+    #[cfg(unix)]
+    use std::sync::Arc;
+    use std::thread;
+
+    #[cfg(unix)]
+    /// The options of `sftp` that authenticate with the key in `key`, or with a password.
+    const KEY: &[&str] = &["-i", "key", "-o", "BatchMode=yes"];
+    #[cfg(unix)]
+    const PASSWORD: &[&str] = &[
+        "-o",
+        "BatchMode=no",
+        "-o",
+        "PreferredAuthentications=password",
+        "-o",
+        "NumberOfPasswordPrompts=1",
+    ];
+
+    #[cfg(unix)]
+    /// Serves `server` in the background. Returns its address.
+    fn serve(server: Server<Arc<MemoryFS>>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve_listener(listener));
+        addr
+    }
+
+    #[cfg(unix)]
+    /// Runs the commands of `batch` with OpenSSH's `sftp` as `user`, with options before the others. Passwords are
+    /// read from the `askpass` script in `dir`.
+    fn sftp(addr: SocketAddr, dir: &PhysicalFS, options: &[&str], batch: &str) -> Output {
+        write!(dir.create_file("batch").unwrap(), "{batch}").unwrap();
+        Command::new("sftp")
+            .current_dir(dir.root())
+            .env("SSH_ASKPASS", dir.root().join("askpass"))
+            .env("SSH_ASKPASS_REQUIRE", "force")
+            .args(options)
+            .args(["-b", "batch", "-P", &addr.port().to_string()])
+            .args(["-o", "IdentitiesOnly=yes", "-o", "StrictHostKeyChecking=no"])
+            .args(["-o", "UserKnownHostsFile=/dev/null", "user@127.0.0.1"])
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    }
+
+    /// Returns a request to authenticate `user` with `password`.
+    fn password_request(user: &str, password: &str) -> Vec<u8> {
+        Encoder::message(message::USERAUTH_REQUEST)
+            .str(user)
+            .str("ssh-connection")
+            .str("password")
+            .bool(false)
+            .string(password.as_bytes())
+            .finish()
+    }
+
+    /// Returns a request to authenticate `user` with `key`, which is only asked about if `session_id` is `None`.
+    fn key_request(user: &str, key: &SigningKey, session_id: Option<&[u8]>) -> Vec<u8> {
+        let blob = key_blob(&key.verifying_key());
+        let request = |signed| {
+            Encoder::message(message::USERAUTH_REQUEST)
+                .str(user)
+                .str("ssh-connection")
+                .str("publickey")
+                .bool(signed)
+                .str(ED25519)
+                .string(&blob)
+                .finish()
+        };
+        let Some(session_id) = session_id else {
+            return request(false);
+        };
+        let signed_data = Encoder::new()
+            .string(session_id)
+            .raw(&request(true))
+            .finish();
+        let signature = Encoder::new()
+            .str(ED25519)
+            .string(&key.sign(&signed_data).to_bytes())
+            .finish();
+        Encoder::new()
+            .raw(&request(true))
+            .string(&signature)
+            .finish()
+    }
+
+    /// Serves a connection with `server` in the background, and exchanges keys with it as a client. Returns the
+    /// client's transport and the result of serving the connection.
+    fn authenticating<'a>(
+        server: Server<MemoryFS>,
+        host_key: &'a SigningKey,
+    ) -> (
+        Transport<'a, TcpStream>,
+        thread::JoinHandle<std::io::Result<()>>,
+    ) {
+        let (stream, served) = connect(move |stream| server.serve_connection(stream));
+        let mut client = client(stream, host_key);
+        exchange_keys(&mut client, "aes256-ctr").unwrap();
+        (client, served)
+    }
+
+    #[test]
+    fn authentication() {
+        let key = SigningKey::from_bytes(&[9; 32]);
+        let public_key = format!(
+            "{ED25519} {} user@host",
+            encode_base64(&key_blob(&key.verifying_key()))
+        );
+        let server = Server::new(MemoryFS::default(), [7; 32])
+            .password("user", "secret")
+            .authorized_key("user", &public_key)
+            .unwrap();
+        let host_key = host_key();
+        let (mut client, served) = authenticating(server, &host_key);
+
+        let service = Encoder::message(message::SERVICE_REQUEST)
+            .str("ssh-userauth")
+            .finish();
+        client.write(&service).unwrap();
+        assert_eq!(receive(&mut client)[0], message::SERVICE_ACCEPT);
+
+        // the methods are listed for `none`, and wrong passwords, unknown users and other keys and services fail
+        let session_id = client.session_id().to_vec();
+        let other_key = SigningKey::from_bytes(&[10; 32]);
+        let failing = [
+            Encoder::message(message::USERAUTH_REQUEST)
+                .str("user")
+                .str("ssh-connection")
+                .str("none")
+                .finish(),
+            password_request("user", "wrong"),
+            password_request("other", "secret"),
+            Encoder::message(message::USERAUTH_REQUEST)
+                .str("user")
+                .str("other")
+                .str("password")
+                .bool(false)
+                .str("secret")
+                .finish(),
+            key_request("user", &other_key, Some(&session_id)),
+            key_request("user", &key, Some(b"another session")),
+        ];
+        for request in failing {
+            client.write(&request).unwrap();
+            assert_eq!(receive(&mut client)[0], message::USERAUTH_FAILURE);
+        }
+
+        // clients may ask whether a key would be accepted before signing with it
+        client.write(&key_request("user", &key, None)).unwrap();
+        assert_eq!(receive(&mut client)[0], message::USERAUTH_PK_OK);
+        client
+            .write(&key_request("user", &key, Some(&session_id)))
+            .unwrap();
+        assert_eq!(receive(&mut client)[0], message::USERAUTH_SUCCESS);
+
+        close(&client);
+        served.join().unwrap().unwrap();
+
+        // passwords authenticate too
+        let server = Server::new(MemoryFS::default(), [7; 32]).password("user", "secret");
+        let (mut client, _) = authenticating(server, &host_key);
+        client.write(&password_request("user", "secret")).unwrap();
+        assert_eq!(receive(&mut client)[0], message::USERAUTH_SUCCESS);
+    }
+
+    #[test]
+    fn failed_authentication() {
+        let host_key = host_key();
+        let server = || Server::new(MemoryFS::default(), [7; 32]).password("user", "secret");
+
+        // clients are disconnected after too many failures, which asking for the methods isn't
+        let (mut client, served) = authenticating(server(), &host_key);
+        let none = Encoder::message(message::USERAUTH_REQUEST)
+            .str("user")
+            .str("ssh-connection")
+            .str("none")
+            .finish();
+        for _ in 0..20 {
+            client.write(&none).unwrap();
+            assert_eq!(receive(&mut client)[0], message::USERAUTH_FAILURE);
+        }
+        for _ in 1..MAX_AUTH_FAILURES {
+            client.write(&password_request("user", "wrong")).unwrap();
+            assert_eq!(receive(&mut client)[0], message::USERAUTH_FAILURE);
+        }
+        client.write(&password_request("user", "secret!")).unwrap();
+        assert_eq!(
+            disconnect_reason(&receive(&mut client)),
+            disconnect::NO_MORE_AUTH_METHODS_AVAILABLE
+        );
+        served.join().unwrap().unwrap();
+
+        // only authentication is served before it succeeds
+        let (mut client, served) = authenticating(server(), &host_key);
+        client
+            .write(
+                &Encoder::message(message::CHANNEL_OPEN)
+                    .str("session")
+                    .finish(),
+            )
+            .unwrap();
+        assert_eq!(
+            disconnect_reason(&receive(&mut client)),
+            disconnect::PROTOCOL_ERROR
+        );
+        served.join().unwrap().unwrap();
+
+        let (mut client, served) = authenticating(server(), &host_key);
+        client
+            .write(
+                &Encoder::message(message::SERVICE_REQUEST)
+                    .str("ssh-connection")
+                    .finish(),
+            )
+            .unwrap();
+        assert_eq!(
+            disconnect_reason(&receive(&mut client)),
+            disconnect::SERVICE_NOT_AVAILABLE
+        );
+        served.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn base64() {
+        for data in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar"] {
+            assert_eq!(decode_base64(&encode_base64(data)).unwrap(), data);
+        }
+        assert_eq!(encode_base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(encode_base64(b"fooba"), "Zm9vYmE=");
+        assert!(decode_base64("Zm9v!").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn openssh() {
+        let dir = PhysicalFS::new_temp().unwrap();
+        let keygen = Command::new("ssh-keygen")
+            .current_dir(dir.root())
+            .args(["-q", "-t", "ed25519", "-N", "", "-f", "key"])
+            .stdin(Stdio::null())
+            .status();
+        // OpenSSH isn't installed everywhere
+        if keygen.is_err() {
+            return;
+        }
+        let public_key = dir
+            .open_file("key.pub")
+            .unwrap()
+            .read_into_string()
+            .unwrap();
+        // the file is larger than the windows of both ends
+        let contents = (0..3 * 1024 * 1024)
+            .map(|n| (n % 251) as u8)
+            .collect::<Vec<_>>();
+        dir.create_file("local")
+            .unwrap()
+            .write_all(&contents)
+            .unwrap();
+
+        let fs = Arc::new(MemoryFS::default());
+        let server = Server::new(fs.clone(), [7; 32])
+            .authorized_key("user", &public_key)
+            .unwrap();
+        let batch = "mkdir dir\nput local dir/file\nrename dir/file dir/moved\nget dir/moved fetched\nls -l dir\n";
+        let output = sftp(serve(server), &dir, KEY, batch);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(
+            stdout.contains("-rw-r--r--   1 user     user      3145728"),
+            "{stdout}"
+        );
+
+        itertools::assert_equal(read_directory(&fs, "dir").keys(), vec!["dir/moved"]);
+        assert_eq!(
+            fs.open_file("dir/moved").unwrap().read_into_vec().unwrap(),
+            contents
+        );
+        assert_eq!(
+            dir.open_file("fetched").unwrap().read_into_vec().unwrap(),
+            contents
+        );
+
+        // read-only servers deny changes, and report files without the write bits
+        let server = Server::new(fs.clone(), [7; 32])
+            .authorized_key("user", &public_key)
+            .unwrap()
+            .read_only(true);
+        let addr = serve(server);
+        let output = sftp(addr, &dir, KEY, "ls -l dir\n");
+        assert!(String::from_utf8_lossy(&output.stdout).contains("-r--r--r--"));
+        let output = sftp(addr, &dir, KEY, "rm dir/moved\n");
+        assert!(!output.status.success());
+        assert!(fs.exists("dir/moved").unwrap());
+
+        // users may only authenticate with their own keys and passwords
+        let server = Server::new(fs, [7; 32])
+            .authorized_key("other", &public_key)
+            .unwrap()
+            .password("user", "secret");
+        let addr = serve(server);
+        assert!(!sftp(addr, &dir, KEY, "ls\n").status.success());
+        let mut askpass = dir.create_file("askpass").unwrap();
+        write!(askpass, "#!/bin/sh\necho wrong\n").unwrap();
+        drop(askpass);
+        let mode = std::os::unix::fs::PermissionsExt::from_mode(0o755);
+        std::fs::set_permissions(dir.root().join("askpass"), mode).unwrap();
+        assert!(!sftp(addr, &dir, PASSWORD, "ls\n").status.success());
+        write!(
+            dir.create_file("askpass").unwrap(),
+            "#!/bin/sh\necho secret\n"
+        )
+        .unwrap();
+        assert!(sftp(addr, &dir, PASSWORD, "ls\n").status.success());
+    }
+}
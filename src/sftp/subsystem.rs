@@ -0,0 +1,719 @@
+use crate::file::{Metadata, OpenOptions};
+use crate::sftp::{Decoder, Encoder};
+use crate::time::DateTime;
+use crate::{util, FileSystem};
+use std::collections::HashMap;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::time::UNIX_EPOCH;
+
+/// The version of SFTP that's served, which is the one OpenSSH speaks.
+const VERSION: u32 = 3;
+/// The longest request that's accepted, which leaves room for writes of 256 KiB.
+pub(crate) const MAX_REQUEST_LEN: usize = 256 * 1024 + 1024;
+/// The most bytes a single read returns.
+const MAX_READ_LEN: u64 = 256 * 1024;
+/// The most entries a single directory read returns.
+const MAX_NAMES: usize = 128;
+/// The most handles that may be open at once.
+const MAX_HANDLES: usize = 1024;
+
+/// Packet types.
+mod packet {
+    pub(super) const INIT: u8 = 1;
+    pub(super) const VERSION: u8 = 2;
+    pub(super) const OPEN: u8 = 3;
+    pub(super) const CLOSE: u8 = 4;
+    pub(super) const READ: u8 = 5;
+    pub(super) const WRITE: u8 = 6;
+    pub(super) const LSTAT: u8 = 7;
+    pub(super) const FSTAT: u8 = 8;
+    pub(super) const SETSTAT: u8 = 9;
+    pub(super) const FSETSTAT: u8 = 10;
+    pub(super) const OPENDIR: u8 = 11;
+    pub(super) const READDIR: u8 = 12;
+    pub(super) const REMOVE: u8 = 13;
+    pub(super) const MKDIR: u8 = 14;
+    pub(super) const RMDIR: u8 = 15;
+    pub(super) const REALPATH: u8 = 16;
+    pub(super) const STAT: u8 = 17;
+    pub(super) const RENAME: u8 = 18;
+    pub(super) const STATUS: u8 = 101;
+    pub(super) const HANDLE: u8 = 102;
+    pub(super) const DATA: u8 = 103;
+    pub(super) const NAME: u8 = 104;
+    pub(super) const ATTRS: u8 = 105;
+    pub(super) const EXTENDED: u8 = 200;
+    pub(super) const EXTENDED_REPLY: u8 = 201;
+}
+
+/// Status codes.
+mod status {
+    pub(super) const OK: u32 = 0;
+    pub(super) const EOF: u32 = 1;
+    pub(super) const NO_SUCH_FILE: u32 = 2;
+    pub(super) const PERMISSION_DENIED: u32 = 3;
+    pub(super) const FAILURE: u32 = 4;
+    pub(super) const BAD_MESSAGE: u32 = 5;
+    pub(super) const OP_UNSUPPORTED: u32 = 8;
+}
+
+/// Flags of opening files.
+mod open {
+    pub(super) const READ: u32 = 0x1;
+    pub(super) const WRITE: u32 = 0x2;
+    pub(super) const APPEND: u32 = 0x4;
+    pub(super) const CREAT: u32 = 0x8;
+    pub(super) const TRUNC: u32 = 0x10;
+    pub(super) const EXCL: u32 = 0x20;
+}
+
+/// Flags of the attributes that are present.
+mod attr {
+    pub(super) const SIZE: u32 = 0x1;
+    pub(super) const UIDGID: u32 = 0x2;
+    pub(super) const PERMISSIONS: u32 = 0x4;
+    pub(super) const ACMODTIME: u32 = 0x8;
+    pub(super) const EXTENDED: u32 = 0x8000_0000;
+}
+
+/// The types of files, in permissions.
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+/// The flag `statvfs` reports for read-only filesystems.
+const ST_RDONLY: u64 = 0x1;
+
+/// The abbreviated names of the months.
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The SFTP subsystem of a channel, which serves the requests sent on it.
+pub(crate) struct Subsystem<'a, FS> {
+    fs: &'a FS,
+    /// The name of the user, who's reported as the owner of every file.
+    user: String,
+    read_only: bool,
+    handles: HashMap<u32, Handle>,
+    next_handle: u32,
+}
+
+/// What a handle refers to.
+enum Handle {
+    /// A file, which is opened for every read and write.
+    File {
+        path: String,
+        read: bool,
+        write: bool,
+        append: bool,
+    },
+    /// A directory, with the entries that haven't been read yet.
+    Directory {
+        path: String,
+        entries: Vec<(String, Metadata)>,
+    },
+}
+
+impl Handle {
+    fn path(&self) -> &str {
+        match self {
+            Self::File { path, .. } | Self::Directory { path, .. } => path,
+        }
+    }
+}
+
+/// The status a request failed with.
+struct Status(u32, String);
+
+impl Status {
+    fn new(code: u32, message: &str) -> Self {
+        Self(code, message.to_owned())
+    }
+}
+
+impl From<io::Error> for Status {
+    fn from(err: io::Error) -> Self {
+        let code = match err.kind() {
+            ErrorKind::NotFound => status::NO_SUCH_FILE,
+            ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => {
+                status::PERMISSION_DENIED
+            }
+            ErrorKind::Unsupported => status::OP_UNSUPPORTED,
+            ErrorKind::InvalidData => status::BAD_MESSAGE,
+            _ => status::FAILURE,
+        };
+        Self(code, err.to_string())
+    }
+}
+
+/// The attributes of a file that can be set.
+#[derive(Default)]
+struct Attributes {
+    size: Option<u64>,
+    permissions: Option<u32>,
+}
+
+impl Attributes {
+    /// Decodes attributes, of which only the size and permissions are kept.
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        let flags = decoder.u32()?;
+        let mut attributes = Self::default();
+        if flags & attr::SIZE != 0 {
+            attributes.size = Some(decoder.u64()?);
+        }
+        if flags & attr::UIDGID != 0 {
+            decoder.take(8)?;
+        }
+        if flags & attr::PERMISSIONS != 0 {
+            attributes.permissions = Some(decoder.u32()?);
+        }
+        if flags & attr::ACMODTIME != 0 {
+            decoder.take(8)?;
+        }
+        if flags & attr::EXTENDED != 0 {
+            for _ in 0..decoder.u32()? {
+                decoder.string()?;
+                decoder.string()?;
+            }
+        }
+        Ok(attributes)
+    }
+}
+
+impl<'a, FS: FileSystem> Subsystem<'a, FS> {
+    pub(crate) fn new(fs: &'a FS, user: &str, read_only: bool) -> Self {
+        Self {
+            fs,
+            user: user.to_owned(),
+            read_only,
+            handles: HashMap::new(),
+            next_handle: 0,
+        }
+    }
+
+    /// Handles a request. Returns its reply, prefixed with its length.
+    ///
+    /// # Arguments
+    /// `request`: The request, without its length.  
+    pub(crate) fn handle(&mut self, request: &[u8]) -> Vec<u8> {
+        let mut decoder = Decoder::new(request);
+        let reply = match decoder.u8() {
+            // the client starts with its version, and every later request starts with an identifier
+            Ok(packet::INIT) => Encoder::message(packet::VERSION)
+                .u32(VERSION)
+                .str("posix-rename@openssh.com")
+                .str("1")
+                .str("statvfs@openssh.com")
+                .str("2")
+                .finish(),
+            Ok(ty) => {
+                let id = decoder.u32().unwrap_or(0);
+                self.respond(ty, id, &mut decoder)
+                    .unwrap_or_else(|Status(code, message)| status_reply(id, code, &message))
+            }
+            Err(_) => status_reply(0, status::BAD_MESSAGE, "Empty request"),
+        };
+        Encoder::new().string(&reply).finish()
+    }
+
+    /// Handles a request that has an identifier. Returns its reply.
+    fn respond(&mut self, ty: u8, id: u32, request: &mut Decoder) -> Result<Vec<u8>, Status> {
+        let ok = status_reply(id, status::OK, "Success");
+        match ty {
+            packet::OPEN => {
+                let (path, flags) = (self.path(request.str()?), request.u32()?);
+                let attributes = Attributes::decode(request)?;
+                let writes = flags & (open::WRITE | open::APPEND | open::CREAT | open::TRUNC) != 0;
+                if writes {
+                    self.check_writable()?;
+                }
+                if util::metadata_or_root(self.fs, &path)
+                    .is_ok_and(|metadata| metadata.is_directory())
+                {
+                    return Err(Status::new(status::FAILURE, "Is a directory"));
+                }
+                if flags & open::CREAT != 0 && flags & open::EXCL != 0 && self.fs.exists(&path)? {
+                    return Err(Status::new(status::FAILURE, "File exists"));
+                }
+
+                let options = OpenOptions {
+                    create: flags & open::CREAT != 0,
+                    write: writes,
+                    truncate: flags & open::TRUNC != 0,
+                    mode: attributes
+                        .permissions
+                        .map(|permissions| permissions & 0o7777),
+                    ..OpenOptions::default()
+                };
+                drop(self.fs.open_file_options(&path, &options)?);
+                let handle = Handle::File {
+                    path,
+                    read: flags & open::READ != 0,
+                    write: flags & (open::WRITE | open::APPEND) != 0,
+                    append: flags & open::APPEND != 0,
+                };
+                self.open(id, handle)
+            }
+            packet::CLOSE => {
+                let handle = handle_id(request)?;
+                self.handles.remove(&handle).ok_or_else(invalid_handle)?;
+                Ok(ok)
+            }
+            packet::READ => {
+                let (handle, offset, len) =
+                    (self.handle_of(request)?, request.u64()?, request.u32()?);
+                let Handle::File {
+                    path, read: true, ..
+                } = handle
+                else {
+                    return Err(Status::new(
+                        status::PERMISSION_DENIED,
+                        "Not opened for reading",
+                    ));
+                };
+
+                let mut file = self.fs.open_file(path)?;
+                file.seek(SeekFrom::Start(offset))?;
+                let mut data = Vec::new();
+                file.take((len as u64).min(MAX_READ_LEN))
+                    .read_to_end(&mut data)?;
+                if data.is_empty() && len > 0 {
+                    return Err(Status::new(status::EOF, "End of file"));
+                }
+                Ok(Encoder::message(packet::DATA)
+                    .u32(id)
+                    .string(&data)
+                    .finish())
+            }
+            packet::WRITE => {
+                let (handle, offset, data) =
+                    (self.handle_of(request)?, request.u64()?, request.string()?);
+                let Handle::File {
+                    path,
+                    write: true,
+                    append,
+                    ..
+                } = handle
+                else {
+                    return Err(Status::new(
+                        status::PERMISSION_DENIED,
+                        "Not opened for writing",
+                    ));
+                };
+
+                let options = OpenOptions {
+                    write: true,
+                    ..OpenOptions::default()
+                };
+                let mut file = self.fs.open_file_options(path, &options)?;
+                file.seek(match append {
+                    true => SeekFrom::End(0),
+                    false => SeekFrom::Start(offset),
+                })?;
+                file.write_all(data)?;
+                file.flush()?;
+                Ok(ok)
+            }
+            packet::LSTAT | packet::STAT => {
+                let metadata = util::metadata_or_root(self.fs, &self.path(request.str()?))?;
+                Ok(self.attributes_reply(id, &metadata))
+            }
+            packet::FSTAT => {
+                let metadata = util::metadata_or_root(self.fs, self.handle_of(request)?.path())?;
+                Ok(self.attributes_reply(id, &metadata))
+            }
+            packet::SETSTAT | packet::FSETSTAT => {
+                let path = match ty {
+                    packet::SETSTAT => self.path(request.str()?),
+                    _ => self.handle_of(request)?.path().to_owned(),
+                };
+                let attributes = Attributes::decode(request)?;
+                self.check_writable()?;
+                // owners, permissions and times can't be changed, so they're ignored
+                match attributes.size {
+                    Some(size) => util::set_len(self.fs, &path, size)?,
+                    None => {
+                        util::metadata_or_root(self.fs, &path)?;
+                    }
+                }
+                Ok(ok)
+            }
+            packet::OPENDIR => {
+                let path = self.path(request.str()?);
+                if !util::metadata_or_root(self.fs, &path)?.is_directory() {
+                    return Err(Status::new(status::FAILURE, "Not a directory"));
+                }
+                let mut entries = Vec::new();
+                for entry in self.fs.read_dir(&path)? {
+                    let entry = entry?;
+                    if let Some(name) = entry.path.file_name().and_then(|name| name.to_str()) {
+                        entries.push((name.to_owned(), entry.metadata));
+                    }
+                }
+                // the entries are returned in reverse, so that they're read in order
+                entries.sort_by(|(a, _), (b, _)| b.cmp(a));
+                self.open(id, Handle::Directory { path, entries })
+            }
+            packet::READDIR => {
+                let handle = handle_id(request)?;
+                let Some(Handle::Directory { entries, .. }) = self.handles.get_mut(&handle) else {
+                    return Err(invalid_handle());
+                };
+                if entries.is_empty() {
+                    return Err(Status::new(status::EOF, "End of directory"));
+                }
+                let entries = entries.split_off(entries.len().saturating_sub(MAX_NAMES));
+
+                let mut reply = Encoder::message(packet::NAME);
+                reply.u32(id).u32(entries.len() as u32);
+                for (name, metadata) in entries.iter().rev() {
+                    reply
+                        .str(name)
+                        .str(&self.long_name(name, metadata))
+                        .raw(&self.attributes(metadata));
+                }
+                Ok(reply.finish())
+            }
+            packet::REMOVE => {
+                let path = self.path(request.str()?);
+                self.check_writable()?;
+                if util::metadata_or_root(self.fs, &path)?.is_directory() {
+                    return Err(Status::new(status::FAILURE, "Is a directory"));
+                }
+                self.fs.remove_file(&path)?;
+                Ok(ok)
+            }
+            packet::MKDIR => {
+                let path = self.path(request.str()?);
+                let attributes = Attributes::decode(request)?;
+                self.check_writable()?;
+                if self.fs.exists(&path)? {
+                    return Err(Status::new(status::FAILURE, "File exists"));
+                }
+                let mode = attributes.permissions.unwrap_or(0o777) & 0o7777;
+                self.fs.create_dir_with(&path, mode)?;
+                Ok(ok)
+            }
+            packet::RMDIR => {
+                let path = self.path(request.str()?);
+                self.check_writable()?;
+                if path.is_empty() || !util::metadata_or_root(self.fs, &path)?.is_directory() {
+                    return Err(Status::new(status::FAILURE, "Not a directory"));
+                }
+                // some filesystems remove directories along with their contents
+                if self.fs.read_dir(&path)?.next().is_some() {
+                    return Err(Status::new(status::FAILURE, "Directory not empty"));
+                }
+                self.fs.remove_dir(&path)?;
+                Ok(ok)
+            }
+            packet::REALPATH => {
+                let path = format!("/{}", self.path(request.str()?));
+                Ok(Encoder::message(packet::NAME)
+                    .u32(id)
+                    .u32(1)
+                    .str(&path)
+                    .str(&path)
+                    .u32(0)
+                    .finish())
+            }
+            packet::RENAME => {
+                let (from, to) = (self.path(request.str()?), self.path(request.str()?));
+                self.check_writable()?;
+                // renames don't replace existing files, unlike POSIX renames
+                if self.fs.exists(&to)? {
+                    return Err(Status::new(status::FAILURE, "File exists"));
+                }
+                self.rename(&from, &to)?;
+                Ok(ok)
+            }
+            packet::EXTENDED => match request.str()? {
+                "posix-rename@openssh.com" => {
+                    let (from, to) = (self.path(request.str()?), self.path(request.str()?));
+                    self.rename(&from, &to)?;
+                    Ok(ok)
+                }
+                "statvfs@openssh.com" => {
+                    util::metadata_or_root(self.fs, &self.path(request.str()?))?;
+                    let stats = util::stats_or_empty(self.fs);
+                    Ok(Encoder::message(packet::EXTENDED_REPLY)
+                        .u32(id)
+                        .u64(stats.block_size)
+                        .u64(stats.block_size)
+                        .u64(stats.total_space / stats.block_size)
+                        .u64(stats.free_space / stats.block_size)
+                        .u64(stats.available_space / stats.block_size)
+                        // the numbers of files are unknown
+                        .u64(0)
+                        .u64(0)
+                        .u64(0)
+                        .u64(0)
+                        .u64(if self.read_only { ST_RDONLY } else { 0 })
+                        .u64(255)
+                        .finish())
+                }
+                _ => Err(Status::new(status::OP_UNSUPPORTED, "Unsupported extension")),
+            },
+            // links aren't supported
+            _ => Err(Status::new(status::OP_UNSUPPORTED, "Unsupported request")),
+        }
+    }
+
+    /// Returns the path in the filesystem of a path sent by the client, which is relative to the root. `..` can't
+    /// leave the root.
+    fn path(&self, path: &str) -> String {
+        let mut components = Vec::new();
+        for component in path.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => drop(components.pop()),
+                component => components.push(component),
+            }
+        }
+        components.join("/")
+    }
+
+    /// Returns an error if the filesystem is served read-only.
+    fn check_writable(&self) -> Result<(), Status> {
+        match self.read_only {
+            true => Err(Status::new(
+                status::PERMISSION_DENIED,
+                "Read-only filesystem",
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Opens a handle. Returns the reply with it.
+    fn open(&mut self, id: u32, handle: Handle) -> Result<Vec<u8>, Status> {
+        if self.handles.len() >= MAX_HANDLES {
+            return Err(Status::new(status::FAILURE, "Too many open handles"));
+        }
+        while self.handles.contains_key(&self.next_handle) {
+            self.next_handle = self.next_handle.wrapping_add(1);
+        }
+        let handle_id = self.next_handle;
+        self.handles.insert(handle_id, handle);
+        Ok(Encoder::message(packet::HANDLE)
+            .u32(id)
+            .string(&handle_id.to_be_bytes())
+            .finish())
+    }
+
+    /// Takes a handle from a request. Returns what it refers to.
+    fn handle_of(&self, request: &mut Decoder) -> Result<&Handle, Status> {
+        self.handles
+            .get(&handle_id(request)?)
+            .ok_or_else(invalid_handle)
+    }
+
+    /// Renames the entry at `from` to `to`.
+    fn rename(&self, from: &str, to: &str) -> Result<(), Status> {
+        self.check_writable()?;
+        if from.is_empty() || to.is_empty() {
+            return Err(Status::new(status::FAILURE, "Can't rename the root"));
+        }
+        Ok(self.fs.rename(from, to)?)
+    }
+
+    /// Returns the permissions of an entry, including its type. The write bits are cleared if the filesystem is
+    /// served read-only.
+    fn permissions(&self, metadata: &Metadata) -> u32 {
+        let permissions = match metadata.is_directory() {
+            true => S_IFDIR | metadata.mode.unwrap_or(0o755),
+            false => S_IFREG | metadata.mode.unwrap_or(0o644),
+        };
+        match self.read_only {
+            true => permissions & !0o222,
+            false => permissions,
+        }
+    }
+
+    /// Encodes the attributes of an entry.
+    fn attributes(&self, metadata: &Metadata) -> Vec<u8> {
+        let mut attributes = Encoder::new();
+        let modified = metadata
+            .modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok());
+        match modified {
+            Some(modified) => attributes
+                .u32(attr::SIZE | attr::PERMISSIONS | attr::ACMODTIME)
+                .u64(metadata.len())
+                .u32(self.permissions(metadata))
+                .u32(modified.as_secs() as u32)
+                .u32(modified.as_secs() as u32),
+            None => attributes
+                .u32(attr::SIZE | attr::PERMISSIONS)
+                .u64(metadata.len())
+                .u32(self.permissions(metadata)),
+        };
+        attributes.finish()
+    }
+
+    /// Returns the reply with the attributes of an entry.
+    fn attributes_reply(&self, id: u32, metadata: &Metadata) -> Vec<u8> {
+        Encoder::message(packet::ATTRS)
+            .u32(id)
+            .raw(&self.attributes(metadata))
+            .finish()
+    }
+
+    /// Returns the line `ls -l` shows for an entry.
+    fn long_name(&self, name: &str, metadata: &Metadata) -> String {
+        let permissions = self.permissions(metadata);
+        let mut mode = String::from(if metadata.is_directory() { "d" } else { "-" });
+        for (bit, letter) in (0..9).rev().zip("rwxrwxrwx".chars()) {
+            mode.push(if permissions & 1 << bit != 0 {
+                letter
+            } else {
+                '-'
+            });
+        }
+        let modified = DateTime::from_system_time(metadata.modified.unwrap_or(UNIX_EPOCH));
+        format!(
+            "{mode} {:>3} {:<8} {:<8} {:>8} {} {:>2} {:>5} {name}",
+            if metadata.is_directory() { 2 } else { 1 },
+            self.user,
+            self.user,
+            metadata.len(),
+            MONTHS[modified.month as usize - 1],
+            modified.day,
+            modified.year
+        )
+    }
+}
+
+/// Takes a handle from a request.
+fn handle_id(request: &mut Decoder) -> Result<u32, Status> {
+    let handle = request.string()?;
+    Ok(u32::from_be_bytes(
+        handle.try_into().map_err(|_| invalid_handle())?,
+    ))
+}
+
+/// Returns the status of handles that aren't open.
+fn invalid_handle() -> Status {
+    Status::new(status::FAILURE, "Invalid handle")
+}
+
+/// Returns a status reply.
+fn status_reply(id: u32, code: u32, message: &str) -> Vec<u8> {
+    Encoder::message(packet::STATUS)
+        .u32(id)
+        .u32(code)
+        .str(message)
+        .str("")
+        .finish()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    use crate::sftp::subsystem::{open, packet, status, Subsystem, S_IFREG};
+    use crate::sftp::{Decoder, Encoder};
+    use crate::FileSystem;
+    use std::io::Write;
+
+    /// Sends a request with the identifier 1 to `subsystem`. Returns the type and body of its reply.
+    fn request<FS: FileSystem>(
+        subsystem: &mut Subsystem<FS>,
+        request: &mut Encoder,
+    ) -> (u8, Vec<u8>) {
+        let reply = subsystem.handle(&request.finish());
+        let mut decoder = Decoder::new(&reply);
+        let reply = decoder.string().unwrap();
+        assert_eq!(u32::from_be_bytes(reply[1..5].try_into().unwrap()), 1);
+        (reply[0], reply[5..].to_vec())
+    }
+
+    /// Returns the status code of a status reply.
+    fn status_code((ty, body): (u8, Vec<u8>)) -> u32 {
+        assert_eq!(ty, packet::STATUS);
+        Decoder::new(&body).u32().unwrap()
+    }
+
+    #[test]
+    fn read_only() {
+        let fs = MemoryFS::default();
+        write!(fs.create_file("file").unwrap(), "hello").unwrap();
+        let mut subsystem = Subsystem::new(&fs, "user", true);
+
+        let (ty, body) = request(
+            &mut subsystem,
+            Encoder::message(packet::STAT).u32(1).str("/file"),
+        );
+        assert_eq!(ty, packet::ATTRS);
+        let mut attributes = Decoder::new(&body);
+        attributes.u32().unwrap();
+        assert_eq!(attributes.u64().unwrap(), 5);
+        assert_eq!(attributes.u32().unwrap(), S_IFREG | 0o444);
+
+        // files may be read, but not opened for writing or changed
+        assert_eq!(
+            status_code(request(
+                &mut subsystem,
+                Encoder::message(packet::OPEN)
+                    .u32(1)
+                    .str("file")
+                    .u32(open::WRITE)
+                    .u32(0)
+            )),
+            status::PERMISSION_DENIED
+        );
+        assert_eq!(
+            status_code(request(
+                &mut subsystem,
+                Encoder::message(packet::REMOVE).u32(1).str("file")
+            )),
+            status::PERMISSION_DENIED
+        );
+        let (ty, handle) = request(
+            &mut subsystem,
+            Encoder::message(packet::OPEN)
+                .u32(1)
+                .str("file")
+                .u32(open::READ)
+                .u32(0),
+        );
+        assert_eq!(ty, packet::HANDLE);
+        let handle = Decoder::new(&handle).string().unwrap().to_vec();
+        let (ty, data) = request(
+            &mut subsystem,
+            Encoder::message(packet::READ)
+                .u32(1)
+                .string(&handle)
+                .u64(1)
+                .u32(64),
+        );
+        assert_eq!(ty, packet::DATA);
+        assert_eq!(Decoder::new(&data).string().unwrap(), b"ello");
+        assert_eq!(
+            status_code(request(
+                &mut subsystem,
+                Encoder::message(packet::READ)
+                    .u32(1)
+                    .string(&handle)
+                    .u64(5)
+                    .u32(64)
+            )),
+            status::EOF
+        );
+
+        // paths can't leave the root
+        assert_eq!(
+            status_code(request(
+                &mut subsystem,
+                Encoder::message(packet::STAT).u32(1).str("../../missing")
+            )),
+            status::NO_SUCH_FILE
+        );
+        let (ty, name) = request(
+            &mut subsystem,
+            Encoder::message(packet::REALPATH).u32(1).str("a/../.."),
+        );
+        assert_eq!(ty, packet::NAME);
+        let mut name = Decoder::new(&name);
+        assert_eq!(name.u32().unwrap(), 1);
+        assert_eq!(name.str().unwrap(), "/");
+    }
+}
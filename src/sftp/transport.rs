@@ -0,0 +1,849 @@
+use crate::sftp::{disconnect, malformed, message, Decoder, Encoder, ED25519};
+use aes::{Aes128, Aes256};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use ctr::Ctr128BE;
+use ed25519_dalek::{Signer, SigningKey};
+use hmac_sha256::{Hash, HMAC};
+use std::io;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// The version the server identifies itself with.
+const SERVER_VERSION: &str = concat!("SSH-2.0-virtual_filesystem_", env!("CARGO_PKG_VERSION"));
+/// The longest line that may be sent before the version of the client.
+const MAX_LINE_LEN: u64 = 255;
+/// The most lines that may be sent before the version of the client.
+const MAX_LINES: usize = 64;
+/// The longest packet that's accepted, which leaves room for the largest channel data.
+const MAX_PACKET_LEN: usize = 64 * 1024;
+/// The length of HMAC-SHA-256 codes.
+const MAC_LEN: usize = 32;
+
+/// The algorithms that are supported.
+const KEX_ALGORITHMS: &[&str] = &["curve25519-sha256", "curve25519-sha256@libssh.org"];
+const CIPHERS: &[&str] = &["aes128-ctr", "aes256-ctr"];
+const MAC: &str = "hmac-sha2-256";
+const COMPRESSION: &str = "none";
+
+/// The keys that protect the packets sent in one direction, which are unprotected until the first key exchange.
+#[derive(Default)]
+struct Keys {
+    cipher: Option<Box<dyn StreamCipher>>,
+    integrity_key: Option<[u8; 32]>,
+    /// The number of the next packet, which is authenticated along with it.
+    sequence: u32,
+}
+
+impl Keys {
+    /// Creates the keys of a direction, deriving them from the result of a key exchange.
+    ///
+    /// # Arguments
+    /// `cipher`: The name of the cipher.  
+    /// `derive`: Derives the key of a letter with a length.  
+    /// `letters`: The letters of the initialization vector, the encryption key and the integrity key.  
+    fn new(cipher: &str, derive: impl Fn(u8, usize) -> Vec<u8>, letters: [u8; 3]) -> Self {
+        let (iv, key) = (
+            derive(letters[0], 16),
+            derive(letters[1], cipher_key_len(cipher)),
+        );
+        let cipher: Box<dyn StreamCipher> = match cipher {
+            "aes128-ctr" => Box::new(Ctr128BE::<Aes128>::new_from_slices(&key, &iv).unwrap()),
+            _ => Box::new(Ctr128BE::<Aes256>::new_from_slices(&key, &iv).unwrap()),
+        };
+        Self {
+            cipher: Some(cipher),
+            integrity_key: Some(derive(letters[2], MAC_LEN).try_into().unwrap()),
+            sequence: 0,
+        }
+    }
+
+    /// Returns the size packets are padded to a multiple of.
+    fn block_size(&self) -> usize {
+        if self.cipher.is_some() {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Returns the authentication code of the packet numbered with the sequence, if there's an integrity key.
+    fn mac(&self, packet: &[u8]) -> Option<[u8; MAC_LEN]> {
+        self.integrity_key.map(|key| {
+            let mut hmac = HMAC::new(key);
+            hmac.update(self.sequence.to_be_bytes());
+            hmac.update(packet);
+            hmac.finalize()
+        })
+    }
+
+    fn apply_keystream(&mut self, data: &mut [u8]) {
+        if let Some(cipher) = &mut self.cipher {
+            cipher.apply_keystream(data);
+        }
+    }
+}
+
+/// The transport layer of a connection, which exchanges keys with the client and protects the messages sent over it.
+pub(crate) struct Transport<'a, S> {
+    stream: BufReader<S>,
+    host_key: &'a SigningKey,
+    client_version: String,
+    /// The hash of the first key exchange, which identifies the session.
+    session_id: Vec<u8>,
+    incoming: Keys,
+    outgoing: Keys,
+}
+
+impl<'a, S: Read + Write> Transport<'a, S> {
+    /// Accepts a connection, exchanging versions and keys with the client.
+    ///
+    /// # Arguments
+    /// `stream`: The connection.  
+    /// `host_key`: The key the server is identified by.  
+    pub(crate) fn accept(stream: S, host_key: &'a SigningKey) -> io::Result<Self> {
+        let mut stream = BufReader::new(stream);
+        write!(stream.get_mut(), "{SERVER_VERSION}\r\n")?;
+        stream.get_mut().flush()?;
+
+        // clients may send other lines before their version
+        let mut client_version = None;
+        for _ in 0..MAX_LINES {
+            let mut line = Vec::new();
+            (&mut stream)
+                .take(MAX_LINE_LEN)
+                .read_until(b'\n', &mut line)?;
+            if !line.ends_with(b"\n") {
+                return Err(malformed("Malformed SSH version"));
+            }
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.starts_with("SSH-2.0-") || line.starts_with("SSH-1.99-") {
+                client_version = Some(line.to_owned());
+                break;
+            }
+            if line.starts_with("SSH-") {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    "Only SSH 2.0 is supported",
+                ));
+            }
+        }
+
+        let mut transport = Self {
+            stream,
+            host_key,
+            client_version: client_version.ok_or_else(|| malformed("Missing SSH version"))?,
+            session_id: Vec::new(),
+            incoming: Keys::default(),
+            outgoing: Keys::default(),
+        };
+        transport.exchange_keys(None)?;
+        Ok(transport)
+    }
+
+    /// Returns the identifier of the session, which clients sign to authenticate with keys.
+    pub(crate) fn session_id(&self) -> &[u8] {
+        &self.session_id
+    }
+
+    /// Reads the next message for the layers above the transport, exchanging keys again when the client asks and
+    /// skipping messages that are meant to be ignored. Returns `None` once the client disconnects.
+    pub(crate) fn read(&mut self) -> io::Result<Option<Vec<u8>>> {
+        while let Some(payload) = self.read_packet()? {
+            match payload[0] {
+                message::DISCONNECT => break,
+                message::IGNORE | message::DEBUG | message::UNIMPLEMENTED => {}
+                message::KEXINIT => self.exchange_keys(Some(payload))?,
+                _ => return Ok(Some(payload)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Sends a message.
+    ///
+    /// # Arguments
+    /// `payload`: The message.  
+    pub(crate) fn write(&mut self, payload: &[u8]) -> io::Result<()> {
+        let block_size = self.outgoing.block_size();
+        // packets are padded with at least 4 random bytes to a multiple of the block size
+        let mut padding_len = block_size - (5 + payload.len()) % block_size;
+        if padding_len < 4 {
+            padding_len += block_size;
+        }
+        let mut padding = [0; 32];
+        getrandom::getrandom(&mut padding[..padding_len])?;
+
+        let packet = Encoder::new()
+            .u32((1 + payload.len() + padding_len) as u32)
+            .u8(padding_len as u8)
+            .raw(payload)
+            .raw(&padding[..padding_len])
+            .finish();
+        self.send(packet)
+    }
+
+    /// Tells the client that the last message it sent isn't implemented.
+    pub(crate) fn unimplemented(&mut self) -> io::Result<()> {
+        let sequence = self.incoming.sequence.wrapping_sub(1);
+        self.write(
+            &Encoder::message(message::UNIMPLEMENTED)
+                .u32(sequence)
+                .finish(),
+        )
+    }
+
+    /// Disconnects from the client.
+    ///
+    /// # Arguments
+    /// `reason`: The reason for disconnecting.  
+    /// `description`: The description of the reason.  
+    pub(crate) fn disconnect(&mut self, reason: u32, description: &str) -> io::Result<()> {
+        self.write(
+            &Encoder::message(message::DISCONNECT)
+                .u32(reason)
+                .str(description)
+                .str("")
+                .finish(),
+        )
+    }
+
+    /// Exchanges keys with the client, and starts using them.
+    ///
+    /// # Arguments
+    /// `client_init`: The `KEXINIT` message of the client, if it was already received.  
+    fn exchange_keys(&mut self, client_init: Option<Vec<u8>>) -> io::Result<()> {
+        let mut cookie = [0; 16];
+        getrandom::getrandom(&mut cookie)?;
+        let server_init = Encoder::message(message::KEXINIT)
+            .raw(&cookie)
+            .str(&KEX_ALGORITHMS.join(","))
+            .str(ED25519)
+            .str(&CIPHERS.join(","))
+            .str(&CIPHERS.join(","))
+            .str(MAC)
+            .str(MAC)
+            .str(COMPRESSION)
+            .str(COMPRESSION)
+            .str("")
+            .str("")
+            .bool(false)
+            .u32(0)
+            .finish();
+        self.write(&server_init)?;
+        let client_init = match client_init {
+            Some(client_init) => client_init,
+            None => self.expect(message::KEXINIT)?,
+        };
+
+        let mut decoder = Decoder::new(&client_init[1..]);
+        decoder.take(16)?;
+        let kex_algorithms = decoder.str()?;
+        let host_key_algorithms = decoder.str()?;
+        let ciphers = (decoder.str()?, decoder.str()?);
+        let macs = (decoder.str()?, decoder.str()?);
+        let compressions = (decoder.str()?, decoder.str()?);
+        // the languages are ignored
+        decoder.str()?;
+        decoder.str()?;
+        let guessed = decoder.bool()?;
+
+        let negotiated = (|| {
+            let kex_algorithm = negotiate(kex_algorithms, KEX_ALGORITHMS)?;
+            negotiate(host_key_algorithms, &[ED25519])?;
+            let ciphers = (
+                negotiate(ciphers.0, CIPHERS)?,
+                negotiate(ciphers.1, CIPHERS)?,
+            );
+            negotiate(macs.0, &[MAC])?;
+            negotiate(macs.1, &[MAC])?;
+            negotiate(compressions.0, &[COMPRESSION])?;
+            negotiate(compressions.1, &[COMPRESSION])?;
+            Some((kex_algorithm, ciphers))
+        })();
+        let Some((kex_algorithm, (client_cipher, server_cipher))) = negotiated else {
+            self.disconnect(disconnect::KEY_EXCHANGE_FAILED, "No supported algorithms")?;
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "The client supports none of the algorithms",
+            ));
+        };
+        // a key exchange message that was sent for the wrong algorithms is ignored
+        if guessed
+            && (kex_algorithms.split(',').next() != Some(kex_algorithm)
+                || host_key_algorithms.split(',').next() != Some(ED25519))
+        {
+            self.expect(message::KEX_ECDH_INIT)?;
+        }
+
+        let client_public: [u8; 32] = Decoder::new(&self.expect(message::KEX_ECDH_INIT)?[1..])
+            .string()?
+            .try_into()
+            .map_err(|_| malformed("Malformed Curve25519 key"))?;
+        let mut secret = [0; 32];
+        getrandom::getrandom(&mut secret)?;
+        let secret = StaticSecret::from(secret);
+        let server_public = PublicKey::from(&secret);
+        let shared_secret = secret.diffie_hellman(&PublicKey::from(client_public));
+        if !shared_secret.was_contributory() {
+            return Err(malformed("Invalid Curve25519 key"));
+        }
+        let shared_secret = Encoder::new().mpint(shared_secret.as_bytes()).finish();
+
+        let host_key = crate::sftp::key_blob(&self.host_key.verifying_key());
+        let hash = Hash::hash(
+            &Encoder::new()
+                .str(&self.client_version)
+                .str(SERVER_VERSION)
+                .string(&client_init)
+                .string(&server_init)
+                .string(&host_key)
+                .string(&client_public)
+                .string(server_public.as_bytes())
+                .raw(&shared_secret)
+                .finish(),
+        );
+        if self.session_id.is_empty() {
+            self.session_id = hash.to_vec();
+        }
+        let signature = Encoder::new()
+            .str(ED25519)
+            .string(&self.host_key.sign(&hash).to_bytes())
+            .finish();
+        self.write(
+            &Encoder::message(message::KEX_ECDH_REPLY)
+                .string(&host_key)
+                .string(server_public.as_bytes())
+                .string(&signature)
+                .finish(),
+        )?;
+
+        let derive = |letter, len| derive_key(&shared_secret, &hash, &self.session_id, letter, len);
+        let mut incoming = Keys::new(client_cipher, derive, *b"ACE");
+        let mut outgoing = Keys::new(server_cipher, derive, *b"BDF");
+
+        // the keys are used from the messages after `NEWKEYS`, which keep being numbered in sequence
+        self.write(&Encoder::message(message::NEWKEYS).finish())?;
+        outgoing.sequence = self.outgoing.sequence;
+        self.outgoing = outgoing;
+        self.expect(message::NEWKEYS)?;
+        incoming.sequence = self.incoming.sequence;
+        self.incoming = incoming;
+        Ok(())
+    }
+
+    /// Authenticates, encrypts and sends a packet with its length and padding.
+    ///
+    /// # Arguments
+    /// `packet`: The packet.  
+    fn send(&mut self, mut packet: Vec<u8>) -> io::Result<()> {
+        let mac = self.outgoing.mac(&packet);
+        self.outgoing.apply_keystream(&mut packet);
+        packet.extend(mac.iter().flatten());
+        self.outgoing.sequence = self.outgoing.sequence.wrapping_add(1);
+
+        let stream = self.stream.get_mut();
+        stream.write_all(&packet)?;
+        stream.flush()
+    }
+
+    /// Reads the next message during a key exchange, which must be `ty`.
+    ///
+    /// # Arguments
+    /// `ty`: The number of the message.  
+    fn expect(&mut self, ty: u8) -> io::Result<Vec<u8>> {
+        loop {
+            let payload = self
+                .read_packet()?
+                .ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))?;
+            match payload[0] {
+                message::IGNORE | message::DEBUG => {}
+                received if received == ty => return Ok(payload),
+                _ => return Err(malformed("Unexpected message during key exchange")),
+            }
+        }
+    }
+
+    /// Reads the payload of the next packet. Returns `None` if the connection was closed before it.
+    fn read_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.stream.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        let block_size = self.incoming.block_size();
+        let mut packet = vec![0; block_size];
+        self.stream.read_exact(&mut packet)?;
+        self.incoming.apply_keystream(&mut packet);
+        let len = u32::from_be_bytes(packet[..4].try_into().unwrap()) as usize + 4;
+        if !(16..=MAX_PACKET_LEN).contains(&len) || !len.is_multiple_of(block_size) {
+            return Err(malformed("Malformed SSH packet length"));
+        }
+        packet.resize(len, 0);
+        self.stream.read_exact(&mut packet[block_size..])?;
+        self.incoming.apply_keystream(&mut packet[block_size..]);
+
+        if let Some(expected) = self.incoming.mac(&packet) {
+            let mut mac = [0; MAC_LEN];
+            self.stream.read_exact(&mut mac)?;
+            // the codes are compared by their hashes, so that the time taken doesn't reveal where they differ
+            if Hash::hash(&mac) != Hash::hash(&expected) {
+                return Err(malformed("Corrupt SSH packet"));
+            }
+        }
+        self.incoming.sequence = self.incoming.sequence.wrapping_add(1);
+
+        let padding_len = packet[4] as usize;
+        if padding_len < 4 || padding_len + 6 > len {
+            return Err(malformed("Malformed SSH packet padding"));
+        }
+        Ok(Some(packet[5..len - padding_len].to_vec()))
+    }
+}
+
+/// Returns the first of the client's algorithms that's supported.
+///
+/// # Arguments
+/// `client`: The algorithms of the client, by order of preference.  
+/// `supported`: The algorithms that are supported.  
+fn negotiate<'a>(client: &str, supported: &[&'a str]) -> Option<&'a str> {
+    client
+        .split(',')
+        .find_map(|algorithm| supported.iter().find(|name| **name == algorithm).copied())
+}
+
+/// Derives a key from the result of a key exchange, its shared secret, its hash, a letter and the session identifier.
+///
+/// # Arguments
+/// `shared_secret`: The shared secret, encoded as an `mpint`.  
+/// `hash`: The hash of the key exchange.  
+/// `session_id`: The identifier of the session.  
+/// `letter`: The letter of the key.  
+/// `len`: The length of the key.  
+fn derive_key(
+    shared_secret: &[u8],
+    hash: &[u8],
+    session_id: &[u8],
+    letter: u8,
+    len: usize,
+) -> Vec<u8> {
+    let mut key = Hash::hash(
+        &Encoder::new()
+            .raw(shared_secret)
+            .raw(hash)
+            .u8(letter)
+            .raw(session_id)
+            .finish(),
+    )
+    .to_vec();
+    while key.len() < len {
+        let more = Hash::hash(
+            &Encoder::new()
+                .raw(shared_secret)
+                .raw(hash)
+                .raw(&key)
+                .finish(),
+        );
+        key.extend_from_slice(&more);
+    }
+    key.truncate(len);
+    key
+}
+
+/// Returns the length of the keys of a cipher.
+fn cipher_key_len(cipher: &str) -> usize {
+    match cipher {
+        "aes128-ctr" => 16,
+        _ => 32,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::sftp::transport::{
+        derive_key, Keys, Transport, CIPHERS, MAX_PACKET_LEN, SERVER_VERSION,
+    };
+    use crate::sftp::{disconnect, key_blob, message, verify, Decoder, Encoder, ED25519};
+    use ed25519_dalek::SigningKey;
+    use hmac_sha256::Hash;
+    use std::io;
+    use std::io::{BufRead, BufReader, ErrorKind, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::thread;
+    use std::thread::JoinHandle;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    /// The version the client identifies itself with.
+    const CLIENT_VERSION: &str = "SSH-2.0-test";
+
+    /// Returns the key that servers are identified by.
+    pub(crate) fn host_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    /// Accepts a connection with `serve` in the background. Returns the client's end of the connection.
+    ///
+    /// # Arguments
+    /// `serve`: Serves the connection.  
+    pub(crate) fn connect<T: Send + 'static>(
+        serve: impl FnOnce(TcpStream) -> T + Send + 'static,
+    ) -> (TcpStream, JoinHandle<T>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let server = thread::spawn(move || serve(listener.accept().unwrap().0));
+        (stream, server)
+    }
+
+    /// Exchanges versions with the server as a client. Returns the client's transport, whose packets are protected
+    /// like the server's with the directions swapped.
+    ///
+    /// # Arguments
+    /// `stream`: The client's end of the connection.  
+    /// `host_key`: The key the server is expected to be identified by.  
+    pub(crate) fn client(stream: TcpStream, host_key: &SigningKey) -> Transport<'_, TcpStream> {
+        let mut stream = BufReader::new(stream);
+        let mut version = String::new();
+        stream.read_line(&mut version).unwrap();
+        assert_eq!(version, format!("{SERVER_VERSION}\r\n"));
+        write!(stream.get_mut(), "{CLIENT_VERSION}\r\n").unwrap();
+
+        Transport {
+            stream,
+            host_key,
+            client_version: CLIENT_VERSION.to_owned(),
+            session_id: Vec::new(),
+            incoming: Keys::default(),
+            outgoing: Keys::default(),
+        }
+    }
+
+    /// Returns the `KEXINIT` message of a client that supports the algorithms of the server, with `cipher`.
+    fn client_init(cipher: &str) -> Vec<u8> {
+        Encoder::message(message::KEXINIT)
+            .raw(&[3; 16])
+            .str("curve25519-sha256")
+            .str(ED25519)
+            .str(cipher)
+            .str(cipher)
+            .str("hmac-sha2-256")
+            .str("hmac-sha2-256")
+            .str("none")
+            .str("none")
+            .str("")
+            .str("")
+            .bool(false)
+            .u32(0)
+            .finish()
+    }
+
+    /// Exchanges keys with the server as a client, verifying the server's signature, and starts using them.
+    ///
+    /// # Arguments
+    /// `client`: The client's transport.  
+    /// `cipher`: The cipher to use in both directions.  
+    pub(crate) fn exchange_keys(client: &mut Transport<TcpStream>, cipher: &str) -> io::Result<()> {
+        let client_init = client_init(cipher);
+        client.write(&client_init)?;
+        let server_init = client.expect(message::KEXINIT)?;
+
+        let secret = StaticSecret::from([5; 32]);
+        let client_public = PublicKey::from(&secret);
+        client.write(
+            &Encoder::message(message::KEX_ECDH_INIT)
+                .string(client_public.as_bytes())
+                .finish(),
+        )?;
+        let reply = client.expect(message::KEX_ECDH_REPLY)?;
+        let mut decoder = Decoder::new(&reply[1..]);
+        let (host_key, server_public, signature) =
+            (decoder.string()?, decoder.string()?, decoder.string()?);
+        assert_eq!(host_key, key_blob(&client.host_key.verifying_key()));
+        let server_public: [u8; 32] = server_public.try_into().unwrap();
+        let shared_secret = secret.diffie_hellman(&PublicKey::from(server_public));
+        let shared_secret = Encoder::new().mpint(shared_secret.as_bytes()).finish();
+
+        let hash = Hash::hash(
+            &Encoder::new()
+                .str(CLIENT_VERSION)
+                .str(SERVER_VERSION)
+                .string(&client_init)
+                .string(&server_init)
+                .string(host_key)
+                .string(client_public.as_bytes())
+                .string(&server_public)
+                .raw(&shared_secret)
+                .finish(),
+        );
+        assert!(verify(&client.host_key.verifying_key(), &hash, signature));
+        if client.session_id.is_empty() {
+            client.session_id = hash.to_vec();
+        }
+
+        let derive =
+            |letter, len| derive_key(&shared_secret, &hash, &client.session_id, letter, len);
+        let mut incoming = Keys::new(cipher, derive, *b"BDF");
+        let mut outgoing = Keys::new(cipher, derive, *b"ACE");
+        client.expect(message::NEWKEYS)?;
+        incoming.sequence = client.incoming.sequence;
+        client.incoming = incoming;
+        client.write(&Encoder::message(message::NEWKEYS).finish())?;
+        outgoing.sequence = client.outgoing.sequence;
+        client.outgoing = outgoing;
+        Ok(())
+    }
+
+    /// Reads the next packet as a client, including the messages that `read` skips. Panics if there is none.
+    pub(crate) fn receive(client: &mut Transport<TcpStream>) -> Vec<u8> {
+        client.read_packet().unwrap().unwrap()
+    }
+
+    /// Closes the client's side of the connection, after which the server reads no more messages.
+    pub(crate) fn close(client: &Transport<TcpStream>) {
+        client.stream.get_ref().shutdown(Shutdown::Write).unwrap();
+    }
+
+    /// Returns the reason of a `DISCONNECT` message.
+    pub(crate) fn disconnect_reason(payload: &[u8]) -> u32 {
+        assert_eq!(payload[0], message::DISCONNECT);
+        Decoder::new(&payload[1..]).u32().unwrap()
+    }
+
+    /// Runs `client` against a server that accepts a connection and reads its messages until it's closed. Returns
+    /// the error that the server failed with.
+    ///
+    /// # Arguments
+    /// `talk`: Talks to the server as a client.  
+    fn server_error(talk: impl FnOnce(&mut Transport<TcpStream>) -> io::Result<()>) -> io::Error {
+        let (stream, server) = connect(|stream| {
+            let host_key = host_key();
+            let mut transport = Transport::accept(stream, &host_key)?;
+            while transport.read()?.is_some() {}
+            Ok(())
+        });
+        let host_key = host_key();
+        let mut client = client(stream, &host_key);
+        // the server may close the connection before the client is done
+        let _ = talk(&mut client);
+        let _ = client.stream.get_ref().shutdown(Shutdown::Write);
+        server.join().unwrap().unwrap_err()
+    }
+
+    /// Sends a packet that's only protected, so that its length and padding may be malformed.
+    fn send_raw(
+        client: &mut Transport<TcpStream>,
+        len: u32,
+        padding_len: u8,
+        rest: usize,
+    ) -> io::Result<()> {
+        client.send(
+            Encoder::new()
+                .u32(len)
+                .u8(padding_len)
+                .raw(&vec![message::IGNORE; rest])
+                .finish(),
+        )
+    }
+
+    #[test]
+    fn key_exchange() {
+        for cipher in CIPHERS {
+            // the server echoes every message
+            let (stream, server) = connect(|stream| {
+                let host_key = host_key();
+                let mut transport = Transport::accept(stream, &host_key).unwrap();
+                let mut received = Vec::new();
+                while let Some(payload) = transport.read().unwrap() {
+                    transport.write(&payload).unwrap();
+                    received.push(payload);
+                }
+                (transport.session_id().to_vec(), received)
+            });
+            let host_key = host_key();
+            let mut client = client(stream, &host_key);
+            exchange_keys(&mut client, cipher).unwrap();
+            let session_id = client.session_id.clone();
+
+            // ignored messages are skipped, and keys are exchanged again when the client asks
+            client
+                .write(&Encoder::message(message::IGNORE).str("skipped").finish())
+                .unwrap();
+            let first = Encoder::message(message::SERVICE_REQUEST)
+                .str("first")
+                .finish();
+            client.write(&first).unwrap();
+            assert_eq!(client.read().unwrap().unwrap(), first);
+            exchange_keys(&mut client, cipher).unwrap();
+            assert_eq!(client.session_id, session_id);
+            // the packet is larger than a block, and is padded
+            let second = Encoder::message(message::SERVICE_REQUEST)
+                .string(&[1; 100])
+                .finish();
+            client.write(&second).unwrap();
+            assert_eq!(client.read().unwrap().unwrap(), second);
+
+            close(&client);
+            let (server_session_id, received) = server.join().unwrap();
+            assert_eq!(server_session_id, session_id);
+            assert_eq!(received, [first, second]);
+        }
+    }
+
+    #[test]
+    fn versions() {
+        let accept = |sent: &'static [u8]| {
+            let (mut stream, server) = connect(|stream| {
+                Transport::accept(stream, &host_key())
+                    .map(drop)
+                    .unwrap_err()
+            });
+            stream.write_all(sent).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            server.join().unwrap()
+        };
+
+        // SSH 1 isn't supported, and the version must come in time
+        assert_eq!(accept(b"SSH-1.5-old\r\n").kind(), ErrorKind::Unsupported);
+        assert_eq!(accept(&[b'a'; 300]).kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            accept("banner\r\n".repeat(64).leak().as_bytes()).kind(),
+            ErrorKind::InvalidData
+        );
+        // lines before the version are skipped, after which keys are exchanged
+        assert_eq!(
+            accept(b"banner\r\nSSH-2.0-client\r\n").kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn unsupported_algorithms() {
+        let (stream, server) = connect(|stream| {
+            Transport::accept(stream, &host_key())
+                .map(drop)
+                .unwrap_err()
+        });
+        let host_key = host_key();
+        let mut client = client(stream, &host_key);
+        client.write(&client_init("3des-cbc")).unwrap();
+        assert_eq!(receive(&mut client)[0], message::KEXINIT);
+        assert_eq!(
+            disconnect_reason(&receive(&mut client)),
+            disconnect::KEY_EXCHANGE_FAILED
+        );
+        assert_eq!(server.join().unwrap().kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn out_of_order_key_exchange() {
+        let unexpected = "Unexpected message during key exchange";
+        let ecdh_init = Encoder::message(message::KEX_ECDH_INIT)
+            .string(PublicKey::from(&StaticSecret::from([5; 32])).as_bytes())
+            .finish();
+
+        // the exchange must start with `KEXINIT`
+        let error = server_error(|client| client.write(&ecdh_init));
+        assert_eq!(error.to_string(), unexpected);
+        let error = server_error(|client| {
+            client.write(&Encoder::message(message::SERVICE_REQUEST).finish())
+        });
+        assert_eq!(error.to_string(), unexpected);
+
+        // and continue with `KEX_ECDH_INIT`
+        let error = server_error(|client| {
+            client.write(&client_init(CIPHERS[0]))?;
+            client.write(&Encoder::message(message::NEWKEYS).finish())
+        });
+        assert_eq!(error.to_string(), unexpected);
+
+        // after which `NEWKEYS` must come before anything else
+        let error = server_error(|client| {
+            client.write(&client_init(CIPHERS[0]))?;
+            client.write(&ecdh_init)?;
+            client.write(&Encoder::message(message::SERVICE_REQUEST).finish())
+        });
+        assert_eq!(error.to_string(), unexpected);
+
+        // a client that disconnects in the middle of the exchange isn't waited for
+        let error = server_error(|client| client.write(&client_init(CIPHERS[0])));
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn bad_mac() {
+        // a packet authenticated with another key is rejected
+        let error = server_error(|client| {
+            exchange_keys(client, CIPHERS[0])?;
+            client.outgoing.integrity_key = Some([0; 32]);
+            client.write(&Encoder::message(message::SERVICE_REQUEST).finish())
+        });
+        assert_eq!(error.to_string(), "Corrupt SSH packet");
+
+        // so are replayed and reordered packets, which are authenticated with their sequence numbers
+        let error = server_error(|client| {
+            exchange_keys(client, CIPHERS[0])?;
+            client.outgoing.sequence += 1;
+            client.write(&Encoder::message(message::SERVICE_REQUEST).finish())
+        });
+        assert_eq!(error.to_string(), "Corrupt SSH packet");
+
+        // and packets whose ciphertext was changed
+        let error = server_error(|client| {
+            exchange_keys(client, CIPHERS[0])?;
+            let mut packet = Encoder::new()
+                .u32(12)
+                .u8(4)
+                .raw(&[message::IGNORE; 11])
+                .finish();
+            let mac = client.outgoing.mac(&packet);
+            client.outgoing.apply_keystream(&mut packet);
+            packet[5] ^= 1;
+            packet.extend(mac.iter().flatten());
+            client.stream.get_mut().write_all(&packet)
+        });
+        assert_eq!(error.to_string(), "Corrupt SSH packet");
+    }
+
+    #[test]
+    fn bad_padding_length() {
+        let malformed = "Malformed SSH packet padding";
+        // less than 4 bytes of padding, before and after the keys are exchanged
+        let error = server_error(|client| send_raw(client, 12, 2, 11));
+        assert_eq!(error.to_string(), malformed);
+        let error = server_error(|client| {
+            exchange_keys(client, CIPHERS[0])?;
+            send_raw(client, 28, 3, 27)
+        });
+        assert_eq!(error.to_string(), malformed);
+
+        // padding that leaves no room for the message
+        let error = server_error(|client| send_raw(client, 12, 11, 11));
+        assert_eq!(error.to_string(), malformed);
+        let error = server_error(|client| send_raw(client, 12, 255, 11));
+        assert_eq!(error.to_string(), malformed);
+    }
+
+    #[test]
+    fn bad_packet_length() {
+        let malformed = "Malformed SSH packet length";
+        // packets that are too large are rejected before they're read
+        for len in [MAX_PACKET_LEN as u32, u32::MAX] {
+            let error = server_error(|client| send_raw(client, len, 4, 3));
+            assert_eq!(error.to_string(), malformed);
+        }
+        let error = server_error(|client| {
+            exchange_keys(client, CIPHERS[1])?;
+            send_raw(client, MAX_PACKET_LEN as u32, 4, 11)
+        });
+        assert_eq!(error.to_string(), malformed);
+
+        // as are packets that are too small, or that aren't a multiple of the block size
+        for len in [0, 8, 13] {
+            let error = server_error(|client| send_raw(client, len, 4, 3));
+            assert_eq!(error.to_string(), malformed);
+        }
+        let error = server_error(|client| {
+            exchange_keys(client, CIPHERS[0])?;
+            send_raw(client, 20, 4, 19)
+        });
+        assert_eq!(error.to_string(), malformed);
+    }
+}
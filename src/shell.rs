@@ -0,0 +1,330 @@
+use crate::util::normalize_path;
+use crate::{util, FileSystem};
+use std::fs;
+use std::io;
+use std::io::{BufRead, Write};
+use std::time::UNIX_EPOCH;
+
+/// The commands of the shell.
+const HELP: &str = "\
+cd [path]               Changes the working directory, or returns to the root
+pwd                     Prints the working directory
+ls [path]               Lists the entries of a directory, marking directories with a trailing slash
+cat <path>...           Prints the contents of files
+put <host> [path]       Copies a file from the host into the filesystem
+get <path> [host]       Copies a file from the filesystem to the host
+stat <path>             Prints the metadata of an entry
+help                    Prints the commands
+exit                    Leaves the shell
+";
+
+/// Runs an interactive shell over `fs` on the standard input and output until the input ends or `exit` is entered.
+/// The shell has the commands `cd`, `pwd`, `ls`, `cat`, `put`, `get` and `stat`, and arguments containing spaces can
+/// be quoted with `"`.
+///
+/// # Arguments
+/// `fs`: The filesystem to explore.  
+pub fn run<FS: FileSystem + ?Sized>(fs: &FS) -> io::Result<()> {
+    run_with(fs, io::stdin().lock(), io::stdout().lock())
+}
+
+/// Runs an interactive shell over `fs` until `input` ends or `exit` is entered, such as within the debug console of
+/// an application. See `run` for the commands.
+///
+/// # Arguments
+/// `fs`: The filesystem to explore.  
+/// `input`: The stream commands are read from.  
+/// `output`: The stream the prompt, and the output and errors of commands, are written to.  
+pub fn run_with<FS: FileSystem + ?Sized, R: BufRead, W: Write>(
+    fs: &FS,
+    mut input: R,
+    mut output: W,
+) -> io::Result<()> {
+    let mut shell = Shell {
+        fs,
+        directory: String::new(),
+    };
+
+    let mut line = String::new();
+    loop {
+        write!(output, "/{}> ", shell.directory)?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let args = split(&line);
+        match args
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            [] => {}
+            ["exit" | "quit"] => return Ok(()),
+            args => {
+                if let Err(err) = shell.execute(args, &mut output) {
+                    writeln!(output, "error: {err}")?;
+                }
+            }
+        }
+    }
+}
+
+/// The state of a shell.
+struct Shell<'a, FS: ?Sized> {
+    fs: &'a FS,
+    /// The working directory, without leading or trailing slashes.
+    directory: String,
+}
+
+impl<FS: FileSystem + ?Sized> Shell<'_, FS> {
+    /// Executes a command.
+    ///
+    /// # Arguments
+    /// `args`: The command and its arguments.  
+    /// `output`: The stream the output of the command is written to.  
+    fn execute<W: Write>(&mut self, args: &[&str], output: &mut W) -> io::Result<()> {
+        match args {
+            ["cd"] => self.directory.clear(),
+            ["cd", path] => {
+                let path = self.resolve(path);
+                if !util::metadata_or_root(self.fs, &path)?.is_directory() {
+                    return Err(io::Error::other("Not a directory"));
+                }
+                self.directory = path;
+            }
+            ["pwd"] => writeln!(output, "/{}", self.directory)?,
+            ["ls"] => self.ls(&self.directory.clone(), output)?,
+            ["ls", path] => self.ls(&self.resolve(path), output)?,
+            ["cat", paths @ ..] if !paths.is_empty() => {
+                for path in paths {
+                    io::copy(&mut self.fs.open_file(&self.resolve(path))?, output)?;
+                }
+            }
+            ["put", host_path] => self.put(host_path, &self.resolve(file_name(host_path)))?,
+            ["put", host_path, path] => self.put(host_path, &self.resolve(path))?,
+            ["get", path] => self.get(&self.resolve(path), file_name(path))?,
+            ["get", path, host_path] => self.get(&self.resolve(path), host_path)?,
+            ["stat", path] => self.stat(&self.resolve(path), output)?,
+            ["help"] => write!(output, "{HELP}")?,
+            [command, ..] => {
+                return Err(io::Error::other(format!(
+                    "Invalid use of `{command}`, see `help`"
+                )))
+            }
+            [] => {}
+        }
+        Ok(())
+    }
+
+    /// Lists the entries of the directory at `path`, sorted by name.
+    ///
+    /// # Arguments
+    /// `path`: The resolved path of the directory.  
+    /// `output`: The stream the entries are written to.  
+    fn ls<W: Write>(&self, path: &str, output: &mut W) -> io::Result<()> {
+        let mut entries = Vec::new();
+        for entry in self.fs.read_dir(path)? {
+            let entry = entry?;
+            let suffix = if entry.metadata.is_directory() {
+                "/"
+            } else {
+                ""
+            };
+            entries.push(format!("{}{suffix}", entry.file_name()));
+        }
+
+        entries.sort();
+        for entry in entries {
+            writeln!(output, "{entry}")?;
+        }
+        Ok(())
+    }
+
+    /// Copies the host file at `host_path` to `path`, replacing the file there.
+    ///
+    /// # Arguments
+    /// `host_path`: The path of the file on the host.  
+    /// `path`: The resolved path to copy the file to.  
+    fn put(&self, host_path: &str, path: &str) -> io::Result<()> {
+        let mut host_file = fs::File::open(host_path)?;
+        io::copy(&mut host_file, &mut self.fs.create_file(path)?)?;
+        Ok(())
+    }
+
+    /// Copies the file at `path` to `host_path` on the host, replacing the file there.
+    ///
+    /// # Arguments
+    /// `path`: The resolved path of the file.  
+    /// `host_path`: The path to copy the file to on the host.  
+    fn get(&self, path: &str, host_path: &str) -> io::Result<()> {
+        let mut file = self.fs.open_file(path)?;
+        io::copy(&mut file, &mut fs::File::create(host_path)?)?;
+        Ok(())
+    }
+
+    /// Prints the metadata of the entry at `path`.
+    ///
+    /// # Arguments
+    /// `path`: The resolved path of the entry.  
+    /// `output`: The stream the metadata is written to.  
+    fn stat<W: Write>(&self, path: &str, output: &mut W) -> io::Result<()> {
+        let metadata = util::metadata_or_root(self.fs, path)?;
+        writeln!(output, "path: /{path}")?;
+        let file_type = if metadata.is_directory() {
+            "directory"
+        } else {
+            "file"
+        };
+        writeln!(output, "type: {file_type}")?;
+        writeln!(output, "size: {}", metadata.len)?;
+        if let Some(mode) = metadata.mode {
+            writeln!(output, "mode: {:o}", mode & 0o7777)?;
+        }
+        if let Some(modified) = metadata.modified {
+            match modified.duration_since(UNIX_EPOCH) {
+                Ok(modified) => writeln!(output, "modified: {} (Unix time)", modified.as_secs())?,
+                Err(_) => writeln!(output, "modified: before the Unix epoch")?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves `path` against the working directory, returning it without leading or trailing slashes.
+    ///
+    /// # Arguments
+    /// `path`: The absolute path, or the path relative to the working directory.  
+    fn resolve(&self, path: &str) -> String {
+        let path = if path.starts_with('/') {
+            path.to_owned()
+        } else {
+            format!("/{}/{path}", self.directory)
+        };
+        let path = normalize_path(path);
+        let path = path.to_str().unwrap_or_default().trim_matches('/');
+        match path {
+            "." => String::new(),
+            path => path.to_owned(),
+        }
+    }
+}
+
+/// Splits a command line into its arguments, which are separated by whitespace unless they're quoted with `"`.
+///
+/// # Arguments
+/// `line`: The command line.  
+fn split(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = None::<String>;
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                arg.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => args.extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    args
+}
+
+/// Returns the last component of `path`.
+fn file_name(path: &str) -> &str {
+    let path = path.trim_end_matches(['/', '\\']);
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    use crate::shell::{run_with, split};
+    use crate::FileSystem;
+    use std::io::Write;
+    use std::{env, fs, process};
+
+    /// Runs the shell over `fs` with the commands in `input`, returning its output.
+    fn output(fs: &MemoryFS, input: &str) -> String {
+        let mut output = Vec::new();
+        run_with(fs, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn navigate() {
+        let fs = MemoryFS::default();
+        fs.create_dir_all("dir/sub").unwrap();
+        writeln!(fs.create_file("dir/file").unwrap(), "contents").unwrap();
+        writeln!(fs.create_file("top").unwrap(), "top").unwrap();
+
+        assert_eq!(
+            output(
+                &fs,
+                "ls\ncd dir\npwd\nls\ncat file ../top\ncd sub\ncd ../..\nls /dir/sub\ncd nonsense\ncd top\nbogus\n"
+            ),
+            "/> dir/\ntop\n\
+             /> /dir> /dir\n\
+             /dir> file\nsub/\n\
+             /dir> contents\ntop\n\
+             /dir> /dir/sub> \
+             /> \
+             /> error: File not found\n\
+             /> error: Not a directory\n\
+             /> error: Invalid use of `bogus`, see `help`\n\
+             /> "
+        );
+        assert_eq!(output(&fs, "cd dir\nexit\nls\n"), "/> /dir> ");
+    }
+
+    #[test]
+    fn stat() {
+        let fs = MemoryFS::default();
+        fs.create_dir("dir").unwrap();
+        write!(fs.create_file("dir/file").unwrap(), "contents").unwrap();
+
+        let output = output(&fs, "stat /\nstat dir/file\n");
+        assert!(output.starts_with("/> path: /\ntype: directory\nsize: 0\n"));
+        assert!(output.contains("path: /dir/file\ntype: file\nsize: 8\n"));
+    }
+
+    #[test]
+    fn put_get() {
+        let host_dir = env::temp_dir().join(format!("shell-{}", process::id()));
+        fs::create_dir_all(&host_dir).unwrap();
+        let host_file = host_dir.join("host file");
+        fs::write(&host_file, "from the host").unwrap();
+
+        let fs = MemoryFS::default();
+        fs.create_dir("dir").unwrap();
+        let host_file = host_file.to_str().unwrap();
+        let copy = host_dir.join("copy");
+        let input = format!(
+            "cd dir\nput \"{host_file}\"\nput \"{host_file}\" /renamed\nget \"host file\" {}\n",
+            copy.to_str().unwrap()
+        );
+        assert_eq!(output(&fs, &input), "/> /dir> /dir> /dir> /dir> ");
+
+        assert_eq!(
+            fs.open_file("dir/host file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "from the host"
+        );
+        assert!(fs.exists("renamed").unwrap());
+        assert_eq!(fs::read_to_string(copy).unwrap(), "from the host");
+        fs::remove_dir_all(host_dir).unwrap();
+    }
+
+    #[test]
+    fn split_quoted() {
+        assert_eq!(
+            split("  put \"a file\"  b\"\" \"\"\n"),
+            vec!["put", "a file", "b", ""]
+        );
+    }
+}
@@ -0,0 +1,479 @@
+use crate::util::now;
+use hmac::{Hmac, Mac};
+use md4::{Digest, Md4};
+use md5::Md5;
+use std::io;
+use std::io::ErrorKind;
+use std::time::UNIX_EPOCH;
+
+/// The signature every NTLM message starts with.
+const NTLMSSP: &[u8] = b"NTLMSSP\0";
+/// The DER-encoded object identifier of SPNEGO.
+const SPNEGO_OID: &[u8] = &[0x06, 0x06, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x02];
+/// The DER-encoded object identifier of NTLM.
+const NTLM_OID: &[u8] = &[
+    0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x0a,
+];
+
+/// The NTLM flags negotiated: Unicode, the target's name, signing, NTLM with extended session security, target
+/// information, and 128-bit keys.
+const NEGOTIATE_FLAGS: u32 =
+    0x1 | 0x4 | 0x10 | 0x200 | 0x8000 | 0x80000 | 0x80_0000 | 0x2000_0000 | 0x8000_0000;
+/// The identifier of the timestamp in the target information of a challenge.
+const MSV_AV_TIMESTAMP: u16 = 7;
+/// The number of 100-nanosecond intervals between 1601 and the Unix epoch.
+pub(crate) const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+/// The credentials a session is authenticated with.
+#[derive(Clone, Default)]
+pub struct Credentials {
+    /// The domain of the user, which may be empty.
+    pub domain: String,
+    /// The user name, which is empty for an anonymous session.
+    pub user: String,
+    /// The password of the user.
+    pub password: String,
+}
+
+impl Credentials {
+    /// Creates the credentials of a user without a domain.
+    ///
+    /// # Arguments
+    /// `user`: The user name.  
+    /// `password`: The password of the user.  
+    pub fn new<S: Into<String>, T: Into<String>>(user: S, password: T) -> Self {
+        Self {
+            domain: String::new(),
+            user: user.into(),
+            password: password.into(),
+        }
+    }
+
+    /// # Arguments
+    /// `domain`: The domain of the user.  
+    pub fn domain<S: Into<String>>(mut self, domain: S) -> Self {
+        self.domain = domain.into();
+        self
+    }
+
+    /// Returns true if the credentials are anonymous.
+    pub(crate) fn is_anonymous(&self) -> bool {
+        self.user.is_empty()
+    }
+}
+
+/// Returns the first SPNEGO token of a session setup, which wraps an NTLM negotiate message.
+pub(crate) fn negotiate_token() -> Vec<u8> {
+    let mut negotiate = NTLMSSP.to_vec();
+    negotiate.extend_from_slice(&1u32.to_le_bytes());
+    negotiate.extend_from_slice(&NEGOTIATE_FLAGS.to_le_bytes());
+    // the domain and workstation aren't supplied
+    negotiate.extend_from_slice(&[0; 16]);
+
+    let mech_types = der(0xa0, &der(0x30, NTLM_OID));
+    let mech_token = der(0xa2, &der(0x04, &negotiate));
+    let init = der(0xa0, &der(0x30, &[mech_types, mech_token].concat()));
+    der(0x60, &[SPNEGO_OID, &init].concat())
+}
+
+/// Returns the second SPNEGO token of a session setup, which wraps an NTLM authenticate message answering the
+/// challenge in the server's first token, and the session key.
+///
+/// # Arguments
+/// `credentials`: The credentials to authenticate with.  
+/// `server_token`: The server's first token.  
+/// `client_challenge`: A random challenge.  
+pub(crate) fn authenticate_token(
+    credentials: &Credentials,
+    server_token: &[u8],
+    client_challenge: [u8; 8],
+) -> io::Result<(Vec<u8>, [u8; 16])> {
+    let malformed = || io::Error::new(ErrorKind::InvalidData, "Malformed NTLM challenge");
+
+    // the challenge is the response token of the SPNEGO reply, or the whole reply from servers that skip SPNEGO
+    let start = server_token
+        .windows(NTLMSSP.len())
+        .position(|window| window == NTLMSSP)
+        .ok_or_else(malformed)?;
+    let challenge = &server_token[start..];
+    if challenge.get(8..12) != Some(&2u32.to_le_bytes()[..]) {
+        return Err(malformed());
+    }
+    let server_challenge: [u8; 8] = challenge
+        .get(24..32)
+        .ok_or_else(malformed)?
+        .try_into()
+        .unwrap();
+    let target_info = security_buffer(challenge, 40).ok_or_else(malformed)?;
+
+    let (lm_response, nt_response, session_key) = if credentials.is_anonymous() {
+        (vec![0], Vec::new(), [0; 16])
+    } else {
+        // servers that send a timestamp expect it back, rather than the client's clock, and no LMv2 response
+        let timestamp = av_pairs(target_info)
+            .find(|(id, _)| *id == MSV_AV_TIMESTAMP)
+            .and_then(|(_, value)| value.try_into().ok());
+        let key = ntowfv2(credentials);
+        let lm_response = match timestamp {
+            Some(_) => vec![0; 24],
+            None => lmv2_response(&key, &server_challenge, &client_challenge),
+        };
+        let timestamp = timestamp.map_or_else(filetime_now, u64::from_le_bytes);
+        let (nt_response, session_key) = ntlmv2_response(
+            &key,
+            &server_challenge,
+            &client_challenge,
+            timestamp,
+            target_info,
+        );
+        (lm_response, nt_response, session_key)
+    };
+
+    let payloads = [
+        lm_response,
+        nt_response,
+        utf16(&credentials.domain),
+        utf16(&credentials.user),
+        Vec::new(),
+        Vec::new(),
+    ];
+    let mut authenticate = NTLMSSP.to_vec();
+    authenticate.extend_from_slice(&3u32.to_le_bytes());
+    let mut offset = 64;
+    for payload in &payloads {
+        authenticate.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        authenticate.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        authenticate.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += payload.len();
+    }
+    authenticate.extend_from_slice(&NEGOTIATE_FLAGS.to_le_bytes());
+    authenticate.extend(payloads.concat());
+
+    let token = der(0xa1, &der(0x30, &der(0xa2, &der(0x04, &authenticate))));
+    Ok((token, session_key))
+}
+
+/// Returns the NTLMv2 hash of the credentials.
+///
+/// # Arguments
+/// `credentials`: The credentials.  
+pub(crate) fn ntowfv2(credentials: &Credentials) -> [u8; 16] {
+    let nt_hash = Md4::digest(utf16(&credentials.password));
+    hmac_md5(
+        &nt_hash,
+        &utf16(&(credentials.user.to_uppercase() + &credentials.domain)),
+    )
+}
+
+/// Returns the NTLMv2 response to a challenge and the session base key.
+///
+/// # Arguments
+/// `key`: The NTLMv2 hash of the credentials.  
+/// `server_challenge`: The challenge of the server.  
+/// `client_challenge`: The challenge of the client.  
+/// `timestamp`: The time of the response as a Windows file time.  
+/// `target_info`: The target information of the challenge.  
+pub(crate) fn ntlmv2_response(
+    key: &[u8; 16],
+    server_challenge: &[u8; 8],
+    client_challenge: &[u8; 8],
+    timestamp: u64,
+    target_info: &[u8],
+) -> (Vec<u8>, [u8; 16]) {
+    let blob = [
+        &[1, 1, 0, 0, 0, 0, 0, 0][..],
+        &timestamp.to_le_bytes(),
+        client_challenge,
+        &[0; 4],
+        target_info,
+        &[0; 4],
+    ]
+    .concat();
+    let proof = hmac_md5(key, &[&server_challenge[..], &blob].concat());
+    let session_key = hmac_md5(key, &proof);
+    ([&proof[..], &blob].concat(), session_key)
+}
+
+/// Returns the LMv2 response to a challenge.
+///
+/// # Arguments
+/// `key`: The NTLMv2 hash of the credentials.  
+/// `server_challenge`: The challenge of the server.  
+/// `client_challenge`: The challenge of the client.  
+fn lmv2_response(
+    key: &[u8; 16],
+    server_challenge: &[u8; 8],
+    client_challenge: &[u8; 8],
+) -> Vec<u8> {
+    let proof = hmac_md5(key, &[&server_challenge[..], client_challenge].concat());
+    [&proof[..], client_challenge].concat()
+}
+
+/// Returns the HMAC-MD5 of `data`.
+///
+/// # Arguments
+/// `key`: The key.  
+/// `data`: The authenticated data.  
+pub(crate) fn hmac_md5(key: &[u8], data: &[u8]) -> [u8; 16] {
+    let mut mac = Hmac::<Md5>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Returns the current time as a Windows file time.
+pub(crate) fn filetime_now() -> u64 {
+    let since_epoch = now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    FILETIME_UNIX_EPOCH + (since_epoch.as_nanos() / 100) as u64
+}
+
+/// Returns the payload of the NTLM security buffer described at `offset` in `message`.
+///
+/// # Arguments
+/// `message`: The NTLM message.  
+/// `offset`: The offset of the length and offset of the payload.  
+pub(crate) fn security_buffer(message: &[u8], offset: usize) -> Option<&[u8]> {
+    let len = u16::from_le_bytes(message.get(offset..offset + 2)?.try_into().unwrap()) as usize;
+    let start =
+        u32::from_le_bytes(message.get(offset + 4..offset + 8)?.try_into().unwrap()) as usize;
+    message.get(start..start.checked_add(len)?)
+}
+
+/// Iterates over the identifiers and values of NTLM target information, up to its end marker.
+///
+/// # Arguments
+/// `target_info`: The target information.  
+pub(crate) fn av_pairs(mut target_info: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let id = u16::from_le_bytes(target_info.get(..2)?.try_into().unwrap());
+        let len = u16::from_le_bytes(target_info.get(2..4)?.try_into().unwrap()) as usize;
+        let value = target_info.get(4..4 + len)?;
+        target_info = &target_info[4 + len..];
+        (id != 0).then_some((id, value))
+    })
+}
+
+/// Encodes a string as UTF-16LE.
+///
+/// # Arguments
+/// `value`: The string.  
+pub(crate) fn utf16(value: &str) -> Vec<u8> {
+    value.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+/// Encodes a DER value.
+///
+/// # Arguments
+/// `tag`: The tag of the value.  
+/// `contents`: The encoded contents.  
+fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut value = vec![tag];
+    let len = contents.len();
+    if len < 0x80 {
+        value.push(len as u8);
+    } else {
+        let len_bytes: Vec<_> = len
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|byte| *byte == 0)
+            .collect();
+        value.push(0x80 | len_bytes.len() as u8);
+        value.extend(len_bytes);
+    }
+    value.extend_from_slice(contents);
+    value
+}
+
+#[cfg(test)]
+mod test {
+    use crate::smb_fs::auth::{
+        authenticate_token, av_pairs, lmv2_response, negotiate_token, ntlmv2_response, ntowfv2,
+        security_buffer, utf16, Credentials, NEGOTIATE_FLAGS, NTLMSSP, SPNEGO_OID,
+    };
+    use std::io::ErrorKind;
+
+    /// The server challenge of the examples in MS-NLMP.
+    const SERVER_CHALLENGE: [u8; 8] = [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef];
+    /// The client challenge of the examples in MS-NLMP.
+    const CLIENT_CHALLENGE: [u8; 8] = [0xaa; 8];
+
+    fn hex(value: &str) -> Vec<u8> {
+        (0..value.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Returns the credentials of the examples in MS-NLMP.
+    fn credentials() -> Credentials {
+        Credentials::new("User", "Password").domain("Domain")
+    }
+
+    /// Returns the target information of the examples in MS-NLMP: the domain and server names.
+    fn target_info() -> Vec<u8> {
+        hex("02000c0044006f006d00610069006e0001000c0053006500720076006500720000000000")
+    }
+
+    /// Returns an NTLM challenge message.
+    fn challenge(target_info: &[u8]) -> Vec<u8> {
+        let mut challenge = NTLMSSP.to_vec();
+        challenge.extend_from_slice(&2u32.to_le_bytes());
+        challenge.extend_from_slice(&[0, 0, 0, 0, 56, 0, 0, 0]);
+        challenge.extend_from_slice(&NEGOTIATE_FLAGS.to_le_bytes());
+        challenge.extend_from_slice(&SERVER_CHALLENGE);
+        challenge.extend_from_slice(&[0; 8]);
+        challenge.extend_from_slice(&(target_info.len() as u16).to_le_bytes());
+        challenge.extend_from_slice(&(target_info.len() as u16).to_le_bytes());
+        challenge.extend_from_slice(&56u32.to_le_bytes());
+        challenge.extend_from_slice(&[0; 8]);
+        challenge.extend_from_slice(target_info);
+        challenge
+    }
+
+    /// Returns the NTLM authenticate message in an SPNEGO token.
+    fn authenticate_message(token: &[u8]) -> &[u8] {
+        let start = token
+            .windows(NTLMSSP.len())
+            .position(|window| window == NTLMSSP)
+            .unwrap();
+        &token[start..]
+    }
+
+    #[test]
+    fn ntlmv2() {
+        // MS-NLMP 4.2.4
+        let key = ntowfv2(&credentials());
+        assert_eq!(key.to_vec(), hex("0c868a403bfd7a93a3001ef22ef02e3f"));
+
+        let (response, session_key) = ntlmv2_response(
+            &key,
+            &SERVER_CHALLENGE,
+            &CLIENT_CHALLENGE,
+            0,
+            &target_info(),
+        );
+        assert_eq!(response[..16], hex("68cd0ab851e51c96aabc927bebef6a1c"));
+        let blob = [
+            &hex("0101000000000000")[..],
+            &[0; 8],
+            &CLIENT_CHALLENGE,
+            &[0; 4],
+            &target_info(),
+            &[0; 4],
+        ]
+        .concat();
+        assert_eq!(response[16..], blob);
+        assert_eq!(
+            session_key.to_vec(),
+            hex("8de40ccadbc14a82f15cb0ad0de95ca3")
+        );
+
+        assert_eq!(
+            lmv2_response(&key, &SERVER_CHALLENGE, &CLIENT_CHALLENGE),
+            hex("86c35097ac9cec102554764a57cccc19aaaaaaaaaaaaaaaa")
+        );
+    }
+
+    #[test]
+    fn negotiate() {
+        let token = negotiate_token();
+        assert_eq!(token[0], 0x60);
+        assert_eq!(token[2..2 + SPNEGO_OID.len()], *SPNEGO_OID);
+
+        let message = authenticate_message(&token);
+        assert_eq!(message.len(), 32);
+        assert_eq!(message[8..12], 1u32.to_le_bytes());
+        assert_eq!(message[12..16], NEGOTIATE_FLAGS.to_le_bytes());
+    }
+
+    #[test]
+    fn authenticate() {
+        let (token, session_key) =
+            authenticate_token(&credentials(), &challenge(&target_info()), CLIENT_CHALLENGE)
+                .unwrap();
+        assert_eq!(token[0], 0xa1);
+        let message = authenticate_message(&token);
+        assert_eq!(message[8..12], 3u32.to_le_bytes());
+        assert_eq!(message[60..64], NEGOTIATE_FLAGS.to_le_bytes());
+        assert_eq!(security_buffer(message, 28).unwrap(), utf16("Domain"));
+        assert_eq!(security_buffer(message, 36).unwrap(), utf16("User"));
+        assert!(security_buffer(message, 44).unwrap().is_empty());
+
+        // without a timestamp from the server, the client sends its own and an LMv2 response
+        let key = ntowfv2(&credentials());
+        assert_eq!(
+            security_buffer(message, 12).unwrap(),
+            lmv2_response(&key, &SERVER_CHALLENGE, &CLIENT_CHALLENGE)
+        );
+        let nt_response = security_buffer(message, 20).unwrap();
+        let timestamp = u64::from_le_bytes(nt_response[24..32].try_into().unwrap());
+        assert_ne!(timestamp, 0);
+        let expected = ntlmv2_response(
+            &key,
+            &SERVER_CHALLENGE,
+            &CLIENT_CHALLENGE,
+            timestamp,
+            &target_info(),
+        );
+        assert_eq!(nt_response, expected.0);
+        assert_eq!(session_key, expected.1);
+    }
+
+    #[test]
+    fn authenticate_timestamp() {
+        // a timestamp is echoed back, and the LMv2 response is zeroed
+        let mut target_info = vec![7, 0, 8, 0];
+        target_info.extend_from_slice(&0x01d0_0000_0000_0000u64.to_le_bytes());
+        target_info.extend_from_slice(&[0; 4]);
+        assert_eq!(av_pairs(&target_info).count(), 1);
+
+        let (token, session_key) =
+            authenticate_token(&credentials(), &challenge(&target_info), CLIENT_CHALLENGE).unwrap();
+        let message = authenticate_message(&token);
+        assert_eq!(security_buffer(message, 12).unwrap(), [0; 24]);
+
+        let key = ntowfv2(&credentials());
+        let expected = ntlmv2_response(
+            &key,
+            &SERVER_CHALLENGE,
+            &CLIENT_CHALLENGE,
+            0x01d0_0000_0000_0000,
+            &target_info,
+        );
+        assert_eq!(security_buffer(message, 20).unwrap(), expected.0);
+        assert_eq!(session_key, expected.1);
+    }
+
+    #[test]
+    fn authenticate_anonymous() {
+        let (token, session_key) = authenticate_token(
+            &Credentials::default(),
+            &challenge(&target_info()),
+            CLIENT_CHALLENGE,
+        )
+        .unwrap();
+        let message = authenticate_message(&token);
+        assert_eq!(security_buffer(message, 12).unwrap(), [0]);
+        assert!(security_buffer(message, 20).unwrap().is_empty());
+        assert!(security_buffer(message, 36).unwrap().is_empty());
+        assert_eq!(session_key, [0; 16]);
+    }
+
+    #[test]
+    fn malformed_challenge() {
+        let mut wrong_type = challenge(&target_info());
+        wrong_type[8] = 1;
+        let mut truncated = challenge(&target_info());
+        truncated.truncate(60);
+        for server_token in [
+            &b"not NTLM"[..],
+            &wrong_type,
+            &challenge(&[])[..30],
+            &truncated,
+        ] {
+            let err = authenticate_token(&credentials(), server_token, CLIENT_CHALLENGE)
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,1692 @@
+mod auth;
+mod session;
+
+use crate::file::{DirEntry, File, FileSystemStats, FileType, Metadata, OpenOptions};
+use crate::smb_fs::auth::{utf16, FILETIME_UNIX_EPOCH};
+use crate::smb_fs::session::{
+    command, flags, malformed, status, status_error, PathNotCovered, Reply, Request, Session,
+    HEADER_SIZE, RELATED_FILE_ID,
+};
+use crate::tree::normalize_and_relativize;
+use crate::util::{component_iter, invalid_input, not_found};
+use crate::FileSystem;
+pub use auth::Credentials;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The port of SMB over Direct TCP.
+const DEFAULT_PORT: u16 = 445;
+/// The most referrals followed for a single path, which guards against referral loops.
+const MAX_REFERRALS: usize = 8;
+/// The control code that asks for DFS referrals.
+const FSCTL_DFS_GET_REFERRALS: u32 = 0x0006_0194;
+
+/// Access rights.
+const FILE_READ_DATA: u32 = 0x1;
+const FILE_READ_ATTRIBUTES: u32 = 0x80;
+const DELETE: u32 = 0x1_0000;
+const GENERIC_WRITE: u32 = 0x4000_0000;
+const GENERIC_READ: u32 = 0x8000_0000;
+
+/// Create dispositions.
+const FILE_OPEN: u32 = 1;
+const FILE_CREATE: u32 = 2;
+const FILE_OPEN_IF: u32 = 3;
+const FILE_OVERWRITE: u32 = 4;
+const FILE_OVERWRITE_IF: u32 = 5;
+
+/// Create options.
+const FILE_DIRECTORY_FILE: u32 = 0x1;
+const FILE_NON_DIRECTORY_FILE: u32 = 0x40;
+
+/// The attribute of directories.
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+
+/// Information classes.
+const INFO_FILE: u8 = 1;
+const INFO_FILESYSTEM: u8 = 2;
+const FILE_DIRECTORY_INFORMATION: u8 = 1;
+const FILE_RENAME_INFORMATION: u8 = 10;
+const FILE_DISPOSITION_INFORMATION: u8 = 13;
+const FILE_END_OF_FILE_INFORMATION: u8 = 20;
+const FILE_NETWORK_OPEN_INFORMATION: u8 = 34;
+const FILE_FS_FULL_SIZE_INFORMATION: u8 = 7;
+
+/// A read-write filesystem on a Windows or Samba share, spoken to with SMB 2 and 3 over Direct TCP. Sessions are
+/// authenticated with NTLMv2 and signed unless they're anonymous or guest sessions.
+///
+/// Shares in DFS namespaces are supported: paths below links are referred to the shares they point to, which are
+/// connected to on the same port with the same credentials.
+pub struct SmbFS {
+    client: Arc<Mutex<Client>>,
+    host: String,
+    share: String,
+}
+
+impl SmbFS {
+    /// Connects to a share.
+    ///
+    /// # Arguments
+    /// `server`: The name or address of the server, with an optional port, such as `fileserver` or `10.0.0.2:4450`.  
+    /// `share`: The name of the share.  
+    /// `credentials`: The credentials to authenticate with.  
+    pub fn connect(server: &str, share: &str, credentials: Credentials) -> crate::Result<Self> {
+        // bracketed IPv6 addresses have colons of their own
+        let (host, port) = match server.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') || host.ends_with(']') => (
+                host,
+                port.parse()
+                    .map_err(|_| invalid_input("Invalid port of the server"))?,
+            ),
+            _ => (server, DEFAULT_PORT),
+        };
+
+        let mut client = Client {
+            credentials,
+            port,
+            sessions: HashMap::default(),
+            trees: HashMap::default(),
+            referrals: Vec::new(),
+        };
+        client.tree(host, share)?;
+
+        Ok(Self {
+            client: Arc::new(Mutex::new(client)),
+            host: host.to_owned(),
+            share: share.to_owned(),
+        })
+    }
+
+    /// Returns the location of `path` on the share.
+    ///
+    /// # Arguments
+    /// `path`: The virtual path.  
+    fn location(&self, path: &str) -> Location {
+        let path = normalize_and_relativize(path);
+        Location {
+            host: self.host.clone(),
+            share: self.share.clone(),
+            names: component_iter(&path).map(str::to_owned).collect(),
+        }
+    }
+
+    /// Opens the entry at `path`, sends the requests `requests` returns for its handle and closes it, all in a single
+    /// compound. Returns the replies to the create and to the requests.
+    ///
+    /// # Arguments
+    /// `path`: The virtual path.  
+    /// `access`: The access rights to open the entry with.  
+    /// `options`: The create options.  
+    /// `requests`: Returns the requests for the target of the entry, which refer to it by [`RELATED_FILE_ID`].  
+    fn with_handle(
+        &self,
+        path: &str,
+        access: u32,
+        options: u32,
+        mut requests: impl FnMut(&Target) -> crate::Result<Vec<Request>>,
+    ) -> crate::Result<Vec<Reply>> {
+        let location = self.location(path);
+        self.client
+            .lock()
+            .with_location(&location, |session, target| {
+                let create = create_request(target, access, FILE_OPEN, options, 0);
+                compound_open(session, create, requests(target)?)
+            })
+    }
+
+    /// Marks the entry at `path` for deletion, which happens once it's closed.
+    ///
+    /// # Arguments
+    /// `path`: The virtual path.  
+    /// `options`: The create options that select a file or a directory.  
+    fn delete(&self, path: &str, options: u32) -> crate::Result<()> {
+        self.with_handle(path, DELETE | FILE_READ_ATTRIBUTES, options, |target| {
+            Ok(vec![set_info_request(
+                target.tree_id,
+                &RELATED_FILE_ID,
+                FILE_DISPOSITION_INFORMATION,
+                &[1],
+            )])
+        })?;
+        Ok(())
+    }
+}
+
+impl FileSystem for SmbFS {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        let location = self.location(path);
+        self.client
+            .lock()
+            .with_location(&location, |session, target| {
+                let create = create_request(
+                    target,
+                    FILE_READ_ATTRIBUTES,
+                    FILE_CREATE,
+                    FILE_DIRECTORY_FILE,
+                    FILE_ATTRIBUTE_DIRECTORY,
+                );
+                compound_open(session, create, Vec::new())?;
+                Ok(())
+            })
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        let replies = self.with_handle(path, FILE_READ_ATTRIBUTES, 0, |_| Ok(Vec::new()))?;
+        Ok(handle(&replies[0])?.metadata)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        let mut access = FILE_READ_ATTRIBUTES;
+        if options.read {
+            access |= GENERIC_READ;
+        }
+        if options.writable() {
+            access |= GENERIC_WRITE;
+        }
+        let disposition = match (options.create_new, options.create, options.truncate) {
+            (true, _, _) => FILE_CREATE,
+            (false, true, true) => FILE_OVERWRITE_IF,
+            (false, true, false) => FILE_OPEN_IF,
+            (false, false, true) => FILE_OVERWRITE,
+            (false, false, false) => FILE_OPEN,
+        };
+
+        let location = self.location(path);
+        let (host, tree_id, handle) =
+            self.client
+                .lock()
+                .with_location(&location, |session, target| {
+                    let handle = create(
+                        session,
+                        target,
+                        access,
+                        disposition,
+                        FILE_NON_DIRECTORY_FILE,
+                        0,
+                    )?;
+                    Ok((target.location.host.clone(), target.tree_id, handle))
+                })?;
+
+        Ok(Box::new(SmbFile {
+            client: self.client.clone(),
+            host,
+            tree_id,
+            file_id: handle.file_id,
+            position: if options.append {
+                handle.metadata.len()
+            } else {
+                0
+            },
+            append: options.append,
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        // the directory may take more than one query to list, so its handle outlives a compound
+        let location = self.location(path);
+        let entries = self
+            .client
+            .lock()
+            .with_location(&location, |session, target| {
+                let handle = create(
+                    session,
+                    target,
+                    FILE_READ_DATA | FILE_READ_ATTRIBUTES,
+                    FILE_OPEN,
+                    FILE_DIRECTORY_FILE,
+                    0,
+                )?;
+                let entries = query_directory(session, target.tree_id, &handle.file_id);
+                close(session, target.tree_id, &handle.file_id);
+                entries
+            })?;
+
+        let directory = normalize_and_relativize(path);
+        Ok(Box::new(entries.into_iter().map(
+            move |(name, metadata)| {
+                Ok(DirEntry {
+                    path: directory.join(name),
+                    metadata,
+                })
+            },
+        )))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.delete(path, FILE_DIRECTORY_FILE)
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.delete(path, FILE_NON_DIRECTORY_FILE)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        // the new name is relative to the share, so both paths have to resolve to the same share
+        let to = self.client.lock().resolve(&self.location(to));
+        self.with_handle(from, DELETE | FILE_READ_ATTRIBUTES, 0, |target| {
+            if !target.location.same_share(&to) {
+                return Err(io::Error::new(
+                    ErrorKind::CrossesDevices,
+                    "Can't rename across DFS links",
+                ));
+            }
+
+            let name = utf16(&to.names.join("\\"));
+            let mut rename = vec![1];
+            rename.extend_from_slice(&[0; 7 + 8]);
+            rename.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            rename.extend(name);
+            Ok(vec![set_info_request(
+                target.tree_id,
+                &RELATED_FILE_ID,
+                FILE_RENAME_INFORMATION,
+                &rename,
+            )])
+        })?;
+        Ok(())
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        let replies = self.with_handle("", FILE_READ_ATTRIBUTES, 0, |target| {
+            Ok(vec![query_info_request(
+                target.tree_id,
+                &RELATED_FILE_ID,
+                INFO_FILESYSTEM,
+                FILE_FS_FULL_SIZE_INFORMATION,
+            )])
+        })?;
+        let info = info_output(&replies[1])?;
+
+        let field = |offset: usize, len: usize| -> crate::Result<u64> {
+            let bytes = info.get(offset..offset + len).ok_or_else(malformed)?;
+            Ok(bytes
+                .iter()
+                .rev()
+                .fold(0, |value, byte| value << 8 | *byte as u64))
+        };
+        // the sizes are in allocation units, which are made of sectors
+        let unit_size = field(24, 4)? * field(28, 4)?;
+        Ok(FileSystemStats {
+            total_space: field(0, 8)? * unit_size,
+            free_space: field(16, 8)? * unit_size,
+            available_space: field(8, 8)? * unit_size,
+            block_size: unit_size,
+        })
+    }
+}
+
+/// A path on a share.
+#[derive(Clone, Debug)]
+struct Location {
+    host: String,
+    share: String,
+    /// The names of the components of the path, relative to the share.
+    names: Vec<String>,
+}
+
+impl Location {
+    /// Returns true if the location is on the same share as `other`.
+    ///
+    /// # Arguments
+    /// `other`: The other location.  
+    fn same_share(&self, other: &Location) -> bool {
+        self.host.eq_ignore_ascii_case(&other.host) && self.share.eq_ignore_ascii_case(&other.share)
+    }
+
+    /// Returns the names of the components of the path if it's within `prefix`.
+    ///
+    /// # Arguments
+    /// `prefix`: The location of the directory that may contain this one.  
+    fn strip_prefix(&self, prefix: &Location) -> Option<&[String]> {
+        if !self.same_share(prefix) || self.names.len() < prefix.names.len() {
+            return None;
+        }
+
+        let (head, rest) = self.names.split_at(prefix.names.len());
+        head.iter()
+            .zip(&prefix.names)
+            .all(|(name, prefix)| name.eq_ignore_ascii_case(prefix))
+            .then_some(rest)
+    }
+
+    /// Parses a location from a UNC path like `\server\share\path`.
+    ///
+    /// # Arguments
+    /// `path`: The UNC path, with one or two leading backslashes.  
+    fn parse(path: &str) -> crate::Result<Self> {
+        let mut names = path.split('\\').filter(|name| !name.is_empty());
+        let (Some(host), Some(share)) = (names.next(), names.next()) else {
+            return Err(malformed());
+        };
+
+        Ok(Self {
+            host: host.to_owned(),
+            share: share.to_owned(),
+            names: names.map(str::to_owned).collect(),
+        })
+    }
+
+    /// Returns the UNC path of the location, with a single leading backslash.
+    fn unc(&self) -> String {
+        let mut path = format!("\\{}\\{}", self.host, self.share);
+        for name in &self.names {
+            path.push('\\');
+            path.push_str(name);
+        }
+        path
+    }
+}
+
+/// A DFS referral of the paths within a link to the location it points to.
+struct Referral {
+    link: Location,
+    target: Location,
+}
+
+/// The connections to every server, and the referrals of the DFS links found so far.
+struct Client {
+    credentials: Credentials,
+    port: u16,
+    /// The session with each server, by its lowercase name.
+    sessions: HashMap<String, Session>,
+    /// The tree ID of each share and whether it's in a DFS namespace, by its lowercase server and share names.
+    trees: HashMap<(String, String), (u32, bool)>,
+    referrals: Vec<Referral>,
+}
+
+/// A request's resolved target on a connected share.
+struct Target {
+    location: Location,
+    tree_id: u32,
+    /// The flags of the header of requests for the share.
+    header_flags: u32,
+}
+
+impl Target {
+    /// Returns the name of the path that's sent to the server.
+    fn name(&self) -> String {
+        let path = self.location.names.join("\\");
+        // shares in DFS namespaces expect the server and share in front
+        if self.header_flags & flags::DFS_OPERATIONS == 0 {
+            return path;
+        }
+
+        let prefix = format!("{}\\{}", self.location.host, self.location.share);
+        if path.is_empty() {
+            prefix
+        } else {
+            format!("{prefix}\\{path}")
+        }
+    }
+}
+
+impl Client {
+    /// Returns the session with `host`, connecting and authenticating if there's none.
+    ///
+    /// # Arguments
+    /// `host`: The name of the server.  
+    fn session(&mut self, host: &str) -> crate::Result<&mut Session> {
+        let key = host.to_lowercase();
+        if !self.sessions.contains_key(&key) {
+            let session = Session::connect(&format!("{host}:{}", self.port), &self.credentials)?;
+            self.sessions.insert(key.clone(), session);
+        }
+
+        Ok(self.sessions.get_mut(&key).unwrap())
+    }
+
+    /// Returns the tree ID of a share and whether it's in a DFS namespace, connecting to it if needed.
+    ///
+    /// # Arguments
+    /// `host`: The name of the server.  
+    /// `share`: The name of the share.  
+    fn tree(&mut self, host: &str, share: &str) -> crate::Result<(u32, bool)> {
+        let key = (host.to_lowercase(), share.to_lowercase());
+        if let Some(tree) = self.trees.get(&key) {
+            return Ok(*tree);
+        }
+
+        let tree = self.session(host)?.tree_connect(host, share)?;
+        self.trees.insert(key, tree);
+        Ok(tree)
+    }
+
+    /// Resolves a location through the referrals found so far.
+    ///
+    /// # Arguments
+    /// `location`: The location.  
+    fn resolve(&self, location: &Location) -> Location {
+        let mut location = location.clone();
+        for _ in 0..MAX_REFERRALS {
+            // the most specific link wins
+            let Some((referral, rest)) = self
+                .referrals
+                .iter()
+                .filter_map(|referral| Some((referral, location.strip_prefix(&referral.link)?)))
+                .max_by_key(|(referral, _)| referral.link.names.len())
+            else {
+                break;
+            };
+
+            let mut target = referral.target.clone();
+            target.names.extend_from_slice(rest);
+            location = target;
+        }
+
+        location
+    }
+
+    /// Calls `op` with the session and target of `location`, following DFS referrals.
+    ///
+    /// # Arguments
+    /// `location`: The location.  
+    /// `op`: The operation.  
+    fn with_location<R>(
+        &mut self,
+        location: &Location,
+        mut op: impl FnMut(&mut Session, &Target) -> crate::Result<R>,
+    ) -> crate::Result<R> {
+        for _ in 0..MAX_REFERRALS {
+            let resolved = self.resolve(location);
+            let (tree_id, dfs) = self.tree(&resolved.host, &resolved.share)?;
+            let target = Target {
+                location: resolved,
+                tree_id,
+                header_flags: if dfs { flags::DFS_OPERATIONS } else { 0 },
+            };
+
+            let session = self.session(&target.location.host)?;
+            match op(session, &target) {
+                Err(err) if err.get_ref().is_some_and(|err| err.is::<PathNotCovered>()) => {
+                    self.refer(&target.location)?;
+                }
+                result => return result,
+            }
+        }
+
+        Err(io::Error::other("Too many DFS referrals"))
+    }
+
+    /// Asks the server of `location` where it's referred to, remembering the referral.
+    ///
+    /// # Arguments
+    /// `location`: The location that isn't covered by its share.  
+    fn refer(&mut self, location: &Location) -> crate::Result<()> {
+        let path = location.unc();
+        let (ipc, _) = self.tree(&location.host, "IPC$")?;
+        let session = self.session(&location.host)?;
+
+        // the highest referral version understood, and the path
+        let mut input = 4u16.to_le_bytes().to_vec();
+        input.extend(utf16(&path));
+        input.extend_from_slice(&[0; 2]);
+        let mut ioctl = Vec::new();
+        ioctl.extend_from_slice(&57u16.to_le_bytes());
+        ioctl.extend_from_slice(&[0; 2]);
+        ioctl.extend_from_slice(&FSCTL_DFS_GET_REFERRALS.to_le_bytes());
+        ioctl.extend_from_slice(&[0xff; 16]);
+        ioctl.extend_from_slice(&((HEADER_SIZE + 56) as u32).to_le_bytes());
+        ioctl.extend_from_slice(&(input.len() as u32).to_le_bytes());
+        ioctl.extend_from_slice(&[0; 12]);
+        ioctl.extend_from_slice(&session.max_io_size.to_le_bytes());
+        ioctl.extend_from_slice(&1u32.to_le_bytes());
+        ioctl.extend_from_slice(&[0; 4]);
+        ioctl.extend(input);
+        let reply = session.call(Request::new(command::IOCTL, ipc, ioctl))?;
+
+        let start = reply.u32(32)? as usize;
+        let len = reply.u32(36)? as usize;
+        let output = reply
+            .message
+            .get(start..start + len)
+            .ok_or_else(malformed)?;
+        let referral = parse_referral(output, &path)?;
+        // a referral to the same place would loop forever
+        if referral.link.names.len() > location.names.len()
+            || referral.target.strip_prefix(&referral.link).is_some()
+        {
+            return Err(malformed());
+        }
+        self.referrals.push(referral);
+        Ok(())
+    }
+}
+
+/// Parses a referral response, returning the referral of the first target.
+///
+/// # Arguments
+/// `output`: The response.  
+/// `path`: The UNC path the referral was requested for.  
+fn parse_referral(output: &[u8], path: &str) -> crate::Result<Referral> {
+    let u16_at = |offset: usize| -> crate::Result<u16> {
+        let bytes = output.get(offset..offset + 2).ok_or_else(malformed)?;
+        Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    // the consumed part of the path is the link, in bytes of UTF-16
+    let consumed = u16_at(0)? as usize / 2;
+    if u16_at(2)? == 0 {
+        return Err(not_found());
+    }
+    let link: String = char::decode_utf16(path.encode_utf16().take(consumed))
+        .collect::<Result<_, _>>()
+        .map_err(|_| malformed())?;
+
+    let version = u16_at(8)?;
+    let address_offset = match version {
+        2 => 8 + 20,
+        3 | 4 => {
+            // referrals to a list of names are for domains rather than links
+            if u16_at(8 + 6)? & 0x2 != 0 {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    "Domain referrals aren't supported",
+                ));
+            }
+            8 + 16
+        }
+        _ => return Err(malformed()),
+    };
+    let address = read_utf16z(output, 8 + u16_at(address_offset)? as usize)?;
+
+    Ok(Referral {
+        link: Location::parse(&link)?,
+        target: Location::parse(&address)?,
+    })
+}
+
+/// Reads a null-terminated UTF-16 string.
+///
+/// # Arguments
+/// `data`: The data the string is in.  
+/// `offset`: The offset of the string.  
+fn read_utf16z(data: &[u8], offset: usize) -> crate::Result<String> {
+    let units = data
+        .get(offset..)
+        .ok_or_else(malformed)?
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|unit| *unit != 0);
+    char::decode_utf16(units)
+        .collect::<Result<_, _>>()
+        .map_err(|_| malformed())
+}
+
+/// An open handle.
+struct Handle {
+    file_id: [u8; 16],
+    metadata: Metadata,
+}
+
+/// Returns the request that opens or creates an entry.
+///
+/// # Arguments
+/// `target`: The target of the entry.  
+/// `access`: The access rights.  
+/// `disposition`: What to do if the entry does or doesn't exist.  
+/// `options`: The create options.  
+/// `attributes`: The attributes of a created entry.  
+fn create_request(
+    target: &Target,
+    access: u32,
+    disposition: u32,
+    options: u32,
+    attributes: u32,
+) -> Request {
+    let mut name = utf16(&target.name());
+    let name_len = name.len();
+    // the buffer can't be empty, even for the root of the share
+    if name.is_empty() {
+        name.push(0);
+    }
+
+    let mut create = Vec::new();
+    create.extend_from_slice(&57u16.to_le_bytes());
+    create.extend_from_slice(&[0; 2]);
+    // impersonate the user
+    create.extend_from_slice(&2u32.to_le_bytes());
+    create.extend_from_slice(&[0; 16]);
+    create.extend_from_slice(&access.to_le_bytes());
+    create.extend_from_slice(&attributes.to_le_bytes());
+    // share reads, writes and deletes with other handles
+    create.extend_from_slice(&7u32.to_le_bytes());
+    create.extend_from_slice(&disposition.to_le_bytes());
+    create.extend_from_slice(&options.to_le_bytes());
+    create.extend_from_slice(&((HEADER_SIZE + 56) as u16).to_le_bytes());
+    create.extend_from_slice(&(name_len as u16).to_le_bytes());
+    create.extend_from_slice(&[0; 8]);
+    create.extend(name);
+
+    Request {
+        header_flags: target.header_flags,
+        ..Request::new(command::CREATE, target.tree_id, create)
+    }
+}
+
+/// Opens or creates an entry.
+///
+/// # Arguments
+/// `session`: The session.  
+/// `target`: The target of the entry.  
+/// `access`: The access rights.  
+/// `disposition`: What to do if the entry does or doesn't exist.  
+/// `options`: The create options.  
+/// `attributes`: The attributes of a created entry.  
+fn create(
+    session: &mut Session,
+    target: &Target,
+    access: u32,
+    disposition: u32,
+    options: u32,
+    attributes: u32,
+) -> crate::Result<Handle> {
+    let request = create_request(target, access, disposition, options, attributes);
+    handle(&session.call(request)?)
+}
+
+/// Returns the handle a create opened.
+///
+/// # Arguments
+/// `reply`: The reply to the create.  
+fn handle(reply: &Reply) -> crate::Result<Handle> {
+    Ok(Handle {
+        file_id: reply.bytes(64, 16)?.try_into().unwrap(),
+        metadata: metadata(reply.u64(24)?, reply.u64(48)?, reply.u32(56)?),
+    })
+}
+
+/// Opens or creates an entry, sends `requests` for its handle and closes it, all in a single compound. Returns the
+/// replies to the create and to the requests, failing with the status of the first that failed.
+///
+/// # Arguments
+/// `session`: The session.  
+/// `create`: The request that opens or creates the entry.  
+/// `requests`: The requests for the handle, which refer to it by [`RELATED_FILE_ID`].  
+fn compound_open(
+    session: &mut Session,
+    create: Request,
+    requests: Vec<Request>,
+) -> crate::Result<Vec<Reply>> {
+    let tree_id = create.tree_id;
+    let mut compound = vec![create];
+    compound.extend(requests);
+    compound.push(close_request(tree_id, &RELATED_FILE_ID));
+
+    // the handle is gone whether or not it closed cleanly
+    let mut replies = session.compound(compound)?;
+    replies.pop();
+    match replies.iter().find(|reply| reply.status != status::SUCCESS) {
+        Some(reply) => Err(status_error(reply.status)),
+        None => Ok(replies),
+    }
+}
+
+/// Returns the request that closes a handle.
+///
+/// # Arguments
+/// `tree_id`: The tree of the handle.  
+/// `file_id`: The handle.  
+fn close_request(tree_id: u32, file_id: &[u8; 16]) -> Request {
+    let mut close = Vec::new();
+    close.extend_from_slice(&24u16.to_le_bytes());
+    close.extend_from_slice(&[0; 6]);
+    close.extend_from_slice(file_id);
+    Request::new(command::CLOSE, tree_id, close)
+}
+
+/// Closes a handle. Errors are ignored, since the handle is gone either way.
+///
+/// # Arguments
+/// `session`: The session.  
+/// `tree_id`: The tree of the handle.  
+/// `file_id`: The handle.  
+fn close(session: &mut Session, tree_id: u32, file_id: &[u8; 16]) {
+    let _ = session.call(close_request(tree_id, file_id));
+}
+
+/// Returns the request that queries information about a handle or its filesystem.
+///
+/// # Arguments
+/// `tree_id`: The tree of the handle.  
+/// `file_id`: The handle.  
+/// `info_type`: Whether the information is about the file or its filesystem.  
+/// `class`: The class of the information.  
+fn query_info_request(tree_id: u32, file_id: &[u8; 16], info_type: u8, class: u8) -> Request {
+    let mut query = Vec::new();
+    query.extend_from_slice(&41u16.to_le_bytes());
+    query.extend_from_slice(&[info_type, class]);
+    query.extend_from_slice(&1024u32.to_le_bytes());
+    query.extend_from_slice(&[0; 16]);
+    query.extend_from_slice(file_id);
+    query.push(0);
+    Request::new(command::QUERY_INFO, tree_id, query)
+}
+
+/// Returns the information a query returned.
+///
+/// # Arguments
+/// `reply`: The reply to the query.  
+fn info_output(reply: &Reply) -> crate::Result<Vec<u8>> {
+    Ok(reply.buffer(2, reply.u32(4)? as usize)?.to_vec())
+}
+
+/// Returns the request that sets information about a handle.
+///
+/// # Arguments
+/// `tree_id`: The tree of the handle.  
+/// `file_id`: The handle.  
+/// `class`: The class of the information.  
+/// `info`: The information.  
+fn set_info_request(tree_id: u32, file_id: &[u8; 16], class: u8, info: &[u8]) -> Request {
+    let mut set = Vec::new();
+    set.extend_from_slice(&33u16.to_le_bytes());
+    set.extend_from_slice(&[INFO_FILE, class]);
+    set.extend_from_slice(&(info.len() as u32).to_le_bytes());
+    set.extend_from_slice(&((HEADER_SIZE + 32) as u16).to_le_bytes());
+    set.extend_from_slice(&[0; 6]);
+    set.extend_from_slice(file_id);
+    set.extend_from_slice(info);
+    Request::new(command::SET_INFO, tree_id, set)
+}
+
+/// Lists an open directory. Returns the name and metadata of every entry.
+///
+/// # Arguments
+/// `session`: The session.  
+/// `tree_id`: The tree of the directory.  
+/// `file_id`: The handle of the directory.  
+fn query_directory(
+    session: &mut Session,
+    tree_id: u32,
+    file_id: &[u8; 16],
+) -> crate::Result<Vec<(String, Metadata)>> {
+    let pattern = utf16("*");
+    let mut entries = Vec::new();
+    let mut restart = true;
+    loop {
+        let mut query = Vec::new();
+        query.extend_from_slice(&33u16.to_le_bytes());
+        query.extend_from_slice(&[FILE_DIRECTORY_INFORMATION, u8::from(restart)]);
+        query.extend_from_slice(&[0; 4]);
+        query.extend_from_slice(file_id);
+        query.extend_from_slice(&((HEADER_SIZE + 32) as u16).to_le_bytes());
+        query.extend_from_slice(&(pattern.len() as u16).to_le_bytes());
+        query.extend_from_slice(&session.max_io_size.to_le_bytes());
+        query.extend_from_slice(&pattern);
+        let reply = session.raw_call(Request::new(command::QUERY_DIRECTORY, tree_id, query))?;
+        match reply.status {
+            status::SUCCESS => {}
+            status::NO_MORE_FILES => return Ok(entries),
+            status => return Err(status_error(status)),
+        }
+        restart = false;
+
+        let mut listing = reply.buffer(2, reply.u32(4)? as usize)?;
+        loop {
+            let field =
+                |offset: usize, len: usize| listing.get(offset..offset + len).ok_or_else(malformed);
+            let u32_at = |offset| {
+                Ok::<_, io::Error>(u32::from_le_bytes(field(offset, 4)?.try_into().unwrap()))
+            };
+            let u64_at = |offset| {
+                Ok::<_, io::Error>(u64::from_le_bytes(field(offset, 8)?.try_into().unwrap()))
+            };
+
+            let name_len = u32_at(60)? as usize;
+            let name: Vec<_> = field(64, name_len)?
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .collect();
+            let name = String::from_utf16(&name).map_err(|_| malformed())?;
+            if name != "." && name != ".." {
+                entries.push((name, metadata(u64_at(24)?, u64_at(40)?, u32_at(56)?)));
+            }
+
+            let next = u32_at(0)? as usize;
+            if next == 0 {
+                break;
+            }
+            listing = listing.get(next..).ok_or_else(malformed)?;
+        }
+    }
+}
+
+/// Returns the metadata of an entry.
+///
+/// # Arguments
+/// `last_write_time`: The time the entry was last written, as a Windows file time.  
+/// `end_of_file`: The length of the entry.  
+/// `attributes`: The attributes of the entry.  
+fn metadata(last_write_time: u64, end_of_file: u64, attributes: u32) -> Metadata {
+    let (file_type, len) = if attributes & FILE_ATTRIBUTE_DIRECTORY != 0 {
+        (FileType::Directory, 0)
+    } else {
+        (FileType::File, end_of_file)
+    };
+
+    let mut metadata = Metadata::new(file_type, len);
+    metadata.modified = last_write_time
+        .checked_sub(FILETIME_UNIX_EPOCH)
+        .filter(|_| last_write_time != 0)
+        .map(|since_epoch| SystemTime::UNIX_EPOCH + Duration::from_nanos(since_epoch * 100));
+    metadata
+}
+
+/// A file open on a share, which is read and written in place.
+struct SmbFile {
+    client: Arc<Mutex<Client>>,
+    host: String,
+    tree_id: u32,
+    file_id: [u8; 16],
+    position: u64,
+    append: bool,
+}
+
+impl SmbFile {
+    /// Calls `f` with the session the file is open in.
+    ///
+    /// # Arguments
+    /// `f`: The function.  
+    fn with_session<R>(
+        &self,
+        f: impl FnOnce(&mut Session) -> crate::Result<R>,
+    ) -> crate::Result<R> {
+        let mut client = self.client.lock();
+        f(client.session(&self.host)?)
+    }
+}
+
+impl File for SmbFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        let info = self.with_session(|session| {
+            let request = query_info_request(
+                self.tree_id,
+                &self.file_id,
+                INFO_FILE,
+                FILE_NETWORK_OPEN_INFORMATION,
+            );
+            info_output(&session.call(request)?)
+        })?;
+
+        let field = |offset: usize| -> crate::Result<u64> {
+            let bytes = info.get(offset..offset + 8).ok_or_else(malformed)?;
+            Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        Ok(metadata(field(16)?, field(40)?, field(48)? as u32))
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        self.with_session(|session| {
+            let request = set_info_request(
+                self.tree_id,
+                &self.file_id,
+                FILE_END_OF_FILE_INFORMATION,
+                &len.to_le_bytes(),
+            );
+            session.call(request).map(drop)
+        })
+    }
+}
+
+impl Read for SmbFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_len = self.with_session(|session| {
+            let count = buf.len().min(session.max_io_size as usize);
+            let mut read = Vec::new();
+            read.extend_from_slice(&49u16.to_le_bytes());
+            read.extend_from_slice(&[0x50, 0]);
+            read.extend_from_slice(&(count as u32).to_le_bytes());
+            read.extend_from_slice(&self.position.to_le_bytes());
+            read.extend_from_slice(&self.file_id);
+            read.extend_from_slice(&[0; 17]);
+            let reply = session.raw_call(Request::new(command::READ, self.tree_id, read))?;
+            match reply.status {
+                status::SUCCESS => {}
+                status::END_OF_FILE => return Ok(0),
+                status => return Err(status_error(status)),
+            }
+
+            let start = reply.body().get(2).copied().ok_or_else(malformed)? as usize;
+            let len = (reply.u32(4)? as usize).min(count);
+            let data = reply
+                .message
+                .get(start..start + len)
+                .ok_or_else(malformed)?;
+            buf[..len].copy_from_slice(data);
+            Ok(len)
+        })?;
+
+        self.position += read_len as u64;
+        Ok(read_len)
+    }
+}
+
+impl Seek for SmbFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.metadata()?.len().checked_add_signed(offset),
+        };
+        self.position =
+            position.ok_or_else(|| invalid_input("seek to a negative or overflowing position"))?;
+        Ok(self.position)
+    }
+}
+
+impl Write for SmbFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.append {
+            self.position = self.metadata()?.len();
+        }
+
+        let written = self.with_session(|session| {
+            let count = buf.len().min(session.max_io_size as usize);
+            let mut write = Vec::new();
+            write.extend_from_slice(&49u16.to_le_bytes());
+            write.extend_from_slice(&((HEADER_SIZE + 48) as u16).to_le_bytes());
+            write.extend_from_slice(&(count as u32).to_le_bytes());
+            write.extend_from_slice(&self.position.to_le_bytes());
+            write.extend_from_slice(&self.file_id);
+            write.extend_from_slice(&[0; 16]);
+            write.extend_from_slice(&buf[..count]);
+            let reply = session.call(Request::new(command::WRITE, self.tree_id, write))?;
+            Ok((reply.u32(4)? as usize).min(count))
+        })?;
+
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SmbFile {
+    fn drop(&mut self) {
+        let _ = self.with_session(|session| {
+            close(session, self.tree_id, &self.file_id);
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::{Metadata, OpenOptions};
+    use crate::memory_fs::MemoryFS;
+    use crate::smb_fs::auth::{filetime_now, hmac_md5, ntowfv2, security_buffer, utf16};
+    use crate::smb_fs::session::{command, flags, read_frame, status, write_frame, Signing};
+    use crate::smb_fs::{
+        Credentials, SmbFS, FILE_ATTRIBUTE_DIRECTORY, FILE_CREATE, FILE_DIRECTORY_FILE,
+        FILE_DISPOSITION_INFORMATION, FILE_END_OF_FILE_INFORMATION, FILE_FS_FULL_SIZE_INFORMATION,
+        FILE_NETWORK_OPEN_INFORMATION, FILE_NON_DIRECTORY_FILE, FILE_OPEN, FILE_OVERWRITE,
+        FILE_OVERWRITE_IF, FILE_RENAME_INFORMATION, FSCTL_DFS_GET_REFERRALS, HEADER_SIZE,
+        INFO_FILE,
+    };
+    use crate::util::test::{check_open_options, check_set_len, read_directory};
+    use crate::{util, FileSystem};
+    use std::collections::HashMap;
+    use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+
+    const USER: &str = "alice";
+    const PASSWORD: &str = "hunter2";
+    const SERVER_CHALLENGE: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+    const SESSION_ID: u64 = 0x1234;
+    /// The tree IDs of the shares.
+    const DATA_TREE: u32 = 1;
+    const DFS_TREE: u32 = 2;
+    const IPC_TREE: u32 = 3;
+
+    /// What a file ID of the test server refers to.
+    struct Open {
+        path: String,
+        directory: bool,
+        delete: bool,
+        listed: bool,
+    }
+
+    /// The state of a connection to the test server.
+    #[derive(Default)]
+    struct Connection {
+        signing: Option<Signing>,
+        opens: HashMap<u64, Open>,
+        next_file_id: u64,
+        /// The file ID the last create opened, which related requests refer to.
+        related: u64,
+    }
+
+    fn le16(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn le32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn le64(data: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// Decodes a UTF-16LE string.
+    fn string(data: &[u8]) -> String {
+        let units: Vec<_> = data
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect();
+        String::from_utf16(&units).unwrap()
+    }
+
+    /// Serves `fs` over SMB 3.0.2 on TCP, with a share `data` over its directory `data`, and a share `dfs` in a DFS
+    /// namespace over its directory `dfs` whose link `link` is referred to `data\sub`. Returns the address of the
+    /// server.
+    fn serve(fs: Arc<MemoryFS>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("127.0.0.1:{}", listener.local_addr().unwrap().port());
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let fs = fs.clone();
+                thread::spawn(move || session(&fs, stream.unwrap()));
+            }
+        });
+
+        addr
+    }
+
+    /// Serves a single connection until it's closed.
+    fn session(fs: &MemoryFS, mut stream: TcpStream) {
+        let mut connection = Connection::default();
+        while let Ok(frame) = read_frame(&mut stream) {
+            let mut replies = Vec::new();
+            let mut failed = None;
+            let mut rest = &frame[..];
+            while !rest.is_empty() {
+                let next = le32(rest, 20) as usize;
+                let (request, remaining) = rest.split_at(if next == 0 { rest.len() } else { next });
+                rest = remaining;
+
+                let command = le16(request, 12);
+                let request_flags = le32(request, 16);
+                // every request after the session is set up must be signed
+                if let Some(signing) = &connection.signing {
+                    if request_flags & flags::SIGNED == 0 || !signing.verify(request) {
+                        return;
+                    }
+                }
+
+                // related requests fail with the status of the request before them
+                let mut tree_id = le32(request, 36);
+                let related = request_flags & flags::RELATED_OPERATIONS != 0;
+                let (status, body) = match failed.filter(|_| related) {
+                    Some(status) => Err(status),
+                    None => handle(fs, &mut connection, command, request, &mut tree_id),
+                }
+                .unwrap_or_else(|status| (status, vec![9, 0, 0, 0, 0, 0, 0, 0, 0]));
+                failed = (status != status::SUCCESS && status != status::MORE_PROCESSING_REQUIRED)
+                    .then_some(status);
+
+                // grant the credits asked for
+                let mut reply = request[..HEADER_SIZE].to_vec();
+                reply[8..12].copy_from_slice(&status.to_le_bytes());
+                reply[14..16].copy_from_slice(&request[14..16]);
+                reply[16..20].copy_from_slice(&flags::SERVER_TO_REDIR.to_le_bytes());
+                reply[20..24].fill(0);
+                reply[36..40].copy_from_slice(&tree_id.to_le_bytes());
+                reply[40..48].copy_from_slice(&SESSION_ID.to_le_bytes());
+                reply[48..64].fill(0);
+                reply.extend(body);
+                replies.push(reply);
+            }
+
+            let count = replies.len();
+            let mut compound = Vec::new();
+            for (index, mut reply) in replies.into_iter().enumerate() {
+                if index + 1 < count {
+                    reply.resize(reply.len().next_multiple_of(8), 0);
+                    let next = reply.len() as u32;
+                    reply[20..24].copy_from_slice(&next.to_le_bytes());
+                }
+                if let Some(signing) = &connection.signing {
+                    signing.sign_message(&mut reply);
+                }
+                compound.extend(reply);
+            }
+            if write_frame(&mut stream, &compound).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Returns the path in `fs` of a name on a share.
+    fn resolve(tree_id: u32, name: &str, dfs_name: bool) -> Result<String, u32> {
+        let mut names: Vec<_> = name.split('\\').filter(|name| !name.is_empty()).collect();
+        let base = match tree_id {
+            DATA_TREE => "data",
+            DFS_TREE => {
+                // names on DFS shares start with the server and share
+                if dfs_name {
+                    names.drain(..2.min(names.len()));
+                }
+                if names
+                    .first()
+                    .is_some_and(|name| name.eq_ignore_ascii_case("link"))
+                {
+                    return Err(status::PATH_NOT_COVERED);
+                }
+                "dfs"
+            }
+            _ => return Err(status::ACCESS_DENIED),
+        };
+
+        Ok([base]
+            .into_iter()
+            .chain(names)
+            .collect::<Vec<_>>()
+            .join("/"))
+    }
+
+    /// Converts an error of `fs` to a status.
+    fn error_status(err: std::io::Error) -> u32 {
+        match err.kind() {
+            ErrorKind::NotFound => status::OBJECT_NAME_NOT_FOUND,
+            ErrorKind::AlreadyExists => status::OBJECT_NAME_COLLISION,
+            ErrorKind::DirectoryNotEmpty => status::DIRECTORY_NOT_EMPTY,
+            _ => status::INVALID_PARAMETER,
+        }
+    }
+
+    /// Returns the attributes of an entry.
+    fn attributes(metadata: &Metadata) -> u32 {
+        if metadata.is_directory() {
+            FILE_ATTRIBUTE_DIRECTORY
+        } else {
+            0x80
+        }
+    }
+
+    /// Handles a request. Returns the status and body of its reply, or the status of an error.
+    fn handle(
+        fs: &MemoryFS,
+        connection: &mut Connection,
+        command: u16,
+        request: &[u8],
+        tree_id: &mut u32,
+    ) -> Result<(u32, Vec<u8>), u32> {
+        let body = &request[HEADER_SIZE..];
+        let buffer = |offset: usize, len: usize| &request[offset..offset + len];
+        let related = connection.related;
+        let file_id = |offset: usize| {
+            if body[offset..offset + 16] == [0xff; 16] {
+                related
+            } else {
+                le64(body, offset)
+            }
+        };
+        let mut reply = Vec::new();
+
+        match command {
+            command::NEGOTIATE => {
+                reply.extend_from_slice(&65u16.to_le_bytes());
+                // signing is required, and reads and writes are small enough to be split
+                reply.extend_from_slice(&3u16.to_le_bytes());
+                reply.extend_from_slice(&0x0302u16.to_le_bytes());
+                reply.extend_from_slice(&[0; 2 + 16 + 4]);
+                for max_size in [1 << 20, 4096, 4096] {
+                    reply.extend_from_slice(&(max_size as u32).to_le_bytes());
+                }
+                reply.extend_from_slice(&filetime_now().to_le_bytes());
+                reply.extend_from_slice(&[0; 8]);
+                reply.extend_from_slice(&128u16.to_le_bytes());
+                reply.extend_from_slice(&[0; 7]);
+            }
+            command::SESSION_SETUP => {
+                let token = buffer(le16(body, 12) as usize, le16(body, 14) as usize);
+                let start = token.windows(8).position(|window| window == b"NTLMSSP\0");
+                let message = &token[start.ok_or(status::INVALID_PARAMETER)?..];
+                let token = if message[8] == 1 {
+                    // the challenge, whose target information is only a timestamp
+                    let mut target_info = vec![7, 0, 8, 0];
+                    target_info.extend_from_slice(&filetime_now().to_le_bytes());
+                    target_info.extend_from_slice(&[0; 4]);
+                    let mut challenge = b"NTLMSSP\0".to_vec();
+                    challenge.extend_from_slice(&2u32.to_le_bytes());
+                    challenge.extend_from_slice(&[0, 0, 0, 0, 56, 0, 0, 0]);
+                    challenge.extend_from_slice(&le32(message, 12).to_le_bytes());
+                    challenge.extend_from_slice(&SERVER_CHALLENGE);
+                    challenge.extend_from_slice(&[0; 8]);
+                    challenge.extend_from_slice(&(target_info.len() as u16).to_le_bytes());
+                    challenge.extend_from_slice(&(target_info.len() as u16).to_le_bytes());
+                    challenge.extend_from_slice(&56u32.to_le_bytes());
+                    challenge.extend_from_slice(&[0; 8]);
+                    challenge.extend(target_info);
+                    challenge
+                } else {
+                    let field = |offset| security_buffer(message, offset).unwrap();
+                    let credentials =
+                        Credentials::new(string(field(36)), PASSWORD).domain(string(field(28)));
+                    let key = ntowfv2(&credentials);
+                    let response = field(20);
+                    if credentials.user != USER || response.len() < 16 {
+                        return Err(status::LOGON_FAILURE);
+                    }
+                    let (proof, blob) = response.split_at(16);
+                    if hmac_md5(&key, &[&SERVER_CHALLENGE[..], blob].concat()) != proof {
+                        return Err(status::LOGON_FAILURE);
+                    }
+                    connection.signing = Some(Signing::new(0x0302, hmac_md5(&key, proof)));
+                    Vec::new()
+                };
+
+                reply.extend_from_slice(&9u16.to_le_bytes());
+                reply.extend_from_slice(&[0; 2]);
+                reply.extend_from_slice(&((HEADER_SIZE + 8) as u16).to_le_bytes());
+                reply.extend_from_slice(&(token.len() as u16).to_le_bytes());
+                let status = if token.is_empty() {
+                    status::SUCCESS
+                } else {
+                    status::MORE_PROCESSING_REQUIRED
+                };
+                reply.extend(token);
+                return Ok((status, reply));
+            }
+            command::LOGOFF => {
+                reply.extend_from_slice(&[4, 0, 0, 0]);
+            }
+            command::TREE_CONNECT => {
+                let path = string(buffer(le16(body, 4) as usize, le16(body, 6) as usize));
+                let (tree, share_type, share_flags, capabilities) =
+                    match path.rsplit('\\').next().unwrap() {
+                        "data" => (DATA_TREE, 1, 0, 0),
+                        "dfs" => (DFS_TREE, 1, 0x1, 0x8),
+                        "IPC$" => (IPC_TREE, 2, 0, 0),
+                        _ => return Err(status::BAD_NETWORK_NAME),
+                    };
+                *tree_id = tree;
+                reply.extend_from_slice(&16u16.to_le_bytes());
+                reply.extend_from_slice(&[share_type, 0]);
+                reply.extend_from_slice(&(share_flags as u32).to_le_bytes());
+                reply.extend_from_slice(&(capabilities as u32).to_le_bytes());
+                reply.extend_from_slice(&0x1f01ffu32.to_le_bytes());
+            }
+            command::CREATE => {
+                let (disposition, options) = (le32(body, 36), le32(body, 40));
+                let name = string(buffer(le16(body, 44) as usize, le16(body, 46) as usize));
+                let path = resolve(*tree_id, &name, *tree_id == DFS_TREE)?;
+
+                match (fs.metadata(&path), disposition) {
+                    (Ok(_), FILE_CREATE) => return Err(status::OBJECT_NAME_COLLISION),
+                    (Err(_), FILE_OPEN | FILE_OVERWRITE) => {
+                        return Err(status::OBJECT_NAME_NOT_FOUND)
+                    }
+                    (Err(_), _) if options & FILE_DIRECTORY_FILE != 0 => {
+                        fs.create_dir(&path).map_err(error_status)?;
+                    }
+                    (Err(_), _) => {
+                        fs.create_file(&path).map_err(error_status)?;
+                    }
+                    (Ok(metadata), FILE_OVERWRITE | FILE_OVERWRITE_IF) => {
+                        if metadata.is_directory() {
+                            return Err(status::FILE_IS_A_DIRECTORY);
+                        }
+                        fs.create_file(&path).map_err(error_status)?;
+                    }
+                    _ => {}
+                }
+
+                let metadata = fs.metadata(&path).map_err(error_status)?;
+                if options & FILE_DIRECTORY_FILE != 0 && !metadata.is_directory() {
+                    return Err(status::NOT_A_DIRECTORY);
+                }
+                if options & FILE_NON_DIRECTORY_FILE != 0 && metadata.is_directory() {
+                    return Err(status::FILE_IS_A_DIRECTORY);
+                }
+
+                let file_id = connection.next_file_id;
+                connection.next_file_id += 1;
+                connection.related = file_id;
+                connection.opens.insert(
+                    file_id,
+                    Open {
+                        path,
+                        directory: metadata.is_directory(),
+                        delete: false,
+                        listed: false,
+                    },
+                );
+
+                reply.extend_from_slice(&89u16.to_le_bytes());
+                reply.extend_from_slice(&[0; 6]);
+                for _ in 0..4 {
+                    reply.extend_from_slice(&filetime_now().to_le_bytes());
+                }
+                reply.extend_from_slice(&metadata.len().to_le_bytes());
+                reply.extend_from_slice(&metadata.len().to_le_bytes());
+                reply.extend_from_slice(&attributes(&metadata).to_le_bytes());
+                reply.extend_from_slice(&[0; 4]);
+                reply.extend_from_slice(&file_id.to_le_bytes());
+                reply.extend_from_slice(&file_id.to_le_bytes());
+                reply.extend_from_slice(&[0; 9]);
+            }
+            command::CLOSE => {
+                let open = connection.opens.remove(&file_id(8)).unwrap();
+                if open.delete && open.directory {
+                    fs.remove_dir(&open.path).map_err(error_status)?;
+                } else if open.delete {
+                    fs.remove_file(&open.path).map_err(error_status)?;
+                }
+                reply.extend_from_slice(&60u16.to_le_bytes());
+                reply.extend_from_slice(&[0; 58]);
+            }
+            command::READ => {
+                let (len, offset) = (le32(body, 4), le64(body, 8));
+                let open = &connection.opens[&file_id(16)];
+                let mut file = fs.open_file(&open.path).map_err(error_status)?;
+                file.seek(SeekFrom::Start(offset)).unwrap();
+                let mut data = Vec::new();
+                file.take(len as u64).read_to_end(&mut data).unwrap();
+                if data.is_empty() && len > 0 {
+                    return Err(status::END_OF_FILE);
+                }
+
+                reply.extend_from_slice(&17u16.to_le_bytes());
+                reply.extend_from_slice(&[(HEADER_SIZE + 16) as u8, 0]);
+                reply.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                reply.extend_from_slice(&[0; 8]);
+                reply.extend(data);
+            }
+            command::WRITE => {
+                let (len, offset) = (le32(body, 4), le64(body, 8));
+                let open = &connection.opens[&file_id(16)];
+                let options = OpenOptions {
+                    write: true,
+                    ..OpenOptions::default()
+                };
+                let mut file = fs
+                    .open_file_options(&open.path, &options)
+                    .map_err(error_status)?;
+                file.seek(SeekFrom::Start(offset)).unwrap();
+                file.write_all(buffer(le16(body, 2) as usize, len as usize))
+                    .unwrap();
+
+                reply.extend_from_slice(&17u16.to_le_bytes());
+                reply.extend_from_slice(&[0; 2]);
+                reply.extend_from_slice(&len.to_le_bytes());
+                reply.extend_from_slice(&[0; 9]);
+            }
+            command::QUERY_DIRECTORY => {
+                let open = connection.opens.get_mut(&file_id(8)).unwrap();
+                if open.listed && body[3] & 0x1 == 0 {
+                    return Err(status::NO_MORE_FILES);
+                }
+                open.listed = true;
+
+                let mut listing = Vec::new();
+                let entries = read_directory(fs, &open.path);
+                for (index, (path, metadata)) in entries.iter().enumerate() {
+                    let name = utf16(path.rsplit('/').next().unwrap());
+                    let mut entry = Vec::new();
+                    let len = (64 + name.len()).next_multiple_of(8);
+                    let next = if index + 1 == entries.len() { 0 } else { len };
+                    entry.extend_from_slice(&(next as u32).to_le_bytes());
+                    entry.extend_from_slice(&[0; 4]);
+                    for _ in 0..4 {
+                        entry.extend_from_slice(&filetime_now().to_le_bytes());
+                    }
+                    entry.extend_from_slice(&metadata.len().to_le_bytes());
+                    entry.extend_from_slice(&metadata.len().to_le_bytes());
+                    entry.extend_from_slice(&attributes(metadata).to_le_bytes());
+                    entry.extend_from_slice(&(name.len() as u32).to_le_bytes());
+                    entry.extend(name);
+                    entry.resize(len, 0);
+                    listing.extend(entry);
+                }
+                if listing.is_empty() {
+                    return Err(status::NO_MORE_FILES);
+                }
+
+                reply.extend_from_slice(&9u16.to_le_bytes());
+                reply.extend_from_slice(&((HEADER_SIZE + 8) as u16).to_le_bytes());
+                reply.extend_from_slice(&(listing.len() as u32).to_le_bytes());
+                reply.extend(listing);
+            }
+            command::QUERY_INFO => {
+                let open = &connection.opens[&file_id(24)];
+                let mut info = Vec::new();
+                match (body[2], body[3]) {
+                    (INFO_FILE, FILE_NETWORK_OPEN_INFORMATION) => {
+                        let metadata = fs.metadata(&open.path).map_err(error_status)?;
+                        for _ in 0..4 {
+                            info.extend_from_slice(&filetime_now().to_le_bytes());
+                        }
+                        info.extend_from_slice(&metadata.len().to_le_bytes());
+                        info.extend_from_slice(&metadata.len().to_le_bytes());
+                        info.extend_from_slice(&attributes(&metadata).to_le_bytes());
+                        info.extend_from_slice(&[0; 4]);
+                    }
+                    (_, FILE_FS_FULL_SIZE_INFORMATION) => {
+                        // 100 units in total, 40 free and 30 available, of 8 sectors of 512 bytes
+                        for units in [100u64, 30, 40] {
+                            info.extend_from_slice(&units.to_le_bytes());
+                        }
+                        info.extend_from_slice(&8u32.to_le_bytes());
+                        info.extend_from_slice(&512u32.to_le_bytes());
+                    }
+                    _ => return Err(status::NOT_SUPPORTED),
+                }
+
+                reply.extend_from_slice(&9u16.to_le_bytes());
+                reply.extend_from_slice(&((HEADER_SIZE + 8) as u16).to_le_bytes());
+                reply.extend_from_slice(&(info.len() as u32).to_le_bytes());
+                reply.extend(info);
+            }
+            command::SET_INFO => {
+                let info = buffer(le16(body, 8) as usize, le32(body, 4) as usize);
+                let open = connection.opens.get_mut(&file_id(16)).unwrap();
+                match body[3] {
+                    FILE_DISPOSITION_INFORMATION => {
+                        // like Windows, directories that aren't empty can't be marked for deletion
+                        if open.directory && !read_directory(fs, &open.path).is_empty() {
+                            return Err(status::DIRECTORY_NOT_EMPTY);
+                        }
+                        open.delete = info[0] != 0;
+                    }
+                    FILE_RENAME_INFORMATION => {
+                        let name = string(&info[20..20 + le32(info, 16) as usize]);
+                        let to = resolve(*tree_id, &name, false)?;
+                        fs.rename(&open.path, &to).map_err(error_status)?;
+                        open.path = to;
+                    }
+                    FILE_END_OF_FILE_INFORMATION => {
+                        util::set_len(fs, &open.path, le64(info, 0)).map_err(error_status)?;
+                    }
+                    _ => return Err(status::NOT_SUPPORTED),
+                }
+                reply.extend_from_slice(&2u16.to_le_bytes());
+            }
+            command::IOCTL => {
+                if *tree_id != IPC_TREE || le32(body, 4) != FSCTL_DFS_GET_REFERRALS {
+                    return Err(status::NOT_SUPPORTED);
+                }
+                let input = buffer(le32(body, 24) as usize, le32(body, 28) as usize);
+                let path = string(&input[2..input.len() - 2]);
+                let names: Vec<_> = path.split('\\').filter(|name| !name.is_empty()).collect();
+                if names.len() < 3 || names[1] != "dfs" || !names[2].eq_ignore_ascii_case("link") {
+                    return Err(status::OBJECT_PATH_NOT_FOUND);
+                }
+
+                // a single version 3 referral of the link to the data share
+                let link = utf16(&format!("\\{}", names[..3].join("\\")));
+                let target = utf16("\\127.0.0.1\\data\\sub");
+                let mut output = Vec::new();
+                output.extend_from_slice(&(link.len() as u16).to_le_bytes());
+                output.extend_from_slice(&1u16.to_le_bytes());
+                output.extend_from_slice(&0x2u32.to_le_bytes());
+                output.extend_from_slice(&3u16.to_le_bytes());
+                output.extend_from_slice(&34u16.to_le_bytes());
+                output.extend_from_slice(&[0; 4]);
+                output.extend_from_slice(&300u32.to_le_bytes());
+                let alt_offset = 34 + link.len() + 2;
+                for offset in [34, alt_offset, alt_offset + link.len() + 2] {
+                    output.extend_from_slice(&(offset as u16).to_le_bytes());
+                }
+                output.extend_from_slice(&[0; 16]);
+                for name in [&link, &link, &target] {
+                    output.extend_from_slice(name);
+                    output.extend_from_slice(&[0; 2]);
+                }
+
+                reply.extend_from_slice(&49u16.to_le_bytes());
+                reply.extend_from_slice(&[0; 2]);
+                reply.extend_from_slice(&FSCTL_DFS_GET_REFERRALS.to_le_bytes());
+                reply.extend_from_slice(&[0xff; 16]);
+                reply.extend_from_slice(&[0; 8]);
+                reply.extend_from_slice(&((HEADER_SIZE + 48) as u32).to_le_bytes());
+                reply.extend_from_slice(&(output.len() as u32).to_le_bytes());
+                reply.extend_from_slice(&[0; 8]);
+                reply.extend(output);
+            }
+            _ => return Err(status::NOT_SUPPORTED),
+        }
+
+        Ok((status::SUCCESS, reply))
+    }
+
+    /// Returns a memory filesystem with the directories of the shares, and the filesystem connected to `share`.
+    fn connect(share: &str) -> (Arc<MemoryFS>, SmbFS) {
+        let fs = Arc::new(MemoryFS::default());
+        for dir in ["data", "data/sub", "dfs", "dfs/link"] {
+            fs.create_dir(dir).unwrap();
+        }
+        let addr = serve(fs.clone());
+
+        let credentials = Credentials::new(USER, PASSWORD).domain("WORKGROUP");
+        (fs, SmbFS::connect(&addr, share, credentials).unwrap())
+    }
+
+    #[test]
+    fn read_write() {
+        let (_, fs) = connect("data");
+        fs.create_dir("dir").unwrap();
+        fs.create_dir("dir/a").unwrap();
+        write!(fs.create_file("dir/file").unwrap(), "hello").unwrap();
+        assert_eq!(
+            fs.create_dir("dir").err().unwrap().kind(),
+            ErrorKind::AlreadyExists
+        );
+
+        let mut file = fs
+            .open_file_options("dir/file", &OpenOptions::default().append(true))
+            .unwrap();
+        write!(file, " world").unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 11);
+        drop(file);
+
+        let mut file = fs.open_file("dir/file").unwrap();
+        file.seek(SeekFrom::Start(6)).unwrap();
+        assert_eq!(file.read_into_string().unwrap(), "world");
+        drop(file);
+
+        // reads and writes larger than the server allows are split
+        let large: Vec<_> = (0..10_000).map(|n| n as u8).collect();
+        fs.create_file("dir/large")
+            .unwrap()
+            .write_all(&large)
+            .unwrap();
+        assert_eq!(
+            fs.open_file("dir/large").unwrap().read_into_vec().unwrap(),
+            large
+        );
+
+        let dir = read_directory(&fs, "dir");
+        itertools::assert_equal(dir.keys(), vec!["dir/a", "dir/file", "dir/large"]);
+        assert!(dir["dir/a"].is_directory());
+        assert_eq!(dir["dir/file"].len(), 11);
+        assert!(dir["dir/file"].modified.is_some());
+
+        fs.rename("dir/file", "dir/a/moved").unwrap();
+        assert_eq!(fs.metadata("dir/a/moved").unwrap().len(), 11);
+        assert_eq!(
+            fs.open_file("dir/file").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            fs.open_file("dir/a").err().unwrap().kind(),
+            ErrorKind::IsADirectory
+        );
+        assert_eq!(
+            fs.remove_dir("dir/a").err().unwrap().kind(),
+            ErrorKind::DirectoryNotEmpty
+        );
+        fs.remove_file("dir/a/moved").unwrap();
+        fs.remove_dir("dir/a").unwrap();
+        itertools::assert_equal(read_directory(&fs, "dir").keys(), vec!["dir/large"]);
+
+        let stats = fs.stats().unwrap();
+        assert_eq!(stats.total_space, 100 * 4096);
+        assert_eq!(stats.available_space, 30 * 4096);
+    }
+
+    #[test]
+    fn dfs() {
+        let (memory_fs, fs) = connect("dfs");
+        write!(fs.create_file("local").unwrap(), "local").unwrap();
+        write!(fs.create_file("link/file").unwrap(), "referred").unwrap();
+
+        // the link is referred to the data share
+        assert_eq!(
+            memory_fs
+                .open_file("data/sub/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "referred"
+        );
+        assert_eq!(fs.metadata("link/file").unwrap().len(), 8);
+        itertools::assert_equal(read_directory(&fs, "link").keys(), vec!["link/file"]);
+        itertools::assert_equal(read_directory(&fs, "").keys(), vec!["link", "local"]);
+        fs.rename("link/file", "link/renamed").unwrap();
+        assert!(memory_fs.exists("data/sub/renamed").unwrap());
+        assert_eq!(
+            fs.rename("local", "link/local").err().unwrap().kind(),
+            ErrorKind::CrossesDevices
+        );
+    }
+
+    #[test]
+    fn bad_credentials() {
+        let addr = serve(Arc::new(MemoryFS::default()));
+        let credentials = Credentials::new(USER, "wrong");
+        assert_eq!(
+            SmbFS::connect(&addr, "data", credentials)
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn open_options() {
+        let (_, fs) = connect("data");
+        check_open_options(&fs, "sub/options");
+    }
+
+    #[test]
+    fn set_len() {
+        let (_, fs) = connect("data");
+        check_set_len(&fs, "sub/set_len");
+    }
+}
@@ -0,0 +1,955 @@
+use crate::smb_fs::auth::{authenticate_token, negotiate_token, utf16, Credentials};
+use aes::Aes128;
+use cmac::{Cmac, Mac};
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+
+/// The size of the header of every message.
+pub(crate) const HEADER_SIZE: usize = 64;
+/// The most bytes read or written by a single request, which costs a single credit.
+pub(crate) const MAX_IO_SIZE: u32 = 64 * 1024;
+/// The credits the client asks the server to keep it topped up to.
+const CREDIT_TARGET: u32 = 32;
+/// The file ID that refers to the handle opened by an earlier request of the same compound.
+pub(crate) const RELATED_FILE_ID: [u8; 16] = [0xff; 16];
+
+/// The commands of requests.
+pub(crate) mod command {
+    pub(crate) const NEGOTIATE: u16 = 0x0;
+    pub(crate) const SESSION_SETUP: u16 = 0x1;
+    pub(crate) const LOGOFF: u16 = 0x2;
+    pub(crate) const TREE_CONNECT: u16 = 0x3;
+    pub(crate) const CREATE: u16 = 0x5;
+    pub(crate) const CLOSE: u16 = 0x6;
+    pub(crate) const READ: u16 = 0x8;
+    pub(crate) const WRITE: u16 = 0x9;
+    pub(crate) const IOCTL: u16 = 0xb;
+    pub(crate) const QUERY_DIRECTORY: u16 = 0xe;
+    pub(crate) const QUERY_INFO: u16 = 0x10;
+    pub(crate) const SET_INFO: u16 = 0x11;
+}
+
+/// The status codes the client acts on.
+pub(crate) mod status {
+    pub(crate) const SUCCESS: u32 = 0;
+    pub(crate) const PENDING: u32 = 0x0000_0103;
+    pub(crate) const NO_MORE_FILES: u32 = 0x8000_0006;
+    pub(crate) const INVALID_PARAMETER: u32 = 0xc000_000d;
+    pub(crate) const NO_SUCH_FILE: u32 = 0xc000_000f;
+    pub(crate) const END_OF_FILE: u32 = 0xc000_0011;
+    pub(crate) const MORE_PROCESSING_REQUIRED: u32 = 0xc000_0016;
+    pub(crate) const ACCESS_DENIED: u32 = 0xc000_0022;
+    pub(crate) const OBJECT_NAME_NOT_FOUND: u32 = 0xc000_0034;
+    pub(crate) const OBJECT_NAME_COLLISION: u32 = 0xc000_0035;
+    pub(crate) const OBJECT_PATH_NOT_FOUND: u32 = 0xc000_003a;
+    pub(crate) const SHARING_VIOLATION: u32 = 0xc000_0043;
+    pub(crate) const QUOTA_EXCEEDED: u32 = 0xc000_0044;
+    pub(crate) const LOGON_FAILURE: u32 = 0xc000_006d;
+    pub(crate) const DISK_FULL: u32 = 0xc000_007f;
+    pub(crate) const MEDIA_WRITE_PROTECTED: u32 = 0xc000_00a2;
+    pub(crate) const FILE_IS_A_DIRECTORY: u32 = 0xc000_00ba;
+    pub(crate) const NOT_SUPPORTED: u32 = 0xc000_00bb;
+    pub(crate) const BAD_NETWORK_NAME: u32 = 0xc000_00cc;
+    pub(crate) const DIRECTORY_NOT_EMPTY: u32 = 0xc000_0101;
+    pub(crate) const NOT_A_DIRECTORY: u32 = 0xc000_0103;
+    pub(crate) const PATH_NOT_COVERED: u32 = 0xc000_0257;
+}
+
+/// The flags of the header of a message.
+pub(crate) mod flags {
+    pub(crate) const SERVER_TO_REDIR: u32 = 0x1;
+    pub(crate) const ASYNC_COMMAND: u32 = 0x2;
+    pub(crate) const RELATED_OPERATIONS: u32 = 0x4;
+    pub(crate) const SIGNED: u32 = 0x8;
+    pub(crate) const DFS_OPERATIONS: u32 = 0x1000_0000;
+}
+
+/// The dialects offered, from SMB 2.0.2 to SMB 3.0.2.
+pub(crate) const DIALECTS: [u16; 4] = [0x0202, 0x0210, 0x0300, 0x0302];
+/// The session flags of guest and anonymous sessions, which aren't signed.
+const SESSION_FLAG_GUEST_OR_NULL: u16 = 0x1 | 0x2;
+
+/// How messages are signed.
+#[derive(Clone)]
+pub(crate) enum Signing {
+    /// HMAC-SHA256, used by SMB 2.
+    HmacSha256([u8; 16]),
+    /// AES-128-CMAC, used by SMB 3.
+    AesCmac([u8; 16]),
+}
+
+impl Signing {
+    /// Returns how messages are signed with `session_key` in `dialect`.
+    ///
+    /// # Arguments
+    /// `dialect`: The negotiated dialect.  
+    /// `session_key`: The key of the session.  
+    pub(crate) fn new(dialect: u16, session_key: [u8; 16]) -> Self {
+        if dialect < 0x0300 {
+            return Self::HmacSha256(session_key);
+        }
+
+        // the signing key is derived with the SP800-108 counter-mode KDF
+        let input = [
+            &1u32.to_be_bytes()[..],
+            b"SMB2AESCMAC\0",
+            &[0],
+            b"SmbSign\0",
+            &128u32.to_be_bytes(),
+        ]
+        .concat();
+        let key = hmac_sha256::HMAC::mac(input, session_key);
+        Self::AesCmac(key[..16].try_into().unwrap())
+    }
+
+    /// Returns the signature of `message`, whose signature field must be zeroed.
+    ///
+    /// # Arguments
+    /// `message`: The message.  
+    pub(crate) fn sign(&self, message: &[u8]) -> [u8; 16] {
+        match self {
+            Self::HmacSha256(key) => hmac_sha256::HMAC::mac(message, key)[..16]
+                .try_into()
+                .unwrap(),
+            Self::AesCmac(key) => {
+                let mut mac = <Cmac<Aes128> as Mac>::new_from_slice(key).unwrap();
+                mac.update(message);
+                mac.finalize().into_bytes().into()
+            }
+        }
+    }
+
+    /// Signs `message` in place, setting its signed flag.
+    ///
+    /// # Arguments
+    /// `message`: The message, without its transport header.  
+    pub(crate) fn sign_message(&self, message: &mut [u8]) {
+        let header_flags = u32::from_le_bytes(message[16..20].try_into().unwrap());
+        message[16..20].copy_from_slice(&(header_flags | flags::SIGNED).to_le_bytes());
+        message[48..64].fill(0);
+        let signature = self.sign(message);
+        message[48..64].copy_from_slice(&signature);
+    }
+
+    /// Returns true if the signature of `message` is valid.
+    ///
+    /// # Arguments
+    /// `message`: The message, without its transport header.  
+    pub(crate) fn verify(&self, message: &[u8]) -> bool {
+        let mut unsigned = message.to_vec();
+        unsigned[48..64].fill(0);
+        self.sign(&unsigned) == message[48..64]
+    }
+}
+
+/// A request, which may be sent on its own or compounded with others.
+pub(crate) struct Request {
+    pub(crate) command: u16,
+    /// The tree the request is for, or zero.
+    pub(crate) tree_id: u32,
+    /// Extra flags of the header.
+    pub(crate) header_flags: u32,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Request {
+    /// Creates a request without extra flags.
+    ///
+    /// # Arguments
+    /// `command`: The command of the request.  
+    /// `tree_id`: The tree the request is for, or zero.  
+    /// `body`: The body of the request.  
+    pub(crate) fn new(command: u16, tree_id: u32, body: Vec<u8>) -> Self {
+        Self {
+            command,
+            tree_id,
+            header_flags: 0,
+            body,
+        }
+    }
+}
+
+/// A reply to a request.
+pub(crate) struct Reply {
+    pub(crate) status: u32,
+    /// The whole message, which offsets in the body are relative to.
+    pub(crate) message: Vec<u8>,
+}
+
+impl Reply {
+    /// Returns the body of the reply, after its header.
+    pub(crate) fn body(&self) -> &[u8] {
+        &self.message[HEADER_SIZE..]
+    }
+
+    /// Returns the bytes at `offset` in the body.
+    ///
+    /// # Arguments
+    /// `offset`: The offset in the body.  
+    /// `len`: The number of bytes.  
+    pub(crate) fn bytes(&self, offset: usize, len: usize) -> io::Result<&[u8]> {
+        self.body().get(offset..offset + len).ok_or_else(malformed)
+    }
+
+    pub(crate) fn u16(&self, offset: usize) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(
+            self.bytes(offset, 2)?.try_into().unwrap(),
+        ))
+    }
+
+    pub(crate) fn u32(&self, offset: usize) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(
+            self.bytes(offset, 4)?.try_into().unwrap(),
+        ))
+    }
+
+    pub(crate) fn u64(&self, offset: usize) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(
+            self.bytes(offset, 8)?.try_into().unwrap(),
+        ))
+    }
+
+    /// Returns a buffer the body points to with a 16-bit offset from the start of the message and a length.
+    ///
+    /// # Arguments
+    /// `offset`: The offset of the buffer's offset in the body.  
+    /// `len`: The length of the buffer.  
+    pub(crate) fn buffer(&self, offset: usize, len: usize) -> io::Result<&[u8]> {
+        let start = self.u16(offset)? as usize;
+        self.message.get(start..start + len).ok_or_else(malformed)
+    }
+
+    /// Returns the tree ID of the reply.
+    pub(crate) fn tree_id(&self) -> u32 {
+        u32::from_le_bytes(self.message[36..40].try_into().unwrap())
+    }
+
+    /// Returns the session ID of the reply.
+    pub(crate) fn session_id(&self) -> u64 {
+        u64::from_le_bytes(self.message[40..48].try_into().unwrap())
+    }
+}
+
+/// An authenticated session with a server.
+pub(crate) struct Session {
+    stream: TcpStream,
+    dialect: u16,
+    next_message_id: u64,
+    session_id: u64,
+    signing: Option<Signing>,
+    /// The number of requests the server has granted credits for.
+    credits: u32,
+    /// The most bytes a single read, write or transaction may transfer.
+    pub(crate) max_io_size: u32,
+}
+
+impl Session {
+    /// Connects to a server, negotiates a dialect and authenticates.
+    ///
+    /// # Arguments
+    /// `addr`: The address of the server.  
+    /// `credentials`: The credentials to authenticate with.  
+    pub(crate) fn connect(addr: &str, credentials: &Credentials) -> crate::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let mut session = Self {
+            stream,
+            dialect: 0,
+            next_message_id: 0,
+            session_id: 0,
+            signing: None,
+            // every connection starts with a single credit, for the negotiation
+            credits: 1,
+            max_io_size: MAX_IO_SIZE,
+        };
+
+        // offer signing, and the dialects up to SMB 3.0.2
+        let mut negotiate = Vec::new();
+        negotiate.extend_from_slice(&36u16.to_le_bytes());
+        negotiate.extend_from_slice(&(DIALECTS.len() as u16).to_le_bytes());
+        negotiate.extend_from_slice(&1u16.to_le_bytes());
+        negotiate.extend_from_slice(&[0; 2 + 4]);
+        let mut client_guid = [0; 16];
+        getrandom::getrandom(&mut client_guid)?;
+        negotiate.extend_from_slice(&client_guid);
+        negotiate.extend_from_slice(&[0; 8]);
+        for dialect in DIALECTS {
+            negotiate.extend_from_slice(&dialect.to_le_bytes());
+        }
+        let reply = session.call(Request::new(command::NEGOTIATE, 0, negotiate))?;
+        session.dialect = reply.u16(4)?;
+        if !DIALECTS.contains(&session.dialect) {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "The server doesn't speak SMB 2 or 3",
+            ));
+        }
+        let max_io_size = reply.u32(28)?.min(reply.u32(32)?).min(reply.u32(36)?);
+        session.max_io_size = max_io_size.clamp(512, MAX_IO_SIZE);
+
+        session.authenticate(credentials)?;
+        Ok(session)
+    }
+
+    /// Sets up the session with NTLM authentication, wrapped in SPNEGO.
+    ///
+    /// # Arguments
+    /// `credentials`: The credentials to authenticate with.  
+    fn authenticate(&mut self, credentials: &Credentials) -> crate::Result<()> {
+        let reply = self.raw_call(Request::new(
+            command::SESSION_SETUP,
+            0,
+            session_setup(&negotiate_token()),
+        ))?;
+        if reply.status != status::MORE_PROCESSING_REQUIRED {
+            return Err(status_error(reply.status));
+        }
+        self.session_id = reply.session_id();
+
+        let mut client_challenge = [0; 8];
+        getrandom::getrandom(&mut client_challenge)?;
+        let server_token = reply.buffer(4, reply.u16(6)? as usize)?;
+        let (token, session_key) = authenticate_token(credentials, server_token, client_challenge)?;
+
+        // the final reply is signed with the new key in SMB 3, so it's checked once the key is known
+        let reply = self.raw_call(Request::new(
+            command::SESSION_SETUP,
+            0,
+            session_setup(&token),
+        ))?;
+        if reply.status != status::SUCCESS {
+            return Err(status_error(reply.status));
+        }
+        if reply.u16(2)? & SESSION_FLAG_GUEST_OR_NULL == 0 && !credentials.is_anonymous() {
+            let signing = Signing::new(self.dialect, session_key);
+            if self.dialect >= 0x0300 && !signing.verify(&reply.message) {
+                return Err(bad_signature());
+            }
+            self.signing = Some(signing);
+        }
+
+        Ok(())
+    }
+
+    /// Sends a request and waits for its reply, failing unless it succeeded.
+    ///
+    /// # Arguments
+    /// `request`: The request.  
+    pub(crate) fn call(&mut self, request: Request) -> crate::Result<Reply> {
+        let reply = self.raw_call(request)?;
+        match reply.status {
+            status::SUCCESS => Ok(reply),
+            status => Err(status_error(status)),
+        }
+    }
+
+    /// Sends a request and waits for its reply, whatever its status.
+    ///
+    /// # Arguments
+    /// `request`: The request.  
+    pub(crate) fn raw_call(&mut self, request: Request) -> crate::Result<Reply> {
+        Ok(self.compound(vec![request])?.remove(0))
+    }
+
+    /// Sends requests in a single compound and waits for their replies, whatever their statuses. Every request after
+    /// the first is related to the one before it, so it can refer to a handle the compound opened with
+    /// [`RELATED_FILE_ID`]. Returns the replies in the order of the requests.
+    ///
+    /// # Arguments
+    /// `requests`: The requests.  
+    pub(crate) fn compound(&mut self, requests: Vec<Request>) -> crate::Result<Vec<Reply>> {
+        // every request costs a credit, and the server can't be sent more requests than it granted credits for
+        let count = requests.len() as u32;
+        if count > self.credits {
+            return Err(io::Error::other("The server granted too few credits"));
+        }
+        self.credits -= count;
+        // the first request asks for enough credits to top the balance back up, and the rest for their own
+        let wanted = CREDIT_TARGET.saturating_sub(self.credits).max(count);
+        // SMB 2.0.2 predates credit charges
+        let credit_charge = u16::from(self.dialect != 0x0202);
+        let first_message_id = self.next_message_id;
+        self.next_message_id += u64::from(count);
+
+        let mut frame = Vec::new();
+        for (index, request) in requests.iter().enumerate() {
+            let credit_request = if index == 0 { wanted - (count - 1) } else { 1 };
+            let mut header_flags = request.header_flags;
+            if index > 0 {
+                header_flags |= flags::RELATED_OPERATIONS;
+            }
+
+            let mut message = Vec::with_capacity(HEADER_SIZE + request.body.len());
+            message.extend_from_slice(b"\xfeSMB");
+            message.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+            message.extend_from_slice(&credit_charge.to_le_bytes());
+            message.extend_from_slice(&[0; 4]);
+            message.extend_from_slice(&request.command.to_le_bytes());
+            message
+                .extend_from_slice(&(credit_request.min(u32::from(u16::MAX)) as u16).to_le_bytes());
+            message.extend_from_slice(&header_flags.to_le_bytes());
+            message.extend_from_slice(&[0; 4]);
+            message.extend_from_slice(&(first_message_id + index as u64).to_le_bytes());
+            message.extend_from_slice(&[0; 4]);
+            message.extend_from_slice(&request.tree_id.to_le_bytes());
+            message.extend_from_slice(&self.session_id.to_le_bytes());
+            message.extend_from_slice(&[0; 16]);
+            message.extend_from_slice(&request.body);
+            // compounded messages start on 8-byte boundaries, and each points to the next
+            if index + 1 < requests.len() {
+                message.resize(message.len().next_multiple_of(8), 0);
+                let next_command = message.len() as u32;
+                message[20..24].copy_from_slice(&next_command.to_le_bytes());
+            }
+            if let Some(signing) = self.signing_of(request.command) {
+                signing.sign_message(&mut message);
+            }
+            frame.extend(message);
+        }
+        write_frame(&mut self.stream, &frame)?;
+
+        let mut replies: Vec<Option<Reply>> = requests.iter().map(|_| None).collect();
+        while replies.iter().any(Option::is_none) {
+            let frame = read_frame(&mut self.stream)?;
+            let mut rest = &frame[..];
+            while !rest.is_empty() {
+                if rest.len() < HEADER_SIZE || &rest[..4] != b"\xfeSMB" {
+                    return Err(malformed());
+                }
+                let len = match u32::from_le_bytes(rest[20..24].try_into().unwrap()) as usize {
+                    0 => rest.len(),
+                    next if (HEADER_SIZE..=rest.len()).contains(&next) => next,
+                    _ => return Err(malformed()),
+                };
+                let (message, next) = rest.split_at(len);
+                rest = next;
+
+                let reply = Reply {
+                    status: u32::from_le_bytes(message[8..12].try_into().unwrap()),
+                    message: message.to_vec(),
+                };
+                let reply_flags = u32::from_le_bytes(message[16..20].try_into().unwrap());
+                if reply_flags & flags::SERVER_TO_REDIR == 0 {
+                    return Err(malformed());
+                }
+                let credit_response = u16::from_le_bytes(message[14..16].try_into().unwrap());
+                self.credits = self.credits.saturating_add(credit_response.into());
+
+                // the server may acknowledge a slow request before replying to it for real
+                if reply_flags & flags::ASYNC_COMMAND != 0 && reply.status == status::PENDING {
+                    continue;
+                }
+                let message_id = u64::from_le_bytes(message[24..32].try_into().unwrap());
+                let index = message_id
+                    .checked_sub(first_message_id)
+                    .filter(|index| *index < u64::from(count))
+                    .ok_or_else(malformed)? as usize;
+                if replies[index].is_some() {
+                    return Err(malformed());
+                }
+                if let Some(signing) = self.signing_of(requests[index].command) {
+                    if reply_flags & flags::SIGNED == 0 || !signing.verify(&reply.message) {
+                        return Err(bad_signature());
+                    }
+                }
+                replies[index] = Some(reply);
+            }
+        }
+
+        Ok(replies.into_iter().flatten().collect())
+    }
+
+    /// Returns how the messages of a command are signed, if they are. Session setups are signed by their own rules.
+    ///
+    /// # Arguments
+    /// `command`: The command.  
+    fn signing_of(&self, command: u16) -> Option<&Signing> {
+        self.signing
+            .as_ref()
+            .filter(|_| command != command::SESSION_SETUP)
+    }
+
+    /// Connects to a share. Returns the tree ID and true if the share is in a DFS namespace.
+    ///
+    /// # Arguments
+    /// `host`: The name of the server, as it's known to the server.  
+    /// `share`: The name of the share.  
+    pub(crate) fn tree_connect(&mut self, host: &str, share: &str) -> crate::Result<(u32, bool)> {
+        let path = utf16(&format!("\\\\{host}\\{share}"));
+        let mut tree_connect = Vec::new();
+        tree_connect.extend_from_slice(&9u16.to_le_bytes());
+        tree_connect.extend_from_slice(&[0; 2]);
+        tree_connect.extend_from_slice(&((HEADER_SIZE + 8) as u16).to_le_bytes());
+        tree_connect.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        tree_connect.extend(path);
+
+        let reply = self.call(Request::new(command::TREE_CONNECT, 0, tree_connect))?;
+        // shares in a namespace are flagged as DFS, and so are namespace roots by their capability
+        let dfs = reply.u32(4)? & 0x1 != 0 || reply.u32(8)? & 0x8 != 0;
+        Ok((reply.tree_id(), dfs))
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.call(Request::new(command::LOGOFF, 0, vec![4, 0, 0, 0]));
+    }
+}
+
+/// Returns the body of a session setup request carrying `token`.
+///
+/// # Arguments
+/// `token`: The security token.  
+fn session_setup(token: &[u8]) -> Vec<u8> {
+    let mut setup = Vec::new();
+    setup.extend_from_slice(&25u16.to_le_bytes());
+    // no flags, signing enabled, no capabilities and no channel
+    setup.extend_from_slice(&[0, 1]);
+    setup.extend_from_slice(&[0; 8]);
+    setup.extend_from_slice(&((HEADER_SIZE + 24) as u16).to_le_bytes());
+    setup.extend_from_slice(&(token.len() as u16).to_le_bytes());
+    setup.extend_from_slice(&[0; 8]);
+    setup.extend_from_slice(token);
+    setup
+}
+
+/// Writes a message with its Direct TCP transport header.
+///
+/// # Arguments
+/// `writer`: The connection.  
+/// `message`: The message.  
+pub(crate) fn write_frame<W: Write>(writer: &mut W, message: &[u8]) -> io::Result<()> {
+    let len = (message.len() as u32).to_be_bytes();
+    writer.write_all(&[&[0], &len[1..], message].concat())?;
+    writer.flush()
+}
+
+/// Reads a message, stripping its Direct TCP transport header.
+///
+/// # Arguments
+/// `reader`: The connection.  
+pub(crate) fn read_frame<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut header = [0; 4];
+    reader.read_exact(&mut header)?;
+    let len = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+    let mut message = vec![0; len as usize];
+    reader.read_exact(&mut message)?;
+    Ok(message)
+}
+
+/// The error of a request for a path in another part of a DFS namespace, which has to be referred.
+#[derive(Debug)]
+pub(crate) struct PathNotCovered;
+
+impl std::fmt::Display for PathNotCovered {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("The path is in another part of the DFS namespace")
+    }
+}
+
+impl std::error::Error for PathNotCovered {}
+
+/// Converts an error status to an IO error.
+///
+/// # Arguments
+/// `status`: The status.  
+pub(crate) fn status_error(status: u32) -> io::Error {
+    let kind = match status {
+        status::NO_SUCH_FILE
+        | status::OBJECT_NAME_NOT_FOUND
+        | status::OBJECT_PATH_NOT_FOUND
+        | status::BAD_NETWORK_NAME => ErrorKind::NotFound,
+        status::OBJECT_NAME_COLLISION => ErrorKind::AlreadyExists,
+        status::ACCESS_DENIED | status::LOGON_FAILURE => ErrorKind::PermissionDenied,
+        status::DIRECTORY_NOT_EMPTY => ErrorKind::DirectoryNotEmpty,
+        status::FILE_IS_A_DIRECTORY => ErrorKind::IsADirectory,
+        status::NOT_A_DIRECTORY => ErrorKind::NotADirectory,
+        status::SHARING_VIOLATION => ErrorKind::ResourceBusy,
+        status::DISK_FULL => ErrorKind::StorageFull,
+        status::QUOTA_EXCEEDED => ErrorKind::QuotaExceeded,
+        status::MEDIA_WRITE_PROTECTED => ErrorKind::ReadOnlyFilesystem,
+        status::NOT_SUPPORTED => ErrorKind::Unsupported,
+        status::INVALID_PARAMETER => ErrorKind::InvalidInput,
+        status::PATH_NOT_COVERED => return io::Error::other(PathNotCovered),
+        _ => ErrorKind::Other,
+    };
+
+    io::Error::new(kind, format!("SMB error {status:#010x}"))
+}
+
+/// Returns the error of a malformed message.
+pub(crate) fn malformed() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "Malformed SMB message")
+}
+
+/// Returns the error of a message with a bad signature.
+fn bad_signature() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "Bad SMB message signature")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::smb_fs::session::{
+        command, flags, read_frame, status, status_error, write_frame, PathNotCovered, Request,
+        Session, Signing, HEADER_SIZE, MAX_IO_SIZE, RELATED_FILE_ID,
+    };
+    use std::io::ErrorKind;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::thread::JoinHandle;
+
+    const SESSION_KEY: [u8; 16] = [7; 16];
+
+    fn hex(value: &str) -> Vec<u8> {
+        (0..value.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn le32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn le64(data: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// Returns a signed SMB 3.0.2 session with `credits`, connected to a server that runs `script`. Returns the
+    /// session and the server's thread, which returns what the script returns.
+    fn loopback<T: Send + 'static>(
+        credits: u32,
+        script: impl FnOnce(TcpStream) -> T + Send + 'static,
+    ) -> (Session, JoinHandle<T>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let server = listener.accept().unwrap().0;
+        let session = Session {
+            stream,
+            dialect: 0x0302,
+            next_message_id: 10,
+            session_id: 0x1234,
+            signing: Some(Signing::new(0x0302, SESSION_KEY)),
+            credits,
+            max_io_size: MAX_IO_SIZE,
+        };
+        (session, thread::spawn(move || script(server)))
+    }
+
+    /// Splits a compound frame into its messages.
+    fn split(frame: &[u8]) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        let mut rest = frame;
+        while !rest.is_empty() {
+            let next = le32(rest, 20) as usize;
+            let (message, remaining) = rest.split_at(if next == 0 { rest.len() } else { next });
+            messages.push(message.to_vec());
+            rest = remaining;
+        }
+        messages
+    }
+
+    /// Returns the reply to `request`, granting `credits`, with a body of `body`.
+    fn reply(request: &[u8], status: u32, credits: u16, body: &[u8]) -> Vec<u8> {
+        let mut reply = request[..HEADER_SIZE].to_vec();
+        reply[8..12].copy_from_slice(&status.to_le_bytes());
+        reply[14..16].copy_from_slice(&credits.to_le_bytes());
+        reply[16..20].copy_from_slice(&flags::SERVER_TO_REDIR.to_le_bytes());
+        reply[20..24].fill(0);
+        reply.extend_from_slice(body);
+        reply
+    }
+
+    /// Chains replies into a compound, each signed by `signing`.
+    fn compound(replies: Vec<Vec<u8>>, signing: &Signing) -> Vec<u8> {
+        let count = replies.len();
+        let mut frame = Vec::new();
+        for (index, mut reply) in replies.into_iter().enumerate() {
+            if index + 1 < count {
+                reply.resize(reply.len().next_multiple_of(8), 0);
+                let next = reply.len() as u32;
+                reply[20..24].copy_from_slice(&next.to_le_bytes());
+            }
+            signing.sign_message(&mut reply);
+            frame.extend(reply);
+        }
+        frame
+    }
+
+    /// Returns a request for an open handle.
+    fn request(command: u16) -> Request {
+        let mut body = vec![command as u8; 3];
+        body.extend_from_slice(&RELATED_FILE_ID);
+        Request::new(command, 5, body)
+    }
+
+    #[test]
+    fn signing() {
+        // the SMB 3 signing key is derived with the KDF of SP800-108
+        let key = hex("7CD451825D0450D235424E44BA6E78CC").try_into().unwrap();
+        match Signing::new(0x0300, key) {
+            Signing::AesCmac(key) => {
+                assert_eq!(key.to_vec(), hex("0b7e9c5cac36c0f6ea9ab275298cedce"))
+            }
+            Signing::HmacSha256(_) => panic!("SMB 3 signs with AES-CMAC"),
+        }
+
+        // AES-CMAC, from RFC 4493
+        let cmac = Signing::AesCmac(hex("2b7e151628aed2a6abf7158809cf4f3c").try_into().unwrap());
+        assert_eq!(
+            cmac.sign(&[]).to_vec(),
+            hex("bb1d6929e95937287fa37d129b756746")
+        );
+        assert_eq!(
+            cmac.sign(&hex("6bc1bee22e409f96e93d7e117393172a")).to_vec(),
+            hex("070a16b46b4d4144f79bdd9dd04a287c")
+        );
+
+        // SMB 2 signs with HMAC-SHA256 of the session key, truncated
+        let hmac = Signing::new(0x0210, [0x0b; 16]);
+        assert_eq!(
+            hmac.sign(b"Hi There").to_vec(),
+            hex("492ce020fe2534a5789dc3848806c78f")
+        );
+
+        for signing in [
+            Signing::new(0x0202, SESSION_KEY),
+            Signing::new(0x0302, SESSION_KEY),
+        ] {
+            let mut message = reply(&[0; HEADER_SIZE], status::SUCCESS, 1, b"body");
+            signing.sign_message(&mut message);
+            assert_ne!(le32(&message, 16) & flags::SIGNED, 0);
+            assert!(signing.verify(&message));
+            assert!(!Signing::new(0x0302, [8; 16]).verify(&message));
+
+            let mut tampered = message.clone();
+            *tampered.last_mut().unwrap() ^= 1;
+            assert!(!signing.verify(&tampered));
+        }
+    }
+
+    #[test]
+    fn frames() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, &[1; 0x1_0203]).unwrap();
+        assert_eq!(frame[..4], [0, 1, 2, 3]);
+        assert_eq!(read_frame(&mut &frame[..]).unwrap(), [1; 0x1_0203]);
+        assert_eq!(
+            read_frame(&mut &frame[..100]).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn compound_requests() {
+        let (mut session, server) = loopback(8, |mut stream| {
+            let signing = Signing::new(0x0302, SESSION_KEY);
+            let frame = read_frame(&mut stream).unwrap();
+            let requests = split(&frame);
+            assert_eq!(requests.len(), 3);
+
+            let mut offset = 0;
+            for (index, request) in requests.iter().enumerate() {
+                // every message starts on an 8-byte boundary, and is signed
+                assert_eq!(offset % 8, 0);
+                offset += request.len();
+                assert!(signing.verify(request));
+                assert_eq!(le64(request, 24), 10 + index as u64);
+                assert_eq!(le32(request, 36), 5);
+                assert_eq!(le64(request, 40), 0x1234);
+                assert_eq!(request[6..8], 1u16.to_le_bytes());
+                let related = le32(request, 16) & flags::RELATED_OPERATIONS != 0;
+                assert_eq!(related, index > 0);
+            }
+            assert_ne!(le32(&requests[1], 20), 0);
+            assert_eq!(le32(&requests[2], 20), 0);
+            assert_eq!(
+                requests[1][HEADER_SIZE + 3..HEADER_SIZE + 19],
+                RELATED_FILE_ID
+            );
+
+            // 5 credits remain, and the compound asks for enough to get back to 32
+            let credit_requests: Vec<_> = requests
+                .iter()
+                .map(|request| u16::from_le_bytes([request[14], request[15]]))
+                .collect();
+            assert_eq!(credit_requests, [25, 1, 1]);
+
+            // the second request goes async before the compound completes
+            let mut interim = reply(
+                &requests[1],
+                status::PENDING,
+                0,
+                &[9, 0, 0, 0, 0, 0, 0, 0, 0],
+            );
+            interim[16..20]
+                .copy_from_slice(&(flags::SERVER_TO_REDIR | flags::ASYNC_COMMAND).to_le_bytes());
+            write_frame(&mut stream, &compound(vec![interim], &signing)).unwrap();
+            let replies = requests
+                .iter()
+                .zip([status::SUCCESS, status::NO_MORE_FILES, status::SUCCESS])
+                .map(|(request, status)| {
+                    let credits = u16::from_le_bytes([request[14], request[15]]);
+                    reply(request, status, credits, &request[HEADER_SIZE..])
+                })
+                .collect();
+            write_frame(&mut stream, &compound(replies, &signing)).unwrap();
+        });
+
+        let replies = session
+            .compound(vec![
+                request(command::CREATE),
+                request(command::QUERY_DIRECTORY),
+                request(command::CLOSE),
+            ])
+            .unwrap();
+        server.join().unwrap();
+
+        let statuses: Vec<_> = replies.iter().map(|reply| reply.status).collect();
+        assert_eq!(
+            statuses,
+            [status::SUCCESS, status::NO_MORE_FILES, status::SUCCESS]
+        );
+        for (reply, command) in
+            replies
+                .iter()
+                .zip([command::CREATE, command::QUERY_DIRECTORY, command::CLOSE])
+        {
+            assert_eq!(reply.body()[0], command as u8);
+        }
+        assert_eq!(session.credits, 32);
+        assert_eq!(session.next_message_id, 13);
+    }
+
+    #[test]
+    fn compound_bad_signature() {
+        let (mut session, server) = loopback(8, |mut stream| {
+            let requests = split(&read_frame(&mut stream).unwrap());
+            let replies = requests
+                .iter()
+                .map(|request| reply(request, status::SUCCESS, 1, &[]))
+                .collect();
+            // the replies are signed with another key
+            let frame = compound(replies, &Signing::new(0x0302, [8; 16]));
+            write_frame(&mut stream, &frame).unwrap();
+        });
+
+        let err = session
+            .compound(vec![request(command::CREATE), request(command::CLOSE)])
+            .err()
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn unsigned_reply() {
+        let (mut session, server) = loopback(8, |mut stream| {
+            let request = read_frame(&mut stream).unwrap();
+            write_frame(&mut stream, &reply(&request, status::SUCCESS, 1, &[])).unwrap();
+        });
+
+        let err = session.call(request(command::CLOSE)).err().unwrap();
+        server.join().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn unexpected_message_id() {
+        let (mut session, server) = loopback(8, |mut stream| {
+            let signing = Signing::new(0x0302, SESSION_KEY);
+            let request = read_frame(&mut stream).unwrap();
+            let mut reply = reply(&request, status::SUCCESS, 1, &[]);
+            reply[24..32].copy_from_slice(&99u64.to_le_bytes());
+            write_frame(&mut stream, &compound(vec![reply], &signing)).unwrap();
+        });
+
+        let err = session.call(request(command::CLOSE)).err().unwrap();
+        server.join().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn bad_next_command() {
+        let (mut session, server) = loopback(8, |mut stream| {
+            let signing = Signing::new(0x0302, SESSION_KEY);
+            let request = read_frame(&mut stream).unwrap();
+            let mut frame = compound(vec![reply(&request, status::SUCCESS, 1, &[])], &signing);
+            // the next reply would start past the end of the frame
+            frame[20..24].copy_from_slice(&1000u32.to_le_bytes());
+            write_frame(&mut stream, &frame).unwrap();
+        });
+
+        let err = session.call(request(command::CLOSE)).err().unwrap();
+        server.join().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn credits() {
+        let (mut session, server) = loopback(1, |mut stream| {
+            let signing = Signing::new(0x0302, SESSION_KEY);
+            let mut frames = 0;
+            while let Ok(frame) = read_frame(&mut stream) {
+                frames += 1;
+                // no credits are granted back
+                let replies = split(&frame)
+                    .iter()
+                    .map(|request| reply(request, status::SUCCESS, 0, &[]))
+                    .collect();
+                write_frame(&mut stream, &compound(replies, &signing)).unwrap();
+            }
+            frames
+        });
+
+        session.call(request(command::CLOSE)).unwrap();
+        assert_eq!(session.credits, 0);
+        // requests without credits aren't sent
+        let err = session.call(request(command::CLOSE)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Other);
+        drop(session);
+        assert_eq!(server.join().unwrap(), 1);
+
+        // nor are compounds with more requests than credits
+        let (mut session, server) = loopback(2, |mut stream| read_frame(&mut stream).is_ok());
+        let requests = vec![
+            request(command::CREATE),
+            request(command::QUERY_INFO),
+            request(command::CLOSE),
+        ];
+        assert!(session.compound(requests).is_err());
+        assert_eq!(session.credits, 2);
+        // the logoff is sent, since it fits
+        drop(session);
+        assert!(server.join().unwrap());
+    }
+
+    #[test]
+    fn status_errors() {
+        for (status, kind) in [
+            (status::NO_SUCH_FILE, ErrorKind::NotFound),
+            (status::OBJECT_NAME_NOT_FOUND, ErrorKind::NotFound),
+            (status::OBJECT_PATH_NOT_FOUND, ErrorKind::NotFound),
+            (status::BAD_NETWORK_NAME, ErrorKind::NotFound),
+            (status::OBJECT_NAME_COLLISION, ErrorKind::AlreadyExists),
+            (status::ACCESS_DENIED, ErrorKind::PermissionDenied),
+            (status::LOGON_FAILURE, ErrorKind::PermissionDenied),
+            (status::DIRECTORY_NOT_EMPTY, ErrorKind::DirectoryNotEmpty),
+            (status::FILE_IS_A_DIRECTORY, ErrorKind::IsADirectory),
+            (status::NOT_A_DIRECTORY, ErrorKind::NotADirectory),
+            (status::SHARING_VIOLATION, ErrorKind::ResourceBusy),
+            (status::DISK_FULL, ErrorKind::StorageFull),
+            (status::QUOTA_EXCEEDED, ErrorKind::QuotaExceeded),
+            (status::MEDIA_WRITE_PROTECTED, ErrorKind::ReadOnlyFilesystem),
+            (status::NOT_SUPPORTED, ErrorKind::Unsupported),
+            (status::INVALID_PARAMETER, ErrorKind::InvalidInput),
+            (status::END_OF_FILE, ErrorKind::Other),
+        ] {
+            assert_eq!(status_error(status).kind(), kind, "{status:#x}");
+        }
+
+        let err = status_error(status::PATH_NOT_COVERED);
+        assert!(err.get_ref().unwrap().is::<PathNotCovered>());
+    }
+}
@@ -0,0 +1,241 @@
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::{invalid_path, not_a_directory};
+use crate::FileSystem;
+use itertools::Itertools;
+use std::path::{Path, PathBuf};
+
+/// A filesystem that exposes a directory of another filesystem as its root, so that a subtree can be handed out
+/// without exposing its siblings.
+///
+/// Paths are normalized before they're resolved against the directory, so backtracking can't escape it. Confinement
+/// is lexical: symbolic links within the directory of a physical filesystem are followed by the inner filesystem.
+pub struct SubdirFS<FS> {
+    inner: FS,
+    root: PathBuf,
+}
+
+impl<FS: FileSystem> SubdirFS<FS> {
+    /// Creates a new filesystem rooted at a directory of another filesystem.
+    ///
+    /// # Arguments
+    /// `inner`: The filesystem containing the directory.  
+    /// `root`: The path of the directory, which must exist.  
+    pub fn new(inner: FS, root: &str) -> crate::Result<Self> {
+        if !inner.metadata(root)?.is_directory() {
+            return Err(not_a_directory());
+        }
+
+        Ok(Self {
+            inner,
+            root: normalize_and_relativize(root),
+        })
+    }
+
+    /// Returns the filesystem containing the directory.
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Returns the normalized path of the directory within the inner filesystem.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolves a path against the directory, returning its path in the inner filesystem.
+    ///
+    /// # Arguments
+    /// `path`: The path to resolve.  
+    fn inner_path(&self, path: &str) -> crate::Result<String> {
+        self.root
+            .join(normalize_and_relativize(path))
+            .to_str()
+            .map(str::to_owned)
+            .ok_or_else(invalid_path)
+    }
+
+    /// Resolves a path against the directory for an operation that can't be applied to the directory itself.
+    ///
+    /// # Arguments
+    /// `path`: The path to resolve.  
+    fn inner_child_path(&self, path: &str) -> crate::Result<String> {
+        if normalize_and_relativize(path).as_os_str().is_empty() {
+            return Err(invalid_path());
+        }
+
+        self.inner_path(path)
+    }
+}
+
+impl<FS: FileSystem> FileSystem for SubdirFS<FS> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.inner.create_dir(&self.inner_path(path)?)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.inner.metadata(&self.inner_path(path)?)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        self.inner
+            .open_file_options(&self.inner_path(path)?, options)
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        // entries are listed by their full path, which must not leak the directory
+        let directory = normalize_and_relativize(path);
+        Ok(Box::new(
+            self.inner
+                .read_dir(&self.inner_path(path)?)?
+                .map_ok(move |entry| DirEntry {
+                    path: directory.join(entry.file_name()),
+                    ..entry
+                }),
+        ))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.inner.remove_dir(&self.inner_child_path(path)?)
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.inner.remove_file(&self.inner_child_path(path)?)
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        self.inner.create_dir_with(&self.inner_path(path)?, mode)
+    }
+
+    fn create_dir_all(&self, path: &str) -> crate::Result<()> {
+        self.inner.create_dir_all(&self.inner_path(path)?)
+    }
+
+    fn create_file(&self, path: &str) -> crate::Result<Box<dyn File>> {
+        self.inner.create_file(&self.inner_path(path)?)
+    }
+
+    fn exists(&self, path: &str) -> crate::Result<bool> {
+        self.inner.exists(&self.inner_path(path)?)
+    }
+
+    fn open_file(&self, path: &str) -> crate::Result<Box<dyn File>> {
+        self.inner.open_file(&self.inner_path(path)?)
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> crate::Result<u64> {
+        self.inner
+            .copy_file(&self.inner_path(from)?, &self.inner_path(to)?)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        self.inner
+            .rename(&self.inner_child_path(from)?, &self.inner_child_path(to)?)
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        self.inner.stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    use crate::subdir_fs::SubdirFS;
+    use crate::util::test::{check_open_options, read_directory};
+    use crate::FileSystem;
+    use std::io::{ErrorKind, Write};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn subdir_fs() -> (Arc<MemoryFS>, SubdirFS<Arc<MemoryFS>>) {
+        let inner = Arc::new(MemoryFS::default());
+        inner.create_dir_all("lent/dir").unwrap();
+        inner.create_dir_all("secret").unwrap();
+        write!(inner.create_file("lent/dir/file").unwrap(), "lent").unwrap();
+        write!(inner.create_file("secret/file").unwrap(), "secret").unwrap();
+
+        (inner.clone(), SubdirFS::new(inner, "/lent/").unwrap())
+    }
+
+    #[test]
+    fn scoped() {
+        let (inner, subdir_fs) = subdir_fs();
+
+        assert_eq!(subdir_fs.root(), Path::new("lent"));
+        itertools::assert_equal(read_directory(&subdir_fs, "").keys(), vec!["dir"]);
+        itertools::assert_equal(
+            subdir_fs
+                .walk_dir("")
+                .unwrap()
+                .map(|entry| entry.unwrap().path),
+            vec![Path::new("dir"), Path::new("dir/file")],
+        );
+        assert_eq!(
+            subdir_fs
+                .open_file("dir/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "lent"
+        );
+
+        subdir_fs.create_dir_all("new/nested").unwrap();
+        write!(subdir_fs.create_file("new/file").unwrap(), "new").unwrap();
+        subdir_fs.rename("new/file", "dir/renamed").unwrap();
+        assert!(inner.exists("lent/new/nested").unwrap());
+        assert!(inner.exists("lent/dir/renamed").unwrap());
+    }
+
+    #[test]
+    fn open_options() {
+        let (inner, subdir_fs) = subdir_fs();
+        check_open_options(&subdir_fs, "dir/options");
+        assert!(!inner.exists("options").unwrap());
+    }
+
+    #[test]
+    fn confined() {
+        let (inner, subdir_fs) = subdir_fs();
+
+        for path in [
+            "../secret/file",
+            "/../../secret/file",
+            "dir/../../secret/file",
+            "..\\secret\\file",
+        ] {
+            assert!(!subdir_fs.exists(path).unwrap(), "{path}");
+            assert!(subdir_fs.open_file(path).is_err(), "{path}");
+        }
+        itertools::assert_equal(read_directory(&subdir_fs, "..").keys(), vec!["dir"]);
+
+        // writes land within the directory too
+        write!(subdir_fs.create_file("../escaped").unwrap(), "no").unwrap();
+        assert!(inner.exists("lent/escaped").unwrap());
+        assert!(!inner.exists("escaped").unwrap());
+
+        // the directory itself can't be removed or moved
+        assert!(subdir_fs.remove_dir("dir/..").is_err());
+        assert!(subdir_fs.rename("", "elsewhere").is_err());
+        assert!(inner.exists("lent/dir").unwrap());
+    }
+
+    #[test]
+    fn missing_root() {
+        let (inner, _) = subdir_fs();
+
+        assert_eq!(
+            SubdirFS::new(inner.clone(), "missing")
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            SubdirFS::new(inner, "secret/file").err().unwrap().kind(),
+            ErrorKind::NotADirectory
+        );
+    }
+}
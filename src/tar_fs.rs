@@ -1,17 +1,54 @@
-use crate::file::{DirEntry, File, Metadata, OpenOptions};
-use crate::memory_fs::MemoryFS;
-use crate::util::{not_supported, parent_iter};
+use crate::file::{DirEntry, File, FileType, Metadata, OpenOptions};
+use crate::tree::{normalize_and_relativize, Directory, Entry, FilesystemTree};
+use crate::util::{
+    already_exists, invalid_input, invalid_path, is_a_directory, not_a_directory, not_found,
+    not_supported, now, read_only,
+};
 use crate::FileSystem;
-use std::io::{Read, Write};
-use std::path::Path;
-use tar::{Archive, EntryType};
+use itertools::Itertools;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io;
+use std::io::{BufRead, BufReader, Cursor, Empty, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tar::{Archive, Builder, Entries, EntryType, GnuExtSparseHeader, Header};
 
-/// A filesystem mounted on a Tarball archive, backed by a Memory FS.
-/// Because the FS is backed by memory, all files are immediately loaded
-/// into memory, so `filtered` variants of constructors should be used
-/// to avoid large files that may not need to be accessed.
-pub struct TarFS {
-    memory_fs: MemoryFS,
+/// The maximum number of symbolic links followed while resolving a path.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// A filesystem mounted on a Tarball archive.
+///
+/// Filesystems created with `new` read the archive as a stream, so all files are immediately
+/// loaded into memory and `filtered` variants of constructors should be used to avoid large files
+/// that may not need to be accessed. Filesystems created with `new_indexed` only record where
+/// each file is located within a seekable archive, and read files on demand.
+///
+/// Filesystems created with `new_appendable` can also be written to. Files opened for writing are
+/// buffered in memory and appended to the archive as new entries when they are flushed, so the
+/// archive is only ever extended and every flushed version of a file remains in the archive.
+///
+/// Hard links share the contents of their targets. Symbolic links are followed when they are
+/// accessed, so they appear as their targets. GNU sparse files have their full logical length, and
+/// their holes read as zeros.
+pub struct TarFS<R = Empty> {
+    tree: Arc<FilesystemTree<TarEntry>>,
+    /// The metadata of directories with their own entries in the archive, by normalized path.
+    directories: HashMap<PathBuf, Metadata>,
+    archive: Arc<Mutex<R>>,
+    appender: Option<Arc<Appender<R>>>,
+}
+
+/// Writes bytes to an archive at the given offset.
+type WriteAt<R> = fn(&mut R, u64, &[u8]) -> io::Result<()>;
+
+/// The state required to append entries to an archive.
+struct Appender<R> {
+    /// The offset of the end of the last entry, where the next entry is written.
+    end: Mutex<u64>,
+    write_at: WriteAt<R>,
 }
 
 /// Filters over filesystems.
@@ -29,15 +66,150 @@ impl<F: Fn(&Path) -> bool> FileSystemFilter for F {
     }
 }
 
+/// A non-directory entry within a tarball.
+#[derive(Clone)]
+enum TarEntry {
+    /// A regular file and its metadata.
+    File(TarFile, Metadata),
+    /// A symbolic link to the normalized path of its target.
+    Symlink(PathBuf),
+}
+
+/// The contents of a file within a tarball.
+#[derive(Clone)]
+enum TarFile {
+    /// The contents were loaded into memory when the archive was mounted.
+    Loaded(Arc<[u8]>),
+    /// The contents are read from the archive on demand.
+    Indexed { offset: u64, len: u64 },
+    /// The contents are read from the archive on demand, with holes between the stored runs of
+    /// data. The map of runs is read from the file's headers when it is opened.
+    Sparse { header_position: u64, len: u64 },
+}
+
+impl TarFile {
+    /// Returns the length of the file, in bytes.
+    fn len(&self) -> u64 {
+        match self {
+            TarFile::Loaded(contents) => contents.len() as u64,
+            TarFile::Indexed { len, .. } | TarFile::Sparse { len, .. } => *len,
+        }
+    }
+}
+
+/// A run of data stored within a sparse file.
+#[derive(Copy, Clone)]
+struct SparseBlock {
+    /// The offset of the run within the file.
+    offset: u64,
+    /// The position of the run within the archive.
+    position: u64,
+    len: u64,
+}
+
+/// Where the last entry of an archive ends.
+enum ArchiveEnd {
+    /// The offset of the end of the last entry.
+    Offset(u64),
+    /// The last entry is a sparse file, whose data follows an unknown number of extension headers.
+    Sparse { header_position: u64, size: u64 },
+}
+
+/// The compression format of a tarball.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Compression {
+    None,
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// Detects the compression format from the first bytes of the archive.
+    ///
+    /// # Arguments
+    /// `magic`: The first bytes of the archive.  
+    fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(&[0x1F, 0x8B]) {
+            Self::Gzip
+        } else if magic.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+            Self::Xz
+        } else if magic.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            Self::Zstd
+        } else if magic.starts_with(b"BZh") {
+            Self::Bzip2
+        } else {
+            Self::None
+        }
+    }
+
+    /// Wraps `archive` in a decoder for its compression format, detected from its magic bytes.
+    ///
+    /// # Arguments
+    /// `archive`: The possibly compressed archive.  
+    pub(crate) fn decompress<'a, R: Read + 'a>(archive: R) -> crate::Result<Box<dyn Read + 'a>> {
+        let mut archive = BufReader::new(archive);
+
+        match Self::detect(archive.fill_buf()?) {
+            Self::None => Ok(Box::new(archive)),
+            #[cfg(feature = "gzip")]
+            Self::Gzip => Ok(Box::new(flate2::bufread::MultiGzDecoder::new(archive))),
+            #[cfg(feature = "xz")]
+            Self::Xz => Ok(Box::new(xz::bufread::XzDecoder::new_multi_decoder(archive))),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(Box::new(zstd::stream::read::Decoder::with_buffer(archive)?)),
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2 => Ok(Box::new(bzip2::bufread::MultiBzDecoder::new(archive))),
+            #[allow(unreachable_patterns)]
+            compression => Err(io::Error::new(
+                ErrorKind::Unsupported,
+                format!("{compression:?} decompression is not enabled"),
+            )),
+        }
+    }
+}
+
+/// The result of looking up a path that may pass through symbolic links.
+enum Lookup<RV> {
+    /// The path was resolved to an entry.
+    Resolved(RV),
+    /// The path passes through a symbolic link, and resolves to the contained path instead.
+    Follow(PathBuf),
+}
+
 impl TarFS {
     /// Creates a new tar-backed filesystem.
     ///
     /// # Arguments
-    /// `archive`: The tarball archive itself.
+    /// `archive`: The tarball archive itself.  
     pub fn new<R: Read>(archive: R) -> crate::Result<Self> {
         Self::new_filtered(archive, |_: &_| true)
     }
 
+    /// Creates a new tar-backed filesystem, transparently decompressing gzip, xz, zstd and bzip2
+    /// archives. The compression format is detected from the archive's magic bytes, and each format
+    /// requires its respective feature to be enabled.
+    ///
+    /// # Arguments
+    /// `archive`: The possibly compressed tarball archive.  
+    pub fn open<R: Read>(archive: R) -> crate::Result<Self> {
+        Self::open_filtered(archive, |_: &_| true)
+    }
+
+    /// Creates a new tar-backed filesystem with filtered contents, transparently decompressing the
+    /// archive like `open`.
+    ///
+    /// # Arguments
+    /// `archive`: The possibly compressed tarball archive.  
+    /// `filter`: A filter that determines which entries are included in the filesystem.  
+    pub fn open_filtered<R: Read, F: FileSystemFilter>(
+        archive: R,
+        filter: F,
+    ) -> crate::Result<Self> {
+        Self::new_filtered(Compression::decompress(archive)?, filter)
+    }
+
     /// Creates a new tar-backed filesystem with filtered contents.
     ///
     /// # Arguments
@@ -47,100 +219,886 @@ impl TarFS {
         archive: R,
         filter: F,
     ) -> crate::Result<Self> {
-        // iterate through each entry and build the memory FS
-        let archive = Archive::new(archive);
+        Self::new_multi_filtered([archive], filter)
+    }
 
-        Self::build_fs(archive, filter).map(|fs| Self { memory_fs: fs })
+    /// Creates a new tar-backed filesystem from a sequence of tarballs, such as the volumes of a
+    /// split archive or the layers of an incremental backup. The tarballs are treated as a single
+    /// archive, so entries in later tarballs take precedence over those in earlier ones.
+    ///
+    /// # Arguments
+    /// `archives`: The tarball archives, in order.  
+    pub fn new_multi<R: Read, I: IntoIterator<Item = R>>(archives: I) -> crate::Result<Self> {
+        Self::new_multi_filtered(archives, |_: &_| true)
     }
 
-    /// Builds the memory file system from the archive.
+    /// Creates a new tar-backed filesystem with filtered contents from a sequence of tarballs.
     ///
     /// # Arguments
-    /// `archive`: The archive itself.
-    fn build_fs<R: Read, F: FileSystemFilter>(
-        mut archive: Archive<R>,
+    /// `archives`: The tarball archives, in order.  
+    /// `filter`: A filter that determines which entries are included in the filesystem.  
+    pub fn new_multi_filtered<R: Read, I: IntoIterator<Item = R>, F: FileSystemFilter>(
+        archives: I,
         filter: F,
-    ) -> crate::Result<MemoryFS> {
-        let memory_fs = MemoryFS::default();
+    ) -> crate::Result<Self> {
+        let tree = FilesystemTree::default();
+        let mut directories = HashMap::new();
 
-        // iterate over the archive and read in any files that don't already exist
-        for entry in archive.entries()? {
-            let mut entry = entry?;
+        // read in every included file
+        for archive in archives {
+            let mut archive = Archive::new(archive);
+            Self::build_tree(
+                &tree,
+                &mut directories,
+                archive.entries()?,
+                &filter,
+                |entry| {
+                    let mut file_contents = Vec::with_capacity(entry.size() as usize);
+                    entry.read_to_end(&mut file_contents)?;
+                    Ok(TarFile::Loaded(file_contents.into()))
+                },
+            )?;
+        }
 
-            // ignore anything that isn't a regular folder
-            if entry.header().entry_type() != EntryType::Regular {
-                continue;
-            }
+        Ok(Self {
+            tree: Arc::new(tree),
+            directories,
+            archive: Arc::new(Mutex::new(io::empty())),
+            appender: None,
+        })
+    }
+}
+
+impl<R: Read + Seek> TarFS<R> {
+    /// Creates a new tar-backed filesystem from a seekable archive. Rather than loading every file
+    /// into memory, only the location of each file within the archive is recorded, and files are
+    /// read from the archive on demand.
+    ///
+    /// # Arguments
+    /// `archive`: The tarball archive itself.  
+    pub fn new_indexed(archive: R) -> crate::Result<Self> {
+        Self::new_indexed_filtered(archive, |_: &_| true)
+    }
+
+    /// Creates a new tar-backed filesystem with filtered contents from a seekable archive. Files
+    /// are read from the archive on demand.
+    ///
+    /// # Arguments
+    /// `archive`: The tarball archive itself.  
+    /// `filter`: A filter that determines which entries are included in the filesystem.  
+    pub fn new_indexed_filtered<F: FileSystemFilter>(archive: R, filter: F) -> crate::Result<Self> {
+        Self::index(archive, filter).map(|(fs, _)| fs)
+    }
+
+    /// Indexes a seekable archive, returning the filesystem along with the offset of the end of
+    /// the last entry.
+    ///
+    /// # Arguments
+    /// `archive`: The tarball archive itself.  
+    /// `filter`: A filter that determines which entries are included in the filesystem.  
+    fn index<F: FileSystemFilter>(archive: R, filter: F) -> crate::Result<(Self, u64)> {
+        let mut archive = Archive::new(archive);
+
+        // only record where each file is, skipping over the contents
+        let tree = FilesystemTree::default();
+        let mut directories = HashMap::new();
+        let end = Self::build_tree(
+            &tree,
+            &mut directories,
+            archive.entries_with_seek()?,
+            &filter,
+            |entry| {
+                Ok(if entry.header().entry_type().is_gnu_sparse() {
+                    TarFile::Sparse {
+                        header_position: entry.raw_header_position(),
+                        len: entry.size(),
+                    }
+                } else {
+                    TarFile::Indexed {
+                        offset: entry.raw_file_position(),
+                        len: entry.size(),
+                    }
+                })
+            },
+        )?;
+
+        let mut archive = archive.into_inner();
+        let end = match end {
+            ArchiveEnd::Offset(end) => end,
+            ArchiveEnd::Sparse {
+                header_position,
+                size,
+            } => read_sparse_map(&mut archive, header_position)?.1 + size,
+        };
+
+        Ok((
+            Self {
+                tree: Arc::new(tree),
+                directories,
+                archive: Arc::new(Mutex::new(archive)),
+                appender: None,
+            },
+            end,
+        ))
+    }
+}
+
+impl<R: Read + Write + Seek> TarFS<R> {
+    /// Creates a new tar-backed filesystem from an uncompressed archive that can be written to.
+    /// Files are read from the archive on demand like `new_indexed`, and files opened for writing
+    /// are appended to the end of the archive as new entries whenever they are flushed or closed.
+    /// An empty archive is treated as a new tarball.
+    ///
+    /// # Arguments
+    /// `archive`: The tarball archive itself.  
+    pub fn new_appendable(archive: R) -> crate::Result<Self> {
+        let (mut fs, end) = Self::index(archive, |_: &_| true)?;
+        fs.appender = Some(Arc::new(Appender {
+            end: Mutex::new(end),
+            write_at: |archive, offset, bytes| {
+                archive.seek(SeekFrom::Start(offset))?;
+                archive.write_all(bytes)?;
+                archive.flush()
+            },
+        }));
+
+        Ok(fs)
+    }
+}
+
+impl<R> TarFS<R> {
+    /// Adds the archive's entries to the file tree, returning where the last entry ends.
+    ///
+    /// # Arguments
+    /// `tree`: The file tree.  
+    /// `directories`: The metadata of the archive's directories.  
+    /// `entries`: The archive's entries.  
+    /// `filter`: A filter that determines which entries are included in the filesystem.  
+    /// `read_file`: Produces the contents of a regular file entry.  
+    fn build_tree<
+        T: Read,
+        F: FileSystemFilter,
+        C: FnMut(&mut tar::Entry<T>) -> io::Result<TarFile>,
+    >(
+        tree: &FilesystemTree<TarEntry>,
+        directories: &mut HashMap<PathBuf, Metadata>,
+        entries: Entries<T>,
+        filter: &F,
+        mut read_file: C,
+    ) -> crate::Result<ArchiveEnd> {
+        let mut end = ArchiveEnd::Offset(0);
 
+        for entry in entries {
+            let mut entry = entry?;
             let entry_path = entry.path()?.into_owned();
 
+            // entries are padded to the block size
+            let size = entry.header().entry_size()?.next_multiple_of(512);
+            end = if entry.header().entry_type().is_gnu_sparse() {
+                ArchiveEnd::Sparse {
+                    header_position: entry.raw_header_position(),
+                    size,
+                }
+            } else {
+                ArchiveEnd::Offset(entry.raw_file_position() + size)
+            };
+
             // ignore filtered files
             if !filter.should_include(&entry_path) {
                 continue;
             }
 
-            // recursively create parent directories
-            for parent_path in parent_iter(&entry_path).map(Path::to_string_lossy).rev() {
-                // only care about directories that exist
-                if memory_fs.exists(&parent_path)? {
+            let tar_entry = match entry.header().entry_type() {
+                EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => {
+                    let metadata =
+                        Self::header_metadata(entry.header(), Metadata::file(entry.size()))?;
+                    TarEntry::File(read_file(&mut entry)?, metadata)
+                }
+                EntryType::Link => {
+                    // hard links always refer to an earlier entry, which may have been filtered
+                    let target = entry.link_name()?.ok_or_else(invalid_path)?;
+                    match tree.with_entry(target, |entry| match entry {
+                        Err((tar_entry, remaining_path))
+                            if remaining_path.as_os_str().is_empty() =>
+                        {
+                            Ok(tar_entry.clone())
+                        }
+                        _ => Err(not_found()),
+                    }) {
+                        Ok(tar_entry) => tar_entry,
+                        Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                        Err(err) => return Err(err),
+                    }
+                }
+                EntryType::Symlink => {
+                    // relative targets are relative to the link's directory
+                    let target = entry.link_name()?.ok_or_else(invalid_path)?;
+                    let link_directory = entry_path.parent().unwrap_or(Path::new(""));
+                    TarEntry::Symlink(normalize_and_relativize(link_directory.join(target)))
+                }
+                EntryType::Directory => {
+                    Self::insert_directory(tree, &entry_path)?;
+                    directories.insert(
+                        normalize_and_relativize(&entry_path),
+                        Self::header_metadata(entry.header(), Metadata::directory())?,
+                    );
                     continue;
                 }
+                // ignore anything else
+                _ => continue,
+            };
+
+            Self::insert_entry(tree, &entry_path, tar_entry)?;
+            directories.remove(&normalize_and_relativize(&entry_path));
+        }
+
+        Ok(end)
+    }
+
+    /// Returns the metadata of a file or directory from its header.
+    ///
+    /// # Arguments
+    /// `header`: The header of the entry.  
+    /// `metadata`: The metadata of the entry's type.  
+    fn header_metadata(header: &Header, metadata: Metadata) -> crate::Result<Metadata> {
+        Ok(Metadata {
+            mode: Some(header.mode()?),
+            modified: Some(UNIX_EPOCH + Duration::from_secs(header.mtime()?)),
+            uid: header.uid().ok().and_then(|uid| uid.try_into().ok()),
+            gid: header.gid().ok().and_then(|gid| gid.try_into().ok()),
+            ..metadata
+        })
+    }
+
+    /// Inserts an entry into the tree, creating its parent directories. Entries replace any entry
+    /// already at the same path, so later entries in the archive take precedence.
+    ///
+    /// # Arguments
+    /// `tree`: The file tree.  
+    /// `path`: The path of the entry within the archive.  
+    /// `tar_entry`: The entry.  
+    fn insert_entry(
+        tree: &FilesystemTree<TarEntry>,
+        path: &Path,
+        tar_entry: TarEntry,
+    ) -> crate::Result<()> {
+        let normalized_path = normalize_and_relativize(path);
+        let parent_path = normalized_path.parent().ok_or_else(invalid_path)?;
+        let file_name = normalized_path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .ok_or_else(invalid_path)?;
+
+        tree.create_dir_all(parent_path, |dir| {
+            dir.insert(file_name.to_owned(), Entry::UserData(tar_entry));
+        })
+    }
+
+    /// Inserts a directory into the tree, creating its parent directories. Directories replace any
+    /// file already at the same path, but keep the contents of an existing directory.
+    ///
+    /// # Arguments
+    /// `tree`: The file tree.  
+    /// `path`: The path of the directory within the archive.  
+    fn insert_directory(tree: &FilesystemTree<TarEntry>, path: &Path) -> crate::Result<()> {
+        let normalized_path = normalize_and_relativize(path);
 
-                memory_fs.create_dir(&parent_path)?;
+        // the root directory always exists
+        let Some(parent_path) = normalized_path.parent() else {
+            return Ok(());
+        };
+        let file_name = normalized_path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .ok_or_else(invalid_path)?;
+
+        tree.create_dir_all(parent_path, |dir| {
+            let entry = dir
+                .entry(file_name.to_owned())
+                .or_insert_with(|| Entry::Directory(Default::default()));
+            if let Entry::UserData(_) = entry {
+                *entry = Entry::Directory(Default::default());
             }
+        })
+    }
 
-            // read the entire entry to a vec
-            let mut file_contents = Vec::with_capacity(entry.header().size()? as usize);
-            entry.read_to_end(&mut file_contents)?;
+    /// Calls `f` with the directory or file at `path`, following any symbolic links along the way.
+    /// Directories are passed along with their normalized path.
+    ///
+    /// # Arguments
+    /// `path`: The path to resolve.  
+    /// `f`: The function.  
+    fn with_resolved_entry<
+        RV,
+        F: FnOnce(
+            Result<(&mut Directory<TarEntry>, &Path), (&TarFile, &Metadata)>,
+        ) -> crate::Result<RV>,
+    >(
+        &self,
+        path: &Path,
+        f: F,
+    ) -> crate::Result<RV> {
+        let mut path = path.to_owned();
+        let mut f = Some(f);
 
-            // create the file and write all of the contents
-            let mut file = memory_fs.create_file(&format!("/{}", entry_path.to_string_lossy()))?;
-            file.write_all(&file_contents)?;
+        for _ in 0..=MAX_SYMLINK_HOPS {
+            let lookup = self.tree.with_entry(&path, |entry| {
+                // `f` is only taken when the lookup resolves, which ends the loop
+                let mut resolve = |entry| f.take().unwrap()(entry).map(Lookup::Resolved);
+                match entry {
+                    Ok(dir) => resolve(Ok((dir, &normalize_and_relativize(&path)))),
+                    Err((TarEntry::Symlink(target), remaining_path)) => {
+                        Ok(Lookup::Follow(target.join(remaining_path)))
+                    }
+                    Err((TarEntry::File(file, metadata), remaining_path))
+                        if remaining_path.as_os_str().is_empty() =>
+                    {
+                        resolve(Err((file, metadata)))
+                    }
+                    Err(_) => Err(not_a_directory()),
+                }
+            })?;
+
+            match lookup {
+                Lookup::Resolved(rv) => return Ok(rv),
+                Lookup::Follow(target) => path = target,
+            }
         }
 
-        Ok(memory_fs)
+        Err(invalid_input("Too many levels of symbolic links"))
+    }
+
+    /// Opens a file for writing. The file's contents are buffered by the handle and appended to the
+    /// archive when it is flushed.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file.  
+    /// `options`: The options the file is opened with.  
+    /// `appender`: The state used to append to the archive.  
+    fn open_append_handle(
+        &self,
+        path: &str,
+        options: &OpenOptions,
+        appender: Arc<Appender<R>>,
+    ) -> crate::Result<Box<dyn File>>
+    where
+        R: Read + Seek + 'static,
+    {
+        let path = normalize_and_relativize(path);
+        let existing = match self.with_resolved_entry(&path, |entry| {
+            Ok(entry
+                .err()
+                .map(|(file, metadata)| (file.clone(), metadata.clone())))
+        }) {
+            Ok(Some(_)) if options.create_new => return Err(already_exists()),
+            Ok(Some(existing)) => Some(existing),
+            // directories can't be written to
+            Ok(None) => return Err(not_found()),
+            Err(err)
+                if err.kind() == ErrorKind::NotFound && (options.create || options.create_new) =>
+            {
+                None
+            }
+            Err(err) => return Err(err),
+        };
+
+        // new and truncated files are appended even if they are never written to
+        let dirty = existing.is_none() || options.truncate;
+        let mut contents = Vec::new();
+        let mut mode = None;
+        if let Some((file, metadata)) = existing {
+            mode = metadata.mode;
+            if !options.truncate {
+                TarFileHandle::new(self.archive.clone(), file, metadata)?
+                    .read_to_end(&mut contents)?;
+            }
+        }
+
+        let mut contents = Cursor::new(contents);
+        if options.append {
+            contents.seek(SeekFrom::End(0))?;
+        }
+
+        Ok(Box::new(TarAppendHandle {
+            archive: self.archive.clone(),
+            tree: self.tree.clone(),
+            appender,
+            path,
+            contents,
+            mode: mode.unwrap_or(0o644),
+            dirty,
+        }))
+    }
+
+    /// Returns the metadata of the entry at `path`, following symbolic links.
+    ///
+    /// # Arguments
+    /// `path`: The path of the entry.  
+    fn resolved_metadata(&self, path: &Path) -> crate::Result<Metadata> {
+        self.with_resolved_entry(path, |entry| {
+            Ok(match entry {
+                Ok((_, path)) => self.directory_metadata(path),
+                Err((_, metadata)) => metadata.clone(),
+            })
+        })
+    }
+
+    /// Returns the metadata of the directory at the normalized `path`. Directories without their
+    /// own entry in the archive have default metadata.
+    ///
+    /// # Arguments
+    /// `path`: The normalized path of the directory.  
+    fn directory_metadata(&self, path: &Path) -> Metadata {
+        self.directories
+            .get(path)
+            .cloned()
+            .unwrap_or_else(Metadata::directory)
     }
 }
 
-impl FileSystem for TarFS {
+impl<R: Read + Seek + 'static> FileSystem for TarFS<R> {
     fn create_dir(&self, _path: &str) -> crate::Result<()> {
-        Err(not_supported())
+        Err(read_only())
     }
 
     fn metadata(&self, path: &str) -> crate::Result<Metadata> {
-        self.memory_fs.metadata(path)
+        self.resolved_metadata(Path::new(path))
     }
 
     fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
-        if options.write {
-            return Err(not_supported());
+        options.validate()?;
+        if options.writable() {
+            let appender = self.appender.clone().ok_or_else(read_only)?;
+            return self.open_append_handle(path, options, appender);
         }
 
-        self.memory_fs.open_file_options(path, options)
+        let (file, metadata) = self.with_resolved_entry(Path::new(path), |entry| {
+            entry
+                .err()
+                .map(|(file, metadata)| (file.clone(), metadata.clone()))
+                .ok_or_else(is_a_directory)
+        })?;
+
+        Ok(Box::new(TarFileHandle::new(
+            self.archive.clone(),
+            file,
+            metadata,
+        )?))
     }
 
     fn read_dir(
         &self,
         path: &str,
     ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
-        self.memory_fs.read_dir(path)
+        let directory = normalize_and_relativize(path);
+        let entries = self.with_resolved_entry(Path::new(path), |entry| {
+            let (dir, dir_path) = entry.map_err(|_| not_a_directory())?;
+            Ok(dir
+                .iter()
+                .map(|(name, entry)| {
+                    let metadata = match entry {
+                        Entry::Directory(_) => Ok(self.directory_metadata(&dir_path.join(name))),
+                        Entry::UserData(TarEntry::File(_, metadata)) => Ok(metadata.clone()),
+                        Entry::UserData(TarEntry::Symlink(target)) => Err(target.clone()),
+                    };
+                    (name.clone(), metadata)
+                })
+                .collect_vec())
+        })?;
+
+        // resolve symbolic links once the tree is no longer borrowed. links that can't be resolved
+        // are reported with an unknown type, and either way with their targets
+        Ok(Box::new(
+            entries
+                .into_iter()
+                .map(|(name, metadata)| {
+                    let metadata = metadata.unwrap_or_else(|target| Metadata {
+                        link_target: Some(target.clone()),
+                        ..self
+                            .resolved_metadata(&target)
+                            .unwrap_or(Metadata::new(FileType::Unknown, 0))
+                    });
+
+                    Ok(DirEntry {
+                        path: directory.join(name),
+                        metadata,
+                    })
+                })
+                .collect_vec()
+                .into_iter(),
+        ))
     }
 
     fn remove_dir(&self, _path: &str) -> crate::Result<()> {
-        Err(not_supported())
+        Err(read_only())
     }
 
     fn remove_file(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+}
+
+/// Writes the contents of the directory `root` within `fs` to `writer` as a tarball, recursively.
+/// Paths within the tarball are relative to `root`. Unless it is known, files are archived with
+/// mode `0o644`, directories with mode `0o755`, and modification times with the Unix epoch.
+///
+/// To compress the tarball, wrap `writer` in an encoder. The writer is returned once the tarball is
+/// complete, so that the encoder can be finished.
+///
+/// # Arguments
+/// `fs`: The filesystem to archive.  
+/// `root`: The directory within `fs` to archive.  
+/// `writer`: The writer that receives the tarball.  
+pub fn write_tar<FS: FileSystem + ?Sized, W: Write>(
+    fs: &FS,
+    root: &str,
+    writer: W,
+) -> crate::Result<W> {
+    let mut builder = Builder::new(writer);
+    append_directory(fs, &mut builder, Path::new(root), Path::new(""))?;
+    builder.into_inner()
+}
+
+/// Appends the contents of the directory `path` within `fs` to the tarball, recursively.
+///
+/// # Arguments
+/// `fs`: The filesystem to archive.  
+/// `builder`: The tarball builder.  
+/// `path`: The path to the directory within `fs`.  
+/// `archive_path`: The path to the directory within the tarball.  
+fn append_directory<FS: FileSystem + ?Sized, W: Write>(
+    fs: &FS,
+    builder: &mut Builder<W>,
+    path: &Path,
+    archive_path: &Path,
+) -> crate::Result<()> {
+    // archive entries in a stable order
+    let entries = fs
+        .read_dir(path.to_str().ok_or_else(invalid_path)?)?
+        .try_collect::<_, Vec<_>, _>()?
+        .into_iter()
+        .sorted_by(|a, b| a.path.cmp(&b.path));
+
+    for entry in entries {
+        let name = entry.path.file_name().ok_or_else(invalid_path)?;
+        let path = path.join(name);
+        let archive_path = archive_path.join(name);
+        let is_directory = entry.metadata.is_directory();
+
+        let mut header = Header::new_gnu();
+        header.set_mode(
+            entry
+                .metadata
+                .mode
+                .unwrap_or(if is_directory { 0o755 } else { 0o644 }),
+        );
+        header.set_mtime(
+            entry
+                .metadata
+                .modified
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |modified| modified.as_secs()),
+        );
+
+        match entry.metadata.file_type {
+            FileType::Directory => {
+                header.set_entry_type(EntryType::Directory);
+                header.set_size(0);
+                builder.append_data(&mut header, &archive_path, io::empty())?;
+
+                append_directory(fs, builder, &path, &archive_path)?;
+            }
+            FileType::File => {
+                let file = fs.open_file(path.to_str().ok_or_else(invalid_path)?)?;
+
+                // the file may have changed since the directory was read
+                let len = file.metadata()?.len();
+                header.set_entry_type(EntryType::Regular);
+                header.set_size(len);
+                builder.append_data(&mut header, &archive_path, file.take(len))?;
+            }
+            // there's nothing to archive
+            FileType::Unknown => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the map of runs stored within a sparse file from its headers, returning it along with the
+/// position of the file's data within the archive.
+///
+/// # Arguments
+/// `archive`: The tarball archive.  
+/// `header_position`: The position of the file's header within the archive.  
+fn read_sparse_map<R: Read + Seek>(
+    archive: &mut R,
+    header_position: u64,
+) -> crate::Result<(Vec<SparseBlock>, u64)> {
+    archive.seek(SeekFrom::Start(header_position))?;
+    let mut header = Header::new_gnu();
+    archive.read_exact(header.as_mut_bytes())?;
+    let header = header
+        .as_gnu()
+        .ok_or_else(|| invalid_input("Sparse file without a GNU header"))?;
+
+    // collect the runs from the header, then from any extension headers that follow it
+    let mut runs = header
+        .sparse
+        .iter()
+        .filter(|run| !run.is_empty())
+        .map(|run| Ok((run.offset()?, run.length()?)))
+        .collect::<crate::Result<Vec<_>>>()?;
+    let mut is_extended = header.is_extended();
+    while is_extended {
+        let mut extension = GnuExtSparseHeader::new();
+        archive.read_exact(extension.as_mut_bytes())?;
+        for run in extension.sparse.iter().filter(|run| !run.is_empty()) {
+            runs.push((run.offset()?, run.length()?));
+        }
+        is_extended = extension.is_extended();
+    }
+
+    // the runs are stored back to back after the headers
+    let data_position = archive.stream_position()?;
+    let mut position = data_position;
+    let blocks = runs
+        .into_iter()
+        .map(|(offset, len)| {
+            let block = SparseBlock {
+                offset,
+                position,
+                len,
+            };
+            position += len;
+            block
+        })
+        .collect();
+
+    Ok((blocks, data_position))
+}
+
+/// A read-only handle to a file within a tarball.
+struct TarFileHandle<R> {
+    archive: Arc<Mutex<R>>,
+    file: TarFile,
+    /// The runs of data stored within a sparse file.
+    blocks: Vec<SparseBlock>,
+    metadata: Metadata,
+    pos: u64,
+}
+
+impl<R: Read + Seek> TarFileHandle<R> {
+    /// Creates a new handle to a file, reading its map of runs if it is sparse.
+    ///
+    /// # Arguments
+    /// `archive`: The tarball archive.  
+    /// `file`: The file.  
+    /// `metadata`: The metadata of the file.  
+    fn new(archive: Arc<Mutex<R>>, file: TarFile, metadata: Metadata) -> crate::Result<Self> {
+        let blocks = match file {
+            TarFile::Sparse {
+                header_position, ..
+            } => read_sparse_map(&mut *archive.lock(), header_position)?.0,
+            _ => Vec::new(),
+        };
+
+        Ok(Self {
+            archive,
+            file,
+            blocks,
+            metadata,
+            pos: 0,
+        })
+    }
+}
+
+impl<R: Read + Seek> Read for TarFileHandle<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // never read past the end of the file
+        let remaining = self.file.len().saturating_sub(self.pos);
+        let buf_len = remaining.min(buf.len() as u64) as usize;
+        if buf_len == 0 {
+            return Ok(0);
+        }
+
+        let buf = &mut buf[..buf_len];
+        let n = match &self.file {
+            TarFile::Loaded(contents) => (&contents[self.pos as usize..]).read(buf)?,
+            TarFile::Indexed { offset, .. } => {
+                let mut archive = self.archive.lock();
+                archive.seek(SeekFrom::Start(offset + self.pos))?;
+                archive.read(buf)?
+            }
+            TarFile::Sparse { len, .. } => {
+                let pos = self.pos;
+                match self
+                    .blocks
+                    .iter()
+                    .find(|block| block.offset + block.len > pos)
+                {
+                    Some(block) if block.offset <= pos => {
+                        let remaining = (block.offset + block.len - pos).min(buf.len() as u64);
+                        let mut archive = self.archive.lock();
+                        archive.seek(SeekFrom::Start(block.position + pos - block.offset))?;
+                        archive.read(&mut buf[..remaining as usize])?
+                    }
+                    // fill the hole up to the next run
+                    next_block => {
+                        let hole_end = next_block.map_or(*len, |block| block.offset);
+                        let n = (hole_end - pos).min(buf.len() as u64) as usize;
+                        buf[..n].fill(0);
+                        n
+                    }
+                }
+            }
+        };
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl<R> Seek for TarFileHandle<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base_pos, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::Current(n) => (self.pos, n),
+            SeekFrom::End(n) => (self.file.len(), n),
+        };
+
+        if let Some(n) = base_pos.checked_add_signed(offset) {
+            self.pos = n;
+            Ok(n)
+        } else {
+            Err(invalid_input(
+                "Invalid seek to a negative or overflowing position",
+            ))
+        }
+    }
+}
+
+impl<R> Write for TarFileHandle<R> {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(not_supported())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
         Err(not_supported())
     }
 }
 
+impl<R: Read + Seek> File for TarFileHandle<R> {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(self.metadata.clone())
+    }
+}
+
+/// A handle to a file being written to an appendable tarball. The contents are buffered in memory,
+/// and appended to the archive as a new entry when the handle is flushed or dropped.
+struct TarAppendHandle<R> {
+    archive: Arc<Mutex<R>>,
+    tree: Arc<FilesystemTree<TarEntry>>,
+    appender: Arc<Appender<R>>,
+    path: PathBuf,
+    contents: Cursor<Vec<u8>>,
+    mode: u32,
+    dirty: bool,
+}
+
+impl<R> Read for TarAppendHandle<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.contents.read(buf)
+    }
+}
+
+impl<R> Seek for TarAppendHandle<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.contents.seek(pos)
+    }
+}
+
+impl<R> Write for TarAppendHandle<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.dirty = true;
+        self.contents.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let contents = self.contents.get_ref();
+        let len = contents.len() as u64;
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(len);
+        header.set_mode(self.mode);
+        header.set_mtime(
+            now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs()),
+        );
+
+        // the builder also writes any extension headers required by the path, followed by the
+        // end-of-archive marker. the marker is written past the entry, so the archive remains
+        // valid, and is overwritten by the next entry
+        let mut builder = Builder::new(Vec::new());
+        builder.append_data(&mut header, &self.path, contents.as_slice())?;
+        let entry = builder.into_inner()?;
+        let entry_len = entry.len() as u64 - 2 * 512;
+        let header_len = entry_len - len.next_multiple_of(512);
+
+        let mut archive = self.archive.lock();
+        let mut end = self.appender.end.lock();
+        (self.appender.write_at)(&mut archive, *end, &entry)?;
+
+        let file = TarFile::Indexed {
+            offset: *end + header_len,
+            len,
+        };
+        let metadata = TarFS::<R>::header_metadata(&header, Metadata::file(len))?;
+        TarFS::<R>::insert_entry(&self.tree, &self.path, TarEntry::File(file, metadata))?;
+        *end += entry_len;
+        self.dirty = false;
+
+        Ok(())
+    }
+}
+
+impl<R> File for TarAppendHandle<R> {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        Ok(Metadata {
+            mode: Some(self.mode),
+            ..Metadata::file(self.contents.get_ref().len() as u64)
+        })
+    }
+}
+
+impl<R> Drop for TarAppendHandle<R> {
+    fn drop(&mut self) {
+        // errors can't be reported from here, so callers wanting them should flush first
+        let _ = self.flush();
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::fs::File;
-    use std::io::Read;
+    use std::io;
+    use std::io::{Cursor, ErrorKind, Read, Write};
+    use std::time::{Duration, UNIX_EPOCH};
 
+    use crate::file::{FileType, Metadata, OpenOptions};
+    use crate::memory_fs::MemoryFS;
+    use crate::util::test::{check_open_options, read_directory};
     use crate::FileSystem;
+    use itertools::Itertools;
+    use tar::{Builder, EntryType, GnuExtSparseHeader, GnuSparseHeader, Header};
     use xz::read::XzDecoder;
 
     use super::TarFS;
@@ -200,4 +1158,521 @@ mod test {
 
         assert_eq!(file_contents, "it\n");
     }
+
+    #[test]
+    fn deep_fs_indexed() {
+        let archive = TarFS::new_indexed(Cursor::new(deep_fs_tar())).unwrap();
+
+        let folder = read_directory(&archive, "folder");
+        itertools::assert_equal(folder.keys(), vec!["folder/and", "folder/desc"]);
+        itertools::assert_equal(
+            folder.values().map(|md| (md.file_type, md.len)),
+            vec![(FileType::Directory, 0), (FileType::File, 7)],
+        );
+
+        // interleave reads from two handles to ensure each reads from its own position
+        let mut first = archive.open_file("/folder/and/it/goes/desc").unwrap();
+        let mut second = archive.open_file("folder/and/it/desc").unwrap();
+        let mut first_contents = [0; 2];
+        first.read_exact(&mut first_contents).unwrap();
+        assert_eq!(second.read_into_string().unwrap(), "it\n");
+        assert_eq!(&first_contents, b"go");
+        assert_eq!(first.read_into_string().unwrap(), "es\n");
+
+        let md = archive.metadata("file").unwrap();
+        assert_eq!(md.file_type, FileType::File);
+        assert_eq!(md.len, 5);
+        assert_eq!(md.mode, Some(0o644));
+        assert_eq!(
+            md.modified,
+            Some(UNIX_EPOCH + Duration::from_secs(1705974859))
+        );
+        assert_eq!(archive.open_file("file").unwrap().metadata().unwrap(), md);
+        assert!(archive.open_file("folder").is_err());
+        assert!(archive.open_file("file/nested").is_err());
+    }
+
+    /// Builds a tarball containing a file along with hard and symbolic links.
+    fn linked_archive() -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        let mut header = Header::new_gnu();
+        header.set_size(8);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        builder
+            .append_data(&mut header, "dir/file", &b"contents"[..])
+            .unwrap();
+
+        for (entry_type, path, target) in [
+            (EntryType::Link, "hard", "dir/file"),
+            (EntryType::Symlink, "dir/sym", "file"),
+            (EntryType::Symlink, "abs", "/dir/file"),
+            (EntryType::Symlink, "dirlink", "dir"),
+            (EntryType::Symlink, "loop", "loop"),
+            (EntryType::Symlink, "broken", "nothing"),
+        ] {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_size(0);
+            header.set_mode(0o777);
+            header.set_mtime(0);
+            builder.append_link(&mut header, path, target).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn check_links(archive: &dyn FileSystem) {
+        for path in [
+            "dir/file",
+            "hard",
+            "dir/sym",
+            "abs",
+            "dirlink/file",
+            "dirlink/sym",
+        ] {
+            let contents = archive.open_file(path).unwrap().read_into_string().unwrap();
+            assert_eq!(contents, "contents");
+            let md = archive.metadata(path).unwrap();
+            assert_eq!(md.file_type, FileType::File);
+            assert_eq!(md.len, 8);
+            assert_eq!(md.mode, Some(0o644));
+        }
+
+        assert_eq!(archive.metadata("dirlink").unwrap(), Metadata::directory());
+        let dirlink = archive
+            .read_dir("dirlink")
+            .unwrap()
+            .map(|entry| entry.unwrap().path.to_str().unwrap().to_owned())
+            .sorted()
+            .collect_vec();
+        assert_eq!(dirlink, vec!["dirlink/file", "dirlink/sym"]);
+
+        assert_eq!(
+            archive.metadata("loop").unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            archive.metadata("broken").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+
+        let root = archive
+            .read_dir("")
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (
+                    entry.path.to_str().unwrap().to_owned(),
+                    entry.metadata.file_type,
+                    entry.metadata.link_target,
+                )
+            })
+            .sorted_by(|(a, ..), (b, ..)| a.cmp(b))
+            .collect_vec();
+        assert_eq!(
+            root,
+            vec![
+                ("abs".to_owned(), FileType::File, Some("dir/file".into())),
+                (
+                    "broken".to_owned(),
+                    FileType::Unknown,
+                    Some("nothing".into())
+                ),
+                ("dir".to_owned(), FileType::Directory, None),
+                (
+                    "dirlink".to_owned(),
+                    FileType::Directory,
+                    Some("dir".into())
+                ),
+                ("hard".to_owned(), FileType::File, None),
+                ("loop".to_owned(), FileType::Unknown, Some("loop".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_directories() {
+        let mut builder = Builder::new(Vec::new());
+        for (entry_type, path) in [
+            (EntryType::Directory, "./"),
+            (EntryType::Directory, "empty/"),
+            (EntryType::Directory, "nested/empty/"),
+            (EntryType::Regular, "replaced"),
+            (EntryType::Directory, "replaced/"),
+        ] {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_size(0);
+            header.set_mode(0o755);
+            header.set_mtime(0);
+            builder.append_data(&mut header, path, io::empty()).unwrap();
+        }
+        let contents = builder.into_inner().unwrap();
+
+        let check = |archive: &dyn FileSystem| {
+            let root = read_directory(archive, "");
+            itertools::assert_equal(root.keys(), vec!["empty", "nested", "replaced"]);
+            assert!(root.values().all(|md| md.file_type == FileType::Directory));
+            assert!(archive.exists("nested/empty").unwrap());
+            assert_eq!(archive.read_dir("nested/empty").unwrap().count(), 0);
+        };
+        check(&TarFS::new(Cursor::new(contents.clone())).unwrap());
+        check(&TarFS::new_indexed(Cursor::new(contents)).unwrap());
+    }
+
+    #[test]
+    fn directory_metadata() {
+        let mut builder = Builder::new(Vec::new());
+        for (entry_type, path, mode, mtime) in [
+            (EntryType::Directory, "dir/", 0o700, 1705974859),
+            (EntryType::Regular, "dir/file", 0o644, 0),
+            (EntryType::Regular, "implicit/file", 0o644, 0),
+            (EntryType::Directory, "replaced/", 0o700, 0),
+            (EntryType::Regular, "replaced", 0o600, 0),
+        ] {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_size(0);
+            header.set_mode(mode);
+            header.set_mtime(mtime);
+            header.set_uid(1000);
+            header.set_gid(1000);
+            builder.append_data(&mut header, path, io::empty()).unwrap();
+        }
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        header.set_mtime(0);
+        builder.append_link(&mut header, "link", "dir").unwrap();
+        let contents = builder.into_inner().unwrap();
+
+        let check = |archive: &dyn FileSystem| {
+            let dir = Metadata {
+                mode: Some(0o700),
+                modified: Some(UNIX_EPOCH + Duration::from_secs(1705974859)),
+                uid: Some(1000),
+                gid: Some(1000),
+                ..Metadata::directory()
+            };
+            assert_eq!(archive.metadata("dir").unwrap(), dir);
+            assert_eq!(archive.metadata("/dir/").unwrap(), dir);
+            assert_eq!(archive.metadata("link").unwrap(), dir);
+            assert_eq!(archive.metadata("implicit").unwrap(), Metadata::directory());
+            assert_eq!(archive.metadata("replaced").unwrap().mode, Some(0o600));
+
+            let root = read_directory(archive, "");
+            assert_eq!(root["dir"], dir);
+            assert_eq!(root["implicit"], Metadata::directory());
+            assert_eq!(root["replaced"].file_type, FileType::File);
+            assert_eq!(root["link"].mode, dir.mode);
+        };
+        check(&TarFS::new(Cursor::new(contents.clone())).unwrap());
+        check(&TarFS::new_indexed(Cursor::new(contents)).unwrap());
+    }
+
+    /// Builds a tarball containing a GNU sparse file, storing a run of 512 bytes at each offset in
+    /// `runs`. Each run is filled with its index, plus one. Like GNU tar, an empty run marks the
+    /// end of the file.
+    fn sparse_archive(runs: &[u64], len: u64) -> Vec<u8> {
+        fn set_octal(field: &mut [u8; 12], value: u64) {
+            field.copy_from_slice(format!("{value:011o}\0").as_bytes());
+        }
+
+        let mut header = Header::new_gnu();
+        header.set_path("disk.img").unwrap();
+        header.set_entry_type(EntryType::GNUSparse);
+        header.set_size(runs.len() as u64 * 512);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+
+        // the first four runs are described by the header, and the rest by extension headers
+        let gnu = header.as_gnu_mut().unwrap();
+        set_octal(&mut gnu.realsize, len);
+        let mut blocks = runs
+            .iter()
+            .map(|&offset| (offset, 512))
+            .chain([(len, 0)])
+            .map(|(offset, numbytes)| {
+                let mut block = GnuSparseHeader {
+                    offset: [0; 12],
+                    numbytes: [0; 12],
+                };
+                set_octal(&mut block.offset, offset);
+                set_octal(&mut block.numbytes, numbytes);
+                block
+            });
+        for (slot, block) in gnu.sparse.iter_mut().zip(blocks.by_ref()) {
+            *slot = block;
+        }
+        let mut extensions = blocks
+            .chunks(21)
+            .into_iter()
+            .map(|chunk| {
+                let mut extension = GnuExtSparseHeader::new();
+                for (slot, block) in extension.sparse.iter_mut().zip(chunk) {
+                    *slot = block;
+                }
+                extension
+            })
+            .collect_vec();
+        for i in 1..extensions.len() {
+            extensions[i - 1].isextended[0] = 1;
+        }
+        gnu.isextended[0] = u8::from(!extensions.is_empty());
+        header.set_cksum();
+
+        let mut contents = header.as_bytes().to_vec();
+        for extension in &extensions {
+            contents.extend_from_slice(extension.as_bytes());
+        }
+        for i in 0..runs.len() {
+            contents.extend_from_slice(&[i as u8 + 1; 512]);
+        }
+        contents.extend_from_slice(&[0; 1024]);
+        contents
+    }
+
+    fn check_sparse(archive: &dyn FileSystem, runs: &[u64], len: u64) {
+        let md = archive.metadata("disk.img").unwrap();
+        assert_eq!(md.len, len);
+
+        let contents = archive
+            .open_file("disk.img")
+            .unwrap()
+            .read_into_vec()
+            .unwrap();
+        let mut expected = vec![0; len as usize];
+        for (i, &offset) in runs.iter().enumerate() {
+            expected[offset as usize..][..512].fill(i as u8 + 1);
+        }
+        assert!(contents == expected);
+    }
+
+    #[test]
+    fn sparse() {
+        let runs = [0, 2048, 4096, 8192, 12288, 20480];
+        let len = 24576;
+        let contents = sparse_archive(&runs, len);
+
+        check_sparse(
+            &TarFS::new(Cursor::new(contents.clone())).unwrap(),
+            &runs,
+            len,
+        );
+        check_sparse(
+            &TarFS::new_indexed(Cursor::new(contents.clone())).unwrap(),
+            &runs,
+            len,
+        );
+
+        // entries appended after the sparse file must not overwrite its data
+        let fs = TarFS::new_appendable(Cursor::new(contents)).unwrap();
+        write!(fs.create_file("log").unwrap(), "log").unwrap();
+        check_sparse(&fs, &runs, len);
+        assert_eq!(
+            fs.open_file("log").unwrap().read_into_string().unwrap(),
+            "log"
+        );
+    }
+
+    #[test]
+    fn multi() {
+        let volumes = [
+            [("kept", "first"), ("shadowed", "first")],
+            [("shadowed", "second"), ("added", "second")],
+        ]
+        .map(|files| {
+            let mut builder = Builder::new(Vec::new());
+            for (path, contents) in files {
+                let mut header = Header::new_gnu();
+                header.set_size(contents.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(0);
+                builder
+                    .append_data(&mut header, path, contents.as_bytes())
+                    .unwrap();
+            }
+            builder.into_inner().unwrap()
+        });
+
+        let archive = TarFS::new_multi(volumes.iter().map(|volume| volume.as_slice())).unwrap();
+        for (path, contents) in [
+            ("kept", "first"),
+            ("shadowed", "second"),
+            ("added", "second"),
+        ] {
+            let file = archive.open_file(path).unwrap().read_into_string().unwrap();
+            assert_eq!(file, contents);
+        }
+    }
+
+    #[test]
+    fn links() {
+        let archive = TarFS::new(Cursor::new(linked_archive())).unwrap();
+        check_links(&archive);
+    }
+
+    #[test]
+    fn links_indexed() {
+        let archive = TarFS::new_indexed(Cursor::new(linked_archive())).unwrap();
+        check_links(&archive);
+    }
+
+    /// Returns the uncompressed contents of `test/deep_fs.tar.xz`.
+    fn deep_fs_tar() -> Vec<u8> {
+        let mut contents = Vec::new();
+        XzDecoder::new(File::open("test/deep_fs.tar.xz").unwrap())
+            .read_to_end(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    fn check_deep_fs(archive: TarFS) {
+        let files = read_directory(&archive, "folder/and/it");
+        itertools::assert_equal(
+            files.keys(),
+            vec!["folder/and/it/desc", "folder/and/it/goes"],
+        );
+
+        let contents = archive
+            .open_file("/folder/and/it/desc")
+            .unwrap()
+            .read_into_string()
+            .unwrap();
+        assert_eq!(contents, "it\n");
+    }
+
+    #[test]
+    fn open_uncompressed() {
+        check_deep_fs(TarFS::open(Cursor::new(deep_fs_tar())).unwrap());
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn open_xz() {
+        check_deep_fs(TarFS::open(File::open("test/deep_fs.tar.xz").unwrap()).unwrap());
+    }
+
+    #[cfg(not(feature = "xz"))]
+    #[test]
+    fn open_xz_disabled() {
+        let archive = TarFS::open(File::open("test/deep_fs.tar.xz").unwrap());
+        assert_eq!(archive.err().unwrap().kind(), ErrorKind::Unsupported);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn open_gzip() {
+        use flate2::write::GzEncoder;
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&deep_fs_tar()).unwrap();
+        let contents = encoder.finish().unwrap();
+
+        check_deep_fs(TarFS::open(Cursor::new(contents)).unwrap());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn open_zstd() {
+        let contents = zstd::encode_all(Cursor::new(deep_fs_tar()), 0).unwrap();
+
+        check_deep_fs(TarFS::open(Cursor::new(contents)).unwrap());
+    }
+
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn open_bzip2() {
+        use bzip2::read::BzEncoder;
+
+        let mut contents = Vec::new();
+        BzEncoder::new(Cursor::new(deep_fs_tar()), bzip2::Compression::default())
+            .read_to_end(&mut contents)
+            .unwrap();
+
+        check_deep_fs(TarFS::open(Cursor::new(contents)).unwrap());
+    }
+
+    #[test]
+    fn appendable() {
+        let path = std::env::temp_dir().join(format!("appendable-{}.tar", std::process::id()));
+        std::fs::write(&path, deep_fs_tar()).unwrap();
+
+        let archive = File::options().read(true).write(true).open(&path).unwrap();
+        let fs = TarFS::new_appendable(archive).unwrap();
+        assert!(fs.create_file("folder").is_err());
+
+        // files are only appended once they are flushed
+        let mut log = fs.create_file("logs/first").unwrap();
+        write!(log, "first").unwrap();
+        assert!(!fs.exists("logs/first").unwrap());
+        log.flush().unwrap();
+        assert_eq!(fs.metadata("logs/first").unwrap().len, 5);
+        write!(log, " line").unwrap();
+        drop(log);
+
+        let mut file = fs
+            .open_file_options("file", &OpenOptions::default().append(true))
+            .unwrap();
+        write!(file, "appended").unwrap();
+        drop(file);
+
+        let long_name = format!("logs/{}", "long".repeat(50));
+        fs.create_file(&long_name).unwrap();
+
+        // the archive is still valid, with later entries shadowing earlier ones
+        check_deep_fs(TarFS::new(File::open(&path).unwrap()).unwrap());
+        for fs in [fs, TarFS::new_indexed(File::open(&path).unwrap()).unwrap()] {
+            let logs = read_directory(&fs, "logs");
+            assert_eq!(logs.len(), 2);
+            assert_eq!(logs[&long_name].len, 0);
+            let first = fs
+                .open_file("logs/first")
+                .unwrap()
+                .read_into_string()
+                .unwrap();
+            assert_eq!(first, "first line");
+            let file = fs.open_file("file").unwrap().read_into_string().unwrap();
+            assert_eq!(file, "file\nappended");
+            assert_eq!(fs.metadata("file").unwrap().mode, Some(0o644));
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn write_tar() {
+        let fs = MemoryFS::default();
+        fs.create_dir_all("root/folder/empty").unwrap();
+        write!(fs.create_file("root/file").unwrap(), "file").unwrap();
+        write!(fs.create_file("root/folder/nested").unwrap(), "nested").unwrap();
+        write!(fs.create_file("outside").unwrap(), "outside").unwrap();
+
+        let contents = super::write_tar(&fs, "/root", Vec::new()).unwrap();
+        let archive = TarFS::new(Cursor::new(contents)).unwrap();
+
+        let root = read_directory(&archive, "");
+        itertools::assert_equal(root.keys(), vec!["file", "folder"]);
+
+        let nested = archive
+            .open_file("folder/nested")
+            .unwrap()
+            .read_into_string()
+            .unwrap();
+        assert_eq!(nested, "nested");
+
+        let md = archive.metadata("file").unwrap();
+        assert_eq!(md.len, 4);
+        assert_eq!(md.mode, Some(0o644));
+        assert_eq!(md.modified, Some(UNIX_EPOCH));
+    }
+
+    #[test]
+    fn open_options() {
+        let fs = TarFS::new_appendable(Cursor::new(deep_fs_tar())).unwrap();
+        check_open_options(&fs, "options");
+    }
 }
@@ -0,0 +1,325 @@
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::util::{sleep, Instant};
+use crate::FileSystem;
+use parking_lot::Mutex;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Paces transfers to a number of bytes per second, across every file that shares it.
+struct Pacer {
+    bytes_per_second: u64,
+    /// The instant at which the previous transfer is complete.
+    available_at: Instant,
+}
+
+impl Pacer {
+    /// Waits until `len` bytes have been transferred at the paced rate, after any transfer that's still in progress.
+    ///
+    /// # Arguments
+    /// `pacer`: The pacer.  
+    /// `len`: The number of bytes transferred.  
+    fn transfer(pacer: &Mutex<Pacer>, len: usize) {
+        let done_at = {
+            let mut pacer = pacer.lock();
+            let duration =
+                Duration::from_secs_f64(len as f64 / pacer.bytes_per_second.max(1) as f64);
+            pacer.available_at = pacer.available_at.max(Instant::now()) + duration;
+            pacer.available_at
+        };
+
+        let now = Instant::now();
+        if done_at > now {
+            sleep(done_at - now);
+        }
+    }
+}
+
+/// The throttling settings shared between the filesystem and its open files.
+#[derive(Clone, Default)]
+struct Throttle {
+    latency: Duration,
+    jitter: Duration,
+    read: Option<Arc<Mutex<Pacer>>>,
+    write: Option<Arc<Mutex<Pacer>>>,
+}
+
+impl Throttle {
+    /// Waits for the latency of an operation, plus a random amount of jitter.
+    fn delay(&self) {
+        let mut delay = self.latency;
+        if !self.jitter.is_zero() {
+            // a freshly keyed hasher is a source of randomness that doesn't need another dependency
+            let random = RandomState::new().build_hasher().finish();
+            delay += self.jitter.mul_f64(random as f64 / u64::MAX as f64);
+        }
+
+        if !delay.is_zero() {
+            sleep(delay);
+        }
+    }
+}
+
+/// A filesystem that slows down another filesystem, so that an application can be tested against a slow disk or a
+/// network filesystem without the real infrastructure.
+///
+/// Every operation on the filesystem, including opening a file, waits once for a fixed latency plus a random amount
+/// of jitter, even when it's made up of several operations on the inner filesystem, like walking a directory. Reads
+/// and writes of open files are paced to a bandwidth in bytes per second, which is shared between every file opened
+/// through the filesystem.
+pub struct ThrottleFS<FS> {
+    inner: FS,
+    throttle: Arc<Throttle>,
+}
+
+impl<FS: FileSystem> ThrottleFS<FS> {
+    /// Creates a new throttled filesystem, which isn't slowed down until it's configured to be.
+    ///
+    /// # Arguments
+    /// `inner`: The filesystem to slow down.  
+    pub fn new(inner: FS) -> Self {
+        Self {
+            inner,
+            throttle: Arc::default(),
+        }
+    }
+
+    /// Sets the latency of every operation.
+    ///
+    /// # Arguments
+    /// `latency`: The fixed latency of every operation.  
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.throttle_mut().latency = latency;
+        self
+    }
+
+    /// Sets the jitter of every operation.
+    ///
+    /// # Arguments
+    /// `jitter`: The maximum random latency added to every operation.  
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.throttle_mut().jitter = jitter;
+        self
+    }
+
+    /// Limits the bandwidth of reads.
+    ///
+    /// # Arguments
+    /// `bytes_per_second`: The number of bytes that can be read per second.  
+    pub fn read_bandwidth(mut self, bytes_per_second: u64) -> Self {
+        self.throttle_mut().read = Some(Arc::new(Mutex::new(Pacer {
+            bytes_per_second,
+            available_at: Instant::now(),
+        })));
+        self
+    }
+
+    /// Limits the bandwidth of writes.
+    ///
+    /// # Arguments
+    /// `bytes_per_second`: The number of bytes that can be written per second.  
+    pub fn write_bandwidth(mut self, bytes_per_second: u64) -> Self {
+        self.throttle_mut().write = Some(Arc::new(Mutex::new(Pacer {
+            bytes_per_second,
+            available_at: Instant::now(),
+        })));
+        self
+    }
+
+    /// Returns the slowed down filesystem.
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Returns the settings to configure, which are copied if they're shared with an open file.
+    fn throttle_mut(&mut self) -> &mut Throttle {
+        Arc::make_mut(&mut self.throttle)
+    }
+}
+
+impl<FS: FileSystem> FileSystem for ThrottleFS<FS> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.throttle.delay();
+        self.inner.create_dir(path)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.throttle.delay();
+        self.inner.metadata(path)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        self.throttle.delay();
+        Ok(Box::new(ThrottledFile {
+            inner: self.inner.open_file_options(path, options)?,
+            throttle: self.throttle.clone(),
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        self.throttle.delay();
+        self.inner.read_dir(path)
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.throttle.delay();
+        self.inner.remove_dir(path)
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.throttle.delay();
+        self.inner.remove_file(path)
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        self.throttle.delay();
+        self.inner.create_dir_with(path, mode)
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> crate::Result<u64> {
+        self.throttle.delay();
+        let copied = self.inner.copy_file(from, to)?;
+        if let Some(pacer) = &self.throttle.read {
+            Pacer::transfer(pacer, copied as usize);
+        }
+        if let Some(pacer) = &self.throttle.write {
+            Pacer::transfer(pacer, copied as usize);
+        }
+        Ok(copied)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        self.throttle.delay();
+        self.inner.rename(from, to)
+    }
+
+    fn walk_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>> + '_>> {
+        self.throttle.delay();
+        self.inner.walk_dir(path)
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        self.throttle.delay();
+        self.inner.stats()
+    }
+}
+
+/// A file whose reads and writes are paced to the bandwidth of its filesystem.
+struct ThrottledFile {
+    inner: Box<dyn File>,
+    throttle: Arc<Throttle>,
+}
+
+impl Read for ThrottledFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_len = self.inner.read(buf)?;
+        if let Some(pacer) = &self.throttle.read {
+            Pacer::transfer(pacer, read_len);
+        }
+        Ok(read_len)
+    }
+}
+
+impl Seek for ThrottledFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl Write for ThrottledFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(pacer) = &self.throttle.write {
+            Pacer::transfer(pacer, written);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl File for ThrottledFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        self.inner.metadata()
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        self.inner.set_len(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    use crate::throttle_fs::ThrottleFS;
+    use crate::util::Instant;
+    use crate::FileSystem;
+    use std::io::Write;
+    use std::time::Duration;
+
+    #[test]
+    fn latency() {
+        let fs = ThrottleFS::new(MemoryFS::default())
+            .latency(Duration::from_millis(20))
+            .jitter(Duration::from_millis(10));
+
+        let start = Instant::now();
+        fs.create_dir("dir").unwrap();
+        fs.create_file("dir/file").unwrap();
+        fs.metadata("dir/file").unwrap();
+        assert_eq!(fs.read_dir("dir").unwrap().count(), 1);
+        fs.remove_file("dir/file").unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(100), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(1000), "{elapsed:?}");
+    }
+
+    #[test]
+    fn bandwidth() {
+        let fs = ThrottleFS::new(MemoryFS::default())
+            .read_bandwidth(100_000)
+            .write_bandwidth(50_000);
+
+        // no latency is added unless it's configured
+        let start = Instant::now();
+        fs.create_dir("dir").unwrap();
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        let start = Instant::now();
+        fs.create_file("dir/first")
+            .unwrap()
+            .write_all(&[0; 10_000])
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        fs.inner()
+            .create_file("dir/second")
+            .unwrap()
+            .write_all(&[0; 10_000])
+            .unwrap();
+
+        // the bandwidth is shared between open files
+        let mut first = fs.open_file("dir/first").unwrap();
+        let mut second = fs.open_file("dir/second").unwrap();
+        let start = Instant::now();
+        assert_eq!(first.read_into_vec().unwrap().len(), 10_000);
+        assert_eq!(second.read_into_vec().unwrap().len(), 10_000);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        drop((first, second));
+
+        // copies are delayed once, and paced in both directions
+        let start = Instant::now();
+        assert_eq!(fs.copy_file("dir/first", "dir/copy").unwrap(), 10_000);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+}
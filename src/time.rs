@@ -0,0 +1,120 @@
+//! Conversions between `SystemTime` and calendar dates, for the backends whose protocols or formats carry them.
+
+use std::time::{Duration, SystemTime};
+
+/// A UTC date and time in the proleptic Gregorian calendar.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct DateTime {
+    pub year: i64,
+    /// The month, from 1 to 12.
+    pub month: u32,
+    /// The day of the month, from 1 to 31.
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    /// Returns the date and time of `time`. Times before the epoch are clamped to the epoch.
+    ///
+    /// # Arguments
+    /// `time`: The time to convert.
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let seconds = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+        // the date of a number of days since the epoch
+        let days = days + 719468;
+        let era = days / 146097;
+        let day_of_era = days - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+        Self {
+            year: year as i64,
+            month: month as u32,
+            day: day as u32,
+            hour: (seconds / 3600) as u32,
+            minute: (seconds / 60 % 60) as u32,
+            second: (seconds % 60) as u32,
+        }
+    }
+
+    /// Returns the time of the date and time, or `None` if it's invalid or before the epoch.
+    pub fn to_system_time(self) -> Option<SystemTime> {
+        if !(1..=12).contains(&self.month)
+            || !(1..=31).contains(&self.day)
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 60
+        {
+            return None;
+        }
+
+        // days since the epoch of the date
+        let (year, month) = if self.month <= 2 {
+            (self.year - 1, i64::from(self.month) + 9)
+        } else {
+            (self.year, i64::from(self.month) - 3)
+        };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * month + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+
+        let seconds = days * 86400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second);
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(u64::try_from(seconds).ok()?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::time::DateTime;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn round_trip() {
+        for seconds in [0, 951782400, 1709208000, 4102444799] {
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+            assert_eq!(
+                DateTime::from_system_time(time).to_system_time(),
+                Some(time)
+            );
+        }
+        assert_eq!(
+            DateTime::from_system_time(SystemTime::UNIX_EPOCH + Duration::from_secs(1709208000)),
+            DateTime {
+                year: 2024,
+                month: 2,
+                day: 29,
+                hour: 12,
+                minute: 0,
+                second: 0
+            }
+        );
+        assert_eq!(
+            DateTime {
+                year: 1969,
+                month: 12,
+                day: 31,
+                hour: 23,
+                minute: 59,
+                second: 59
+            }
+            .to_system_time(),
+            None
+        );
+    }
+}
@@ -0,0 +1,370 @@
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::util::Instant;
+use crate::FileSystem;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::field::Empty;
+use tracing::Span;
+
+/// A filesystem that emits `tracing` spans and events for every operation on another filesystem, so that a stack of
+/// layered filesystems can be debugged in production.
+///
+/// Every operation runs in a `vfs` span at the `DEBUG` level, with the name of the filesystem, the operation, its
+/// path and, for copies and renames, its destination. An event with the duration of the operation and its error, if
+/// it failed, is emitted within the span once it completes. Open files have a `vfs_file` span of their own, in which
+/// every read and write emits a `TRACE` event with its byte count and duration, and closing the file emits a `DEBUG`
+/// event with the total bytes read and written.
+pub struct TracingFS<FS> {
+    inner: FS,
+    name: Arc<str>,
+}
+
+impl<FS: FileSystem> TracingFS<FS> {
+    /// Creates a new traced filesystem.
+    ///
+    /// # Arguments
+    /// `inner`: The filesystem to trace.  
+    pub fn new(inner: FS) -> Self {
+        Self {
+            inner,
+            name: Arc::from(""),
+        }
+    }
+
+    /// Sets the name the spans of the filesystem are recorded with, which tells apart the layers of a stack.
+    ///
+    /// # Arguments
+    /// `name`: The name of the filesystem.  
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Arc::from(name);
+        self
+    }
+
+    /// Returns the traced filesystem.
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Consumes the traced filesystem, returning the inner filesystem.
+    pub fn into_inner(self) -> FS {
+        self.inner
+    }
+
+    /// Returns the span of an operation.
+    ///
+    /// # Arguments
+    /// `operation`: The name of the operation.  
+    /// `path`: The path the operation is on.  
+    fn span(&self, operation: &'static str, path: &str) -> Span {
+        tracing::debug_span!(
+            "vfs",
+            fs = %self.name,
+            operation,
+            path,
+            to = Empty,
+            bytes = Empty
+        )
+    }
+
+    /// Runs an operation within `span`, emitting its outcome once it completes.
+    ///
+    /// # Arguments
+    /// `span`: The span of the operation.  
+    /// `operation`: The operation.  
+    fn trace<T>(
+        &self,
+        span: &Span,
+        operation: impl FnOnce() -> crate::Result<T>,
+    ) -> crate::Result<T> {
+        let _entered = span.enter();
+        let start = Instant::now();
+        let result = operation();
+        record_outcome(&result, start.elapsed());
+        result
+    }
+}
+
+/// Emits the outcome of an operation in the current span.
+///
+/// # Arguments
+/// `result`: The result of the operation.  
+/// `duration`: How long the operation took.  
+fn record_outcome<T>(result: &crate::Result<T>, duration: Duration) {
+    match result {
+        Ok(_) => tracing::debug!(?duration, "completed"),
+        Err(err) => tracing::debug!(?duration, error = %err, kind = ?err.kind(), "failed"),
+    }
+}
+
+impl<FS: FileSystem> FileSystem for TracingFS<FS> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.trace(&self.span("create_dir", path), || {
+            self.inner.create_dir(path)
+        })
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.trace(&self.span("metadata", path), || self.inner.metadata(path))
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        let inner = self.trace(&self.span("open_file", path), || {
+            self.inner.open_file_options(path, options)
+        })?;
+
+        Ok(Box::new(TracedFile {
+            inner,
+            span: tracing::debug_span!("vfs_file", fs = %self.name, path),
+            read: 0,
+            written: 0,
+            opened_at: Instant::now(),
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        self.trace(&self.span("read_dir", path), || self.inner.read_dir(path))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.trace(&self.span("remove_dir", path), || {
+            self.inner.remove_dir(path)
+        })
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.trace(&self.span("remove_file", path), || {
+            self.inner.remove_file(path)
+        })
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        self.trace(&self.span("create_dir", path), || {
+            self.inner.create_dir_with(path, mode)
+        })
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> crate::Result<u64> {
+        let span = self.span("copy_file", from);
+        span.record("to", to);
+        self.trace(&span, || {
+            let copied = self.inner.copy_file(from, to)?;
+            span.record("bytes", copied);
+            Ok(copied)
+        })
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        let span = self.span("rename", from);
+        span.record("to", to);
+        self.trace(&span, || self.inner.rename(from, to))
+    }
+
+    fn walk_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>> + '_>> {
+        self.trace(&self.span("walk_dir", path), || self.inner.walk_dir(path))
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        self.trace(&self.span("stats", ""), || self.inner.stats())
+    }
+}
+
+/// A file whose reads and writes are traced within its own span.
+struct TracedFile {
+    inner: Box<dyn File>,
+    span: Span,
+    /// The number of bytes read from the file.
+    read: u64,
+    /// The number of bytes written to the file.
+    written: u64,
+    opened_at: Instant,
+}
+
+impl Read for TracedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let _entered = self.span.enter();
+        let start = Instant::now();
+        let result = self.inner.read(buf);
+        match &result {
+            Ok(bytes) => {
+                self.read += *bytes as u64;
+                tracing::trace!(bytes, duration = ?start.elapsed(), "read");
+            }
+            Err(err) => tracing::debug!(duration = ?start.elapsed(), error = %err, "read failed"),
+        }
+        result
+    }
+}
+
+impl Seek for TracedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl Write for TracedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _entered = self.span.enter();
+        let start = Instant::now();
+        let result = self.inner.write(buf);
+        match &result {
+            Ok(bytes) => {
+                self.written += *bytes as u64;
+                tracing::trace!(bytes, duration = ?start.elapsed(), "wrote");
+            }
+            Err(err) => tracing::debug!(duration = ?start.elapsed(), error = %err, "write failed"),
+        }
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let _entered = self.span.enter();
+        let start = Instant::now();
+        let result = self.inner.flush();
+        record_outcome(&result, start.elapsed());
+        result
+    }
+}
+
+impl File for TracedFile {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        self.inner.metadata()
+    }
+
+    fn set_len(&mut self, len: u64) -> crate::Result<()> {
+        self.inner.set_len(len)
+    }
+}
+
+impl Drop for TracedFile {
+    fn drop(&mut self) {
+        let _entered = self.span.enter();
+        tracing::debug!(
+            read = self.read,
+            written = self.written,
+            duration = ?self.opened_at.elapsed(),
+            "closed"
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    use crate::tracing_fs::TracingFS;
+    use crate::FileSystem;
+    use parking_lot::Mutex;
+    use std::fmt::Debug;
+    use std::io::{ErrorKind, Write};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Formats the fields of spans and events as `name=value` pairs.
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0 += &format!(" {}={value:?}", field.name());
+        }
+    }
+
+    /// A subscriber that records every span and event it's sent as a line of text.
+    #[derive(Default)]
+    struct Recorder {
+        lines: Arc<Mutex<Vec<String>>>,
+        next_id: AtomicU64,
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = Fields(span.metadata().name().to_owned());
+            span.record(&mut fields);
+            self.lines.lock().push(fields.0);
+            Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, _span: &Id, values: &Record<'_>) {
+            let mut fields = Fields("record".to_owned());
+            values.record(&mut fields);
+            self.lines.lock().push(fields.0);
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields(event.metadata().level().to_string());
+            event.record(&mut fields);
+            self.lines.lock().push(fields.0);
+        }
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[test]
+    fn trace() {
+        let recorder = Recorder::default();
+        let lines = recorder.lines.clone();
+        let fs = TracingFS::new(MemoryFS::default()).name("memory");
+
+        tracing::subscriber::with_default(recorder, || {
+            fs.create_dir("dir").unwrap();
+            write!(fs.create_file("dir/file").unwrap(), "hello").unwrap();
+            assert_eq!(fs.copy_file("dir/file", "dir/copy").unwrap(), 5);
+            assert_eq!(
+                fs.remove_dir("missing").err().unwrap().kind(),
+                ErrorKind::NotFound
+            );
+        });
+
+        let lines = lines.lock();
+        let lines: Vec<_> = lines
+            .iter()
+            .map(|line| {
+                // durations aren't deterministic
+                match line.find(" duration=") {
+                    Some(start) => {
+                        let end = line[start + 1..]
+                            .find(' ')
+                            .map_or(line.len(), |end| start + 1 + end);
+                        format!("{}{}", &line[..start], &line[end..])
+                    }
+                    None => line.clone(),
+                }
+            })
+            .collect();
+        itertools::assert_equal(
+            lines.iter().map(String::as_str),
+            [
+                "vfs fs=memory operation=\"create_dir\" path=\"dir\"",
+                "DEBUG message=completed",
+                "vfs fs=memory operation=\"open_file\" path=\"dir/file\"",
+                "DEBUG message=completed",
+                "vfs_file fs=memory path=\"dir/file\"",
+                "TRACE message=wrote bytes=5",
+                "DEBUG message=closed read=0 written=5",
+                "vfs fs=memory operation=\"copy_file\" path=\"dir/file\"",
+                "record to=\"dir/copy\"",
+                "record bytes=5",
+                "DEBUG message=completed",
+                "vfs fs=memory operation=\"remove_dir\" path=\"missing\"",
+                "DEBUG message=failed error=File not found kind=NotFound",
+            ],
+        );
+    }
+}
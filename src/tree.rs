@@ -1,4 +1,6 @@
-use crate::util::{component_iter, invalid_path, make_relative, normalize_path, not_found};
+use crate::util::{
+    component_iter, invalid_path, make_relative, normalize_path, not_a_directory, not_found,
+};
 use parking_lot::Mutex;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
@@ -40,7 +42,7 @@ impl<T> FilesystemTree<T> {
         let mut entry = &mut *entry;
         for component in component_iter(&normalize_and_relativize(path)) {
             let Entry::Directory(dir) = entry else {
-                return Err(not_found());
+                return Err(not_a_directory());
             };
 
             entry = dir
@@ -52,7 +54,7 @@ impl<T> FilesystemTree<T> {
         if let Entry::Directory(dir) = entry {
             Ok(f(dir))
         } else {
-            Err(not_found())
+            Err(not_a_directory())
         }
     }
 
@@ -65,7 +67,7 @@ impl<T> FilesystemTree<T> {
         path: P,
         f: F,
     ) -> crate::Result<R> {
-        self.with_entry(path, |entry| entry.map(f).map_err(|_| not_found()))
+        self.with_entry(path, |entry| entry.map(f).map_err(|_| not_a_directory()))
     }
 
     /// Calls `f` with the entry at `path`, or the last found entry and remaining path.
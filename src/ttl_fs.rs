@@ -0,0 +1,343 @@
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::{invalid_path, not_found, Instant};
+use crate::FileSystem;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::Duration;
+
+/// When the files of a `TtlFS` expire. Unset limits never expire a file.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Expiry {
+    /// How long a file lives after it was last written.
+    pub ttl: Option<Duration>,
+    /// How long a file lives after it was last opened.
+    pub idle: Option<Duration>,
+}
+
+/// When a file was last written and opened.
+#[derive(Copy, Clone)]
+struct Lease {
+    written: Instant,
+    opened: Instant,
+}
+
+impl Lease {
+    /// Returns true if the file has expired at `now`.
+    ///
+    /// # Arguments
+    /// `expiry`: When files expire.  
+    /// `now`: The current instant.  
+    fn is_expired(&self, expiry: &Expiry, now: Instant) -> bool {
+        expiry
+            .ttl
+            .is_some_and(|ttl| now.duration_since(self.written) >= ttl)
+            || expiry
+                .idle
+                .is_some_and(|idle| now.duration_since(self.opened) >= idle)
+    }
+}
+
+/// A filesystem whose files expire after a time-to-live since they were last written, or after being idle since they
+/// were last opened, such as a cache of generated thumbnails or build artifacts. It wraps a `MemoryFS` or any other
+/// writable filesystem.
+///
+/// Expired files vanish as soon as they're looked up, listed or opened, and are removed from the inner filesystem
+/// then. `gc` removes every expired file at once, which should be called periodically to reclaim the space of files
+/// that are never looked at again. The times are tracked in memory: files already in the inner filesystem, or written
+/// to it directly, are treated as written and opened when they're first seen.
+pub struct TtlFS<FS> {
+    inner: FS,
+    expiry: Mutex<Expiry>,
+    leases: Mutex<HashMap<String, Lease>>,
+}
+
+impl<FS: FileSystem> TtlFS<FS> {
+    /// Creates a new expiring filesystem.
+    ///
+    /// # Arguments
+    /// `inner`: The filesystem to store files in.  
+    /// `expiry`: When files expire.  
+    pub fn new(inner: FS, expiry: Expiry) -> Self {
+        Self {
+            inner,
+            expiry: Mutex::new(expiry),
+            leases: Mutex::default(),
+        }
+    }
+
+    /// Returns the filesystem the files are stored in.
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Returns when files expire.
+    pub fn expiry(&self) -> Expiry {
+        *self.expiry.lock()
+    }
+
+    /// Changes when files expire, which applies to files already written.
+    ///
+    /// # Arguments
+    /// `expiry`: When files expire.  
+    pub fn set_expiry(&self, expiry: Expiry) {
+        *self.expiry.lock() = expiry;
+    }
+
+    /// Removes every expired file. Returns the number of files removed.
+    pub fn gc(&self) -> crate::Result<usize> {
+        let mut removed = 0;
+        for entry in self.inner.walk_dir("")? {
+            let entry = entry?;
+            let path = entry.path.to_str().ok_or_else(invalid_path)?;
+            if entry.metadata.is_file() && self.expire(&lease_key(path)?)? {
+                removed += 1;
+            }
+        }
+
+        // forget the files that were removed from the inner filesystem directly
+        let mut leases = self.leases.lock();
+        let keys: Vec<_> = leases.keys().cloned().collect();
+        for key in keys {
+            if !self.inner.exists(&key)? {
+                leases.remove(&key);
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes the file with the lease `key` if it has expired. Files that haven't been seen before are leased now.
+    /// Returns true if the file was removed.
+    ///
+    /// # Arguments
+    /// `key`: The key of the lease, which is the normalized path of the file.  
+    fn expire(&self, key: &str) -> crate::Result<bool> {
+        let now = Instant::now();
+        let expiry = self.expiry();
+        let mut leases = self.leases.lock();
+        let lease = leases.entry(key.to_owned()).or_insert(Lease {
+            written: now,
+            opened: now,
+        });
+        if !lease.is_expired(&expiry, now) {
+            return Ok(false);
+        }
+
+        leases.remove(key);
+        match self.inner.remove_file(key) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns the metadata of the entry at `path`, failing with `NotFound` if it's an expired file, which is removed.
+    ///
+    /// # Arguments
+    /// `path`: The path of the entry.  
+    fn live_metadata(&self, path: &str) -> crate::Result<Metadata> {
+        let metadata = self.inner.metadata(path)?;
+        if metadata.is_file() && self.expire(&lease_key(path)?)? {
+            return Err(not_found());
+        }
+
+        Ok(metadata)
+    }
+
+    /// Forgets the leases of the entry at `path` and everything within it.
+    ///
+    /// # Arguments
+    /// `path`: The path of the entry.  
+    fn forget(&self, path: &str) -> crate::Result<()> {
+        let key = lease_key(path)?;
+        self.leases
+            .lock()
+            .retain(|lease_key, _| !Path::new(lease_key).starts_with(&key));
+        Ok(())
+    }
+}
+
+impl<FS: FileSystem> FileSystem for TtlFS<FS> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.inner.create_dir(path)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.live_metadata(path)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        match self.live_metadata(path) {
+            Ok(_) => {}
+            Err(err)
+                if err.kind() == ErrorKind::NotFound && (options.create || options.create_new) => {}
+            Err(err) => return Err(err),
+        }
+
+        let file = self.inner.open_file_options(path, options)?;
+        let now = Instant::now();
+        let mut leases = self.leases.lock();
+        let lease = leases.entry(lease_key(path)?).or_insert(Lease {
+            written: now,
+            opened: now,
+        });
+        lease.opened = now;
+        if options.writable() {
+            lease.written = now;
+        }
+        Ok(file)
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let mut entries = Vec::new();
+        for entry in self.inner.read_dir(path)? {
+            let entry = entry?;
+            let key = lease_key(entry.path.to_str().ok_or_else(invalid_path)?)?;
+            if !entry.metadata.is_file() || !self.expire(&key)? {
+                entries.push(Ok(entry));
+            }
+        }
+
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.inner.remove_dir(path)?;
+        self.forget(path)
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.live_metadata(path)?;
+        self.inner.remove_file(path)?;
+        self.forget(path)
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        self.inner.create_dir_with(path, mode)
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> crate::Result<u64> {
+        self.live_metadata(from)?;
+        let copied = self.inner.copy_file(from, to)?;
+        // the copy is a new file
+        self.forget(to)?;
+        Ok(copied)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        self.live_metadata(from)?;
+        self.inner.rename(from, to)?;
+
+        // the leases move along with the entries
+        let (from, to) = (lease_key(from)?, lease_key(to)?);
+        let mut leases = self.leases.lock();
+        leases.retain(|key, _| !Path::new(key).starts_with(&to));
+        let moved: Vec<_> = leases
+            .keys()
+            .filter(|key| Path::new(key).starts_with(&from))
+            .cloned()
+            .collect();
+        for key in moved {
+            let lease = leases.remove(&key).unwrap();
+            leases.insert(format!("{to}{}", &key[from.len()..]), lease);
+        }
+        Ok(())
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        self.inner.stats()
+    }
+}
+
+/// Returns the key of the lease of the entry at `path`, which is its normalized path.
+///
+/// # Arguments
+/// `path`: The virtual path.  
+fn lease_key(path: &str) -> crate::Result<String> {
+    let path = normalize_and_relativize(path);
+    Ok(path.to_str().ok_or_else(invalid_path)?.replace('\\', "/"))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    use crate::ttl_fs::{Expiry, TtlFS};
+    use crate::util::sleep;
+    use crate::util::test::{check_open_options, read_directory};
+    use crate::FileSystem;
+    use std::io::{ErrorKind, Write};
+    use std::time::Duration;
+
+    #[test]
+    fn ttl() {
+        let fs = TtlFS::new(
+            MemoryFS::default(),
+            Expiry {
+                ttl: Some(Duration::from_millis(200)),
+                idle: None,
+            },
+        );
+        fs.create_dir("thumbs").unwrap();
+        write!(fs.create_file("thumbs/a").unwrap(), "a").unwrap();
+        write!(fs.create_file("thumbs/b").unwrap(), "b").unwrap();
+        sleep(Duration::from_millis(120));
+
+        // writing renews the lease, but reading doesn't
+        write!(fs.create_file("thumbs/b").unwrap(), "b").unwrap();
+        fs.open_file("thumbs/a").unwrap();
+        fs.rename("thumbs/b", "thumbs/c").unwrap();
+        sleep(Duration::from_millis(120));
+
+        itertools::assert_equal(read_directory(&fs, "thumbs").keys(), vec!["thumbs/c"]);
+        assert!(!fs.inner().exists("thumbs/a").unwrap());
+        assert_eq!(
+            fs.open_file("thumbs/a").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        sleep(Duration::from_millis(120));
+        assert!(fs.inner().exists("thumbs/c").unwrap());
+        assert_eq!(fs.gc().unwrap(), 1);
+        assert!(read_directory(fs.inner(), "thumbs").is_empty());
+    }
+
+    #[test]
+    fn idle() {
+        let fs = TtlFS::new(
+            MemoryFS::default(),
+            Expiry {
+                ttl: None,
+                idle: Some(Duration::from_millis(200)),
+            },
+        );
+        write!(fs.create_file("a").unwrap(), "a").unwrap();
+        write!(fs.create_file("b").unwrap(), "b").unwrap();
+        sleep(Duration::from_millis(120));
+
+        // opening the file renews the lease
+        fs.open_file("a").unwrap();
+        sleep(Duration::from_millis(120));
+        assert_eq!(fs.gc().unwrap(), 1);
+        assert!(fs.exists("a").unwrap());
+        assert!(!fs.exists("b").unwrap());
+    }
+
+    #[test]
+    fn open_options() {
+        let fs = TtlFS::new(
+            MemoryFS::default(),
+            Expiry {
+                ttl: Some(Duration::from_secs(60)),
+                idle: None,
+            },
+        );
+        check_open_options(&fs, "options");
+    }
+}
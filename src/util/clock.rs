@@ -0,0 +1,110 @@
+use std::ops::{Add, Sub};
+use std::time::{Duration, SystemTime};
+
+/// True on targets whose standard library has no clock and panics when it's read.
+const NO_STD_CLOCK: bool = cfg!(all(target_arch = "wasm32", target_os = "unknown"));
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32", target_os = "unknown"))]
+#[wasm_bindgen::prelude::wasm_bindgen]
+extern "C" {
+    /// The milliseconds since the time origin of the page or worker, from its monotonic clock.
+    #[wasm_bindgen(js_namespace = performance, js_name = now)]
+    fn performance_now() -> f64;
+}
+
+/// Returns the current time. `wasm32-unknown-unknown` has no clock and panics on `SystemTime::now`, so the clock of
+/// the browser is read there with the `wasm` feature, and the Unix epoch is returned without it.
+pub(crate) fn now() -> SystemTime {
+    #[cfg(all(feature = "wasm", target_arch = "wasm32", target_os = "unknown"))]
+    return SystemTime::UNIX_EPOCH + Duration::from_secs_f64(js_sys::Date::now() / 1000.0);
+    #[cfg(all(not(feature = "wasm"), target_arch = "wasm32", target_os = "unknown"))]
+    return SystemTime::UNIX_EPOCH;
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    #[allow(clippy::disallowed_methods)]
+    SystemTime::now()
+}
+
+/// A point on a monotonic clock, which stands in for `std::time::Instant` since that panics on
+/// `wasm32-unknown-unknown`. There, the clock of the browser is read with the `wasm` feature, and the clock stands
+/// still without it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub(crate) struct Instant(Duration);
+
+impl Instant {
+    /// Returns the current point on the clock.
+    pub(crate) fn now() -> Self {
+        #[cfg(all(feature = "wasm", target_arch = "wasm32", target_os = "unknown"))]
+        return Self(Duration::from_secs_f64(performance_now() / 1000.0));
+        #[cfg(all(not(feature = "wasm"), target_arch = "wasm32", target_os = "unknown"))]
+        return Self(Duration::ZERO);
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        #[allow(clippy::disallowed_methods)]
+        {
+            static ORIGIN: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+            Self(ORIGIN.get_or_init(std::time::Instant::now).elapsed())
+        }
+    }
+
+    /// Returns the time elapsed since the instant.
+    #[cfg(any(feature = "metrics", feature = "tracing", test))]
+    pub(crate) fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+
+    /// Returns the time elapsed from `earlier` to the instant, or zero if `earlier` is later.
+    ///
+    /// # Arguments
+    /// `earlier`: The earlier instant.  
+    pub(crate) fn duration_since(&self, earlier: Self) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Self;
+
+    fn add(self, duration: Duration) -> Self {
+        Self(self.0 + duration)
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Self) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Blocks the current thread for `duration`. `wasm32-unknown-unknown` can't block, so it returns at once there.
+///
+/// # Arguments
+/// `duration`: How long to block for.  
+pub(crate) fn sleep(duration: Duration) {
+    if !NO_STD_CLOCK {
+        #[allow(clippy::disallowed_methods)]
+        std::thread::sleep(duration);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::util::clock::{now, sleep, Instant};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn clock() {
+        assert!(now() > SystemTime::UNIX_EPOCH);
+
+        let start = Instant::now();
+        sleep(Duration::from_millis(20));
+        let end = Instant::now();
+        assert!(end > start);
+        assert!(end - start >= Duration::from_millis(20));
+        assert_eq!(start - end, Duration::ZERO);
+        assert_eq!(
+            (start + Duration::from_secs(1)).duration_since(start),
+            Duration::from_secs(1)
+        );
+    }
+}
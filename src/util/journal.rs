@@ -0,0 +1,165 @@
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::future::{poll_fn, Future};
+use std::io;
+use std::sync::Arc;
+use std::task::{Poll, Waker};
+
+/// A queue of changes that were applied to memory at once and are persisted in the background, in order, by a single
+/// writer, for backends whose storage can only be written to asynchronously.
+pub(crate) struct Journal<Op> {
+    state: Arc<Mutex<State<Op>>>,
+}
+
+struct State<Op> {
+    changes: VecDeque<Op>,
+    /// True while a writer is persisting changes.
+    writing: bool,
+    /// The first error that persisting a change failed with since the journal was last flushed.
+    error: Option<io::Error>,
+    /// The tasks waiting for every change to be persisted.
+    flushes: Vec<Waker>,
+}
+
+impl<Op> Default for Journal<Op> {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                changes: VecDeque::new(),
+                writing: false,
+                error: None,
+                flushes: Vec::new(),
+            })),
+        }
+    }
+}
+
+impl<Op> Clone for Journal<Op> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<Op> Journal<Op> {
+    /// Queues a change. Returns true if there's no writer, in which case the caller has to start one, which persists
+    /// changes until `take` returns none.
+    ///
+    /// # Arguments
+    /// `change`: The change.  
+    pub(crate) fn push(&self, change: Op) -> bool {
+        let mut state = self.state.lock();
+        state.changes.push_back(change);
+        !std::mem::replace(&mut state.writing, true)
+    }
+
+    /// Takes every queued change, for the writer to persist in order. Once there are none, the writer is done and the
+    /// tasks waiting for a flush are woken.
+    pub(crate) fn take(&self) -> Vec<Op> {
+        let mut state = self.state.lock();
+        if state.changes.is_empty() {
+            state.writing = false;
+            state.flushes.drain(..).for_each(Waker::wake);
+        }
+        state.changes.drain(..).collect()
+    }
+
+    /// Records that persisting a change failed. Only the first error until the next flush is kept.
+    ///
+    /// # Arguments
+    /// `err`: The error.  
+    pub(crate) fn fail(&self, err: io::Error) {
+        self.state.lock().error.get_or_insert(err);
+    }
+
+    /// Waits until every queued change is persisted. Returns the first error that persisting a change failed with
+    /// since the last flush.
+    pub(crate) fn flush(&self) -> impl Future<Output = crate::Result<()>> + '_ {
+        poll_fn(|cx| {
+            let mut state = self.state.lock();
+            if state.writing {
+                state.flushes.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+            Poll::Ready(state.error.take().map_or(Ok(()), Err))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::util::journal::Journal;
+    use std::future::Future;
+    use std::io;
+    use std::io::ErrorKind;
+    use std::pin::pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    /// Records that it was woken.
+    #[derive(Default)]
+    struct Flag(AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn poll_flush(journal: &Journal<u32>) -> Poll<crate::Result<()>> {
+        pin!(journal.flush()).poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    #[test]
+    fn journal() {
+        let journal = Journal::default();
+        assert!(matches!(poll_flush(&journal), Poll::Ready(Ok(()))));
+
+        // the first change starts a writer, which later changes are left to
+        assert!(journal.push(1));
+        assert!(!journal.push(2));
+        assert!(poll_flush(&journal).is_pending());
+        assert_eq!(journal.take(), [1, 2]);
+        assert!(!journal.clone().push(3));
+        assert_eq!(journal.take(), [3]);
+        assert!(poll_flush(&journal).is_pending());
+
+        // the writer is done once there's nothing left, which wakes the flushes
+        let flag = Arc::new(Flag::default());
+        let waker = Waker::from(flag.clone());
+        let mut flush = pin!(journal.flush());
+        assert!(flush
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+        assert!(journal.take().is_empty());
+        assert!(flag.0.load(Ordering::SeqCst));
+        assert!(matches!(
+            flush.poll(&mut Context::from_waker(&waker)),
+            Poll::Ready(Ok(()))
+        ));
+        assert!(matches!(poll_flush(&journal), Poll::Ready(Ok(()))));
+        assert!(journal.push(4));
+        assert_eq!(journal.take(), [4]);
+        assert!(journal.take().is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let journal = Journal::default();
+        assert!(journal.push(1));
+        journal.fail(io::Error::from(ErrorKind::StorageFull));
+        journal.fail(io::Error::from(ErrorKind::NotFound));
+        assert_eq!(journal.take(), [1]);
+        assert!(journal.take().is_empty());
+
+        // the first error is reported by the next flush only
+        match poll_flush(&journal) {
+            Poll::Ready(Err(err)) => assert_eq!(err.kind(), ErrorKind::StorageFull),
+            _ => panic!("the flush should fail"),
+        }
+        assert!(matches!(poll_flush(&journal), Poll::Ready(Ok(()))));
+    }
+}
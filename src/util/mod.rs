@@ -0,0 +1,1482 @@
+mod clock;
+#[cfg(all(feature = "wasm", any(target_arch = "wasm32", test)))]
+pub(crate) mod journal;
+pub mod path;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub(crate) mod web;
+
+use crate::error::VfsErrorKind;
+use crate::file::{DirEntry, FileSystemStats, FileType, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::FileSystem;
+use normalize_path::NormalizePath;
+use path_slash::PathBufExt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::iter;
+use std::iter::once;
+use std::ops::{Bound, Not, RangeBounds};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+pub(crate) use clock::{now, sleep, Instant};
+
+/// Iterates over all path components.
+///
+/// # Arguments
+/// `path`: The current path.  
+///
+/// # Example
+/// ```
+/// use std::path::Path;
+/// use virtual_filesystem::util::component_iter;
+///
+/// itertools::assert_equal(
+///     component_iter(Path::new("../many/files/and/directories/")),
+///     vec!["many", "files", "and", "directories"],
+/// );
+/// ```
+pub fn component_iter(path: &Path) -> impl DoubleEndedIterator<Item = &str> {
+    path.components().filter_map(|component| {
+        if let Component::Normal(component) = component {
+            component.to_str()
+        } else {
+            None
+        }
+    })
+}
+
+/// Creates all directories by iteratively creating parent directories. Returns an error if the operation fails for
+/// any reason other than `AlreadyExists`.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `path`: The path of the directory to create.  
+pub fn create_dir_all<FS: FileSystem + ?Sized>(fs: &FS, path: &str) -> crate::Result<()> {
+    let normalized = normalize_path(make_relative(path));
+
+    for path in parent_iter(&normalized).chain(once(normalized.as_ref())) {
+        // unwrap: `path` should already be a valid UTF-8 string
+        if let Err(err) = fs.create_dir(path.to_str().unwrap()) {
+            if err.kind() != ErrorKind::AlreadyExists {
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies a file by reading it and writing its contents to the destination.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `from`: The path of the file to copy.  
+/// `to`: The path to copy the file to.  
+pub fn copy_file<FS: FileSystem + ?Sized>(fs: &FS, from: &str, to: &str) -> crate::Result<u64> {
+    let mut source = fs.open_file(from)?;
+    if !source.metadata()?.is_file() {
+        return Err(invalid_input("Source is not a file"));
+    }
+
+    io::copy(&mut source, &mut fs.create_file(to)?)
+}
+
+/// Renames a file by copying it and removing the original. Directories can't be renamed this way.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `from`: The path of the file to rename.  
+/// `to`: The new path of the file.  
+pub fn rename<FS: FileSystem + ?Sized>(fs: &FS, from: &str, to: &str) -> crate::Result<()> {
+    if !fs.metadata(from)?.is_file() {
+        return Err(not_supported());
+    }
+    if normalize_path(make_relative(from)) == normalize_path(make_relative(to)) {
+        return Ok(());
+    }
+
+    fs.copy_file(from, to)?;
+    fs.remove_file(from)
+}
+
+/// Changes the length of the file at `path` to `len`, extending it with zeros. Files that can't be shrunk in place
+/// are shrunk by rewriting the contents that are kept.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `path`: The path of the file.  
+/// `len`: The new length of the file.  
+pub fn set_len<FS: FileSystem + ?Sized>(fs: &FS, path: &str, len: u64) -> crate::Result<()> {
+    let options = OpenOptions {
+        read: true,
+        write: true,
+        ..OpenOptions::default()
+    };
+    let mut file = fs.open_file_options(path, &options)?;
+    match file.set_len(len) {
+        Err(err) if err.kind() == ErrorKind::Unsupported => {}
+        result => return result.and_then(|_| file.flush()),
+    }
+
+    let mut contents = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    (&mut file).take(len).read_to_end(&mut contents)?;
+    // the file is closed before it's reopened, since some filesystems lock open files
+    drop(file);
+    let options = OpenOptions {
+        write: true,
+        truncate: true,
+        ..OpenOptions::default()
+    };
+    let mut file = fs.open_file_options(path, &options)?;
+    file.write_all(&contents)?;
+    file.flush()
+}
+
+/// Returns the capacity of the volume backing `fs`, as servers report it. Filesystems that aren't backed by a volume
+/// are reported as empty, with 4 KiB blocks.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+pub fn stats_or_empty<FS: FileSystem + ?Sized>(fs: &FS) -> FileSystemStats {
+    fs.stats()
+        .map(|stats| FileSystemStats {
+            block_size: stats.block_size.max(1),
+            ..stats
+        })
+        .unwrap_or(FileSystemStats {
+            total_space: 0,
+            free_space: 0,
+            available_space: 0,
+            block_size: 4096,
+        })
+}
+
+/// Returns the metadata of the entry at `path`, where the root is a directory even if the filesystem has no metadata
+/// for it, as servers need to list it.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `path`: The path of the entry.  
+pub fn metadata_or_root<FS: FileSystem + ?Sized>(fs: &FS, path: &str) -> crate::Result<Metadata> {
+    match fs.metadata(path) {
+        Err(_) if normalize_and_relativize(path).as_os_str().is_empty() => {
+            Ok(Metadata::directory())
+        }
+        result => result,
+    }
+}
+
+/// Recursively walks the directory at `path` by reading each directory in turn. Entries are returned depth-first,
+/// with each directory preceding its contents, and their paths are relative to the root of the filesystem.
+/// Directories are read as the walk reaches them, and errors are returned where they occur.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `path`: The path of the directory to walk.  
+pub fn walk_dir<'a, FS: FileSystem + ?Sized>(
+    fs: &'a FS,
+    path: &str,
+) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>> + 'a>> {
+    let mut stack = vec![fs.read_dir(path)?];
+    let mut pending_error = None;
+
+    Ok(Box::new(iter::from_fn(move || loop {
+        if let Some(err) = pending_error.take() {
+            return Some(Err(err));
+        }
+
+        let entry = match stack.last_mut()?.next() {
+            Some(Ok(entry)) => entry,
+            Some(Err(err)) => return Some(Err(err)),
+            None => {
+                stack.pop();
+                continue;
+            }
+        };
+
+        if entry.metadata.is_directory() {
+            match entry
+                .path
+                .to_str()
+                .ok_or_else(invalid_path)
+                .and_then(|path| fs.read_dir(path))
+            {
+                Ok(dir) => stack.push(dir),
+                Err(err) => pending_error = Some(err),
+            }
+        }
+
+        return Some(Ok(entry));
+    })))
+}
+
+/// Options for `sync`. By default, new and changed files are copied, and nothing is deleted.
+#[derive(Clone, Debug, Default)]
+pub struct SyncOptions {
+    /// True if the entries of the destination that aren't in the source are removed.
+    pub delete: bool,
+    /// True if the changes are only listed, without being made.
+    pub dry_run: bool,
+    /// True if files of the same length are always compared by their contents, rather than by their modification
+    /// times where both filesystems know them.
+    pub checksum: bool,
+}
+
+impl SyncOptions {
+    /// # Arguments
+    /// `delete`: If true, the entries of the destination that aren't in the source are removed.  
+    pub fn delete(mut self, delete: bool) -> Self {
+        self.delete = delete;
+        self
+    }
+
+    /// # Arguments
+    /// `dry_run`: If true, the changes are only listed, without being made.  
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// # Arguments
+    /// `checksum`: If true, files of the same length are always compared by their contents.  
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+}
+
+/// A change that `sync` makes to the destination.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SyncAction {
+    /// The directory is created.
+    CreateDir(PathBuf),
+    /// The file, which isn't in the destination, is copied.
+    CopyFile(PathBuf),
+    /// The file, which differs in the destination, is copied over it.
+    UpdateFile(PathBuf),
+    /// The file is removed.
+    RemoveFile(PathBuf),
+    /// The directory is removed.
+    RemoveDir(PathBuf),
+}
+
+/// Makes `dst` match `src` by creating the directories and copying the files that are new or changed, and optionally
+/// removing the entries that aren't in `src`, like a one-way `rsync` between any two filesystems. Entries whose type
+/// differs are always replaced. Files are changed if their lengths differ, if the file in `src` was modified later
+/// than the one in `dst`, or if their contents differ where the modification times aren't known. Returns the changes
+/// in the order they're made.
+///
+/// # Arguments
+/// `src`: The filesystem to copy from.  
+/// `dst`: The filesystem to make match `src`.  
+/// `options`: The options of the sync.  
+pub fn sync<S: FileSystem + ?Sized, D: FileSystem + ?Sized>(
+    src: &S,
+    dst: &D,
+    options: SyncOptions,
+) -> crate::Result<Vec<SyncAction>> {
+    let src_entries = src
+        .walk_dir("")?
+        .map(|entry| entry.map(|entry| (entry.path, entry.metadata)))
+        .collect::<crate::Result<BTreeMap<_, _>>>()?;
+    let dst_entries = dst
+        .walk_dir("")?
+        .map(|entry| entry.map(|entry| (entry.path, entry.metadata)))
+        .collect::<crate::Result<BTreeMap<_, _>>>()?;
+
+    // entries are replaced if their type differs, along with everything within them
+    let replaced = dst_entries
+        .iter()
+        .filter(|(path, metadata)| {
+            src_entries
+                .get(*path)
+                .is_some_and(|src_metadata| src_metadata.file_type != metadata.file_type)
+        })
+        .map(|(path, _)| path.as_path())
+        .collect::<BTreeSet<_>>();
+    let is_replaced = |path: &Path| path.ancestors().any(|path| replaced.contains(path));
+
+    // children are removed before their parents
+    let mut actions = Vec::new();
+    for (path, metadata) in dst_entries.iter().rev() {
+        if is_replaced(path) || (options.delete && !src_entries.contains_key(path)) {
+            actions.push(match metadata.is_directory() {
+                true => SyncAction::RemoveDir(path.clone()),
+                false => SyncAction::RemoveFile(path.clone()),
+            });
+        }
+    }
+
+    // parents are created before their children
+    for (path, metadata) in &src_entries {
+        let dst_metadata = dst_entries.get(path).filter(|_| !is_replaced(path));
+        let action = match (metadata.is_directory(), dst_metadata) {
+            (true, None) => SyncAction::CreateDir(path.clone()),
+            (true, Some(_)) => continue,
+            (false, None) => SyncAction::CopyFile(path.clone()),
+            (false, Some(dst_metadata)) => {
+                let path_str = path.to_str().ok_or_else(invalid_path)?;
+                let changed = match (metadata.modified, dst_metadata.modified) {
+                    _ if metadata.len != dst_metadata.len => true,
+                    (Some(modified), Some(dst_modified)) if !options.checksum => {
+                        modified > dst_modified
+                    }
+                    _ => {
+                        src.open_file(path_str)?.read_into_vec()?
+                            != dst.open_file(path_str)?.read_into_vec()?
+                    }
+                };
+                if !changed {
+                    continue;
+                }
+                SyncAction::UpdateFile(path.clone())
+            }
+        };
+        actions.push(action);
+    }
+
+    if !options.dry_run {
+        for action in &actions {
+            match action {
+                SyncAction::CreateDir(path) => {
+                    dst.create_dir(path.to_str().ok_or_else(invalid_path)?)?
+                }
+                SyncAction::CopyFile(path) | SyncAction::UpdateFile(path) => {
+                    let path = path.to_str().ok_or_else(invalid_path)?;
+                    let mut file = dst.create_file(path)?;
+                    io::copy(&mut src.open_file(path)?, &mut file)?;
+                    file.flush()?;
+                }
+                SyncAction::RemoveFile(path) => {
+                    dst.remove_file(path.to_str().ok_or_else(invalid_path)?)?
+                }
+                SyncAction::RemoveDir(path) => {
+                    dst.remove_dir(path.to_str().ok_or_else(invalid_path)?)?
+                }
+            }
+        }
+    }
+
+    Ok(actions)
+}
+
+/// An algorithm that `hash_tree` hashes with.
+#[cfg(feature = "checksum")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HashAlgorithm {
+    /// SHA-1, with 20-byte digests.
+    Sha1,
+    /// SHA-256, with 32-byte digests.
+    Sha256,
+}
+
+/// The hashes of the files and directories within a directory, as computed by `hash_tree`.
+#[cfg(feature = "checksum")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HashTree {
+    /// The digest of the directory.
+    pub digest: Vec<u8>,
+    /// The digests of the files and directories within the directory, by their paths relative to the root of the
+    /// filesystem.
+    pub entries: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+/// A hash being computed.
+#[cfg(feature = "checksum")]
+enum Hasher {
+    Sha1(sha1_smol::Sha1),
+    Sha256(hmac_sha256::Hash),
+}
+
+#[cfg(feature = "checksum")]
+impl Hasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha1 => Self::Sha1(sha1_smol::Sha1::new()),
+            HashAlgorithm::Sha256 => Self::Sha256(hmac_sha256::Hash::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha1(hasher) => hasher.update(data),
+            Self::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Sha1(hasher) => hasher.digest().bytes().to_vec(),
+            Self::Sha256(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+/// Computes the hash of every file within the directory at `root`, and a Merkle digest of every directory, such as to
+/// verify that an extracted install matches the archive it was shipped in. A file's digest is the hash of its
+/// contents, and a directory's is the hash of its entries sorted by name, each as `d` or `f` for its type, its name, a
+/// zero byte and its digest. Directories with the same contents have the same digest wherever they are.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `root`: The path of the directory to hash.  
+/// `algorithm`: The algorithm to hash with.  
+#[cfg(feature = "checksum")]
+pub fn hash_tree<FS: FileSystem + ?Sized>(
+    fs: &FS,
+    root: &str,
+    algorithm: HashAlgorithm,
+) -> crate::Result<HashTree> {
+    let root = normalize_and_relativize(root);
+    let mut entries = BTreeMap::new();
+    let mut directories = BTreeSet::new();
+    for entry in fs.walk_dir(root.to_str().ok_or_else(invalid_path)?)? {
+        let entry = entry?;
+        if entry.metadata.is_directory() {
+            directories.insert(entry.path);
+            continue;
+        }
+
+        let mut file = fs.open_file(entry.path.to_str().ok_or_else(invalid_path)?)?;
+        let mut hasher = Hasher::new(algorithm);
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read_len) => hasher.update(&buf[..read_len]),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        entries.insert(entry.path, hasher.finalize());
+    }
+
+    // the entries of each directory, by name, are complete once everything after it in path order has been seen
+    let mut children = BTreeMap::<PathBuf, BTreeMap<String, (bool, Vec<u8>)>>::new();
+    let paths = entries
+        .keys()
+        .chain(&directories)
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    let digest_directory = |children: Option<BTreeMap<String, (bool, Vec<u8>)>>| {
+        let mut hasher = Hasher::new(algorithm);
+        for (name, (is_directory, digest)) in children.into_iter().flatten() {
+            hasher.update(if is_directory { b"d" } else { b"f" });
+            hasher.update(name.as_bytes());
+            hasher.update(&[0]);
+            hasher.update(&digest);
+        }
+        hasher.finalize()
+    };
+    for path in paths.iter().rev() {
+        let is_directory = directories.contains(path);
+        if is_directory {
+            let digest = digest_directory(children.remove(path));
+            entries.insert(path.clone(), digest);
+        }
+
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(invalid_path)?;
+        children
+            .entry(path.parent().unwrap_or(Path::new("")).to_owned())
+            .or_default()
+            .insert(name.to_owned(), (is_directory, entries[path].clone()));
+    }
+
+    Ok(HashTree {
+        digest: digest_directory(children.remove(&root)),
+        entries,
+    })
+}
+
+/// The space taken by the files within a directory, as computed by `disk_usage`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DiskUsage {
+    /// The total length of the files, in bytes.
+    pub bytes: u64,
+    /// The number of files.
+    pub files: u64,
+    /// The number of directories.
+    pub directories: u64,
+}
+
+/// Totals the lengths of the files within the directory at `root` and counts its files and directories, for it and
+/// for every directory within it, like `du`. Each directory's usage includes that of its subdirectories, and paths
+/// are relative to the root of the filesystem. Entries are counted as `FileSystem::walk_dir` returns them, so only the
+/// totals are kept.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `root`: The path of the directory to total.  
+pub fn disk_usage<FS: FileSystem + ?Sized>(
+    fs: &FS,
+    root: &str,
+) -> crate::Result<BTreeMap<PathBuf, DiskUsage>> {
+    let root = normalize_and_relativize(root);
+    let mut usage = BTreeMap::from([(root.clone(), DiskUsage::default())]);
+    for entry in fs.walk_dir(root.to_str().ok_or_else(invalid_path)?)? {
+        let entry = entry?;
+        if entry.metadata.is_directory() {
+            usage.entry(entry.path.clone()).or_default();
+        }
+
+        for directory in entry.path.ancestors().skip(1) {
+            let directory_usage = usage.entry(directory.to_owned()).or_default();
+            if entry.metadata.is_directory() {
+                directory_usage.directories += 1;
+            } else {
+                directory_usage.files += 1;
+                directory_usage.bytes += entry.metadata.len();
+            }
+            if directory == root {
+                break;
+            }
+        }
+    }
+    Ok(usage)
+}
+
+/// A condition on the entries that `find` returns. Conditions compose with `and`, `or` and `!`.
+///
+/// # Example
+/// ```
+/// use virtual_filesystem::file::FileType;
+/// use virtual_filesystem::util::Predicate;
+///
+/// // large files that aren't logs
+/// let predicate = Predicate::file_type(FileType::File)
+///     .and(Predicate::size(1024 * 1024..))
+///     .and(!Predicate::name("*.log"));
+/// ```
+#[derive(Clone, Debug)]
+pub enum Predicate {
+    /// Matches entries whose names match a glob.
+    Name(String),
+    /// Matches files whose lengths are within the bounds, in bytes.
+    Size(Bound<u64>, Bound<u64>),
+    /// Matches entries of the type.
+    FileType(FileType),
+    /// Matches entries that were last modified within the bounds. Entries whose modification times aren't known don't
+    /// match.
+    Modified(Bound<SystemTime>, Bound<SystemTime>),
+    /// Matches entries that match both predicates.
+    And(Box<Predicate>, Box<Predicate>),
+    /// Matches entries that match either predicate.
+    Or(Box<Predicate>, Box<Predicate>),
+    /// Matches entries that don't match the predicate.
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    /// Matches entries whose names match `glob`, where `*` matches any number of characters and `?` matches one.
+    ///
+    /// # Arguments
+    /// `glob`: The glob, such as `*.txt`.  
+    pub fn name(glob: &str) -> Self {
+        Self::Name(glob.to_owned())
+    }
+
+    /// Matches files whose lengths are within `range`, in bytes. Directories don't match.
+    ///
+    /// # Arguments
+    /// `range`: The range of lengths, such as `1024..`.  
+    pub fn size<R: RangeBounds<u64>>(range: R) -> Self {
+        Self::Size(range.start_bound().cloned(), range.end_bound().cloned())
+    }
+
+    /// Matches entries of a type.
+    ///
+    /// # Arguments
+    /// `file_type`: The type of the entries.  
+    pub fn file_type(file_type: FileType) -> Self {
+        Self::FileType(file_type)
+    }
+
+    /// Matches entries that were last modified within `range`.
+    ///
+    /// # Arguments
+    /// `range`: The range of modification times.  
+    pub fn modified<R: RangeBounds<SystemTime>>(range: R) -> Self {
+        Self::Modified(range.start_bound().cloned(), range.end_bound().cloned())
+    }
+
+    /// Matches entries that match both this and `other`.
+    ///
+    /// # Arguments
+    /// `other`: The other predicate.  
+    pub fn and(self, other: Predicate) -> Self {
+        Self::And(Box::new(self), Box::new(other))
+    }
+
+    /// Matches entries that match either this or `other`.
+    ///
+    /// # Arguments
+    /// `other`: The other predicate.  
+    pub fn or(self, other: Predicate) -> Self {
+        Self::Or(Box::new(self), Box::new(other))
+    }
+
+    /// Returns true if `entry` matches the predicate.
+    ///
+    /// # Arguments
+    /// `entry`: The entry.  
+    pub fn matches(&self, entry: &DirEntry) -> bool {
+        match self {
+            Self::Name(glob) => entry
+                .path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| glob_matches(glob, name)),
+            Self::Size(start, end) => {
+                entry.metadata.is_file() && (*start, *end).contains(&entry.metadata.len())
+            }
+            Self::FileType(file_type) => entry.metadata.file_type == *file_type,
+            Self::Modified(start, end) => entry
+                .metadata
+                .modified
+                .is_some_and(|modified| (*start, *end).contains(&modified)),
+            Self::And(a, b) => a.matches(entry) && b.matches(entry),
+            Self::Or(a, b) => a.matches(entry) || b.matches(entry),
+            Self::Not(predicate) => !predicate.matches(entry),
+        }
+    }
+}
+
+impl Not for Predicate {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self::Not(Box::new(self))
+    }
+}
+
+/// Returns true if `name` matches `glob`, where `*` matches any number of characters and `?` matches one.
+///
+/// # Arguments
+/// `glob`: The glob.  
+/// `name`: The name to match.  
+fn glob_matches(glob: &str, name: &str) -> bool {
+    let (glob, name) = (
+        glob.chars().collect::<Vec<_>>(),
+        name.chars().collect::<Vec<_>>(),
+    );
+    let (mut glob_pos, mut name_pos) = (0, 0);
+    // the position of the last `*` and of the name when it was reached, to backtrack to
+    let mut star = None;
+    while name_pos < name.len() {
+        match glob.get(glob_pos) {
+            Some('*') => {
+                star = Some((glob_pos, name_pos));
+                glob_pos += 1;
+            }
+            Some(c) if *c == '?' || *c == name[name_pos] => {
+                glob_pos += 1;
+                name_pos += 1;
+            }
+            _ => match star {
+                // let the last `*` match one more character
+                Some((star_glob_pos, star_name_pos)) => {
+                    star = Some((star_glob_pos, star_name_pos + 1));
+                    glob_pos = star_glob_pos + 1;
+                    name_pos = star_name_pos + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[glob_pos..].iter().all(|c| *c == '*')
+}
+
+/// Walks the directory at `root` and returns the entries that match `predicate`, as they're walked. Entries are
+/// returned in the order of `FileSystem::walk_dir`, and errors are passed through.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `root`: The path of the directory to search.  
+/// `predicate`: The condition the entries must match.  
+pub fn find<'a, FS: FileSystem + ?Sized>(
+    fs: &'a FS,
+    root: &str,
+    predicate: Predicate,
+) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>> + 'a>> {
+    Ok(Box::new(fs.walk_dir(root)?.filter(move |entry| {
+        entry
+            .as_ref()
+            .map_or(true, |entry| predicate.matches(entry))
+    })))
+}
+
+/// Normalizes a path by stripping slashes, resolving backtracking, and using forward slashes. Virtual paths are
+/// normalized the same way on every host, so Windows prefixes aren't recognized; host paths are resolved by the
+/// physical filesystem instead.
+///
+/// # Arguments
+/// `path`: The path to normalize.  
+///
+/// # Example
+/// ```
+/// use std::path::Path;
+/// use virtual_filesystem::util::normalize_path;
+///
+/// assert_eq!(normalize_path("///////"), Path::new("/"));
+/// assert_eq!(normalize_path("./test/something/../"), Path::new("test"));
+/// assert_eq!(normalize_path("../test"), Path::new("test"));
+/// ```
+pub fn normalize_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = with_forward_slashes(path);
+    Path::new(path.normalize().to_slash_lossy().as_ref()).to_owned()
+}
+
+/// Produces an iterator iterating over all parent directories, exclusive of `path`.
+///
+/// # Arguments
+/// `path`: The current path.  
+///
+/// # Example
+/// ```
+/// use std::path::Path;
+/// use virtual_filesystem::util::parent_iter;
+///
+/// itertools::assert_equal(
+///     parent_iter(Path::new("/many/files/and/directories")),
+///     vec![
+///         Path::new("/many/files/and"),
+///         Path::new("/many/files"),
+///         Path::new("/many"),
+///         Path::new("/"),
+///     ],
+/// );
+///
+/// itertools::assert_equal(
+///     parent_iter(Path::new("../many/files/and/directories")),
+///     vec![
+///         Path::new("../many/files/and"),
+///         Path::new("../many/files"),
+///         Path::new("../many"),
+///         Path::new(".."),
+///     ],
+/// );
+/// ```
+pub fn parent_iter(path: &Path) -> impl DoubleEndedIterator<Item = &Path> {
+    // collect parent paths
+    path.ancestors()
+        .filter(|path| !path.as_os_str().is_empty())
+        .skip(1)
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+/// Trims the `/` and `\\` roots off of the beginning path, making it relative.
+pub(crate) fn make_relative<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = with_forward_slashes(path);
+    path.to_str().unwrap_or("").trim_start_matches('/').into()
+}
+
+/// Replaces all `\\` separators with `/`, so virtual paths behave the same on every host.
+pub(crate) fn with_forward_slashes<P: AsRef<Path>>(path: P) -> PathBuf {
+    path.as_ref()
+        .to_str()
+        .unwrap_or("")
+        .replace('\\', "/")
+        .into()
+}
+
+/// Encodes `data` as lowercase hexadecimal.
+#[cfg(any(feature = "dedup", feature = "s3"))]
+pub(crate) fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The alphabet of Base64.
+#[cfg(any(feature = "ssh-server", feature = "websocket"))]
+pub(crate) const BASE64: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes `data` with padded Base64.
+#[cfg(any(feature = "ssh-server", feature = "websocket"))]
+pub(crate) fn encode_base64(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (n, byte)| {
+            bits | (*byte as u32) << (16 - 8 * n)
+        });
+        for n in 0..4 {
+            match n <= chunk.len() {
+                true => encoded.push(BASE64[(bits >> (18 - 6 * n) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+/// Returns an error indicating that the path already exists.
+pub(crate) fn already_exists() -> io::Error {
+    io::Error::new(ErrorKind::AlreadyExists, "Already exists")
+}
+
+/// Returns an error indicating that the path already exists.
+pub(crate) fn invalid_input(error: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, error)
+}
+
+/// Returns an error indicating that the path already exists.
+pub(crate) fn invalid_path() -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, "Invalid path")
+}
+
+/// Returns an error indicating that the file was not found.
+pub(crate) fn not_found() -> io::Error {
+    io::Error::new(ErrorKind::NotFound, "File not found")
+}
+
+/// Returns an error indicating that the operation is not supported.
+pub(crate) fn not_supported() -> io::Error {
+    io::Error::new(ErrorKind::Unsupported, "Not supported")
+}
+
+/// Returns an error indicating that the file wasn't opened for writing.
+pub(crate) fn not_writable() -> io::Error {
+    io::Error::new(ErrorKind::PermissionDenied, "File not opened for writing")
+}
+
+/// Returns an error indicating that the filesystem is read-only.
+pub(crate) fn read_only() -> io::Error {
+    VfsErrorKind::ReadOnlyFilesystem.into()
+}
+
+/// Returns an error indicating that a component of the path isn't a directory.
+pub(crate) fn not_a_directory() -> io::Error {
+    VfsErrorKind::NotADirectory.into()
+}
+
+/// Returns an error indicating that the path is a directory, where a file was expected.
+pub(crate) fn is_a_directory() -> io::Error {
+    VfsErrorKind::IsADirectory.into()
+}
+
+/// Returns an error indicating that the operation spans two mounted filesystems.
+pub(crate) fn crosses_mount_point() -> io::Error {
+    VfsErrorKind::CrossesMountPoint.into()
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::file::{DirEntry, File, FileSystemStats, FileType, Metadata, OpenOptions};
+    use crate::memory_fs::MemoryFS;
+    use crate::util::{
+        component_iter, create_dir_all, glob_matches, make_relative, normalize_path, now,
+        parent_iter, DiskUsage, Predicate, SyncAction, SyncOptions,
+    };
+    use crate::{util, FileSystem, MockFileSystem};
+    use itertools::Itertools;
+    use std::collections::BTreeMap;
+    use std::io;
+    use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+    use std::iter;
+    use std::iter::once;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    /// Reads the directory and sorts all entries into a map.
+    pub(crate) fn read_directory<F: FileSystem + ?Sized>(
+        fs: &F,
+        dir: &str,
+    ) -> BTreeMap<String, Metadata> {
+        fs.read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.path.to_str().unwrap().to_owned(), entry.metadata)
+            })
+            .collect()
+    }
+
+    /// Checks that `fs` opens files with the semantics of `std::fs::OpenOptions`, using the file at `path`, which
+    /// must not exist yet.
+    pub(crate) fn check_open_options<F: FileSystem + ?Sized>(fs: &F, path: &str) {
+        let error = |options: OpenOptions| {
+            fs.open_file_options(path, &options)
+                .map(drop)
+                .unwrap_err()
+                .kind()
+        };
+        let write = |options: OpenOptions, contents: &str| {
+            let mut file = fs.open_file_options(path, &options).unwrap();
+            file.write_all(contents.as_bytes()).unwrap();
+            file.flush().unwrap();
+        };
+        let read = || fs.open_file(path).unwrap().read_into_string().unwrap();
+
+        // invalid combinations are rejected before anything is created
+        for options in [
+            OpenOptions::new(),
+            OpenOptions::default().create(true),
+            OpenOptions::default().create_new(true),
+            OpenOptions::default().truncate(true),
+            OpenOptions::new().append(true).truncate(true),
+        ] {
+            assert_eq!(error(options), ErrorKind::InvalidInput);
+        }
+        assert!(!fs.exists(path).unwrap());
+
+        assert_eq!(error(OpenOptions::new().write(true)), ErrorKind::NotFound);
+        write(
+            OpenOptions::new().write(true).create_new(true),
+            "hello world",
+        );
+        assert_eq!(
+            error(OpenOptions::new().write(true).create_new(true)),
+            ErrorKind::AlreadyExists
+        );
+
+        // writes overwrite the contents in place, and appends grant write access by themselves
+        write(OpenOptions::new().write(true).create(true), "HELLO");
+        assert_eq!(read(), "HELLO world");
+        write(OpenOptions::new().append(true), "!");
+        assert_eq!(read(), "HELLO world!");
+
+        write(OpenOptions::new().write(true).truncate(true), "bye");
+        assert_eq!(read(), "bye");
+    }
+
+    /// Checks that files of `fs` are resized in place by `File::set_len`, using the file at `path`, which must not
+    /// exist yet.
+    pub(crate) fn check_set_len<F: FileSystem + ?Sized>(fs: &F, path: &str) {
+        let mut file = fs.create_file(path).unwrap();
+        file.write_all(b"hello world").unwrap();
+        file.seek(SeekFrom::Start(3)).unwrap();
+
+        // the cursor stays put, so writes land where they would have
+        file.set_len(5).unwrap();
+        assert_eq!(file.stream_position().unwrap(), 3);
+        file.set_len(8).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 8);
+        file.write_all(b"LO").unwrap();
+        file.flush().unwrap();
+        drop(file);
+        assert_eq!(
+            fs.open_file(path).unwrap().read_into_vec().unwrap(),
+            b"helLO\0\0\0"
+        );
+
+        let mut file = fs
+            .open_file_options(path, &OpenOptions::new().write(true))
+            .unwrap();
+        file.set_len(0).unwrap();
+        file.flush().unwrap();
+        drop(file);
+        assert_eq!(fs.metadata(path).unwrap().len(), 0);
+    }
+
+    /// A file that resizes with the default `File::set_len`, which can't shrink files.
+    pub(crate) struct DefaultLenFile(pub(crate) Box<dyn File>);
+
+    impl Read for DefaultLenFile {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for DefaultLenFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl Seek for DefaultLenFile {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    impl File for DefaultLenFile {
+        fn metadata(&self) -> crate::Result<Metadata> {
+            self.0.metadata()
+        }
+    }
+
+    #[test]
+    fn copy_and_rename() {
+        let fs = MemoryFS::default();
+        fs.create_dir("folder").unwrap();
+        write!(fs.create_file("file").unwrap(), "file").unwrap();
+
+        assert_eq!(fs.copy_file("file", "folder/copy").unwrap(), 4);
+        fs.rename("/folder/copy", "folder/renamed").unwrap();
+        fs.rename("folder/renamed", "./folder//renamed").unwrap();
+        assert!(!fs.exists("folder/copy").unwrap());
+        assert_eq!(
+            fs.open_file("folder/renamed")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "file"
+        );
+        assert_eq!(fs.metadata("file").unwrap(), Metadata::file(4));
+
+        assert!(fs.copy_file("nonsense", "copy").is_err());
+        assert_eq!(
+            fs.rename("folder", "renamed").unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    fn set_len() {
+        // memory files are resized in place
+        let fs = MemoryFS::default();
+        write!(fs.create_file("file").unwrap(), "hello world").unwrap();
+        util::set_len(&fs, "file", 5).unwrap();
+        assert_eq!(fs.metadata("file").unwrap(), Metadata::file(5));
+        util::set_len(&fs, "file", 7).unwrap();
+        assert_eq!(
+            fs.open_file("file").unwrap().read_into_vec().unwrap(),
+            b"hello\0\0"
+        );
+
+        // files that can't be shrunk in place are rewritten
+        let memory_fs = Arc::new(MemoryFS::default());
+        write!(memory_fs.create_file("file").unwrap(), "hello world").unwrap();
+        let mut fs = MockFileSystem::new();
+        let inner = memory_fs.clone();
+        fs.expect_open_file_options()
+            .returning(move |path, options| {
+                let file = inner.open_file_options(path, options)?;
+                Ok(Box::new(DefaultLenFile(file)))
+            });
+        util::set_len(&fs, "file", 5).unwrap();
+        util::set_len(&fs, "file", 7).unwrap();
+        assert_eq!(
+            memory_fs
+                .open_file("file")
+                .unwrap()
+                .read_into_vec()
+                .unwrap(),
+            b"hello\0\0"
+        );
+        util::set_len(&fs, "file", 0).unwrap();
+        assert_eq!(memory_fs.metadata("file").unwrap().len(), 0);
+
+        assert_eq!(
+            util::set_len(&fs, "missing", 1).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn components() {
+        itertools::assert_equal(
+            component_iter(Path::new("../many/files/and/directories/")),
+            vec!["many", "files", "and", "directories"],
+        );
+    }
+
+    const TARGET_DIR: &str = "/some/directory/somewhere/";
+
+    #[test]
+    fn create_all_happy_case() {
+        let mut mock_fs = MockFileSystem::new();
+
+        let mut i = 0;
+        mock_fs.expect_create_dir().times(3).returning(move |_| {
+            i += 1;
+
+            if i == 1 {
+                Err(io::Error::new(ErrorKind::AlreadyExists, ""))
+            } else {
+                Ok(())
+            }
+        });
+
+        assert!(create_dir_all(&mock_fs, TARGET_DIR).is_ok())
+    }
+
+    #[test]
+    fn create_all_error() {
+        let mut mock_fs = MockFileSystem::new();
+
+        mock_fs
+            .expect_create_dir()
+            .returning(|_| Err(io::Error::new(ErrorKind::Unsupported, "")));
+
+        assert!(create_dir_all(&mock_fs, TARGET_DIR).is_err())
+    }
+
+    #[test]
+    fn walk_dir() {
+        let fs = MemoryFS::default();
+        fs.create_dir_all("a/b/c").unwrap();
+        fs.create_file("a/b/file").unwrap();
+        fs.create_file("top").unwrap();
+
+        let mut entries = util::walk_dir(&fs, "/")
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.path.to_str().unwrap().to_owned(), entry.metadata)
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        assert_eq!(
+            entries,
+            vec![
+                ("a".to_owned(), Metadata::directory()),
+                ("a/b".to_owned(), Metadata::directory()),
+                ("a/b/c".to_owned(), Metadata::directory()),
+                ("a/b/file".to_owned(), Metadata::file(0)),
+                ("top".to_owned(), Metadata::file(0)),
+            ]
+        );
+
+        itertools::assert_equal(
+            util::walk_dir(&fs, "a/b")
+                .unwrap()
+                .map(|entry| entry.unwrap().path)
+                .sorted(),
+            vec![Path::new("a/b/c"), Path::new("a/b/file")],
+        );
+    }
+
+    #[test]
+    fn metadata_or_root() {
+        let mut mock_fs = MockFileSystem::new();
+        mock_fs
+            .expect_metadata()
+            .returning(|_| Err(ErrorKind::NotFound.into()));
+
+        for root in ["", "/", "."] {
+            assert_eq!(
+                util::metadata_or_root(&mock_fs, root).unwrap(),
+                Metadata::directory()
+            );
+        }
+        assert_eq!(
+            util::metadata_or_root(&mock_fs, "missing")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn stats_or_empty() {
+        let stats = FileSystemStats {
+            total_space: 8192,
+            free_space: 4096,
+            available_space: 2048,
+            block_size: 0,
+        };
+        let mut mock_fs = MockFileSystem::new();
+        mock_fs.expect_stats().times(1).returning(move || Ok(stats));
+        mock_fs
+            .expect_stats()
+            .returning(|| Err(ErrorKind::Unsupported.into()));
+
+        // blocks are never empty, and filesystems without a volume have no space
+        assert_eq!(
+            util::stats_or_empty(&mock_fs),
+            FileSystemStats {
+                block_size: 1,
+                ..stats
+            }
+        );
+        assert_eq!(
+            util::stats_or_empty(&mock_fs),
+            FileSystemStats {
+                total_space: 0,
+                free_space: 0,
+                available_space: 0,
+                block_size: 4096,
+            }
+        );
+    }
+
+    #[test]
+    fn walk_dir_lazily() {
+        let entries = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| {
+                    Ok(DirEntry {
+                        path: (*name).into(),
+                        metadata: Metadata::directory(),
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut mock_fs = MockFileSystem::new();
+        mock_fs
+            .expect_read_dir()
+            .withf(|path| path.is_empty())
+            .returning(move |_| Ok(Box::new(entries(&["a", "b"]).into_iter())));
+        mock_fs
+            .expect_read_dir()
+            .withf(|path| path == "a")
+            .returning(|_| Err(ErrorKind::PermissionDenied.into()));
+
+        // `b` is only read once the walk reaches it, and the error is returned in its place
+        let mut walk = util::walk_dir(&mock_fs, "").unwrap();
+        assert_eq!(walk.next().unwrap().unwrap().path, Path::new("a"));
+        assert_eq!(
+            walk.next().unwrap().unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn sync() {
+        let src = MemoryFS::default();
+        src.create_dir_all("a/b").unwrap();
+        write!(src.create_file("a/b/file").unwrap(), "file").unwrap();
+        write!(src.create_file("same").unwrap(), "same").unwrap();
+        write!(src.create_file("changed").unwrap(), "new").unwrap();
+        write!(src.create_file("replaced").unwrap(), "file").unwrap();
+
+        let dst = MemoryFS::default();
+        write!(dst.create_file("same").unwrap(), "same").unwrap();
+        write!(dst.create_file("changed").unwrap(), "old").unwrap();
+        dst.create_dir_all("replaced/dir").unwrap();
+        write!(dst.create_file("extra").unwrap(), "extra").unwrap();
+
+        // nothing is changed by a dry run
+        let actions = util::sync(&src, &dst, SyncOptions::default().dry_run(true)).unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                SyncAction::RemoveDir("replaced/dir".into()),
+                SyncAction::RemoveDir("replaced".into()),
+                SyncAction::CreateDir("a".into()),
+                SyncAction::CreateDir("a/b".into()),
+                SyncAction::CopyFile("a/b/file".into()),
+                SyncAction::UpdateFile("changed".into()),
+                SyncAction::CopyFile("replaced".into()),
+            ]
+        );
+        assert!(dst.exists("replaced/dir").unwrap());
+
+        let actions = util::sync(&src, &dst, SyncOptions::default().delete(true)).unwrap();
+        assert!(actions.contains(&SyncAction::RemoveFile("extra".into())));
+        assert_eq!(actions.len(), 8);
+        let entries = |fs: &MemoryFS| {
+            util::walk_dir(fs, "")
+                .unwrap()
+                .map(|entry| {
+                    let entry = entry.unwrap();
+                    let contents = match entry.metadata.is_file() {
+                        true => fs
+                            .open_file(entry.path.to_str().unwrap())
+                            .unwrap()
+                            .read_into_string()
+                            .unwrap(),
+                        false => String::new(),
+                    };
+                    (entry.path, contents)
+                })
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(entries(&dst), entries(&src));
+        assert!(util::sync(&src, &dst, SyncOptions::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn hash_tree() {
+        use crate::util::HashAlgorithm;
+
+        let fs = MemoryFS::default();
+        fs.create_dir_all("install/bin").unwrap();
+        write!(fs.create_file("install/bin/file").unwrap(), "hello").unwrap();
+        fs.create_dir_all("archive/nested/bin").unwrap();
+        write!(fs.create_file("archive/nested/bin/file").unwrap(), "hello").unwrap();
+
+        let install = util::hash_tree(&fs, "/install", HashAlgorithm::Sha256).unwrap();
+        let hello = install.entries[Path::new("install/bin/file")]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .join("");
+        assert_eq!(
+            hello,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        itertools::assert_equal(
+            install.entries.keys(),
+            vec![Path::new("install/bin"), Path::new("install/bin/file")],
+        );
+
+        // the same contents have the same digest wherever they are
+        let archive = util::hash_tree(&fs, "archive/nested", HashAlgorithm::Sha256).unwrap();
+        assert_eq!(archive.digest, install.digest);
+        assert_ne!(
+            util::hash_tree(&fs, "install", HashAlgorithm::Sha1)
+                .unwrap()
+                .digest,
+            install.digest
+        );
+
+        write!(fs.create_file("install/bin/file").unwrap(), "hellO").unwrap();
+        let changed = util::hash_tree(&fs, "install", HashAlgorithm::Sha256).unwrap();
+        assert_ne!(changed.digest, install.digest);
+        assert_ne!(
+            changed.entries[Path::new("install/bin")],
+            install.entries[Path::new("install/bin")]
+        );
+    }
+
+    #[test]
+    fn disk_usage() {
+        let fs = MemoryFS::default();
+        fs.create_dir_all("a/b/empty").unwrap();
+        write!(fs.create_file("a/b/file").unwrap(), "file").unwrap();
+        write!(fs.create_file("a/top").unwrap(), "top").unwrap();
+        write!(fs.create_file("outside").unwrap(), "outside").unwrap();
+
+        let usage = |files, bytes, directories| DiskUsage {
+            bytes,
+            files,
+            directories,
+        };
+        assert_eq!(
+            util::disk_usage(&fs, "/a/").unwrap(),
+            BTreeMap::from([
+                ("a".into(), usage(2, 7, 2)),
+                ("a/b".into(), usage(1, 4, 1)),
+                ("a/b/empty".into(), usage(0, 0, 0)),
+            ])
+        );
+        assert_eq!(
+            util::disk_usage(&fs, "").unwrap()[Path::new("")],
+            usage(3, 14, 3)
+        );
+    }
+
+    #[test]
+    fn find() {
+        let fs = MemoryFS::default();
+        fs.create_dir_all("logs/old.d").unwrap();
+        write!(
+            fs.create_file("logs/big.log").unwrap(),
+            "{}",
+            "a".repeat(100)
+        )
+        .unwrap();
+        write!(fs.create_file("logs/small.log").unwrap(), "a").unwrap();
+        write!(fs.create_file("readme.txt").unwrap(), "{}", "a".repeat(100)).unwrap();
+
+        let find = |root, predicate| {
+            util::find(&fs, root, predicate)
+                .unwrap()
+                .map(|entry| entry.unwrap().path.to_str().unwrap().to_owned())
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            find("", Predicate::name("*.log")),
+            vec!["logs/big.log", "logs/small.log"]
+        );
+        assert_eq!(
+            find("", Predicate::size(10..).and(!Predicate::name("*.txt"))),
+            vec!["logs/big.log"]
+        );
+        assert_eq!(
+            find(
+                "logs",
+                Predicate::file_type(FileType::Directory).or(Predicate::name("s?all.*"))
+            ),
+            vec!["logs/old.d", "logs/small.log"]
+        );
+        // memory files don't know when they were modified
+        assert!(find("", Predicate::modified(..now())).is_empty());
+
+        // matches are returned as they're walked, without reading past them
+        let mut mock_fs = MockFileSystem::new();
+        mock_fs.expect_walk_dir().returning(|_| {
+            let first = DirEntry {
+                path: "first.log".into(),
+                metadata: Metadata::file(1),
+            };
+            Ok(Box::new(
+                once(Ok(first)).chain(iter::from_fn(|| panic!("walked past the match"))),
+            ))
+        });
+        let mut found = util::find(&mock_fs, "", Predicate::name("*.log")).unwrap();
+        assert_eq!(found.next().unwrap().unwrap().path, Path::new("first.log"));
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_matches("*.log", "big.log"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(glob_matches("?at", "cat"));
+        assert!(!glob_matches("?at", "at"));
+        assert!(!glob_matches("*.log", "big.log.gz"));
+        assert!(!glob_matches("a*b", "aXbY"));
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_path("///////"), Path::new("/"));
+        assert_eq!(normalize_path("./test/something/../"), Path::new("test"));
+        assert_eq!(normalize_path("../test"), Path::new("test"));
+    }
+
+    #[test]
+    fn normalize_separators() {
+        // virtual paths use either separator on every host
+        assert_eq!(normalize_path(r"dir\sub\..\file"), Path::new("dir/file"));
+        assert_eq!(normalize_path(r"\dir/\file"), Path::new("/dir/file"));
+        assert_eq!(make_relative(r"\dir\file"), Path::new("dir/file"));
+    }
+
+    #[test]
+    fn normalize_host_independent() {
+        // Windows prefixes are ordinary components of virtual paths
+        assert_eq!(
+            normalize_path(r"\\server\share\f"),
+            Path::new("/server/share/f")
+        );
+        assert_eq!(normalize_path(r"\\.\COM1"), Path::new("/COM1"));
+        assert_eq!(normalize_path(r"\\?\C:\dir\..\f"), Path::new("/?/C:/f"));
+
+        let fs = MemoryFS::default();
+        fs.create_dir_all("server/share").unwrap();
+        fs.create_file("server/share/f").unwrap();
+        assert!(fs.exists(r"\\server\share\f").unwrap());
+    }
+
+    #[test]
+    fn parent() {
+        itertools::assert_equal(
+            parent_iter(Path::new("/many/files/and/directories")),
+            vec![
+                Path::new("/many/files/and"),
+                Path::new("/many/files"),
+                Path::new("/many"),
+                Path::new("/"),
+            ],
+        );
+
+        itertools::assert_equal(
+            parent_iter(Path::new("../many/files/and/directories")),
+            vec![
+                Path::new("../many/files/and"),
+                Path::new("../many/files"),
+                Path::new("../many"),
+                Path::new(".."),
+            ],
+        );
+    }
+}
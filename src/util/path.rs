@@ -0,0 +1,268 @@
+//! Helpers for the paths of virtual filesystems, as normalized forward-slash strings. Paths are relative to the root
+//! of the filesystem whether or not they start with `/`, `.` components are skipped, `..` components resolve
+//! backtracking and can't climb above the root, and both `/` and `\` separate components, so the results are the same
+//! on every host. The root is the empty string.
+
+/// Returns the components of `path`, with backtracking resolved.
+///
+/// # Arguments
+/// `path`: The path.  
+fn components(path: &str) -> Vec<&str> {
+    let mut components = Vec::new();
+    for component in path.split(['/', '\\']) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    components
+}
+
+/// Normalizes `path`.
+///
+/// # Arguments
+/// `path`: The path to normalize.  
+///
+/// # Example
+/// ```
+/// use virtual_filesystem::util::path;
+///
+/// assert_eq!(path::normalize("/dir//./sub/../file"), "dir/file");
+/// assert_eq!(path::normalize("\\dir\\file"), "dir/file");
+/// assert_eq!(path::normalize("/"), "");
+/// ```
+pub fn normalize(path: &str) -> String {
+    components(path).join("/")
+}
+
+/// Joins `path` to `base`. Like `Path::join`, a `path` that starts with `/` replaces `base`.
+///
+/// # Arguments
+/// `base`: The path to join to.  
+/// `path`: The path to join, relative to `base`.  
+///
+/// # Example
+/// ```
+/// use virtual_filesystem::util::path;
+///
+/// assert_eq!(path::join("dir", "sub/file"), "dir/sub/file");
+/// assert_eq!(path::join("dir/sub", "../file"), "dir/file");
+/// assert_eq!(path::join("dir", "/file"), "file");
+/// ```
+pub fn join(base: &str, path: &str) -> String {
+    if path.starts_with(['/', '\\']) {
+        return normalize(path);
+    }
+    normalize(&format!("{base}/{path}"))
+}
+
+/// Returns the path of the directory containing `path`, or `None` if `path` is the root.
+///
+/// # Arguments
+/// `path`: The path.  
+///
+/// # Example
+/// ```
+/// use virtual_filesystem::util::path;
+///
+/// assert_eq!(path::parent("/dir/file").as_deref(), Some("dir"));
+/// assert_eq!(path::parent("file").as_deref(), Some(""));
+/// assert_eq!(path::parent("/"), None);
+/// ```
+pub fn parent(path: &str) -> Option<String> {
+    let mut components = components(path);
+    components.pop()?;
+    Some(components.join("/"))
+}
+
+/// Returns the name of the file or directory at `path`, or `None` if `path` is the root.
+///
+/// # Arguments
+/// `path`: The path.  
+///
+/// # Example
+/// ```
+/// use virtual_filesystem::util::path;
+///
+/// assert_eq!(path::file_name("/dir/file.txt"), Some("file.txt"));
+/// assert_eq!(path::file_name("dir/file/.."), Some("dir"));
+/// assert_eq!(path::file_name(""), None);
+/// ```
+pub fn file_name(path: &str) -> Option<&str> {
+    components(path).pop()
+}
+
+/// Returns `path` relative to `base`, or `None` if `path` isn't within `base`.
+///
+/// # Arguments
+/// `path`: The path.  
+/// `base`: The path of the directory that `path` is relative to.  
+///
+/// # Example
+/// ```
+/// use virtual_filesystem::util::path;
+///
+/// assert_eq!(path::relative_to("/dir/sub/file", "dir").as_deref(), Some("sub/file"));
+/// assert_eq!(path::relative_to("dir", "/dir/").as_deref(), Some(""));
+/// assert_eq!(path::relative_to("directory", "dir"), None);
+/// ```
+pub fn relative_to(path: &str, base: &str) -> Option<String> {
+    let (path, base) = (components(path), components(base));
+    path.strip_prefix(base.as_slice())
+        .map(|components| components.join("/"))
+}
+
+/// Returns true if `path` is `base` or is within it. Components are compared whole, so `directory` isn't within
+/// `dir`.
+///
+/// # Arguments
+/// `path`: The path.  
+/// `base`: The path of the directory.  
+///
+/// # Example
+/// ```
+/// use virtual_filesystem::util::path;
+///
+/// assert!(path::is_within("/dir/sub/file", "dir"));
+/// assert!(path::is_within("anything", ""));
+/// assert!(!path::is_within("dir/../file", "dir"));
+/// ```
+pub fn is_within(path: &str, base: &str) -> bool {
+    components(path).starts_with(&components(base))
+}
+
+/// Percent-encodes the characters of `path` that aren't allowed in a URL path, leaving unreserved characters and `/`
+/// as they are.
+///
+/// # Arguments
+/// `path`: The path to encode.  
+///
+/// # Example
+/// ```
+/// use virtual_filesystem::util::path;
+///
+/// assert_eq!(path::encode("dir/my file.txt"), "dir/my%20file.txt");
+/// assert_eq!(path::encode("caf\u{e9}?"), "caf%C3%A9%3F");
+/// ```
+pub fn encode(path: &str) -> String {
+    percent_encode(path, false)
+}
+
+/// Percent-encodes a single component of a URL, such as a query parameter or a file name, leaving only unreserved
+/// characters as they are.
+///
+/// # Arguments
+/// `component`: The component to encode.  
+///
+/// # Example
+/// ```
+/// use virtual_filesystem::util::path;
+///
+/// assert_eq!(path::encode_component("a/b c"), "a%2Fb%20c");
+/// ```
+pub fn encode_component(component: &str) -> String {
+    percent_encode(component, true)
+}
+
+/// Decodes the percent-encoded characters of `path`. Malformed escapes are kept as they are, and invalid UTF-8 is
+/// replaced.
+///
+/// # Arguments
+/// `path`: The path to decode.  
+///
+/// # Example
+/// ```
+/// use virtual_filesystem::util::path;
+///
+/// assert_eq!(path::decode("dir/my%20file.txt"), "dir/my file.txt");
+/// assert_eq!(path::decode("100%"), "100%");
+/// ```
+pub fn decode(path: &str) -> String {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut remaining = path.as_bytes();
+
+    while let Some((&byte, rest)) = remaining.split_first() {
+        let decoded = (byte == b'%')
+            .then(|| rest.get(..2))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                remaining = &rest[2..];
+            }
+            None => {
+                bytes.push(byte);
+                remaining = rest;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Percent-encodes every byte of `value` but the unreserved characters.
+///
+/// # Arguments
+/// `value`: The value to encode.  
+/// `encode_slash`: True if `/` should be encoded too.  
+fn percent_encode(value: &str, encode_slash: bool) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            b'/' if !encode_slash => "/".to_owned(),
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::util::path::{
+        decode, encode, encode_component, file_name, is_within, join, normalize, parent,
+        relative_to,
+    };
+
+    #[test]
+    fn backtracking() {
+        assert_eq!(normalize("../../file"), "file");
+        assert_eq!(join("dir", "../../file"), "file");
+        assert_eq!(parent("dir/.."), None);
+        assert_eq!(file_name(".."), None);
+        assert!(is_within("..", ""));
+        assert_eq!(
+            relative_to("dir/sub/../file", "dir").as_deref(),
+            Some("file")
+        );
+    }
+
+    #[test]
+    fn separators() {
+        assert_eq!(join("dir\\sub", "file"), "dir/sub/file");
+        assert_eq!(parent("dir\\sub\\file").as_deref(), Some("dir/sub"));
+        assert_eq!(file_name("dir\\file"), Some("file"));
+        assert!(is_within("\\dir\\file", "/dir"));
+    }
+
+    #[test]
+    fn percent_encoding() {
+        let name = "dir/a b%c\u{e9}~";
+        assert_eq!(encode(name), "dir/a%20b%25c%C3%A9~");
+        assert_eq!(encode_component(name), "dir%2Fa%20b%25c%C3%A9~");
+        assert_eq!(decode(&encode(name)), name);
+        assert_eq!(decode(&encode_component(name)), name);
+        assert_eq!(encode("a b/ü#"), "a%20b/%C3%BC%23");
+        assert_eq!(decode("a%20b/%C3%BC%23%zz"), "a b/ü#%zz");
+
+        // malformed escapes are kept, and invalid UTF-8 is replaced
+        assert_eq!(decode("%zz%4"), "%zz%4");
+        assert_eq!(decode("%FF"), "\u{fffd}");
+    }
+}
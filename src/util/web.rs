@@ -0,0 +1,65 @@
+use js_sys::{Promise, Reflect};
+use std::io;
+use std::io::ErrorKind;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::DomException;
+
+/// Converts a JavaScript error, which is usually a `DOMException`, to an IO error.
+///
+/// # Arguments
+/// `value`: The error.  
+pub(crate) fn js_error(value: JsValue) -> io::Error {
+    if let Some(exception) = value.dyn_ref::<DomException>() {
+        let kind = match exception.name().as_str() {
+            "NotFoundError" => ErrorKind::NotFound,
+            "TypeMismatchError" => ErrorKind::InvalidInput,
+            "InvalidModificationError" => ErrorKind::DirectoryNotEmpty,
+            "NoModificationAllowedError" => ErrorKind::ResourceBusy,
+            "QuotaExceededError" => ErrorKind::QuotaExceeded,
+            "NotAllowedError" | "SecurityError" => ErrorKind::PermissionDenied,
+            _ => ErrorKind::Other,
+        };
+        return io::Error::new(kind, exception.message());
+    }
+
+    match value.dyn_ref::<js_sys::Error>() {
+        Some(error) => io::Error::other(String::from(error.message())),
+        None => io::Error::other(format!("{value:?}")),
+    }
+}
+
+/// Waits for a promise to settle. Returns its value.
+///
+/// # Arguments
+/// `promise`: The promise.  
+pub(crate) async fn settle(promise: Promise) -> crate::Result<JsValue> {
+    JsFuture::from(promise).await.map_err(js_error)
+}
+
+/// Returns a property of the global object, such as `indexedDB` or `navigator`, which the page and its workers both
+/// have.
+///
+/// # Arguments
+/// `name`: The name of the property.  
+pub(crate) fn global<T: JsCast>(name: &str) -> crate::Result<T> {
+    property(&js_sys::global(), name)
+}
+
+/// Returns a property of an object, failing with `Unsupported` if it's missing or of another type, which is how
+/// browsers without an API tell.
+///
+/// # Arguments
+/// `object`: The object.  
+/// `name`: The name of the property.  
+pub(crate) fn property<T: JsCast>(object: &JsValue, name: &str) -> crate::Result<T> {
+    Reflect::get(object, &JsValue::from_str(name))
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(|_| {
+            io::Error::new(
+                ErrorKind::Unsupported,
+                format!("The browser doesn't support `{name}`"),
+            )
+        })
+}
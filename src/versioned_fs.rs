@@ -0,0 +1,540 @@
+use crate::file::{DirEntry, File, FileSystemStats, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::{invalid_input, invalid_path, not_found};
+use crate::FileSystem;
+use parking_lot::Mutex;
+use std::io;
+use std::io::{ErrorKind, Write};
+
+/// The path of the index of versions within the store.
+const INDEX: &str = "index";
+
+/// A prior version of a file in a `VersionedFS`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Version {
+    /// The snapshot that replaced the version. The version is the contents of the file as of every earlier snapshot.
+    pub replaced_at: u64,
+    /// The length of the version in bytes.
+    pub len: u64,
+}
+
+/// What a file was before it was changed.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum State {
+    /// The file held contents of a length, which are kept in the store.
+    File(u64),
+    /// There was no file.
+    Absent,
+    /// The file held contents that were dropped by the retention policy.
+    Pruned,
+}
+
+/// A record of what a file was before the snapshot that changed it.
+struct Record {
+    /// The snapshot that changed the file.
+    snapshot: u64,
+    /// The position of the file among the files changed by the snapshot.
+    part: usize,
+    path: String,
+    state: State,
+}
+
+impl Record {
+    /// Returns the path of the contents of the record within the store.
+    fn blob(&self) -> String {
+        format!("{}-{}", self.snapshot, self.part)
+    }
+}
+
+/// The history shared by every operation of the filesystem.
+struct History {
+    /// The latest snapshot.
+    snapshot: u64,
+    /// The records of every change, oldest first.
+    records: Vec<Record>,
+}
+
+/// A filesystem that keeps the prior versions of the files of another filesystem as they're changed, as a lightweight
+/// version control system for a directory of application data.
+///
+/// Every change that can modify or replace a file takes a snapshot, which is numbered one past the one before it.
+/// Before the change is made, the file's contents are copied into the store, another filesystem that holds the
+/// versions and an index of them, so that the file can be listed, opened and restored as of any earlier snapshot.
+/// Opening a file for writing is a change, even if nothing is written to it. Directories aren't versioned.
+///
+/// Only the changes made through the versioned filesystem are recorded. By default every version is kept, which can be
+/// limited per file with `max_versions`; opening a file as of a snapshot whose version was dropped fails with
+/// `NotFound`.
+pub struct VersionedFS<FS, S> {
+    inner: FS,
+    store: S,
+    history: Mutex<History>,
+    max_versions: Option<usize>,
+}
+
+impl<FS: FileSystem, S: FileSystem> VersionedFS<FS, S> {
+    /// Creates a new versioned filesystem, loading the history already in the store.
+    ///
+    /// # Arguments
+    /// `inner`: The filesystem to version.  
+    /// `store`: The filesystem to keep the versions in, which must not be modified otherwise.  
+    pub fn new(inner: FS, store: S) -> crate::Result<Self> {
+        let records = match store.open_file(INDEX) {
+            Ok(mut index) => parse_index(&index.read_into_string()?)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            inner,
+            store,
+            history: Mutex::new(History {
+                snapshot: records.last().map_or(0, |record| record.snapshot),
+                records,
+            }),
+            max_versions: None,
+        })
+    }
+
+    /// Limits the number of versions kept of each file, dropping the oldest versions beyond it as files change.
+    ///
+    /// # Arguments
+    /// `max_versions`: The maximum number of versions of each file.  
+    pub fn max_versions(mut self, max_versions: usize) -> Self {
+        self.max_versions = Some(max_versions);
+        self
+    }
+
+    /// Returns the versioned filesystem.
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Returns the filesystem the versions are kept in.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the latest snapshot, as of which every file is as it currently is.
+    pub fn snapshot(&self) -> u64 {
+        self.history.lock().snapshot
+    }
+
+    /// Returns the versions of the file at `path` that are kept, oldest first.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file.  
+    pub fn versions(&self, path: &str) -> crate::Result<Vec<Version>> {
+        let path = history_path(path)?;
+        let history = self.history.lock();
+        Ok(history
+            .records
+            .iter()
+            .filter(|record| record.path == path)
+            .filter_map(|record| match record.state {
+                State::File(len) => Some(Version {
+                    replaced_at: record.snapshot,
+                    len,
+                }),
+                State::Absent | State::Pruned => None,
+            })
+            .collect())
+    }
+
+    /// Opens the file at `path` read-only, as it was as of `snapshot`.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file.  
+    /// `snapshot`: The snapshot.  
+    pub fn open_file_at(&self, path: &str, snapshot: u64) -> crate::Result<Box<dyn File>> {
+        let history_path = history_path(path)?;
+        let history = self.history.lock();
+        match find_record(&history, &history_path, snapshot) {
+            Some(record) => match record.state {
+                State::File(_) => self.store.open_file(&record.blob()),
+                State::Absent => Err(not_found()),
+                State::Pruned => Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    "Version no longer kept",
+                )),
+            },
+            None => self.inner.open_file(path),
+        }
+    }
+
+    /// Restores the file at `path` to how it was as of `snapshot`, which is itself a change. If there was no file, the
+    /// file is removed.
+    ///
+    /// # Arguments
+    /// `path`: The path of the file.  
+    /// `snapshot`: The snapshot to restore.  
+    pub fn restore(&self, path: &str, snapshot: u64) -> crate::Result<()> {
+        let history_path = history_path(path)?;
+        let mut history = self.history.lock();
+        let (blob, state) = match find_record(&history, &history_path, snapshot) {
+            Some(record) => (record.blob(), record.state),
+            // the file is already as it was
+            None => return Ok(()),
+        };
+
+        match state {
+            State::File(_) => self.change(&mut history, &[path], || {
+                let mut version = self.store.open_file(&blob)?;
+                let mut file = self.inner.create_file(path)?;
+                io::copy(&mut version, &mut file)?;
+                Ok(())
+            }),
+            State::Absent if !self.inner.exists(path)? => Ok(()),
+            State::Absent => self.change(&mut history, &[path], || self.inner.remove_file(path)),
+            State::Pruned => Err(io::Error::new(
+                ErrorKind::NotFound,
+                "Version no longer kept",
+            )),
+        }
+    }
+
+    /// Takes a snapshot that changes the files at `paths` through `operation`, recording what they were before. The
+    /// snapshot is discarded if the operation fails.
+    ///
+    /// # Arguments
+    /// `history`: The history, which is locked for the duration of the change.  
+    /// `paths`: The paths of the changed files.  
+    /// `operation`: The change.  
+    fn change<T>(
+        &self,
+        history: &mut History,
+        paths: &[&str],
+        operation: impl FnOnce() -> crate::Result<T>,
+    ) -> crate::Result<T> {
+        let snapshot = history.snapshot + 1;
+        let mut records = Vec::with_capacity(paths.len());
+        for (part, path) in paths.iter().enumerate() {
+            let mut record = Record {
+                snapshot,
+                part,
+                path: history_path(path)?,
+                state: State::Absent,
+            };
+            match self.inner.metadata(path) {
+                Ok(metadata) if metadata.is_file() => {
+                    let mut file = self.inner.open_file(path)?;
+                    let mut version = self.store.create_file(&record.blob())?;
+                    record.state = State::File(io::copy(&mut file, &mut version)?);
+                }
+                Ok(_) => return Err(invalid_input("Directories aren't versioned")),
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+            records.push(record);
+        }
+
+        let result = operation();
+        if result.is_err() {
+            for record in records {
+                if let State::File(_) = record.state {
+                    let _ = self.store.remove_file(&record.blob());
+                }
+            }
+            return result;
+        }
+
+        history.snapshot = snapshot;
+        history.records.extend(records);
+        if let Some(max_versions) = self.max_versions {
+            for path in paths {
+                self.prune(history, &history_path(path)?, max_versions)?;
+            }
+        }
+        self.save(history)?;
+        result
+    }
+
+    /// Drops the oldest versions of a file beyond `max_versions`.
+    ///
+    /// # Arguments
+    /// `history`: The history.  
+    /// `path`: The path of the file in the history.  
+    /// `max_versions`: The maximum number of versions to keep.  
+    fn prune(&self, history: &mut History, path: &str, max_versions: usize) -> crate::Result<()> {
+        let mut versions: Vec<_> = history
+            .records
+            .iter_mut()
+            .filter(|record| record.path == path && matches!(record.state, State::File(_)))
+            .collect();
+        let excess = versions.len().saturating_sub(max_versions);
+        for record in versions.drain(..excess) {
+            match self.store.remove_file(&record.blob()) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+            record.state = State::Pruned;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the index of the history to the store.
+    ///
+    /// # Arguments
+    /// `history`: The history.  
+    fn save(&self, history: &History) -> crate::Result<()> {
+        let mut index = String::new();
+        for record in &history.records {
+            let state = match record.state {
+                State::File(len) => len.to_string(),
+                State::Absent => "-".to_owned(),
+                State::Pruned => "x".to_owned(),
+            };
+            index += &format!(
+                "{} {} {state} {}\n",
+                record.snapshot,
+                record.part,
+                escape(&record.path)
+            );
+        }
+
+        self.store.create_file(INDEX)?.write_all(index.as_bytes())
+    }
+}
+
+impl<FS: FileSystem, S: FileSystem> FileSystem for VersionedFS<FS, S> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.inner.create_dir(path)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.inner.metadata(path)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        if !options.writable() {
+            return self.inner.open_file_options(path, options);
+        }
+
+        let mut history = self.history.lock();
+        self.change(&mut history, &[path], || {
+            self.inner.open_file_options(path, options)
+        })
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        self.inner.read_dir(path)
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.inner.remove_dir(path)
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        let mut history = self.history.lock();
+        self.change(&mut history, &[path], || self.inner.remove_file(path))
+    }
+
+    fn create_dir_with(&self, path: &str, mode: u32) -> crate::Result<()> {
+        self.inner.create_dir_with(path, mode)
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> crate::Result<u64> {
+        let mut history = self.history.lock();
+        self.change(&mut history, &[to], || self.inner.copy_file(from, to))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        if self.inner.metadata(from)?.is_directory() {
+            return self.inner.rename(from, to);
+        }
+
+        let mut history = self.history.lock();
+        self.change(&mut history, &[from, to], || self.inner.rename(from, to))
+    }
+
+    fn stats(&self) -> crate::Result<FileSystemStats> {
+        self.inner.stats()
+    }
+}
+
+/// Returns the record that holds what the file at `path` was as of `snapshot`, which is the first one that changed it
+/// after the snapshot. Returns `None` if the file hasn't changed since.
+///
+/// # Arguments
+/// `history`: The history.  
+/// `path`: The path of the file in the history.  
+/// `snapshot`: The snapshot.  
+fn find_record<'a>(history: &'a History, path: &str, snapshot: u64) -> Option<&'a Record> {
+    history
+        .records
+        .iter()
+        .find(|record| record.snapshot > snapshot && record.path == path)
+}
+
+/// Returns the normalized form of `path` that files are recorded in the history by.
+///
+/// # Arguments
+/// `path`: The virtual path.  
+fn history_path(path: &str) -> crate::Result<String> {
+    let path = normalize_and_relativize(path);
+    Ok(path.to_str().ok_or_else(invalid_path)?.replace('\\', "/"))
+}
+
+/// Escapes the characters that delimit the lines of the index.
+///
+/// # Arguments
+/// `path`: The path to escape.  
+fn escape(path: &str) -> String {
+    path.replace('%', "%25").replace('\n', "%0A")
+}
+
+/// Reverses `escape`.
+///
+/// # Arguments
+/// `path`: The escaped path.  
+fn unescape(path: &str) -> String {
+    path.replace("%0A", "\n").replace("%25", "%")
+}
+
+/// Parses the index of a history, in which every record is a line of its snapshot, its part, its state and its path.
+///
+/// # Arguments
+/// `index`: The contents of the index.  
+fn parse_index(index: &str) -> crate::Result<Vec<Record>> {
+    index
+        .lines()
+        .map(|line| {
+            let mut fields = line.splitn(4, ' ');
+            let mut field = || {
+                fields
+                    .next()
+                    .ok_or_else(|| invalid_input("Invalid version index"))
+            };
+            let snapshot = field()?
+                .parse()
+                .map_err(|_| invalid_input("Invalid snapshot"))?;
+            let part = field()?
+                .parse()
+                .map_err(|_| invalid_input("Invalid part"))?;
+            let state = match field()? {
+                "-" => State::Absent,
+                "x" => State::Pruned,
+                len => State::File(len.parse().map_err(|_| invalid_input("Invalid length"))?),
+            };
+            Ok(Record {
+                snapshot,
+                part,
+                state,
+                path: unescape(field()?),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    use crate::util::test::check_open_options;
+    use crate::versioned_fs::{Version, VersionedFS};
+    use crate::FileSystem;
+    use std::io::{ErrorKind, Write};
+    use std::sync::Arc;
+
+    fn read(fs: &dyn FileSystem, path: &str) -> String {
+        fs.open_file(path).unwrap().read_into_string().unwrap()
+    }
+
+    #[test]
+    fn history() {
+        let fs = VersionedFS::new(MemoryFS::default(), MemoryFS::default()).unwrap();
+        assert_eq!(fs.snapshot(), 0);
+
+        fs.create_dir("dir").unwrap();
+        write!(fs.create_file("dir/file").unwrap(), "first").unwrap();
+        write!(fs.create_file("dir/file").unwrap(), "second").unwrap();
+        fs.rename("dir/file", "dir/renamed").unwrap();
+        assert_eq!(fs.snapshot(), 3);
+
+        assert_eq!(
+            fs.versions("/dir/file").unwrap(),
+            vec![
+                Version {
+                    replaced_at: 2,
+                    len: 5
+                },
+                Version {
+                    replaced_at: 3,
+                    len: 6
+                }
+            ]
+        );
+        assert_eq!(
+            fs.open_file_at("dir/file", 0).err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        let read_at = |path, snapshot| {
+            fs.open_file_at(path, snapshot)
+                .unwrap()
+                .read_into_string()
+                .unwrap()
+        };
+        assert_eq!(read_at("dir/file", 1), "first");
+        assert_eq!(read_at("dir/file", 2), "second");
+        assert!(fs.open_file_at("dir/file", 3).is_err());
+        assert!(fs.open_file_at("dir/renamed", 2).is_err());
+        assert_eq!(read_at("dir/renamed", 3), "second");
+
+        // restoring is a change of its own
+        fs.restore("dir/file", 1).unwrap();
+        assert_eq!(read(&fs, "dir/file"), "first");
+        fs.restore("dir/renamed", 0).unwrap();
+        assert!(!fs.exists("dir/renamed").unwrap());
+        assert_eq!(fs.snapshot(), 5);
+        assert_eq!(read_at("dir/renamed", 4), "second");
+
+        // failed changes don't take a snapshot
+        assert!(fs.remove_file("missing").is_err());
+        assert_eq!(fs.snapshot(), 5);
+    }
+
+    #[test]
+    fn persistence() {
+        let store = Arc::new(MemoryFS::default());
+        let fs = VersionedFS::new(MemoryFS::default(), store.clone())
+            .unwrap()
+            .max_versions(1);
+        for contents in ["1", "2", "3"] {
+            write!(fs.create_file("file").unwrap(), "{contents}").unwrap();
+        }
+        fs.remove_file("file").unwrap();
+
+        let fs = VersionedFS::new(fs.inner, store).unwrap();
+        assert_eq!(fs.snapshot(), 4);
+        assert_eq!(
+            fs.versions("file").unwrap(),
+            vec![Version {
+                replaced_at: 4,
+                len: 1
+            }]
+        );
+        assert_eq!(
+            fs.open_file_at("file", 3)
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "3"
+        );
+        assert_eq!(
+            fs.open_file_at("file", 2).err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn open_options() {
+        let fs = VersionedFS::new(MemoryFS::default(), MemoryFS::default()).unwrap();
+        check_open_options(&fs, "options");
+        // appends replace the file like any other write
+        assert_eq!(fs.versions("options").unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,592 @@
+use crate::file::{DirEntry, File, FileType, Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::util::{already_exists, invalid_path, not_found, not_supported};
+use crate::FileSystem;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+use vfs::error::VfsErrorKind;
+use vfs::{SeekAndRead, SeekAndWrite, VfsError, VfsFileType, VfsMetadata, VfsResult};
+
+/// A filesystem backed by a `vfs::FileSystem`, such as `vfs::MemoryFS` or an existing `vfs`-based backend, so that it
+/// can be mounted or layered with the filesystems of this crate.
+///
+/// `vfs` files are either read or written, and can't be written within. Files that are written in place are read into
+/// memory and written back as a whole when they're flushed or closed, so they can also be read. Files that are
+/// created, truncated or appended to are written directly, so they can't be read.
+pub struct FromVfs<FS: vfs::FileSystem> {
+    fs: Arc<FS>,
+}
+
+impl<FS: vfs::FileSystem> FromVfs<FS> {
+    /// Creates a filesystem backed by a `vfs` filesystem.
+    ///
+    /// # Arguments
+    /// `fs`: The `vfs` filesystem.  
+    pub fn new(fs: FS) -> Self {
+        Self { fs: Arc::new(fs) }
+    }
+
+    /// Returns the `vfs` filesystem.
+    pub fn inner(&self) -> &FS {
+        &self.fs
+    }
+}
+
+impl<FS: vfs::FileSystem> FileSystem for FromVfs<FS> {
+    fn create_dir(&self, path: &str) -> crate::Result<()> {
+        self.fs.create_dir(&vfs_path(path)).map_err(io_error)
+    }
+
+    fn metadata(&self, path: &str) -> crate::Result<Metadata> {
+        self.fs
+            .metadata(&vfs_path(path))
+            .map(convert_metadata)
+            .map_err(io_error)
+    }
+
+    fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
+        options.validate()?;
+        let path = vfs_path(path);
+        if !options.writable() {
+            let reader = self.fs.open_file(&path).map_err(io_error)?;
+            return Ok(Box::new(VfsFile {
+                fs: self.fs.clone(),
+                path,
+                handle: Handle::Read(reader),
+            }));
+        }
+
+        let exists = self.fs.exists(&path).map_err(io_error)?;
+        let writer = match (exists, options.create || options.create_new) {
+            (false, false) => return Err(not_found()),
+            (false, true) => self.fs.create_file(&path),
+            (true, _) if options.create_new => return Err(already_exists()),
+            (true, _) if options.truncate => self.fs.create_file(&path),
+            (true, _) if options.append => self.fs.append_file(&path),
+            // `vfs` can't write within files, so the contents are rewritten
+            (true, _) => {
+                let mut contents = Vec::new();
+                self.fs
+                    .open_file(&path)
+                    .map_err(io_error)?
+                    .read_to_end(&mut contents)?;
+                return Ok(Box::new(VfsFile {
+                    fs: self.fs.clone(),
+                    path,
+                    handle: Handle::Buffered {
+                        contents: Cursor::new(contents),
+                        dirty: false,
+                    },
+                }));
+            }
+        }
+        .map_err(io_error)?;
+        Ok(Box::new(VfsFile {
+            fs: self.fs.clone(),
+            path,
+            handle: Handle::Write(writer),
+        }))
+    }
+
+    fn read_dir(
+        &self,
+        path: &str,
+    ) -> crate::Result<Box<dyn Iterator<Item = crate::Result<DirEntry>>>> {
+        let dir_path = normalize_and_relativize(path);
+        let vfs_dir_path = vfs_path(path);
+        let fs = self.fs.clone();
+        Ok(Box::new(
+            self.fs
+                .read_dir(&vfs_dir_path)
+                .map_err(io_error)?
+                .map(move |name| {
+                    let metadata = fs
+                        .metadata(&format!("{vfs_dir_path}/{name}"))
+                        .map_err(io_error)?;
+                    Ok(DirEntry {
+                        path: dir_path.join(name),
+                        metadata: convert_metadata(metadata),
+                    })
+                }),
+        ))
+    }
+
+    fn remove_dir(&self, path: &str) -> crate::Result<()> {
+        self.fs.remove_dir(&vfs_path(path)).map_err(io_error)
+    }
+
+    fn remove_file(&self, path: &str) -> crate::Result<()> {
+        self.fs.remove_file(&vfs_path(path)).map_err(io_error)
+    }
+
+    fn exists(&self, path: &str) -> crate::Result<bool> {
+        self.fs.exists(&vfs_path(path)).map_err(io_error)
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> crate::Result<u64> {
+        match self.fs.copy_file(&vfs_path(from), &vfs_path(to)) {
+            Ok(()) => Ok(self.metadata(to)?.len()),
+            Err(err) if matches!(err.kind(), VfsErrorKind::NotSupported) => {
+                crate::util::copy_file(self, from, to)
+            }
+            Err(err) => Err(io_error(err)),
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> crate::Result<()> {
+        let (vfs_from, vfs_to) = (vfs_path(from), vfs_path(to));
+        let result = if self.metadata(from)?.is_directory() {
+            self.fs.move_dir(&vfs_from, &vfs_to)
+        } else {
+            self.fs.move_file(&vfs_from, &vfs_to)
+        };
+        match result {
+            Ok(()) => Ok(()),
+            Err(err) if matches!(err.kind(), VfsErrorKind::NotSupported) => {
+                crate::util::rename(self, from, to)
+            }
+            Err(err) => Err(io_error(err)),
+        }
+    }
+}
+
+/// A `vfs` file handle, which either reads or writes.
+enum Handle {
+    Read(Box<dyn SeekAndRead + Send>),
+    Write(Box<dyn SeekAndWrite + Send>),
+    /// The contents of a file written in place, which are written back when they're flushed.
+    Buffered {
+        contents: Cursor<Vec<u8>>,
+        dirty: bool,
+    },
+}
+
+/// A file of a `vfs` filesystem.
+struct VfsFile<FS: vfs::FileSystem> {
+    fs: Arc<FS>,
+    path: String,
+    handle: Handle,
+}
+
+impl<FS: vfs::FileSystem> Read for VfsFile<FS> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.handle {
+            Handle::Read(reader) => reader.read(buf),
+            Handle::Write(_) => Err(not_supported()),
+            Handle::Buffered { contents, .. } => contents.read(buf),
+        }
+    }
+}
+
+impl<FS: vfs::FileSystem> Write for VfsFile<FS> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.handle {
+            Handle::Read(_) => Err(not_supported()),
+            Handle::Write(writer) => writer.write(buf),
+            Handle::Buffered { contents, dirty } => {
+                *dirty = true;
+                contents.write(buf)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.handle {
+            Handle::Read(_) => Ok(()),
+            Handle::Write(writer) => writer.flush(),
+            Handle::Buffered { dirty: false, .. } => Ok(()),
+            Handle::Buffered { contents, dirty } => {
+                let mut writer = self.fs.create_file(&self.path).map_err(io_error)?;
+                writer.write_all(contents.get_ref())?;
+                writer.flush()?;
+                *dirty = false;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<FS: vfs::FileSystem> Seek for VfsFile<FS> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match &mut self.handle {
+            Handle::Read(reader) => reader.seek(pos),
+            Handle::Write(writer) => writer.seek(pos),
+            Handle::Buffered { contents, .. } => contents.seek(pos),
+        }
+    }
+}
+
+impl<FS: vfs::FileSystem> Drop for VfsFile<FS> {
+    fn drop(&mut self) {
+        // errors can't be reported here, so callers who need to know should flush first
+        let _ = self.flush();
+    }
+}
+
+impl<FS: vfs::FileSystem> File for VfsFile<FS> {
+    fn metadata(&self) -> crate::Result<Metadata> {
+        if let Handle::Buffered { contents, .. } = &self.handle {
+            return Ok(Metadata::file(contents.get_ref().len() as u64));
+        }
+        self.fs
+            .metadata(&self.path)
+            .map(convert_metadata)
+            .map_err(io_error)
+    }
+}
+
+/// A `vfs::FileSystem` backed by a filesystem of this crate, so that projects with `vfs`-based code can use it through
+/// `vfs::VfsPath`.
+///
+/// `vfs` files must be sendable between threads, which the files of this crate aren't, so files aren't held open.
+/// Instead, every read, write and seek to the end opens the file again.
+pub struct IntoVfs<FS: FileSystem + Send + Sync + 'static> {
+    fs: Arc<FS>,
+}
+
+impl<FS: FileSystem + Send + Sync + 'static> IntoVfs<FS> {
+    /// Creates a `vfs` filesystem backed by a filesystem.
+    ///
+    /// # Arguments
+    /// `fs`: The filesystem.  
+    pub fn new(fs: FS) -> Self {
+        Self { fs: Arc::new(fs) }
+    }
+
+    /// Returns the filesystem.
+    pub fn inner(&self) -> &FS {
+        &self.fs
+    }
+}
+
+impl<FS: FileSystem + Send + Sync + 'static> Debug for IntoVfs<FS> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IntoVfs").finish_non_exhaustive()
+    }
+}
+
+impl<FS: FileSystem + Send + Sync + 'static> vfs::FileSystem for IntoVfs<FS> {
+    fn read_dir(&self, path: &str) -> VfsResult<Box<dyn Iterator<Item = String> + Send>> {
+        // the entries are read up front, since the iterators of this crate can't be sent between threads
+        let names = self
+            .fs
+            .read_dir(path)?
+            .map(|entry| {
+                entry?
+                    .path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map(str::to_owned)
+                    .ok_or_else(invalid_path)
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+        Ok(Box::new(names.into_iter()))
+    }
+
+    fn create_dir(&self, path: &str) -> VfsResult<()> {
+        match self.fs.metadata(path) {
+            Ok(metadata) if metadata.is_directory() => Err(VfsErrorKind::DirectoryExists.into()),
+            Ok(_) => Err(VfsErrorKind::FileExists.into()),
+            Err(_) => Ok(self.fs.create_dir(path)?),
+        }
+    }
+
+    fn open_file(&self, path: &str) -> VfsResult<Box<dyn SeekAndRead + Send>> {
+        if !self.fs.metadata(path)?.is_file() {
+            return Err(VfsErrorKind::Other("Not a file".to_owned()).into());
+        }
+        Ok(Box::new(SendFile {
+            fs: self.fs.clone(),
+            path: path.to_owned(),
+            position: 0,
+            append: false,
+        }))
+    }
+
+    fn create_file(&self, path: &str) -> VfsResult<Box<dyn SeekAndWrite + Send>> {
+        self.fs.create_file(path)?;
+        Ok(Box::new(SendFile {
+            fs: self.fs.clone(),
+            path: path.to_owned(),
+            position: 0,
+            append: false,
+        }))
+    }
+
+    fn append_file(&self, path: &str) -> VfsResult<Box<dyn SeekAndWrite + Send>> {
+        if !self.fs.metadata(path)?.is_file() {
+            return Err(VfsErrorKind::Other("Not a file".to_owned()).into());
+        }
+        Ok(Box::new(SendFile {
+            fs: self.fs.clone(),
+            path: path.to_owned(),
+            position: 0,
+            append: true,
+        }))
+    }
+
+    fn metadata(&self, path: &str) -> VfsResult<VfsMetadata> {
+        // `vfs` expects the root to always exist, which not every filesystem can describe
+        let metadata = match path.is_empty() {
+            true => Metadata::directory(),
+            false => self.fs.metadata(path)?,
+        };
+        Ok(VfsMetadata {
+            file_type: match metadata.is_directory() {
+                true => VfsFileType::Directory,
+                false => VfsFileType::File,
+            },
+            len: metadata.len(),
+            created: metadata.created,
+            modified: metadata.modified,
+            accessed: metadata.accessed,
+        })
+    }
+
+    fn exists(&self, path: &str) -> VfsResult<bool> {
+        Ok(path.is_empty() || self.fs.exists(path)?)
+    }
+
+    fn remove_file(&self, path: &str) -> VfsResult<()> {
+        Ok(self.fs.remove_file(path)?)
+    }
+
+    fn remove_dir(&self, path: &str) -> VfsResult<()> {
+        Ok(self.fs.remove_dir(path)?)
+    }
+
+    fn copy_file(&self, src: &str, dest: &str) -> VfsResult<()> {
+        self.fs.copy_file(src, dest)?;
+        Ok(())
+    }
+
+    fn move_file(&self, src: &str, dest: &str) -> VfsResult<()> {
+        Ok(self.fs.rename(src, dest)?)
+    }
+
+    fn move_dir(&self, src: &str, dest: &str) -> VfsResult<()> {
+        Ok(self.fs.rename(src, dest)?)
+    }
+}
+
+/// A file that can be sent between threads, which opens the file it refers to for every operation.
+struct SendFile<FS: FileSystem> {
+    fs: Arc<FS>,
+    path: String,
+    position: u64,
+    /// True if writes are made at the end of the file.
+    append: bool,
+}
+
+impl<FS: FileSystem> Read for SendFile<FS> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut file = self.fs.open_file(&self.path)?;
+        file.seek(SeekFrom::Start(self.position))?;
+        let read_len = file.read(buf)?;
+        self.position += read_len as u64;
+        Ok(read_len)
+    }
+}
+
+impl<FS: FileSystem> Write for SendFile<FS> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut file = self
+            .fs
+            .open_file_options(&self.path, &OpenOptions::default().write(true))?;
+        self.position = file.seek(match self.append {
+            true => SeekFrom::End(0),
+            false => SeekFrom::Start(self.position),
+        })?;
+        file.write_all(buf)?;
+        file.flush()?;
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // every write is flushed when it's made
+        Ok(())
+    }
+}
+
+impl<FS: FileSystem> Seek for SendFile<FS> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self
+                .fs
+                .metadata(&self.path)?
+                .len()
+                .checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(ErrorKind::InvalidInput, "Seek before the start of the file")
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// Converts a virtual path to a `vfs` path, which is absolute, or empty for the root.
+///
+/// # Arguments
+/// `path`: The virtual path.  
+fn vfs_path(path: &str) -> String {
+    crate::util::component_iter(&normalize_and_relativize(path))
+        .flat_map(|component| ["/", component])
+        .collect()
+}
+
+/// Converts `vfs` metadata to virtual metadata.
+///
+/// # Arguments
+/// `metadata`: The `vfs` metadata.  
+fn convert_metadata(metadata: VfsMetadata) -> Metadata {
+    let file_type = match metadata.file_type {
+        VfsFileType::File => FileType::File,
+        VfsFileType::Directory => FileType::Directory,
+    };
+    let mut converted = Metadata::new(file_type, metadata.len);
+    converted.modified = metadata.modified;
+    converted.accessed = metadata.accessed;
+    converted.created = metadata.created;
+    converted
+}
+
+/// Converts a `vfs` error to an I/O error.
+///
+/// # Arguments
+/// `err`: The `vfs` error.  
+fn io_error(err: VfsError) -> io::Error {
+    match err.kind() {
+        VfsErrorKind::IoError(io_err) => io::Error::new(io_err.kind(), err),
+        VfsErrorKind::FileNotFound => not_found(),
+        VfsErrorKind::InvalidPath => invalid_path(),
+        VfsErrorKind::DirectoryExists | VfsErrorKind::FileExists => already_exists(),
+        VfsErrorKind::NotSupported => not_supported(),
+        _ => io::Error::other(err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::OpenOptions;
+    use crate::memory_fs::MemoryFS;
+    use crate::util::test::{check_open_options, read_directory};
+    use crate::vfs_compat::{vfs_path, FromVfs, IntoVfs};
+    use crate::FileSystem;
+    use itertools::Itertools;
+    use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+    use vfs::VfsPath;
+
+    #[test]
+    fn paths() {
+        assert_eq!(vfs_path(""), "");
+        assert_eq!(vfs_path("/"), "");
+        assert_eq!(vfs_path("dir/../dir/./file"), "/dir/file");
+    }
+
+    #[test]
+    fn from_vfs() {
+        let fs = FromVfs::new(vfs::MemoryFS::new());
+        fs.create_dir_all("/dir/sub").unwrap();
+        write!(fs.create_file("dir/file").unwrap(), "hello").unwrap();
+        let mut file = fs
+            .open_file_options("dir/file", &OpenOptions::default().append(true))
+            .unwrap();
+        write!(file, " world").unwrap();
+        drop(file);
+
+        let mut file = fs.open_file("dir/file").unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 11);
+        file.seek(SeekFrom::Start(6)).unwrap();
+        assert_eq!(file.read_into_string().unwrap(), "world");
+
+        // files are written in place by rewriting them
+        let mut file = fs
+            .open_file_options("dir/file", &OpenOptions::default().write(true))
+            .unwrap();
+        write!(file, "HELLO").unwrap();
+        drop(file);
+        assert_eq!(
+            fs.open_file("dir/file")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "HELLO world"
+        );
+        for (options, kind) in [
+            (
+                OpenOptions::new().write(true).create_new(true),
+                ErrorKind::AlreadyExists,
+            ),
+            (OpenOptions::default().create(true), ErrorKind::InvalidInput),
+        ] {
+            assert_eq!(
+                fs.open_file_options("dir/file", &options)
+                    .err()
+                    .unwrap()
+                    .kind(),
+                kind
+            );
+        }
+        itertools::assert_equal(
+            read_directory(&fs, "dir").keys(),
+            vec!["dir/file", "dir/sub"],
+        );
+
+        assert_eq!(fs.copy_file("dir/file", "copy").unwrap(), 11);
+        fs.rename("copy", "dir/sub/renamed").unwrap();
+        assert!(!fs.exists("copy").unwrap());
+        assert_eq!(
+            fs.open_file("dir/sub/renamed")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "HELLO world"
+        );
+        assert_eq!(
+            fs.metadata("missing").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn open_options() {
+        check_open_options(&FromVfs::new(vfs::MemoryFS::new()), "options");
+    }
+
+    #[test]
+    fn into_vfs() {
+        let root = VfsPath::new(IntoVfs::new(MemoryFS::default()));
+        let dir = root.join("dir").unwrap();
+        dir.create_dir().unwrap();
+        assert!(dir.create_dir().is_err());
+
+        let file = dir.join("file").unwrap();
+        write!(file.create_file().unwrap(), "hello").unwrap();
+        write!(file.append_file().unwrap(), " world").unwrap();
+        assert_eq!(file.read_to_string().unwrap(), "hello world");
+        assert_eq!(file.metadata().unwrap().len, 11);
+
+        let mut reader = file.open_file().unwrap();
+        reader.seek(SeekFrom::End(-5)).unwrap();
+        let mut tail = String::new();
+        reader.read_to_string(&mut tail).unwrap();
+        assert_eq!(tail, "world");
+
+        file.copy_file(&root.join("copy").unwrap()).unwrap();
+        itertools::assert_equal(
+            root.read_dir()
+                .unwrap()
+                .map(|path| path.as_str().to_owned())
+                .sorted(),
+            vec!["/copy", "/dir"],
+        );
+        file.remove_file().unwrap();
+        assert!(!file.exists().unwrap());
+        assert!(root.join("missing").unwrap().open_file().is_err());
+    }
+}
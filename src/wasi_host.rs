@@ -0,0 +1,1128 @@
+use crate::file::{Metadata, OpenOptions};
+use crate::tree::normalize_and_relativize;
+use crate::FileSystem;
+use bindings::wasi::filesystem::{preopens, types};
+use bytes::Bytes;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use types::{
+    DescriptorFlags, DescriptorStat, DescriptorType, DirectoryEntry, ErrorCode, Filesize,
+    MetadataHashValue, NewTimestamp, OpenFlags, PathFlags,
+};
+use wasmtime::component::{Linker, Resource};
+use wasmtime_wasi::bindings::clocks::wall_clock::Datetime;
+use wasmtime_wasi::bindings::io::streams::{
+    Error as StreamErrorResource, InputStream, OutputStream,
+};
+use wasmtime_wasi::{
+    DynInputStream, DynOutputStream, IoView, Pollable, StreamError, StreamResult, TrappableError,
+};
+
+/// The most bytes a stream reads or writes at once.
+const MAX_STREAM_LEN: usize = 1024 * 1024;
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit",
+        world: "virtual-filesystem:wasi-host/host",
+        trappable_imports: true,
+        trappable_error_type: {
+            "wasi:filesystem/types/error-code" => crate::wasi_host::FsError,
+        },
+        with: {
+            "wasi:io": wasmtime_wasi::bindings::io,
+            "wasi:clocks": wasmtime_wasi::bindings::clocks,
+            "wasi:filesystem/types/descriptor": crate::wasi_host::Descriptor,
+            "wasi:filesystem/types/directory-entry-stream": crate::wasi_host::DirectoryEntryStream,
+        },
+        require_store_data_send: true,
+    });
+}
+
+/// The error of a `wasi:filesystem` operation, which is either an error code for the guest or a trap.
+pub type FsError = TrappableError<ErrorCode>;
+type FsResult<T> = Result<T, FsError>;
+
+/// The filesystem a WASM guest hosted with `wasmtime` sees through the WASI preview 2 `wasi:filesystem` interface,
+/// such as a `MemoryFS`, a `ZipFS` or an `OverlayFS` of them. Guests can only reach the directories of the filesystem
+/// that are preopened for them, and nothing outside of it, so they're sandboxed by construction.
+///
+/// It replaces the filesystem of `wasmtime_wasi` in a linker that it was added to: add `wasmtime_wasi` first, allow
+/// shadowing, and then add this with `add_to_linker`. Wrap the filesystem in a `ReadOnlyFS` to keep guests from
+/// modifying it.
+///
+/// Files aren't held open by guests, but opened whenever they're read or written, since files can't be sent between
+/// threads. Symbolic links, hard links and timestamps can't be changed, and descriptors are only as fresh as the
+/// filesystem: a file that's renamed while a guest has it open is no longer found by its descriptor.
+///
+/// # Example
+/// ```no_run
+/// use virtual_filesystem::memory_fs::MemoryFS;
+/// use virtual_filesystem::wasi_host::{WasiVfs, WasiVfsView};
+/// use wasmtime::component::{Linker, ResourceTable};
+/// use wasmtime::{Engine, Store};
+/// use wasmtime_wasi::{IoView, WasiCtx, WasiCtxBuilder, WasiView};
+///
+/// struct State {
+///     ctx: WasiCtx,
+///     vfs: WasiVfs,
+///     table: ResourceTable,
+/// }
+///
+/// impl IoView for State {
+///     fn table(&mut self) -> &mut ResourceTable {
+///         &mut self.table
+///     }
+/// }
+///
+/// impl WasiView for State {
+///     fn ctx(&mut self) -> &mut WasiCtx {
+///         &mut self.ctx
+///     }
+/// }
+///
+/// impl WasiVfsView for State {
+///     fn vfs(&mut self) -> &mut WasiVfs {
+///         &mut self.vfs
+///     }
+/// }
+///
+/// let engine = Engine::default();
+/// let mut linker = Linker::<State>::new(&engine);
+/// wasmtime_wasi::add_to_linker_sync(&mut linker).unwrap();
+/// linker.allow_shadowing(true);
+/// virtual_filesystem::wasi_host::add_to_linker(&mut linker).unwrap();
+///
+/// let state = State {
+///     ctx: WasiCtxBuilder::new().build(),
+///     vfs: WasiVfs::new(MemoryFS::default()).preopen("", "/"),
+///     table: ResourceTable::new(),
+/// };
+/// let mut store = Store::new(&engine, state);
+/// // instantiate a component with the linker and the store, and run it
+/// ```
+pub struct WasiVfs {
+    fs: Arc<dyn FileSystem + Send + Sync>,
+    /// The directories that are preopened, and the paths the guest sees them at.
+    preopens: Vec<(String, String)>,
+}
+
+impl WasiVfs {
+    /// Creates the filesystem a guest sees, which has no preopened directories.
+    ///
+    /// # Arguments
+    /// `fs`: The filesystem the guest reads and writes.  
+    pub fn new<FS: FileSystem + Send + Sync + 'static>(fs: FS) -> Self {
+        Self {
+            fs: Arc::new(fs),
+            preopens: Vec::new(),
+        }
+    }
+
+    /// Preopens a directory for the guest, which can reach everything within it.
+    ///
+    /// # Arguments
+    /// `path`: The path of the directory within the filesystem.  
+    /// `guest_path`: The path the guest sees the directory at, such as `/` or `data`.  
+    pub fn preopen(mut self, path: &str, guest_path: &str) -> Self {
+        let path = normalize_and_relativize(path)
+            .to_string_lossy()
+            .trim_end_matches('/')
+            .to_owned();
+        self.preopens.push((path, guest_path.to_owned()));
+        self
+    }
+}
+
+/// A state of a store that holds a `WasiVfs`.
+pub trait WasiVfsView: IoView {
+    /// Returns the filesystem the guest sees.
+    fn vfs(&mut self) -> &mut WasiVfs;
+}
+
+impl<T: WasiVfsView + ?Sized> WasiVfsView for &mut T {
+    fn vfs(&mut self) -> &mut WasiVfs {
+        T::vfs(self)
+    }
+}
+
+/// Implements `wasi:filesystem` over the `WasiVfs` of a state.
+#[repr(transparent)]
+pub struct WasiVfsImpl<T>(pub T);
+
+/// Adds `wasi:filesystem/types` and `wasi:filesystem/preopens` to `linker`, implemented by the `WasiVfs` of the state
+/// of the store. The `wasi:io` and `wasi:clocks` interfaces that they depend on must be added too, such as by
+/// `wasmtime_wasi::add_to_linker_sync` with shadowing allowed.
+///
+/// # Arguments
+/// `linker`: The linker to add the interfaces to.  
+pub fn add_to_linker<T: WasiVfsView>(linker: &mut Linker<T>) -> wasmtime::Result<()> {
+    fn type_annotate<T, F: Fn(&mut T) -> WasiVfsImpl<&mut T>>(f: F) -> F {
+        f
+    }
+    let closure = type_annotate::<T, _>(|state| WasiVfsImpl(state));
+    types::add_to_linker_get_host(linker, closure)?;
+    preopens::add_to_linker_get_host(linker, closure)
+}
+
+/// An open file or directory, by its path.
+pub struct Descriptor {
+    /// The path of the entry within the filesystem.
+    path: String,
+    directory: bool,
+    flags: DescriptorFlags,
+}
+
+/// The remaining entries of a directory being read.
+pub struct DirectoryEntryStream(std::vec::IntoIter<DirectoryEntry>);
+
+impl<T: WasiVfsView> WasiVfsImpl<T> {
+    /// Returns the descriptor behind `fd`.
+    fn descriptor(&mut self, fd: &Resource<Descriptor>) -> FsResult<&Descriptor> {
+        self.0.table().get(fd).map_err(FsError::trap)
+    }
+
+    /// Returns the path within the filesystem of `path` relative to the directory behind `fd`.
+    ///
+    /// # Arguments
+    /// `fd`: The descriptor of the directory.  
+    /// `path`: The path relative to the directory.  
+    fn resolve(&mut self, fd: &Resource<Descriptor>, path: &str) -> FsResult<String> {
+        let descriptor = self.descriptor(fd)?;
+        if !descriptor.directory {
+            return Err(ErrorCode::NotDirectory.into());
+        }
+        Ok(resolve(&descriptor.path, path)?)
+    }
+
+    /// Returns the path of a directory whose entries may be changed.
+    fn mutable_dir(&mut self, fd: &Resource<Descriptor>, path: &str) -> FsResult<String> {
+        if !self
+            .descriptor(fd)?
+            .flags
+            .contains(DescriptorFlags::MUTATE_DIRECTORY)
+        {
+            return Err(ErrorCode::NotPermitted.into());
+        }
+        self.resolve(fd, path)
+    }
+
+    /// Returns the path of the file behind `fd`, if it was opened with `flag`.
+    fn file(&mut self, fd: &Resource<Descriptor>, flag: DescriptorFlags) -> FsResult<String> {
+        let descriptor = self.descriptor(fd)?;
+        if descriptor.directory {
+            return Err(ErrorCode::IsDirectory.into());
+        }
+        if !descriptor.flags.contains(flag) {
+            return Err(ErrorCode::BadDescriptor.into());
+        }
+        Ok(descriptor.path.clone())
+    }
+
+    fn fs(&mut self) -> Arc<dyn FileSystem + Send + Sync> {
+        self.0.vfs().fs.clone()
+    }
+
+    fn stat_path(&mut self, path: &str) -> FsResult<DescriptorStat> {
+        let metadata = self.fs().metadata(path).map_err(fs_error)?;
+        let modified = metadata
+            .modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| Datetime {
+                seconds: modified.as_secs(),
+                nanoseconds: modified.subsec_nanos(),
+            });
+        Ok(DescriptorStat {
+            type_: descriptor_type(&metadata),
+            link_count: 1,
+            size: metadata.len,
+            data_access_timestamp: None,
+            data_modification_timestamp: modified,
+            status_change_timestamp: None,
+        })
+    }
+
+    fn metadata_hash_path(&mut self, path: &str) -> FsResult<MetadataHashValue> {
+        let metadata = self.fs().metadata(path).map_err(fs_error)?;
+        let hash = |seed: u8| {
+            let mut hasher = DefaultHasher::new();
+            (seed, path, metadata.len, metadata.modified).hash(&mut hasher);
+            hasher.finish()
+        };
+        Ok(MetadataHashValue {
+            lower: hash(0),
+            upper: hash(1),
+        })
+    }
+}
+
+impl<T: WasiVfsView> types::Host for WasiVfsImpl<T> {
+    fn convert_error_code(&mut self, err: FsError) -> wasmtime::Result<ErrorCode> {
+        err.downcast()
+    }
+
+    fn filesystem_error_code(
+        &mut self,
+        err: Resource<StreamErrorResource>,
+    ) -> wasmtime::Result<Option<ErrorCode>> {
+        Ok(self
+            .0
+            .table()
+            .get(&err)?
+            .downcast_ref::<io::Error>()
+            .map(error_code))
+    }
+}
+
+impl<T: WasiVfsView> types::HostDescriptor for WasiVfsImpl<T> {
+    fn read_via_stream(
+        &mut self,
+        fd: Resource<Descriptor>,
+        offset: Filesize,
+    ) -> FsResult<Resource<InputStream>> {
+        let stream = ReadStream {
+            path: self.file(&fd, DescriptorFlags::READ)?,
+            fs: self.fs(),
+            offset,
+        };
+        let stream: DynInputStream = Box::new(stream);
+        self.0.table().push(stream).map_err(FsError::trap)
+    }
+
+    fn write_via_stream(
+        &mut self,
+        fd: Resource<Descriptor>,
+        offset: Filesize,
+    ) -> FsResult<Resource<OutputStream>> {
+        let stream = WriteStream {
+            path: self.file(&fd, DescriptorFlags::WRITE)?,
+            fs: self.fs(),
+            offset: Some(offset),
+        };
+        let stream: DynOutputStream = Box::new(stream);
+        self.0.table().push(stream).map_err(FsError::trap)
+    }
+
+    fn append_via_stream(&mut self, fd: Resource<Descriptor>) -> FsResult<Resource<OutputStream>> {
+        let stream = WriteStream {
+            path: self.file(&fd, DescriptorFlags::WRITE)?,
+            fs: self.fs(),
+            offset: None,
+        };
+        let stream: DynOutputStream = Box::new(stream);
+        self.0.table().push(stream).map_err(FsError::trap)
+    }
+
+    fn advise(
+        &mut self,
+        fd: Resource<Descriptor>,
+        _offset: Filesize,
+        _len: Filesize,
+        _advice: types::Advice,
+    ) -> FsResult<()> {
+        self.descriptor(&fd).map(|_| ())
+    }
+
+    fn sync_data(&mut self, fd: Resource<Descriptor>) -> FsResult<()> {
+        // every write is flushed when it's made
+        self.descriptor(&fd).map(|_| ())
+    }
+
+    fn get_flags(&mut self, fd: Resource<Descriptor>) -> FsResult<DescriptorFlags> {
+        Ok(self.descriptor(&fd)?.flags)
+    }
+
+    fn get_type(&mut self, fd: Resource<Descriptor>) -> FsResult<DescriptorType> {
+        Ok(match self.descriptor(&fd)?.directory {
+            true => DescriptorType::Directory,
+            false => DescriptorType::RegularFile,
+        })
+    }
+
+    fn set_size(&mut self, fd: Resource<Descriptor>, size: Filesize) -> FsResult<()> {
+        let path = self.file(&fd, DescriptorFlags::WRITE)?;
+        let fs = self.fs();
+        if size == 0 {
+            let options = OpenOptions::default().write(true).truncate(true);
+            return fs
+                .open_file_options(&path, &options)
+                .map(drop)
+                .map_err(fs_error);
+        }
+
+        // files can only be extended, by writing zeroes past their end
+        let mut file = fs
+            .open_file_options(&path, &OpenOptions::default().write(true))
+            .map_err(fs_error)?;
+        let len = file.seek(SeekFrom::End(0)).map_err(fs_error)?;
+        if size < len {
+            return Err(ErrorCode::Unsupported.into());
+        }
+        io::copy(&mut io::repeat(0).take(size - len), &mut file).map_err(fs_error)?;
+        file.flush().map_err(fs_error)
+    }
+
+    fn set_times(
+        &mut self,
+        fd: Resource<Descriptor>,
+        _data_access_timestamp: NewTimestamp,
+        _data_modification_timestamp: NewTimestamp,
+    ) -> FsResult<()> {
+        self.descriptor(&fd)?;
+        Err(ErrorCode::Unsupported.into())
+    }
+
+    fn read(
+        &mut self,
+        fd: Resource<Descriptor>,
+        len: Filesize,
+        offset: Filesize,
+    ) -> FsResult<(Vec<u8>, bool)> {
+        let path = self.file(&fd, DescriptorFlags::READ)?;
+        let data = read_at(
+            &*self.fs(),
+            &path,
+            offset,
+            len.min(MAX_STREAM_LEN as u64) as usize,
+        )
+        .map_err(fs_error)?;
+        let end = (data.len() as u64) < len;
+        Ok((data, end))
+    }
+
+    fn write(
+        &mut self,
+        fd: Resource<Descriptor>,
+        buffer: Vec<u8>,
+        offset: Filesize,
+    ) -> FsResult<Filesize> {
+        let path = self.file(&fd, DescriptorFlags::WRITE)?;
+        write_at(&*self.fs(), &path, Some(offset), &buffer).map_err(fs_error)?;
+        Ok(buffer.len() as Filesize)
+    }
+
+    fn read_directory(
+        &mut self,
+        fd: Resource<Descriptor>,
+    ) -> FsResult<Resource<DirectoryEntryStream>> {
+        let descriptor = self.descriptor(&fd)?;
+        if !descriptor.directory {
+            return Err(ErrorCode::NotDirectory.into());
+        }
+        let path = descriptor.path.clone();
+
+        let mut entries = Vec::new();
+        for entry in self.fs().read_dir(&path).map_err(fs_error)? {
+            let entry = entry.map_err(fs_error)?;
+            entries.push(DirectoryEntry {
+                type_: descriptor_type(&entry.metadata),
+                name: entry.file_name().to_owned(),
+            });
+        }
+        self.0
+            .table()
+            .push(DirectoryEntryStream(entries.into_iter()))
+            .map_err(FsError::trap)
+    }
+
+    fn sync(&mut self, fd: Resource<Descriptor>) -> FsResult<()> {
+        self.descriptor(&fd).map(|_| ())
+    }
+
+    fn create_directory_at(&mut self, fd: Resource<Descriptor>, path: String) -> FsResult<()> {
+        let path = self.mutable_dir(&fd, &path)?;
+        self.fs().create_dir(&path).map_err(fs_error)
+    }
+
+    fn stat(&mut self, fd: Resource<Descriptor>) -> FsResult<DescriptorStat> {
+        let path = self.descriptor(&fd)?.path.clone();
+        self.stat_path(&path)
+    }
+
+    fn stat_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        _path_flags: PathFlags,
+        path: String,
+    ) -> FsResult<DescriptorStat> {
+        let path = self.resolve(&fd, &path)?;
+        self.stat_path(&path)
+    }
+
+    fn set_times_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        _path_flags: PathFlags,
+        path: String,
+        _data_access_timestamp: NewTimestamp,
+        _data_modification_timestamp: NewTimestamp,
+    ) -> FsResult<()> {
+        self.resolve(&fd, &path)?;
+        Err(ErrorCode::Unsupported.into())
+    }
+
+    fn link_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        _old_path_flags: PathFlags,
+        old_path: String,
+        _new_descriptor: Resource<Descriptor>,
+        _new_path: String,
+    ) -> FsResult<()> {
+        self.resolve(&fd, &old_path)?;
+        Err(ErrorCode::Unsupported.into())
+    }
+
+    fn open_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        _path_flags: PathFlags,
+        path: String,
+        open_flags: OpenFlags,
+        flags: DescriptorFlags,
+    ) -> FsResult<Resource<Descriptor>> {
+        let parent_flags = self.descriptor(&fd)?.flags;
+        let path = self.resolve(&fd, &path)?;
+        let fs = self.fs();
+
+        // descriptors can't be opened with more permissions than the directory they're opened from
+        let writes = flags.intersects(DescriptorFlags::WRITE | DescriptorFlags::MUTATE_DIRECTORY)
+            || open_flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNCATE);
+        if writes && !parent_flags.contains(DescriptorFlags::MUTATE_DIRECTORY) {
+            return Err(ErrorCode::NotPermitted.into());
+        }
+
+        let metadata = match fs.metadata(&path) {
+            Ok(_) if open_flags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) => {
+                return Err(ErrorCode::Exist.into())
+            }
+            Ok(metadata) => Some(metadata),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(fs_error(err)),
+        };
+        let directory = match metadata {
+            Some(metadata) if metadata.is_directory() => {
+                if flags.contains(DescriptorFlags::WRITE)
+                    || open_flags.contains(OpenFlags::TRUNCATE)
+                {
+                    return Err(ErrorCode::IsDirectory.into());
+                }
+                true
+            }
+            _ if open_flags.contains(OpenFlags::DIRECTORY) => match metadata {
+                Some(_) => return Err(ErrorCode::NotDirectory.into()),
+                None => return Err(ErrorCode::NoEntry.into()),
+            },
+            _ => {
+                // the file is opened now to create or truncate it, and to check that it can be opened at all
+                let write = flags.contains(DescriptorFlags::WRITE)
+                    || open_flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNCATE);
+                let options = OpenOptions::new()
+                    .read(flags.contains(DescriptorFlags::READ) || !write)
+                    .write(write)
+                    .create(open_flags.contains(OpenFlags::CREATE))
+                    .truncate(open_flags.contains(OpenFlags::TRUNCATE));
+                fs.open_file_options(&path, &options).map_err(fs_error)?;
+                false
+            }
+        };
+
+        let descriptor = Descriptor {
+            path,
+            directory,
+            flags: match directory {
+                true => flags & (DescriptorFlags::READ | DescriptorFlags::MUTATE_DIRECTORY),
+                false => flags & (DescriptorFlags::READ | DescriptorFlags::WRITE),
+            },
+        };
+        self.0.table().push(descriptor).map_err(FsError::trap)
+    }
+
+    fn readlink_at(&mut self, fd: Resource<Descriptor>, path: String) -> FsResult<String> {
+        // there are no symbolic links, so whatever's at the path isn't one
+        let path = self.resolve(&fd, &path)?;
+        self.fs().metadata(&path).map_err(fs_error)?;
+        Err(ErrorCode::Invalid.into())
+    }
+
+    fn remove_directory_at(&mut self, fd: Resource<Descriptor>, path: String) -> FsResult<()> {
+        let path = self.mutable_dir(&fd, &path)?;
+        let fs = self.fs();
+        if fs.read_dir(&path).map_err(fs_error)?.next().is_some() {
+            return Err(ErrorCode::NotEmpty.into());
+        }
+        fs.remove_dir(&path).map_err(fs_error)
+    }
+
+    fn rename_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        old_path: String,
+        new_descriptor: Resource<Descriptor>,
+        new_path: String,
+    ) -> FsResult<()> {
+        let old_path = self.mutable_dir(&fd, &old_path)?;
+        let new_path = self.mutable_dir(&new_descriptor, &new_path)?;
+        self.fs().rename(&old_path, &new_path).map_err(fs_error)
+    }
+
+    fn symlink_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        _old_path: String,
+        new_path: String,
+    ) -> FsResult<()> {
+        self.mutable_dir(&fd, &new_path)?;
+        Err(ErrorCode::Unsupported.into())
+    }
+
+    fn unlink_file_at(&mut self, fd: Resource<Descriptor>, path: String) -> FsResult<()> {
+        let path = self.mutable_dir(&fd, &path)?;
+        let fs = self.fs();
+        if fs.metadata(&path).map_err(fs_error)?.is_directory() {
+            return Err(ErrorCode::IsDirectory.into());
+        }
+        fs.remove_file(&path).map_err(fs_error)
+    }
+
+    fn is_same_object(
+        &mut self,
+        fd: Resource<Descriptor>,
+        other: Resource<Descriptor>,
+    ) -> wasmtime::Result<bool> {
+        let path = self.0.table().get(&fd)?.path.clone();
+        Ok(self.0.table().get(&other)?.path == path)
+    }
+
+    fn metadata_hash(&mut self, fd: Resource<Descriptor>) -> FsResult<MetadataHashValue> {
+        let path = self.descriptor(&fd)?.path.clone();
+        self.metadata_hash_path(&path)
+    }
+
+    fn metadata_hash_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        _path_flags: PathFlags,
+        path: String,
+    ) -> FsResult<MetadataHashValue> {
+        let path = self.resolve(&fd, &path)?;
+        self.metadata_hash_path(&path)
+    }
+
+    fn drop(&mut self, fd: Resource<Descriptor>) -> wasmtime::Result<()> {
+        self.0.table().delete(fd)?;
+        Ok(())
+    }
+}
+
+impl<T: WasiVfsView> types::HostDirectoryEntryStream for WasiVfsImpl<T> {
+    fn read_directory_entry(
+        &mut self,
+        stream: Resource<DirectoryEntryStream>,
+    ) -> FsResult<Option<DirectoryEntry>> {
+        let stream = self.0.table().get_mut(&stream).map_err(FsError::trap)?;
+        Ok(stream.0.next())
+    }
+
+    fn drop(&mut self, stream: Resource<DirectoryEntryStream>) -> wasmtime::Result<()> {
+        self.0.table().delete(stream)?;
+        Ok(())
+    }
+}
+
+impl<T: WasiVfsView> preopens::Host for WasiVfsImpl<T> {
+    fn get_directories(&mut self) -> wasmtime::Result<Vec<(Resource<Descriptor>, String)>> {
+        let preopens = self.0.vfs().preopens.clone();
+        preopens
+            .into_iter()
+            .map(|(path, guest_path)| {
+                let descriptor = Descriptor {
+                    path,
+                    directory: true,
+                    flags: DescriptorFlags::READ | DescriptorFlags::MUTATE_DIRECTORY,
+                };
+                Ok((self.0.table().push(descriptor)?, guest_path))
+            })
+            .collect()
+    }
+}
+
+/// A stream that reads a file from an offset.
+struct ReadStream {
+    fs: Arc<dyn FileSystem + Send + Sync>,
+    path: String,
+    offset: u64,
+}
+
+#[wasmtime_wasi::async_trait]
+impl Pollable for ReadStream {
+    async fn ready(&mut self) {}
+}
+
+impl wasmtime_wasi::InputStream for ReadStream {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        let data = read_at(&*self.fs, &self.path, self.offset, size.min(MAX_STREAM_LEN))
+            .map_err(|err| StreamError::LastOperationFailed(err.into()))?;
+        if data.is_empty() && size > 0 {
+            return Err(StreamError::Closed);
+        }
+        self.offset += data.len() as u64;
+        Ok(data.into())
+    }
+}
+
+/// A stream that writes a file from an offset, or at its end.
+struct WriteStream {
+    fs: Arc<dyn FileSystem + Send + Sync>,
+    path: String,
+    /// The offset of the next write, or `None` to append.
+    offset: Option<u64>,
+}
+
+#[wasmtime_wasi::async_trait]
+impl Pollable for WriteStream {
+    async fn ready(&mut self) {}
+}
+
+impl wasmtime_wasi::OutputStream for WriteStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        write_at(&*self.fs, &self.path, self.offset, &bytes)
+            .map_err(|err| StreamError::LastOperationFailed(err.into()))?;
+        if let Some(offset) = &mut self.offset {
+            *offset += bytes.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        // every write is flushed when it's made
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        Ok(MAX_STREAM_LEN)
+    }
+}
+
+/// Reads up to `len` bytes of the file at `path` from `offset`.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `path`: The path of the file.  
+/// `offset`: The offset to read from.  
+/// `len`: The most bytes to read.  
+fn read_at(fs: &dyn FileSystem, path: &str, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut file = fs.open_file(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::with_capacity(len);
+    file.take(len as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Writes `data` to the file at `path` at `offset`, or at its end if there's no offset.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `path`: The path of the file.  
+/// `offset`: The offset to write at.  
+/// `data`: The data to write.  
+fn write_at(fs: &dyn FileSystem, path: &str, offset: Option<u64>, data: &[u8]) -> io::Result<()> {
+    let mut file = fs.open_file_options(path, &OpenOptions::default().write(true))?;
+    file.seek(match offset {
+        Some(offset) => SeekFrom::Start(offset),
+        None => SeekFrom::End(0),
+    })?;
+    file.write_all(data)?;
+    file.flush()
+}
+
+/// Returns the path of `path` relative to the directory at `base`. Absolute paths and paths that leave the directory
+/// aren't permitted, so guests can't escape their preopened directories.
+///
+/// # Arguments
+/// `base`: The path of the directory within the filesystem.  
+/// `path`: The path relative to the directory.  
+fn resolve(base: &str, path: &str) -> Result<String, ErrorCode> {
+    if path.starts_with('/') {
+        return Err(ErrorCode::NotPermitted);
+    }
+
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop().ok_or(ErrorCode::NotPermitted)?;
+            }
+            name => components.push(name),
+        }
+    }
+    Ok(match (base.is_empty(), components.is_empty()) {
+        (_, true) => base.to_owned(),
+        (true, false) => components.join("/"),
+        (false, false) => format!("{base}/{}", components.join("/")),
+    })
+}
+
+/// Returns the type of the entry described by `metadata`.
+fn descriptor_type(metadata: &Metadata) -> DescriptorType {
+    match metadata.is_directory() {
+        true => DescriptorType::Directory,
+        false if metadata.is_file() => DescriptorType::RegularFile,
+        false => DescriptorType::Unknown,
+    }
+}
+
+/// Returns the error of an operation for the guest.
+fn fs_error(err: io::Error) -> FsError {
+    error_code(&err).into()
+}
+
+/// Returns the error code of an I/O error.
+fn error_code(err: &io::Error) -> ErrorCode {
+    match err.kind() {
+        ErrorKind::NotFound => ErrorCode::NoEntry,
+        ErrorKind::PermissionDenied => ErrorCode::NotPermitted,
+        ErrorKind::AlreadyExists => ErrorCode::Exist,
+        ErrorKind::NotADirectory => ErrorCode::NotDirectory,
+        ErrorKind::IsADirectory => ErrorCode::IsDirectory,
+        ErrorKind::DirectoryNotEmpty => ErrorCode::NotEmpty,
+        ErrorKind::InvalidInput => ErrorCode::Invalid,
+        ErrorKind::Unsupported => ErrorCode::Unsupported,
+        ErrorKind::ReadOnlyFilesystem => ErrorCode::ReadOnly,
+        ErrorKind::StorageFull => ErrorCode::InsufficientSpace,
+        ErrorKind::QuotaExceeded => ErrorCode::Quota,
+        ErrorKind::CrossesDevices => ErrorCode::CrossDevice,
+        ErrorKind::FileTooLarge => ErrorCode::FileTooLarge,
+        ErrorKind::Interrupted => ErrorCode::Interrupted,
+        ErrorKind::WouldBlock => ErrorCode::WouldBlock,
+        _ => ErrorCode::Io,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    use crate::wasi_host::bindings::wasi::filesystem::preopens::Host as _;
+    use crate::wasi_host::bindings::wasi::filesystem::types::{
+        DescriptorFlags, DescriptorType, ErrorCode, HostDescriptor, HostDirectoryEntryStream,
+        OpenFlags, PathFlags,
+    };
+    use crate::wasi_host::{resolve, Descriptor, FsError, WasiVfs, WasiVfsImpl, WasiVfsView};
+    use crate::FileSystem;
+    use std::io::Write;
+    use wasmtime::component::{Resource, ResourceTable};
+    use wasmtime_wasi::{DynInputStream, DynOutputStream, IoView, StreamError};
+
+    struct State {
+        vfs: WasiVfs,
+        table: ResourceTable,
+    }
+
+    impl IoView for State {
+        fn table(&mut self) -> &mut ResourceTable {
+            &mut self.table
+        }
+    }
+
+    impl WasiVfsView for State {
+        fn vfs(&mut self) -> &mut WasiVfs {
+            &mut self.vfs
+        }
+    }
+
+    /// Returns a state with `dir` of `fs` preopened, and the descriptor of it.
+    fn preopened(fs: MemoryFS, dir: &str) -> (State, Resource<Descriptor>) {
+        let mut state = State {
+            vfs: WasiVfs::new(fs).preopen(dir, "/"),
+            table: ResourceTable::new(),
+        };
+        let (fd, guest_path) = WasiVfsImpl(&mut state)
+            .get_directories()
+            .unwrap()
+            .pop()
+            .unwrap();
+        assert_eq!(guest_path, "/");
+        (state, fd)
+    }
+
+    fn code(err: FsError) -> ErrorCode {
+        err.downcast().unwrap()
+    }
+
+    fn open(
+        host: &mut WasiVfsImpl<&mut State>,
+        fd: &Resource<Descriptor>,
+        path: &str,
+        open_flags: OpenFlags,
+        flags: DescriptorFlags,
+    ) -> Result<Resource<Descriptor>, ErrorCode> {
+        host.open_at(
+            borrow(fd),
+            PathFlags::empty(),
+            path.to_owned(),
+            open_flags,
+            flags,
+        )
+        .map_err(code)
+    }
+
+    fn borrow(fd: &Resource<Descriptor>) -> Resource<Descriptor> {
+        Resource::new_borrow(fd.rep())
+    }
+
+    #[test]
+    fn resolve_paths() {
+        assert_eq!(resolve("", "a/./b//c").unwrap(), "a/b/c");
+        assert_eq!(resolve("dir", "a/../b").unwrap(), "dir/b");
+        assert_eq!(resolve("dir", ".").unwrap(), "dir");
+        assert_eq!(resolve("dir", "..").unwrap_err(), ErrorCode::NotPermitted);
+        assert_eq!(
+            resolve("dir", "a/../../b").unwrap_err(),
+            ErrorCode::NotPermitted
+        );
+        assert_eq!(resolve("dir", "/etc").unwrap_err(), ErrorCode::NotPermitted);
+    }
+
+    #[test]
+    fn read_write() {
+        let fs = MemoryFS::default();
+        fs.create_dir("dir").unwrap();
+        let (mut state, dir) = preopened(fs, "/dir/");
+        let mut host = WasiVfsImpl(&mut state);
+
+        let file = open(
+            &mut host,
+            &dir,
+            "file",
+            OpenFlags::CREATE,
+            DescriptorFlags::READ | DescriptorFlags::WRITE,
+        )
+        .unwrap();
+        assert_eq!(host.write(borrow(&file), b"hello".to_vec(), 0).unwrap(), 5);
+        assert_eq!(host.write(borrow(&file), b" world".to_vec(), 5).unwrap(), 6);
+        assert_eq!(
+            host.read(borrow(&file), 5, 6).unwrap(),
+            (b"world".to_vec(), false)
+        );
+        assert_eq!(
+            host.read(borrow(&file), 10, 6).unwrap(),
+            (b"world".to_vec(), true)
+        );
+
+        host.set_size(borrow(&file), 13).unwrap();
+        let stat = host.stat(borrow(&file)).unwrap();
+        assert_eq!(stat.type_, DescriptorType::RegularFile);
+        assert_eq!(stat.size, 13);
+        assert_eq!(
+            code(host.set_size(borrow(&file), 2).unwrap_err()),
+            ErrorCode::Unsupported
+        );
+        host.set_size(borrow(&file), 0).unwrap();
+        assert_eq!(host.stat(borrow(&file)).unwrap().size, 0);
+
+        // files can't be written through read-only descriptors, nor opened exclusively if they exist
+        let read_only = open(
+            &mut host,
+            &dir,
+            "file",
+            OpenFlags::empty(),
+            DescriptorFlags::READ,
+        )
+        .unwrap();
+        assert_eq!(
+            code(
+                host.write(borrow(&read_only), b"hi".to_vec(), 0)
+                    .unwrap_err()
+            ),
+            ErrorCode::BadDescriptor
+        );
+        assert_eq!(
+            open(
+                &mut host,
+                &dir,
+                "file",
+                OpenFlags::CREATE | OpenFlags::EXCLUSIVE,
+                DescriptorFlags::WRITE
+            )
+            .unwrap_err(),
+            ErrorCode::Exist
+        );
+        assert!(host
+            .is_same_object(borrow(&file), borrow(&read_only))
+            .unwrap());
+        assert_eq!(
+            host.metadata_hash(borrow(&file)).unwrap().lower,
+            host.metadata_hash_at(borrow(&dir), PathFlags::empty(), "file".to_owned())
+                .unwrap()
+                .lower
+        );
+    }
+
+    #[test]
+    fn sandboxed() {
+        let fs = MemoryFS::default();
+        fs.create_dir("dir").unwrap();
+        write!(fs.create_file("secret").unwrap(), "secret").unwrap();
+        let (mut state, dir) = preopened(fs, "dir");
+        let mut host = WasiVfsImpl(&mut state);
+
+        for path in ["../secret", "/secret"] {
+            assert_eq!(
+                open(
+                    &mut host,
+                    &dir,
+                    path,
+                    OpenFlags::empty(),
+                    DescriptorFlags::READ
+                )
+                .unwrap_err(),
+                ErrorCode::NotPermitted
+            );
+        }
+        assert_eq!(
+            open(
+                &mut host,
+                &dir,
+                "secret",
+                OpenFlags::empty(),
+                DescriptorFlags::READ
+            )
+            .unwrap_err(),
+            ErrorCode::NoEntry
+        );
+
+        // directories opened without `MUTATE_DIRECTORY` can't be changed through
+        let read_only = open(
+            &mut host,
+            &dir,
+            ".",
+            OpenFlags::DIRECTORY,
+            DescriptorFlags::READ,
+        )
+        .unwrap();
+        assert_eq!(
+            code(
+                host.create_directory_at(borrow(&read_only), "sub".to_owned())
+                    .unwrap_err()
+            ),
+            ErrorCode::NotPermitted
+        );
+        assert_eq!(
+            open(
+                &mut host,
+                &read_only,
+                "file",
+                OpenFlags::CREATE,
+                DescriptorFlags::WRITE
+            )
+            .unwrap_err(),
+            ErrorCode::NotPermitted
+        );
+    }
+
+    #[test]
+    fn directories() {
+        let (mut state, root) = preopened(MemoryFS::default(), "/");
+        let mut host = WasiVfsImpl(&mut state);
+
+        host.create_directory_at(borrow(&root), "dir".to_owned())
+            .unwrap();
+        open(
+            &mut host,
+            &root,
+            "dir/file",
+            OpenFlags::CREATE,
+            DescriptorFlags::WRITE,
+        )
+        .unwrap();
+        let dir = open(
+            &mut host,
+            &root,
+            "dir",
+            OpenFlags::DIRECTORY,
+            DescriptorFlags::READ | DescriptorFlags::MUTATE_DIRECTORY,
+        )
+        .unwrap();
+        assert_eq!(
+            host.get_type(borrow(&dir)).unwrap(),
+            DescriptorType::Directory
+        );
+
+        let entries = host.read_directory(borrow(&root)).unwrap();
+        let entry = host
+            .read_directory_entry(Resource::new_borrow(entries.rep()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.name, "dir");
+        assert_eq!(entry.type_, DescriptorType::Directory);
+        assert!(host
+            .read_directory_entry(Resource::new_borrow(entries.rep()))
+            .unwrap()
+            .is_none());
+
+        assert_eq!(
+            code(
+                host.remove_directory_at(borrow(&root), "dir".to_owned())
+                    .unwrap_err()
+            ),
+            ErrorCode::NotEmpty
+        );
+        assert_eq!(
+            code(
+                host.unlink_file_at(borrow(&root), "dir".to_owned())
+                    .unwrap_err()
+            ),
+            ErrorCode::IsDirectory
+        );
+        host.rename_at(
+            borrow(&dir),
+            "file".to_owned(),
+            borrow(&root),
+            "moved".to_owned(),
+        )
+        .unwrap();
+        host.remove_directory_at(borrow(&root), "dir".to_owned())
+            .unwrap();
+        host.unlink_file_at(borrow(&root), "moved".to_owned())
+            .unwrap();
+        assert_eq!(
+            code(
+                host.stat_at(borrow(&root), PathFlags::empty(), "moved".to_owned())
+                    .unwrap_err()
+            ),
+            ErrorCode::NoEntry
+        );
+    }
+
+    #[test]
+    fn streams() {
+        let (mut state, root) = preopened(MemoryFS::default(), "");
+        let mut host = WasiVfsImpl(&mut state);
+        let file = open(
+            &mut host,
+            &root,
+            "file",
+            OpenFlags::CREATE,
+            DescriptorFlags::READ | DescriptorFlags::WRITE,
+        )
+        .unwrap();
+
+        let output = host.write_via_stream(borrow(&file), 0).unwrap();
+        let append = host.append_via_stream(borrow(&file)).unwrap();
+        let input = host.read_via_stream(borrow(&file), 2).unwrap();
+
+        let output: &mut DynOutputStream = host.0.table().get_mut(&output).unwrap();
+        output.write("hello".into()).unwrap();
+        let append: &mut DynOutputStream = host.0.table().get_mut(&append).unwrap();
+        append.write(" world".into()).unwrap();
+
+        let input: &mut DynInputStream = host.0.table().get_mut(&input).unwrap();
+        assert_eq!(input.read(4).unwrap(), "llo ");
+        assert_eq!(input.read(100).unwrap(), "world");
+        assert!(matches!(input.read(100), Err(StreamError::Closed)));
+    }
+}
@@ -0,0 +1,957 @@
+use crate::file::{Metadata, OpenOptions};
+use crate::time::DateTime;
+use crate::util::{normalize_path, path};
+use crate::{util, FileSystem};
+use std::io;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::thread;
+use std::time::SystemTime;
+
+/// The longest request line or header accepted.
+const MAX_LINE_LEN: usize = 64 * 1024;
+/// The most headers a request may have.
+const MAX_HEADERS: usize = 256;
+/// The methods that are served.
+const METHODS: &str = "OPTIONS, GET, HEAD, PUT, DELETE, MKCOL, MOVE, PROPFIND";
+
+/// A WebDAV server that serves a filesystem over HTTP, so that it can be browsed and edited by file managers such as
+/// Finder and Explorer, or by any WebDAV client.
+///
+/// It's a class 1 server, so locks aren't supported. Some clients mount such servers read-only, such as Finder, while
+/// the others may edit files concurrently. Requests aren't authenticated, and connections aren't secured with TLS.
+pub struct Server<FS> {
+    fs: FS,
+}
+
+impl<FS: FileSystem + Sync> Server<FS> {
+    /// Creates a server for a filesystem, whose root is served as `/`.
+    ///
+    /// # Arguments
+    /// `fs`: The filesystem to serve.  
+    pub fn new(fs: FS) -> Self {
+        Self { fs }
+    }
+
+    /// Serves the filesystem on `addr` until accepting a connection fails.
+    ///
+    /// # Arguments
+    /// `addr`: The address to listen on.  
+    pub fn serve<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        self.serve_listener(TcpListener::bind(addr)?)
+    }
+
+    /// Serves the filesystem on the connections accepted by `listener` until accepting one fails, each on its own
+    /// thread.
+    ///
+    /// # Arguments
+    /// `listener`: The listener to accept connections from.  
+    pub fn serve_listener(&self, listener: TcpListener) -> io::Result<()> {
+        thread::scope(|scope| {
+            for stream in listener.incoming() {
+                let stream = stream?;
+                scope.spawn(move || self.serve_connection(stream));
+            }
+            Ok(())
+        })
+    }
+
+    /// Serves the requests of a single connection until it's closed, such as one that was accepted elsewhere or that's
+    /// secured with TLS.
+    ///
+    /// # Arguments
+    /// `stream`: The connection.  
+    pub fn serve_connection<S: Read + Write>(&self, stream: S) -> io::Result<()> {
+        let mut stream = BufReader::new(stream);
+        while let Some(request) = Request::read(&mut stream)? {
+            let mut body = Body::new(&request, &mut stream)?;
+            let mut response = self.respond(&request, &mut body);
+            // the rest of the body is discarded, so that the next request can be read
+            if io::copy(&mut body, &mut io::sink()).is_err() {
+                response.close = true;
+            }
+            response.close |= request.close;
+            let close = response.close;
+            response.write(stream.get_mut(), request.method == "HEAD")?;
+            if close {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a request, returning its response.
+    fn respond(&self, request: &Request, body: &mut Body<impl BufRead>) -> Response {
+        let Some(path) = request.path() else {
+            return Response::status(400);
+        };
+        let result = match request.method.as_str() {
+            "OPTIONS" => Ok(Response::status(200)
+                .header("DAV", "1")
+                .header("Allow", METHODS)
+                .header("MS-Author-Via", "DAV")),
+            "GET" | "HEAD" => self.get(request, &path),
+            "PUT" => self.put(&path, body),
+            "DELETE" => self.delete(&path),
+            "MKCOL" => self.mkcol(&path, body),
+            "MOVE" => self.r#move(request, &path),
+            "PROPFIND" => self.propfind(request, &path),
+            _ => Ok(Response::status(405).header("Allow", METHODS)),
+        };
+        result.unwrap_or_else(|err| Response::status(error_status(&err)))
+    }
+
+    /// Returns the contents of a file, or an index of a directory.
+    fn get(&self, request: &Request, path: &str) -> crate::Result<Response> {
+        let metadata = util::metadata_or_root(&self.fs, path)?;
+        if metadata.is_directory() {
+            return self.index(path);
+        }
+
+        let mut file = self.fs.open_file(path)?;
+        let len = metadata.len();
+        let mut response = Response::status(200)
+            .header("Content-Type", "application/octet-stream")
+            .header("Accept-Ranges", "bytes");
+        if let Some(modified) = metadata.modified {
+            response = response.header("Last-Modified", &http_date(modified));
+        }
+
+        let range = request.header("range").map(|range| parse_range(range, len));
+        let (start, end) = match range {
+            Some(Some((start, end))) => {
+                response.code = 206;
+                response = response.header("Content-Range", &format!("bytes {start}-{end}/{len}"));
+                (start, end + 1)
+            }
+            Some(None) => {
+                return Ok(Response::status(416).header("Content-Range", &format!("bytes */{len}")))
+            }
+            None => (0, len),
+        };
+        file.seek(SeekFrom::Start(start))?;
+        Ok(response.body(Box::new(file), end - start))
+    }
+
+    /// Returns an HTML index of the directory at `path`, which browsers show.
+    fn index(&self, path: &str) -> crate::Result<Response> {
+        let mut entries = self.entries(path)?;
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let title = escape(&format!("/{path}"));
+        let mut html = format!("<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Index of {title}</title></head><body><h1>Index of {title}</h1><ul>");
+        if !path.is_empty() {
+            html.push_str("<li><a href=\"../\">../</a></li>");
+        }
+        for (name, metadata) in entries {
+            let suffix = if metadata.is_directory() { "/" } else { "" };
+            html.push_str(&format!(
+                "<li><a href=\"{}{suffix}\">{}{suffix}</a></li>",
+                path::encode(&name),
+                escape(&name)
+            ));
+        }
+        html.push_str("</ul></body></html>");
+        let len = html.len() as u64;
+        Ok(Response::status(200)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(Box::new(io::Cursor::new(html.into_bytes())), len))
+    }
+
+    /// Writes the body of the request to the file at `path`, replacing its contents.
+    fn put(&self, path: &str, body: &mut Body<impl BufRead>) -> crate::Result<Response> {
+        let existed = match util::metadata_or_root(&self.fs, path) {
+            Ok(metadata) if metadata.is_directory() => return Ok(Response::status(405)),
+            Ok(_) => true,
+            Err(err) if err.kind() == ErrorKind::NotFound => false,
+            Err(err) => return Err(err),
+        };
+        if !existed && !self.parent_exists(path)? {
+            return Ok(Response::status(409));
+        }
+
+        let options = OpenOptions {
+            create: true,
+            write: true,
+            truncate: true,
+            ..OpenOptions::default()
+        };
+        let mut file = self.fs.open_file_options(path, &options)?;
+        io::copy(body, &mut file)?;
+        file.flush()?;
+        Ok(Response::status(if existed { 204 } else { 201 }))
+    }
+
+    /// Removes the file or directory at `path`, along with the contents of the directory.
+    fn delete(&self, path: &str) -> crate::Result<Response> {
+        // the root can't be removed
+        if path.is_empty() {
+            return Ok(Response::status(403));
+        }
+        self.remove(path)?;
+        Ok(Response::status(204))
+    }
+
+    /// Creates a directory at `path`.
+    fn mkcol(&self, path: &str, body: &mut Body<impl BufRead>) -> crate::Result<Response> {
+        // bodies describing what to create aren't understood
+        if body.read(&mut [0])? > 0 {
+            return Ok(Response::status(415));
+        }
+        if self.fs.exists(path)? || path.is_empty() {
+            return Ok(Response::status(405));
+        }
+        if !self.parent_exists(path)? {
+            return Ok(Response::status(409));
+        }
+
+        self.fs.create_dir(path)?;
+        Ok(Response::status(201))
+    }
+
+    /// Moves the file or directory at `path` to the destination of the request.
+    fn r#move(&self, request: &Request, path: &str) -> crate::Result<Response> {
+        let Some(destination) = request.header("destination").and_then(destination_path) else {
+            return Ok(Response::status(400));
+        };
+        // an entry can't be moved onto or into itself, and the root can't be moved
+        if path.is_empty()
+            || destination.is_empty()
+            || destination == path
+            || destination.starts_with(&format!("{path}/"))
+        {
+            return Ok(Response::status(403));
+        }
+        util::metadata_or_root(&self.fs, path)?;
+
+        let overwrite = request
+            .header("overwrite")
+            .is_none_or(|overwrite| !overwrite.trim().eq_ignore_ascii_case("f"));
+        let existed = self.fs.exists(&destination)?;
+        if existed {
+            if !overwrite {
+                return Ok(Response::status(412));
+            }
+            self.remove(&destination)?;
+        } else if !self.parent_exists(&destination)? {
+            return Ok(Response::status(409));
+        }
+
+        self.fs.rename(path, &destination)?;
+        Ok(Response::status(if existed { 204 } else { 201 }))
+    }
+
+    /// Returns the properties of the entry at `path`, and of the entries in it if it's a directory and the depth is
+    /// 1.
+    fn propfind(&self, request: &Request, path: &str) -> crate::Result<Response> {
+        // the properties that are asked for are ignored, since all of them are cheap to return
+        let depth = match request.header("depth").map(str::trim) {
+            Some("0") => 0,
+            Some("1") => 1,
+            // listing whole trees isn't supported, since they may be huge
+            _ => {
+                let error = r#"<?xml version="1.0" encoding="utf-8"?><D:error xmlns:D="DAV:"><D:propfind-finite-depth/></D:error>"#;
+                return Ok(xml_response(403, error.to_owned()));
+            }
+        };
+        let metadata = util::metadata_or_root(&self.fs, path)?;
+
+        let href = match path {
+            "" => String::from("/"),
+            path => format!("/{}", path::encode(path)),
+        };
+        let mut xml =
+            String::from(r#"<?xml version="1.0" encoding="utf-8"?><D:multistatus xmlns:D="DAV:">"#);
+        let name = path.rsplit('/').next().unwrap_or_default();
+        push_properties(&mut xml, &href, name, &metadata);
+        if depth == 1 && metadata.is_directory() {
+            let base = href.trim_end_matches('/');
+            for (name, metadata) in self.entries(path)? {
+                let href = format!("{base}/{}", path::encode(&name));
+                push_properties(&mut xml, &href, &name, &metadata);
+            }
+        }
+        xml.push_str("</D:multistatus>");
+        Ok(xml_response(207, xml))
+    }
+
+    /// Returns true if the parent of `path` is a directory.
+    fn parent_exists(&self, path: &str) -> crate::Result<bool> {
+        let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        match util::metadata_or_root(&self.fs, parent) {
+            Ok(metadata) => Ok(metadata.is_directory()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns the names and metadata of the entries in the directory at `path`.
+    fn entries(&self, path: &str) -> crate::Result<Vec<(String, Metadata)>> {
+        let mut entries = Vec::new();
+        for entry in self.fs.read_dir(path)? {
+            let entry = entry?;
+            if let Some(name) = entry.path.file_name().and_then(|name| name.to_str()) {
+                entries.push((name.to_owned(), entry.metadata));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Removes the file or directory at `path`, removing the contents of the directory first since some filesystems
+    /// only remove empty directories.
+    fn remove(&self, path: &str) -> crate::Result<()> {
+        if !util::metadata_or_root(&self.fs, path)?.is_directory() {
+            return self.fs.remove_file(path);
+        }
+        for (name, metadata) in self.entries(path)? {
+            let entry = format!("{path}/{name}");
+            match metadata.is_directory() {
+                true => self.remove(&entry)?,
+                false => self.fs.remove_file(&entry)?,
+            }
+        }
+        self.fs.remove_dir(path)
+    }
+}
+
+/// A request, without its body.
+struct Request {
+    method: String,
+    target: String,
+    /// The headers, with lowercase names.
+    headers: Vec<(String, String)>,
+    /// True if the connection is closed after the response.
+    close: bool,
+}
+
+impl Request {
+    /// Reads the head of a request. Returns `None` if the connection was closed.
+    fn read<R: BufRead>(reader: &mut R) -> io::Result<Option<Self>> {
+        // empty lines may precede the request line
+        let line = loop {
+            match read_line(reader)? {
+                Some(line) if line.is_empty() => {}
+                Some(line) => break line,
+                None => return Ok(None),
+            }
+        };
+        let mut parts = line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid_data("Malformed HTTP request line"));
+        };
+
+        let mut headers = Vec::new();
+        loop {
+            let line = read_line(reader)?.ok_or_else(|| invalid_data("Truncated HTTP request"))?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(invalid_data("Too many HTTP headers"));
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid_data("Malformed HTTP header"))?;
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+        }
+
+        let mut request = Self {
+            method: method.to_owned(),
+            target: target.to_owned(),
+            headers,
+            close: version != "HTTP/1.1",
+        };
+        match request.header("connection").map(str::to_ascii_lowercase) {
+            Some(connection) if connection.contains("close") => request.close = true,
+            Some(connection) if connection.contains("keep-alive") => request.close = false,
+            _ => {}
+        }
+        Ok(Some(request))
+    }
+
+    /// Returns the value of the header `name`, which must be lowercase.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the path in the filesystem of the target of the request, or `None` if it isn't valid.
+    fn path(&self) -> Option<String> {
+        let target = match self.target.split_once("://") {
+            // absolute targets include the authority
+            Some((_, rest)) => &rest[rest.find('/')?..],
+            None => self.target.as_str(),
+        };
+        target.starts_with('/').then(|| target_path(target))?
+    }
+}
+
+/// Reads a line without its line ending. Returns `None` if the connection was closed before it.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    let len = reader
+        .take(MAX_LINE_LEN as u64 + 2)
+        .read_until(b'\n', &mut line)?;
+    if len == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(invalid_data("HTTP line is too long or truncated"));
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| invalid_data("HTTP line isn't UTF-8"))
+}
+
+/// The body of a request, which is read from the connection as it's consumed.
+struct Body<'a, R> {
+    reader: &'a mut R,
+    /// The bytes left in the body, or in the current chunk of a chunked body.
+    remaining: u64,
+    /// True if the body is chunked.
+    chunked: bool,
+    /// True if the end of the body was reached.
+    done: bool,
+}
+
+impl<'a, R: BufRead> Body<'a, R> {
+    /// Returns the body of a request.
+    fn new(request: &Request, reader: &'a mut R) -> io::Result<Self> {
+        let chunked = request
+            .header("transfer-encoding")
+            .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+        let remaining = match request.header("content-length") {
+            Some(len) if !chunked => len
+                .parse()
+                .map_err(|_| invalid_data("Malformed HTTP content length"))?,
+            _ => 0,
+        };
+        Ok(Self {
+            reader,
+            remaining,
+            chunked,
+            done: !chunked && remaining == 0,
+        })
+    }
+
+    /// Reads the size of the next chunk, and the trailers after the last one.
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let line = read_line(self.reader)?.ok_or_else(|| invalid_data("Truncated HTTP chunk"))?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        self.remaining =
+            u64::from_str_radix(size, 16).map_err(|_| invalid_data("Malformed HTTP chunk size"))?;
+        if self.remaining == 0 {
+            // the trailers end with an empty line
+            while !read_line(self.reader)?
+                .ok_or_else(|| invalid_data("Truncated HTTP trailers"))?
+                .is_empty()
+            {}
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for Body<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            self.next_chunk()?;
+            if self.done {
+                return Ok(0);
+            }
+        }
+
+        let len = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let len = self.reader.read(&mut buf[..len])?;
+        if len == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= len as u64;
+        if self.remaining == 0 {
+            match self.chunked {
+                // each chunk ends with a line ending
+                true => {
+                    read_line(self.reader)?;
+                }
+                false => self.done = true,
+            }
+        }
+        Ok(len)
+    }
+}
+
+/// A response.
+struct Response {
+    code: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Box<dyn Read>,
+    len: u64,
+    /// True if the connection is closed after the response.
+    close: bool,
+}
+
+impl Response {
+    /// Returns a response with a status code and no body.
+    fn status(code: u16) -> Self {
+        Self {
+            code,
+            headers: Vec::new(),
+            body: Box::new(io::empty()),
+            len: 0,
+            close: false,
+        }
+    }
+
+    /// Adds a header to the response.
+    fn header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.push((name, value.to_owned()));
+        self
+    }
+
+    /// Sets the body of the response, which is `len` bytes long.
+    fn body(mut self, body: Box<dyn Read>, len: u64) -> Self {
+        self.body = body;
+        self.len = len;
+        self
+    }
+
+    /// Writes the response. The body of responses to `HEAD` requests is omitted.
+    fn write<W: Write>(mut self, writer: &mut W, head: bool) -> io::Result<()> {
+        let mut head_bytes = format!(
+            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\n",
+            self.code,
+            reason(self.code),
+            self.len
+        );
+        for (name, value) in &self.headers {
+            head_bytes.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if self.close {
+            head_bytes.push_str("Connection: close\r\n");
+        }
+        head_bytes.push_str("\r\n");
+        writer.write_all(head_bytes.as_bytes())?;
+
+        if !head {
+            let copied = io::copy(&mut (&mut self.body).take(self.len), writer)?;
+            // the length was promised, so the connection can't be reused if the body was cut short
+            if copied < self.len {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// Returns a response with an XML body.
+fn xml_response(code: u16, xml: String) -> Response {
+    let len = xml.len() as u64;
+    Response::status(code)
+        .header("Content-Type", "application/xml; charset=utf-8")
+        .body(Box::new(io::Cursor::new(xml.into_bytes())), len)
+}
+
+/// Appends the response with the properties of an entry to a multistatus.
+///
+/// # Arguments
+/// `xml`: The multistatus.  
+/// `href`: The encoded path of the entry.  
+/// `name`: The name of the entry.  
+/// `metadata`: The metadata of the entry.  
+fn push_properties(xml: &mut String, href: &str, name: &str, metadata: &Metadata) {
+    // collections end with slashes
+    let slash = if metadata.is_directory() && !href.ends_with('/') {
+        "/"
+    } else {
+        ""
+    };
+    xml.push_str(&format!(
+        "<D:response><D:href>{}{slash}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>",
+        escape(href),
+        escape(name)
+    ));
+    match metadata.is_directory() {
+        true => xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>"),
+        false => xml.push_str(&format!(
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>application/octet-stream</D:getcontenttype>",
+            metadata.len()
+        )),
+    }
+    if let Some(modified) = metadata.modified {
+        xml.push_str(&format!(
+            "<D:getlastmodified>{}</D:getlastmodified>",
+            http_date(modified)
+        ));
+    }
+    xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>");
+}
+
+/// Parses a `Range` header of a file that's `len` bytes long. Returns the first and last byte of the range, or `None`
+/// if it can't be satisfied. Only single ranges are supported.
+///
+/// # Arguments
+/// `range`: The value of the header.  
+/// `len`: The length of the file.  
+fn parse_range(range: &str, len: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.trim().strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // the last bytes of the file
+        ("", suffix) => (
+            len.saturating_sub(suffix.parse().ok()?),
+            len.checked_sub(1)?,
+        ),
+        (start, "") => (start.parse().ok()?, len.checked_sub(1)?),
+        (start, end) => (
+            start.parse().ok()?,
+            end.parse::<u64>().ok()?.min(len.checked_sub(1)?),
+        ),
+    };
+    (start <= end && start < len).then_some((start, end))
+}
+
+/// Returns the path in the filesystem of the `Destination` header of a `MOVE`.
+///
+/// # Arguments
+/// `destination`: The value of the header.  
+fn destination_path(destination: &str) -> Option<String> {
+    let destination = match destination.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/')?..],
+        None => destination,
+    };
+    target_path(destination)
+}
+
+/// Returns the path in the filesystem of the path of a target, without its query.
+///
+/// # Arguments
+/// `target`: The path and query of the target.  
+fn target_path(target: &str) -> Option<String> {
+    let path = target.split('?').next()?;
+    let path = normalize_path(path::decode(path));
+    Some(path.to_str()?.trim_matches('/').to_owned())
+}
+
+/// Escapes the characters of `text` that are special in XML and HTML.
+///
+/// # Arguments
+/// `text`: The text to escape.  
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Formats a time as an HTTP date, such as `Sun, 06 Nov 1994 08:49:37 GMT`.
+///
+/// # Arguments
+/// `time`: The time to format.  
+fn http_date(time: SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let days = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() / 86400);
+    let DateTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    } = DateTime::from_system_time(time);
+    format!(
+        "{}, {day:02} {} {year} {hour:02}:{minute:02}:{second:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        MONTHS[month as usize - 1]
+    )
+}
+
+/// Returns the status code of an error.
+fn error_status(err: &io::Error) -> u16 {
+    match err.kind() {
+        ErrorKind::NotFound => 404,
+        ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => 403,
+        ErrorKind::AlreadyExists
+        | ErrorKind::NotADirectory
+        | ErrorKind::IsADirectory
+        | ErrorKind::DirectoryNotEmpty => 409,
+        ErrorKind::InvalidInput => 400,
+        ErrorKind::Unsupported => 501,
+        ErrorKind::StorageFull | ErrorKind::QuotaExceeded => 507,
+        _ => 500,
+    }
+}
+
+/// Returns the reason phrase of a status code.
+fn reason(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        207 => "Multi-Status",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        412 => "Precondition Failed",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        501 => "Not Implemented",
+        507 => "Insufficient Storage",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Returns an error for a malformed request.
+fn invalid_data(error: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_fs::MemoryFS;
+    use crate::webdav::{http_date, parse_range, Server};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    /// A response, with lowercase header names.
+    struct Response {
+        code: u16,
+        headers: Vec<(String, String)>,
+        body: String,
+    }
+
+    impl Response {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value.as_str())
+        }
+    }
+
+    /// Serves `fs` in the background. Returns a connection to the server.
+    fn connect(fs: MemoryFS) -> BufReader<TcpStream> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || Server::new(fs).serve_listener(listener));
+        BufReader::new(TcpStream::connect(addr).unwrap())
+    }
+
+    /// Makes a request on a connection, which is kept alive.
+    fn request(
+        stream: &mut BufReader<TcpStream>,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> Response {
+        let mut request = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n");
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        request.push_str(&format!("Content-Length: {}\r\n\r\n{body}", body.len()));
+        stream.get_mut().write_all(request.as_bytes()).unwrap();
+        read_response(stream, method == "HEAD")
+    }
+
+    fn read_response(stream: &mut BufReader<TcpStream>, head: bool) -> Response {
+        let mut line = String::new();
+        stream.read_line(&mut line).unwrap();
+        let code = line.split(' ').nth(1).unwrap().parse().unwrap();
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).unwrap();
+            match line.trim_end().split_once(": ") {
+                Some((name, value)) => headers.push((name.to_lowercase(), value.to_owned())),
+                None => break,
+            }
+        }
+
+        let mut response = Response {
+            code,
+            headers,
+            body: String::new(),
+        };
+        let len: u64 = response.header("content-length").unwrap().parse().unwrap();
+        if !head {
+            stream.take(len).read_to_string(&mut response.body).unwrap();
+        }
+        response
+    }
+
+    #[test]
+    fn read_write() {
+        let mut stream = connect(MemoryFS::default());
+
+        let response = request(&mut stream, "OPTIONS", "/", &[], "");
+        assert_eq!(response.code, 200);
+        assert_eq!(response.header("dav"), Some("1"));
+
+        assert_eq!(request(&mut stream, "MKCOL", "/dir", &[], "").code, 201);
+        assert_eq!(request(&mut stream, "MKCOL", "/dir", &[], "").code, 405);
+        assert_eq!(
+            request(&mut stream, "MKCOL", "/missing/dir", &[], "").code,
+            409
+        );
+        assert_eq!(
+            request(&mut stream, "PUT", "/dir/a%20file", &[], "hello world").code,
+            201
+        );
+        assert_eq!(
+            request(
+                &mut stream,
+                "PUT",
+                "/dir/a%20file",
+                &[],
+                "hello there world"
+            )
+            .code,
+            204
+        );
+        assert_eq!(
+            request(&mut stream, "PUT", "/missing/file", &[], "").code,
+            409
+        );
+        assert_eq!(request(&mut stream, "PUT", "/dir", &[], "").code, 405);
+
+        // chunked bodies are decoded
+        stream
+            .get_mut()
+            .write_all(b"PUT /dir/chunked HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6;ext\r\n world\r\n0\r\nTrailer: x\r\n\r\n")
+            .unwrap();
+        assert_eq!(read_response(&mut stream, false).code, 201);
+
+        let response = request(&mut stream, "GET", "/dir/a%20file", &[], "");
+        assert_eq!(response.code, 200);
+        assert_eq!(response.body, "hello there world");
+        let response = request(&mut stream, "GET", "/dir/chunked", &[], "");
+        assert_eq!(response.body, "hello world");
+        let response = request(&mut stream, "HEAD", "/dir/chunked", &[], "");
+        assert_eq!(response.header("content-length"), Some("11"));
+        assert_eq!(response.body, "");
+
+        // ranges of files are served
+        let response = request(
+            &mut stream,
+            "GET",
+            "/dir/chunked",
+            &[("Range", "bytes=6-")],
+            "",
+        );
+        assert_eq!(response.code, 206);
+        assert_eq!(response.header("content-range"), Some("bytes 6-10/11"));
+        assert_eq!(response.body, "world");
+        let response = request(
+            &mut stream,
+            "GET",
+            "/dir/chunked",
+            &[("Range", "bytes=20-")],
+            "",
+        );
+        assert_eq!(response.code, 416);
+
+        // directories are indexed for browsers
+        let response = request(&mut stream, "GET", "/dir/", &[], "");
+        assert!(response.body.contains(r#"<a href="a%20file">a file</a>"#));
+        assert_eq!(request(&mut stream, "GET", "/missing", &[], "").code, 404);
+
+        let response = request(&mut stream, "PROPFIND", "/dir", &[("Depth", "1")], "");
+        assert_eq!(response.code, 207);
+        assert!(response.body.contains("<D:href>/dir/</D:href>"));
+        assert!(response.body.contains("<D:collection/>"));
+        assert!(response.body.contains("<D:href>/dir/a%20file</D:href>"));
+        assert!(response
+            .body
+            .contains("<D:getcontentlength>17</D:getcontentlength>"));
+        assert!(response.body.contains("<D:href>/dir/chunked</D:href>"));
+        let response = request(&mut stream, "PROPFIND", "/dir", &[("Depth", "0")], "");
+        assert!(!response.body.contains("chunked"));
+        let response = request(&mut stream, "PROPFIND", "/", &[("Depth", "infinity")], "");
+        assert_eq!(response.code, 403);
+        assert!(response.body.contains("propfind-finite-depth"));
+
+        // moves overwrite unless they're told not to
+        let destination = [("Destination", "http://localhost/dir/moved")];
+        assert_eq!(
+            request(&mut stream, "MOVE", "/dir/chunked", &destination, "").code,
+            201
+        );
+        let destination = [("Destination", "/dir/moved"), ("Overwrite", "F")];
+        assert_eq!(
+            request(&mut stream, "MOVE", "/dir/a%20file", &destination, "").code,
+            412
+        );
+        let destination = [("Destination", "/dir/moved")];
+        assert_eq!(
+            request(&mut stream, "MOVE", "/dir/a%20file", &destination, "").code,
+            204
+        );
+        let response = request(&mut stream, "GET", "/dir/moved", &[], "");
+        assert_eq!(response.body, "hello there world");
+        assert_eq!(
+            request(&mut stream, "GET", "/dir/a%20file", &[], "").code,
+            404
+        );
+        let destination = [("Destination", "/dir/sub")];
+        assert_eq!(
+            request(&mut stream, "MOVE", "/dir", &destination, "").code,
+            403
+        );
+
+        // directories are removed along with their contents
+        assert_eq!(
+            request(&mut stream, "MKCOL", "/dir/nested", &[], "").code,
+            201
+        );
+        assert_eq!(
+            request(&mut stream, "PUT", "/dir/nested/file", &[], "").code,
+            201
+        );
+        assert_eq!(request(&mut stream, "DELETE", "/dir", &[], "").code, 204);
+        assert_eq!(request(&mut stream, "DELETE", "/dir", &[], "").code, 404);
+        let response = request(&mut stream, "PROPFIND", "/", &[("Depth", "1")], "");
+        assert_eq!(response.body.matches("<D:response>").count(), 1);
+
+        assert_eq!(request(&mut stream, "LOCK", "/", &[], "").code, 405);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("bytes=0-4", 10), Some((0, 4)));
+        assert_eq!(parse_range("bytes=5-", 10), Some((5, 9)));
+        assert_eq!(parse_range("bytes=-3", 10), Some((7, 9)));
+        assert_eq!(parse_range("bytes=5-100", 10), Some((5, 9)));
+        assert_eq!(parse_range("bytes=10-", 10), None);
+        assert_eq!(parse_range("bytes=4-2", 10), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+    }
+
+    #[test]
+    fn dates() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+}
@@ -1,34 +1,205 @@
-use crate::file::{DirEntry, File, FileType, Metadata, OpenOptions};
-use crate::util::{make_relative, not_found, not_supported, parent_iter};
+use crate::file::{DirEntry, File, Metadata, OpenOptions};
+use crate::tar_fs::FileSystemFilter;
+use crate::util::{
+    is_a_directory, make_relative, not_a_directory, not_found, not_supported, parent_iter,
+    read_only,
+};
 use crate::{util, FileSystem};
-use itertools::Itertools;
 use parking_lot::Mutex;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::io;
 use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+#[cfg(any(unix, windows))]
+use std::sync::Arc;
 use zip::read::ZipFile;
 use zip::result::{ZipError, ZipResult};
 use zip::ZipArchive;
 
+/// Information about an entry within a ZIP archive that isn't covered by `Metadata`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ZipEntryInfo {
+    /// The comment attached to the entry.
+    pub comment: String,
+    /// The raw extra fields of the entry's central directory record.
+    pub extra_data: Vec<u8>,
+    /// The compressed size of the entry, in bytes.
+    pub compressed_size: u64,
+    /// The CRC-32 checksum of the entry's contents.
+    pub crc32: u32,
+}
+
+/// A host file that can be cloned for concurrent readers of a `ZipFS` mounted with `ZipFS::new_cloneable`. Clones share
+/// the file, but each has its own cursor, since they read at their own offset rather than seeking the file.
+#[cfg(any(unix, windows))]
+#[derive(Debug, Clone)]
+pub struct SharedFile {
+    file: Arc<std::fs::File>,
+    position: u64,
+}
+
+#[cfg(any(unix, windows))]
+impl SharedFile {
+    /// Wraps a host file for concurrent readers.
+    ///
+    /// # Arguments
+    /// `file`: The host file.  
+    pub fn new(file: std::fs::File) -> Self {
+        Self {
+            file: Arc::new(file),
+            position: 0,
+        }
+    }
+}
+
+#[cfg(any(unix, windows))]
+impl Read for SharedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(unix)]
+        let read_len = std::os::unix::fs::FileExt::read_at(&*self.file, buf, self.position)?;
+        #[cfg(windows)]
+        let read_len = std::os::windows::fs::FileExt::seek_read(&*self.file, buf, self.position)?;
+
+        self.position += read_len as u64;
+        Ok(read_len)
+    }
+}
+
+#[cfg(any(unix, windows))]
+impl Seek for SharedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.file.metadata()?.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// A memory-mapped archive, shared between all readers of a `ZipFS` mounted with `ZipFS::new_mmap`.
+#[cfg(feature = "mmap")]
+#[derive(Debug, Clone)]
+pub struct MmapBytes(std::sync::Arc<memmap2::Mmap>);
+
+#[cfg(feature = "mmap")]
+impl AsRef<[u8]> for MmapBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Clones an archive handle for an additional concurrent reader.
+type CloneArchive<R> = fn(&ZipArchive<R>) -> ZipArchive<R>;
+
 /// A virtual FileSystem backed by a ZIP file. Only supports read operations for now.
 #[derive(Debug)]
 pub struct ZipFS<R: Read + Seek> {
     zip_file: Mutex<ZipArchive<R>>,
+    idle_archives: Mutex<Vec<ZipArchive<R>>>,
+    clone_archive: Option<CloneArchive<R>>,
     directories: HashSet<PathBuf>,
-    normalized_lower_to_path: HashMap<PathBuf, PathBuf>,
+    file_names: Vec<String>,
+    normalized_lower_to_name: HashMap<PathBuf, String>,
+    entries: HashMap<String, IndexedEntry>,
+}
+
+/// The size and type of an entry, as recorded in the central directory.
+#[derive(Debug, Copy, Clone)]
+struct IndexedEntry {
+    size: u64,
+    is_dir: bool,
+}
+
+impl<R: Read + Seek + Clone> ZipFS<R> {
+    /// Mounts a ZIP file onto the local filesystem, allowing concurrent readers. Rather than
+    /// serializing every read through a single archive, each concurrent reader gets its own clone
+    /// of the archive handle. Clones are pooled and reused once a read completes.
+    ///
+    /// The reader should be cheap to clone and each clone should have an independent cursor, such
+    /// as `Cursor<Arc<[u8]>>`. `std::fs::File` isn't `Clone`, and the handles returned by
+    /// `File::try_clone` share a cursor, so host files are mounted by wrapping them in a
+    /// `SharedFile`.
+    ///
+    /// # Arguments
+    /// `zip_file`: The ZIP archive reader.  
+    pub fn new_cloneable(zip_file: R) -> ZipResult<Self> {
+        let mut zip_fs = Self::new(zip_file)?;
+        zip_fs.clone_archive = Some(ZipArchive::clone);
+        Ok(zip_fs)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl ZipFS<Cursor<MmapBytes>> {
+    /// Mounts a ZIP file onto the local filesystem by memory-mapping it, which avoids a syscall
+    /// per read. The mapping is shared, so concurrent readers are supported like
+    /// `ZipFS::new_cloneable`.
+    ///
+    /// # Arguments
+    /// `path`: The path to the ZIP archive on the host.  
+    ///
+    /// # Safety
+    /// The archive must not be modified or truncated, by this process or any other, for as long
+    /// as the filesystem or any file opened from it is alive. The mapped bytes are read as an
+    /// immutable slice, so changes to the underlying file are undefined behaviour.
+    pub unsafe fn new_mmap<P: AsRef<Path>>(path: P) -> ZipResult<Self> {
+        let file = std::fs::File::open(path)?;
+        // safety: the caller guarantees that the archive isn't modified while it is mounted
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+
+        Self::new_cloneable(Cursor::new(MmapBytes(mmap.into())))
+    }
 }
 
 impl<R: Read + Seek> ZipFS<R> {
     /// Mounts a ZIP file onto the local filesystem.
     pub fn new(zip_file: R) -> ZipResult<Self> {
-        let zip_file = ZipArchive::new(zip_file)?;
+        Self::new_filtered(zip_file, |_: &_| true)
+    }
+
+    /// Mounts a ZIP file onto the local filesystem with filtered contents. Entries that are
+    /// filtered out are excluded from the index entirely, as if they were not in the archive.
+    ///
+    /// # Arguments
+    /// `zip_file`: The ZIP archive reader.  
+    /// `filter`: A filter that determines which entries are included in the filesystem.  
+    pub fn new_filtered<F: FileSystemFilter>(zip_file: R, filter: F) -> ZipResult<Self> {
+        let mut zip_file = ZipArchive::new(zip_file)?;
+
+        // the sizes and types of entries are indexed from their central directory records up front, since looking an
+        // entry up in the archive again reads its local header, which is a round trip for remote archives
+        let mut entries = HashMap::new();
+        for index in 0..zip_file.len() {
+            let entry = zip_file.by_index_raw(index)?;
+            entries.insert(
+                entry.name().to_owned(),
+                IndexedEntry {
+                    size: entry.size(),
+                    is_dir: entry.is_dir(),
+                },
+            );
+        }
 
         // collect folders
         let mut directories = HashSet::from_iter([Path::new("").to_owned()]);
-        let mut normalized_lower_to_path = HashMap::new();
+        let mut file_names = Vec::new();
+        let mut normalized_lower_to_name = HashMap::new();
         for file_name in zip_file.file_names() {
+            // ignore filtered files
+            if !filter.should_include(Path::new(file_name)) {
+                continue;
+            }
+
+            file_names.push(file_name.to_owned());
             for parent in parent_iter(Path::new(&file_name.to_lowercase())) {
                 directories.insert(parent.to_owned());
             }
@@ -41,13 +212,36 @@ impl<R: Read + Seek> ZipFS<R> {
                     .to_lowercase(),
             );
 
-            normalized_lower_to_path.insert(lower, normalized);
+            normalized_lower_to_name.insert(lower, file_name.to_owned());
         }
 
         Ok(Self {
             zip_file: Mutex::new(zip_file),
+            idle_archives: Mutex::default(),
+            clone_archive: None,
             directories,
-            normalized_lower_to_path,
+            file_names,
+            normalized_lower_to_name,
+            entries,
+        })
+    }
+
+    /// Returns the comment of the archive itself.
+    pub fn comment(&self) -> Vec<u8> {
+        self.with_archive(|zip_file| zip_file.comment().to_owned())
+    }
+
+    /// Returns the ZIP-specific information about the entry at `path`, including its comment and
+    /// extra fields.
+    ///
+    /// # Arguments
+    /// `path`: The path to the entry.  
+    pub fn entry_info(&self, path: &str) -> crate::Result<ZipEntryInfo> {
+        self.with_file(&Self::normalize_path(path), |entry| ZipEntryInfo {
+            comment: entry.comment().to_owned(),
+            extra_data: entry.extra_data().to_owned(),
+            compressed_size: entry.compressed_size(),
+            crc32: entry.crc32(),
         })
     }
 
@@ -66,11 +260,13 @@ impl<R: Read + Seek> ZipFS<R> {
         })
     }
 
-    /// Returns the cased path for the given normalized path.
-    fn get_cased_path(&self, normalized_path: &Path) -> Option<&PathBuf> {
+    /// Returns the name of the archive entry for the given normalized path.
+    fn get_entry_name(&self, normalized_path: &Path) -> Option<&str> {
         // find the cased path
         let lowercase_path = PathBuf::from(normalized_path.to_str()?.to_lowercase());
-        self.normalized_lower_to_path.get(&lowercase_path)
+        self.normalized_lower_to_name
+            .get(&lowercase_path)
+            .map(String::as_str)
     }
 
     fn normalize_path<P: AsRef<Path>>(path: P) -> PathBuf {
@@ -78,25 +274,43 @@ impl<R: Read + Seek> ZipFS<R> {
         make_relative(util::normalize_path(path))
     }
 
+    /// Calls `f` with an archive handle. If the filesystem was mounted with a cloneable reader,
+    /// the handle is exclusive to this call and other readers are not blocked.
+    ///
+    /// # Arguments
+    /// `f`: The function.  
+    fn with_archive<RV, F: FnOnce(&mut ZipArchive<R>) -> RV>(&self, f: F) -> RV {
+        let Some(clone_archive) = self.clone_archive else {
+            return f(&mut self.zip_file.lock());
+        };
+
+        // grab an idle handle, or clone a new one if all are in use
+        let idle_archive = self.idle_archives.lock().pop();
+        let mut archive = idle_archive.unwrap_or_else(|| clone_archive(&self.zip_file.lock()));
+
+        let rv = f(&mut archive);
+        self.idle_archives.lock().push(archive);
+        rv
+    }
+
     fn with_file<RV, F: FnOnce(ZipFile) -> RV>(
         &self,
         normalized_path: &Path,
         f: F,
     ) -> crate::Result<RV> {
         // find the cased path
-        let cased_path = self.get_cased_path(normalized_path).ok_or_else(not_found)?;
+        let entry_name = self.get_entry_name(normalized_path).ok_or_else(not_found)?;
 
-        let mut zip_file = self.zip_file.lock();
-
-        let entry =
-            Self::convert_error(zip_file.by_name(cased_path.to_str().ok_or_else(not_supported)?))?;
-        Ok(f(entry))
+        self.with_archive(|zip_file| {
+            let entry = Self::convert_error(zip_file.by_name(entry_name))?;
+            Ok(f(entry))
+        })
     }
 }
 
 impl<R: Read + Seek> FileSystem for ZipFS<R> {
     fn create_dir(&self, _path: &str) -> crate::Result<()> {
-        Err(not_supported())
+        Err(read_only())
     }
 
     fn metadata(&self, path: &str) -> crate::Result<Metadata> {
@@ -110,37 +324,43 @@ impl<R: Read + Seek> FileSystem for ZipFS<R> {
                 .ok_or_else(not_supported)?
                 .to_lowercase(),
         );
-        if self.directories.get(&lowercase_path).is_some() {
-            return Ok(Metadata {
-                file_type: FileType::Directory,
-                len: 0,
-            });
+        if self.directories.contains(&lowercase_path) {
+            return Ok(Metadata::directory());
         }
 
         // now files
-        self.with_file(normalized_path.as_path(), |file| Metadata {
-            file_type: FileType::File,
-            len: file.size(),
-        })
+        let entry_name = self
+            .get_entry_name(&normalized_path)
+            .ok_or_else(not_found)?;
+        Ok(Metadata::file(self.indexed_entry(entry_name)?.size))
     }
 
     fn open_file_options(&self, path: &str, options: &OpenOptions) -> crate::Result<Box<dyn File>> {
         // ensure we only want to read
-        if !options.read || options.write {
-            return Err(not_supported());
+        options.validate()?;
+        if options.writable() {
+            return Err(read_only());
+        }
+
+        // directories without entries of their own aren't found in the archive
+        let normalized_path = Self::normalize_path(path);
+        if self.directories.contains(&normalized_path) {
+            return Err(is_a_directory());
         }
 
         // open the file and read into a readable buffer
-        self.with_file::<crate::Result<Box<dyn File>>, _>(
-            &Self::normalize_path(path),
-            |mut entry| {
-                let mut contents = Vec::with_capacity(entry.size() as usize);
-                entry.read_to_end(&mut contents)?;
-                Ok(Box::new(ZipFileContents {
-                    inner: Cursor::new(contents),
-                }))
-            },
-        )?
+        self.with_file::<crate::Result<Box<dyn File>>, _>(&normalized_path, |mut entry| {
+            // directories can't be opened
+            if entry.is_dir() {
+                return Err(is_a_directory());
+            }
+
+            let mut contents = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut contents)?;
+            Ok(Box::new(ZipFileContents {
+                inner: Cursor::new(contents),
+            }))
+        })?
     }
 
     fn read_dir(
@@ -151,31 +371,62 @@ impl<R: Read + Seek> FileSystem for ZipFS<R> {
 
         // if there are no folders with this path, error out
         if !self.directories.contains(&directory) {
-            return Err(not_found());
+            return Err(if self.get_entry_name(&directory).is_some() {
+                not_a_directory()
+            } else {
+                not_found()
+            });
         }
 
-        let mut zip_file = self.zip_file.lock();
         let mut files = HashMap::new();
-        for file in zip_file
-            .file_names()
-            .map(|file_name| file_name.to_owned())
-            .collect_vec()
-        {
-            let normalized_file = Self::normalize_path(&file);
+        self.collect_directory(&directory, &mut files)?;
+
+        Ok(Box::new(
+            files
+                .into_iter()
+                .map(|(path, metadata)| Ok(DirEntry { path, metadata })),
+        ))
+    }
+
+    fn remove_dir(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+
+    fn remove_file(&self, _path: &str) -> crate::Result<()> {
+        Err(read_only())
+    }
+}
+
+impl<R: Read + Seek> ZipFS<R> {
+    /// Collects the entries directly contained in `directory` into `files`.
+    ///
+    /// # Arguments
+    /// `directory`: The normalized directory path.  
+    /// `files`: The collected entries.  
+    fn collect_directory(
+        &self,
+        directory: &Path,
+        files: &mut HashMap<PathBuf, Metadata>,
+    ) -> crate::Result<()> {
+        for file in &self.file_names {
+            let normalized_file = Self::normalize_path(file);
 
             let mut add_parent = |normalized_path: &Path, metadata| {
                 if normalized_path.parent()? == directory {
-                    files.insert(PathBuf::from(normalized_path.file_name()?), metadata);
+                    files.insert(normalized_path.to_owned(), metadata);
                 }
 
                 Some(())
             };
 
             // if the file's parent is the directory, it's in the directory
-            add_parent(
-                &normalized_file,
-                Metadata::file(zip_file.by_name(&file)?.size()),
-            );
+            let entry = self.indexed_entry(file)?;
+            let metadata = if entry.is_dir {
+                Metadata::directory()
+            } else {
+                Metadata::file(entry.size)
+            };
+            add_parent(&normalized_file, metadata);
 
             // if the file's parent directory is in the directory, add it
             if let Some(file_parent) = normalized_file.parent() {
@@ -183,19 +434,15 @@ impl<R: Read + Seek> FileSystem for ZipFS<R> {
             }
         }
 
-        Ok(Box::new(
-            files
-                .into_iter()
-                .map(|(path, metadata)| Ok(DirEntry { path, metadata })),
-        ))
-    }
-
-    fn remove_dir(&self, _path: &str) -> crate::Result<()> {
-        Err(not_supported())
+        Ok(())
     }
 
-    fn remove_file(&self, _path: &str) -> crate::Result<()> {
-        Err(not_supported())
+    /// Returns the size and type of the entry named `entry_name`, from the central directory index.
+    ///
+    /// # Arguments
+    /// `entry_name`: The name of the entry in the archive.  
+    fn indexed_entry(&self, entry_name: &str) -> crate::Result<IndexedEntry> {
+        self.entries.get(entry_name).copied().ok_or_else(not_found)
     }
 }
 
@@ -234,10 +481,19 @@ impl File for ZipFileContents {
 #[cfg(test)]
 mod test {
     use crate::file::{FileType, Metadata};
-    use crate::zip_fs::ZipFS;
+    use crate::zip_fs::{SharedFile, ZipEntryInfo, ZipFS};
     use crate::FileSystem;
     use std::collections::BTreeMap;
     use std::fs::File;
+    use std::io;
+    use std::io::ErrorKind;
+    use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::{fs, thread};
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
 
     fn read_directory(fs: &ZipFS<File>, path: &str) -> crate::Result<BTreeMap<String, Metadata>> {
         Ok(fs
@@ -278,9 +534,15 @@ mod test {
         assert_eq!(root, another_root);
 
         let deeper_root = read_directory(&fs, "folder/and/it").unwrap();
-        itertools::assert_equal(deeper_root.keys(), vec!["desc", "goes"]);
+        itertools::assert_equal(
+            deeper_root.keys(),
+            vec!["folder/and/it/desc", "folder/and/it/goes"],
+        );
 
-        assert!(read_directory(&fs, "file").is_err());
+        assert_eq!(
+            read_directory(&fs, "file").unwrap_err().kind(),
+            ErrorKind::NotADirectory
+        );
         assert!(read_directory(&fs, "not_a_real_path").is_err());
     }
 
@@ -346,4 +608,266 @@ mod test {
         assert!(fs.exists("///test/something_else/../../file").unwrap());
         assert!(fs.exists("///test/something_elsE/../../file").unwrap());
     }
+
+    fn read_concurrently<R: Read + Seek + Clone + Send>(fs: &ZipFS<R>) {
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..16 {
+                        let file = fs.open_file("file").unwrap().read_into_string().unwrap();
+                        assert!(file.starts_with("Lorem ipsum dolor"));
+
+                        let nested_file = fs
+                            .open_file("folder/and/it/goes/deeper/desc")
+                            .unwrap()
+                            .read_into_string()
+                            .unwrap();
+                        assert_eq!(nested_file, "deeper\n");
+                    }
+                });
+            }
+        });
+
+        let root = fs.read_dir("").unwrap().count();
+        assert_eq!(root, 2);
+    }
+
+    /// A reader that counts the reads made through it.
+    struct CountingReader {
+        inner: File,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl Read for CountingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for CountingReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn indexed_central_directory() {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let fs = ZipFS::new(CountingReader {
+            inner: File::open("test/deep_fs.zip").unwrap(),
+            reads: reads.clone(),
+        })
+        .unwrap();
+
+        // listing and metadata are served from the central directory, without reading any entry
+        reads.store(0, Ordering::Relaxed);
+        assert_eq!(fs.walk_dir("").unwrap().count(), 11);
+        assert_eq!(
+            fs.metadata("folder/and/it/goes/deeper/desc").unwrap().len,
+            7
+        );
+        assert_eq!(reads.load(Ordering::Relaxed), 0);
+
+        // only the opened entries are read
+        assert_eq!(
+            fs.open_file("folder/and/it/goes/deeper/desc")
+                .unwrap()
+                .read_into_string()
+                .unwrap(),
+            "deeper\n"
+        );
+        assert_ne!(reads.load(Ordering::Relaxed), 0);
+    }
+
+    /// Builds an archive with a single stored entry, whose sizes and central directory are only described by ZIP64
+    /// records, as archives larger than 4 GiB are.
+    fn zip64_archive(name: &str, contents: &[u8], crc32: u32) -> Vec<u8> {
+        let len = contents.len() as u64;
+        let zip64_extra = [
+            &1u16.to_le_bytes()[..],
+            &16u16.to_le_bytes(),
+            &len.to_le_bytes(),
+            &len.to_le_bytes(),
+        ]
+        .concat();
+        let common = [
+            &45u16.to_le_bytes()[..],
+            &0u16.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            &0u32.to_le_bytes(),
+            &crc32.to_le_bytes(),
+            &u32::MAX.to_le_bytes(),
+            &u32::MAX.to_le_bytes(),
+            &(name.len() as u16).to_le_bytes(),
+            &(zip64_extra.len() as u16).to_le_bytes(),
+        ]
+        .concat();
+
+        let mut archive = [
+            &0x04034b50u32.to_le_bytes()[..],
+            &common,
+            name.as_bytes(),
+            &zip64_extra,
+            contents,
+        ]
+        .concat();
+        let directory_start = archive.len() as u64;
+        archive.extend(
+            [
+                &0x02014b50u32.to_le_bytes()[..],
+                &45u16.to_le_bytes(),
+                &common,
+                &[0; 14],
+                name.as_bytes(),
+                &zip64_extra,
+            ]
+            .concat(),
+        );
+        let directory_len = archive.len() as u64 - directory_start;
+
+        let record_start = archive.len() as u64;
+        archive.extend(
+            [
+                &0x06064b50u32.to_le_bytes()[..],
+                &44u64.to_le_bytes(),
+                &45u16.to_le_bytes(),
+                &45u16.to_le_bytes(),
+                &[0; 8],
+                &1u64.to_le_bytes(),
+                &1u64.to_le_bytes(),
+                &directory_len.to_le_bytes(),
+                &directory_start.to_le_bytes(),
+            ]
+            .concat(),
+        );
+        archive.extend(
+            [
+                &0x07064b50u32.to_le_bytes()[..],
+                &0u32.to_le_bytes(),
+                &record_start.to_le_bytes(),
+                &1u32.to_le_bytes(),
+            ]
+            .concat(),
+        );
+        archive.extend(
+            [
+                &0x06054b50u32.to_le_bytes()[..],
+                &[0; 4],
+                &u16::MAX.to_le_bytes(),
+                &u16::MAX.to_le_bytes(),
+                &u32::MAX.to_le_bytes(),
+                &u32::MAX.to_le_bytes(),
+                &0u16.to_le_bytes(),
+            ]
+            .concat(),
+        );
+        archive
+    }
+
+    #[test]
+    fn indexed_zip64_entries() {
+        let archive = zip64_archive("large", b"zip64 sizes", 0xc2c5f90d);
+
+        let fs = ZipFS::new(Cursor::new(archive)).unwrap();
+        assert_eq!(fs.metadata("large").unwrap().len, 11);
+        assert_eq!(
+            fs.open_file("large").unwrap().read_into_string().unwrap(),
+            "zip64 sizes"
+        );
+    }
+
+    #[test]
+    fn malformed_central_directory() {
+        // a central directory header without its signature fails to mount, rather than losing the index
+        let mut archive = fs::read("test/deep_fs.zip").unwrap();
+        let header = archive
+            .windows(4)
+            .position(|window| window == [0x50, 0x4b, 0x01, 0x02])
+            .unwrap();
+        archive[header] = 0;
+        assert!(ZipFS::new(Cursor::new(archive)).is_err());
+    }
+
+    #[test]
+    fn concurrent_reads() {
+        let contents: Arc<[u8]> = fs::read("test/deep_fs.zip").unwrap().into();
+        read_concurrently(&ZipFS::new_cloneable(Cursor::new(contents)).unwrap());
+    }
+
+    #[test]
+    fn concurrent_file_reads() {
+        let file = SharedFile::new(fs::File::open("test/deep_fs.zip").unwrap());
+        read_concurrently(&ZipFS::new_cloneable(file).unwrap());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap() {
+        // safety: the test archive is never modified
+        let fs = unsafe { ZipFS::new_mmap("test/deep_fs.zip") }.unwrap();
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    let nested_file = fs
+                        .open_file("folder/and/it/goes/deeper/desc")
+                        .unwrap()
+                        .read_into_string()
+                        .unwrap();
+                    assert_eq!(nested_file, "deeper\n");
+                });
+            }
+        });
+
+        assert_eq!(fs.metadata("file").unwrap(), Metadata::file(2571));
+    }
+
+    #[test]
+    fn filtered() {
+        let fs = ZipFS::new_filtered(File::open("test/deep_fs.zip").unwrap(), |path: &Path| {
+            !path.starts_with("folder/and/it")
+        })
+        .unwrap();
+
+        assert!(fs.exists("file").unwrap());
+        assert!(fs.exists("folder/and/desc").unwrap());
+        assert!(!fs.exists("folder/and/it").unwrap());
+        assert!(!fs.exists("folder/and/it/desc").unwrap());
+        assert!(fs.open_file("folder/and/it/goes/desc").is_err());
+
+        let and = read_directory(&fs, "folder/and").unwrap();
+        itertools::assert_equal(and.keys(), vec!["folder/and/desc"]);
+    }
+
+    #[test]
+    fn entry_info() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.set_comment("build 1234");
+        writer
+            .start_file_with_extra_data("Info.txt", FileOptions::default())
+            .unwrap();
+        writer.write_all(&[0xEF, 0xBE, 2, 0, 1, 2]).unwrap();
+        writer.end_extra_data().unwrap();
+        writer.write_all(b"info").unwrap();
+        let contents = writer.finish().unwrap().into_inner();
+
+        let fs = ZipFS::new(Cursor::new(contents)).unwrap();
+        assert_eq!(fs.comment(), b"build 1234");
+
+        let info = fs.entry_info("/info.txt").unwrap();
+        assert_eq!(
+            info,
+            ZipEntryInfo {
+                comment: String::new(),
+                extra_data: vec![0xEF, 0xBE, 2, 0, 1, 2],
+                compressed_size: info.compressed_size,
+                crc32: 0xCB893157,
+            }
+        );
+        assert_eq!(
+            fs.entry_info("missing").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
 }
@@ -0,0 +1,477 @@
+//! A command line tool for inspecting and manipulating `virtual-filesystem` stacks, so that archives, directories and
+//! overlays of them can be browsed and copied between without writing a program. Each command takes locations, which
+//! are backend URIs optionally followed by `!` and a path within the backend:
+//!
+//! ```sh
+//! vfs-cli tree zip://assets.zip!textures
+//! vfs-cli cat dir://patches+zip://assets.zip!config.toml
+//! vfs-cli cp tar://backup.tar.gz!settings dir://restored
+//! ```
+
+mod uri;
+
+use crate::uri::Location;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::process::ExitCode;
+use std::{env, fs, io};
+use virtual_filesystem::file::Metadata;
+use virtual_filesystem::physical_fs::PhysicalFS;
+use virtual_filesystem::FileSystem;
+
+/// The usage of the tool.
+const USAGE: &str = "\
+usage: vfs-cli <command> <arguments>
+
+commands:
+  ls <location>                    Lists the entries of a directory
+  cat <location>...                Writes the contents of files to stdout
+  cp <source> <destination>        Copies a file or directory, into the destination if it's a directory
+  tree <location>                  Prints the tree of a directory
+  du <location>                    Prints the size of a directory and each directory within it
+  extract <location> <directory>   Extracts a file or the contents of a directory into a host directory
+
+locations are backend URIs, optionally followed by `!` and a path within the backend:
+  dir://<directory>                A directory on the host
+  zip://<archive>                  A ZIP archive
+  tar://<archive>                  A possibly compressed tarball
+  memory://                        An empty in-memory filesystem
+
+URIs joined by `+` are overlaid, with the first receiving all writes and shadowing the rest, such as
+`dir://patches+zip://base.zip`.
+";
+
+/// An error that stops a command.
+#[derive(Debug)]
+enum Error {
+    /// The command or its arguments are invalid.
+    Usage,
+    /// The command failed.
+    Io(io::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Usage => f.write_str(USAGE),
+            Self::Io(err) => write!(f, "vfs-cli: {err}"),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    match run(&args, &mut io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err @ Error::Usage) => {
+            eprint!("{err}");
+            ExitCode::from(2)
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs the command in `args`.
+///
+/// # Arguments
+/// `args`: The command and its arguments.  
+/// `out`: The stream the output of the command is written to.  
+fn run(args: &[String], out: &mut dyn Write) -> Result<(), Error> {
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    match args.as_slice() {
+        ["ls", location] => ls(&Location::parse(location)?, out)?,
+        ["cat", locations @ ..] if !locations.is_empty() => {
+            for location in locations {
+                let location = Location::parse(location)?;
+                io::copy(&mut location.fs.open_file(&location.path)?, out)?;
+            }
+        }
+        ["cp", source, destination] => {
+            cp(&Location::parse(source)?, &Location::parse(destination)?)?
+        }
+        ["tree", location] => tree(&Location::parse(location)?, out)?,
+        ["du", location] => {
+            let location = Location::parse(location)?;
+            let metadata = metadata(&*location.fs, &location.path)?;
+            if metadata.is_directory() {
+                du(&*location.fs, &location.path, out)?;
+            } else {
+                writeln!(out, "{}\t{}", metadata.len, location.path)?;
+            }
+        }
+        ["extract", location, directory] => extract(&Location::parse(location)?, directory)?,
+        ["help" | "-h" | "--help"] => write!(out, "{USAGE}")?,
+        _ => return Err(Error::Usage),
+    }
+    Ok(())
+}
+
+/// Lists the entries of the directory at a location, marking directories with a trailing slash. A file is listed by
+/// itself.
+///
+/// # Arguments
+/// `location`: The location of the directory.  
+/// `out`: The stream the entries are written to.  
+fn ls(location: &Location, out: &mut dyn Write) -> io::Result<()> {
+    if !metadata(&*location.fs, &location.path)?.is_directory() {
+        return writeln!(out, "{}", name(&location.path));
+    }
+
+    for (name, metadata) in entries(&*location.fs, &location.path)? {
+        let suffix = if metadata.is_directory() { "/" } else { "" };
+        writeln!(out, "{name}{suffix}")?;
+    }
+    Ok(())
+}
+
+/// Copies the file or directory at `source` to `destination`. If `destination` is an existing directory, the source
+/// is copied into it under its own name.
+///
+/// # Arguments
+/// `source`: The location of the file or directory to copy.  
+/// `destination`: The location to copy to.  
+fn cp(source: &Location, destination: &Location) -> io::Result<()> {
+    let metadata = metadata(&*source.fs, &source.path)?;
+    let into_directory = !source.path.is_empty()
+        && crate::metadata(&*destination.fs, &destination.path)
+            .is_ok_and(|metadata| metadata.is_directory());
+    let destination_path = if into_directory {
+        join(&destination.path, name(&source.path))
+    } else {
+        destination.path.clone()
+    };
+
+    copy(
+        &*source.fs,
+        &source.path,
+        &metadata,
+        &*destination.fs,
+        &destination_path,
+    )
+}
+
+/// Extracts the file or the contents of the directory at a location into a directory on the host, which is created
+/// if it doesn't exist.
+///
+/// # Arguments
+/// `source`: The location of the file or directory to extract.  
+/// `directory`: The path of the host directory.  
+fn extract(source: &Location, directory: &str) -> io::Result<()> {
+    fs::create_dir_all(directory)?;
+    let destination = PhysicalFS::new(directory);
+    let metadata = metadata(&*source.fs, &source.path)?;
+    let destination_path = if metadata.is_directory() {
+        ""
+    } else {
+        name(&source.path)
+    };
+
+    copy(
+        &*source.fs,
+        &source.path,
+        &metadata,
+        &destination,
+        destination_path,
+    )
+}
+
+/// Prints the tree of the directory at a location, followed by the number of directories and files within it.
+///
+/// # Arguments
+/// `location`: The location of the directory.  
+/// `out`: The stream the tree is written to.  
+fn tree(location: &Location, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", display_path(&location.path))?;
+    let mut counts = (0, 0);
+    print_tree(&*location.fs, &location.path, "", &mut counts, out)?;
+    writeln!(out, "\n{} directories, {} files", counts.0, counts.1)
+}
+
+/// Prints the entries of the directory at `path` and everything within them.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `path`: The path of the directory.  
+/// `prefix`: The prefix of the lines of the entries, which continues the lines of their ancestors.  
+/// `counts`: The number of directories and files printed so far.  
+/// `out`: The stream the tree is written to.  
+fn print_tree(
+    fs: &dyn FileSystem,
+    path: &str,
+    prefix: &str,
+    counts: &mut (u64, u64),
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let entries = entries(fs, path)?;
+    for (index, (name, metadata)) in entries.iter().enumerate() {
+        let last = index + 1 == entries.len();
+        let (branch, continuation) = if last {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        writeln!(out, "{prefix}{branch}{name}")?;
+
+        if metadata.is_directory() {
+            counts.0 += 1;
+            let prefix = format!("{prefix}{continuation}");
+            print_tree(fs, &join(path, name), &prefix, counts, out)?;
+        } else {
+            counts.1 += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Prints the size of the directory at `path` and of each directory within it, deepest first. Returns the size of
+/// the directory.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `path`: The path of the directory.  
+/// `out`: The stream the sizes are written to.  
+fn du(fs: &dyn FileSystem, path: &str, out: &mut dyn Write) -> io::Result<u64> {
+    let mut size = 0;
+    for (name, metadata) in entries(fs, path)? {
+        size += if metadata.is_directory() {
+            du(fs, &join(path, &name), out)?
+        } else {
+            metadata.len
+        };
+    }
+
+    writeln!(out, "{size}\t{}", display_path(path))?;
+    Ok(size)
+}
+
+/// Copies the file or directory at `source_path` to `destination_path`, creating directories as needed.
+///
+/// # Arguments
+/// `source`: The filesystem to copy from.  
+/// `source_path`: The path of the file or directory to copy.  
+/// `metadata`: The metadata of the file or directory to copy.  
+/// `destination`: The filesystem to copy to.  
+/// `destination_path`: The path to copy to.  
+fn copy(
+    source: &dyn FileSystem,
+    source_path: &str,
+    metadata: &Metadata,
+    destination: &dyn FileSystem,
+    destination_path: &str,
+) -> io::Result<()> {
+    if !metadata.is_directory() {
+        let mut file = source.open_file(source_path)?;
+        io::copy(&mut file, &mut destination.create_file(destination_path)?)?;
+        return Ok(());
+    }
+
+    if !destination_path.is_empty() {
+        destination.create_dir_all(destination_path)?;
+    }
+    for (name, metadata) in entries(source, source_path)? {
+        copy(
+            source,
+            &join(source_path, &name),
+            &metadata,
+            destination,
+            &join(destination_path, &name),
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the metadata of the entry at `path`. The root is always a directory, even if the filesystem has no
+/// metadata for it.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `path`: The path of the entry.  
+fn metadata(fs: &dyn FileSystem, path: &str) -> io::Result<Metadata> {
+    if path.is_empty() {
+        Ok(Metadata::directory())
+    } else {
+        fs.metadata(path)
+    }
+}
+
+/// Returns the names and metadata of the entries of the directory at `path`, sorted by name.
+///
+/// # Arguments
+/// `fs`: The filesystem.  
+/// `path`: The path of the directory.  
+fn entries(fs: &dyn FileSystem, path: &str) -> io::Result<Vec<(String, Metadata)>> {
+    let mut entries = Vec::new();
+    for entry in fs.read_dir(path)? {
+        let entry = entry?;
+        // filesystems differ in whether entries have full paths or just names
+        if let Some(name) = entry.path.file_name().and_then(|name| name.to_str()) {
+            entries.push((name.to_owned(), entry.metadata));
+        }
+    }
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(entries)
+}
+
+/// Returns the path of `name` in the directory at `parent`.
+fn join(parent: &str, name: &str) -> String {
+    match parent {
+        "" => name.to_owned(),
+        parent => format!("{parent}/{name}"),
+    }
+}
+
+/// Returns the last component of `path`.
+fn name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Returns `path` as it's printed, with the root printed as `.`.
+fn display_path(path: &str) -> &str {
+    if path.is_empty() {
+        "."
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{run, Error};
+    use std::{env, fs, process};
+
+    /// Runs the command in `args`, returning its output.
+    fn output(args: &[&str]) -> Result<String, Error> {
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let mut out = Vec::new();
+        run(&args, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn ls_cat() {
+        assert_eq!(
+            output(&["ls", "zip://../test/deep_fs.zip"]).unwrap(),
+            "file\nfolder/\n"
+        );
+        assert_eq!(
+            output(&["ls", "zip://../test/deep_fs.zip!/folder/and/"]).unwrap(),
+            "desc\nit/\n"
+        );
+        assert_eq!(
+            output(&["ls", "zip://../test/deep_fs.zip!folder/desc"]).unwrap(),
+            "desc\n"
+        );
+        assert_eq!(
+            output(&[
+                "cat",
+                "zip://../test/deep_fs.zip!folder/and/it/desc",
+                "tar://../test/deep_fs.tar.xz!folder/and/it/desc",
+            ])
+            .unwrap(),
+            "it\nit\n"
+        );
+    }
+
+    #[test]
+    fn tree_du() {
+        assert_eq!(
+            output(&["tree", "zip://../test/deep_fs.zip!folder/and"]).unwrap(),
+            "folder/and
+├── desc
+└── it
+    ├── desc
+    └── goes
+        ├── deeper
+        │   └── desc
+        └── desc
+
+3 directories, 4 files
+"
+        );
+        assert_eq!(
+            output(&["du", "zip://../test/deep_fs.zip!folder"]).unwrap(),
+            "7\tfolder/and/it/goes/deeper
+12\tfolder/and/it/goes
+15\tfolder/and/it
+19\tfolder/and
+26\tfolder
+"
+        );
+    }
+
+    #[test]
+    fn cp_extract_overlay() {
+        let root = env::temp_dir().join(format!("vfs-cli-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dir = root.to_str().unwrap();
+
+        output(&["extract", "zip://../test/deep_fs.zip!folder/and", dir]).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("it/goes/desc")).unwrap(),
+            "goes\n"
+        );
+        output(&[
+            "cp",
+            "zip://../test/deep_fs.zip!file",
+            &format!("dir://{dir}"),
+        ])
+        .unwrap();
+        assert_eq!(fs::metadata(root.join("file")).unwrap().len(), 2571);
+        output(&[
+            "cp",
+            "zip://../test/deep_fs.zip!folder/desc",
+            &format!("dir://{dir}!renamed"),
+        ])
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("renamed")).unwrap(),
+            "folder\n"
+        );
+
+        // the directory shadows the archive, so its `file` is the one that was copied
+        let overlay = format!("dir://{dir}+zip://../test/deep_fs.zip");
+        assert_eq!(
+            output(&["ls", &overlay]).unwrap(),
+            "desc\nfile\nfolder/\nit/\nrenamed\n"
+        );
+        output(&["cp", &format!("{overlay}!folder"), &format!("{overlay}!it")]).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("it/folder/and/it/goes/deeper/desc")).unwrap(),
+            "deeper\n"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        for args in [
+            &["bogus"][..],
+            &["ls"],
+            &["cat"],
+            &["cp", "memory://"],
+            &["ls", "memory://", "extra"],
+        ] {
+            assert!(matches!(output(args), Err(Error::Usage)));
+        }
+
+        for location in [
+            "../test/deep_fs.zip",
+            "nonsense://test",
+            "memory://test",
+            "zip://../test/nonsense.zip",
+            "zip://../test/deep_fs.zip!nonsense",
+        ] {
+            assert!(matches!(output(&["ls", location]), Err(Error::Io(_))));
+        }
+    }
+}
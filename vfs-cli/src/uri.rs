@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io;
+use std::io::ErrorKind;
+use virtual_filesystem::memory_fs::MemoryFS;
+use virtual_filesystem::overlay_fs::OverlayFS;
+use virtual_filesystem::physical_fs::PhysicalFS;
+use virtual_filesystem::roc_fs::RocFS;
+use virtual_filesystem::tar_fs::TarFS;
+use virtual_filesystem::zip_fs::ZipFS;
+use virtual_filesystem::FileSystem;
+
+/// A filesystem opened from a URI.
+pub type FS = Box<dyn FileSystem + Send + Sync>;
+
+/// A path within a filesystem opened from a URI.
+pub struct Location {
+    /// The filesystem.
+    pub fs: FS,
+    /// The path within the filesystem, without leading or trailing slashes.
+    pub path: String,
+}
+
+impl Location {
+    /// Opens the filesystem of a location, which is a backend URI optionally followed by `!` and a path within the
+    /// backend, such as `zip://assets.zip!textures/stone.png`.
+    ///
+    /// # Arguments
+    /// `location`: The location.  
+    pub fn parse(location: &str) -> io::Result<Self> {
+        let (uri, path) = location.rsplit_once('!').unwrap_or((location, ""));
+        Ok(Self {
+            fs: open(uri)?,
+            path: path.trim_matches('/').to_owned(),
+        })
+    }
+}
+
+/// Opens the filesystem described by a URI. Layers joined by `+` are overlaid, so that the first layer receives all
+/// writes and shadows the layers after it:
+/// - `dir://<directory>`: A `PhysicalFS` rooted at the directory.
+/// - `zip://<archive>`: A `ZipFS` over the ZIP archive.
+/// - `tar://<archive>`: A `TarFS` over the possibly compressed tarball.
+/// - `memory://`: An empty `MemoryFS`.
+///
+/// # Arguments
+/// `uri`: The URI of the filesystem, such as `dir://patches+zip://base.zip`.  
+pub fn open(uri: &str) -> io::Result<FS> {
+    let mut layers = uri
+        .split('+')
+        .map(open_layer)
+        .collect::<io::Result<Vec<_>>>()?;
+    if layers.len() == 1 {
+        return Ok(layers.remove(0));
+    }
+
+    let upper = layers.remove(0);
+    Ok(Box::new(OverlayFS::new(upper, RocFS::new(layers))))
+}
+
+/// Opens the filesystem described by the URI of a single layer.
+///
+/// # Arguments
+/// `uri`: The URI of the layer.  
+fn open_layer(uri: &str) -> io::Result<FS> {
+    let (scheme, source) = uri
+        .split_once("://")
+        .ok_or_else(|| invalid_input(format!("`{uri}` has no scheme")))?;
+
+    match scheme {
+        "dir" => Ok(Box::new(PhysicalFS::new(source))),
+        "zip" => Ok(Box::new(ZipFS::new(File::open(source)?)?)),
+        "tar" => Ok(Box::new(TarFS::open(File::open(source)?)?)),
+        "memory" if source.is_empty() => Ok(Box::new(MemoryFS::default())),
+        "memory" => Err(invalid_input("`memory://` doesn't take a path".to_owned())),
+        _ => Err(invalid_input(format!("Unsupported scheme `{scheme}`"))),
+    }
+}
+
+/// Returns an `InvalidInput` error.
+///
+/// # Arguments
+/// `error`: The description of the error.  
+fn invalid_input(error: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, error)
+}
@@ -0,0 +1,167 @@
+//! # Procedural macros for `virtual-filesystem`
+//! This crate implements the macros re-exported by `virtual-filesystem` behind its `embed` feature. It isn't meant to
+//! be depended upon directly.
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+use syn::parse::{Parse, ParseStream};
+use syn::{Ident, LitByteStr, LitStr, Token};
+
+/// Embeds a directory into the binary at build time, and evaluates to a `MemoryFS` preloaded with its contents.
+///
+/// The path is relative to the directory of the crate's manifest. Adding `compress` stores the contents of each file
+/// gzip-compressed in the binary, and decompresses them when the filesystem is constructed.
+///
+/// ```ignore
+/// let assets = virtual_filesystem::embed_dir!("assets");
+/// let compressed_assets = virtual_filesystem::embed_dir!("assets", compress);
+/// ```
+#[proc_macro]
+pub fn embed_dir(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as EmbedDirInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// The arguments of `embed_dir!`.
+struct EmbedDirInput {
+    path: LitStr,
+    compress: bool,
+}
+
+impl Parse for EmbedDirInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let path = input.parse()?;
+        let mut compress = false;
+        if input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+            let option: Ident = input.parse()?;
+            if option != "compress" {
+                return Err(syn::Error::new(option.span(), "expected `compress`"));
+            }
+            compress = true;
+            input.parse::<Option<Token![,]>>()?;
+        }
+
+        Ok(Self { path, compress })
+    }
+}
+
+/// An embedded entry, with the path relative to the embedded directory.
+enum EmbeddedEntry {
+    Directory(String),
+    File(String, PathBuf),
+}
+
+fn expand(input: &EmbedDirInput) -> syn::Result<proc_macro2::TokenStream> {
+    let manifest_dir = env::var_os("CARGO_MANIFEST_DIR")
+        .ok_or_else(|| syn::Error::new(input.path.span(), "`CARGO_MANIFEST_DIR` is not set"))?;
+    let root = Path::new(&manifest_dir).join(input.path.value());
+    if !root.is_dir() {
+        return Err(syn::Error::new(
+            input.path.span(),
+            format!("`{}` is not a directory", root.display()),
+        ));
+    }
+
+    let mut entries = Vec::new();
+    collect_entries(&root, "", &mut entries)
+        .map_err(|err| syn::Error::new(input.path.span(), err))?;
+
+    let entries = entries
+        .into_iter()
+        .map(|entry| match entry {
+            EmbeddedEntry::Directory(path) => Ok(quote! { (#path, ::core::option::Option::None) }),
+            EmbeddedEntry::File(path, absolute) => {
+                let absolute = absolute
+                    .to_str()
+                    .ok_or_else(|| {
+                        syn::Error::new(input.path.span(), "embedded paths must be valid UTF-8")
+                    })?
+                    .to_owned();
+                let contents = if input.compress {
+                    let compressed = compress(&absolute)
+                        .map_err(|err| syn::Error::new(input.path.span(), err))?;
+                    let compressed = LitByteStr::new(&compressed, Span::call_site());
+                    // the file is still included so that the crate is rebuilt when it changes
+                    quote! {{
+                        const _: &[u8] = ::core::include_bytes!(#absolute);
+                        #compressed as &[u8]
+                    }}
+                } else {
+                    quote! { ::core::include_bytes!(#absolute) as &[u8] }
+                };
+                Ok(quote! { (#path, ::core::option::Option::Some(#contents)) })
+            }
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let constructor = if input.compress {
+        quote! { from_embedded_gzip }
+    } else {
+        quote! { from_embedded }
+    };
+
+    Ok(quote! {
+        ::virtual_filesystem::memory_fs::MemoryFS::#constructor(&[#(#entries),*])
+    })
+}
+
+/// Collects the entries of a directory recursively in a stable order, with parents before their children.
+///
+/// # Arguments
+/// `dir`: The directory on disk.  
+/// `prefix`: The path of the directory relative to the embedded root.  
+/// `entries`: The collected entries.  
+fn collect_entries(
+    dir: &Path,
+    prefix: &str,
+    entries: &mut Vec<EmbeddedEntry>,
+) -> Result<(), String> {
+    let mut children = fs::read_dir(dir)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| format!("failed to read `{}`: {err}", dir.display()))?;
+    children.sort_by_key(|child| child.file_name());
+
+    for child in children {
+        let name = child.file_name();
+        let name = name
+            .to_str()
+            .ok_or_else(|| format!("`{}` is not valid UTF-8", child.path().display()))?;
+        let path = if prefix.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{prefix}/{name}")
+        };
+
+        let child_path = child.path();
+        if child_path.is_dir() {
+            entries.push(EmbeddedEntry::Directory(path.clone()));
+            collect_entries(&child_path, &path, entries)?;
+        } else {
+            entries.push(EmbeddedEntry::File(path, child_path));
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a file and compresses it with gzip.
+///
+/// # Arguments
+/// `path`: The path to the file.  
+fn compress(path: &str) -> Result<Vec<u8>, String> {
+    let contents = fs::read(path).map_err(|err| format!("failed to read `{path}`: {err}"))?;
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder
+        .write_all(&contents)
+        .and_then(|_| encoder.finish())
+        .map_err(|err| format!("failed to compress `{path}`: {err}"))
+}